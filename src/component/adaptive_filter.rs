@@ -0,0 +1,397 @@
+//
+//   Copyright 2016 Andrew Hunter
+//
+//   Licensed under the Apache License, Version 2.0 (the "License");
+//   you may not use this file except in compliance with the License.
+//   You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+//   Unless required by applicable law or agreed to in writing, software
+//   distributed under the License is distributed on an "AS IS" BASIS,
+//   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//   See the License for the specific language governing permissions and
+//   limitations under the License.
+//
+
+//!
+//! # Adaptive subscription filtering
+//!
+//! Subscriptions are often made for a whole subtree when the changes that actually arrive are all under a much
+//! narrower part of it. An adaptive filter learns this narrower prefix from the changes that match a subscription,
+//! and once it's seen enough of them it decides most changes by comparing their address against the prefix
+//! instead of calling `applies_to()`.
+//!
+//! The pre-filter never guesses: it only rejects a change when its address leaves the subscribed address, and
+//! only accepts changes that are parents of the learned prefix or inside it. Changes that are inside the
+//! subscription but outside the learned prefix are evaluated in full. If one of these matches, the traffic has
+//! shifted, so the filter counts a correction and starts learning again. As a further safety net, every Nth
+//! change is evaluated in full even when the pre-filter could decide it, and a disagreement resets the filter.
+//!
+//! Only `SubTree` subscriptions are filtered: the other extents are always evaluated in full.
+//!
+//! Adaptive filtering is turned on for a publisher with `enable_adaptive_filtering()`, which affects the
+//! subscriptions made after it's called.
+//!
+
+use super::super::tree::*;
+
+/// The number of recent matches that the sketch remembers
+const SKETCH_SIZE: usize = 8;
+
+///
+/// Settings for an adaptive filter
+///
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct AdaptiveFilterSettings {
+    /// The number of matching changes that must be seen before the pre-filter is used
+    pub confidence: usize,
+
+    /// Every change with a multiple of this number is evaluated in full, even when the pre-filter could decide it
+    pub bypass_interval: usize
+}
+
+impl Default for AdaptiveFilterSettings {
+    fn default() -> AdaptiveFilterSettings {
+        AdaptiveFilterSettings { confidence: 16, bypass_interval: 8 }
+    }
+}
+
+///
+/// Statistics for a single adaptively filtered subscription
+///
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct AdaptiveFilterStats {
+    /// Changes that were evaluated in full because the pre-filter couldn't decide them
+    pub evaluated: usize,
+
+    /// Changes that were decided by the pre-filter alone
+    pub prefiltered: usize,
+
+    /// Changes that the pre-filter could decide, but were evaluated in full anyway
+    pub bypassed: usize,
+
+    /// The number of times the learned prefix turned out to be wrong and was reset
+    pub corrections: usize
+}
+
+///
+/// A single part of a flattened address
+///
+#[derive(Clone, PartialEq, Debug)]
+pub enum AddressPart {
+    Index(usize),
+    Tag(String)
+}
+
+///
+/// Flattens an address into a list of parts, so it can be compared against a prefix
+///
+pub fn flatten_address(address: &TreeAddress) -> Vec<AddressPart> {
+    let mut parts   = vec![];
+    let mut current = address;
+
+    loop {
+        match *current {
+            TreeAddress::Here                           => return parts,
+            TreeAddress::ChildAtIndex(index, ref next)  => { parts.push(AddressPart::Index(index)); current = next; },
+            TreeAddress::ChildWithTag(ref tag, ref next) => { parts.push(AddressPart::Tag(tag.clone())); current = next; }
+        }
+    }
+}
+
+///
+/// What the pre-filter decided about a change
+///
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Prefiltered {
+    /// The change is a parent of the learned prefix or is inside it
+    Accept,
+
+    /// The change is not inside the subscribed address
+    Reject,
+
+    /// The change is inside the subscribed address, but not inside the learned prefix
+    Outside,
+
+    /// The change can't be compared against the prefix (it mixes tags and indexes differently)
+    Unknown
+}
+
+///
+/// Learns which part of a subscription's subtree is actually changing, and uses it to decide which changes
+/// apply to the subscription
+///
+pub struct AdaptiveFilter {
+    /// The subscribed address
+    address: TreeAddress,
+
+    /// The subscribed extent
+    extent: TreeExtent,
+
+    /// The subscribed address, flattened
+    subscribed: Vec<AddressPart>,
+
+    /// How this filter learns
+    settings: AdaptiveFilterSettings,
+
+    /// The address of the first change matched since the filter was last reset
+    anchor: Option<Vec<AddressPart>>,
+
+    /// The depths at which the most recent matching changes diverged from the anchor
+    sketch: [usize; SKETCH_SIZE],
+
+    /// The number of matching changes seen since the filter was last reset
+    matches: usize,
+
+    /// The number of changes this filter has seen
+    seen: usize,
+
+    /// Statistics for this filter
+    stats: AdaptiveFilterStats
+}
+
+impl AdaptiveFilter {
+    ///
+    /// Creates a new adaptive filter for a subscription
+    ///
+    pub fn new(address: &TreeAddress, extent: TreeExtent, settings: AdaptiveFilterSettings) -> AdaptiveFilter {
+        AdaptiveFilter {
+            address:    address.clone(),
+            extent,
+            subscribed: flatten_address(address),
+            settings,
+            anchor:     None,
+            sketch:     [0; SKETCH_SIZE],
+            matches:    0,
+            seen:       0,
+            stats:      AdaptiveFilterStats::default()
+        }
+    }
+
+    ///
+    /// The address of the subscription this filter is for
+    ///
+    pub fn address(&self) -> &TreeAddress {
+        &self.address
+    }
+
+    ///
+    /// The statistics for this filter
+    ///
+    pub fn stats(&self) -> AdaptiveFilterStats {
+        self.stats
+    }
+
+    ///
+    /// The length of the learned prefix, if the filter is confident enough to use it
+    ///
+    fn learned_length(&self) -> Option<usize> {
+        if self.matches < self.settings.confidence || self.matches == 0 {
+            return None;
+        }
+
+        let remembered  = if self.matches < SKETCH_SIZE { self.matches } else { SKETCH_SIZE };
+        let length      = self.sketch[0..remembered].iter().cloned().min().unwrap_or(0);
+
+        // The prefix is only useful if it's narrower than the subscription
+        if length > self.subscribed.len() { Some(length) } else { None }
+    }
+
+    ///
+    /// Records a change that matched the subscription
+    ///
+    fn learn(&mut self, flat: &[AddressPart]) {
+        if self.anchor.is_none() {
+            self.anchor = Some(flat.to_vec());
+        }
+
+        let depth = self.anchor.as_ref().map(|anchor| anchor.iter().zip(flat.iter()).take_while(|&(a, b)| a == b).count()).unwrap_or(0);
+
+        self.sketch[self.matches % SKETCH_SIZE] = depth;
+        self.matches += 1;
+    }
+
+    ///
+    /// Forgets the learned prefix
+    ///
+    fn reset(&mut self) {
+        self.anchor     = None;
+        self.sketch     = [0; SKETCH_SIZE];
+        self.matches    = 0;
+    }
+
+    ///
+    /// Tries to decide whether or not a change applies to the subscription by comparing its flattened address
+    /// against the learned prefix
+    ///
+    fn prefilter(&self, flat: &[AddressPart]) -> Option<Prefiltered> {
+        let length = self.learned_length()?;
+        let prefix = &self.anchor.as_ref()?[0..length];
+
+        for (depth, (part, prefix_part)) in flat.iter().zip(prefix.iter()).enumerate() {
+            let same_kind = matches!((part, prefix_part), (AddressPart::Index(_), AddressPart::Index(_)) | (AddressPart::Tag(_), AddressPart::Tag(_)));
+
+            if !same_kind {
+                return Some(Prefiltered::Unknown);
+            } else if part != prefix_part {
+                return Some(if depth < self.subscribed.len() { Prefiltered::Reject } else { Prefiltered::Outside });
+            }
+        }
+
+        // The change is a parent of the prefix, or inside it
+        Some(Prefiltered::Accept)
+    }
+
+    ///
+    /// Works out if a change applies to the subscription using `applies_to()`
+    ///
+    fn evaluate(&self, change: &TreeChange) -> bool {
+        change.applies_to(&self.address, &self.extent).unwrap_or(false)
+    }
+
+    ///
+    /// Decides whether or not a change should be delivered to the subscription
+    ///
+    /// `flat` is the flattened address of the change (see `flatten_address()`). The result is always the same as
+    /// `change.applies_to(address, extent).unwrap_or(false)`.
+    ///
+    pub fn should_deliver(&mut self, change: &TreeChange, flat: &[AddressPart]) -> bool {
+        if self.extent != TreeExtent::SubTree {
+            self.stats.evaluated += 1;
+            return self.evaluate(change);
+        }
+
+        self.seen += 1;
+        let bypass = self.settings.bypass_interval > 0 && self.seen.is_multiple_of(self.settings.bypass_interval);
+
+        let deliver = match self.prefilter(flat) {
+            Some(decided @ Prefiltered::Accept) | Some(decided @ Prefiltered::Reject) => {
+                let prefiltered = decided == Prefiltered::Accept;
+
+                if !bypass {
+                    self.stats.prefiltered += 1;
+                    prefiltered
+                } else {
+                    // Check that the pre-filter still agrees with the full evaluation
+                    let exact = self.evaluate(change);
+
+                    self.stats.bypassed += 1;
+                    if exact != prefiltered {
+                        self.stats.corrections += 1;
+                        self.reset();
+                    }
+
+                    exact
+                }
+            },
+
+            Some(Prefiltered::Outside) => {
+                // Matching changes outside the learned prefix mean that the traffic has moved
+                let exact = self.evaluate(change);
+
+                self.stats.evaluated += 1;
+                if exact {
+                    self.stats.corrections += 1;
+                    self.reset();
+                }
+
+                exact
+            },
+
+            Some(Prefiltered::Unknown) | None => {
+                self.stats.evaluated += 1;
+                self.evaluate(change)
+            }
+        };
+
+        if deliver {
+            self.learn(flat);
+        }
+
+        deliver
+    }
+}
+
+#[cfg(test)]
+mod adaptive_filter_tests {
+    use std::rc::*;
+    use std::cell::*;
+
+    use super::super::super::tree::*;
+    use super::super::super::component::*;
+    use super::super::immediate_publisher::*;
+    use super::*;
+
+    ///
+    /// Subscribes to a consumer, recording the changes that it receives as text
+    ///
+    fn record(consumer: &mut ConsumerRef, address: TreeAddress) -> Rc<RefCell<Vec<String>>> {
+        let received        = Rc::new(RefCell::new(vec![]));
+        let their_received  = received.clone();
+
+        consumer.subscribe(address, TreeExtent::SubTree, Box::new(move |change| {
+            their_received.borrow_mut().push(format!("{}", change.address()));
+        }));
+
+        received
+    }
+
+    #[test]
+    fn narrow_traffic_is_mostly_prefiltered() {
+        let mut filter = AdaptiveFilter::new(&"app".to_tree_address(), TreeExtent::SubTree, AdaptiveFilterSettings::default());
+
+        for count in 0..200 {
+            let address = if count % 2 == 0 { ("app", ("status", "count")).to_tree_address() } else { ("other", count).to_tree_address() };
+            let change  = TreeChange::new(&address, &("count", count as i32));
+
+            assert!(filter.should_deliver(&change, &flatten_address(&address)) == (count % 2 == 0));
+        }
+
+        let stats = filter.stats();
+        assert!(stats.corrections == 0);
+        assert!(stats.bypassed > 0);
+        assert!(stats.prefiltered > 120);
+    }
+
+    #[test]
+    fn other_extents_are_always_evaluated() {
+        let mut filter  = AdaptiveFilter::new(&"app".to_tree_address(), TreeExtent::Children, AdaptiveFilterSettings::default());
+        let address     = ("app", "status").to_tree_address();
+
+        for _ in 0..50 {
+            assert!(filter.should_deliver(&TreeChange::new(&address, &("status", 1)), &flatten_address(&address)));
+        }
+
+        assert!(filter.stats().evaluated == 50);
+        assert!(filter.stats().prefiltered == 0);
+    }
+
+    #[test]
+    fn traffic_shift_is_corrected_without_missed_deliveries() {
+        let mut adaptive    = ImmediatePublisher::new();
+        let mut reference   = ImmediatePublisher::new();
+        adaptive.enable_adaptive_filtering(AdaptiveFilterSettings { confidence: 8, bypass_interval: 16 });
+
+        let adaptive_received   = record(&mut adaptive.create_consumer(), "app".to_tree_address());
+        let reference_received  = record(&mut reference.create_consumer(), "app".to_tree_address());
+
+        // Traffic is under app.status to start with, then moves to app.config, then comes from both places
+        for count in 0..300 {
+            let section = if count < 100 { "status" } else if count < 200 { "config" } else if count % 2 == 0 { "status" } else { "config" };
+            let address = if count % 3 == 0 { ("elsewhere", "value").to_tree_address() } else { ("app", (section, "value")).to_tree_address() };
+            let change  = TreeChange::new(&address, &("value", count));
+
+            adaptive.publish(change.clone());
+            reference.publish(change);
+        }
+
+        assert!(*adaptive_received.borrow() == *reference_received.borrow());
+
+        let stats = adaptive.adaptive_filter_stats();
+        assert!(stats.len() == 1);
+        assert!(stats[0].0 == "app".to_tree_address());
+        assert!(stats[0].1.corrections > 0);
+        assert!(stats[0].1.prefiltered > 100);
+    }
+}
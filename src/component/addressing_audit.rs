@@ -0,0 +1,406 @@
+//
+//   Copyright 2016 Andrew Hunter
+//
+//   Licensed under the Apache License, Version 2.0 (the "License");
+//   you may not use this file except in compliance with the License.
+//   You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+//   Unless required by applicable law or agreed to in writing, software
+//   distributed under the License is distributed on an "AS IS" BASIS,
+//   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//   See the License for the specific language governing permissions and
+//   limitations under the License.
+//
+
+//!
+//! # Auditing how addresses are written
+//!
+//! An address can pick out a child by its index or by its tag, but the two forms can't be compared without the
+//! tree they refer to. When a change is published using one form and a subscription uses the other, `applies_to()`
+//! can't tell if the change is relevant and the subscription doesn't receive it.
+//!
+//! An `AddressingAudit` attached to a bus or a hub looks at the addresses of the changes it delivers and of the
+//! subscriptions it evaluates them against. For each prefix, it records whether the next part of the addresses
+//! below it is an index or a tag. Prefixes where changes are published with one form and subscriptions use the
+//! other are reported as risky, along with an example of each address and the number of evaluations at that
+//! prefix where the applicability of a change couldn't be decided.
+//!
+//! The audit only keeps track of a limited number of prefixes, so it can be left attached to a busy system: once
+//! the limit is reached, addresses with new prefixes are counted but not recorded.
+//!
+
+use std::rc::*;
+use std::cell::*;
+use std::fmt;
+use std::collections::BTreeMap;
+
+use super::super::tree::*;
+use super::adaptive_filter::*;
+
+tree_struct! {
+    ///
+    /// A prefix where changes and subscriptions use different forms of address
+    ///
+    #[derive(Clone, PartialEq, Debug)]
+    pub struct RiskyPrefix {
+        // The prefix, formatted as an address
+        pub prefix: String,
+
+        // How the changes below this prefix address the next node ("index", "tag" or "mixed")
+        pub publications: String,
+
+        // How the subscriptions below this prefix address the next node ("index", "tag" or "mixed")
+        pub subscriptions: String,
+
+        // The address of a change below this prefix
+        pub example_change: String,
+
+        // The address of a subscription below this prefix that uses a different form to the example change
+        pub example_subscription: String,
+
+        // The number of evaluations of a change against a subscription at this prefix that couldn't be decided
+        pub unknown_evaluations: i32
+    }
+}
+
+tree_struct! {
+    ///
+    /// The results of an addressing audit
+    ///
+    #[derive(Clone, PartialEq, Debug)]
+    pub struct AddressingReport {
+        // The prefixes where changes and subscriptions use different forms of address
+        pub risky_prefixes: Vec<RiskyPrefix>,
+
+        // The number of addresses that weren't recorded because the audit was already tracking as many prefixes
+        // as it's allowed to
+        pub untracked: i32
+    }
+}
+
+impl fmt::Display for AddressingReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.risky_prefixes.is_empty() {
+            writeln!(f, "No mixed addressing found")?;
+        }
+
+        for risky in self.risky_prefixes.iter() {
+            writeln!(f, "{}: changes use {}, subscriptions use {} ({} undecided evaluations)",
+                risky.prefix, risky.publications, risky.subscriptions, risky.unknown_evaluations)?;
+            writeln!(f, "    e.g. change at {} and subscription to {}", risky.example_change, risky.example_subscription)?;
+        }
+
+        if self.untracked > 0 {
+            writeln!(f, "{} addresses were not tracked", self.untracked)?;
+        }
+
+        Ok(())
+    }
+}
+
+///
+/// The forms of address that have been seen for the next part of addresses below a prefix
+///
+#[derive(Clone, Default)]
+struct AddressForms {
+    index: Option<String>,
+    tag: Option<String>
+}
+
+impl AddressForms {
+    ///
+    /// Records the form of an address
+    ///
+    fn record(&mut self, part: &AddressPart, address: &TreeAddress) {
+        let form = match *part {
+            AddressPart::Index(_)   => &mut self.index,
+            AddressPart::Tag(_)     => &mut self.tag
+        };
+
+        if form.is_none() {
+            *form = Some(address.to_string());
+        }
+    }
+
+    ///
+    /// Describes the forms that have been seen
+    ///
+    fn describe(&self) -> String {
+        match (self.index.is_some(), self.tag.is_some()) {
+            (true, true)    => "mixed",
+            (true, false)   => "index",
+            (false, true)   => "tag",
+            (false, false)  => "none"
+        }.to_string()
+    }
+}
+
+///
+/// What's known about the addresses below a single prefix
+///
+#[derive(Clone, Default)]
+struct PrefixUsage {
+    publications: AddressForms,
+    subscriptions: AddressForms,
+
+    /// The change and subscription addresses from the first undecided evaluation at this prefix
+    unknown_example: Option<(String, String)>,
+    unknown_evaluations: usize
+}
+
+impl PrefixUsage {
+    ///
+    /// If changes and subscriptions use different forms below this prefix, describes how
+    ///
+    fn risk(&self, prefix: &str) -> Option<RiskyPrefix> {
+        let index_changes   = (&self.publications.index, &self.subscriptions.tag);
+        let tag_changes     = (&self.publications.tag, &self.subscriptions.index);
+
+        let example = match (index_changes, tag_changes) {
+            _ if self.unknown_example.is_some()         => self.unknown_example.clone(),
+            ((Some(change), Some(subscription)), _)     => Some((change.clone(), subscription.clone())),
+            (_, (Some(change), Some(subscription)))     => Some((change.clone(), subscription.clone())),
+            _                                           => None
+        };
+
+        example.map(|(example_change, example_subscription)| RiskyPrefix {
+            prefix:                 prefix.to_string(),
+            publications:           self.publications.describe(),
+            subscriptions:          self.subscriptions.describe(),
+            example_change,
+            example_subscription,
+            unknown_evaluations:    self.unknown_evaluations as i32
+        })
+    }
+}
+
+///
+/// Converts a list of address parts back into an address
+///
+fn address_from_parts(parts: &[AddressPart]) -> TreeAddress {
+    parts.iter().rev().fold(TreeAddress::Here, |address, part| match *part {
+        AddressPart::Index(index)   => TreeAddress::ChildAtIndex(index, Box::new(address)),
+        AddressPart::Tag(ref tag)   => TreeAddress::ChildWithTag(tag.clone(), Box::new(address))
+    })
+}
+
+///
+/// The running state of an audit
+///
+struct AuditState {
+    /// The maximum number of prefixes to track
+    max_prefixes: usize,
+
+    /// What's known about each prefix, keyed by the prefix formatted as an address
+    prefixes: BTreeMap<String, PrefixUsage>,
+
+    /// The number of addresses that weren't recorded because there were already too many prefixes
+    untracked: usize
+}
+
+impl AuditState {
+    ///
+    /// Retrieves the usage for a prefix, or None if it's not being tracked and there's no room for it
+    ///
+    fn usage(&mut self, prefix: &[AddressPart]) -> Option<&mut PrefixUsage> {
+        let key = address_from_parts(prefix).to_string();
+
+        if !self.prefixes.contains_key(&key) && self.prefixes.len() >= self.max_prefixes {
+            self.untracked += 1;
+            return None;
+        }
+
+        Some(self.prefixes.entry(key).or_default())
+    }
+
+    ///
+    /// Records the form of each part of an address
+    ///
+    fn record_address(&mut self, address: &TreeAddress, is_subscription: bool) {
+        let parts = flatten_address(address);
+
+        for (pos, part) in parts.iter().enumerate() {
+            if let Some(usage) = self.usage(&parts[0..pos]) {
+                let forms = if is_subscription { &mut usage.subscriptions } else { &mut usage.publications };
+                forms.record(part, address);
+            }
+        }
+    }
+
+    ///
+    /// Records an evaluation whose result couldn't be decided, at the point where the two addresses diverge
+    ///
+    fn record_unknown(&mut self, change: &TreeAddress, subscription: &TreeAddress) {
+        let change_parts        = flatten_address(change);
+        let subscription_parts  = flatten_address(subscription);
+        let common              = change_parts.iter().zip(subscription_parts.iter()).take_while(|(a, b)| a == b).count();
+
+        if let Some(usage) = self.usage(&change_parts[0..common]) {
+            usage.unknown_evaluations += 1;
+
+            if usage.unknown_example.is_none() {
+                usage.unknown_example = Some((change.to_string(), subscription.to_string()));
+            }
+        }
+    }
+}
+
+///
+/// Records how the addresses of changes and subscriptions are written, to find where the two forms are mixed
+///
+/// This is a handle: clones of it share the same audit.
+///
+#[derive(Clone)]
+pub struct AddressingAudit {
+    state: Rc<RefCell<AuditState>>
+}
+
+impl AddressingAudit {
+    ///
+    /// Creates an audit that tracks up to the specified number of prefixes
+    ///
+    pub fn new(max_prefixes: usize) -> AddressingAudit {
+        AddressingAudit { state: Rc::new(RefCell::new(AuditState { max_prefixes, prefixes: BTreeMap::new(), untracked: 0 })) }
+    }
+
+    ///
+    /// Records the address of a change that's being delivered
+    ///
+    pub fn record_publication(&self, change: &TreeAddress) {
+        self.state.borrow_mut().record_address(change, false);
+    }
+
+    ///
+    /// Records an evaluation of a change against the address of a subscription, along with its result
+    ///
+    pub fn record_evaluation(&self, change: &TreeAddress, subscription: &TreeAddress, applies: Option<bool>) {
+        let mut state = self.state.borrow_mut();
+
+        state.record_address(subscription, true);
+        if applies.is_none() {
+            state.record_unknown(change, subscription);
+        }
+    }
+
+    ///
+    /// The number of prefixes that are being tracked
+    ///
+    pub fn tracked_prefixes(&self) -> usize {
+        self.state.borrow().prefixes.len()
+    }
+
+    ///
+    /// Creates a report of the prefixes where the forms of address are mixed
+    ///
+    pub fn report(&self) -> AddressingReport {
+        let state = self.state.borrow();
+
+        AddressingReport {
+            risky_prefixes: state.prefixes.iter().filter_map(|(prefix, usage)| usage.risk(prefix)).collect(),
+            untracked:      state.untracked as i32
+        }
+    }
+
+    ///
+    /// Creates a report as a tree (which can be decoded as an `AddressingReport`)
+    ///
+    pub fn as_tree(&self) -> TreeRef {
+        self.report().to_tree_node()
+    }
+}
+
+#[cfg(test)]
+mod addressing_audit_tests {
+    use super::super::super::tree::*;
+    use super::super::bus_publisher::*;
+    use super::*;
+
+    ///
+    /// Creates a bus with an audit and a subscription to each of the specified addresses
+    ///
+    fn audited_bus(audit: &AddressingAudit, subscriptions: Vec<TreeAddress>) -> TreeChangeBus {
+        let mut bus = TreeChangeBus::new();
+        bus.attach_addressing_audit(audit.clone());
+
+        for address in subscriptions {
+            let mut consumer = bus.create_consumer();
+            consumer.subscribe(address, TreeExtent::SubTree, Box::new(|_| { }));
+        }
+
+        bus
+    }
+
+    #[test]
+    fn mixed_addressing_is_reported() {
+        let audit           = AddressingAudit::new(100);
+        let mut bus         = audited_bus(&audit, vec![("items", "first").to_tree_address(), "other".to_tree_address()]);
+        let mut publisher   = bus.create_publisher();
+
+        publisher.publish(TreeChange::new(&("items", 0), &("first", 1)));
+        publisher.publish(TreeChange::new(&("items", 0), &("first", 2)));
+        publisher.publish(TreeChange::new(&("other", "value"), &("value", 3)));
+        bus.flush();
+
+        let report = audit.report();
+        assert!(report.risky_prefixes.len() == 1);
+
+        let risky = &report.risky_prefixes[0];
+        assert!(risky.prefix == ".\"items\".");
+        assert!(risky.publications == "index");
+        assert!(risky.subscriptions == "tag");
+        assert!(risky.example_change == ".\"items\".0.");
+        assert!(risky.example_subscription == ".\"items\".\"first\".");
+        assert!(risky.unknown_evaluations == 2);
+
+        assert!(report.to_string().starts_with(".\"items\".: changes use index, subscriptions use tag (2 undecided evaluations)"));
+    }
+
+    #[test]
+    fn consistent_addressing_reports_nothing() {
+        let audit           = AddressingAudit::new(100);
+        let mut bus         = audited_bus(&audit, vec![("items", "first").to_tree_address(), ("list", 2).to_tree_address()]);
+        let mut publisher   = bus.create_publisher();
+
+        publisher.publish(TreeChange::new(&("items", "first"), &("first", 1)));
+        publisher.publish(TreeChange::new(&("items", "second"), &("second", 1)));
+        publisher.publish(TreeChange::new(&("list", 2), &("x", 1)));
+        bus.flush();
+
+        let report = audit.report();
+        assert!(report.risky_prefixes.is_empty());
+        assert!(report.to_string() == "No mixed addressing found\n");
+    }
+
+    #[test]
+    fn number_of_prefixes_is_bounded() {
+        let audit           = AddressingAudit::new(8);
+        let mut bus         = audited_bus(&audit, vec!["root".to_tree_address()]);
+        let mut publisher   = bus.create_publisher();
+
+        for index in 0..1000 {
+            let tag = format!("item{}", index);
+            publisher.publish(TreeChange::new(&("root", (tag.as_str(), "value")), &("value", index)));
+        }
+        bus.flush();
+
+        assert!(audit.tracked_prefixes() == 8);
+        assert!(audit.report().untracked > 900);
+    }
+
+    #[test]
+    fn tree_decodes_as_report() {
+        let audit           = AddressingAudit::new(100);
+        let mut bus         = audited_bus(&audit, vec![(0, "name").to_tree_address()]);
+        let mut publisher   = bus.create_publisher();
+
+        publisher.publish(TreeChange::new(&("people", "name"), &("name", "alice")));
+        bus.flush();
+
+        let decoded = AddressingReport::new_from_tree(&audit.as_tree()).unwrap();
+        assert!(decoded == audit.report());
+        assert!(decoded.risky_prefixes.len() == 1);
+        assert!(decoded.risky_prefixes[0].prefix == ".");
+    }
+}
@@ -0,0 +1,175 @@
+//
+//   Copyright 2016 Andrew Hunter
+//
+//   Licensed under the Apache License, Version 2.0 (the "License");
+//   you may not use this file except in compliance with the License.
+//   You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+//   Unless required by applicable law or agreed to in writing, software
+//   distributed under the License is distributed on an "AS IS" BASIS,
+//   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//   See the License for the specific language governing permissions and
+//   limitations under the License.
+//
+
+use std::rc::*;
+use std::cell::*;
+
+use super::super::tree::*;
+use super::component::*;
+use super::quarantine::*;
+
+///
+/// The state of a budgeted publisher, shared with the quarantine it releases changes from
+///
+struct BudgetedState {
+    /// Where changes are passed on to
+    target: PublisherRef,
+
+    /// The tree as it is after the changes published so far
+    tree: TreeRef,
+
+    /// The limits on each change
+    budget: ApplyBudget,
+
+    /// The number of changes that were over budget
+    rejected: Rc<Cell<usize>>,
+
+    /// Where changes that are over budget are sent, along with the source name to give them
+    quarantine: Option<(Quarantine, String)>
+}
+
+impl BudgetedState {
+    fn publish(&mut self, change: TreeChange, check_budget: bool) {
+        if check_budget {
+            if let Err(exceeded) = change.check_budget(&self.tree, &self.budget) {
+                self.rejected.set(self.rejected.get() + 1);
+
+                if let Some((ref quarantine, ref source)) = self.quarantine {
+                    quarantine.quarantine(change, &exceeded.to_string(), source);
+                }
+
+                return;
+            }
+        }
+
+        self.tree = change.apply(&self.tree);
+        self.target.publish(change);
+    }
+}
+
+///
+/// A publisher that only passes on changes that are within an `ApplyBudget`
+///
+/// Changes that are over budget are dropped, or sent to a quarantine if one has been attached with
+/// `quarantine_rejected_changes()`.
+///
+pub struct BudgetedPublisher {
+    state: Rc<RefCell<BudgetedState>>
+}
+
+impl BudgetedPublisher {
+    ///
+    /// Creates a publisher that passes on the changes that are within a budget to another publisher
+    ///
+    pub fn new(target: PublisherRef, budget: ApplyBudget) -> Box<BudgetedPublisher> {
+        Box::new(BudgetedPublisher { state: Rc::new(RefCell::new(BudgetedState {
+            target,
+            tree:       "empty".to_tree_node(),
+            budget,
+            rejected:   Rc::new(Cell::new(0)),
+            quarantine: None
+        })) })
+    }
+
+    ///
+    /// Sends the changes that are over budget to a quarantine instead of dropping them
+    ///
+    /// When they're released, the changes are published through this publisher again: normally they're checked
+    /// against the budget again, but with a bypass they're passed on regardless.
+    ///
+    pub fn quarantine_rejected_changes(&mut self, quarantine: &Quarantine, source: &str) {
+        let state = Rc::downgrade(&self.state);
+
+        quarantine.add_source(source, Box::new(move |change, bypass_budget| {
+            if let Some(state) = state.upgrade() {
+                state.borrow_mut().publish(change, !bypass_budget);
+            }
+        }));
+
+        self.state.borrow_mut().quarantine = Some((quarantine.clone(), source.to_string()));
+    }
+
+    ///
+    /// Retrieves a function that returns the number of changes this publisher has found to be over budget
+    ///
+    pub fn get_rejected_reader(&self) -> Box<dyn Fn() -> usize> {
+        let rejected = self.state.borrow().rejected.clone();
+
+        Box::new(move || rejected.get())
+    }
+}
+
+impl Publisher for BudgetedPublisher {
+    ///
+    /// Publishes a change to the consumers of this component
+    ///
+    fn publish(&mut self, change: TreeChange) {
+        self.state.borrow_mut().publish(change, true);
+    }
+}
+
+#[cfg(test)]
+mod budgeted_publisher_tests {
+    use super::super::super::tree::*;
+    use super::super::super::component::*;
+    use super::super::output_tree_publisher::*;
+    use super::*;
+
+    #[test]
+    fn over_budget_changes_are_dropped() {
+        let output          = OutputTreePublisher::new();
+        let reader          = output.get_tree_reader();
+        let budget          = ApplyBudget { max_new_nodes: 100, max_depth: 10, max_replacement_nodes: 100 };
+        let mut publisher   = BudgetedPublisher::new(output, budget);
+        let rejected        = publisher.get_rejected_reader();
+
+        publisher.publish(TreeChange::new(&(), &tree!("root", ("value", 1))));
+        publisher.publish(TreeChange::new(&1000, &"far_away"));
+        publisher.publish(TreeChange::new(&"value", &("value", 2)));
+
+        assert!(rejected() == 1);
+        assert!(reader().get_child_at("value").get_value().to_int(0) == 2);
+        assert!(reader().iter_children().count() == 1);
+    }
+
+    #[test]
+    fn released_changes_skip_the_budget() {
+        let output          = OutputTreePublisher::new();
+        let reader          = output.get_tree_reader();
+        let budget          = ApplyBudget { max_new_nodes: 100, max_depth: 10, max_replacement_nodes: 100 };
+        let mut publisher   = BudgetedPublisher::new(output, budget);
+        let mut quarantine  = Quarantine::new(10, QuarantineOverflow::RejectNew);
+
+        publisher.quarantine_rejected_changes(&quarantine, "budget");
+        publisher.publish(TreeChange::new(&(), &"root"));
+        publisher.publish(TreeChange::new(&200, &"far_away"));
+
+        let pending = quarantine.pending();
+        assert!(pending.len() == 1);
+        assert!(pending[0].source == "budget");
+        assert!(pending[0].reason == "applying the change would create more than 100 nodes");
+
+        // Releasing without a bypass checks the budget again
+        assert!(quarantine.release(pending[0].id));
+        let pending = quarantine.pending();
+        assert!(pending.len() == 1);
+        assert!(reader().iter_children().count() == 0);
+
+        assert!(quarantine.release_with_bypass(pending[0].id));
+        assert!(quarantine.pending().is_empty());
+        assert!(reader().get_child_at(200).get_tag() == "far_away");
+    }
+}
@@ -25,14 +25,50 @@
 //! order that they arrive. This can be used to aggregate the output of several components
 //! into a single tree.
 //!
+//! Barriers can be added to a bus to inspect changes before they are sent to any consumer.
+//! A barrier can block a change, in which case no consumer will see it. Barriers are run
+//! in priority order (highest first) and evaluation stops at the first barrier that blocks
+//! the change. Ordinary subscriptions are always called after every barrier has passed the
+//! change and have no way to block it.
+//!
+//! Blocked changes are normally dropped, but can be kept in a `Quarantine` instead by calling
+//! `quarantine_blocked_changes()`. Changes released from the quarantine with a bypass are delivered without
+//! being checked by the barriers, but wait their turn in the priority order like any other change.
+//!
+//! Subscriptions can be made in a `SubscriptionScope`, using a consumer from `BusConnection::create_scoped_consumer()`.
+//! When the scope is closed, its subscriptions stop receiving changes and are removed from the bus.
+//!
+//! A `ConvergenceMonitor` can be attached to a bus to keep track of whether or not the feedback between its
+//! consumers is settling down. `flush_until_stable()` uses it to give up on a flush that is diverging.
+//!
+//! An `AddressingAudit` can be attached to record where the changes and the subscriptions on a bus address the
+//! same part of the tree in different ways.
+//!
+//! `enable_consumer_timing()` makes the bus measure how long each subscription takes to process its changes (see
+//! `tametree::component::consumer_timing`), so that slow consumers can be found.
+//!
+//! Each pump delivers the changes with the highest priority (see `TreeChange::priority()`) first, and changes with
+//! the same priority in the order they were published. A change published while a consumer is processing another
+//! change is a consequence of it, so it's given at least the same priority: an urgent change stays urgent as it
+//! passes through a chain of components. So that less urgent changes aren't held up indefinitely, one of them is
+//! let through after every `set_starvation_guard()` changes that are delivered ahead of it.
+//!
 
 use std::rc::*;
 use std::cell::*;
 use std::mem;
+use std::fmt;
+use std::time::Duration;
+use std::collections::{BTreeMap, VecDeque};
 
 use super::super::tree::*;
 use super::component::*;
 use super::subscriptionmanager::*;
+use super::convergence::*;
+use super::adaptive_filter::*;
+use super::quarantine::*;
+use super::addressing_audit::*;
+use super::consumer_timing::*;
 
 ///
 /// A tree change bus queues up published changes until they are ready to send
@@ -43,14 +79,187 @@ pub struct TreeChangeBus {
     waiting: Rc<RefCell<Box<WaitingChanges>>>,
 
     /// Consumers of this publisher
-    subscriptions: Rc<SubscriptionManager<ConsumerRegistration>>
+    subscriptions: Rc<SubscriptionManager<ConsumerRegistration>>,
+
+    /// Barriers that are checked before changes are sent to the consumers, in priority order
+    barriers: Vec<Barrier>,
+
+    /// Monitors the changes generated by each pump
+    convergence: Option<ConvergenceMonitor>,
+
+    /// The settings for the adaptive filters to attach to new subscriptions, if they're enabled
+    adaptive: Rc<Cell<Option<AdaptiveFilterSettings>>>,
+
+    /// Where blocked changes are sent, along with the source name to give them, instead of dropping them
+    quarantine: Option<(Quarantine, String)>,
+
+    /// Records the addresses of the changes and subscriptions that are evaluated
+    audit: Option<AddressingAudit>,
+
+    /// Measures how long the subscriptions take, if enabled
+    timing: ConsumerTiming,
+
+    /// The number of changes delivered ahead of a lower priority change before one is let through (0 for no limit)
+    starvation_guard: usize,
+
+    /// The number of changes that have been delivered in a row while lower priority changes were waiting
+    priority_streak: usize
+}
+
+///
+/// The result of a barrier inspecting a change
+///
+#[derive(Clone, PartialEq, Debug)]
+pub enum BarrierVerdict {
+    /// The change can be sent on to the next barrier or the consumers
+    Pass,
+
+    /// The change should be dropped, for the specified reason
+    Block(String)
+}
+
+///
+/// Callback used to decide whether or not a change should be sent to the consumers of a bus
+///
+pub type BarrierCallback = Box<dyn FnMut(&TreeChange) -> BarrierVerdict>;
+
+///
+/// A barrier registered with a bus
+///
+struct Barrier {
+    priority: i32,
+    registration: ConsumerRegistration,
+    callback: BarrierCallback
+}
+
+///
+/// Statistics describing what happened to the changes sent by a pump or a flush
+///
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct PumpStats {
+    /// The number of changes that were sent to at least one subscription
+    pub delivered: usize,
+
+    /// The number of changes that were dropped by a barrier
+    pub blocked: usize,
+
+    /// The reasons given by the barriers for the blocked changes, in the order they were blocked
+    pub block_reasons: Vec<String>,
+
+    /// The time spent in the subscriptions (zero unless consumer timing is enabled)
+    pub consumer_time: Duration,
+
+    /// The number of subscriptions that were slow at the end of the pump (see `ConsumerTiming::slow_consumers()`)
+    pub slow_consumers: usize
+}
+
+///
+/// The reason that `flush_until_stable()` stopped before the bus was empty
+///
+#[derive(Clone, PartialEq, Debug)]
+pub struct FlushAborted {
+    /// What happened to the changes that were sent before the flush stopped
+    pub stats: PumpStats,
+
+    /// The number of generations that were pumped
+    pub generations: usize,
+
+    /// The convergence status when the flush stopped (`Unknown` if there is no monitor)
+    pub status: ConvergenceStatus,
+
+    /// The recent generations recorded by the convergence monitor
+    pub history: Vec<GenerationCounts>
+}
+
+impl fmt::Display for FlushAborted {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let generated: Vec<String> = self.history.iter().map(|counts| counts.generated.to_string()).collect();
+
+        write!(f, "flush stopped after {} generations with status {:?} (recently generated: {})", self.generations, self.status, generated.join(", "))
+    }
+}
+
+///
+/// A change waiting to be sent
+///
+struct WaitingChange {
+    change: TreeChange,
+
+    /// False for changes released from a quarantine, which are sent without checking the barriers
+    check_barriers: bool
 }
 
 ///
 /// Changes waiting to be sent
 ///
 struct WaitingChanges {
-    waiting: Vec<Box<TreeChange>>
+    waiting: Vec<WaitingChange>,
+
+    /// True while these changes are being published by the consumers of a pump
+    pumping: bool,
+
+    /// The number of changes that were published while pumping
+    generated: usize,
+
+    /// The priority of the change that's being delivered, if there is one: changes published while it's being
+    /// delivered are its consequences and are given at least the same priority
+    cause: Option<i32>
+}
+
+impl WaitingChanges {
+    fn new(pumping: bool) -> Box<WaitingChanges> {
+        Box::new(WaitingChanges { waiting: vec![], pumping, generated: 0, cause: None })
+    }
+}
+
+///
+/// Puts the changes waiting to be delivered in priority order, keeping the order they were published in for each
+/// priority
+///
+/// After `guard` changes in a row have been put ahead of a change with a lower priority, the earliest of the lower
+/// priority changes is put next. `streak` is the number of changes in the current run, which continues between pumps.
+///
+fn priority_order(changes: Vec<WaitingChange>, guard: usize, streak: &mut usize) -> Vec<WaitingChange> {
+    // Usually every change has the same priority, so there's nothing to reorder
+    let first_priority = changes.first().map(|waiting| waiting.change.priority());
+    if changes.iter().all(|waiting| Some(waiting.change.priority()) == first_priority) {
+        *streak = 0;
+        return changes;
+    }
+
+    let mut ordered = Vec::with_capacity(changes.len());
+    let mut queues: BTreeMap<i32, VecDeque<(usize, WaitingChange)>> = BTreeMap::new();
+
+    for (sequence, waiting) in changes.into_iter().enumerate() {
+        queues.entry(waiting.change.priority()).or_default().push_back((sequence, waiting));
+    }
+
+    while let Some(&highest) = queues.keys().next_back() {
+        let lower_waiting = queues.len() > 1;
+
+        let priority = if lower_waiting && guard > 0 && *streak >= guard {
+            // Let the earliest lower priority change through
+            *streak = 0;
+            queues.iter()
+                .filter(|(priority, _)| **priority != highest)
+                .min_by_key(|(_, queue)| queue.front().map(|(sequence, _)| *sequence))
+                .map(|(priority, _)| *priority)
+                .unwrap_or(highest)
+        } else {
+            *streak = if lower_waiting { *streak + 1 } else { 0 };
+            highest
+        };
+
+        if let Some(queue) = queues.get_mut(&priority) {
+            ordered.extend(queue.pop_front().map(|(_, change)| change));
+
+            if queue.is_empty() {
+                queues.remove(&priority);
+            }
+        }
+    }
+
+    ordered
 }
 
 ///
@@ -59,14 +268,73 @@ struct WaitingChanges {
 #[derive(Clone)]
 struct ConsumerRegistration {
     address: TreeAddress,
-    extent: TreeExtent
+    extent: TreeExtent,
+    filter: Option<Rc<RefCell<AdaptiveFilter>>>,
+    scope: Option<Weak<()>>
+}
+
+impl ConsumerRegistration {
+    ///
+    /// True if the scope this registration was made in has not been closed
+    ///
+    fn is_open(&self) -> bool {
+        self.scope.as_ref().map(|scope| scope.upgrade().is_some()).unwrap_or(true)
+    }
+}
+
+///
+/// A group of subscriptions to a bus that are removed together when the scope is closed (or dropped)
+///
+pub struct SubscriptionScope {
+    token: Rc<()>
+}
+
+impl Default for SubscriptionScope {
+    fn default() -> SubscriptionScope {
+        SubscriptionScope::new()
+    }
+}
+
+impl SubscriptionScope {
+    ///
+    /// Creates a new subscription scope
+    ///
+    pub fn new() -> SubscriptionScope {
+        SubscriptionScope { token: Rc::new(()) }
+    }
+
+    ///
+    /// Closes this scope, so that the subscriptions made in it stop receiving changes
+    ///
+    pub fn close(self) {
+        // Subscriptions only keep a weak reference to the token, so dropping it closes the scope
+    }
+}
+
+///
+/// A connection to a bus, which can create publishers and consumers without needing to borrow it
+///
+#[derive(Clone)]
+pub struct BusConnection {
+    waiting: Rc<RefCell<Box<WaitingChanges>>>,
+    subscriptions: Rc<SubscriptionManager<ConsumerRegistration>>,
+    adaptive: Rc<Cell<Option<AdaptiveFilterSettings>>>,
+    timing: ConsumerTiming
 }
 
 ///
 /// A consumer that receives changes from a TreeChangeBus
 ///
 struct BusConsumer {
-    subscriptions: Rc<SubscriptionManager<ConsumerRegistration>>
+    subscriptions: Rc<SubscriptionManager<ConsumerRegistration>>,
+    adaptive: Rc<Cell<Option<AdaptiveFilterSettings>>>,
+    scope: Option<Weak<()>>,
+
+    /// Measures the subscriptions made by this consumer
+    timing: ConsumerTiming,
+
+    /// The name of the component that the subscriptions are made for, if known
+    component: Option<String>
 }
 
 ///
@@ -74,7 +342,10 @@ struct BusConsumer {
 ///
 struct BusPublisher {
     /// Changes that are waiting to be published
-    waiting: Rc<RefCell<Box<WaitingChanges>>>
+    waiting: Rc<RefCell<Box<WaitingChanges>>>,
+
+    /// If this publisher was created in a scope, it stops publishing when the scope is closed
+    scope: Option<Weak<()>>
 }
 
 impl TreeChangeBus {
@@ -83,33 +354,193 @@ impl TreeChangeBus {
     ///
     pub fn new() -> TreeChangeBus {
         TreeChangeBus { 
-            waiting:        Rc::new(RefCell::new(Box::new(WaitingChanges { waiting: vec![] }))),
-            subscriptions:  Rc::new(SubscriptionManager::new())
+            waiting:        Rc::new(RefCell::new(WaitingChanges::new(false))),
+            subscriptions:  Rc::new(SubscriptionManager::new()),
+            barriers:       vec![],
+            convergence:    None,
+            adaptive:       Rc::new(Cell::new(None)),
+            quarantine:     None,
+            audit:          None,
+            timing:         ConsumerTiming::new(),
+            starvation_guard:   8,
+            priority_streak:    0
+        }
+    }
+
+    ///
+    /// Sets the number of changes that can be delivered ahead of a lower priority change before it's let through
+    ///
+    /// The default is 8. With a value of 0, changes are always delivered in priority order.
+    ///
+    pub fn set_starvation_guard(&mut self, guard: usize) {
+        self.starvation_guard = guard;
+    }
+
+    ///
+    /// Sends the changes blocked by the barriers on this bus to a quarantine instead of dropping them
+    ///
+    /// The changes are recorded as coming from the specified source. When they're released, they're published to
+    /// this bus again: normally they're checked by the barriers again, but with a bypass they're delivered without
+    /// being checked. Blocked changes are still counted in the pump statistics.
+    ///
+    pub fn quarantine_blocked_changes(&mut self, quarantine: &Quarantine, source: &str) {
+        let waiting = self.waiting.clone();
+
+        quarantine.add_source(source, Box::new(move |change, bypass_barriers| {
+            let mut waiting = waiting.borrow_mut();
+
+            waiting.waiting.push(WaitingChange { change, check_barriers: !bypass_barriers });
+        }));
+
+        self.quarantine = Some((quarantine.clone(), source.to_string()));
+    }
+
+    ///
+    /// Attaches a monitor that records the changes generated by each pump of this bus
+    ///
+    pub fn attach_convergence_monitor(&mut self, monitor: ConvergenceMonitor) {
+        self.convergence = Some(monitor);
+    }
+
+    ///
+    /// Retrieves the convergence monitor attached to this bus, if there is one
+    ///
+    pub fn convergence_monitor(&self) -> Option<&ConvergenceMonitor> {
+        self.convergence.as_ref()
+    }
+
+    ///
+    /// Attaches an audit that records the addresses of the changes sent by this bus and of the subscriptions they're
+    /// evaluated against
+    ///
+    pub fn attach_addressing_audit(&mut self, audit: AddressingAudit) {
+        self.audit = Some(audit);
+    }
+
+    ///
+    /// Starts measuring how long each subscription to this bus takes to process its changes
+    ///
+    /// This applies to the subscriptions that already exist as well as to new ones.
+    ///
+    pub fn enable_consumer_timing(&mut self, clock: Rc<dyn TimingClock>, settings: ConsumerTimingSettings) {
+        self.timing.enable(clock, settings);
+    }
+
+    ///
+    /// Stops measuring the subscriptions to this bus (the measurements made so far are kept)
+    ///
+    pub fn disable_consumer_timing(&mut self) {
+        self.timing.disable();
+    }
+
+    ///
+    /// The measurements of how long the subscriptions to this bus take
+    ///
+    pub fn consumer_timing(&self) -> ConsumerTiming {
+        self.timing.clone()
+    }
+
+    ///
+    /// Adds a barrier that is checked before any consumer receives a change affecting the specified part of the tree
+    ///
+    /// Barriers with a higher priority are checked first. Barriers with the same priority are checked in the
+    /// order they were added. As for consumers, the change passed to the barrier is relative to the address.
+    ///
+    pub fn add_barrier(&mut self, priority: i32, address: TreeAddress, extent: TreeExtent, callback: BarrierCallback) {
+        let insert_index = self.barriers.iter().position(|barrier| barrier.priority < priority).unwrap_or(self.barriers.len());
+
+        self.barriers.insert(insert_index, Barrier { priority, registration: ConsumerRegistration { address, extent, filter: None, scope: None }, callback });
+    }
+
+    ///
+    /// Checks a change against the barriers, returning the verdict of the first one to block it
+    ///
+    fn check_barriers(&mut self, change: &TreeChange) -> BarrierVerdict {
+        for barrier in self.barriers.iter_mut() {
+            if !change.applies_to(&barrier.registration.address, &barrier.registration.extent).unwrap_or(false) {
+                continue;
+            }
+
+            if let Some(relative_change) = change.relative_to(&barrier.registration.address) {
+                let verdict = (barrier.callback)(&relative_change);
+
+                if verdict != BarrierVerdict::Pass {
+                    return verdict;
+                }
+            }
         }
+
+        BarrierVerdict::Pass
     }
 
     ///
     /// Creates a publisher that will send notifications to this object
     ///
     pub fn create_publisher(&self) -> PublisherRef {
-        Box::new(BusPublisher { waiting: self.waiting.to_owned() })
+        Box::new(BusPublisher { waiting: self.waiting.to_owned(), scope: None })
     }
 
     ///
     /// Creates a consumer that will receive notifications from this publisher
     ///
     pub fn create_consumer(&self) -> ConsumerRef {
-        Box::new(BusConsumer { subscriptions: self.subscriptions.clone() })
+        self.connection().create_consumer()
+    }
+
+    ///
+    /// Creates a consumer for a named component, whose subscriptions are labelled with the name in the consumer timings
+    ///
+    pub fn create_named_consumer(&self, component: &str) -> ConsumerRef {
+        Box::new(BusConsumer { subscriptions: self.subscriptions.clone(), adaptive: self.adaptive.clone(), scope: None, timing: self.timing.clone(), component: Some(component.to_string()) })
+    }
+
+    ///
+    /// Creates a connection to this bus, which can be used to create publishers and consumers later on
+    ///
+    pub fn connection(&self) -> BusConnection {
+        BusConnection { waiting: self.waiting.clone(), subscriptions: self.subscriptions.clone(), adaptive: self.adaptive.clone(), timing: self.timing.clone() }
+    }
+
+    ///
+    /// The number of subscriptions to this bus (after removing any whose scope has been closed)
+    ///
+    pub fn subscription_count(&self) -> usize {
+        self.connection().remove_closed_subscriptions();
+        self.subscriptions.subscription_data().len()
+    }
+
+    ///
+    /// Attaches an adaptive filter to the subscriptions made after this is called
+    ///
+    pub fn enable_adaptive_filtering(&mut self, settings: AdaptiveFilterSettings) {
+        self.adaptive.set(Some(settings));
+    }
+
+    ///
+    /// Retrieves the statistics for each subscription that has an adaptive filter
+    ///
+    pub fn adaptive_filter_stats(&self) -> Vec<(TreeAddress, AdaptiveFilterStats)> {
+        self.subscriptions.subscription_data().iter()
+            .filter_map(|registration| registration.filter.as_ref().map(|filter| { let filter = filter.borrow(); (filter.address().clone(), filter.stats()) }))
+            .collect()
+    }
+
+    ///
+    /// True if there are no changes waiting to be pumped
+    ///
+    pub fn is_quiescent(&self) -> bool {
+        let waiting = self.waiting.borrow();
+        waiting.waiting.is_empty()
     }
 
     ///
     /// Pumps any published messages to the consumer
     ///
-    pub fn pump(&mut self) {
+    pub fn pump(&mut self) -> PumpStats {
         // Create a new list of waiting items and swap it for the active list
         let to_send = {
             let mut borrowed_waiting    = self.waiting.borrow_mut();
-            let mut current_value       = Box::new(WaitingChanges { waiting: vec![] });
+            let mut current_value       = WaitingChanges::new(true);
 
             mem::swap(&mut *borrowed_waiting, &mut current_value);
 
@@ -117,35 +548,197 @@ impl TreeChangeBus {
         };
 
         // Publish the items in to_send
-        for change in to_send.waiting {
-            self.subscriptions.call_subscriptions(&|registration| {
-                change.applies_to(&registration.address, &registration.extent).unwrap_or(false)
+        let mut stats   = PumpStats::default();
+        let external    = to_send.waiting.len() - to_send.generated;
+
+        for WaitingChange { change, check_barriers } in priority_order(to_send.waiting, self.starvation_guard, &mut self.priority_streak) {
+            // Changes blocked by a barrier are not sent to any consumer
+            if check_barriers {
+                if let BarrierVerdict::Block(reason) = self.check_barriers(&change) {
+                    if let Some((ref quarantine, ref source)) = self.quarantine {
+                        quarantine.quarantine(change, &reason, source);
+                    }
+
+                    stats.blocked += 1;
+                    stats.block_reasons.push(reason);
+                    continue;
+                }
+            }
+
+            let flat = if self.adaptive.get().is_some() { flatten_address(change.address()) } else { vec![] };
+            let audit = self.audit.as_ref();
+
+            if let Some(audit) = audit {
+                audit.record_publication(change.address());
+            }
+
+            self.waiting.borrow_mut().cause = Some(change.priority());
+            let called = self.subscriptions.call_subscriptions(&|registration| {
+                if !registration.is_open() {
+                    return false;
+                }
+
+                match registration.filter {
+                    Some(ref filter)    => filter.borrow_mut().should_deliver(&change, &flat),
+                    None                => {
+                        let applies = change.applies_to(&registration.address, &registration.extent);
+
+                        if let Some(audit) = audit {
+                            audit.record_evaluation(change.address(), &registration.address, applies);
+                        }
+
+                        applies.unwrap_or(false)
+                    }
+                }
             }, &change);
+
+            if called > 0 {
+                stats.delivered += 1;
+            }
+        }
+
+        // Anything published while the consumers were running was generated by this pump
+        let generated = {
+            let mut waiting = self.waiting.borrow_mut();
+            waiting.pumping = false;
+            waiting.cause   = None;
+            waiting.generated
+        };
+
+        if let Some(ref mut convergence) = self.convergence {
+            convergence.record(GenerationCounts { external, generated });
         }
+
+        let (consumer_time, slow_consumers) = self.timing.end_pump();
+        stats.consumer_time     = consumer_time;
+        stats.slow_consumers    = slow_consumers;
+
+        stats
     }
 
     ///
     /// Pumps published messages to the consumer repeatedly until there are none left to process
     ///
-    pub fn flush(&mut self) {
+    pub fn flush(&mut self) -> PumpStats {
+        let mut stats = PumpStats::default();
+
         // Pump published messages until no more are generated
         loop {
-            if self.waiting.borrow().waiting.len() <= 0 {
-                return;
+            if self.is_quiescent() {
+                return stats;
+            }
+
+            let pumped = self.pump();
+            stats.delivered += pumped.delivered;
+            stats.blocked   += pumped.blocked;
+            stats.block_reasons.extend(pumped.block_reasons);
+            stats.consumer_time += pumped.consumer_time;
+            stats.slow_consumers = pumped.slow_consumers;
+        }
+    }
+
+    ///
+    /// Pumps published messages until there are none left, giving up if the attached convergence monitor
+    /// decides that the bus is diverging or if the specified number of generations is reached
+    ///
+    pub fn flush_until_stable(&mut self, max_generations: usize) -> Result<PumpStats, FlushAborted> {
+        let mut stats       = PumpStats::default();
+        let mut generations = 0;
+
+        loop {
+            if self.is_quiescent() {
+                return Ok(stats);
+            }
+
+            let status      = self.convergence.as_ref().map(|monitor| monitor.status()).unwrap_or(ConvergenceStatus::Unknown);
+            let diverging = matches!(status, ConvergenceStatus::Diverging { .. });
+
+            if diverging || generations >= max_generations {
+                let history = self.convergence.as_ref().map(|monitor| monitor.history()).unwrap_or_default();
+
+                return Err(FlushAborted { stats, generations, status, history });
             }
 
-            self.pump();
+            let pumped = self.pump();
+            stats.delivered += pumped.delivered;
+            stats.blocked   += pumped.blocked;
+            stats.block_reasons.extend(pumped.block_reasons);
+            stats.consumer_time += pumped.consumer_time;
+            stats.slow_consumers = pumped.slow_consumers;
+            generations += 1;
         }
     }
 }
 
+impl BusConnection {
+    ///
+    /// Creates a publisher that will send notifications to the bus
+    ///
+    pub fn create_publisher(&self) -> PublisherRef {
+        Box::new(BusPublisher { waiting: self.waiting.clone(), scope: None })
+    }
+
+    ///
+    /// Creates a consumer that will receive notifications from the bus
+    ///
+    pub fn create_consumer(&self) -> ConsumerRef {
+        Box::new(BusConsumer { subscriptions: self.subscriptions.clone(), adaptive: self.adaptive.clone(), scope: None, timing: self.timing.clone(), component: None })
+    }
+
+    ///
+    /// Creates a publisher that stops sending notifications to the bus once a scope is closed
+    ///
+    pub fn create_scoped_publisher(&self, scope: &SubscriptionScope) -> PublisherRef {
+        Box::new(BusPublisher { waiting: self.waiting.clone(), scope: Some(Rc::downgrade(&scope.token)) })
+    }
+
+    ///
+    /// Creates a consumer whose subscriptions are removed from the bus when a scope is closed
+    ///
+    pub fn create_scoped_consumer(&self, scope: &SubscriptionScope) -> ConsumerRef {
+        Box::new(BusConsumer { subscriptions: self.subscriptions.clone(), adaptive: self.adaptive.clone(), scope: Some(Rc::downgrade(&scope.token)), timing: self.timing.clone(), component: None })
+    }
+
+    ///
+    /// Creates a consumer for a named component whose subscriptions are removed from the bus when a scope is closed
+    ///
+    pub fn create_named_scoped_consumer(&self, scope: &SubscriptionScope, component: &str) -> ConsumerRef {
+        Box::new(BusConsumer { subscriptions: self.subscriptions.clone(), adaptive: self.adaptive.clone(), scope: Some(Rc::downgrade(&scope.token)), timing: self.timing.clone(), component: Some(component.to_string()) })
+    }
+
+    ///
+    /// Removes the subscriptions whose scope has been closed, returning the number that were removed
+    ///
+    pub fn remove_closed_subscriptions(&self) -> usize {
+        self.subscriptions.retain(&|registration| registration.is_open())
+    }
+}
+
 impl Publisher for BusPublisher {
     ///
     /// Publishes a change to the consumers of this component
     ///
     #[inline]
     fn publish(&mut self, change: TreeChange) {
-        self.waiting.borrow_mut().waiting.push(Box::new(change))
+        if let Some(ref scope) = self.scope {
+            if scope.upgrade().is_none() {
+                return;
+            }
+        }
+
+        let mut waiting = self.waiting.borrow_mut();
+
+        if waiting.pumping {
+            waiting.generated += 1;
+        }
+
+        // Consequences of a change are at least as urgent as the change itself
+        let change = match waiting.cause {
+            Some(cause) if cause > change.priority()    => change.with_priority(cause),
+            _                                           => change
+        };
+
+        waiting.waiting.push(WaitingChange { change, check_barriers: true })
     }
 }
 
@@ -157,12 +750,25 @@ impl Consumer for BusConsumer {
         // Need to persuade rust that it can call the FnMut (assign parameter to a mutable variable)
         let mut also_callback = callback;
 
-        self.subscriptions.add_subscription(ConsumerRegistration { address: address.clone(), extent: extent }, Box::new(move |change| {
+        let filter = self.adaptive.get().map(|settings| Rc::new(RefCell::new(AdaptiveFilter::new(&address, extent, settings))));
+
+        let timing          = self.timing.clone();
+        let component       = self.component.clone();
+        let subscription    = timing.allocate_subscription();
+
+        self.subscriptions.add_subscription(ConsumerRegistration { address: address.clone(), extent, filter, scope: self.scope.clone() }, Box::new(move |change| {
             // The change we get from the subscription will have an address relative to the root of the tree
             // Make the subscription change relative to the address that was subscribed to 
             let maybe_relative_change = change.relative_to(&address);
             if let Some(relative_change) = maybe_relative_change {
-                also_callback(&relative_change);
+                // The clock is only read if timing is enabled
+                match timing.start() {
+                    None            => also_callback(&relative_change),
+                    Some(started)   => {
+                        also_callback(&relative_change);
+                        timing.finish(subscription, &address, &component, started);
+                    }
+                }
             }
         }));
     }
@@ -170,6 +776,10 @@ impl Consumer for BusConsumer {
 
 #[cfg(test)]
 mod bus_publisher_tests {
+    use std::rc::*;
+    use std::cell::*;
+
+    use super::super::super::tree::*;
     use super::super::super::component::*;
     use super::super::output_tree_publisher::*;
     use super::*;
@@ -223,4 +833,408 @@ mod bus_publisher_tests {
         input_bus.flush();
         assert!(output_reader().get_value().to_int(0) == 0);
     }
+
+    #[test]
+    pub fn blocking_barrier_prevents_delivery() {
+        let mut input_bus           = TreeChangeBus::new();
+        let mut input_publisher     = input_bus.create_publisher();
+        let output_publisher        = OutputTreePublisher::new();
+        let input_consumer          = input_bus.create_consumer();
+        let output_reader           = output_publisher.get_tree_reader();
+        let add_one                 = component_fn(|x: &i32| { x+1 });
+
+        let _add_component          = add_one.into_component(input_consumer, output_publisher);
+
+        // Block any negative number
+        input_bus.add_barrier(0, TreeAddress::Here, TreeExtent::SubTree, Box::new(|change| {
+            let value = change.apply(&"".to_tree_node()).get_value().to_int(0);
+
+            if value < 0 { BarrierVerdict::Block("negative".to_string()) } else { BarrierVerdict::Pass }
+        }));
+
+        input_publisher.publish(TreeChange::new(&(), &1));
+        let stats = input_bus.pump();
+        assert!(output_reader().get_value().to_int(0) == 2);
+        assert!(stats.delivered == 1);
+        assert!(stats.blocked == 0);
+
+        input_publisher.publish(TreeChange::new(&(), &-5));
+        let stats = input_bus.pump();
+        assert!(output_reader().get_value().to_int(0) == 2);
+        assert!(stats.delivered == 0);
+        assert!(stats.blocked == 1);
+        assert!(stats.block_reasons == vec!["negative".to_string()]);
+    }
+
+    #[test]
+    fn changes_without_subscribers_are_not_counted_as_delivered() {
+        let mut bus         = TreeChangeBus::new();
+        let mut publisher   = bus.create_publisher();
+        let mut consumer    = bus.create_consumer();
+
+        consumer.subscribe("watched".to_tree_address(), TreeExtent::SubTree, Box::new(|_| { }));
+
+        publisher.publish(TreeChange::new(&"watched", &1));
+        publisher.publish(TreeChange::new(&"ignored", &2));
+        let stats = bus.pump();
+
+        assert!(stats.delivered == 1);
+        assert!(stats.blocked == 0);
+    }
+
+    #[test]
+    pub fn passing_barrier_allows_delivery() {
+        let mut input_bus           = TreeChangeBus::new();
+        let mut input_publisher     = input_bus.create_publisher();
+        let mut input_consumer      = input_bus.create_consumer();
+
+        let barrier_count           = Rc::new(Cell::new(0));
+        let delivered_count         = Rc::new(Cell::new(0));
+        let their_barrier_count     = barrier_count.clone();
+        let their_delivered_count   = delivered_count.clone();
+
+        input_bus.add_barrier(0, TreeAddress::Here, TreeExtent::SubTree, Box::new(move |_change| {
+            their_barrier_count.set(their_barrier_count.get() + 1);
+            BarrierVerdict::Pass
+        }));
+        input_consumer.subscribe(TreeAddress::Here, TreeExtent::SubTree, Box::new(move |_change| {
+            their_delivered_count.set(their_delivered_count.get() + 1);
+        }));
+
+        input_publisher.publish(TreeChange::new(&(), &1));
+        input_publisher.publish(TreeChange::new(&(), &2));
+        let stats = input_bus.flush();
+
+        assert!(barrier_count.get() == 2);
+        assert!(delivered_count.get() == 2);
+        assert!(stats == PumpStats { delivered: 2, blocked: 0, block_reasons: vec![], ..PumpStats::default() });
+    }
+
+    #[test]
+    pub fn barriers_run_in_priority_order() {
+        let mut input_bus           = TreeChangeBus::new();
+        let mut input_publisher     = input_bus.create_publisher();
+
+        let order                   = Rc::new(RefCell::new(vec![]));
+        let low_order               = order.clone();
+        let high_order              = order.clone();
+        let blocking_order          = order.clone();
+
+        // Added in the opposite order to their priority
+        input_bus.add_barrier(1, TreeAddress::Here, TreeExtent::SubTree, Box::new(move |_change| {
+            low_order.borrow_mut().push("low");
+            BarrierVerdict::Pass
+        }));
+        input_bus.add_barrier(10, TreeAddress::Here, TreeExtent::SubTree, Box::new(move |_change| {
+            high_order.borrow_mut().push("high");
+            BarrierVerdict::Pass
+        }));
+
+        input_publisher.publish(TreeChange::new(&(), &1));
+        input_bus.pump();
+        assert!(*order.borrow() == vec!["high", "low"]);
+
+        // A blocking barrier between the two stops the lower priority one from seeing the change
+        order.borrow_mut().clear();
+        input_bus.add_barrier(5, TreeAddress::Here, TreeExtent::SubTree, Box::new(move |_change| {
+            blocking_order.borrow_mut().push("blocking");
+            BarrierVerdict::Block("blocked".to_string())
+        }));
+
+        input_publisher.publish(TreeChange::new(&(), &1));
+        input_bus.pump();
+        assert!(*order.borrow() == vec!["high", "blocking"]);
+    }
+
+    #[test]
+    pub fn barrier_only_sees_changes_it_applies_to() {
+        let mut input_bus           = TreeChangeBus::new();
+        let mut input_publisher     = input_bus.create_publisher();
+        let mut consumer            = input_bus.create_consumer();
+
+        consumer.subscribe(TreeAddress::Here, TreeExtent::SubTree, Box::new(|_| { }));
+        input_bus.add_barrier(0, "protected".to_tree_address(), TreeExtent::SubTree, Box::new(|_change| BarrierVerdict::Block("protected".to_string())));
+
+        input_publisher.publish(TreeChange::new(&"other", &1));
+        input_publisher.publish(TreeChange::new(&("protected", "child"), &1));
+        let stats = input_bus.pump();
+
+        assert!(stats.delivered == 1);
+        assert!(stats.blocked == 1);
+    }
+
+    #[test]
+    pub fn subscriptions_cannot_block() {
+        let mut input_bus           = TreeChangeBus::new();
+        let mut input_publisher     = input_bus.create_publisher();
+        let mut first_consumer      = input_bus.create_consumer();
+        let mut second_consumer     = input_bus.create_consumer();
+
+        let delivered_count         = Rc::new(Cell::new(0));
+        let first_count             = delivered_count.clone();
+        let second_count            = delivered_count.clone();
+
+        first_consumer.subscribe(TreeAddress::Here, TreeExtent::SubTree, Box::new(move |_change| { first_count.set(first_count.get() + 1); }));
+        second_consumer.subscribe(TreeAddress::Here, TreeExtent::SubTree, Box::new(move |_change| { second_count.set(second_count.get() + 1); }));
+
+        input_publisher.publish(TreeChange::new(&(), &1));
+        let stats = input_bus.pump();
+
+        assert!(delivered_count.get() == 2);
+        assert!(stats.blocked == 0);
+    }
+
+    #[test]
+    pub fn feedback_to_zero_converges_then_steadies() {
+        let mut input_bus           = TreeChangeBus::new();
+        let mut input_publisher     = input_bus.create_publisher();
+        let mut feedback_publisher  = input_bus.create_publisher();
+        let output_publisher        = OutputTreePublisher::new();
+        let input_consumer          = input_bus.create_consumer();
+
+        let statuses                = Rc::new(RefCell::new(vec![]));
+        let their_statuses          = statuses.clone();
+        input_bus.attach_convergence_monitor(ConvergenceMonitor::new(4).with_transition_callback(Box::new(move |_old, new| their_statuses.borrow_mut().push(new.clone()))));
+
+        let tend_to_zero            = component_fn_mut(move |x: &i32| {
+            if *x > 0 {
+                feedback_publisher.publish(TreeChange::new(&(), &(x-1)));
+            }
+            *x
+        });
+        let _becomes_zero_component = tend_to_zero.into_component(input_consumer, output_publisher);
+
+        input_publisher.publish(TreeChange::new(&(), &10));
+        assert!(input_bus.flush_until_stable(100).is_ok());
+
+        assert!(*statuses.borrow() == vec![ConvergenceStatus::Converging { rate: 0.0 }, ConvergenceStatus::Steady]);
+        assert!(input_bus.convergence_monitor().unwrap().history().last() == Some(&GenerationCounts { external: 0, generated: 0 }));
+    }
+
+    #[test]
+    pub fn two_cycle_oscillation_is_detected() {
+        let mut input_bus           = TreeChangeBus::new();
+        let mut input_publisher     = input_bus.create_publisher();
+        let mut ping_publisher      = input_bus.create_publisher();
+        let mut pong_publisher      = input_bus.create_publisher();
+        let mut ping_consumer       = input_bus.create_consumer();
+        let mut pong_consumer       = input_bus.create_consumer();
+        let pongs_received          = Rc::new(Cell::new(0));
+
+        input_bus.attach_convergence_monitor(ConvergenceMonitor::new(4));
+
+        // Each ping produces two pongs, and every second pong produces a ping
+        ping_consumer.subscribe("ping".to_tree_address(), TreeExtent::SubTree, Box::new(move |_| {
+            pong_publisher.publish(TreeChange::new(&"pong", &("pong", 1)));
+            pong_publisher.publish(TreeChange::new(&"pong", &("pong", 2)));
+        }));
+        pong_consumer.subscribe("pong".to_tree_address(), TreeExtent::SubTree, Box::new(move |_| {
+            pongs_received.set(pongs_received.get()+1);
+            if pongs_received.get() % 2 == 0 {
+                ping_publisher.publish(TreeChange::new(&"ping", &("ping", 1)));
+            }
+        }));
+
+        input_publisher.publish(TreeChange::new(&"ping", &("ping", 0)));
+        let aborted = input_bus.flush_until_stable(10).unwrap_err();
+
+        assert!(aborted.generations == 10);
+        assert!(aborted.status == ConvergenceStatus::Oscillating { period: 2 });
+    }
+
+    #[test]
+    pub fn diverging_loop_aborts_flush_early() {
+        let mut input_bus           = TreeChangeBus::new();
+        let mut input_publisher     = input_bus.create_publisher();
+        let mut feedback_publisher  = input_bus.create_publisher();
+        let mut consumer            = input_bus.create_consumer();
+
+        let diverged                = Rc::new(Cell::new(false));
+        let their_diverged          = diverged.clone();
+        input_bus.attach_convergence_monitor(ConvergenceMonitor::new(4).with_transition_callback(Box::new(move |_old, new| {
+            if let ConvergenceStatus::Diverging { .. } = *new { their_diverged.set(true); }
+        })));
+
+        // Every change produces two more
+        consumer.subscribe(TreeAddress::Here, TreeExtent::SubTree, Box::new(move |_| {
+            feedback_publisher.publish(TreeChange::new(&(), &1));
+            feedback_publisher.publish(TreeChange::new(&(), &2));
+        }));
+
+        input_publisher.publish(TreeChange::new(&(), &0));
+        let aborted = input_bus.flush_until_stable(1000).unwrap_err();
+
+        assert!(diverged.get());
+        assert!(aborted.generations == 4);
+        assert!(aborted.status == ConvergenceStatus::Diverging { growth: 8.0 });
+        assert!(aborted.history.iter().map(|counts| counts.generated).collect::<Vec<_>>() == vec![2, 4, 8, 16]);
+    }
+
+    #[test]
+    pub fn steady_external_traffic_is_not_divergence() {
+        let mut input_bus           = TreeChangeBus::new();
+        let mut input_publisher     = input_bus.create_publisher();
+        let mut relay_publisher     = input_bus.create_publisher();
+        let mut consumer            = input_bus.create_consumer();
+
+        input_bus.attach_convergence_monitor(ConvergenceMonitor::new(4));
+
+        // Relays each input to an output address (which nothing reacts to)
+        consumer.subscribe("in".to_tree_address(), TreeExtent::SubTree, Box::new(move |change| {
+            relay_publisher.publish(TreeChange::new(&"out", change.replacement()));
+        }));
+
+        for generation in 0..10 {
+            for _ in 0..3 {
+                input_publisher.publish(TreeChange::new(&"in", &("in", generation)));
+            }
+
+            input_bus.pump();
+            let status = input_bus.convergence_monitor().unwrap().status();
+            assert!(!matches!(status, ConvergenceStatus::Diverging { .. }));
+        }
+
+        assert!(input_bus.convergence_monitor().unwrap().history().last() == Some(&GenerationCounts { external: 3, generated: 3 }));
+        assert!(input_bus.flush_until_stable(10).is_ok());
+        assert!(input_bus.convergence_monitor().unwrap().status() == ConvergenceStatus::Steady);
+    }
+
+    ///
+    /// Publishes changes with the specified (value, priority) pairs to an address
+    ///
+    fn publish_with_priorities(publisher: &mut PublisherRef, address: &str, changes: &[(i32, i32)]) {
+        for &(value, priority) in changes {
+            publisher.publish(TreeChange::new(&address, &(address, value)).with_priority(priority));
+        }
+    }
+
+    ///
+    /// Records the value and priority of every change delivered to an address
+    ///
+    fn record_deliveries(bus: &TreeChangeBus, address: &str) -> Rc<RefCell<Vec<(i32, i32)>>> {
+        let delivered   = Rc::new(RefCell::new(vec![]));
+        let record      = delivered.clone();
+
+        bus.create_consumer().subscribe(address.to_tree_address(), TreeExtent::SubTree, Box::new(move |change| {
+            if let TreeReplacement::NewNode(ref node) = *change.replacement() {
+                record.borrow_mut().push((node.get_value().to_int(-1), change.priority()));
+            }
+        }));
+
+        delivered
+    }
+
+    #[test]
+    pub fn changes_are_delivered_in_priority_order() {
+        let mut input_bus       = TreeChangeBus::new();
+        let mut input_publisher = input_bus.create_publisher();
+        let delivered           = record_deliveries(&input_bus, "in");
+
+        input_bus.set_starvation_guard(0);
+        publish_with_priorities(&mut input_publisher, "in", &[(1, 0), (2, 5), (3, 0), (4, 10), (5, 5), (6, 0)]);
+        input_bus.pump();
+
+        // Highest priority first, and in the order they were published within each priority
+        assert!(*delivered.borrow() == vec![(4, 10), (2, 5), (5, 5), (1, 0), (3, 0), (6, 0)]);
+    }
+
+    #[test]
+    pub fn released_changes_wait_for_higher_priority_changes() {
+        let mut input_bus       = TreeChangeBus::new();
+        let mut input_publisher = input_bus.create_publisher();
+        let mut quarantine      = Quarantine::new(10, QuarantineOverflow::RejectNew);
+        let delivered           = record_deliveries(&input_bus, "in");
+
+        input_bus.set_starvation_guard(0);
+        input_bus.quarantine_blocked_changes(&quarantine, "test");
+        input_bus.add_barrier(0, TreeAddress::Here, TreeExtent::SubTree, Box::new(|change| {
+            if change.priority() < 0 { BarrierVerdict::Block("low".to_string()) } else { BarrierVerdict::Pass }
+        }));
+
+        publish_with_priorities(&mut input_publisher, "in", &[(1, -5)]);
+        input_bus.pump();
+        assert!(delivered.borrow().is_empty());
+
+        // The released change bypasses the barrier but is still delivered after the more urgent changes
+        let id = quarantine.pending()[0].id;
+        publish_with_priorities(&mut input_publisher, "in", &[(2, 5)]);
+        assert!(quarantine.release_with_bypass(id));
+        publish_with_priorities(&mut input_publisher, "in", &[(3, 10)]);
+        input_bus.pump();
+
+        assert!(*delivered.borrow() == vec![(3, 10), (2, 5), (1, -5)]);
+    }
+
+    #[test]
+    pub fn consequences_inherit_priority_at_every_hop() {
+        let mut input_bus       = TreeChangeBus::new();
+        let mut input_publisher = input_bus.create_publisher();
+        let mut background      = input_bus.create_publisher();
+        let delivered           = Rc::new(RefCell::new(vec![]));
+
+        // Each hop republishes its input at the next address, with the same priority as a change published directly
+        for &(from, to) in [("in", "middle"), ("middle", "out"), ("out", "")].iter() {
+            let mut relay   = input_bus.create_publisher();
+            let record      = delivered.clone();
+
+            input_bus.create_consumer().subscribe(from.to_tree_address(), TreeExtent::SubTree, Box::new(move |change| {
+                if let TreeReplacement::NewNode(ref node) = *change.replacement() {
+                    let value = node.get_value().to_int(-1);
+                    record.borrow_mut().push((from, value, change.priority()));
+
+                    if !to.is_empty() {
+                        relay.publish(TreeChange::new(&to, &(to, value)));
+                    }
+                }
+            }));
+        }
+
+        // Fill the first two hops with a backlog of normal changes
+        publish_with_priorities(&mut input_publisher, "in", &[(1, 0), (2, 0), (3, 0)]);
+        input_bus.pump();
+        publish_with_priorities(&mut background, "middle", &[(100, 0), (101, 0)]);
+
+        // The urgent change arrives after the backlog at every hop
+        publish_with_priorities(&mut input_publisher, "in", &[(99, 10)]);
+        delivered.borrow_mut().clear();
+
+        let mut first_per_pump = vec![];
+        for _ in 0..3 {
+            input_bus.pump();
+            first_per_pump.push(delivered.borrow()[0]);
+            delivered.borrow_mut().clear();
+        }
+
+        // Each hop delivers the urgent change and its consequences ahead of the older changes, with the same priority
+        assert!(first_per_pump == vec![("in", 99, 10), ("middle", 99, 10), ("out", 99, 10)]);
+    }
+
+    #[test]
+    pub fn starvation_guard_lets_lower_priority_changes_through() {
+        let mut input_bus       = TreeChangeBus::new();
+        let mut input_publisher = input_bus.create_publisher();
+        let delivered           = record_deliveries(&input_bus, "in");
+
+        input_bus.set_starvation_guard(2);
+        publish_with_priorities(&mut input_publisher, "in", &[(1, 0), (10, 5), (11, 5), (12, 5), (13, 5), (2, 0), (14, 5), (15, 5)]);
+        input_bus.pump();
+
+        let values = delivered.borrow().iter().map(|(value, _)| *value).collect::<Vec<_>>();
+        assert!(values == vec![10, 11, 1, 12, 13, 2, 14, 15]);
+    }
+
+    #[test]
+    pub fn subscriptions_see_the_priority_of_a_change() {
+        let mut input_bus       = TreeChangeBus::new();
+        let mut input_publisher = input_bus.create_publisher();
+        let delivered           = Rc::new(RefCell::new(vec![]));
+        let record              = delivered.clone();
+
+        // The change is made relative to the subscription's address on the way
+        input_bus.create_consumer().subscribe("in".to_tree_address(), TreeExtent::SubTree, Box::new(move |change| record.borrow_mut().push(change.priority())));
+        input_publisher.publish(TreeChange::new(&("in", "value"), &("value", 1)).with_priority(3));
+        input_bus.pump();
+
+        assert!(*delivered.borrow() == vec![3]);
+    }
 }
@@ -0,0 +1,457 @@
+//
+//   Copyright 2016 Andrew Hunter
+//
+//   Licensed under the Apache License, Version 2.0 (the "License");
+//   you may not use this file except in compliance with the License.
+//   You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+//   Unless required by applicable law or agreed to in writing, software
+//   distributed under the License is distributed on an "AS IS" BASIS,
+//   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//   See the License for the specific language governing permissions and
+//   limitations under the License.
+//
+
+//!
+//! # Causal ordering
+//!
+//! When changes arrive from several peers, the order they arrive in doesn't say much about the order they were
+//! made in. Each peer can stamp its changes with a version vector using a `CausalStamper`: this records how many
+//! changes the peer had seen from every peer (including itself) when it made the change.
+//!
+//! A `CausalMerger` receives stamped changes from any number of peers and publishes them in an order that respects
+//! these vectors: a change is only published once every change it depends on has been published. Changes that
+//! arrive early wait in a buffer of limited size; if the buffer fills up, the merger reports which change it is
+//! still waiting for.
+//!
+//! Two changes are concurrent if neither peer had seen the other's change when making its own. The merger
+//! reports concurrent changes whose addresses overlap, so a conflict can be resolved deliberately rather than by
+//! whichever change happened to arrive last.
+//!
+
+use std::fmt;
+use std::collections::BTreeMap;
+use std::collections::VecDeque;
+
+use super::super::tree::*;
+use super::component::*;
+
+///
+/// Identifies a peer that produces changes
+///
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct PeerId(pub u32);
+
+///
+/// A version vector records the number of changes seen from each peer
+///
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct VersionVector {
+    versions: BTreeMap<PeerId, u64>
+}
+
+impl VersionVector {
+    ///
+    /// Creates a version vector where no changes have been seen
+    ///
+    pub fn new() -> VersionVector {
+        VersionVector { versions: BTreeMap::new() }
+    }
+
+    ///
+    /// The number of changes seen from a particular peer
+    ///
+    pub fn get(&self, peer: PeerId) -> u64 {
+        self.versions.get(&peer).cloned().unwrap_or(0)
+    }
+
+    ///
+    /// Records one more change from a peer, returning the new count
+    ///
+    pub fn increment(&mut self, peer: PeerId) -> u64 {
+        let version = self.versions.entry(peer).or_insert(0);
+        *version += 1;
+        *version
+    }
+
+    ///
+    /// Updates this vector so it includes every change seen by another vector
+    ///
+    pub fn merge(&mut self, other: &VersionVector) {
+        for (peer, version) in other.versions.iter() {
+            let our_version = self.versions.entry(*peer).or_insert(0);
+            if *our_version < *version {
+                *our_version = *version;
+            }
+        }
+    }
+
+    ///
+    /// True if every change seen by this vector has also been seen by another vector
+    ///
+    pub fn is_covered_by(&self, other: &VersionVector) -> bool {
+        self.versions.iter().all(|(peer, version)| *version <= other.get(*peer))
+    }
+
+    ///
+    /// True if neither vector covers the other
+    ///
+    pub fn is_concurrent_with(&self, other: &VersionVector) -> bool {
+        !self.is_covered_by(other) && !other.is_covered_by(self)
+    }
+}
+
+///
+/// Identifies where a change came from and the changes that were seen before it was made
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct CausalStamp {
+    /// The peer that made the change
+    pub peer: PeerId,
+
+    /// The changes seen by that peer, including this change
+    pub vector: VersionVector
+}
+
+impl CausalStamp {
+    ///
+    /// The position of the change in the sequence of changes from its peer
+    ///
+    pub fn sequence(&self) -> u64 {
+        self.vector.get(self.peer)
+    }
+}
+
+///
+/// A change along with its causal stamp
+///
+pub struct CausalChange {
+    pub stamp: CausalStamp,
+    pub change: TreeChange
+}
+
+///
+/// Stamps the changes made by a peer
+///
+pub struct CausalStamper {
+    peer: PeerId,
+    vector: VersionVector
+}
+
+impl CausalStamper {
+    ///
+    /// Creates a stamper for a particular peer
+    ///
+    pub fn new(peer: PeerId) -> CausalStamper {
+        CausalStamper { peer, vector: VersionVector::new() }
+    }
+
+    ///
+    /// Records that this peer has seen the changes covered by a vector (so later changes will depend on them)
+    ///
+    pub fn observe(&mut self, vector: &VersionVector) {
+        self.vector.merge(vector);
+    }
+
+    ///
+    /// Stamps a new change made by this peer
+    ///
+    pub fn stamp(&mut self, change: TreeChange) -> CausalChange {
+        self.vector.increment(self.peer);
+
+        CausalChange { stamp: CausalStamp { peer: self.peer, vector: self.vector.clone() }, change }
+    }
+}
+
+///
+/// Describes two concurrent changes that affect overlapping parts of the tree
+///
+pub struct ConcurrentChanges {
+    /// The change that was published first
+    pub first: CausalStamp,
+    pub first_address: TreeAddress,
+
+    /// The change that was published second
+    pub second: CausalStamp,
+    pub second_address: TreeAddress
+}
+
+///
+/// Errors that can occur while merging changes
+///
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum CausalError {
+    /// A change couldn't be buffered as the buffer is full. The merger is waiting for change `sequence` from `waiting_for`.
+    BufferOverflow { change: CausalStamp, waiting_for: PeerId, sequence: u64 }
+}
+
+impl fmt::Display for CausalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CausalError::BufferOverflow { ref change, waiting_for, sequence } => {
+                write!(f, "Buffer full when receiving change {} from peer {}: waiting for change {} from peer {}", change.sequence(), change.peer.0, sequence, waiting_for.0)
+            }
+        }
+    }
+}
+
+///
+/// Receives changes from several peers and publishes them in causal order
+///
+pub struct CausalMerger {
+    /// Where changes are published once their dependencies have been published
+    output: PublisherRef,
+
+    /// The changes that have been published so far
+    released: VersionVector,
+
+    /// Changes that are waiting for their dependencies
+    pending: Vec<CausalChange>,
+
+    /// The maximum number of changes that can wait in the buffer
+    capacity: usize,
+
+    /// The most recently published changes, which are checked for conflicts with new changes
+    history: VecDeque<(CausalStamp, TreeAddress)>
+}
+
+impl CausalMerger {
+    ///
+    /// Creates a new merger that publishes to the specified publisher
+    ///
+    /// Up to `capacity` changes can wait for their dependencies. The same number of published changes are
+    /// remembered to check for conflicts.
+    ///
+    pub fn new(output: PublisherRef, capacity: usize) -> CausalMerger {
+        CausalMerger { output, released: VersionVector::new(), pending: vec![], capacity, history: VecDeque::new() }
+    }
+
+    ///
+    /// The changes that have been published so far
+    ///
+    pub fn released(&self) -> &VersionVector {
+        &self.released
+    }
+
+    ///
+    /// The number of changes waiting for their dependencies
+    ///
+    pub fn pending_len(&self) -> usize {
+        self.pending.len()
+    }
+
+    ///
+    /// Finds the first change that a stamp is waiting for, or None if it can be published
+    ///
+    fn missing_dependency(&self, stamp: &CausalStamp) -> Option<(PeerId, u64)> {
+        let next_sequence = self.released.get(stamp.peer) + 1;
+        if stamp.sequence() > next_sequence {
+            return Some((stamp.peer, next_sequence));
+        }
+
+        stamp.vector.versions.iter()
+            .find(|&(peer, version)| *peer != stamp.peer && *version > self.released.get(*peer))
+            .map(|(peer, _)| (*peer, self.released.get(*peer) + 1))
+    }
+
+    ///
+    /// Finds the change that is holding up a stamp: this is a missing dependency that isn't waiting in the buffer
+    ///
+    fn stalled_dependency(&self, stamp: &CausalStamp) -> Option<(PeerId, u64)> {
+        let mut dependency = self.missing_dependency(stamp);
+
+        // Follow the dependencies through the buffer until we reach a change that hasn't arrived
+        while let Some((peer, sequence)) = dependency {
+            match self.pending.iter().find(|waiting| waiting.stamp.peer == peer && waiting.stamp.sequence() == sequence) {
+                Some(waiting)   => dependency = self.missing_dependency(&waiting.stamp),
+                None            => break
+            }
+        }
+
+        dependency
+    }
+
+    ///
+    /// Publishes a change whose dependencies have all been published, returning any conflicts
+    ///
+    fn release(&mut self, change: CausalChange, conflicts: &mut Vec<ConcurrentChanges>) {
+        let address = change.change.address().clone();
+
+        for (stamp, released_address) in self.history.iter() {
+            if !stamp.vector.is_concurrent_with(&change.stamp.vector) {
+                continue;
+            }
+
+            let overlaps = change.change.applies_to(released_address, &TreeExtent::SubTree).unwrap_or(true);
+            if overlaps {
+                conflicts.push(ConcurrentChanges {
+                    first:          stamp.clone(),
+                    first_address:  released_address.clone(),
+                    second:         change.stamp.clone(),
+                    second_address: address.clone()
+                });
+            }
+        }
+
+        self.released.merge(&change.stamp.vector);
+
+        self.history.push_back((change.stamp, address));
+        while self.history.len() > self.capacity {
+            self.history.pop_front();
+        }
+
+        self.output.publish(change.change);
+    }
+
+    ///
+    /// Receives a change from a peer, publishing it and any waiting changes that depended on it
+    ///
+    /// Returns the concurrent changes to overlapping addresses found while publishing. Changes that have
+    /// already been published are ignored.
+    ///
+    pub fn receive(&mut self, change: CausalChange) -> Result<Vec<ConcurrentChanges>, CausalError> {
+        let mut conflicts = vec![];
+
+        if change.stamp.sequence() <= self.released.get(change.stamp.peer) {
+            return Ok(conflicts);
+        }
+
+        if self.missing_dependency(&change.stamp).is_some() {
+            if self.pending.len() >= self.capacity {
+                let (waiting_for, sequence) = self.stalled_dependency(&change.stamp).unwrap_or((change.stamp.peer, change.stamp.sequence()));
+                return Err(CausalError::BufferOverflow { change: change.stamp, waiting_for, sequence });
+            }
+
+            self.pending.push(change);
+            return Ok(conflicts);
+        }
+
+        self.release(change, &mut conflicts);
+
+        // Publishing a change may allow waiting changes to be published too
+        while let Some(ready_index) = self.pending.iter().position(|waiting| self.missing_dependency(&waiting.stamp).is_none()) {
+            let ready = self.pending.remove(ready_index);
+            self.release(ready, &mut conflicts);
+        }
+
+        Ok(conflicts)
+    }
+}
+
+#[cfg(test)]
+mod causal_tests {
+    use super::super::super::tree::*;
+    use super::super::super::component::*;
+    use super::super::immediate_publisher::*;
+    use super::super::output_tree_publisher::*;
+    use super::*;
+
+    fn order_of_values(publisher: &mut Box<ImmediatePublisher>) -> Box<dyn Fn() -> Vec<i32>> {
+        let mut consumer    = publisher.create_consumer();
+        let values          = ::std::rc::Rc::new(::std::cell::RefCell::new(vec![]));
+        let also_values     = values.clone();
+
+        consumer.subscribe(TreeAddress::Here, TreeExtent::SubTree, Box::new(move |change| {
+            let tree = change.apply(&"empty".to_tree_node());
+            values.borrow_mut().push(tree.get_child_ref().map(|child| child.get_value().to_int(0)).unwrap_or(-1));
+        }));
+
+        Box::new(move || also_values.borrow().clone())
+    }
+
+    #[test]
+    fn dependent_changes_are_released_in_order() {
+        let mut alice   = CausalStamper::new(PeerId(1));
+        let mut bob     = CausalStamper::new(PeerId(2));
+
+        // Alice makes two changes, Bob sees them and replies, then Alice replies to Bob
+        let a1 = alice.stamp(TreeChange::new(&"value", &("value", 1)));
+        let a2 = alice.stamp(TreeChange::new(&"value", &("value", 2)));
+        bob.observe(&a2.stamp.vector);
+        let b1 = bob.stamp(TreeChange::new(&"value", &("value", 3)));
+        alice.observe(&b1.stamp.vector);
+        let a3 = alice.stamp(TreeChange::new(&"value", &("value", 4)));
+
+        let mut output  = ImmediatePublisher::new();
+        let values      = order_of_values(&mut output);
+        let mut merger  = CausalMerger::new(output, 10);
+
+        // Deliver in the worst possible order
+        for change in [a3, b1, a2, a1] {
+            assert!(merger.receive(change).unwrap().is_empty());
+        }
+
+        assert!(values() == vec![1, 2, 3, 4]);
+        assert!(merger.pending_len() == 0);
+        assert!(merger.released().get(PeerId(1)) == 3);
+        assert!(merger.released().get(PeerId(2)) == 1);
+    }
+
+    #[test]
+    fn duplicate_changes_are_ignored() {
+        let mut alice   = CausalStamper::new(PeerId(1));
+        let output      = OutputTreePublisher::new();
+        let reader      = output.get_tree_reader();
+        let mut merger  = CausalMerger::new(output, 10);
+
+        let first       = alice.stamp(TreeChange::new(&(), &("value", 1)));
+        let stamp       = first.stamp.clone();
+        merger.receive(first).unwrap();
+        merger.receive(alice.stamp(TreeChange::new(&(), &("value", 2)))).unwrap();
+        merger.receive(CausalChange { stamp, change: TreeChange::new(&(), &("value", 1)) }).unwrap();
+
+        assert!(reader().get_value().to_int(0) == 2);
+    }
+
+    #[test]
+    fn concurrent_conflicting_changes_are_flagged() {
+        let mut alice   = CausalStamper::new(PeerId(1));
+        let mut bob     = CausalStamper::new(PeerId(2));
+        let mut merger  = CausalMerger::new(OutputTreePublisher::new(), 10);
+
+        let from_alice  = alice.stamp(TreeChange::new(&("shared", "x"), &("x", 1)));
+        let from_bob    = bob.stamp(TreeChange::new(&"shared", &tree!("shared", ("x", 2))));
+        let elsewhere   = bob.stamp(TreeChange::new(&"other", &("other", 3)));
+
+        let alice_vector    = from_alice.stamp.vector.clone();
+        let bob_vector      = from_bob.stamp.vector.clone();
+
+        assert!(merger.receive(from_alice).unwrap().is_empty());
+
+        let conflicts = merger.receive(from_bob).unwrap();
+        assert!(conflicts.len() == 1);
+        assert!(conflicts[0].first.peer == PeerId(1));
+        assert!(conflicts[0].first.vector == alice_vector);
+        assert!(conflicts[0].second.peer == PeerId(2));
+        assert!(conflicts[0].second.vector == bob_vector);
+        assert!(conflicts[0].second_address == "shared".to_tree_address());
+
+        // Concurrent, but doesn't overlap
+        assert!(merger.receive(elsewhere).unwrap().is_empty());
+    }
+
+    #[test]
+    fn buffer_overflow_names_stalled_dependency() {
+        let mut alice   = CausalStamper::new(PeerId(1));
+        let mut bob     = CausalStamper::new(PeerId(2));
+        let mut merger  = CausalMerger::new(OutputTreePublisher::new(), 2);
+
+        // Bob's changes all depend on Alice's first change, which never arrives
+        let lost = alice.stamp(TreeChange::new(&"a", &"a"));
+        bob.observe(&lost.stamp.vector);
+
+        assert!(merger.receive(bob.stamp(TreeChange::new(&"b", &"b"))).is_ok());
+        assert!(merger.receive(bob.stamp(TreeChange::new(&"b", &"b"))).is_ok());
+
+        let overflow = bob.stamp(TreeChange::new(&"b", &"b"));
+        let stamp    = overflow.stamp.clone();
+        assert!(merger.receive(overflow).err() == Some(CausalError::BufferOverflow { change: stamp, waiting_for: PeerId(1), sequence: 1 }));
+
+        // Delivering the missing change releases everything that was buffered
+        assert!(merger.receive(lost).is_ok());
+        assert!(merger.pending_len() == 0);
+        assert!(merger.released().get(PeerId(2)) == 2);
+    }
+}
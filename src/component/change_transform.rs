@@ -0,0 +1,636 @@
+//
+//   Copyright 2016 Andrew Hunter
+//
+//   Licensed under the Apache License, Version 2.0 (the "License");
+//   you may not use this file except in compliance with the License.
+//   You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+//   Unless required by applicable law or agreed to in writing, software
+//   distributed under the License is distributed on an "AS IS" BASIS,
+//   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//   See the License for the specific language governing permissions and
+//   limitations under the License.
+//
+
+//!
+//! # Transforming changes before they're delivered
+//!
+//! Most subscription callbacks start the same way: they apply the change to a tree they're keeping, look up the
+//! part of it they're interested in and decode or convert it. A `ChangeTransform` is a pipeline that does this
+//! before the callback is called, so a callback passed to `subscribe_mapped()` receives the result instead of the
+//! change.
+//!
+//! A pipeline starts with the tree published by the change and is assembled from these steps:
+//!
+//! * `track_tree()` applies the change to a tree kept by the pipeline and continues with the whole of that tree
+//! * `extract_subtree_at()` continues with the subtree at an address
+//! * `decode_into::<T>()` decodes the tree into a `T`, delivered as a `Box<dyn Any>`
+//! * `convert_value_to::<V>()` continues with the value of the tree, converted to the kind of value used for `V`
+//!
+//! When a step fails (for instance because the subtree doesn't exist yet, or because it can't be decoded), the
+//! callback isn't called. The change and the error are passed to the error callback instead, if there is one.
+//!
+//! ```
+//! # use tametree::prelude::*;
+//! # use std::rc::*;
+//! # use std::cell::*;
+//! let mut publisher   = ImmediatePublisher::new();
+//! let mut consumer    = publisher.create_consumer();
+//! let counts          = Rc::new(RefCell::new(vec![]));
+//! let recorded        = counts.clone();
+//!
+//! let transform = ChangeTransform::builder()
+//!     .track_tree()
+//!     .extract_subtree_at(&"count")
+//!     .convert_value_to::<i32>()
+//!     .build();
+//!
+//! consumer.subscribe_mapped(TreeAddress::Here, TreeExtent::SubTree, transform, Box::new(move |count| {
+//!     recorded.borrow_mut().push(count.value().unwrap().to_int(0));
+//! }));
+//!
+//! publisher.publish(TreeChange::new(&"count", &("count", 1)));
+//! publisher.publish(TreeChange::new(&"count", &("count", "2")));
+//! assert!(*counts.borrow() == vec![1, 2]);
+//! ```
+//!
+
+use std::rc::*;
+use std::cell::*;
+use std::fmt;
+use std::mem;
+use std::any::Any;
+
+use super::super::tree::*;
+use super::component::*;
+
+///
+/// What a change transform delivers to its callback
+///
+pub enum Transformed {
+    /// A tree
+    Tree(TreeRef),
+
+    /// A decoded object (use `decoded()` to retrieve it)
+    Decoded(Box<dyn Any>),
+
+    /// A value
+    Value(TreeValue)
+}
+
+impl Transformed {
+    ///
+    /// The tree that was delivered, if the pipeline produces a tree
+    ///
+    pub fn tree(&self) -> Option<TreeRef> {
+        match *self {
+            Transformed::Tree(ref tree) => Some(tree.clone()),
+            _                           => None
+        }
+    }
+
+    ///
+    /// The value that was delivered, if the pipeline produces a value
+    ///
+    pub fn value(&self) -> Option<TreeValue> {
+        match *self {
+            Transformed::Value(ref value)   => Some(value.clone()),
+            _                               => None
+        }
+    }
+
+    ///
+    /// The object that was delivered, if the pipeline decodes an object of this type
+    ///
+    pub fn decoded<T: 'static>(self) -> Option<T> {
+        match self {
+            Transformed::Decoded(decoded)   => decoded.downcast::<T>().ok().map(|decoded| *decoded),
+            _                               => None
+        }
+    }
+}
+
+///
+/// Why a change transform couldn't produce a result for a change
+///
+pub enum TransformError {
+    /// The change removed the tree that the pipeline starts with
+    NoTree,
+
+    /// There was no subtree at an address
+    MissingSubtree(TreeAddress),
+
+    /// The tree couldn't be decoded
+    Decode(TreeNodeDecodingError),
+
+    /// A value couldn't be converted
+    Conversion { value: TreeValue, target: &'static str },
+
+    /// A step was given something it can't process, such as a value when it needs a tree
+    WrongInput { step: &'static str }
+}
+
+impl fmt::Display for TransformError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TransformError::NoTree                          => write!(f, "the change removed the tree"),
+            TransformError::MissingSubtree(ref address)     => write!(f, "there is no subtree at {}", address),
+            TransformError::Decode(ref error)               => write!(f, "the tree could not be decoded: {:?}", error),
+            TransformError::Conversion { ref value, target } => write!(f, "the value {} could not be converted to {}", render_value(value), target),
+            TransformError::WrongInput { step }             => write!(f, "the {} step was given something it can't process", step)
+        }
+    }
+}
+
+///
+/// Describes a value for an error message
+///
+fn render_value(value: &TreeValue) -> String {
+    match *value {
+        TreeValue::Nothing              => "nothing".to_string(),
+        TreeValue::Bool(value)          => value.to_string(),
+        TreeValue::Int(value)           => value.to_string(),
+        TreeValue::Real(value)          => value.to_string(),
+        TreeValue::String(ref value)    => format!("{:?}", value),
+        TreeValue::Data(ref data)       => format!("({} bytes of data)", data.len())
+    }
+}
+
+///
+/// Types whose kind of tree value other values can be converted to
+///
+pub trait ValueConversion {
+    ///
+    /// The name of this type, for error messages
+    ///
+    const NAME: &'static str;
+
+    ///
+    /// Converts a value to the kind used for this type, or returns None if that's not possible
+    ///
+    fn convert_value(value: &TreeValue) -> Option<TreeValue>;
+}
+
+impl ValueConversion for bool {
+    const NAME: &'static str = "bool";
+
+    fn convert_value(value: &TreeValue) -> Option<TreeValue> {
+        match *value {
+            TreeValue::Bool(value)          => Some(TreeValue::Bool(value)),
+            TreeValue::Int(value)           => Some(TreeValue::Bool(value != 0)),
+            TreeValue::String(ref value)    => value.parse().ok().map(TreeValue::Bool),
+            _                               => None
+        }
+    }
+}
+
+impl ValueConversion for i32 {
+    const NAME: &'static str = "i32";
+
+    fn convert_value(value: &TreeValue) -> Option<TreeValue> {
+        match *value {
+            TreeValue::Bool(value)          => Some(TreeValue::Int(if value { 1 } else { 0 })),
+            TreeValue::Int(value)           => Some(TreeValue::Int(value)),
+            TreeValue::Real(value)          => if value.fract() == 0.0 && value >= i32::MIN as f64 && value <= i32::MAX as f64 { Some(TreeValue::Int(value as i32)) } else { None },
+            TreeValue::String(ref value)    => value.trim().parse().ok().map(TreeValue::Int),
+            _                               => None
+        }
+    }
+}
+
+impl ValueConversion for f64 {
+    const NAME: &'static str = "f64";
+
+    fn convert_value(value: &TreeValue) -> Option<TreeValue> {
+        match *value {
+            TreeValue::Int(value)           => Some(TreeValue::Real(value as f64)),
+            TreeValue::Real(value)          => Some(TreeValue::Real(value)),
+            TreeValue::String(ref value)    => value.trim().parse().ok().map(TreeValue::Real),
+            _                               => None
+        }
+    }
+}
+
+impl ValueConversion for String {
+    const NAME: &'static str = "String";
+
+    fn convert_value(value: &TreeValue) -> Option<TreeValue> {
+        match *value {
+            TreeValue::Bool(value)          => Some(value.to_string().to_tree_value()),
+            TreeValue::Int(value)           => Some(value.to_string().to_tree_value()),
+            TreeValue::Real(value)          => Some(value.to_string().to_tree_value()),
+            TreeValue::String(_)            => Some(value.clone()),
+            _                               => None
+        }
+    }
+}
+
+///
+/// Decodes a tree into a boxed object
+///
+type DecodeFn = Box<dyn Fn(&TreeRef) -> Result<Box<dyn Any>, TreeNodeDecodingError>>;
+
+///
+/// Callback for the changes that a change transform couldn't produce a result for
+///
+pub type TransformErrorCallback = Box<dyn FnMut(&TreeChange, &TransformError)>;
+
+///
+/// Callback for the results of a change transform
+///
+pub type MappedCallback = Box<dyn FnMut(Transformed)>;
+
+///
+/// A step in a change transform
+///
+enum TransformStep {
+    /// Applies changes to a tree, which is kept here
+    TrackTree(TreeRef),
+
+    /// Looks up the subtree at an address
+    ExtractSubtree(TreeAddress),
+
+    /// Decodes a tree
+    Decode(DecodeFn),
+
+    /// Converts a value (and the name of the type it's converted for)
+    ConvertValue(fn(&TreeValue) -> Option<TreeValue>, &'static str)
+}
+
+///
+/// What's passed between the steps of a change transform
+///
+enum Artifact {
+    /// The change itself, before any step has run
+    Change,
+
+    Tree(TreeRef),
+    Decoded(Box<dyn Any>),
+    Value(TreeValue)
+}
+
+///
+/// A pipeline that turns changes into the trees, objects or values that a subscription is interested in
+///
+pub struct ChangeTransform {
+    steps: Vec<TransformStep>,
+    on_error: Option<TransformErrorCallback>,
+
+    /// The number of boxes or nodes that the pipeline has allocated itself
+    allocations: Rc<Cell<usize>>
+}
+
+///
+/// Assembles a change transform from its steps
+///
+pub struct ChangeTransformBuilder {
+    steps: Vec<TransformStep>,
+    on_error: Option<TransformErrorCallback>
+}
+
+impl ChangeTransformBuilder {
+    ///
+    /// Applies each change to a tree kept by the pipeline, continuing with the whole of that tree
+    ///
+    /// The tree starts out empty, so the result is the same as the tree read by a `Receiver`.
+    ///
+    pub fn track_tree(mut self) -> Self {
+        self.steps.push(TransformStep::TrackTree("".to_tree_node()));
+        self
+    }
+
+    ///
+    /// Continues with the subtree at an address. It's an error if there's no subtree there.
+    ///
+    pub fn extract_subtree_at<TAddress: ToTreeAddress>(mut self, address: &TAddress) -> Self {
+        self.steps.push(TransformStep::ExtractSubtree(address.to_tree_address()));
+        self
+    }
+
+    ///
+    /// Decodes the tree into a `T`
+    ///
+    pub fn decode_into<T: 'static + DecodeFromTreeNode>(mut self) -> Self {
+        self.steps.push(TransformStep::Decode(Box::new(|tree| T::new_from_tree(tree).map(|decoded| Box::new(decoded) as Box<dyn Any>))));
+        self
+    }
+
+    ///
+    /// Continues with the value of the tree, converted to the kind of value used for `V`
+    ///
+    pub fn convert_value_to<V: ValueConversion>(mut self) -> Self {
+        self.steps.push(TransformStep::ConvertValue(V::convert_value, V::NAME));
+        self
+    }
+
+    ///
+    /// Calls a function with the changes that the pipeline can't produce a result for
+    ///
+    pub fn with_error_callback(mut self, on_error: TransformErrorCallback) -> Self {
+        self.on_error = Some(on_error);
+        self
+    }
+
+    ///
+    /// Creates the change transform
+    ///
+    pub fn build(self) -> ChangeTransform {
+        ChangeTransform { steps: self.steps, on_error: self.on_error, allocations: Rc::new(Cell::new(0)) }
+    }
+}
+
+impl ChangeTransform {
+    ///
+    /// Starts assembling a change transform
+    ///
+    pub fn builder() -> ChangeTransformBuilder {
+        ChangeTransformBuilder { steps: vec![], on_error: None }
+    }
+
+    ///
+    /// Retrieves a function that returns the number of boxes and nodes that this transform has allocated itself
+    ///
+    /// This doesn't include the nodes created by applying changes to the tree tracked by `track_tree()`.
+    ///
+    pub fn get_allocation_reader(&self) -> Box<dyn Fn() -> usize> {
+        let allocations = self.allocations.clone();
+
+        Box::new(move || allocations.get())
+    }
+
+    ///
+    /// Retrieves the tree that an artifact refers to
+    ///
+    fn tree_of(&self, artifact: Artifact, change: &TreeChange, step: &'static str) -> Result<TreeRef, TransformError> {
+        match artifact {
+            Artifact::Tree(tree)    => Ok(tree),
+            Artifact::Change        => match *change.replacement() {
+                TreeReplacement::NewNode(ref node)          => Ok(node.clone()),
+                TreeReplacement::NewValue(ref tag, ref value) => {
+                    self.allocations.set(self.allocations.get() + 1);
+                    Ok((tag.as_str(), value).to_tree_node())
+                },
+                TreeReplacement::Remove                     => Err(TransformError::NoTree)
+            },
+
+            _                       => Err(TransformError::WrongInput { step })
+        }
+    }
+
+    ///
+    /// Runs the pipeline for a change
+    ///
+    pub fn run(&mut self, change: &TreeChange) -> Result<Transformed, TransformError> {
+        let mut artifact    = Artifact::Change;
+        let mut steps       = mem::take(&mut self.steps);
+        let mut result      = Ok(());
+
+        for step in steps.iter_mut() {
+            let next = match *step {
+                TransformStep::TrackTree(ref mut tree) => {
+                    *tree = change.apply(tree);
+                    Ok(Artifact::Tree(tree.clone()))
+                },
+
+                TransformStep::ExtractSubtree(ref address) => {
+                    self.tree_of(artifact, change, "extract subtree")
+                        .and_then(|tree| address.lookup_index(&tree).ok_or_else(|| TransformError::MissingSubtree(address.clone())))
+                        .map(Artifact::Tree)
+                },
+
+                TransformStep::Decode(ref decode) => {
+                    self.tree_of(artifact, change, "decode").and_then(|tree| {
+                        self.allocations.set(self.allocations.get() + 1);
+                        decode(&tree).map(Artifact::Decoded).map_err(TransformError::Decode)
+                    })
+                },
+
+                TransformStep::ConvertValue(convert, target) => {
+                    let value = match artifact {
+                        Artifact::Value(value)  => Ok(value),
+                        artifact                => self.tree_of(artifact, change, "convert value").map(|tree| tree.get_value().clone())
+                    };
+
+                    value.and_then(|value| convert(&value).map(Artifact::Value).ok_or(TransformError::Conversion { value, target }))
+                }
+            };
+
+            match next {
+                Ok(next)    => artifact = next,
+                Err(error)  => {
+                    result      = Err(error);
+                    artifact    = Artifact::Change;
+                    break;
+                }
+            }
+        }
+
+        self.steps = steps;
+        result?;
+
+        match artifact {
+            Artifact::Decoded(decoded)  => Ok(Transformed::Decoded(decoded)),
+            Artifact::Value(value)      => Ok(Transformed::Value(value)),
+            artifact                    => self.tree_of(artifact, change, "deliver").map(Transformed::Tree)
+        }
+    }
+
+    ///
+    /// Runs the pipeline for a change, passing the result to a callback or any error to the error callback
+    ///
+    pub fn dispatch(&mut self, change: &TreeChange, callback: &mut MappedCallback) {
+        match self.run(change) {
+            Ok(result)  => callback(result),
+            Err(error)  => if let Some(ref mut on_error) = self.on_error {
+                on_error(change, &error);
+            }
+        }
+    }
+}
+
+///
+/// Consumers that can transform changes before passing them to a subscription callback
+///
+pub trait MappedConsumer {
+    ///
+    /// Calls a function with the result of a change transform whenever a particular section of the tree has changed
+    ///
+    fn subscribe_mapped(&mut self, address: TreeAddress, extent: TreeExtent, transform: ChangeTransform, callback: MappedCallback);
+}
+
+impl MappedConsumer for ConsumerRef {
+    fn subscribe_mapped(&mut self, address: TreeAddress, extent: TreeExtent, transform: ChangeTransform, callback: MappedCallback) {
+        let mut transform   = transform;
+        let mut callback    = callback;
+
+        self.subscribe(address, extent, Box::new(move |change| transform.dispatch(change, &mut callback)));
+    }
+}
+
+#[cfg(test)]
+mod change_transform_tests {
+    use std::rc::*;
+    use std::cell::*;
+
+    use super::super::super::tree::*;
+    use super::super::immediate_publisher::*;
+    use super::*;
+
+    tree_struct! {
+        #[derive(PartialEq, Debug)]
+        struct Point {
+            x: i32,
+            y: i32
+        }
+    }
+
+    ///
+    /// Publishes some changes to a consumer subscribed with a transform, returning what was delivered and the
+    /// descriptions of any errors
+    ///
+    fn deliver(builder: ChangeTransformBuilder, changes: Vec<TreeChange>) -> (Vec<Transformed>, Vec<String>) {
+        let mut publisher   = ImmediatePublisher::new();
+        let mut consumer    = publisher.create_consumer();
+        let delivered       = Rc::new(RefCell::new(vec![]));
+        let errors          = Rc::new(RefCell::new(vec![]));
+        let record          = delivered.clone();
+        let record_error    = errors.clone();
+
+        let transform = builder.with_error_callback(Box::new(move |_change, error| record_error.borrow_mut().push(error.to_string()))).build();
+        consumer.subscribe_mapped(TreeAddress::Here, TreeExtent::SubTree, transform, Box::new(move |result| record.borrow_mut().push(result)));
+
+        changes.into_iter().for_each(|change| publisher.publish(change));
+
+        let delivered   = delivered.replace(vec![]);
+        let errors      = errors.replace(vec![]);
+        (delivered, errors)
+    }
+
+    ///
+    /// A point tagged 'point', as it would be if it was published to that address in a hub
+    ///
+    fn point(x: i32, y: i32) -> TreeRef {
+        Rc::new(BasicTree::new("point", (), Point { x, y }.to_tree_node().get_child_ref(), None))
+    }
+
+    #[test]
+    fn without_steps_delivers_the_changed_tree() {
+        let (delivered, errors) = deliver(ChangeTransform::builder(), vec![TreeChange::new(&"a", &("a", 1)), TreeChange::new(&"a", &TreeReplacement::Remove)]);
+
+        assert!(delivered.len() == 1);
+        assert!(delivered[0].tree().unwrap().get_value().to_int(0) == 1);
+        assert!(errors == vec!["the change removed the tree"]);
+    }
+
+    #[test]
+    fn track_tree_applies_each_change() {
+        let (delivered, _) = deliver(ChangeTransform::builder().track_tree(), vec![
+            TreeChange::new(&(), &("root", ())),
+            TreeChange::new(&"a", &("a", 1)),
+            TreeChange::new(&"b", &("b", 2))
+        ]);
+
+        let last = delivered.last().unwrap().tree().unwrap();
+        assert!(last.get_child_ref_at("a").unwrap().get_value().to_int(0) == 1);
+        assert!(last.get_child_ref_at("b").unwrap().get_value().to_int(0) == 2);
+    }
+
+    #[test]
+    fn extract_subtree_reports_missing_subtrees() {
+        let (delivered, errors) = deliver(ChangeTransform::builder().track_tree().extract_subtree_at(&"b"), vec![
+            TreeChange::new(&(), &("root", ())),
+            TreeChange::new(&"a", &("a", 1)),
+            TreeChange::new(&"b", &("b", 2))
+        ]);
+
+        assert!(delivered.len() == 1);
+        assert!(delivered[0].tree().unwrap().get_value().to_int(0) == 2);
+        assert!(errors.len() == 2);
+        assert!(errors[0].starts_with("there is no subtree at"));
+    }
+
+    #[test]
+    fn decode_into_delivers_objects() {
+        let (delivered, errors) = deliver(ChangeTransform::builder().decode_into::<Point>(), vec![
+            TreeChange::new(&(), &point(1, 2)),
+            TreeChange::new(&(), &("point", "not a point"))
+        ]);
+
+        let decoded: Vec<Point> = delivered.into_iter().filter_map(|result| result.decoded::<Point>()).collect();
+        assert!(decoded == vec![Point { x: 1, y: 2 }]);
+        assert!(errors.len() == 1 && errors[0].starts_with("the tree could not be decoded"));
+    }
+
+    #[test]
+    fn convert_value_to_converts_between_kinds() {
+        let changes = || vec![TreeChange::new(&(), &("v", 3)), TreeChange::new(&(), &("v", "4")), TreeChange::new(&(), &("v", 2.5))];
+
+        let (as_int, int_errors)    = deliver(ChangeTransform::builder().convert_value_to::<i32>(), changes());
+        let (as_real, _)            = deliver(ChangeTransform::builder().convert_value_to::<f64>(), changes());
+        let (as_string, _)          = deliver(ChangeTransform::builder().convert_value_to::<String>(), changes());
+
+        assert!(as_int.iter().map(|result| result.value().unwrap().to_int(0)).collect::<Vec<_>>() == vec![3, 4]);
+        assert!(int_errors == vec!["the value 2.5 could not be converted to i32"]);
+        assert!(as_real.iter().map(|result| result.value().unwrap().to_real(0.0)).collect::<Vec<_>>() == vec![3.0, 4.0, 2.5]);
+        assert!(as_string.iter().map(|result| result.value().unwrap().to_str("").to_string()).collect::<Vec<_>>() == vec!["3", "4", "2.5"]);
+    }
+
+    #[test]
+    fn steps_compose() {
+        let (delivered, errors) = deliver(ChangeTransform::builder().track_tree().extract_subtree_at(&"point").decode_into::<Point>(), vec![
+            TreeChange::new(&(), &("root", ())),
+            TreeChange::new(&"point", &point(1, 2)),
+            TreeChange::new(&("point", "y"), &("y", 5))
+        ]);
+
+        let decoded: Vec<Point> = delivered.into_iter().filter_map(|result| result.decoded::<Point>()).collect();
+        assert!(decoded == vec![Point { x: 1, y: 2 }, Point { x: 1, y: 5 }]);
+        assert!(errors.len() == 1);
+    }
+
+    #[test]
+    fn steps_after_decoding_are_errors() {
+        let (delivered, errors) = deliver(ChangeTransform::builder().decode_into::<Point>().extract_subtree_at(&"x"), vec![TreeChange::new(&(), &point(1, 2))]);
+
+        assert!(delivered.is_empty());
+        assert!(errors == vec!["the extract subtree step was given something it can't process"]);
+    }
+
+    #[test]
+    fn failures_without_an_error_callback_are_skipped() {
+        let mut publisher   = ImmediatePublisher::new();
+        let mut consumer    = publisher.create_consumer();
+        let count           = Rc::new(Cell::new(0));
+        let also_count      = count.clone();
+
+        consumer.subscribe_mapped(TreeAddress::Here, TreeExtent::SubTree, ChangeTransform::builder().convert_value_to::<i32>().build(), Box::new(move |_| also_count.set(also_count.get() + 1)));
+        publisher.publish(TreeChange::new(&(), &("v", "nope")));
+        publisher.publish(TreeChange::new(&(), &("v", 1)));
+
+        assert!(count.get() == 1);
+    }
+
+    #[test]
+    fn value_pipeline_does_not_allocate_per_change() {
+        let mut publisher   = ImmediatePublisher::new();
+        let mut consumer    = publisher.create_consumer();
+        let values          = ChangeTransform::builder().track_tree().extract_subtree_at(&"count").convert_value_to::<i32>().build();
+        let objects         = ChangeTransform::builder().track_tree().extract_subtree_at(&"point").decode_into::<Point>().build();
+        let value_allocs    = values.get_allocation_reader();
+        let object_allocs   = objects.get_allocation_reader();
+
+        consumer.subscribe_mapped(TreeAddress::Here, TreeExtent::SubTree, values, Box::new(|_| { }));
+        consumer.subscribe_mapped(TreeAddress::Here, TreeExtent::SubTree, objects, Box::new(|_| { }));
+
+        publisher.publish(TreeChange::new(&(), &("root", ())));
+        publisher.publish(TreeChange::new(&"point", &point(0, 0)));
+        for count in 0..100 {
+            publisher.publish(TreeChange::new(&"count", &("count", count)));
+        }
+
+        assert!(value_allocs() == 0);
+        assert!(object_allocs() == 101);
+    }
+}
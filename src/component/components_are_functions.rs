@@ -33,53 +33,44 @@
 //! Here's the definition of a component that adds two numbers together:
 //!
 //! ```
-//! # extern crate tametree;
-//! # extern crate rustc_serialize;
-//! # fn main() {
-//! # use tametree::component::*;
-//! # use tametree::component::immediate_publisher::*;
+//! # use tametree::prelude::*;
 //! #
-//! #[derive(RustcEncodable, RustcDecodable)]
-//! struct InputTree {
-//!     a: i32,
-//!     b: i32,
-//! };
-//! impl EncodeToTreeNode for InputTree { }
+//! tree_struct! {
+//!     struct InputTree {
+//!         a: i32,
+//!         b: i32,
+//!     }
+//! }
 //!
-//! #[derive(RustcEncodable, RustcDecodable)]
-//! struct ResultTree {
-//!     result: i32
-//! };
-//! impl EncodeToTreeNode for ResultTree { }
+//! tree_struct! {
+//!     struct ResultTree {
+//!         result: i32
+//!     }
+//! }
 //!
 //! let component = component_fn(|input: &InputTree| { 
 //!    ResultTree { result: input.a + input.b } 
 //! });
-//! # }
 //! ```
 //!
 //! We can call `ComponentEndPoint::<InputTree, ResultTree>::new(component)` to turn this into
 //! a pair of functions that are convenient to call:
 //!
 //! ```
-//! # extern crate tametree;
-//! # extern crate rustc_serialize;
-//! # fn main() {
-//! # use tametree::component::*;
-//! # use tametree::component::immediate_publisher::*;
+//! # use tametree::prelude::*;
 //! #
-//! # #[derive(RustcEncodable, RustcDecodable)]
-//! # struct InputTree {
-//! #     a: i32,
-//! #     b: i32,
-//! # };
-//! # impl EncodeToTreeNode for InputTree { }
+//! # tree_struct! {
+//! #     struct InputTree {
+//! #         a: i32,
+//! #         b: i32,
+//! #     }
+//! # }
 //! # 
-//! # #[derive(RustcEncodable, RustcDecodable)]
-//! # struct ResultTree {
-//! #     result: i32
-//! # };
-//! # impl EncodeToTreeNode for ResultTree { }
+//! # tree_struct! {
+//! #     struct ResultTree {
+//! #         result: i32
+//! #     }
+//! # }
 //! # 
 //! # let component = component_fn(|input: &InputTree| { 
 //! #    ResultTree { result: input.a + input.b } 
@@ -90,7 +81,31 @@
 //! endpoint.send(InputTree { a: 4, b: 7 });
 //! let result_tree = endpoint.recv();  // == Some({ result: 11 }) as this component updates immediately
 //! # assert!(result_tree.unwrap().result == 11);
-//! # }
+//! ```
+//!
+//! `new()` sends data straight to the component and reads its whole output tree. `ComponentEndPoint::builder()`
+//! can be used instead to change how the endpoint is wired up: `with_bus()` queues input on a `TreeChangeBus`
+//! until `pump()` is called, and `with_input_address()` and `with_output_address()` send and receive data at a
+//! subtree of the component's input and output trees.
+//!
+//! ```
+//! # use tametree::prelude::*;
+//! let component = component_fn(|input: &TreeRef| {
+//!     let doubled = input.get_child_ref_at("value").map(|value| value.get_value().to_int(0)*2).unwrap_or(0);
+//!     tree!("output", ("doubled", doubled))
+//! });
+//!
+//! let mut endpoint = ComponentEndPoint::<i32, i32>::builder(component)
+//!     .with_bus()
+//!     .with_input_address("value")
+//!     .with_output_address("doubled")
+//!     .build();
+//!
+//! endpoint.send(21);
+//! assert!(endpoint.recv().is_none());    // Nothing happens until the bus is pumped
+//!
+//! endpoint.pump();
+//! assert!(endpoint.recv() == Some(42));
 //! ```
 //!
 //! # Receiver functions
@@ -102,24 +117,20 @@
 //! ## Example
 //!
 //! ```
-//! # extern crate tametree;
-//! # extern crate rustc_serialize;
-//! # fn main() {
-//! # use tametree::component::*;
-//! # use tametree::component::immediate_publisher::*;
+//! # use tametree::prelude::*;
 //! #
-//! # #[derive(RustcEncodable, RustcDecodable)]
-//! # struct InputTree {
-//! #     a: i32,
-//! #     b: i32,
-//! # };
-//! # impl EncodeToTreeNode for InputTree { }
+//! # tree_struct! {
+//! #     struct InputTree {
+//! #         a: i32,
+//! #         b: i32,
+//! #     }
+//! # }
 //! # 
-//! # #[derive(RustcEncodable, RustcDecodable)]
-//! # struct ResultTree {
-//! #     result: i32
-//! # };
-//! # impl EncodeToTreeNode for ResultTree { }
+//! # tree_struct! {
+//! #     struct ResultTree {
+//! #         result: i32
+//! #     }
+//! # }
 //! # 
 //! # let component = component_fn(|input: &InputTree| { 
 //! #    ResultTree { result: input.a + input.b } 
@@ -135,7 +146,6 @@
 //! publisher.publish(TreeChange::new(&(), &InputTree { a: 4, b: 7 }));
 //! let result_tree = receiver();           // == Some(12) for our test component
 //! # assert!(result_tree.unwrap().result == 11);
-//! # }
 //!
 //! ```
 
@@ -146,7 +156,9 @@ use super::super::tree::*;
 use super::super::util::clonecell::*;
 use super::component::*;
 use super::immediate_publisher::*;
+use super::bus_publisher::*;
 use super::output_tree_publisher::*;
+use super::change_transform::*;
 
 ///
 /// Defines the type of a receiver function
@@ -171,10 +183,10 @@ impl Receiver<TreeRef> for ConsumerRef {
         let tree        = Rc::new(CloneCell::new("".to_tree_node()));
         let also_tree   = tree.clone();
 
-        self.subscribe(TreeAddress::Here, TreeExtent::SubTree, Box::new(move |change| {
-            let current_tree = (*tree).get();
-            let altered_tree = change.apply(&current_tree);
-            (*tree).set(altered_tree);
+        self.subscribe_mapped(TreeAddress::Here, TreeExtent::SubTree, ChangeTransform::builder().track_tree().build(), Box::new(move |altered| {
+            if let Transformed::Tree(altered_tree) = altered {
+                (*tree).set(altered_tree);
+            }
         }));
 
         Box::new(move || {
@@ -211,14 +223,95 @@ impl<TOut: 'static + DecodeFromTreeNode + Sized> Receiver<TOut> for ConsumerRef
 pub struct ComponentEndPoint<TIn, TOut>
     where   TIn: 'static + ToTreeNode,
             TOut: 'static + DecodeFromTreeNode {
-    _component: ComponentRef,
-    reader:     Box<Fn() -> TreeRef>,
-    input:      PublisherRef,
+    _component:     ComponentRef,
+    reader:         Box<dyn Fn() -> TreeRef>,
+    input:          PublisherRef,
+    bus:            Option<TreeChangeBus>,
+    input_address:  TreeAddress,
+    output_address: TreeAddress,
+
+    phantom_in: PhantomData<TIn>,
+    phantom_out: PhantomData<TOut>
+}
+
+///
+/// Builds a component endpoint with options for how it's connected to its component
+///
+/// The defaults are the same as for `ComponentEndPoint::new()`: data is sent immediately to the root of
+/// the component's input tree and received from the root of its output tree.
+///
+pub struct ComponentEndPointBuilder<TIn, TOut, TComponent>
+    where   TIn: 'static + ToTreeNode,
+            TOut: 'static + DecodeFromTreeNode,
+            TComponent: ConvertToComponent {
+    component:      TComponent,
+    use_bus:        bool,
+    input_address:  TreeAddress,
+    output_address: TreeAddress,
 
     phantom_in: PhantomData<TIn>,
     phantom_out: PhantomData<TOut>
 }
 
+impl<TIn, TOut, TComponent> ComponentEndPointBuilder<TIn, TOut, TComponent>
+    where   TIn: 'static + ToTreeNode,
+            TOut: 'static + DecodeFromTreeNode,
+            TComponent: ConvertToComponent {
+    ///
+    /// Queues the data sent to the component on a bus, so it's only delivered when the endpoint's `pump()` is called
+    ///
+    pub fn with_bus(mut self) -> Self {
+        self.use_bus = true;
+        self
+    }
+
+    ///
+    /// Sends data to a particular address in the component's input tree
+    ///
+    pub fn with_input_address<TAddress: ToTreeAddress>(mut self, address: TAddress) -> Self {
+        self.input_address = address.to_tree_address();
+        self
+    }
+
+    ///
+    /// Receives data from a particular address in the component's output tree
+    ///
+    pub fn with_output_address<TAddress: ToTreeAddress>(mut self, address: TAddress) -> Self {
+        self.output_address = address.to_tree_address();
+        self
+    }
+
+    ///
+    /// Creates the component and an endpoint connected to it
+    ///
+    pub fn build(self) -> ComponentEndPoint<TIn, TOut> {
+        let output      = OutputTreePublisher::new();
+        let reader      = output.get_tree_reader();
+
+        let (input, consumer, bus) = if self.use_bus {
+            let bus = TreeChangeBus::new();
+            (bus.create_publisher(), bus.create_consumer(), Some(bus))
+        } else {
+            let input       = ImmediatePublisher::new();
+            let consumer    = input.create_consumer();
+            (input as PublisherRef, consumer, None)
+        };
+
+        let component   = self.component.into_component(consumer, output);
+
+        ComponentEndPoint {
+            _component:     component,
+            reader,
+            input,
+            bus,
+            input_address:  self.input_address,
+            output_address: self.output_address,
+            phantom_in:     PhantomData,
+            phantom_out:    PhantomData
+        }
+    }
+}
+
 impl<TIn, TOut> ComponentEndPoint<TIn, TOut>
     where   TIn: 'static + ToTreeNode,
             TOut: 'static + DecodeFromTreeNode {
@@ -226,14 +319,21 @@ impl<TIn, TOut> ComponentEndPoint<TIn, TOut>
     /// Creates a new endpoint from an object that can create a component
     ///
     pub fn new<TComponent: ConvertToComponent>(component: TComponent) -> ComponentEndPoint<TIn, TOut> {
-        let input       = ImmediatePublisher::new();
-        let consumer    = input.create_consumer();
-        let output      = OutputTreePublisher::new();
-        let reader      = output.get_tree_reader();
-
-        let component   = component.into_component(consumer, output);
+        Self::builder(component).build()
+    }
 
-        ComponentEndPoint { _component: component, reader: reader, input: input, phantom_in: PhantomData, phantom_out: PhantomData }
+    ///
+    /// Starts building an endpoint for a component with options for how it's connected
+    ///
+    pub fn builder<TComponent: ConvertToComponent>(component: TComponent) -> ComponentEndPointBuilder<TIn, TOut, TComponent> {
+        ComponentEndPointBuilder {
+            component,
+            use_bus:        false,
+            input_address:  TreeAddress::Here,
+            output_address: TreeAddress::Here,
+            phantom_in:     PhantomData,
+            phantom_out:    PhantomData
+        }
     }
 
     ///
@@ -241,7 +341,26 @@ impl<TIn, TOut> ComponentEndPoint<TIn, TOut>
     ///
     #[inline]
     pub fn send(&mut self, data: TIn) {
-        self.input.publish(TreeChange::new(&(), &data.to_tree_node()));
+        let mut node = data.to_tree_node();
+
+        // Data sent to a tagged address needs that tag so it can be found there again
+        if let TreeAddress::ChildWithTag(ref tag, _) = *self.input_address.last_part() {
+            node = Rc::new(BasicTree::new(tag, node.get_value().clone(), node.get_child_ref(), None));
+        }
+
+        self.input.publish(TreeChange::new(&self.input_address, &node));
+    }
+
+    ///
+    /// Delivers any data waiting on the bus to the component
+    ///
+    /// Endpoints that aren't built with `with_bus()` deliver data as soon as it's sent, so there's never anything to pump.
+    ///
+    pub fn pump(&mut self) -> PumpStats {
+        match self.bus {
+            Some(ref mut bus)   => bus.pump(),
+            None                => PumpStats::default()
+        }
     }
 
     ///
@@ -253,6 +372,63 @@ impl<TIn, TOut> ComponentEndPoint<TIn, TOut>
     pub fn recv(&self) -> Option<TOut> {
         let reader = &self.reader;
 
-        TOut::new_from_tree(&reader()).ok()
+        reader().get_child_ref_at(self.output_address.clone()).and_then(|output| TOut::new_from_tree(&output).ok())
+    }
+}
+
+#[cfg(test)]
+mod components_are_functions_tests {
+    use super::super::super::tree::*;
+    use super::super::super::component::*;
+    use super::super::bus_publisher::*;
+
+    fn add_one() -> Box<dyn Fn(&i32) -> i32> {
+        component_fn(|x: &i32| { x+1 })
+    }
+
+    #[test]
+    fn default_builder_matches_new() {
+        let mut from_new        = ComponentEndPoint::<i32, i32>::new(add_one());
+        let mut from_builder    = ComponentEndPoint::<i32, i32>::builder(add_one()).build();
+
+        assert!(from_new.recv() == from_builder.recv());
+
+        from_new.send(4);
+        from_builder.send(4);
+        assert!(from_new.recv() == Some(5));
+        assert!(from_builder.recv() == Some(5));
+        assert!(from_builder.pump() == PumpStats::default());
+    }
+
+    #[test]
+    fn bus_endpoint_needs_pump() {
+        let mut endpoint = ComponentEndPoint::<i32, i32>::builder(add_one()).with_bus().build();
+
+        endpoint.send(4);
+        assert!(endpoint.recv().is_none());
+
+        assert!(endpoint.pump().delivered == 1);
+        assert!(endpoint.recv() == Some(5));
+    }
+
+    #[test]
+    fn addressed_endpoint_uses_subtrees() {
+        let swap = component_fn(|input: &TreeRef| {
+            let left    = input.get_child_ref_at("left").map(|node| node.get_value().to_int(0)).unwrap_or(-1);
+            let right   = input.get_child_ref_at("right").map(|node| node.get_value().to_int(0)).unwrap_or(-1);
+
+            tree!("output", ("left", right), ("right", left))
+        });
+
+        let mut endpoint = ComponentEndPoint::<i32, i32>::builder(swap)
+            .with_input_address("left")
+            .with_output_address("right")
+            .build();
+
+        endpoint.send(7);
+        assert!(endpoint.recv() == Some(7));
+
+        endpoint.send(8);
+        assert!(endpoint.recv() == Some(8));
     }
 }
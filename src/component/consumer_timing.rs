@@ -0,0 +1,547 @@
+//
+//   Copyright 2016 Andrew Hunter
+//
+//   Licensed under the Apache License, Version 2.0 (the "License");
+//   you may not use this file except in compliance with the License.
+//   You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+//   Unless required by applicable law or agreed to in writing, software
+//   distributed under the License is distributed on an "AS IS" BASIS,
+//   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//   See the License for the specific language governing permissions and
+//   limitations under the License.
+//
+
+//!
+//! # Timing consumers
+//!
+//! A single slow subscription makes every pump of a bus slow, so it's useful to know where the time is going.
+//! After `TreeChangeBus::enable_consumer_timing()` is called, the bus measures how long each subscription's
+//! callback takes every time it's called, using a `TimingClock`. Timing is off by default: while it's disabled the
+//! clock is never read.
+//!
+//! The totals for each subscription are kept until `reset()` is called. A subscription is marked as slow when its
+//! share of the time spent in the callbacks over the last few pumps goes over a threshold (set by
+//! `ConsumerTimingSettings`), and callbacks registered with `on_slow_consumer()` are called each time a
+//! subscription becomes slow. Subscriptions made by the components attached to a `Hub` are labelled with the name
+//! of the component.
+//!
+//! `SystemClock` reads the time from the system. Tests can supply their own clock to make the measurements
+//! predictable.
+//!
+
+use std::rc::*;
+use std::cell::*;
+use std::fmt;
+use std::time::{Duration, Instant};
+use std::collections::VecDeque;
+
+use super::super::tree::*;
+
+///
+/// Supplies the time for measuring how long consumers take
+///
+pub trait TimingClock {
+    ///
+    /// The time that has passed since some fixed point
+    ///
+    fn now(&self) -> Duration;
+}
+
+///
+/// A timing clock that reads the system's monotonic clock
+///
+pub struct SystemClock {
+    origin: Instant
+}
+
+impl Default for SystemClock {
+    fn default() -> SystemClock {
+        SystemClock::new()
+    }
+}
+
+impl SystemClock {
+    ///
+    /// Creates a clock that measures time from when it was created
+    ///
+    pub fn new() -> SystemClock {
+        SystemClock { origin: Instant::now() }
+    }
+}
+
+impl TimingClock for SystemClock {
+    fn now(&self) -> Duration {
+        self.origin.elapsed()
+    }
+}
+
+///
+/// Settings that decide when a subscription is slow
+///
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ConsumerTimingSettings {
+    /// The number of recent pumps that are considered
+    pub window: usize,
+
+    /// The share of the time spent in callbacks during the recent pumps (between 0 and 1) above which a subscription is slow
+    pub slow_share: f64
+}
+
+impl Default for ConsumerTimingSettings {
+    fn default() -> ConsumerTimingSettings {
+        ConsumerTimingSettings { window: 16, slow_share: 0.5 }
+    }
+}
+
+///
+/// The time taken by the callback of a subscription
+///
+#[derive(Clone, PartialEq)]
+pub struct SubscriptionTiming {
+    /// Identifies the subscription on its bus (in the order the subscriptions were made)
+    pub subscription: usize,
+
+    /// The address that was subscribed to
+    pub address: TreeAddress,
+
+    /// The name of the component that made the subscription, if it's known
+    pub component: Option<String>,
+
+    /// The number of times the callback has been called
+    pub invocations: usize,
+
+    /// The total time spent in the callback
+    pub total: Duration,
+
+    /// The longest time taken by a single call
+    pub max: Duration,
+
+    /// The share of the time spent in callbacks during the recent pumps that was spent in this one
+    pub share: f64,
+
+    /// True if this subscription is currently considered to be slow
+    pub slow: bool
+}
+
+impl fmt::Display for SubscriptionTiming {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.component {
+            Some(ref component) => write!(f, "{} ({})", component, self.address)?,
+            None                => write!(f, "subscription {} ({})", self.subscription, self.address)?
+        }
+
+        write!(f, ": {} calls, {:?} total, {:?} max, {:.0}% of recent time{}", self.invocations, self.total, self.max, self.share * 100.0, if self.slow { " (slow)" } else { "" })
+    }
+}
+
+impl fmt::Debug for SubscriptionTiming {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SubscriptionTiming({})", self)
+    }
+}
+
+///
+/// Callback notified when a subscription becomes slow
+///
+pub type SlowConsumerCallback = Box<dyn FnMut(&SubscriptionTiming)>;
+
+///
+/// The measurements made for a bus
+///
+struct TimingState {
+    /// The clock to read and the settings to use, or None if timing is disabled
+    enabled: Option<(Rc<dyn TimingClock>, ConsumerTimingSettings)>,
+
+    /// The identifier to give the next subscription
+    next_subscription: usize,
+
+    /// The timing of each subscription that has been called, in order of identifier
+    subscriptions: Vec<SubscriptionTiming>,
+
+    /// The time spent in each subscription during the current pump
+    current_pump: Vec<(usize, Duration)>,
+
+    /// The time spent in each subscription during the recent pumps
+    recent_pumps: VecDeque<Vec<(usize, Duration)>>
+}
+
+///
+/// Records how long the subscriptions to a bus take
+///
+/// Every bus has one of these, which records nothing until timing is enabled. Clones share the same measurements.
+///
+#[derive(Clone)]
+pub struct ConsumerTiming {
+    state: Rc<RefCell<TimingState>>,
+    callbacks: Rc<RefCell<Vec<SlowConsumerCallback>>>
+}
+
+impl Default for ConsumerTiming {
+    fn default() -> ConsumerTiming {
+        ConsumerTiming::new()
+    }
+}
+
+impl ConsumerTiming {
+    ///
+    /// Creates a new set of timings, with timing disabled
+    ///
+    pub fn new() -> ConsumerTiming {
+        let state = TimingState {
+            enabled:            None,
+            next_subscription:  0,
+            subscriptions:      vec![],
+            current_pump:       vec![],
+            recent_pumps:       VecDeque::new()
+        };
+
+        ConsumerTiming { state: Rc::new(RefCell::new(state)), callbacks: Rc::new(RefCell::new(vec![])) }
+    }
+
+    ///
+    /// Starts measuring subscriptions using a clock
+    ///
+    pub fn enable(&self, clock: Rc<dyn TimingClock>, settings: ConsumerTimingSettings) {
+        self.state.borrow_mut().enabled = Some((clock, settings));
+    }
+
+    ///
+    /// Stops measuring subscriptions (the measurements made so far are kept)
+    ///
+    pub fn disable(&self) {
+        self.state.borrow_mut().enabled = None;
+    }
+
+    ///
+    /// True if subscriptions are being measured
+    ///
+    pub fn is_enabled(&self) -> bool {
+        self.state.borrow().enabled.is_some()
+    }
+
+    ///
+    /// Adds a callback that's called whenever a subscription becomes slow
+    ///
+    /// The callback isn't called again for the same subscription until it has stopped being slow.
+    ///
+    pub fn on_slow_consumer(&self, callback: SlowConsumerCallback) {
+        self.callbacks.borrow_mut().push(callback);
+    }
+
+    ///
+    /// The timing of each subscription that has been measured
+    ///
+    pub fn stats(&self) -> Vec<SubscriptionTiming> {
+        self.state.borrow().subscriptions.clone()
+    }
+
+    ///
+    /// The timing of the subscriptions that are currently slow
+    ///
+    pub fn slow_consumers(&self) -> Vec<SubscriptionTiming> {
+        self.state.borrow().subscriptions.iter().filter(|timing| timing.slow).cloned().collect()
+    }
+
+    ///
+    /// Discards all of the measurements
+    ///
+    pub fn reset(&self) {
+        let mut state = self.state.borrow_mut();
+
+        state.subscriptions.clear();
+        state.current_pump.clear();
+        state.recent_pumps.clear();
+    }
+
+    ///
+    /// Assigns an identifier to a new subscription
+    ///
+    pub fn allocate_subscription(&self) -> usize {
+        let mut state   = self.state.borrow_mut();
+        let id          = state.next_subscription;
+
+        state.next_subscription += 1;
+        id
+    }
+
+    ///
+    /// Reads the clock before a callback is called, if timing is enabled
+    ///
+    pub fn start(&self) -> Option<Duration> {
+        let clock = self.state.borrow().enabled.as_ref().map(|(clock, _)| clock.clone());
+        clock.map(|clock| clock.now())
+    }
+
+    ///
+    /// Records the time taken by a callback that started at a time returned by `start()`
+    ///
+    pub fn finish(&self, subscription: usize, address: &TreeAddress, component: &Option<String>, started: Duration) {
+        let clock = match self.state.borrow().enabled {
+            Some((ref clock, _))    => clock.clone(),
+            None                    => return
+        };
+
+        let elapsed     = clock.now().checked_sub(started).unwrap_or_default();
+        let mut state   = self.state.borrow_mut();
+
+        let index = match state.subscriptions.binary_search_by_key(&subscription, |timing| timing.subscription) {
+            Ok(index)   => index,
+            Err(index)  => {
+                let timing = SubscriptionTiming { subscription, address: address.clone(), component: component.clone(), invocations: 0, total: Duration::default(), max: Duration::default(), share: 0.0, slow: false };
+                state.subscriptions.insert(index, timing);
+                index
+            }
+        };
+
+        {
+            let timing = &mut state.subscriptions[index];
+            timing.invocations  += 1;
+            timing.total        += elapsed;
+            timing.max          = timing.max.max(elapsed);
+        }
+
+        match state.current_pump.iter().position(|(id, _)| *id == subscription) {
+            Some(pos)   => state.current_pump[pos].1 += elapsed,
+            None        => state.current_pump.push((subscription, elapsed))
+        }
+    }
+
+    ///
+    /// Called by the bus at the end of a pump: returns the time spent in the callbacks during the pump and the
+    /// number of subscriptions that are slow afterwards
+    ///
+    pub fn end_pump(&self) -> (Duration, usize) {
+        let became_slow = {
+            let mut state   = self.state.borrow_mut();
+            let settings    = match state.enabled {
+                Some((_, settings)) => settings,
+                None                => return (Duration::default(), 0)
+            };
+
+            let pump: Vec<_> = state.current_pump.drain(..).collect();
+            if pump.is_empty() {
+                return (Duration::default(), state.subscriptions.iter().filter(|timing| timing.slow).count());
+            }
+
+            let pump_time = pump.iter().map(|(_, time)| *time).sum();
+            state.recent_pumps.push_back(pump);
+            while state.recent_pumps.len() > settings.window.max(1) {
+                state.recent_pumps.pop_front();
+            }
+
+            // Work out each subscription's share of the time spent in the recent pumps
+            let window_time: Duration = state.recent_pumps.iter().flat_map(|pump| pump.iter().map(|(_, time)| *time)).sum();
+            let mut became_slow = vec![];
+
+            if window_time > Duration::default() {
+                let TimingState { ref mut subscriptions, ref recent_pumps, .. } = *state;
+
+                for timing in subscriptions.iter_mut() {
+                    let time: Duration = recent_pumps.iter()
+                        .flat_map(|pump| pump.iter().filter(|(id, _)| *id == timing.subscription).map(|(_, time)| *time))
+                        .sum();
+                    let was_slow = timing.slow;
+
+                    timing.share    = time.as_secs_f64() / window_time.as_secs_f64();
+                    timing.slow     = timing.share > settings.slow_share;
+
+                    if timing.slow && !was_slow {
+                        became_slow.push(timing.clone());
+                    }
+                }
+            }
+
+            (pump_time, became_slow)
+        };
+
+        // The callbacks are called without holding the state so they can read the statistics
+        let (pump_time, became_slow) = became_slow;
+        for timing in became_slow.iter() {
+            for callback in self.callbacks.borrow_mut().iter_mut() {
+                callback(timing);
+            }
+        }
+
+        (pump_time, self.state.borrow().subscriptions.iter().filter(|timing| timing.slow).count())
+    }
+}
+
+#[cfg(test)]
+mod consumer_timing_tests {
+    use std::rc::*;
+    use std::cell::*;
+    use std::time::Duration;
+
+    use super::*;
+    use super::super::super::component::*;
+    use super::super::bus_publisher::*;
+
+    ///
+    /// A clock that only moves when it's told to, and counts how many times it's read
+    ///
+    #[derive(Clone)]
+    struct FakeClock {
+        time: Rc<Cell<Duration>>,
+        reads: Rc<Cell<usize>>
+    }
+
+    impl FakeClock {
+        fn new() -> FakeClock {
+            FakeClock { time: Rc::new(Cell::new(Duration::default())), reads: Rc::new(Cell::new(0)) }
+        }
+
+        fn advance(&self, millis: u64) {
+            self.time.set(self.time.get() + Duration::from_millis(millis));
+        }
+    }
+
+    impl TimingClock for FakeClock {
+        fn now(&self) -> Duration {
+            self.reads.set(self.reads.get() + 1);
+            self.time.get()
+        }
+    }
+
+    ///
+    /// A component that takes a fixed amount of (fake) time to add one to its input
+    ///
+    fn timed_component(clock: &FakeClock, millis: u64) -> Box<dyn Fn(&i32) -> i32> {
+        let clock = clock.clone();
+        component_fn(move |x: &i32| { clock.advance(millis); x+1 })
+    }
+
+    ///
+    /// A hub with a slow component and two fast ones all reading from 'in', with timing enabled
+    ///
+    fn timed_hub(clock: &FakeClock) -> (Hub, PublisherRef) {
+        let mut hub = Hub::new();
+        let input   = hub.publish_to(&"in");
+
+        hub.add_named_component("fast-1", timed_component(clock, 1), &"in", &"out1");
+        hub.add_named_component("slow", timed_component(clock, 20), &"in", &"out2");
+        hub.add_named_component("fast-2", timed_component(clock, 1), &"in", &"out3");
+        hub.enable_consumer_timing(Rc::new(clock.clone()), ConsumerTimingSettings { window: 4, slow_share: 0.5 });
+
+        (hub, input)
+    }
+
+    fn timing_for(timing: &ConsumerTiming, component: &str) -> SubscriptionTiming {
+        timing.stats().into_iter().find(|timing| timing.component.as_ref().map(|name| name == component).unwrap_or(false)).unwrap()
+    }
+
+    #[test]
+    fn slow_component_is_flagged() {
+        let clock               = FakeClock::new();
+        let (mut hub, mut input) = timed_hub(&clock);
+
+        input.publish(TreeChange::new(&(), &1));
+        hub.flush();
+
+        let timing  = hub.consumer_timing();
+        let slow    = timing_for(&timing, "slow");
+        let fast    = timing_for(&timing, "fast-1");
+
+        assert!(slow.slow);
+        assert!(slow.invocations == 1);
+        assert!(slow.total == Duration::from_millis(20));
+        assert!(slow.address == "in".to_tree_address());
+        assert!(!fast.slow);
+        assert!(fast.max == Duration::from_millis(1));
+        assert!(!timing_for(&timing, "fast-2").slow);
+
+        assert!(timing.slow_consumers().iter().map(|timing| timing.component.clone()).collect::<Vec<_>>() == vec![Some("slow".to_string())]);
+    }
+
+    #[test]
+    fn hook_fires_once_per_transition() {
+        let clock               = FakeClock::new();
+        let (mut hub, mut input) = timed_hub(&clock);
+        let alerts              = Rc::new(RefCell::new(vec![]));
+        let hook_alerts         = alerts.clone();
+
+        hub.consumer_timing().on_slow_consumer(Box::new(move |timing| hook_alerts.borrow_mut().push(timing.component.clone())));
+
+        for value in 0..3 {
+            input.publish(TreeChange::new(&(), &value));
+            hub.flush();
+        }
+
+        assert!(*alerts.borrow() == vec![Some("slow".to_string())]);
+
+        // Once the slow component has dropped out of the window it isn't slow any more, and becomes slow again later
+        let mut echo_input = hub.publish_to(&"echo");
+        hub.add_named_component("echo", timed_component(&clock, 1), &"echo", &"echoed");
+
+        for value in 0..4 {
+            echo_input.publish(TreeChange::new(&(), &value));
+            hub.flush();
+        }
+
+        assert!(!timing_for(&hub.consumer_timing(), "slow").slow);
+        assert!(timing_for(&hub.consumer_timing(), "echo").slow);
+
+        input.publish(TreeChange::new(&(), &4));
+        hub.flush();
+
+        assert!(*alerts.borrow() == vec![Some("slow".to_string()), Some("echo".to_string()), Some("slow".to_string())]);
+        assert!(!timing_for(&hub.consumer_timing(), "echo").slow);
+    }
+
+    #[test]
+    fn disabled_timing_does_not_read_clock() {
+        let clock               = FakeClock::new();
+        let (mut hub, mut input) = timed_hub(&clock);
+
+        input.publish(TreeChange::new(&(), &1));
+        hub.flush();
+        assert!(clock.reads.get() > 0);
+
+        hub.disable_consumer_timing();
+        let reads = clock.reads.get();
+
+        input.publish(TreeChange::new(&(), &2));
+        hub.flush();
+
+        assert!(clock.reads.get() == reads);
+        assert!(timing_for(&hub.consumer_timing(), "slow").invocations == 1);
+    }
+
+    #[test]
+    fn stats_accumulate_across_generations_until_reset() {
+        let clock       = FakeClock::new();
+        let mut bus     = TreeChangeBus::new();
+        let mut input   = bus.create_publisher();
+        let mut relay   = bus.create_publisher();
+        let mut first   = bus.create_consumer();
+        let mut second  = bus.create_consumer();
+        let relay_clock = clock.clone();
+        let read_clock  = clock.clone();
+
+        bus.enable_consumer_timing(Rc::new(clock.clone()), ConsumerTimingSettings::default());
+
+        // The first subscription publishes a change that the second one reads in the next generation
+        first.subscribe("in".to_tree_address(), TreeExtent::SubTree, Box::new(move |change| { relay_clock.advance(2); relay.publish(change.rebased_to(&"out".to_tree_address())); }));
+        second.subscribe("out".to_tree_address(), TreeExtent::SubTree, Box::new(move |_change| read_clock.advance(3)));
+
+        input.publish(TreeChange::new(&"in", &1));
+        let stats = bus.flush();
+
+        assert!(stats.consumer_time == Duration::from_millis(5));
+        assert!(stats.slow_consumers == 1);
+        assert!(bus.consumer_timing().stats().iter().map(|timing| (timing.invocations, timing.total)).collect::<Vec<_>>() == vec![(1, Duration::from_millis(2)), (1, Duration::from_millis(3))]);
+
+        input.publish(TreeChange::new(&"in", &2));
+        bus.flush();
+        assert!(bus.consumer_timing().stats().iter().map(|timing| timing.invocations).collect::<Vec<_>>() == vec![2, 2]);
+
+        bus.consumer_timing().reset();
+        assert!(bus.consumer_timing().stats().is_empty());
+
+        input.publish(TreeChange::new(&"in", &3));
+        bus.flush();
+        assert!(bus.consumer_timing().stats().iter().map(|timing| timing.invocations).collect::<Vec<_>>() == vec![1, 1]);
+    }
+}
@@ -0,0 +1,484 @@
+//
+//   Copyright 2016 Andrew Hunter
+//
+//   Licensed under the Apache License, Version 2.0 (the "License");
+//   you may not use this file except in compliance with the License.
+//   You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+//   Unless required by applicable law or agreed to in writing, software
+//   distributed under the License is distributed on an "AS IS" BASIS,
+//   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//   See the License for the specific language governing permissions and
+//   limitations under the License.
+//
+
+//!
+//! # Control trees
+//!
+//! A control tree lets a hub be administered by publishing changes to it, in the same way as any other part of
+//! the hub. `Hub::enable_control()` reserves an address for the control tree; it's disabled by default.
+//!
+//! Each command is a node published under the control address. The tag of the node is the correlation id of
+//! the command, its value is the name of the command and its children are the parameters. `control_command()`
+//! creates a node of this shape from a structure containing the parameters. The parameters are checked against
+//! the shape of the structure each command expects (see `tametree::tree::shape`) before the command is run.
+//!
+//! The result of each command is published as a `ControlResult` under the `results` child of the control
+//! address, tagged with the command's correlation id. Commands are run one at a time, in the order they're
+//! delivered.
+//!
+//! The commands a hub understands are:
+//!
+//! * `pause` and `resume`, with a `ComponentParameters`: stop or restart delivering changes to a named component
+//! * `set_metrics`, with a `ToggleParameters`: start or stop measuring how long the subscriptions take
+//! * `set_tracing`, with a `ToggleParameters`: start or stop passing invocations to the hub's trace hook
+//! * `release_quarantined` and `discard_quarantined`, with a `QuarantineParameters`: release or discard a
+//!   quarantined change. These are only available if the quarantine was enabled before the control tree
+//!
+//! ```
+//! # use tametree::prelude::*;
+//! # use tametree::component::control::*;
+//! let mut hub     = Hub::new();
+//! let mut control = hub.publish_to(&"control");
+//!
+//! hub.add_named_component("double", component_fn(|x: &i32| x * 2), &"in", &"out");
+//! hub.enable_control(&"control");
+//!
+//! let pause = control_command("pause-1", "pause", &ComponentParameters { component: "double".to_string() });
+//! control.publish(TreeChange::new(&"pause-1", &pause));
+//! hub.flush();
+//!
+//! assert!(hub.is_paused("double"));
+//! ```
+//!
+
+use std::rc::*;
+use std::fmt;
+
+use super::super::tree::*;
+use super::component::*;
+
+///
+/// The tag of the child of the control address that results are published under
+///
+pub const RESULTS_TAG: &str = "results";
+
+tree_struct! {
+    ///
+    /// The parameters of a command that applies to a component
+    ///
+    #[derive(Clone, PartialEq, Default, Debug)]
+    pub struct ComponentParameters {
+        // The name the component was given when it was added to the hub
+        pub component: String
+    }
+}
+
+tree_struct! {
+    ///
+    /// The parameters of a command that turns something on or off
+    ///
+    #[derive(Clone, PartialEq, Default, Debug)]
+    pub struct ToggleParameters {
+        pub enabled: bool
+    }
+}
+
+tree_struct! {
+    ///
+    /// The parameters of a command that applies to a quarantined change
+    ///
+    #[derive(Clone, PartialEq, Default, Debug)]
+    pub struct QuarantineParameters {
+        // The ID of the quarantined change
+        pub id: i32
+    }
+}
+
+impl QuarantineParameters {
+    ///
+    /// The ID of the quarantined change these parameters refer to, or an error if it's negative
+    ///
+    pub fn change_id(&self) -> Result<u64, String> {
+        if self.id < 0 {
+            Err(format!("{} is not a valid quarantine ID", self.id))
+        } else {
+            Ok(self.id as u64)
+        }
+    }
+}
+
+tree_struct! {
+    ///
+    /// The result of a command published to a control tree
+    ///
+    #[derive(Clone, PartialEq, Debug)]
+    pub struct ControlResult {
+        // The correlation id of the command
+        pub id: String,
+
+        // The name of the command
+        pub command: String,
+
+        // "ok", "unknown-command", "schema-violation" or "failed"
+        pub status: String,
+
+        // What the command did, or why it couldn't be run
+        pub message: String
+    }
+}
+
+impl ControlResult {
+    ///
+    /// Creates a new result
+    ///
+    fn new(id: &str, command: &str, status: &str, message: &str) -> ControlResult {
+        ControlResult { id: id.to_string(), command: command.to_string(), status: status.to_string(), message: message.to_string() }
+    }
+
+    ///
+    /// True if the command was run successfully
+    ///
+    pub fn succeeded(&self) -> bool {
+        self.status == "ok"
+    }
+
+    ///
+    /// Creates this result as a tree tagged with the correlation id of its command
+    ///
+    pub fn as_tree(&self) -> TreeRef {
+        Rc::new(BasicTree::new(&self.id, (), self.to_tree_node().get_child_ref(), None))
+    }
+}
+
+impl fmt::Display for ControlResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({}): {}: {}", self.id, self.command, self.status, self.message)
+    }
+}
+
+///
+/// Creates a command node that can be published to a control tree
+///
+pub fn control_command<TParameters: ToTreeNode>(id: &str, command: &str, parameters: &TParameters) -> TreeRef {
+    Rc::new(BasicTree::new(id, command, parameters.to_tree_node().get_child_ref(), None))
+}
+
+///
+/// Runs a command, given its parameters. Returns a description of what it did or of why it failed.
+///
+type ControlHandler = Box<dyn Fn(&TreeRef) -> Result<String, String>>;
+
+///
+/// A command that can be run from a control tree
+///
+struct ControlCommand {
+    /// The name of the command
+    name: String,
+
+    /// The encoded default parameters, whose root the parameters are attached to before they're decoded
+    defaults: TreeRef,
+
+    /// The shape that the parameters must have
+    parameters: TreeShape,
+
+    /// Runs the command
+    handler: ControlHandler
+}
+
+///
+/// The commands understood by a control tree
+///
+#[derive(Default)]
+pub struct ControlCommands {
+    commands: Vec<ControlCommand>
+}
+
+///
+/// Describes a parameter found when checking a command against its shape
+///
+fn parameter_name(address: &TreeAddress) -> String {
+    match *address {
+        TreeAddress::ChildWithTag(ref tag, ref rest) if **rest == TreeAddress::Here => tag.clone(),
+        _ => address.to_string()
+    }
+}
+
+impl ControlCommands {
+    ///
+    /// Creates a set of commands with nothing in it
+    ///
+    pub fn new() -> ControlCommands {
+        ControlCommands { commands: vec![] }
+    }
+
+    ///
+    /// Adds a command, which will be given its parameters decoded as a `TParameters`
+    ///
+    /// Adding a command with the same name as an existing one replaces it.
+    ///
+    pub fn add_command<TParameters, TRun>(&mut self, name: &str, run: TRun)
+    where TParameters: 'static + ToTreeNode + DecodeFromTreeNode + Default, TRun: 'static + Fn(TParameters) -> Result<String, String> {
+        let handler: ControlHandler = Box::new(move |parameters| {
+            TParameters::new_from_tree(parameters)
+                .map_err(|err| format!("could not decode the parameters: {:?}", err))
+                .and_then(&run)
+        });
+
+        let defaults = TParameters::default().to_tree_node();
+
+        self.commands.retain(|command| command.name != name);
+        self.commands.push(ControlCommand { name: name.to_string(), parameters: TreeShape::of_tree(&defaults), defaults, handler });
+    }
+
+    ///
+    /// The names of the commands in this set
+    ///
+    pub fn command_names(&self) -> Vec<String> {
+        self.commands.iter().map(|command| command.name.clone()).collect()
+    }
+
+    ///
+    /// Runs the command described by a command node
+    ///
+    pub fn run(&self, command_node: &TreeRef) -> ControlResult {
+        let id      = command_node.get_tag();
+        let name    = command_node.get_value().to_str("");
+
+        let command = match self.commands.iter().find(|command| command.name == name) {
+            Some(command)   => command,
+            None            => return ControlResult::new(id, name, "unknown-command", &format!("there is no command called '{}' (available commands: {})", name, self.command_names().join(", ")))
+        };
+
+        // The command name is the value of the command node, so only its children are the parameters
+        let parameters = command.defaults.with_children(&command_node.iter_children().collect());
+
+        if let ShapeCompatibility::Incompatible { missing, mismatched } = command.parameters.compatible_with(&TreeShape::of_tree(&parameters)) {
+            let mut problems = vec![];
+            problems.extend(missing.iter().map(|address| format!("'{}' is missing", parameter_name(address))));
+            problems.extend(mismatched.iter().map(|(address, needed, provided)| format!("'{}' should be {:?} but is {:?}", parameter_name(address), needed, provided)));
+
+            return ControlResult::new(id, name, "schema-violation", &problems.join(", "));
+        }
+
+        match (command.handler)(&parameters) {
+            Ok(message)     => ControlResult::new(id, name, "ok", &message),
+            Err(message)    => ControlResult::new(id, name, "failed", &message)
+        }
+    }
+}
+
+///
+/// Runs the commands published to a consumer, publishing their results to a publisher
+///
+/// Both the consumer and the publisher should be relative to the control address. Changes to the results are
+/// ignored, and changes that don't publish a whole command node are reported as schema violations.
+///
+pub fn subscribe_control(mut consumer: ConsumerRef, mut publisher: PublisherRef, commands: ControlCommands) {
+    consumer.subscribe(TreeAddress::Here, TreeExtent::SubTree, Box::new(move |change| {
+        let command_nodes = match (change.address(), change.replacement()) {
+            // The whole control tree was replaced: every child is a command
+            (TreeAddress::Here, TreeReplacement::NewNode(control)) => control.iter_children().filter(|node| node.get_tag() != RESULTS_TAG).collect(),
+
+            (TreeAddress::ChildWithTag(tag, _), _) if tag == RESULTS_TAG => vec![],
+            (TreeAddress::ChildWithTag(_, rest), TreeReplacement::NewNode(command)) if **rest == TreeAddress::Here => vec![command.clone()],
+
+            // Anything else is an edit to part of a command (or a removal), which can't be run
+            (address, _) => {
+                let id = match *address {
+                    TreeAddress::ChildWithTag(ref tag, _)   => tag.clone(),
+                    _                                       => address.to_string()
+                };
+
+                let result = ControlResult::new(&id, "", "schema-violation", "commands must be published as a whole node under the control address");
+                publisher.publish(TreeChange::new(&(RESULTS_TAG, &*id), &result.as_tree()));
+                vec![]
+            }
+        };
+
+        for command_node in command_nodes {
+            let result = commands.run(&command_node);
+            publisher.publish(TreeChange::new(&(RESULTS_TAG, &*result.id), &result.as_tree()));
+        }
+    }));
+}
+
+#[cfg(test)]
+mod control_tests {
+    use std::rc::*;
+    use std::cell::*;
+
+    use super::super::super::tree::*;
+    use super::super::super::component::*;
+    use super::*;
+
+    ///
+    /// Creates a hub with a component called 'double' and a control tree at 'control', returning the publisher
+    /// for commands and the results that are published
+    ///
+    fn control_hub() -> (Hub, PublisherRef, Rc<RefCell<Vec<ControlResult>>>) {
+        let mut hub     = Hub::new();
+        let control     = hub.publish_to(&"control");
+        let results     = Rc::new(RefCell::new(vec![]));
+        let recorded    = results.clone();
+
+        hub.add_named_component("double", component_fn(|x: &i32| x * 2), &"in", &"out");
+        hub.enable_control(&"control");
+
+        hub.read_from(&("control", RESULTS_TAG)).subscribe(TreeAddress::Here, TreeExtent::SubTree, Box::new(move |change| {
+            if let TreeReplacement::NewNode(ref node) = *change.replacement() {
+                recorded.borrow_mut().push(ControlResult::new_from_tree(node).unwrap());
+            }
+        }));
+
+        (hub, control, results)
+    }
+
+    fn component(name: &str) -> ComponentParameters {
+        ComponentParameters { component: name.to_string() }
+    }
+
+    #[test]
+    fn pausing_a_component_stops_its_deliveries() {
+        let (mut hub, mut control, results) = control_hub();
+        let mut input                       = hub.publish_to(&"in");
+        let outputs                         = Rc::new(RefCell::new(vec![]));
+        let recorded                        = outputs.clone();
+
+        hub.read_from(&"out").subscribe(TreeAddress::Here, TreeExtent::SubTree, Box::new(move |change| {
+            if let TreeReplacement::NewNode(ref node) = *change.replacement() {
+                recorded.borrow_mut().push(node.get_value().to_int(0));
+            }
+        }));
+
+        input.publish(TreeChange::new(&(), &1));
+        hub.flush();
+
+        control.publish(TreeChange::new(&"p1", &control_command("p1", "pause", &component("double"))));
+        hub.flush();
+        assert!(hub.is_paused("double"));
+
+        input.publish(TreeChange::new(&(), &2));
+        hub.flush();
+        assert!(*outputs.borrow() == vec![2]);
+
+        control.publish(TreeChange::new(&"r1", &control_command("r1", "resume", &component("double"))));
+        input.publish(TreeChange::new(&(), &3));
+        hub.flush();
+
+        assert!(!hub.is_paused("double"));
+        assert!(*outputs.borrow() == vec![2, 6]);
+        assert!(results.borrow().iter().map(|result| (result.id.clone(), result.succeeded())).collect::<Vec<_>>() == vec![("p1".to_string(), true), ("r1".to_string(), true)]);
+    }
+
+    #[test]
+    fn invalid_commands_report_errors() {
+        let (mut hub, mut control, results) = control_hub();
+
+        control.publish(TreeChange::new(&"bad-type", &control_command("bad-type", "set_metrics", &component("double"))));
+        control.publish(TreeChange::new(&"unknown", &control_command("unknown", "snapshot", &component("double"))));
+        control.publish(TreeChange::new(&"no-such", &control_command("no-such", "pause", &component("triple"))));
+        hub.flush();
+
+        let results = results.borrow();
+        assert!(results.len() == 3);
+
+        assert!(results[0].id == "bad-type" && results[0].status == "schema-violation");
+        assert!(results[0].message.contains("'enabled' is missing"));
+        assert!(results[1].id == "unknown" && results[1].status == "unknown-command");
+        assert!(results[2].id == "no-such" && results[2].status == "failed");
+        assert!(!hub.is_paused("double"));
+    }
+
+    #[test]
+    fn negative_quarantine_ids_fail() {
+        let mut hub     = Hub::new();
+        let mut control = hub.publish_to(&"control");
+        let results     = Rc::new(RefCell::new(vec![]));
+        let recorded    = results.clone();
+
+        hub.enable_quarantine(10, QuarantineOverflow::RejectNew);
+        hub.enable_control(&"control");
+
+        hub.read_from(&("control", RESULTS_TAG)).subscribe(TreeAddress::Here, TreeExtent::SubTree, Box::new(move |change| {
+            if let TreeReplacement::NewNode(ref node) = *change.replacement() {
+                recorded.borrow_mut().push(ControlResult::new_from_tree(node).unwrap());
+            }
+        }));
+
+        control.publish(TreeChange::new(&"r1", &control_command("r1", "release_quarantined", &QuarantineParameters { id: -1 })));
+        control.publish(TreeChange::new(&"d1", &control_command("d1", "discard_quarantined", &QuarantineParameters { id: -2 })));
+        hub.flush();
+
+        let results = results.borrow();
+        assert!(results.len() == 2);
+        assert!(results[0].status == "failed" && results[0].message == "-1 is not a valid quarantine ID");
+        assert!(results[1].status == "failed" && results[1].message == "-2 is not a valid quarantine ID");
+    }
+
+    #[test]
+    fn mistyped_parameters_are_schema_violations() {
+        let (mut hub, mut control, results) = control_hub();
+        let command                         = Rc::new(BasicTree::new("c1", "set_tracing", Some(("enabled", 1).to_tree_node()), None));
+
+        control.publish(TreeChange::new(&"c1", &command));
+        hub.flush();
+
+        assert!(results.borrow()[0].status == "schema-violation");
+        assert!(results.borrow()[0].message == "'enabled' should be Bool but is Int");
+    }
+
+    #[test]
+    fn commands_are_handled_in_order() {
+        let (mut hub, mut control, results) = control_hub();
+
+        // Several commands in one change, followed by more that arrive while the first ones are being processed
+        let batch = tree!("control", control_command("c1", "pause", &component("double")), control_command("c2", "resume", &component("double")));
+        control.publish(TreeChange::new(&(), &batch));
+        control.publish(TreeChange::new(&"c3", &control_command("c3", "pause", &component("double"))));
+        hub.pump();
+
+        control.publish(TreeChange::new(&"c4", &control_command("c4", "resume", &component("double"))));
+        control.publish(TreeChange::new(&"c5", &control_command("c5", "pause", &component("double"))));
+        hub.flush();
+
+        assert!(results.borrow().iter().map(|result| result.id.clone()).collect::<Vec<_>>() == vec!["c1", "c2", "c3", "c4", "c5"]);
+        assert!(results.borrow().iter().all(|result| result.succeeded()));
+        assert!(hub.is_paused("double"));
+    }
+
+    #[test]
+    fn metrics_can_be_switched_on_and_off() {
+        let (mut hub, mut control, results) = control_hub();
+
+        control.publish(TreeChange::new(&"on", &control_command("on", "set_metrics", &ToggleParameters { enabled: true })));
+        hub.flush();
+        assert!(hub.consumer_timing().is_enabled());
+
+        control.publish(TreeChange::new(&"off", &control_command("off", "set_metrics", &ToggleParameters { enabled: false })));
+        hub.flush();
+        assert!(!hub.consumer_timing().is_enabled());
+        assert!(results.borrow().len() == 2);
+    }
+
+    #[test]
+    fn disabling_control_removes_the_subscription() {
+        let mut hub     = Hub::new();
+        let mut control = hub.publish_to(&"control");
+        let before      = hub.subscription_count();
+
+        hub.enable_control(&"control");
+        assert!(hub.subscription_count() == before + 1);
+
+        hub.disable_control();
+        assert!(hub.subscription_count() == before);
+
+        // Commands are ignored once control is disabled
+        control.publish(TreeChange::new(&"p1", &control_command("p1", "pause", &component("double"))));
+        hub.add_named_component("double", component_fn(|x: &i32| x * 2), &"in", &"out");
+        hub.flush();
+        assert!(!hub.is_paused("double"));
+    }
+}
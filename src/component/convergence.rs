@@ -0,0 +1,238 @@
+//
+//   Copyright 2016 Andrew Hunter
+//
+//   Licensed under the Apache License, Version 2.0 (the "License");
+//   you may not use this file except in compliance with the License.
+//   You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+//   Unless required by applicable law or agreed to in writing, software
+//   distributed under the License is distributed on an "AS IS" BASIS,
+//   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//   See the License for the specific language governing permissions and
+//   limitations under the License.
+//
+
+//!
+//! # Convergence monitoring
+//!
+//! Components that publish back to the bus they read from form a feedback loop. A loop that is working properly
+//! generates fewer and fewer changes until it stops, but a mistake can make it run forever, grow without limit or
+//! bounce between states. A `ConvergenceMonitor` can be attached to a `TreeChangeBus` (or a `Hub`) to tell these
+//! cases apart.
+//!
+//! Each pump of the bus is a generation. The monitor records how many changes were published from outside the
+//! bus and how many were generated by the consumers while the changes were being delivered, and classifies the
+//! recent history of generated changes:
+//!
+//! * `Steady` when the last generation didn't generate any changes.
+//! * `Oscillating` when the generated counts repeat with a period of two or more.
+//! * `Diverging` when the generated counts have grown over the whole window.
+//! * `Converging` when the generated counts haven't grown. The rate is the average decrease per generation: a
+//!   loop that stays at a rate of 0 is generating a constant amount of traffic and may have stalled.
+//!
+//! Only generated changes are considered, so a bus with a steady stream of changes from outside isn't
+//! mistaken for a loop.
+//!
+
+use std::collections::VecDeque;
+
+///
+/// The number of changes involved in a single generation of a bus
+///
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct GenerationCounts {
+    /// Changes delivered in this generation that were published from outside the bus
+    pub external: usize,
+
+    /// Changes published by consumers while this generation was being delivered
+    pub generated: usize
+}
+
+///
+/// How the feedback on a bus is behaving
+///
+#[derive(Clone, PartialEq, Debug)]
+pub enum ConvergenceStatus {
+    /// Not enough generations have been seen to tell
+    Unknown,
+
+    /// The number of generated changes is not growing (the rate is the average decrease per generation)
+    Converging { rate: f64 },
+
+    /// No changes were generated by the last generation
+    Steady,
+
+    /// The number of generated changes has grown over the whole window (the growth is the ratio of the last count to the first)
+    Diverging { growth: f64 },
+
+    /// The number of generated changes is repeating with the specified period
+    Oscillating { period: usize }
+}
+
+///
+/// Callback made when the status of a convergence monitor changes (with the old and the new status)
+///
+pub type ConvergenceCallback = Box<dyn FnMut(&ConvergenceStatus, &ConvergenceStatus)>;
+
+///
+/// Records the changes generated by each pump of a bus and works out whether or not its feedback is converging
+///
+pub struct ConvergenceMonitor {
+    /// The number of generations to consider
+    window: usize,
+
+    /// The most recent generations
+    history: VecDeque<GenerationCounts>,
+
+    /// The current status
+    status: ConvergenceStatus,
+
+    /// Called when the status changes
+    on_transition: Option<ConvergenceCallback>
+}
+
+impl ConvergenceMonitor {
+    ///
+    /// Creates a monitor that considers the specified number of generations
+    ///
+    /// The window must be at least 4 generations long for a two-cycle oscillation to be detected.
+    ///
+    pub fn new(window: usize) -> ConvergenceMonitor {
+        ConvergenceMonitor { window: window.max(2), history: VecDeque::new(), status: ConvergenceStatus::Unknown, on_transition: None }
+    }
+
+    ///
+    /// Sets a function to call whenever the status changes
+    ///
+    pub fn with_transition_callback(mut self, callback: ConvergenceCallback) -> ConvergenceMonitor {
+        self.on_transition = Some(callback);
+        self
+    }
+
+    ///
+    /// The current status
+    ///
+    pub fn status(&self) -> ConvergenceStatus {
+        self.status.clone()
+    }
+
+    ///
+    /// The generations in the current window, oldest first
+    ///
+    pub fn history(&self) -> Vec<GenerationCounts> {
+        self.history.iter().cloned().collect()
+    }
+
+    ///
+    /// Records a generation, returning the new status
+    ///
+    pub fn record(&mut self, counts: GenerationCounts) -> ConvergenceStatus {
+        self.history.push_back(counts);
+        while self.history.len() > self.window {
+            self.history.pop_front();
+        }
+
+        let new_status = self.classify();
+
+        if new_status != self.status {
+            let old_status = self.status.clone();
+            self.status = new_status;
+
+            if let Some(ref mut on_transition) = self.on_transition {
+                on_transition(&old_status, &self.status);
+            }
+        }
+
+        self.status.clone()
+    }
+
+    ///
+    /// Works out the status from the history
+    ///
+    fn classify(&self) -> ConvergenceStatus {
+        let generated: Vec<usize> = self.history.iter().map(|counts| counts.generated).collect();
+        let len = generated.len();
+
+        if generated.last() == Some(&0) {
+            return ConvergenceStatus::Steady;
+        }
+
+        // Oscillations need two complete cycles to be recognised
+        let is_constant = generated.iter().all(|count| *count == generated[0]);
+
+        for period in 2..=(len/2) {
+            let repeats = (period..len).all(|index| generated[index] == generated[index-period]);
+
+            if repeats && !is_constant {
+                return ConvergenceStatus::Oscillating { period };
+            }
+        }
+
+        if len < 2 {
+            return ConvergenceStatus::Unknown;
+        }
+
+        let first       = generated[0];
+        let last        = generated[len-1];
+        let growing     = generated.windows(2).all(|pair| pair[1] >= pair[0]);
+        let shrinking   = generated.windows(2).all(|pair| pair[1] <= pair[0]);
+
+        if shrinking {
+            ConvergenceStatus::Converging { rate: (first - last) as f64 / (len-1) as f64 }
+        } else if growing && last > first && len >= self.window {
+            ConvergenceStatus::Diverging { growth: last as f64 / first.max(1) as f64 }
+        } else {
+            ConvergenceStatus::Unknown
+        }
+    }
+}
+
+#[cfg(test)]
+mod convergence_tests {
+    use std::rc::*;
+    use std::cell::*;
+
+    use super::*;
+
+    fn generated(count: usize) -> GenerationCounts {
+        GenerationCounts { external: 0, generated: count }
+    }
+
+    #[test]
+    fn decreasing_counts_are_converging() {
+        let mut monitor = ConvergenceMonitor::new(4);
+
+        assert!(monitor.record(generated(8)) == ConvergenceStatus::Unknown);
+        assert!(monitor.record(generated(4)) == ConvergenceStatus::Converging { rate: 4.0 });
+        assert!(monitor.record(generated(2)) == ConvergenceStatus::Converging { rate: 3.0 });
+        assert!(monitor.record(generated(0)) == ConvergenceStatus::Steady);
+    }
+
+    #[test]
+    fn repeating_counts_are_oscillating() {
+        let mut monitor = ConvergenceMonitor::new(6);
+
+        for count in [3, 1, 2, 3, 1, 2].iter() {
+            monitor.record(generated(*count));
+        }
+
+        assert!(monitor.status() == ConvergenceStatus::Oscillating { period: 3 });
+    }
+
+    #[test]
+    fn growth_over_window_is_diverging() {
+        let transitions     = Rc::new(RefCell::new(vec![]));
+        let also_transitions = transitions.clone();
+        let mut monitor     = ConvergenceMonitor::new(3).with_transition_callback(Box::new(move |_old, new| also_transitions.borrow_mut().push(new.clone())));
+
+        monitor.record(generated(1));
+        monitor.record(generated(2));
+        assert!(monitor.status() == ConvergenceStatus::Unknown);
+
+        monitor.record(generated(4));
+        assert!(monitor.status() == ConvergenceStatus::Diverging { growth: 4.0 });
+        assert!(*transitions.borrow() == vec![ConvergenceStatus::Diverging { growth: 4.0 }]);
+    }
+}
@@ -0,0 +1,1188 @@
+//
+//   Copyright 2016 Andrew Hunter
+//
+//   Licensed under the Apache License, Version 2.0 (the "License");
+//   you may not use this file except in compliance with the License.
+//   You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+//   Unless required by applicable law or agreed to in writing, software
+//   distributed under the License is distributed on an "AS IS" BASIS,
+//   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//   See the License for the specific language governing permissions and
+//   limitations under the License.
+//
+
+//!
+//! # Durable subscriptions
+//!
+//! A `DurableBus` is a bus that writes every change published to it to a log on disk before delivering it, and
+//! remembers how far each named subscriber has got. When a process that forwards changes somewhere else restarts,
+//! it can open the bus again and subscribe with the same names: each subscriber is first sent the changes it
+//! hasn't acknowledged yet, in order, and then carries on receiving new changes as they're published.
+//!
+//! Every change is given a sequence number, starting at 1. A subscriber acknowledges a change by returning true
+//! from its callback, or later on by calling `ack()`. Acknowledging a change also acknowledges every change before
+//! it. Acknowledgements are written to disk after the callback returns, so if the process stops after a
+//! subscriber has acted on a change but before the acknowledgement is written, the change is sent again when the
+//! bus is reopened. Delivery is therefore at least once: subscribers that can't tolerate duplicates should
+//! remember the sequence numbers they have seen.
+//!
+//! The log is split into segments. `rotate_log()` starts a new segment, passes the path of the finished one to the
+//! rotation hook (so it can be archived) and deletes any segments that every subscriber has acknowledged.
+//!
+//! Each record in the log or in the file of acknowledgements is written as a header containing its length and a
+//! checksum followed by the record in the text tree format. If a file ends with a partial or corrupted record (if
+//! the process stopped while writing it, for example), it's cut back to the last complete record when the bus is
+//! opened.
+//!
+//! Ordinary consumers created with `create_consumer()` receive the changes too, but aren't tracked and don't
+//! have anything replayed to them.
+//!
+//! `compact_log()` shortens a finished log segment (one passed to the rotation hook, for example) by dropping the
+//! changes that later changes replace or by replacing runs of changes with snapshots (see
+//! `tametree::tree::compaction`).
+//!
+
+use std::io;
+use std::fs;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::rc::*;
+use std::cell::*;
+use std::collections::{HashMap, VecDeque};
+
+use super::super::tree::*;
+use super::component::*;
+use super::bus_publisher::*;
+
+///
+/// Callback for a durable subscription: receives the sequence number and the change, and returns true to
+/// acknowledge it
+///
+pub type DurableCallback = Box<dyn FnMut(u64, &TreeChange) -> bool>;
+
+///
+/// Callback made with the path of a log segment once the bus has stopped writing to it
+///
+pub type RotationHook = Box<dyn FnMut(&Path)>;
+
+///
+/// The name of the file containing the acknowledgements
+///
+const CURSOR_FILE: &str = "cursors.log";
+
+///
+/// Why a record in a log couldn't be read
+///
+#[derive(Clone, PartialEq, Debug)]
+pub enum RecordError {
+    /// The log ends part of the way through the record
+    Truncated,
+
+    /// The header of the record isn't a length followed by a checksum
+    BadHeader,
+
+    /// The record doesn't match its checksum
+    ChecksumMismatch,
+
+    /// The record isn't UTF-8 text
+    NotText,
+
+    /// The record isn't in the text tree format
+    Text(TextParseError),
+
+    /// The record is a tree, but not one that describes a change
+    NotAChange
+}
+
+impl fmt::Display for RecordError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RecordError::Truncated          => write!(f, "the log ends part of the way through the record"),
+            RecordError::BadHeader          => write!(f, "the record header is not a length and a checksum"),
+            RecordError::ChecksumMismatch   => write!(f, "the record does not match its checksum"),
+            RecordError::NotText            => write!(f, "the record is not UTF-8 text"),
+            RecordError::Text(ref error)    => write!(f, "the record is not a tree: {}", error),
+            RecordError::NotAChange         => write!(f, "the record does not describe a change")
+        }
+    }
+}
+
+///
+/// Error returned when a log can't be compacted
+///
+#[derive(Debug)]
+pub enum CompactError {
+    /// The log couldn't be read, or the compacted log couldn't be written
+    Io(io::Error),
+
+    /// The record at this index in the log (counting from 0) couldn't be read
+    Decode { index: usize, error: RecordError }
+}
+
+impl From<io::Error> for CompactError {
+    fn from(error: io::Error) -> CompactError {
+        CompactError::Io(error)
+    }
+}
+
+impl fmt::Display for CompactError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CompactError::Io(ref error)                 => write!(f, "could not compact the log: {}", error),
+            CompactError::Decode { index, ref error }   => write!(f, "could not read record {} of the log: {}", index, error)
+        }
+    }
+}
+
+///
+/// Computes the checksum of a record (64-bit FNV-1a)
+///
+fn checksum(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| (hash ^ (*byte as u64)).wrapping_mul(0x100000001b3))
+}
+
+///
+/// Frames a record so that a partial or corrupted copy of it can be detected
+///
+fn frame_record(record: &TreeRef) -> Vec<u8> {
+    let text        = to_tree_text(record);
+    let mut framed  = format!("{} {:016x}\n", text.len(), checksum(text.as_bytes())).into_bytes();
+
+    framed.extend(text.as_bytes());
+    framed
+}
+
+///
+/// Reads the record that starts at a particular position in some bytes, returning it along with the position
+/// where it ends
+///
+fn read_framed_record(bytes: &[u8], pos: usize) -> Result<(TreeRef, usize), RecordError> {
+    let header_end = match bytes[pos..].iter().position(|byte| *byte == b'\n') {
+        Some(offset)    => pos + offset,
+        None            => return Err(RecordError::Truncated)
+    };
+
+    let header          = String::from_utf8_lossy(&bytes[pos..header_end]).to_string();
+    let mut parts       = header.split(' ');
+    let length          = parts.next().and_then(|length| length.parse::<usize>().ok());
+    let expected_sum    = parts.next().and_then(|sum| u64::from_str_radix(sum, 16).ok());
+
+    let (length, expected_sum) = match (length, expected_sum) {
+        (Some(length), Some(sum))   => (length, sum),
+        _                           => return Err(RecordError::BadHeader)
+    };
+
+    let start   = header_end + 1;
+    let end     = start.saturating_add(length);
+    if end > bytes.len() {
+        return Err(RecordError::Truncated);
+    }
+
+    if checksum(&bytes[start..end]) != expected_sum {
+        return Err(RecordError::ChecksumMismatch);
+    }
+
+    let text = ::std::str::from_utf8(&bytes[start..end]).map_err(|_| RecordError::NotText)?;
+    let record = parse_tree_text(text).map_err(RecordError::Text)?;
+
+    Ok((record, end))
+}
+
+///
+/// Reads the complete records at the start of some bytes, returning them along with the number of bytes they
+/// take up
+///
+fn read_framed_records(bytes: &[u8]) -> (Vec<TreeRef>, usize) {
+    let mut records = vec![];
+    let mut pos     = 0;
+
+    while let Ok((record, end)) = read_framed_record(bytes, pos) {
+        records.push(record);
+        pos = end;
+    }
+
+    (records, pos)
+}
+
+///
+/// Reads the records in a file, cutting it back to the last complete record. Returns the records and the number
+/// of bytes that were discarded.
+///
+fn recover_file(path: &Path) -> io::Result<(Vec<TreeRef>, u64)> {
+    let mut bytes = vec![];
+    File::open(path)?.read_to_end(&mut bytes)?;
+
+    let (records, good_length) = read_framed_records(&bytes);
+    let discarded = (bytes.len() - good_length) as u64;
+
+    if discarded > 0 {
+        OpenOptions::new().write(true).open(path)?.set_len(good_length as u64)?;
+    }
+
+    Ok((records, discarded))
+}
+
+///
+/// Appends a record to a file and waits for it to reach the disk
+///
+fn append_record(file: &mut File, record: &TreeRef) -> io::Result<()> {
+    file.write_all(&frame_record(record))?;
+    file.sync_data()
+}
+
+///
+/// Converts an address to a record, with one child per part of the address
+///
+fn address_record(address: &TreeAddress) -> TreeRef {
+    let mut parts   = vec![];
+    let mut address = address;
+
+    loop {
+        match *address {
+            TreeAddress::Here                               => break,
+            TreeAddress::ChildAtIndex(index, ref rest)      => { parts.push(("index", index.to_string()).to_tree_node()); address = rest; },
+            TreeAddress::ChildWithTag(ref tag, ref rest)    => { parts.push(("tag", tag.clone()).to_tree_node()); address = rest; }
+        }
+    }
+
+    "address".to_tree_node().with_children(&parts)
+}
+
+///
+/// Reads an address from a record
+///
+fn address_from_record(record: &TreeRef) -> Option<TreeAddress> {
+    let mut parts = vec![];
+
+    for part in record.iter_children() {
+        let value = match *part.get_value() {
+            TreeValue::String(ref value)    => value.to_string(),
+            _                               => return None
+        };
+
+        parts.push(match part.get_tag() {
+            "index" => (Some(value.parse::<usize>().ok()?), value),
+            "tag"   => (None, value),
+            _       => return None
+        });
+    }
+
+    Some(parts.into_iter().rev().fold(TreeAddress::Here, |address, part| match part {
+        (Some(index), _)    => TreeAddress::ChildAtIndex(index, Box::new(address)),
+        (None, tag)         => TreeAddress::ChildWithTag(tag, Box::new(address))
+    }))
+}
+
+///
+/// Converts a change to a log record
+///
+fn change_record(sequence: u64, change: &TreeChange) -> TreeRef {
+    let replacement = match *change.replacement() {
+        TreeReplacement::Remove                         => "remove".to_tree_node(),
+        TreeReplacement::NewNode(ref node)              => "node".to_tree_node().with_child_node(Some(node)),
+        TreeReplacement::NewValue(ref tag, ref value)   => ("value", value.clone()).to_tree_node().with_child_node(Some(&("tag", tag.clone()).to_tree_node()))
+    };
+
+    let mut parts = vec![
+        ("sequence", sequence.to_string()).to_tree_node(),
+        address_record(change.address()),
+        replacement
+    ];
+
+    // The priority is left out when it's the default, so logs written before priorities existed look the same
+    if change.priority() != 0 {
+        parts.push(("priority", change.priority().to_string()).to_tree_node());
+    }
+
+    if change.replaces_following() {
+        parts.push(("replaces_following", "true").to_tree_node());
+    }
+
+    "change".to_tree_node().with_children(&parts)
+}
+
+///
+/// Reads a change from a log record
+///
+fn change_from_record(record: &TreeRef) -> Option<(u64, TreeChange)> {
+    let sequence    = record.get_child_ref_at(0)?.get_value().to_str("").parse::<u64>().ok()?;
+    let address     = address_from_record(&record.get_child_ref_at(1)?)?;
+    let replacement = record.get_child_ref_at(2)?;
+
+    let replacement = match replacement.get_tag() {
+        "remove"    => TreeReplacement::Remove,
+        "node"      => TreeReplacement::NewNode(replacement.get_child_ref()?),
+        "value"     => TreeReplacement::NewValue(replacement.get_child_ref()?.get_value().to_str("").to_string(), replacement.get_value().clone()),
+        _           => return None
+    };
+
+    // The optional parts follow the replacement
+    let mut priority            = 0;
+    let mut replaces_following  = false;
+
+    for part in record.iter_children().skip(3) {
+        match part.get_tag() {
+            "priority"              => priority = part.get_value().to_str("").parse::<i32>().ok()?,
+            "replaces_following"    => replaces_following = part.get_value().to_str("") == "true",
+            _                       => return None
+        }
+    }
+
+    Some((sequence, TreeChange::new(&address, &replacement).with_priority(priority).with_replaces_following(replaces_following)))
+}
+
+///
+/// Converts an acknowledgement to a record
+///
+fn ack_record(name: &str, sequence: u64) -> TreeRef {
+    "ack".to_tree_node().with_children(&vec![
+        ("name", name).to_tree_node(),
+        ("sequence", sequence.to_string()).to_tree_node()
+    ])
+}
+
+///
+/// Reads an acknowledgement from a record
+///
+fn ack_from_record(record: &TreeRef) -> Option<(String, u64)> {
+    let name        = record.get_child_ref_at("name")?.get_value().to_str("").to_string();
+    let sequence    = record.get_child_ref_at("sequence")?.get_value().to_str("").parse::<u64>().ok()?;
+
+    Some((name, sequence))
+}
+
+///
+/// The path of the segment that starts with a particular sequence number
+///
+fn segment_path(directory: &Path, first_sequence: u64) -> PathBuf {
+    directory.join(format!("segment-{:020}.log", first_sequence))
+}
+
+///
+/// Finds the segments in a directory, returning their first sequence number and path in order
+///
+fn find_segments(directory: &Path) -> io::Result<Vec<(u64, PathBuf)>> {
+    let mut segments = vec![];
+
+    for entry in fs::read_dir(directory)? {
+        let path    = entry?.path();
+        let first   = path.file_name()
+            .and_then(|name| name.to_str())
+            .filter(|name| name.starts_with("segment-") && name.ends_with(".log"))
+            .and_then(|name| name["segment-".len()..name.len()-".log".len()].parse::<u64>().ok());
+
+        if let Some(first) = first {
+            segments.push((first, path));
+        }
+    }
+
+    segments.sort_by_key(|(first, _)| *first);
+    Ok(segments)
+}
+
+///
+/// The log and acknowledgements of a durable bus, shared with its publishers
+///
+struct DurableLog {
+    directory: PathBuf,
+
+    /// The first sequence number in the segment that's being written to, and the file itself
+    segment_start: u64,
+    segment: File,
+
+    /// The file acknowledgements are written to
+    cursor_file: File,
+
+    /// The last sequence number acknowledged by each subscriber
+    cursors: HashMap<String, u64>,
+
+    /// The sequence number of the next change to be published
+    next_sequence: u64,
+
+    /// Changes that have been written to the log but not delivered yet
+    pending: VecDeque<(u64, TreeChange)>,
+
+    /// The first error that occurred while writing a change
+    write_error: Option<io::Error>
+}
+
+impl DurableLog {
+    ///
+    /// Writes a change to the log and queues it for delivery
+    ///
+    fn publish(&mut self, change: TreeChange) {
+        let sequence = self.next_sequence;
+
+        match append_record(&mut self.segment, &change_record(sequence, &change)) {
+            Ok(()) => {
+                self.next_sequence += 1;
+                self.pending.push_back((sequence, change));
+            },
+
+            Err(err) => {
+                // Changes that can't be logged aren't delivered
+                if self.write_error.is_none() {
+                    self.write_error = Some(err);
+                }
+            }
+        }
+    }
+
+    ///
+    /// Writes an acknowledgement, if it's later than the last one for this subscriber
+    ///
+    fn ack(&mut self, name: &str, sequence: u64) -> io::Result<()> {
+        if self.cursors.get(name).map(|acked| *acked >= sequence).unwrap_or(false) {
+            return Ok(());
+        }
+
+        append_record(&mut self.cursor_file, &ack_record(name, sequence))?;
+        self.cursors.insert(name.to_string(), sequence);
+
+        Ok(())
+    }
+}
+
+///
+/// Publisher that writes changes to a durable log
+///
+struct DurablePublisher {
+    log: Rc<RefCell<DurableLog>>
+}
+
+impl Publisher for DurablePublisher {
+    fn publish(&mut self, change: TreeChange) {
+        self.log.borrow_mut().publish(change);
+    }
+}
+
+///
+/// A subscriber that's tracked by name
+///
+struct DurableSubscription {
+    name: String,
+    address: TreeAddress,
+    extent: TreeExtent,
+    callback: DurableCallback,
+
+    /// The last sequence number sent to this subscriber
+    delivered: u64
+}
+
+impl DurableSubscription {
+    ///
+    /// Sends a change to this subscriber if it hasn't seen it already, returning true if it should be acknowledged
+    ///
+    fn deliver(&mut self, sequence: u64, change: &TreeChange) -> bool {
+        if sequence <= self.delivered {
+            return false;
+        }
+
+        self.delivered = sequence;
+
+        if change.applies_to(&self.address, &self.extent).unwrap_or(false) {
+            (self.callback)(sequence, change)
+        } else {
+            false
+        }
+    }
+}
+
+///
+/// A bus that logs changes to disk and can resume delivering them to named subscribers after a restart
+///
+pub struct DurableBus {
+    log: Rc<RefCell<DurableLog>>,
+    subscriptions: Vec<DurableSubscription>,
+
+    /// Carries changes to the ordinary consumers
+    bus: TreeChangeBus,
+    bus_publisher: PublisherRef,
+
+    rotation_hook: Option<RotationHook>,
+
+    /// The number of bytes cut from the end of the files when the bus was opened
+    discarded_bytes: u64
+}
+
+impl DurableBus {
+    ///
+    /// Opens the durable bus stored in a directory, creating it if it doesn't exist
+    ///
+    pub fn open<P: AsRef<Path>>(directory: P) -> io::Result<DurableBus> {
+        let directory = directory.as_ref().to_path_buf();
+        fs::create_dir_all(&directory)?;
+
+        let mut discarded_bytes = 0;
+
+        // Recover the acknowledgements
+        let cursor_path = directory.join(CURSOR_FILE);
+        let mut cursors = HashMap::new();
+
+        if cursor_path.exists() {
+            let (records, discarded) = recover_file(&cursor_path)?;
+            discarded_bytes += discarded;
+
+            for (name, sequence) in records.iter().filter_map(ack_from_record) {
+                let acked = cursors.entry(name).or_insert(0);
+                *acked = sequence.max(*acked);
+            }
+        }
+
+        // Recover the log, and find where it ends
+        let segments        = find_segments(&directory)?;
+        let mut last_start  = 1;
+        let mut next        = 1;
+
+        for (first, path) in segments.iter() {
+            let (records, discarded) = recover_file(path)?;
+            discarded_bytes += discarded;
+
+            last_start  = *first;
+            next        = next.max(*first);
+            if let Some((sequence, _)) = records.last().and_then(change_from_record) {
+                next = sequence + 1;
+            }
+        }
+
+        let segment     = OpenOptions::new().create(true).append(true).open(segment_path(&directory, last_start))?;
+        let cursor_file = OpenOptions::new().create(true).append(true).open(&cursor_path)?;
+
+        let log = DurableLog {
+            directory,
+            segment_start:  last_start,
+            segment,
+            cursor_file,
+            cursors,
+            next_sequence:  next,
+            pending:        VecDeque::new(),
+            write_error:    None
+        };
+
+        let bus             = TreeChangeBus::new();
+        let bus_publisher   = bus.create_publisher();
+
+        Ok(DurableBus {
+            log:                Rc::new(RefCell::new(log)),
+            subscriptions:      vec![],
+            bus,
+            bus_publisher,
+            rotation_hook:      None,
+            discarded_bytes
+        })
+    }
+
+    ///
+    /// Creates a publisher that writes to this bus
+    ///
+    pub fn create_publisher(&self) -> PublisherRef {
+        Box::new(DurablePublisher { log: self.log.clone() })
+    }
+
+    ///
+    /// Creates a consumer that receives the changes published to this bus from the point it's created, without
+    /// any tracking
+    ///
+    pub fn create_consumer(&self) -> ConsumerRef {
+        self.bus.create_consumer()
+    }
+
+    ///
+    /// Subscribes to this bus with a name. Any changes this name hasn't acknowledged are sent to the callback
+    /// straight away, and new changes are sent as they are flushed.
+    ///
+    pub fn subscribe(&mut self, name: &str, address: TreeAddress, extent: TreeExtent, callback: DurableCallback) -> io::Result<()> {
+        let (directory, acked) = {
+            let log = self.log.borrow();
+            (log.directory.clone(), log.cursors.get(name).cloned().unwrap_or(0))
+        };
+
+        let mut subscription = DurableSubscription { name: name.to_string(), address, extent, callback, delivered: acked };
+
+        // Replay the changes that haven't been acknowledged (this includes any that are waiting to be flushed)
+        for (_, path) in find_segments(&directory)? {
+            let mut bytes = vec![];
+            File::open(&path)?.read_to_end(&mut bytes)?;
+
+            for (sequence, change) in read_framed_records(&bytes).0.iter().filter_map(change_from_record) {
+                if subscription.deliver(sequence, &change) {
+                    self.log.borrow_mut().ack(name, sequence)?;
+                }
+            }
+        }
+
+        self.subscriptions.push(subscription);
+        Ok(())
+    }
+
+    ///
+    /// Acknowledges every change up to and including a sequence number on behalf of a subscriber
+    ///
+    pub fn ack(&self, name: &str, sequence: u64) -> io::Result<()> {
+        self.log.borrow_mut().ack(name, sequence)
+    }
+
+    ///
+    /// The last sequence number acknowledged by a subscriber
+    ///
+    pub fn acked(&self, name: &str) -> Option<u64> {
+        self.log.borrow().cursors.get(name).cloned()
+    }
+
+    ///
+    /// The sequence number that will be given to the next change
+    ///
+    pub fn next_sequence(&self) -> u64 {
+        self.log.borrow().next_sequence
+    }
+
+    ///
+    /// The number of bytes that were cut from the end of the log or acknowledgement files because they didn't
+    /// contain a complete record when the bus was opened
+    ///
+    pub fn discarded_bytes(&self) -> u64 {
+        self.discarded_bytes
+    }
+
+    ///
+    /// Retrieves the first error that occurred while writing a published change to the log, if there was one.
+    /// Changes that couldn't be written aren't delivered.
+    ///
+    pub fn take_write_error(&self) -> Option<io::Error> {
+        self.log.borrow_mut().write_error.take()
+    }
+
+    ///
+    /// Sets a function to be called with the path of each segment of the log once it's finished with
+    ///
+    pub fn set_rotation_hook(&mut self, hook: RotationHook) {
+        self.rotation_hook = Some(hook);
+    }
+
+    ///
+    /// Delivers the changes that have been published until there are none left, returning the number of changes
+    /// that were delivered
+    ///
+    pub fn flush(&mut self) -> io::Result<usize> {
+        let mut delivered = 0;
+
+        loop {
+            // The log isn't borrowed while the subscribers run, so they can publish more changes
+            let next = self.log.borrow_mut().pending.pop_front();
+            let (sequence, change) = match next {
+                Some(next)  => next,
+                None        => break
+            };
+
+            for subscription in self.subscriptions.iter_mut() {
+                if subscription.deliver(sequence, &change) {
+                    self.log.borrow_mut().ack(&subscription.name, sequence)?;
+                }
+            }
+
+            self.bus_publisher.publish(change);
+            self.bus.flush();
+            delivered += 1;
+        }
+
+        Ok(delivered)
+    }
+
+    ///
+    /// Starts a new segment of the log, passing the finished one to the rotation hook, then deletes any segments
+    /// containing only changes that every subscriber has acknowledged
+    ///
+    /// Segments are only deleted if at least one subscriber has acknowledged something.
+    ///
+    pub fn rotate_log(&mut self) -> io::Result<()> {
+        let (directory, finished, oldest_needed) = {
+            let mut log = self.log.borrow_mut();
+
+            // Nothing to do if the current segment is empty
+            if log.next_sequence == log.segment_start {
+                return Ok(());
+            }
+
+            let finished        = segment_path(&log.directory, log.segment_start);
+            log.segment         = OpenOptions::new().create(true).append(true).open(segment_path(&log.directory, log.next_sequence))?;
+            log.segment_start   = log.next_sequence;
+
+            // Subscribers that haven't acknowledged anything yet still need every change
+            let unacked     = self.subscriptions.iter().filter(|subscription| !log.cursors.contains_key(&subscription.name)).map(|_| 0);
+            let oldest      = log.cursors.values().cloned().chain(unacked).min();
+
+            (log.directory.clone(), finished, oldest.map(|acked| acked + 1))
+        };
+
+        if let Some(ref mut hook) = self.rotation_hook {
+            hook(&finished);
+        }
+
+        // Delete segments where the next segment starts at or before the oldest change that's still needed
+        if let Some(oldest_needed) = oldest_needed {
+            let segments = find_segments(&directory)?;
+
+            for pair in segments.windows(2) {
+                let ((_, ref path), (next_start, _)) = (&pair[0], &pair[1]);
+
+                if *next_start <= oldest_needed {
+                    fs::remove_file(path)?;
+                }
+            }
+        }
+
+        self.compact_cursors()
+    }
+
+    ///
+    /// Rewrites the acknowledgements file so it contains only the latest acknowledgement for each subscriber
+    ///
+    fn compact_cursors(&mut self) -> io::Result<()> {
+        let mut log         = self.log.borrow_mut();
+        let cursor_path     = log.directory.join(CURSOR_FILE);
+        let temp_path       = log.directory.join(format!("{}.new", CURSOR_FILE));
+
+        {
+            let mut temp    = File::create(&temp_path)?;
+            let mut names   = log.cursors.keys().cloned().collect::<Vec<_>>();
+            names.sort();
+
+            for name in names {
+                temp.write_all(&frame_record(&ack_record(&name, log.cursors[&name])))?;
+            }
+            temp.sync_data()?;
+        }
+
+        // Renaming replaces the old file in one step, so a crash leaves either the old or the new version
+        fs::rename(&temp_path, &cursor_path)?;
+        log.cursor_file = OpenOptions::new().append(true).open(&cursor_path)?;
+
+        Ok(())
+    }
+}
+
+///
+/// Compacts a log segment written by a `DurableBus`, writing the result to another stream
+///
+/// The log is assumed to describe a tree that started out empty, as the tree published to a bus does. Replaying the
+/// compacted log produces the same final tree as replaying the original, but the trees produced along the way can
+/// be different. Each change in the compacted log keeps the sequence number of the last of the original changes it
+/// takes the place of, so subscribers' acknowledgements still refer to the right place in the log.
+///
+/// Nothing is written if any of the records in the log can't be read: unlike when a bus is opened, a partial
+/// record at the end of the log is an error.
+///
+pub fn compact_log<TRead: Read, TWrite: Write>(mut input: TRead, mut output: TWrite, strategy: CompactionStrategy) -> Result<CompactionStats, CompactError> {
+    let mut bytes = vec![];
+    input.read_to_end(&mut bytes)?;
+
+    // Read every change, stopping at the first record that can't be read
+    let mut changes = vec![];
+    let mut pos     = 0;
+
+    while pos < bytes.len() {
+        let index           = changes.len();
+        let (record, end)   = read_framed_record(&bytes, pos).map_err(|error| CompactError::Decode { index, error })?;
+        let change          = change_from_record(&record).ok_or(CompactError::Decode { index, error: RecordError::NotAChange })?;
+
+        changes.push(change);
+        pos = end;
+    }
+
+    let (compacted, mut stats) = compact_numbered_changes(&"".to_tree_node(), changes, strategy);
+
+    for (sequence, change) in compacted.iter() {
+        let framed = frame_record(&change_record(*sequence, change));
+
+        output.write_all(&framed)?;
+        stats.output_bytes += framed.len();
+    }
+
+    output.flush()?;
+    stats.input_bytes = bytes.len();
+
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod durable_tests {
+    use std::env;
+    use std::fs;
+    use std::io::Write;
+    use std::fs::OpenOptions;
+    use std::path::PathBuf;
+    use std::rc::*;
+    use std::cell::*;
+
+    use super::super::super::tree::*;
+    use super::*;
+
+    ///
+    /// Creates an empty directory for a test
+    ///
+    fn test_directory(name: &str) -> PathBuf {
+        let directory = env::temp_dir().join(format!("tametree_durable_{}_{}", name, ::std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+
+        directory
+    }
+
+    ///
+    /// Creates a subscription callback that records the sequence numbers and values it sees, acknowledging
+    /// changes as long as the sequence number is accepted by a function
+    ///
+    fn recorder<TAck: Fn(u64) -> bool + 'static>(seen: &Rc<RefCell<Vec<(u64, i32)>>>, should_ack: TAck) -> DurableCallback {
+        let seen = seen.clone();
+
+        Box::new(move |sequence, change| {
+            let value = match *change.replacement() {
+                TreeReplacement::NewNode(ref node)  => node.get_value().to_int(0),
+                _                                   => -1
+            };
+
+            seen.borrow_mut().push((sequence, value));
+            should_ack(sequence)
+        })
+    }
+
+    fn publish_values(bus: &DurableBus, values: Vec<i32>) {
+        let mut publisher = bus.create_publisher();
+
+        for value in values {
+            publisher.publish(TreeChange::new(&("data", "value"), &("value", value)));
+        }
+    }
+
+    #[test]
+    fn crash_replays_unacknowledged_suffix() {
+        let directory   = test_directory("crash");
+        let seen        = Rc::new(RefCell::new(vec![]));
+
+        {
+            let mut bus = DurableBus::open(&directory).unwrap();
+            bus.subscribe("bridge", "data".to_tree_address(), TreeExtent::SubTree, recorder(&seen, |sequence| sequence <= 3)).unwrap();
+
+            publish_values(&bus, vec![10, 20, 30, 40, 50]);
+            bus.flush().unwrap();
+
+            assert!(bus.acked("bridge") == Some(3));
+        }
+
+        let resumed     = Rc::new(RefCell::new(vec![]));
+        let mut bus     = DurableBus::open(&directory).unwrap();
+
+        // Changes published before subscribing are sent once, not again when they are flushed
+        publish_values(&bus, vec![60]);
+        bus.subscribe("bridge", "data".to_tree_address(), TreeExtent::SubTree, recorder(&resumed, |_| true)).unwrap();
+        bus.flush().unwrap();
+
+        assert!(*resumed.borrow() == vec![(4, 40), (5, 50), (6, 60)]);
+        assert!(bus.acked("bridge") == Some(6));
+
+        let _ = fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn delivery_is_at_least_once() {
+        let directory   = test_directory("at_least_once");
+        let effects     = Rc::new(RefCell::new(vec![]));
+
+        {
+            // The acknowledgement for change 2 is written but the process stops after acting on change 3
+            let mut bus = DurableBus::open(&directory).unwrap();
+            bus.subscribe("bridge", TreeAddress::Here, TreeExtent::SubTree, recorder(&effects, |sequence| sequence < 3)).unwrap();
+
+            publish_values(&bus, vec![1, 2, 3]);
+            bus.flush().unwrap();
+        }
+
+        let mut bus = DurableBus::open(&directory).unwrap();
+        bus.subscribe("bridge", TreeAddress::Here, TreeExtent::SubTree, recorder(&effects, |_| true)).unwrap();
+
+        // Change 3 is seen twice
+        assert!(*effects.borrow() == vec![(1, 1), (2, 2), (3, 3), (3, 3)]);
+
+        let _ = fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn replayed_changes_keep_their_priority() {
+        let directory   = test_directory("priority");
+        let priorities  = Rc::new(RefCell::new(vec![]));
+
+        {
+            let bus             = DurableBus::open(&directory).unwrap();
+            let mut publisher   = bus.create_publisher();
+
+            publisher.publish(TreeChange::new(&"value", &("value", 1)).with_priority(5));
+            publisher.publish(TreeChange::new(&"value", &("value", 2)));
+            publisher.publish(TreeChange::new(&"value", &("value", 3)).with_priority(-2));
+        }
+
+        let mut bus     = DurableBus::open(&directory).unwrap();
+        let recorded    = priorities.clone();
+        bus.subscribe("bridge", TreeAddress::Here, TreeExtent::SubTree, Box::new(move |_, change| { recorded.borrow_mut().push(change.priority()); true })).unwrap();
+
+        assert!(*priorities.borrow() == vec![5, 0, -2]);
+
+        let _ = fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn replayed_legacy_child_changes_still_replace_every_child() {
+        let directory   = test_directory("legacy_child");
+        let replayed    = Rc::new(RefCell::new(vec![]));
+
+        {
+            let bus             = DurableBus::open(&directory).unwrap();
+            let mut publisher   = bus.create_publisher();
+
+            publisher.publish(TreeChange::new_legacy(&"list", TreeChangeType::Child, Some(&("only", 1).to_tree_node())).with_priority(3));
+            publisher.publish(TreeChange::new(&("list", 0), &("first", 2)));
+        }
+
+        let mut bus     = DurableBus::open(&directory).unwrap();
+        let recorded    = replayed.clone();
+        bus.subscribe("bridge", TreeAddress::Here, TreeExtent::SubTree, Box::new(move |_, change| { recorded.borrow_mut().push(change.clone()); true })).unwrap();
+
+        let replayed = replayed.borrow();
+        assert!(replayed.len() == 2);
+        assert!(replayed[0].replaces_following() && replayed[0].priority() == 3);
+        assert!(!replayed[1].replaces_following());
+
+        let original    = tree!("root", tree!("list", "a", "b", "c"));
+        let after       = replayed[0].apply(&original);
+        assert!(after.get_child_at("list").iter_children().map(|child| child.get_tag().to_string()).collect::<Vec<_>>() == vec!["only"]);
+
+        let _ = fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn replayed_nodes_keep_their_siblings() {
+        let directory   = test_directory("siblings");
+        let replayed    = Rc::new(RefCell::new(vec![]));
+
+        // Replacing a node with one that has siblings inserts all of them
+        let replacement = tree!("list", ("a", 1), ("b", 2), ("c", 3)).get_child_ref().unwrap();
+
+        {
+            let bus             = DurableBus::open(&directory).unwrap();
+            let mut publisher   = bus.create_publisher();
+
+            publisher.publish(TreeChange::new(&0, &replacement));
+        }
+
+        let mut bus     = DurableBus::open(&directory).unwrap();
+        let recorded    = replayed.clone();
+        bus.subscribe("bridge", TreeAddress::Here, TreeExtent::SubTree, Box::new(move |_, change| { recorded.borrow_mut().push(change.clone()); true })).unwrap();
+
+        let original    = tree!("root", ("x", 0));
+        let expected    = TreeChange::new(&0, &replacement).apply(&original);
+        let result      = replayed.borrow()[0].apply(&original);
+
+        assert!(result.iter_children().map(|child| child.get_tag().to_string()).collect::<Vec<_>>() == vec!["a", "b", "c"]);
+        assert!(expected.iter_children().map(|child| child.get_tag().to_string()).collect::<Vec<_>>() == vec!["a", "b", "c"]);
+
+        let _ = fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn rotation_preserves_resumability() {
+        let directory   = test_directory("rotation");
+        let rotated     = Rc::new(RefCell::new(vec![]));
+        let seen        = Rc::new(RefCell::new(vec![]));
+
+        {
+            let mut bus     = DurableBus::open(&directory).unwrap();
+            let hook_seen   = rotated.clone();
+            bus.set_rotation_hook(Box::new(move |path| hook_seen.borrow_mut().push(path.to_path_buf())));
+            bus.subscribe("bridge", TreeAddress::Here, TreeExtent::SubTree, recorder(&seen, |sequence| sequence <= 4)).unwrap();
+
+            publish_values(&bus, vec![1, 2, 3]);
+            bus.flush().unwrap();
+            bus.rotate_log().unwrap();
+
+            publish_values(&bus, vec![4, 5]);
+            bus.flush().unwrap();
+            bus.rotate_log().unwrap();
+
+            publish_values(&bus, vec![6]);
+            bus.flush().unwrap();
+        }
+
+        // The first segment was fully acknowledged, so it's gone
+        assert!(rotated.borrow().len() == 2);
+        assert!(!rotated.borrow()[0].exists());
+        assert!(rotated.borrow()[1].exists());
+
+        let resumed = Rc::new(RefCell::new(vec![]));
+        let mut bus = DurableBus::open(&directory).unwrap();
+        bus.subscribe("bridge", TreeAddress::Here, TreeExtent::SubTree, recorder(&resumed, |_| true)).unwrap();
+
+        assert!(*resumed.borrow() == vec![(5, 5), (6, 6)]);
+        assert!(bus.next_sequence() == 7);
+
+        let _ = fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn rotation_keeps_changes_for_subscribers_that_never_acknowledged() {
+        let directory   = test_directory("rotation_unacked");
+        let fast        = Rc::new(RefCell::new(vec![]));
+        let slow        = Rc::new(RefCell::new(vec![]));
+
+        {
+            let mut bus = DurableBus::open(&directory).unwrap();
+            bus.subscribe("fast", TreeAddress::Here, TreeExtent::SubTree, recorder(&fast, |_| true)).unwrap();
+            bus.subscribe("slow", TreeAddress::Here, TreeExtent::SubTree, recorder(&slow, |_| false)).unwrap();
+
+            publish_values(&bus, vec![1, 2, 3]);
+            bus.flush().unwrap();
+            bus.rotate_log().unwrap();
+
+            publish_values(&bus, vec![4]);
+            bus.flush().unwrap();
+            bus.rotate_log().unwrap();
+        }
+
+        let resumed = Rc::new(RefCell::new(vec![]));
+        let mut bus = DurableBus::open(&directory).unwrap();
+        bus.subscribe("slow", TreeAddress::Here, TreeExtent::SubTree, recorder(&resumed, |_| true)).unwrap();
+
+        assert!(resumed.borrow().iter().map(|(_, value)| *value).collect::<Vec<_>>() == vec![1, 2, 3, 4]);
+
+        let _ = fs::remove_dir_all(&directory);
+    }
+
+    ///
+    /// Replays the changes in a log from an empty tree
+    ///
+    fn replay_log(bytes: &[u8]) -> (Vec<u64>, TreeRef) {
+        let changes = read_framed_records(bytes).0.iter().filter_map(change_from_record).collect::<Vec<_>>();
+        let tree    = changes.iter().fold("".to_tree_node(), |tree, (_, change)| change.apply(&tree));
+
+        (changes.into_iter().map(|(sequence, _)| sequence).collect(), tree)
+    }
+
+    #[test]
+    fn compacted_log_replays_to_the_same_tree() {
+        let directory = test_directory("compact");
+
+        {
+            let mut bus         = DurableBus::open(&directory).unwrap();
+            let mut publisher   = bus.create_publisher();
+
+            publisher.publish(TreeChange::new(&(), &tree!("root", tree!("status", ("state", "idle"), ("count", 0)), tree!("items", "a", "b", "c"))));
+            for step in 0..60usize {
+                publisher.publish(TreeChange::new(&("status", "count"), &("count", step as i32)));
+                publisher.publish(TreeChange::new(&("items", step % 3), &("item", step as i32)).with_priority((step % 4) as i32));
+
+                if step % 10 == 0 {
+                    publisher.publish(TreeChange::new(&"status", &tree!("status", ("state", "reset"), ("count", step as i32))));
+                }
+            }
+
+            bus.flush().unwrap();
+        }
+
+        let mut bytes = vec![];
+        File::open(segment_path(&directory, 1)).unwrap().read_to_end(&mut bytes).unwrap();
+        let (sequences, expected) = replay_log(&bytes);
+
+        for strategy in [CompactionStrategy::KeepLastPerAddress, CompactionStrategy::SnapshotEvery(10), CompactionStrategy::Combined(10)].iter() {
+            let mut compacted       = vec![];
+            let stats               = compact_log(&bytes[..], &mut compacted, *strategy).unwrap();
+            let (kept, replayed)    = replay_log(&compacted);
+
+            assert!(to_tree_text(&replayed) == to_tree_text(&expected));
+            assert!(stats.input_changes == sequences.len() && stats.output_changes == kept.len());
+            assert!(stats.output_changes < stats.input_changes);
+            assert!(stats.input_bytes == bytes.len() && stats.output_bytes == compacted.len());
+            assert!(stats.output_bytes < stats.input_bytes);
+
+            // Sequence numbers stay in order and the log still ends at the same place
+            assert!(kept.windows(2).all(|pair| pair[0] < pair[1]));
+            assert!(kept.last() == sequences.last());
+        }
+
+        let _ = fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn corrupted_log_reports_the_record_that_could_not_be_read() {
+        let directory = test_directory("compact_corrupted");
+
+        {
+            let mut bus = DurableBus::open(&directory).unwrap();
+            publish_values(&bus, vec![1, 2, 3, 4, 5]);
+            bus.flush().unwrap();
+        }
+
+        let mut bytes = vec![];
+        File::open(segment_path(&directory, 1)).unwrap().read_to_end(&mut bytes).unwrap();
+
+        // Find where the third record ends
+        let mut end = 0;
+        for _ in 0..3 {
+            end = read_framed_record(&bytes, end).unwrap().1;
+        }
+
+        let mut output = vec![];
+        let truncated = compact_log(&bytes[0..end-1], &mut output, CompactionStrategy::KeepLastPerAddress);
+        assert!(matches!(truncated, Err(CompactError::Decode { index: 2, error: RecordError::Truncated })));
+        assert!(output.is_empty());
+
+        let mut corrupted = bytes.clone();
+        corrupted[end-2] ^= 1;
+        let mismatch = compact_log(&corrupted[..], &mut output, CompactionStrategy::KeepLastPerAddress);
+        assert!(matches!(mismatch, Err(CompactError::Decode { index: 2, error: RecordError::ChecksumMismatch })));
+
+        let mut not_a_change = bytes[0..end].to_vec();
+        not_a_change.extend(frame_record(&ack_record("bridge", 3)));
+        let wrong_record = compact_log(&not_a_change[..], &mut output, CompactionStrategy::KeepLastPerAddress);
+        assert!(matches!(wrong_record, Err(CompactError::Decode { index: 3, error: RecordError::NotAChange })));
+        assert!(output.is_empty());
+
+        let _ = fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn corrupted_tail_recovers_to_last_good_record() {
+        let directory = test_directory("corrupted");
+
+        {
+            let mut bus = DurableBus::open(&directory).unwrap();
+            publish_values(&bus, vec![1, 2, 3]);
+            bus.flush().unwrap();
+        }
+
+        // Cut the last record short and add some junk after it
+        let segment = segment_path(&directory, 1);
+        let length  = fs::metadata(&segment).unwrap().len();
+        OpenOptions::new().write(true).open(&segment).unwrap().set_len(length - 5).unwrap();
+        OpenOptions::new().append(true).open(&segment).unwrap().write_all(b"junk").unwrap();
+
+        let seen    = Rc::new(RefCell::new(vec![]));
+        let mut bus = DurableBus::open(&directory).unwrap();
+        assert!(bus.discarded_bytes() > 0);
+        assert!(bus.next_sequence() == 3);
+
+        // New changes follow on from the last good record
+        publish_values(&bus, vec![4]);
+        bus.subscribe("late", TreeAddress::Here, TreeExtent::SubTree, recorder(&seen, |_| true)).unwrap();
+        bus.flush().unwrap();
+        assert!(*seen.borrow() == vec![(1, 1), (2, 2), (3, 4)]);
+
+        let bus = DurableBus::open(&directory).unwrap();
+        assert!(bus.discarded_bytes() == 0);
+        assert!(bus.next_sequence() == 4);
+
+        let _ = fs::remove_dir_all(&directory);
+    }
+}
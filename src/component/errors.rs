@@ -0,0 +1,68 @@
+//
+//   Copyright 2016 Andrew Hunter
+//
+//   Licensed under the Apache License, Version 2.0 (the "License");
+//   you may not use this file except in compliance with the License.
+//   You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+//   Unless required by applicable law or agreed to in writing, software
+//   distributed under the License is distributed on an "AS IS" BASIS,
+//   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//   See the License for the specific language governing permissions and
+//   limitations under the License.
+//
+
+//!
+//! # Component errors
+//!
+//! A component reports that it couldn't process its input by publishing an error tree in place of its output: a
+//! node with a child tagged `__error` whose value is a description of what went wrong. The marker is a child rather
+//! than the tag of the published node because the node is retagged when it's published to an address in a hub.
+//!
+//! Components made from functions with `component_fn()` publish an error when their input can't be decoded.
+//! Components that check their input in other ways can publish `error_tree()` themselves.
+//!
+//! ```
+//! # use tametree::prelude::*;
+//! # use tametree::component::errors::*;
+//! let change = TreeChange::new(&(), &error_tree("the input was out of range"));
+//!
+//! assert!(change_error(&change) == Some("the input was out of range".to_string()));
+//! assert!(change_error(&TreeChange::new(&(), &("result", 1))).is_none());
+//! ```
+//!
+
+use std::rc::*;
+
+use super::super::tree::*;
+
+///
+/// The tag of the child that marks a tree as an error
+///
+pub const ERROR_TAG: &str = "__error";
+
+///
+/// Creates a tree reporting an error
+///
+pub fn error_tree(message: &str) -> TreeRef {
+    Rc::new(BasicTree::new("error", (), Some((ERROR_TAG, message).to_tree_node()), None))
+}
+
+///
+/// If a tree reports an error, the description of the error
+///
+pub fn tree_error(tree: &TreeRef) -> Option<String> {
+    tree.get_child_ref_at(ERROR_TAG).map(|error| error.get_value().to_str("").to_string())
+}
+
+///
+/// If a change publishes an error, the description of the error
+///
+pub fn change_error(change: &TreeChange) -> Option<String> {
+    match *change.replacement() {
+        TreeReplacement::NewNode(ref node)  => tree_error(node),
+        _                                   => None
+    }
+}
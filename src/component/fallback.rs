@@ -0,0 +1,309 @@
+//
+//   Copyright 2016 Andrew Hunter
+//
+//   Licensed under the Apache License, Version 2.0 (the "License");
+//   you may not use this file except in compliance with the License.
+//   You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+//   Unless required by applicable law or agreed to in writing, software
+//   distributed under the License is distributed on an "AS IS" BASIS,
+//   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//   See the License for the specific language governing permissions and
+//   limitations under the License.
+//
+
+//!
+//! # Fallback components
+//!
+//! `Fallback` combines two components: a primary component that normally produces the output, and a simpler
+//! secondary component that takes over whenever the primary one reports an error (see
+//! `tametree::component::errors`) for an input. Both components are given every input, so the secondary one is
+//! always up to date, but only one of them has its output published for each input:
+//!
+//! * if the primary component doesn't report an error, its output is published
+//! * if it does, the output of the secondary component is published instead. New trees in the output are marked as
+//!   degraded with a child tagged `__degraded`, whose value is the error reported by the primary component
+//! * if both components report an error, a single error combining both descriptions is published
+//!
+//! The primary component's output is used again as soon as it stops reporting errors.
+//!
+//! ```
+//! # use tametree::prelude::*;
+//! # use tametree::component::fallback::*;
+//! let mut hub     = Hub::new();
+//! let mut input   = hub.publish_to(&"in");
+//! let fallback    = Fallback::new(component_fn(|x: &i32| x * 2), component_fn(|_x: &TreeRef| 0.to_tree_node()));
+//! let degraded    = fallback.get_degraded_reader();
+//!
+//! hub.add_component(fallback, &"in", &"out");
+//!
+//! input.publish(TreeChange::new(&(), &"not a number"));
+//! hub.flush();
+//! assert!(degraded());
+//! ```
+//!
+
+use std::rc::*;
+use std::cell::*;
+use std::mem;
+
+use super::super::tree::*;
+use super::component::*;
+use super::errors::*;
+use super::immediate_publisher::*;
+
+///
+/// The tag of the child that marks the output of a fallback component as coming from its secondary component
+///
+pub const DEGRADED_TAG: &str = "__degraded";
+
+///
+/// A component that falls back to a secondary component when its primary component reports an error
+///
+pub struct Fallback<TPrimary: ConvertToComponent, TSecondary: ConvertToComponent> {
+    primary: TPrimary,
+    secondary: TSecondary,
+    state: Rc<FallbackState>
+}
+
+///
+/// What has happened to a fallback component so far
+///
+struct FallbackState {
+    /// True if the output for the most recent input came from the secondary component
+    degraded: Cell<bool>,
+
+    /// The number of inputs whose output came from the secondary component
+    substitutions: Cell<usize>
+}
+
+impl<TPrimary: ConvertToComponent, TSecondary: ConvertToComponent> Fallback<TPrimary, TSecondary> {
+    ///
+    /// Creates a component that uses a secondary component when the primary one fails
+    ///
+    pub fn new(primary: TPrimary, secondary: TSecondary) -> Fallback<TPrimary, TSecondary> {
+        Fallback { primary, secondary, state: Rc::new(FallbackState { degraded: Cell::new(false), substitutions: Cell::new(0) }) }
+    }
+
+    ///
+    /// Retrieves a function that returns true if the output for the most recent input came from the secondary component
+    ///
+    pub fn get_degraded_reader(&self) -> Box<dyn Fn() -> bool> {
+        let state = self.state.clone();
+
+        Box::new(move || state.degraded.get())
+    }
+
+    ///
+    /// Retrieves a function that returns the number of inputs whose output came from the secondary component
+    ///
+    pub fn get_substitution_reader(&self) -> Box<dyn Fn() -> usize> {
+        let state = self.state.clone();
+
+        Box::new(move || state.substitutions.get())
+    }
+}
+
+///
+/// Marks a change published by the secondary component as degraded
+///
+fn mark_degraded(change: TreeChange, error: &str) -> TreeChange {
+    match *change.replacement() {
+        TreeReplacement::NewNode(ref node) => {
+            let mut children: Vec<TreeRef> = node.iter_children().filter(|child| child.get_tag() != DEGRADED_TAG).collect();
+            children.push((DEGRADED_TAG, error).to_tree_node());
+
+            TreeChange::new(change.address(), &node.with_children(&children))
+        },
+
+        _ => change
+    }
+}
+
+///
+/// Attaches a component to a new pair of immediate publishers, returning the publisher for its input and the
+/// list that its output is collected in
+///
+fn attach_component<TComponent: ConvertToComponent>(component: TComponent) -> (PublisherRef, Rc<RefCell<Vec<TreeChange>>>, ComponentRef) {
+    let input       = ImmediatePublisher::new();
+    let output      = ImmediatePublisher::new();
+    let collected   = Rc::new(RefCell::new(vec![]));
+    let collect     = collected.clone();
+
+    output.create_consumer().subscribe(TreeAddress::Here, TreeExtent::SubTree, Box::new(move |change| collect.borrow_mut().push(change.clone())));
+    let component = component.into_component(input.create_consumer(), output);
+
+    (input, collected, component)
+}
+
+struct FallbackRef {
+    _primary: ComponentRef,
+    _secondary: ComponentRef
+}
+
+impl Component for FallbackRef {
+}
+
+impl Drop for FallbackRef {
+    fn drop(&mut self) {
+    }
+}
+
+impl<TPrimary: ConvertToComponent, TSecondary: ConvertToComponent> ConvertToComponent for Fallback<TPrimary, TSecondary> {
+    ///
+    /// Creates a component that publishes the output of the primary component, or the secondary one if it fails
+    ///
+    fn into_component(self, consumer: ConsumerRef, publisher: PublisherRef) -> ComponentRef {
+        let mut consumer    = consumer;
+        let mut publisher   = publisher;
+        let state           = self.state;
+
+        let (mut primary_input, primary_output, primary)        = attach_component(self.primary);
+        let (mut secondary_input, secondary_output, secondary)  = attach_component(self.secondary);
+
+        consumer.subscribe(TreeAddress::Here, TreeExtent::SubTree, Box::new(move |change| {
+            // Both components process the input straight away, so their output is ready once they've been given it
+            primary_input.publish(change.clone());
+            secondary_input.publish(change.clone());
+
+            let primary_changes     = mem::take(&mut *primary_output.borrow_mut());
+            let secondary_changes   = mem::take(&mut *secondary_output.borrow_mut());
+
+            let primary_error = match primary_changes.iter().filter_map(change_error).next() {
+                None => {
+                    state.degraded.set(false);
+                    primary_changes.into_iter().for_each(|change| publisher.publish(change));
+                    return;
+                },
+
+                Some(error) => error
+            };
+
+            match secondary_changes.iter().filter_map(change_error).next() {
+                None => {
+                    state.degraded.set(true);
+                    state.substitutions.set(state.substitutions.get() + 1);
+                    secondary_changes.into_iter().for_each(|change| publisher.publish(mark_degraded(change, &primary_error)));
+                },
+
+                Some(secondary_error) => {
+                    state.degraded.set(false);
+                    publisher.publish(TreeChange::new(&TreeAddress::Here, &error_tree(&format!("{} (fallback also failed: {})", primary_error, secondary_error))));
+                }
+            }
+        }));
+
+        Rc::new(FallbackRef { _primary: primary, _secondary: secondary })
+    }
+}
+
+#[cfg(test)]
+mod fallback_tests {
+    use std::rc::*;
+    use std::cell::*;
+
+    use super::super::super::tree::*;
+    use super::super::super::component::*;
+    use super::super::errors::*;
+    use super::*;
+
+    tree_struct! {
+        struct Reading {
+            value: i32
+        }
+    }
+
+    ///
+    /// What was published for an input: the value, whether it's degraded and the error if there is one
+    ///
+    type Output = (i32, bool, Option<String>);
+
+    ///
+    /// Reads the number of substitutions made by a fallback component
+    ///
+    type SubstitutionReader = Box<dyn Fn() -> usize>;
+
+    ///
+    /// Creates a hub containing a fallback component reading from 'in' and publishing to 'out', along with the
+    /// list of outputs it published
+    ///
+    fn fallback_hub<TSecondary: 'static + ConvertToComponent>(secondary: TSecondary) -> (Hub, Rc<RefCell<Vec<Output>>>, SubstitutionReader) {
+        let mut hub     = Hub::new();
+        let outputs     = Rc::new(RefCell::new(vec![]));
+        let recorded    = outputs.clone();
+        let fallback    = Fallback::new(component_fn(|reading: &Reading| reading.value * 10), secondary);
+        let subs        = fallback.get_substitution_reader();
+
+        hub.add_component(fallback, &"in", &"out");
+        hub.read_from(&"out").subscribe(TreeAddress::Here, TreeExtent::SubTree, Box::new(move |change| {
+            if let TreeReplacement::NewNode(ref node) = *change.replacement() {
+                recorded.borrow_mut().push((node.get_value().to_int(0), node.get_child_ref_at(DEGRADED_TAG).is_some(), tree_error(node)));
+            }
+        }));
+
+        (hub, outputs, subs)
+    }
+
+    fn good(value: i32) -> TreeRef {
+        Rc::new(BasicTree::new("in", (), Some(("value", value).to_tree_node()), None))
+    }
+
+    fn bad() -> TreeRef {
+        ("in", "not a reading").to_tree_node()
+    }
+
+    #[test]
+    fn secondary_takes_over_for_bad_inputs() {
+        let (mut hub, outputs, substitutions)   = fallback_hub(component_fn(|_input: &TreeRef| ("fallback", -1).to_tree_node()));
+        let mut input                           = hub.publish_to(&"in");
+
+        for tree in [good(1), bad(), good(3), bad(), bad(), good(6)] {
+            input.publish(TreeChange::new(&(), &tree));
+            hub.flush();
+        }
+
+        // One output for every input, with the primary output coming back straight after a failure
+        assert!(outputs.borrow().iter().map(|(value, degraded, _)| (*value, *degraded)).collect::<Vec<_>>() == vec![(10, false), (-1, true), (30, false), (-1, true), (-1, true), (60, false)]);
+        assert!(outputs.borrow().iter().all(|(_, _, error)| error.is_none()));
+        assert!(substitutions() == 3);
+    }
+
+    #[test]
+    fn double_failure_publishes_one_error() {
+        let (mut hub, outputs, substitutions)   = fallback_hub(component_fn(|_input: &TreeRef| error_tree("fallback is broken")));
+        let mut input                           = hub.publish_to(&"in");
+
+        input.publish(TreeChange::new(&(), &bad()));
+        hub.flush();
+        input.publish(TreeChange::new(&(), &good(2)));
+        hub.flush();
+
+        assert!(outputs.borrow().len() == 2);
+
+        let error = outputs.borrow()[0].2.clone().unwrap();
+        assert!(error.starts_with("could not decode input"));
+        assert!(error.ends_with("(fallback also failed: fallback is broken)"));
+        assert!(outputs.borrow()[1] == (20, false, None));
+        assert!(substitutions() == 0);
+    }
+
+    #[test]
+    fn degraded_reader_follows_the_latest_input() {
+        let mut hub     = Hub::new();
+        let fallback    = Fallback::new(component_fn(|reading: &Reading| reading.value), component_fn(|_input: &TreeRef| 0.to_tree_node()));
+        let degraded    = fallback.get_degraded_reader();
+        let mut input   = hub.publish_to(&"in");
+
+        hub.add_component(fallback, &"in", &"out");
+
+        input.publish(TreeChange::new(&(), &bad()));
+        hub.flush();
+        assert!(degraded());
+
+        input.publish(TreeChange::new(&(), &good(1)));
+        hub.flush();
+        assert!(!degraded());
+    }
+}
@@ -28,40 +28,34 @@
 //! Example:
 //!
 //! ```
-//! # extern crate tametree;
-//! # extern crate rustc_serialize;
-//! # fn main() {
-//! # use tametree::component::*;
-//! # use tametree::component::immediate_publisher::*;
+//! # use tametree::prelude::*;
 //! #
 //! # let input_publisher   = ImmediatePublisher::new();
 //! # let consumer          = input_publisher.create_consumer();
 //! # let publisher         = ImmediatePublisher::new();
-//! #[derive(RustcEncodable, RustcDecodable)]
-//! struct InputTree {
-//!     a: i32,
-//!     b: i32,
-//! };
-//! impl EncodeToTreeNode for InputTree { }
+//! tree_struct! {
+//!     struct InputTree {
+//!         a: i32,
+//!         b: i32,
+//!     }
+//! }
 //!
-//! #[derive(RustcEncodable, RustcDecodable)]
-//! struct ResultTree {
-//!     result: i32
-//! };
-//! impl EncodeToTreeNode for ResultTree { }
+//! tree_struct! {
+//!     struct ResultTree {
+//!         result: i32
+//!     }
+//! }
 //!
 //! let component = to_component(consumer, publisher, |input: &InputTree| { 
 //!    ResultTree { result: input.a + input.b } 
 //! });
-//! # }
 //!
 //! ```
 //!
 //! Alternatively, a component could just respond directly to tree changes:
 //!
 //! ```
-//! # use tametree::component::*;
-//! # use tametree::component::immediate_publisher::*;
+//! # use tametree::prelude::*;
 //! #
 //! # let input_publisher   = ImmediatePublisher::new();
 //! # let consumer          = input_publisher.create_consumer();
@@ -74,10 +68,14 @@
 //! Responding directly to tree changes is useful when a component doesn't want to keep an entire tree in 
 //! memory: an example of where this is useful is when making a subtree act like a stream.
 //!
+//! When the input tree can't be decoded into the type a function takes, the component publishes an error tree
+//! (see `tametree::component::errors`) instead of calling the function.
+//!
 
 use std::rc::*;
 
 use super::component::*;
+use super::errors::*;
 use super::super::tree::*;
 
 struct FunctionComponent;
@@ -204,13 +202,19 @@ impl<TIn: 'static + DecodeFromTreeNode, TOut: 'static + ToTreeNode> ConvertToCom
         our_consumer.subscribe(TreeAddress::Here, TreeExtent::SubTree, Box::new(move |change| {
             tree = change.apply(&tree);
 
-            // TODO: once we have error handling, deal with decoding failing here
+            // Inputs that can't be decoded are reported as errors (see `tametree::component::errors`)
             let decoded_or_err  = TIn::new_from_tree(&tree);
-            if let Ok(decoded) = decoded_or_err {
-                let new_object  = action(&decoded);
-                let new_tree    = new_object.to_tree_node();
+            match decoded_or_err {
+                Ok(decoded) => {
+                    let new_object  = action(&decoded);
+                    let new_tree    = new_object.to_tree_node();
+
+                    our_publisher.publish(TreeChange::new(&TreeAddress::Here, &new_tree));
+                },
 
-                our_publisher.publish(TreeChange::new(&TreeAddress::Here, &new_tree));
+                Err(err) => {
+                    our_publisher.publish(TreeChange::new(&TreeAddress::Here, &error_tree(&format!("could not decode input: {:?}", err))));
+                }
             }
         }));
 
@@ -235,8 +239,7 @@ impl<TIn: 'static + DecodeFromTreeNode, TOut: 'static + ToTreeNode> ConvertToCom
 /// For example:
 ///
 /// ```
-/// # use tametree::component::*;
-/// # use tametree::component::immediate_publisher::*;
+/// # use tametree::prelude::*;
 /// #
 /// # let input_publisher   = ImmediatePublisher::new();
 /// # let consumer          = input_publisher.create_consumer();
@@ -257,8 +260,7 @@ impl<TIn: 'static + DecodeFromTreeNode, TOut: 'static + ToTreeNode> ConvertToCom
 /// Here's what you have to do as a result:
 ///
 /// ```
-/// # use tametree::component::*;
-/// # use tametree::component::immediate_publisher::*;
+/// # use tametree::prelude::*;
 /// #
 /// # let input_publisher   = ImmediatePublisher::new();
 /// # let consumer          = input_publisher.create_consumer();
@@ -284,8 +286,7 @@ pub fn component_fn<TIn, TOut, F>(func: F) -> Box<Fn(&TIn) -> TOut> where F: Fn(
 /// For example:
 ///
 /// ```
-/// # use tametree::component::*;
-/// # use tametree::component::immediate_publisher::*;
+/// # use tametree::prelude::*;
 /// #
 /// # let input_publisher   = ImmediatePublisher::new();
 /// # let consumer          = input_publisher.create_consumer();
@@ -308,8 +309,7 @@ pub fn component_fn_mut<TIn, TOut, F>(func: F) -> Box<FnMut(&TIn) -> TOut> where
 /// # Example
 ///
 /// ```
-/// # use tametree::component::*;
-/// # use tametree::component::immediate_publisher::*;
+/// # use tametree::prelude::*;
 /// #
 /// # let input_publisher   = ImmediatePublisher::new();
 /// # let consumer          = input_publisher.create_consumer();
@@ -331,8 +331,7 @@ pub fn to_component<TIn, TOut, F>(consumer: ConsumerRef, publisher: PublisherRef
 /// # Example
 ///
 /// ```
-/// # use tametree::component::*;
-/// # use tametree::component::immediate_publisher::*;
+/// # use tametree::prelude::*;
 /// #
 /// # let input_publisher   = ImmediatePublisher::new();
 /// # let consumer          = input_publisher.create_consumer();
@@ -355,6 +354,7 @@ pub fn to_component_mut<TIn, TOut, F>(consumer: ConsumerRef, publisher: Publishe
 mod component_function_tests {
     use rustc_serialize::*;
 
+    use super::super::super::tree::*;
     use super::super::super::component::*;
     use super::super::immediate_publisher::*;
     use super::super::output_tree_publisher::*;
@@ -407,18 +407,18 @@ mod component_function_tests {
         let output_publisher    = OutputTreePublisher::new();
         let result_reader       = output_publisher.get_tree_reader();
         
-        #[derive(RustcEncodable, RustcDecodable)]
-        struct InputTree {
-            a: i32,
-            b: i32,
-        };
-        impl EncodeToTreeNode for InputTree { }
-        
-        #[derive(RustcEncodable, RustcDecodable)]
-        struct ResultTree {
-            result: i32
-        };
-        impl EncodeToTreeNode for ResultTree { }
+        tree_struct! {
+            struct InputTree {
+                a: i32,
+                b: i32,
+            }
+        }
+
+        tree_struct! {
+            struct ResultTree {
+                result: i32
+            }
+        }
         
         let _component = to_component(consumer, output_publisher, |input: &InputTree| {
             ResultTree { result: input.a + input.b } 
@@ -20,14 +20,150 @@
 //! A hub provides a way to connect components into a tree. Hubs don't store changes, but can be used with things like
 //! `ComponentEndPoint` to make component results user accessible.
 //!
+//! Components that belong together can be attached through a `HubRegion`, created by `Hub::region()`. Closing the
+//! region removes all of them from the hub at once.
+//!
+//! Components attached with `add_component_with_shapes()` declare the shapes of the trees they read and publish
+//! (see `tametree::tree::shape`), so that wiring a component to another one it can't understand fails straight away.
+//!
+//! A `TraceHook` (see `tametree::component::trace`) can be installed with `set_trace_hook()` to find out when each
+//! component processes a change and how many changes it publishes as a result.
+//!
+//! After `enable_quarantine()` is called, the changes stopped by the hub's barriers and by the publishers created
+//! by `budgeted_publish_to()` are kept in a single `Quarantine` instead of being dropped. Each entry records which
+//! of these enforcement points stopped it.
+//!
+//! `enable_consumer_timing()` measures how long the subscriptions on the hub take. The time spent in the
+//! components attached with `add_named_component()` (or `add_component()`) is labelled with the component's name.
+//!
+//! Changes are delivered in priority order. The changes a component publishes while it's processing a change are
+//! given the priority of that change, so an urgent input stays urgent all the way through a chain of components.
+//!
+//! The hub remembers the addresses that its components and endpoints read from and publish to, so
+//! `validate_wiring()` can look for mistakes before any changes are sent (see `tametree::component::wiring`).
+//!
+//! A component attached with `add_named_component()` or `add_named_multi_component()` can be paused with
+//! `pause_component()`: it isn't sent any changes until it's resumed, and the changes it would have been sent are
+//! dropped. `enable_control()` makes it possible to do this and other administrative tasks by publishing commands
+//! to the hub (see `tametree::component::control`).
+//!
+
+use std::rc::*;
+use std::cell::*;
+use std::mem;
+use std::fmt;
+use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
 
 use super::super::tree::*;
 use super::component::*;
 use super::bus_publisher::*;
 use super::immediate_publisher::*;
+use super::multi_output::*;
+use super::convergence::*;
+use super::trace::*;
+use super::quarantine::*;
+use super::budgeted_publisher::*;
+use super::addressing_audit::*;
+use super::consumer_timing::*;
+use super::wiring::*;
+use super::control::*;
+
+///
+/// Creates a consumer that relays the changes to a particular address received by a bus consumer
+///
+fn relay_from(mut bus_consumer: ConsumerRef, address: TreeAddress) -> ConsumerRef {
+    // Create an immediate publisher to push changes to
+    let mut publisher   = ImmediatePublisher::new();
+    let consumer        = publisher.create_consumer();
+
+    // Push changes to the consumer when the bus changes
+    bus_consumer.subscribe(address, TreeExtent::SubTree, Box::new(move |change| {
+        publisher.publish(change.clone());
+    }));
+
+    consumer
+}
+
+///
+/// The trace hook installed on a hub, shared with the relays for its components
+///
+struct TraceState {
+    /// The hook to notify, if there is one
+    hook: RefCell<Option<Rc<dyn TraceHook>>>,
+
+    /// False if invocations shouldn't be passed to the hook for the time being
+    enabled: Cell<bool>,
+
+    /// The number of invocations that have been passed to a hook
+    invocations: Cell<usize>
+}
+
+///
+/// Creates a consumer that relays changes to a component, telling the trace hook (if there is one) when the
+/// component processes each change. Nothing is relayed while the component is paused.
+///
+fn traced_relay_from(mut bus_consumer: ConsumerRef, address: TreeAddress, component: String, trace: Rc<TraceState>, published: Rc<Cell<usize>>, paused: Rc<Cell<bool>>) -> ConsumerRef {
+    let mut publisher   = ImmediatePublisher::new();
+    let consumer        = publisher.create_consumer();
+
+    bus_consumer.subscribe(address, TreeExtent::SubTree, Box::new(move |change| {
+        if paused.get() {
+            return;
+        }
+
+        let hook = if trace.enabled.get() { trace.hook.borrow().clone() } else { None };
+
+        match hook {
+            None        => publisher.publish(change.clone()),
+            Some(hook)  => {
+                trace.invocations.set(trace.invocations.get() + 1);
+
+                // The component is called synchronously, so anything published in between came from it
+                let published_before    = published.get();
+                let token               = hook.on_invoke_start(&component, change);
+                publisher.publish(change.clone());
+                hook.on_invoke_end(token, published.get() - published_before);
+            }
+        }
+    }));
+
+    consumer
+}
 
 ///
-/// 
+/// Creates a publisher that relays changes to a particular address via a bus publisher
+///
+fn relay_to(mut bus_publisher: PublisherRef, address: TreeAddress) -> PublisherRef {
+    // We use an immediate publish to relay changes to the tree
+    let publisher           = ImmediatePublisher::new();
+    let mut consumer        = publisher.create_consumer();
+
+    // Whenever the user publishes to the immediate publisher, generate a tree publish event
+    consumer.subscribe(TreeAddress::Here, TreeExtent::SubTree, Box::new(move |change| {
+        bus_publisher.publish(change.rebased_to(&address));
+    }));
+
+    publisher
+}
+
+///
+/// Creates a publisher that relays changes to a particular address via a bus publisher, counting how many are published
+///
+fn counted_relay_to(mut bus_publisher: PublisherRef, address: TreeAddress, published: Rc<Cell<usize>>) -> PublisherRef {
+    let publisher           = ImmediatePublisher::new();
+    let mut consumer        = publisher.create_consumer();
+
+    consumer.subscribe(TreeAddress::Here, TreeExtent::SubTree, Box::new(move |change| {
+        published.set(published.get() + 1);
+        bus_publisher.publish(change.rebased_to(&address));
+    }));
+
+    publisher
+}
+
+///
+/// A hub connects components together by sharing a single tree between them
 ///
 pub struct Hub {
     ///
@@ -38,7 +174,95 @@ pub struct Hub {
     ///
     /// Components attached to this hub
     ///
-    components: Vec<ComponentRef>
+    components: Vec<ComponentRef>,
+
+    ///
+    /// The shapes that components attached with `add_component_with_shapes()` read from each address
+    ///
+    input_shapes: Vec<(TreeAddress, TreeShape)>,
+
+    ///
+    /// The shapes that components attached with `add_component_with_shapes()` publish to each address
+    ///
+    output_shapes: Vec<(TreeAddress, TreeShape)>,
+
+    ///
+    /// The trace hook notified when components process changes
+    ///
+    trace: Rc<TraceState>,
+
+    ///
+    /// Where changes stopped by the enforcement points of this hub are kept, if enabled
+    ///
+    quarantine: Option<Quarantine>,
+
+    ///
+    /// The addresses read from and published to by the components and endpoints of this hub
+    ///
+    wiring: Vec<ComponentWiring>,
+
+    ///
+    /// The addresses that have been declared as being published to by more than one component
+    ///
+    shared_outputs: Vec<TreeAddress>,
+
+    ///
+    /// When the wiring of this hub is checked
+    ///
+    wiring_validation: WiringValidation,
+
+    ///
+    /// The report from the last time the wiring was checked before pumping
+    ///
+    wiring_report: Option<WiringReport>,
+
+    ///
+    /// Whether or not each named component is paused
+    ///
+    paused: Rc<RefCell<HashMap<String, Rc<Cell<bool>>>>>,
+
+    ///
+    /// The scope of the subscriptions for the control tree, if it's enabled
+    ///
+    control: Option<SubscriptionScope>,
+
+    ///
+    /// The regions created directly on this hub (which might have been closed)
+    ///
+    regions: Vec<Weak<RefCell<RegionState>>>
+}
+
+///
+/// Error returned when the shape a component reads from an address doesn't match the shape published there
+///
+#[derive(Clone, PartialEq)]
+pub struct ShapeMismatch {
+    /// The address where the shapes don't match
+    pub address: TreeAddress,
+
+    /// The result of comparing the shape read from the address with the shape published there
+    pub compatibility: ShapeCompatibility
+}
+
+impl fmt::Display for ShapeMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.compatibility {
+            ShapeCompatibility::Incompatible { ref missing, ref mismatched } => {
+                let missing: Vec<String>    = missing.iter().map(|address| address.to_string()).collect();
+                let mismatched: Vec<String> = mismatched.iter().map(|(address, needed, provided)| format!("{} ({:?} but {:?} is published)", address, needed, provided)).collect();
+
+                write!(f, "incompatible shapes at {}: missing [{}], mismatched [{}]", self.address, missing.join(", "), mismatched.join(", "))
+            },
+
+            _ => write!(f, "compatible shapes at {}", self.address)
+        }
+    }
+}
+
+impl Default for Hub {
+    fn default() -> Hub {
+        Hub::new()
+    }
 }
 
 impl Hub {
@@ -46,7 +270,21 @@ impl Hub {
     /// Creates a new hub
     ///
     pub fn new() -> Hub {
-        Hub { bus: TreeChangeBus::new(), components: vec![] }
+        Hub {
+            bus:                TreeChangeBus::new(),
+            components:         vec![],
+            input_shapes:       vec![],
+            output_shapes:      vec![],
+            trace:              Rc::new(TraceState { hook: RefCell::new(None), enabled: Cell::new(true), invocations: Cell::new(0) }),
+            quarantine:         None,
+            wiring:             vec![],
+            shared_outputs:     vec![],
+            wiring_validation:  WiringValidation::Manual,
+            wiring_report:      None,
+            paused:             Rc::new(RefCell::new(HashMap::new())),
+            control:            None,
+            regions:            vec![]
+        }
     }
 
     ///
@@ -55,60 +293,297 @@ impl Hub {
     pub fn read_from<T: ToTreeAddress>(&mut self, address: &T) -> ConsumerRef {
         // TODO: smarter routing that doesn't respond to every single event
         // TODO: ensure we stop listening when the ConsumerRef is released
+        let address = address.to_tree_address();
 
-        // Create an immediate publisher to push changes to
-        let mut publisher   = ImmediatePublisher::new();
-        let consumer        = publisher.create_consumer();
-
-        let target_address  = address.to_tree_address();
-
-        // Push changes to the consumer when the bus changes
-        self.bus.create_consumer().subscribe(target_address, TreeExtent::SubTree, Box::new(move |change| {
-            publisher.publish(change.clone());
-        }));
-
-        consumer
+        self.wiring.push(ComponentWiring::endpoint(&format!("reader for {}", address), vec![address.clone()], vec![]));
+        relay_from(self.bus.create_consumer(), address)
     }
 
     ///
     /// Returns a publisher that will write to a particular address relative to this hub
     ///
     pub fn publish_to<T: ToTreeAddress>(&mut self, address: &T) -> PublisherRef {
-        // We use an immediate publish to relay changes to the tree
-        let publisher           = ImmediatePublisher::new();
-        let mut consumer        = publisher.create_consumer();
+        let address = address.to_tree_address();
 
-        // Whenever the user publishes to the immediate publisher, generate a tree publish event
-        let mut bus_publisher   = self.bus.create_publisher();
-        let target_address      = address.to_tree_address();
+        self.wiring.push(ComponentWiring::endpoint(&format!("publisher for {}", address), vec![], vec![address.clone()]));
+        relay_to(self.bus.create_publisher(), address)
+    }
 
-        consumer.subscribe(TreeAddress::Here, TreeExtent::SubTree, Box::new(move |change| {
-            let relative_change = change.relative_to(&target_address);
+    ///
+    /// Returns a publisher that will write to a particular address relative to this hub, but only passes on
+    /// changes that are within a budget
+    ///
+    /// Changes that are over budget are dropped, or sent to the hub's quarantine if it has been enabled. The
+    /// budget is checked against the tree published through this publisher rather than the whole tree.
+    ///
+    pub fn budgeted_publish_to<T: ToTreeAddress>(&mut self, address: &T, budget: ApplyBudget) -> PublisherRef {
+        let address         = address.to_tree_address();
+        let mut publisher   = BudgetedPublisher::new(self.publish_to(&address), budget);
 
-            if let Some(relative_change) = relative_change {
-                bus_publisher.publish(relative_change);
-            }
-        }));
+        if let Some(ref quarantine) = self.quarantine {
+            publisher.quarantine_rejected_changes(quarantine, &format!("budget {}", address));
+        }
 
         publisher
     }
 
+    ///
+    /// Adds a barrier that can block changes affecting part of the tree before they reach any component
+    ///
+    /// See `TreeChangeBus::add_barrier()`.
+    ///
+    pub fn add_barrier(&mut self, priority: i32, address: &TreeAddress, extent: TreeExtent, callback: BarrierCallback) {
+        self.bus.add_barrier(priority, address.clone(), extent, callback);
+    }
+
+    ///
+    /// Keeps the changes stopped by the enforcement points of this hub in a quarantine instead of dropping them
+    ///
+    /// This covers the barriers added to the hub and the publishers created by `budgeted_publish_to()` after this
+    /// is called. Calling this again returns the quarantine that's already enabled.
+    ///
+    pub fn enable_quarantine(&mut self, capacity: usize, overflow: QuarantineOverflow) -> Quarantine {
+        if let Some(ref quarantine) = self.quarantine {
+            return quarantine.clone();
+        }
+
+        let quarantine = Quarantine::new(capacity, overflow);
+        self.bus.quarantine_blocked_changes(&quarantine, "barriers");
+        self.quarantine = Some(quarantine.clone());
+
+        quarantine
+    }
+
+    ///
+    /// The quarantine for this hub, if it has been enabled
+    ///
+    pub fn quarantine(&self) -> Option<Quarantine> {
+        self.quarantine.clone()
+    }
+
+    ///
+    /// Creates a region of this hub
+    ///
+    /// The components and subscriptions created through the region are all removed when it's closed, leaving the
+    /// rest of the hub as it was.
+    ///
+    pub fn region(&mut self, name: &str) -> HubRegion {
+        let region = HubRegion::new(name, self.bus.connection(), self.trace.clone(), self.paused.clone());
+
+        self.regions.retain(|region| region.upgrade().is_some());
+        self.regions.push(Rc::downgrade(&region.state));
+
+        region
+    }
+
+    ///
+    /// The number of subscriptions to the bus that connects the components of this hub
+    ///
+    pub fn subscription_count(&self) -> usize {
+        self.bus.subscription_count()
+    }
+
     ///
     /// Attaches a component that reads from a particular address and publishes its results to another
     ///
     pub fn add_component<TComponent: ConvertToComponent, TFrom: ToTreeAddress, TTo: ToTreeAddress>(&mut self, component: TComponent, read_from: &TFrom, publish_to: &TTo) {
-        let consumer    = self.read_from(read_from);
-        let publisher   = self.publish_to(publish_to);
+        let name = unused_component_name(&self.paused, "", self.components.len());
+        self.add_named_component(&name, component, read_from, publish_to);
+    }
+
+    ///
+    /// Attaches a component that reads from a particular address and publishes its results to another, giving it a
+    /// name that's passed to the trace hook
+    ///
+    pub fn add_named_component<TComponent: ConvertToComponent, TFrom: ToTreeAddress, TTo: ToTreeAddress>(&mut self, name: &str, component: TComponent, read_from: &TFrom, publish_to: &TTo) {
+        let read_from   = read_from.to_tree_address();
+        let publish_to  = publish_to.to_tree_address();
+        let published   = Rc::new(Cell::new(0));
+
+        self.wiring.push(ComponentWiring::component(name, vec![read_from.clone()], vec![publish_to.clone()]));
+
+        let consumer    = self.component_consumer(name, read_from, published.clone());
+        let publisher   = counted_relay_to(self.bus.create_publisher(), publish_to, published);
 
         self.components.push(component.into_component(consumer, publisher));
     }
 
+    ///
+    /// Creates the consumer for a named component, which is traced, timed and can be paused
+    ///
+    fn component_consumer(&mut self, name: &str, read_from: TreeAddress, published: Rc<Cell<usize>>) -> ConsumerRef {
+        let paused = paused_flag(&self.paused, name);
+
+        traced_relay_from(self.bus.create_named_consumer(name), read_from, name.to_string(), self.trace.clone(), published, paused)
+    }
+
+    ///
+    /// Installs a hook that's notified whenever a component attached to this hub processes a change
+    ///
+    /// This replaces any hook that was installed before, and applies to components that are already attached.
+    ///
+    pub fn set_trace_hook(&mut self, hook: Rc<dyn TraceHook>) {
+        *self.trace.hook.borrow_mut() = Some(hook);
+    }
+
+    ///
+    /// Removes the trace hook from this hub
+    ///
+    pub fn clear_trace_hook(&mut self) {
+        *self.trace.hook.borrow_mut() = None;
+    }
+
+    ///
+    /// The number of component invocations that have been reported to a trace hook
+    ///
+    pub fn traced_invocations(&self) -> usize {
+        self.trace.invocations.get()
+    }
+
+    ///
+    /// Stops or restarts passing invocations to the trace hook, without removing it
+    ///
+    pub fn set_tracing_enabled(&mut self, enabled: bool) {
+        self.trace.enabled.set(enabled);
+    }
+
+    ///
+    /// Stops delivering changes to a named component until it's resumed. Returns false if there's no component
+    /// with this name.
+    ///
+    pub fn pause_component(&mut self, name: &str) -> bool {
+        set_paused(&self.paused, name, true).is_ok()
+    }
+
+    ///
+    /// Starts delivering changes to a paused component again. Returns false if there's no component with this name.
+    ///
+    pub fn resume_component(&mut self, name: &str) -> bool {
+        set_paused(&self.paused, name, false).is_ok()
+    }
+
+    ///
+    /// True if the component with a particular name is paused
+    ///
+    pub fn is_paused(&self, name: &str) -> bool {
+        self.paused.borrow().get(name).map(|paused| paused.get()).unwrap_or(false)
+    }
+
+    ///
+    /// Attaches a component that declares the shape of the tree it reads and the shape of the tree it publishes
+    ///
+    /// The shapes are checked against those declared by the components already attached to the same addresses
+    /// (components attached in other ways, or to addresses inside or around these ones, aren't checked). If the
+    /// component reads something that isn't published, or publishes something that doesn't contain what another
+    /// component reads, it isn't attached and the mismatch is returned.
+    ///
+    pub fn add_component_with_shapes<TComponent: ConvertToComponent, TFrom: ToTreeAddress, TTo: ToTreeAddress>(&mut self, component: TComponent, read_from: &TFrom, input_shape: TreeShape, publish_to: &TTo, output_shape: TreeShape) -> Result<(), ShapeMismatch> {
+        let read_from   = read_from.to_tree_address();
+        let publish_to  = publish_to.to_tree_address();
+
+        self.declare_shapes(vec![(read_from.clone(), input_shape)], vec![(publish_to.clone(), output_shape)])?;
+        self.add_component(component, &read_from, &publish_to);
+
+        Ok(())
+    }
+
+    ///
+    /// Declares that something attached to this hub reads trees of particular shapes from some addresses and
+    /// publishes trees of particular shapes to others
+    ///
+    /// The shapes are checked in the same way as for `add_component_with_shapes()`, and are only recorded if they
+    /// are all compatible with the shapes declared already.
+    ///
+    pub fn declare_shapes(&mut self, reads: Vec<(TreeAddress, TreeShape)>, publishes: Vec<(TreeAddress, TreeShape)>) -> Result<(), ShapeMismatch> {
+        for (read_from, input_shape) in reads.iter() {
+            for published in self.shapes_published_to(read_from) {
+                let compatibility = input_shape.compatible_with(published);
+
+                if let ShapeCompatibility::Incompatible { .. } = compatibility {
+                    return Err(ShapeMismatch { address: read_from.clone(), compatibility });
+                }
+            }
+        }
+
+        for (publish_to, output_shape) in publishes.iter() {
+            for read in self.shapes_read_from(publish_to) {
+                let compatibility = read.compatible_with(output_shape);
+
+                if let ShapeCompatibility::Incompatible { .. } = compatibility {
+                    return Err(ShapeMismatch { address: publish_to.clone(), compatibility });
+                }
+            }
+        }
+
+        self.input_shapes.extend(reads);
+        self.output_shapes.extend(publishes);
+
+        Ok(())
+    }
+
+    ///
+    /// The shapes that have been declared as read from an address of this hub
+    ///
+    pub fn shapes_read_from(&self, address: &TreeAddress) -> Vec<&TreeShape> {
+        self.input_shapes.iter().filter(|(read_from, _)| read_from == address).map(|(_, shape)| shape).collect()
+    }
+
+    ///
+    /// The shapes that have been declared as published to an address of this hub
+    ///
+    pub fn shapes_published_to(&self, address: &TreeAddress) -> Vec<&TreeShape> {
+        self.output_shapes.iter().filter(|(publish_to, _)| publish_to == address).map(|(_, shape)| shape).collect()
+    }
+
+    ///
+    /// Attaches a component with several named outputs, each of which is published to its own address
+    ///
+    /// Outputs that aren't listed here are not published anywhere.
+    ///
+    pub fn add_multi_component<TComponent: ConvertToMultiComponent, TFrom: ToTreeAddress>(&mut self, component: TComponent, read_from: &TFrom, outputs: &[(&str, &dyn ToTreeAddress)]) {
+        let name = unused_component_name(&self.paused, "", self.components.len());
+        self.add_named_multi_component(&name, component, read_from, outputs);
+    }
+
+    ///
+    /// Attaches a component with several named outputs, giving it a name that's passed to the trace hook and can
+    /// be used to pause it
+    ///
+    pub fn add_named_multi_component<TComponent: ConvertToMultiComponent, TFrom: ToTreeAddress>(&mut self, name: &str, component: TComponent, read_from: &TFrom, outputs: &[(&str, &dyn ToTreeAddress)]) {
+        let read_from       = read_from.to_tree_address();
+        let published       = Rc::new(Cell::new(0));
+        let mut publishers  = MultiPublisher::new();
+        let mut publishes   = vec![];
+
+        for &(output, address) in outputs {
+            let address = address.to_tree_address();
+
+            publishers.add(output, counted_relay_to(self.bus.create_publisher(), address.clone(), published.clone()));
+            publishes.push(address);
+        }
+
+        self.wiring.push(ComponentWiring::component(name, vec![read_from.clone()], publishes));
+
+        let consumer = self.component_consumer(name, read_from, published);
+        self.components.push(component.into_component_multi(consumer, publishers));
+    }
+
+    ///
+    /// True if there are no messages waiting for this hub
+    ///
+    #[inline]
+    pub fn is_quiescent(&self) -> bool {
+        self.bus.is_quiescent()
+    }
+
     ///
     /// Pumps any messages waiting for this hub
     ///
     #[inline]
     pub fn pump(&mut self) {
-        self.bus.pump();
+        if self.check_wiring_before_pump() {
+            self.bus.pump();
+        }
     }
 
     ///
@@ -116,6 +591,774 @@ impl Hub {
     ///
     #[inline]
     pub fn flush(&mut self) {
-        self.bus.flush();
+        if self.check_wiring_before_pump() {
+            self.bus.flush();
+        }
+    }
+
+    ///
+    /// Processes messages for this hub until there are no more to be processed, giving up if the attached
+    /// convergence monitor decides that the hub is diverging or if the specified number of generations is reached
+    ///
+    /// A hub whose wiring is stopping it from being pumped stops straight away, with no generations pumped.
+    ///
+    #[inline]
+    pub fn flush_until_stable(&mut self, max_generations: usize) -> Result<PumpStats, FlushAborted> {
+        if !self.check_wiring_before_pump() {
+            return Err(FlushAborted { stats: PumpStats::default(), generations: 0, status: ConvergenceStatus::Unknown, history: vec![] });
+        }
+
+        self.bus.flush_until_stable(max_generations)
+    }
+
+    ///
+    /// Checks the wiring of this hub, without sending any changes
+    ///
+    pub fn validate_wiring(&self) -> WiringReport {
+        let mut wiring = self.wiring.clone();
+
+        for region in self.regions.iter().filter_map(|region| region.upgrade()) {
+            region_wiring(&region.borrow(), &mut wiring);
+        }
+
+        validate_wiring(&wiring, &self.shared_outputs)
+    }
+
+    ///
+    /// Declares that more than one component is meant to publish to an address (or to addresses inside it), so
+    /// this isn't reported as a problem with the wiring
+    ///
+    pub fn declare_shared_output<T: ToTreeAddress>(&mut self, address: &T) {
+        self.shared_outputs.push(address.to_tree_address());
+    }
+
+    ///
+    /// Sets when the wiring of this hub is checked
+    ///
+    pub fn set_wiring_validation(&mut self, validation: WiringValidation) {
+        self.wiring_validation  = validation;
+        self.wiring_report      = None;
+    }
+
+    ///
+    /// The report from when the wiring of this hub was checked before it was pumped, if it has been
+    ///
+    pub fn wiring_report(&self) -> Option<&WiringReport> {
+        self.wiring_report.as_ref()
+    }
+
+    ///
+    /// Checks the wiring of this hub if it's due to be checked, returning false if the hub shouldn't be pumped
+    ///
+    fn check_wiring_before_pump(&mut self) -> bool {
+        let last_had_errors = self.wiring_report.as_ref().map(|report| report.has_errors());
+
+        match (self.wiring_validation, last_had_errors) {
+            (WiringValidation::Manual, _)               => true,
+            (WiringValidation::OnFirstPump, Some(_))    => true,
+            (WiringValidation::Strict, Some(false))     => true,
+
+            (validation, _) => {
+                let report      = self.validate_wiring();
+                let has_errors  = report.has_errors();
+
+                self.wiring_report = Some(report);
+                !(validation == WiringValidation::Strict && has_errors)
+            }
+        }
+    }
+
+    ///
+    /// Sets the number of changes that can be delivered ahead of a lower priority change before it's let through
+    ///
+    /// See `TreeChangeBus::set_starvation_guard()`.
+    ///
+    #[inline]
+    pub fn set_starvation_guard(&mut self, guard: usize) {
+        self.bus.set_starvation_guard(guard);
+    }
+
+    ///
+    /// Attaches a monitor that records the changes generated each time this hub is pumped
+    ///
+    #[inline]
+    pub fn attach_convergence_monitor(&mut self, monitor: ConvergenceMonitor) {
+        self.bus.attach_convergence_monitor(monitor);
+    }
+
+    ///
+    /// Retrieves the convergence monitor attached to this hub, if there is one
+    ///
+    #[inline]
+    pub fn convergence_monitor(&self) -> Option<&ConvergenceMonitor> {
+        self.bus.convergence_monitor()
+    }
+
+    ///
+    /// Attaches an audit that records how the changes and subscriptions on this hub are addressed
+    ///
+    #[inline]
+    pub fn attach_addressing_audit(&mut self, audit: AddressingAudit) {
+        self.bus.attach_addressing_audit(audit);
+    }
+
+    ///
+    /// Starts measuring how long the subscriptions on this hub take, returning the measurements
+    ///
+    pub fn enable_consumer_timing(&mut self, clock: Rc<dyn TimingClock>, settings: ConsumerTimingSettings) -> ConsumerTiming {
+        self.bus.enable_consumer_timing(clock, settings);
+        self.bus.consumer_timing()
+    }
+
+    ///
+    /// Stops measuring the subscriptions on this hub
+    ///
+    #[inline]
+    pub fn disable_consumer_timing(&mut self) {
+        self.bus.disable_consumer_timing();
+    }
+
+    ///
+    /// The measurements of how long the subscriptions on this hub take
+    ///
+    #[inline]
+    pub fn consumer_timing(&self) -> ConsumerTiming {
+        self.bus.consumer_timing()
+    }
+
+    ///
+    /// Runs the commands published under an address, publishing their results to its `results` child
+    ///
+    /// See `tametree::component::control` for the commands that can be published. Calling this again moves the
+    /// control tree to a new address.
+    ///
+    pub fn enable_control<T: ToTreeAddress>(&mut self, address: &T) {
+        self.disable_control();
+
+        let address     = address.to_tree_address();
+        let results     = address.to_tree_address_then(RESULTS_TAG.to_tree_address());
+        let scope       = SubscriptionScope::new();
+        let connection  = self.bus.connection();
+
+        self.wiring.push(ComponentWiring::endpoint(CONTROL_ENDPOINT, vec![address.clone()], vec![results]));
+
+        let consumer    = relay_from(connection.create_scoped_consumer(&scope), address.clone());
+        let publisher   = relay_to(connection.create_scoped_publisher(&scope), address);
+        subscribe_control(consumer, publisher, self.control_commands());
+
+        self.control = Some(scope);
+    }
+
+    ///
+    /// Stops running the commands published to the control tree, removing its subscription
+    ///
+    pub fn disable_control(&mut self) {
+        if let Some(scope) = self.control.take() {
+            scope.close();
+            self.bus.connection().remove_closed_subscriptions();
+            self.wiring.retain(|wiring| wiring.name != CONTROL_ENDPOINT);
+        }
+    }
+
+    ///
+    /// The commands that can be published to the control tree of this hub
+    ///
+    fn control_commands(&self) -> ControlCommands {
+        let mut commands = ControlCommands::new();
+
+        let paused = self.paused.clone();
+        commands.add_command("pause", move |parameters: ComponentParameters| set_paused(&paused, &parameters.component, true));
+
+        let paused = self.paused.clone();
+        commands.add_command("resume", move |parameters: ComponentParameters| set_paused(&paused, &parameters.component, false));
+
+        let timing = self.bus.consumer_timing();
+        commands.add_command("set_metrics", move |parameters: ToggleParameters| {
+            if parameters.enabled {
+                timing.enable(Rc::new(SystemClock::new()), ConsumerTimingSettings::default());
+                Ok("started measuring subscriptions".to_string())
+            } else {
+                timing.disable();
+                Ok("stopped measuring subscriptions".to_string())
+            }
+        });
+
+        let trace = self.trace.clone();
+        commands.add_command("set_tracing", move |parameters: ToggleParameters| {
+            trace.enabled.set(parameters.enabled);
+            Ok(format!("tracing {}", if parameters.enabled { "enabled" } else { "disabled" }))
+        });
+
+        // The quarantine commands are only available if there's a quarantine
+        if let Some(ref quarantine) = self.quarantine {
+            let release = quarantine.clone();
+            commands.add_command("release_quarantined", move |parameters: QuarantineParameters| {
+                if release.clone().release(parameters.change_id()?) {
+                    Ok(format!("released quarantined change {}", parameters.id))
+                } else {
+                    Err(format!("quarantined change {} could not be released", parameters.id))
+                }
+            });
+
+            let discard = quarantine.clone();
+            commands.add_command("discard_quarantined", move |parameters: QuarantineParameters| {
+                if discard.clone().discard(parameters.change_id()?) {
+                    Ok(format!("discarded quarantined change {}", parameters.id))
+                } else {
+                    Err(format!("there is no quarantined change {}", parameters.id))
+                }
+            });
+        }
+
+        commands
+    }
+}
+
+///
+/// The name of the control tree in the wiring of a hub
+///
+const CONTROL_ENDPOINT: &str = "control tree";
+
+///
+/// Finds the flag that pauses a named component, creating it if the component hasn't been seen before
+///
+fn paused_flag(paused: &RefCell<HashMap<String, Rc<Cell<bool>>>>, name: &str) -> Rc<Cell<bool>> {
+    paused.borrow_mut().entry(name.to_string()).or_insert_with(|| Rc::new(Cell::new(false))).clone()
+}
+
+///
+/// Picks a name for a component that was attached without one, starting from the number of components attached
+/// before it and skipping any names that are already in use
+///
+fn unused_component_name(paused: &RefCell<HashMap<String, Rc<Cell<bool>>>>, prefix: &str, first_index: usize) -> String {
+    let paused = paused.borrow();
+
+    (first_index..)
+        .map(|index| format!("{}component-{}", prefix, index))
+        .find(|name| !paused.contains_key(name))
+        .expect("there is always an unused component name")
+}
+
+///
+/// Pauses or resumes a named component, returning a description of what happened
+///
+fn set_paused(paused: &RefCell<HashMap<String, Rc<Cell<bool>>>>, name: &str, pause: bool) -> Result<String, String> {
+    match paused.borrow().get(name) {
+        Some(flag)  => {
+            flag.set(pause);
+            Ok(format!("{} {}", if pause { "paused" } else { "resumed" }, name))
+        },
+
+        None        => Err(format!("there is no component called '{}'", name))
+    }
+}
+
+///
+/// Error returned when something is created through a region that has been closed
+///
+#[derive(Clone, PartialEq, Debug)]
+pub enum RegionError {
+    /// The region with this name has been closed
+    Closed(String)
+}
+
+impl fmt::Display for RegionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RegionError::Closed(ref name) => write!(f, "region '{}' has been closed", name)
+        }
+    }
+}
+
+///
+/// Something that went wrong while closing a region
+///
+#[derive(Clone, PartialEq, Debug)]
+pub enum RegionCleanupFailure {
+    /// A component panicked while it was being dropped (the index is the order it was added to the region)
+    ComponentPanicked { region: String, index: usize }
+}
+
+///
+/// The state of a region, shared between its handles and its parent
+///
+struct RegionState {
+    /// The name of this region
+    name: String,
+
+    /// Connects the region to the bus of its hub
+    connection: BusConnection,
+
+    /// The scope of the subscriptions made through this region (None once the region is closed)
+    scope: Option<SubscriptionScope>,
+
+    /// The trace hook of the hub, shared with the components attached through this region
+    trace: Rc<TraceState>,
+
+    /// Whether or not each named component attached to the hub is paused
+    paused: Rc<RefCell<HashMap<String, Rc<Cell<bool>>>>>,
+
+    /// The components attached through this region with their names and the scopes of their subscriptions, in the
+    /// order they were added
+    components: Vec<(String, SubscriptionScope, ComponentRef)>,
+
+    /// The addresses read from and published to by the components and endpoints of this region
+    wiring: Vec<ComponentWiring>,
+
+    /// The regions nested inside this one
+    children: Vec<Rc<RefCell<RegionState>>>
+}
+
+///
+/// Adds the wiring of a region and the regions nested inside it to a list
+///
+fn region_wiring(state: &RegionState, wiring: &mut Vec<ComponentWiring>) {
+    wiring.extend(state.wiring.iter().cloned());
+
+    for child in state.children.iter() {
+        region_wiring(&child.borrow(), wiring);
+    }
+}
+
+///
+/// A region groups together components and subscriptions that are created through it, so that they can be
+/// removed from their hub together
+///
+/// Closing a region closes the regions nested inside it first (most recently created first), then removes its
+/// subscriptions and finally drops its components in the reverse of the order they were added. A region is also
+/// closed when it's dropped, but `close()` is preferred as it can report any problems. Nested regions are kept
+/// open by their parent, so dropping the handle of a nested region doesn't close it.
+///
+/// Components attached through a region are traced and can be paused in the same way as the ones attached directly
+/// to the hub, and everything created through an open region is included when the hub's wiring is checked.
+///
+pub struct HubRegion {
+    state: Rc<RefCell<RegionState>>
+}
+
+impl HubRegion {
+    ///
+    /// Creates a new region connected to a bus
+    ///
+    fn new(name: &str, connection: BusConnection, trace: Rc<TraceState>, paused: Rc<RefCell<HashMap<String, Rc<Cell<bool>>>>>) -> HubRegion {
+        HubRegion { state: Rc::new(RefCell::new(RegionState {
+            name:       name.to_string(),
+            connection,
+            scope:      Some(SubscriptionScope::new()),
+            trace,
+            paused,
+            components: vec![],
+            wiring:     vec![],
+            children:   vec![]
+        })) }
+    }
+
+    ///
+    /// The name of this region
+    ///
+    pub fn name(&self) -> String {
+        self.state.borrow().name.clone()
+    }
+
+    ///
+    /// True if this region has not been closed
+    ///
+    pub fn is_open(&self) -> bool {
+        self.state.borrow().scope.is_some()
+    }
+
+    ///
+    /// Creates a region nested inside this one
+    ///
+    pub fn region(&mut self, name: &str) -> Result<HubRegion, RegionError> {
+        if !self.is_open() {
+            return Err(RegionError::Closed(self.name()));
+        }
+
+        let child = {
+            let state = self.state.borrow();
+            HubRegion::new(name, state.connection.clone(), state.trace.clone(), state.paused.clone())
+        };
+        self.state.borrow_mut().children.push(child.state.clone());
+
+        Ok(child)
+    }
+
+    ///
+    /// Returns a consumer that will read from a particular address relative to the hub, until this region is closed
+    ///
+    pub fn read_from<T: ToTreeAddress>(&mut self, address: &T) -> Result<ConsumerRef, RegionError> {
+        let address     = address.to_tree_address();
+        let mut state   = self.state.borrow_mut();
+
+        let consumer = match state.scope {
+            Some(ref scope) => state.connection.create_scoped_consumer(scope),
+            None            => return Err(RegionError::Closed(state.name.clone()))
+        };
+
+        state.wiring.push(ComponentWiring::endpoint(&format!("reader for {}", address), vec![address.clone()], vec![]));
+        Ok(relay_from(consumer, address))
+    }
+
+    ///
+    /// Returns a publisher that will write to a particular address relative to the hub, until this region is closed
+    ///
+    pub fn publish_to<T: ToTreeAddress>(&mut self, address: &T) -> Result<PublisherRef, RegionError> {
+        let address     = address.to_tree_address();
+        let mut state   = self.state.borrow_mut();
+
+        let publisher = match state.scope {
+            Some(ref scope) => state.connection.create_scoped_publisher(scope),
+            None            => return Err(RegionError::Closed(state.name.clone()))
+        };
+
+        state.wiring.push(ComponentWiring::endpoint(&format!("publisher for {}", address), vec![], vec![address.clone()]));
+        Ok(relay_to(publisher, address))
+    }
+
+    ///
+    /// Attaches a component to the hub that reads from a particular address and publishes its results to another,
+    /// until this region is closed
+    ///
+    pub fn add_component<TComponent: ConvertToComponent, TFrom: ToTreeAddress, TTo: ToTreeAddress>(&mut self, component: TComponent, read_from: &TFrom, publish_to: &TTo) -> Result<(), RegionError> {
+        let name = {
+            let state = self.state.borrow();
+            unused_component_name(&state.paused, &format!("{}/", state.name), state.components.len())
+        };
+
+        self.add_named_component(&name, component, read_from, publish_to)
+    }
+
+    ///
+    /// Attaches a component to the hub that reads from a particular address and publishes its results to another,
+    /// giving it a name that's passed to the trace hook and can be used to pause it, until this region is closed
+    ///
+    pub fn add_named_component<TComponent: ConvertToComponent, TFrom: ToTreeAddress, TTo: ToTreeAddress>(&mut self, name: &str, component: TComponent, read_from: &TFrom, publish_to: &TTo) -> Result<(), RegionError> {
+        if !self.is_open() {
+            return Err(RegionError::Closed(self.name()));
+        }
+
+        let read_from   = read_from.to_tree_address();
+        let publish_to  = publish_to.to_tree_address();
+        let published   = Rc::new(Cell::new(0));
+
+        // Each component gets its own scope, so its subscriptions can be removed at the same time as it's dropped
+        let scope                   = SubscriptionScope::new();
+        let (consumer, publisher)   = {
+            let state   = self.state.borrow();
+            let paused  = paused_flag(&state.paused, name);
+
+            (traced_relay_from(state.connection.create_named_scoped_consumer(&scope, name), read_from.clone(), name.to_string(), state.trace.clone(), published.clone(), paused),
+                counted_relay_to(state.connection.create_scoped_publisher(&scope), publish_to.clone(), published))
+        };
+
+        let component   = component.into_component(consumer, publisher);
+        let mut state   = self.state.borrow_mut();
+
+        state.wiring.push(ComponentWiring::component(name, vec![read_from], vec![publish_to]));
+        state.components.push((name.to_string(), scope, component));
+        Ok(())
+    }
+
+    ///
+    /// Closes this region and the regions nested inside it, removing everything that was created through them
+    ///
+    pub fn close(self) -> Result<(), Vec<RegionCleanupFailure>> {
+        let failures = HubRegion::close_state(&self.state);
+
+        if failures.is_empty() { Ok(()) } else { Err(failures) }
+    }
+
+    ///
+    /// Closes a region, returning anything that failed to clean up
+    ///
+    fn close_state(state: &Rc<RefCell<RegionState>>) -> Vec<RegionCleanupFailure> {
+        let mut failures = vec![];
+
+        // Take everything out of the region so nothing is borrowed while the components are dropped
+        let (name, connection, scope, paused, components, children) = {
+            let mut state = state.borrow_mut();
+            let scope = match state.scope.take() {
+                Some(scope) => scope,
+                None        => return failures
+            };
+
+            // The region's components and endpoints no longer count towards the wiring of the hub
+            state.wiring.clear();
+
+            (state.name.clone(), state.connection.clone(), scope, state.paused.clone(), mem::take(&mut state.components), mem::take(&mut state.children))
+        };
+
+        // Children are closed first
+        for child in children.iter().rev() {
+            failures.extend(HubRegion::close_state(child));
+        }
+
+        // Stop the subscriptions made directly through the region
+        scope.close();
+        connection.remove_closed_subscriptions();
+
+        // Drop the components and their subscriptions, most recent first
+        for (index, (component_name, component_scope, component)) in components.into_iter().enumerate().rev() {
+            paused.borrow_mut().remove(&component_name);
+
+            let dropped = panic::catch_unwind(AssertUnwindSafe(|| {
+                mem::drop(component);
+                component_scope.close();
+                connection.remove_closed_subscriptions();
+            }));
+
+            if dropped.is_err() {
+                failures.push(RegionCleanupFailure::ComponentPanicked { region: name.clone(), index });
+            }
+        }
+
+        failures
+    }
+}
+
+impl Drop for HubRegion {
+    fn drop(&mut self) {
+        // Nested regions are also referenced by their parent, so this only closes regions that nothing else can reach
+        if Rc::strong_count(&self.state) == 1 {
+            HubRegion::close_state(&self.state);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod hub_tests {
+    use std::rc::*;
+    use std::cell::*;
+
+    use super::super::super::tree::*;
+    use super::super::super::component::*;
+    use super::super::bus_publisher::*;
+
+    ///
+    /// Adds its name to a log when it's dropped
+    ///
+    struct DropLogger {
+        name: &'static str,
+        log: Rc<RefCell<Vec<&'static str>>>
+    }
+
+    impl Drop for DropLogger {
+        fn drop(&mut self) {
+            self.log.borrow_mut().push(self.name);
+        }
+    }
+
+    ///
+    /// A component that adds one to its input and logs when it's dropped
+    ///
+    fn logged_component(name: &'static str, log: &Rc<RefCell<Vec<&'static str>>>) -> Box<dyn Fn(&i32) -> i32> {
+        let logger = DropLogger { name, log: log.clone() };
+        component_fn(move |x: &i32| { let _ = &logger; x+1 })
+    }
+
+    tree_struct! {
+        #[derive(Default)]
+        struct Reading {
+            value: i32,
+            unit: String
+        }
+    }
+
+    tree_struct! {
+        #[derive(Default)]
+        struct JustValue {
+            value: i32
+        }
+    }
+
+    tree_struct! {
+        #[derive(Default)]
+        struct TextValue {
+            value: String
+        }
+    }
+
+    #[test]
+    fn compatible_shapes_can_be_wired() {
+        let mut hub = Hub::new();
+
+        assert!(hub.add_component_with_shapes(component_fn(|x: &Reading| JustValue { value: x.value }), &"in", shape_of::<Reading>(), &"mid", shape_of::<JustValue>()).is_ok());
+        assert!(hub.add_component_with_shapes(component_fn(|x: &JustValue| x.value), &"mid", shape_of::<JustValue>(), &"out", shape_of::<i32>()).is_ok());
+
+        // Reading only the value from the input is fine, as the consumer needs less than is published
+        assert!(hub.add_component_with_shapes(component_fn(|x: &JustValue| x.value), &"mid", shape_of::<JustValue>(), &"other", shape_of::<i32>()).is_ok());
+    }
+
+    #[test]
+    fn incompatible_shapes_are_not_wired() {
+        let mut hub = Hub::new();
+
+        assert!(hub.add_component_with_shapes(component_fn(|x: &Reading| JustValue { value: x.value }), &"in", shape_of::<Reading>(), &"mid", shape_of::<JustValue>()).is_ok());
+
+        let mismatch = hub.add_component_with_shapes(component_fn(|x: &TextValue| x.value.len() as i32), &"mid", shape_of::<TextValue>(), &"out", shape_of::<i32>()).err().unwrap();
+        assert!(mismatch.address == "mid".to_tree_address());
+        assert!(match mismatch.compatibility { ShapeCompatibility::Incompatible { ref mismatched, .. } => mismatched[0].0 == "value".to_tree_address(), _ => false });
+
+        // The same mismatch is found when the consumer is attached first
+        let mut hub = Hub::new();
+
+        assert!(hub.add_component_with_shapes(component_fn(|x: &Reading| x.value), &"mid", shape_of::<Reading>(), &"out", shape_of::<i32>()).is_ok());
+        assert!(hub.add_component_with_shapes(component_fn(|x: &i32| JustValue { value: *x }), &"in", shape_of::<i32>(), &"mid", shape_of::<JustValue>()).is_err());
+    }
+
+    #[test]
+    fn component_reads_and_publishes_through_hub() {
+        let mut hub     = Hub::new();
+        let mut input   = hub.publish_to(&"in");
+        hub.add_component(component_fn(|x: &i32| { x+1 }), &"in", &"out");
+
+        let output: RecvFn<i32> = hub.read_from(&"out").get_receiver();
+
+        input.publish(TreeChange::new(&(), &41));
+        hub.flush();
+
+        assert!(output() == Some(42));
+    }
+
+    #[test]
+    fn closing_region_removes_everything_created_through_it() {
+        let mut hub         = Hub::new();
+        let log             = Rc::new(RefCell::new(vec![]));
+        let baseline        = hub.subscription_count();
+
+        let mut region      = hub.region("feature");
+        region.add_component(logged_component("first", &log), &"in", &"a").unwrap();
+        region.add_component(logged_component("second", &log), &"a", &"b").unwrap();
+        region.add_component(logged_component("third", &log), &"b", &"c").unwrap();
+
+        let watch_b: RecvFn<i32>    = region.read_from(&"b").unwrap().get_receiver();
+        let watch_c: RecvFn<i32>    = region.read_from(&"c").unwrap().get_receiver();
+        let mut input               = hub.publish_to(&"in");
+
+        assert!(hub.subscription_count() == baseline + 5);
+
+        input.publish(TreeChange::new(&(), &1));
+        hub.flush();
+        assert!(watch_b() == Some(3));
+        assert!(watch_c() == Some(4));
+
+        assert!(region.close() == Ok(()));
+        assert!(hub.subscription_count() == baseline);
+        assert!(*log.borrow() == vec!["third", "second", "first"]);
+
+        // Nothing is delivered to the watches any more
+        input.publish(TreeChange::new(&(), &10));
+        hub.flush();
+        assert!(watch_c() == Some(4));
+    }
+
+    #[test]
+    fn closing_parent_closes_children_first() {
+        let mut hub         = Hub::new();
+        let log             = Rc::new(RefCell::new(vec![]));
+
+        let mut parent      = hub.region("parent");
+        parent.add_component(logged_component("parent", &log), &"in", &"out").unwrap();
+
+        let mut first       = parent.region("first").unwrap();
+        first.add_component(logged_component("first child", &log), &"in", &"first").unwrap();
+
+        let mut second      = parent.region("second").unwrap();
+        second.add_component(logged_component("second child", &log), &"in", &"second").unwrap();
+        let mut grandchild  = second.region("grandchild").unwrap();
+        grandchild.add_component(logged_component("grandchild", &log), &"in", &"grandchild").unwrap();
+
+        assert!(parent.close() == Ok(()));
+        assert!(*log.borrow() == vec!["grandchild", "second child", "first child", "parent"]);
+        assert!(!first.is_open());
+        assert!(!grandchild.is_open());
+    }
+
+    #[test]
+    fn cannot_create_through_closed_region() {
+        let mut hub     = Hub::new();
+        let mut parent  = hub.region("parent");
+        let mut child   = parent.region("child").unwrap();
+
+        assert!(parent.close() == Ok(()));
+
+        assert!(child.add_component(component_fn(|x: &i32| { x+1 }), &"in", &"out") == Err(RegionError::Closed("child".to_string())));
+        assert!(child.read_from(&"in").err() == Some(RegionError::Closed("child".to_string())));
+        assert!(child.publish_to(&"out").err() == Some(RegionError::Closed("child".to_string())));
+        assert!(child.region("grandchild").err() == Some(RegionError::Closed("child".to_string())));
+    }
+
+    #[test]
+    fn closing_region_leaves_rest_of_hub_alone() {
+        let mut hub     = Hub::new();
+        hub.add_component(component_fn(|x: &i32| { x*2 }), &"in", &"doubled");
+
+        let mut region  = hub.region("feature");
+        region.add_component(component_fn(|x: &i32| { x+1 }), &"in", &"incremented").unwrap();
+
+        let doubled: RecvFn<i32>    = hub.read_from(&"doubled").get_receiver();
+        let mut input               = hub.publish_to(&"in");
+        let count                   = hub.subscription_count();
+
+        drop(region);
+        assert!(hub.subscription_count() == count - 1);
+
+        input.publish(TreeChange::new(&(), &21));
+        hub.flush();
+        assert!(doubled() == Some(42));
+    }
+
+    #[test]
+    fn blocked_changes_are_quarantined_and_can_be_released() {
+        let mut hub         = Hub::new();
+        let mut quarantine  = hub.enable_quarantine(10, QuarantineOverflow::RejectNew);
+        let mut input       = hub.publish_to(&"in");
+        hub.add_component(component_fn(|x: &i32| { x+1 }), &"in", &"out");
+        hub.add_barrier(0, &"in".to_tree_address(), TreeExtent::SubTree, Box::new(|change| {
+            if change.apply(&"".to_tree_node()).get_value().to_int(0) < 0 { BarrierVerdict::Block("negative".to_string()) } else { BarrierVerdict::Pass }
+        }));
+
+        let output: RecvFn<i32> = hub.read_from(&"out").get_receiver();
+
+        input.publish(TreeChange::new(&(), &-5));
+        hub.flush();
+
+        let pending = quarantine.pending();
+        assert!(output().is_none());
+        assert!(pending.len() == 1);
+        assert!(pending[0].reason == "negative");
+        assert!(pending[0].source == "barriers");
+
+        assert!(quarantine.release_with_bypass(pending[0].id));
+        hub.flush();
+
+        assert!(output() == Some(-4));
+        assert!(quarantine.pending().is_empty());
+    }
+
+    #[test]
+    fn quarantine_collects_from_every_enforcement_point() {
+        let mut hub         = Hub::new();
+        let quarantine      = hub.enable_quarantine(10, QuarantineOverflow::DropOldest);
+        let mut input       = hub.publish_to(&"in");
+        let mut limited     = hub.budgeted_publish_to(&"limited", ApplyBudget { max_new_nodes: 10, max_depth: 10, max_replacement_nodes: 10 });
+        hub.add_barrier(0, &"in".to_tree_address(), TreeExtent::SubTree, Box::new(|_change| BarrierVerdict::Block("closed".to_string())));
+
+        input.publish(TreeChange::new(&(), &1));
+        limited.publish(TreeChange::new(&50, &"far_away"));
+        hub.flush();
+
+        let sources: Vec<(String, String)> = quarantine.pending().into_iter().map(|entry| (entry.source, entry.reason)).collect();
+        assert!(sources == vec![
+            ("budget .\"limited\".".to_string(), "applying the change would create more than 10 nodes".to_string()),
+            ("barriers".to_string(), "closed".to_string())
+        ]);
+
+        let tree = hub.quarantine().unwrap().as_tree();
+        assert!(tree.get_tag() == QUARANTINE_TAG);
+        assert!(tree.get_child_at("stats").get_child_at("quarantined").get_value().to_int(0) == 2);
+        assert!(tree.get_child_at("pending").get_child_at(1).get_child_at("source").get_value().to_str("") == "barriers");
     }
 }
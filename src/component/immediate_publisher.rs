@@ -15,11 +15,13 @@
 //
 
 use std::rc::*;
+use std::cell::*;
 
 use super::super::tree::*;
 
 use super::component::*;
 use super::subscriptionmanager::*;
+use super::adaptive_filter::*;
 
 ///
 /// Stores a registration of a consumer
@@ -27,7 +29,8 @@ use super::subscriptionmanager::*;
 #[derive(Clone)]
 struct ConsumerRegistration {
     address: TreeAddress,
-    extent: TreeExtent
+    extent: TreeExtent,
+    filter: Option<Rc<RefCell<AdaptiveFilter>>>
 }
 
 ///
@@ -37,7 +40,12 @@ struct ImmediateConsumer {
     ///
     /// Where subscriptions can be registered for this consumer
     ///
-    subscriptions: Rc<SubscriptionManager<ConsumerRegistration>>
+    subscriptions: Rc<SubscriptionManager<ConsumerRegistration>>,
+
+    ///
+    /// The settings for the adaptive filters to attach to new subscriptions, if they're enabled
+    ///
+    adaptive: Rc<Cell<Option<AdaptiveFilterSettings>>>
 }
 
 impl Consumer for ImmediateConsumer {
@@ -48,7 +56,9 @@ impl Consumer for ImmediateConsumer {
         // Need to persuade rust that it can call the FnMut (assign parameter to a mutable variable)
         let mut also_callback = callback;
 
-        self.subscriptions.add_subscription(ConsumerRegistration { address: address.clone(), extent: extent }, Box::new(move |change| {
+        let filter = self.adaptive.get().map(|settings| Rc::new(RefCell::new(AdaptiveFilter::new(&address, extent, settings))));
+
+        self.subscriptions.add_subscription(ConsumerRegistration { address: address.clone(), extent, filter }, Box::new(move |change| {
             // The change we get from the subscription will have an address relative to the root of the tree
             // Make the subscription change relative to the address that was subscribed to 
             let maybe_relative_change = change.relative_to(&address);
@@ -66,7 +76,12 @@ pub struct ImmediatePublisher {
     ///
     /// Subscriptions for this publisher
     ///
-    subscriptions: Rc<SubscriptionManager<ConsumerRegistration>>
+    subscriptions: Rc<SubscriptionManager<ConsumerRegistration>>,
+
+    ///
+    /// The settings for the adaptive filters to attach to new subscriptions, if they're enabled
+    ///
+    adaptive: Rc<Cell<Option<AdaptiveFilterSettings>>>
 }
 
 impl ImmediatePublisher {
@@ -74,14 +89,30 @@ impl ImmediatePublisher {
     /// Creates a new immediate publisher
     ///
     pub fn new() -> Box<ImmediatePublisher> {
-        Box::new(ImmediatePublisher { subscriptions: Rc::new(SubscriptionManager::new()) })
+        Box::new(ImmediatePublisher { subscriptions: Rc::new(SubscriptionManager::new()), adaptive: Rc::new(Cell::new(None)) })
     }
 
     ///
     /// Creates a consumer that will receive notifications from this publisher
     ///
     pub fn create_consumer(&self) -> ConsumerRef {
-        Box::new(ImmediateConsumer { subscriptions: self.subscriptions.clone() })
+        Box::new(ImmediateConsumer { subscriptions: self.subscriptions.clone(), adaptive: self.adaptive.clone() })
+    }
+
+    ///
+    /// Attaches an adaptive filter to the subscriptions made after this is called
+    ///
+    pub fn enable_adaptive_filtering(&mut self, settings: AdaptiveFilterSettings) {
+        self.adaptive.set(Some(settings));
+    }
+
+    ///
+    /// Retrieves the statistics for each subscription that has an adaptive filter
+    ///
+    pub fn adaptive_filter_stats(&self) -> Vec<(TreeAddress, AdaptiveFilterStats)> {
+        self.subscriptions.subscription_data().iter()
+            .filter_map(|registration| registration.filter.as_ref().map(|filter| { let filter = filter.borrow(); (filter.address().clone(), filter.stats()) }))
+            .collect()
     }
 }
 
@@ -90,8 +121,13 @@ impl Publisher for ImmediatePublisher {
     /// Publishes a change to the consumers of this component
     ///
     fn publish(&mut self, change: TreeChange) {
+        let flat = if self.adaptive.get().is_some() { flatten_address(change.address()) } else { vec![] };
+
         self.subscriptions.call_subscriptions(&|registration| {
-            change.applies_to(&registration.address, &registration.extent).unwrap_or(false)
+            match registration.filter {
+                Some(ref filter)    => filter.borrow_mut().should_deliver(&change, &flat),
+                None                => change.applies_to(&registration.address, &registration.extent).unwrap_or(false)
+            }
         }, &change);
     }
 }
@@ -101,6 +137,7 @@ mod immediate_publisher_tests {
     use std::cell::*;
     use std::rc::*;
 
+    use super::super::super::tree::*;
     use super::super::super::component::*;
     use super::*;
 
@@ -0,0 +1,330 @@
+//
+//   Copyright 2016 Andrew Hunter
+//
+//   Licensed under the Apache License, Version 2.0 (the "License");
+//   you may not use this file except in compliance with the License.
+//   You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+//   Unless required by applicable law or agreed to in writing, software
+//   distributed under the License is distributed on an "AS IS" BASIS,
+//   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//   See the License for the specific language governing permissions and
+//   limitations under the License.
+//
+
+//!
+//! # Interest sets
+//!
+//! A consumer at the far end of a slow connection usually only cares about part of the tree. An
+//! `InterestPublisher` sits in front of the publisher that sends changes to such a consumer and only passes on
+//! the changes that affect the parts of the tree it has declared an interest in.
+//!
+//! Interests can be added and removed at any time using an `InterestControl`. When an interest is added, the
+//! part of the tree that it newly covers is sent as a single change (a backfill), so the consumer doesn't need
+//! to be sent the whole tree when it connects.
+//!
+//! Changes whose relationship to an interest can't be determined (for instance, when an interest uses tags and
+//! the change uses indexes) are always passed on.
+//!
+
+use std::rc::*;
+use std::cell::*;
+
+use super::super::tree::*;
+use super::component::*;
+
+///
+/// The parts of a tree that a consumer is interested in
+///
+#[derive(Clone)]
+pub struct InterestSet {
+    interests: Vec<(TreeAddress, TreeExtent)>
+}
+
+impl InterestSet {
+    ///
+    /// Creates an empty interest set
+    ///
+    pub fn new() -> InterestSet {
+        InterestSet { interests: vec![] }
+    }
+
+    ///
+    /// Adds an interest, returning false if it was already in this set
+    ///
+    pub fn add(&mut self, address: TreeAddress, extent: TreeExtent) -> bool {
+        if self.interests.iter().any(|(existing_address, existing_extent)| *existing_address == address && *existing_extent == extent) {
+            false
+        } else {
+            self.interests.push((address, extent));
+            true
+        }
+    }
+
+    ///
+    /// Removes an interest, returning false if it wasn't in this set
+    ///
+    pub fn remove(&mut self, address: &TreeAddress, extent: &TreeExtent) -> bool {
+        let before = self.interests.len();
+        self.interests.retain(|(existing_address, existing_extent)| existing_address != address || existing_extent != extent);
+
+        self.interests.len() != before
+    }
+
+    ///
+    /// The interests in this set
+    ///
+    pub fn interests(&self) -> &Vec<(TreeAddress, TreeExtent)> {
+        &self.interests
+    }
+
+    ///
+    /// True if a change might affect part of the tree covered by this set
+    ///
+    /// This is true if it's not possible to tell if the change applies to one of the interests.
+    ///
+    pub fn is_interested_in(&self, change: &TreeChange) -> bool {
+        self.interests.iter().any(|(address, extent)| change.applies_to(address, extent).unwrap_or(true))
+    }
+}
+
+impl Default for InterestSet {
+    fn default() -> InterestSet {
+        InterestSet::new()
+    }
+}
+
+///
+/// The state shared between an interest publisher and its controls
+///
+struct InterestState {
+    /// Where changes are sent
+    target: PublisherRef,
+
+    /// The tree as it is after all of the changes published so far
+    tree: TreeRef,
+
+    /// The interests of the target
+    interests: InterestSet,
+
+    /// The number of changes that have been sent to the target (including backfills)
+    sent: usize
+}
+
+impl InterestState {
+    ///
+    /// Sends the current state of the part of the tree covered by an interest to the target
+    ///
+    fn backfill(&mut self, address: &TreeAddress, extent: &TreeExtent) {
+        let node = match address.lookup_index(&self.tree) {
+            Some(node)  => node,
+            None        => return
+        };
+
+        let replacement = match *extent {
+            TreeExtent::ThisNode    => TreeReplacement::NewValue(node.get_tag().to_string(), node.get_value().clone()),
+            TreeExtent::Children    => {
+                let children: Vec<TreeRef> = node.iter_children().map(|child| child.with_child_node(None)).collect();
+                TreeReplacement::NewNode(node.with_child_node(None).with_children(&children))
+            },
+            TreeExtent::SubTree     => TreeReplacement::NewNode(node.with_sibling_node(None))
+        };
+
+        self.sent += 1;
+        self.target.publish(TreeChange::new(address, &replacement));
+    }
+}
+
+///
+/// A publisher that only passes on the changes that a consumer is interested in
+///
+pub struct InterestPublisher {
+    state: Rc<RefCell<InterestState>>
+}
+
+///
+/// Changes the interests of an `InterestPublisher`
+///
+#[derive(Clone)]
+pub struct InterestControl {
+    state: Rc<RefCell<InterestState>>
+}
+
+impl InterestPublisher {
+    ///
+    /// Creates an interest publisher with no interests
+    ///
+    pub fn new(target: PublisherRef) -> Box<InterestPublisher> {
+        let state = InterestState { target, tree: "empty".to_tree_node(), interests: InterestSet::new(), sent: 0 };
+
+        Box::new(InterestPublisher { state: Rc::new(RefCell::new(state)) })
+    }
+
+    ///
+    /// Retrieves an object that can be used to change the interests of this publisher
+    ///
+    pub fn get_control(&self) -> InterestControl {
+        InterestControl { state: self.state.clone() }
+    }
+}
+
+impl InterestControl {
+    ///
+    /// Adds an interest, sending the current state of the part of the tree that it covers
+    ///
+    pub fn add_interest<TAddress: ToTreeAddress>(&self, address: &TAddress, extent: TreeExtent) {
+        let address     = address.to_tree_address();
+        let mut state   = self.state.borrow_mut();
+
+        if state.interests.add(address.clone(), extent) {
+            state.backfill(&address, &extent);
+        }
+    }
+
+    ///
+    /// Removes an interest
+    ///
+    pub fn remove_interest<TAddress: ToTreeAddress>(&self, address: &TAddress, extent: TreeExtent) {
+        self.state.borrow_mut().interests.remove(&address.to_tree_address(), &extent);
+    }
+
+    ///
+    /// The current interests
+    ///
+    pub fn interests(&self) -> InterestSet {
+        self.state.borrow().interests.clone()
+    }
+
+    ///
+    /// The number of changes that have been sent on, including backfills
+    ///
+    pub fn changes_sent(&self) -> usize {
+        self.state.borrow().sent
+    }
+}
+
+impl Publisher for InterestPublisher {
+    ///
+    /// Publishes a change to the consumers of this component
+    ///
+    fn publish(&mut self, change: TreeChange) {
+        let mut state = self.state.borrow_mut();
+
+        state.tree = change.apply(&state.tree);
+
+        if state.interests.is_interested_in(&change) {
+            state.sent += 1;
+            state.target.publish(change);
+        }
+    }
+}
+
+#[cfg(test)]
+mod interest_tests {
+    use std::rc::*;
+    use std::cell::*;
+
+    use super::super::super::tree::*;
+    use super::super::super::component::*;
+    use super::super::output_tree_publisher::*;
+    use super::*;
+
+    ///
+    /// Publisher that records the number of nodes in each change it receives
+    ///
+    struct CountingPublisher {
+        change_sizes: Rc<RefCell<Vec<usize>>>
+    }
+
+    fn count_nodes(tree: &TreeRef) -> usize {
+        1 + tree.iter_children().map(|child| count_nodes(&child)).sum::<usize>()
+    }
+
+    impl Publisher for CountingPublisher {
+        fn publish(&mut self, change: TreeChange) {
+            let size = match *change.replacement() {
+                TreeReplacement::NewNode(ref node)  => count_nodes(node),
+                _                                   => 1
+            };
+
+            self.change_sizes.borrow_mut().push(size);
+        }
+    }
+
+    fn large_tree() -> TreeRef {
+        let data: Vec<TreeRef> = (0..100).map(|index| ("item", index).to_tree_node()).collect();
+        tree!("root", tree!("dashboard", ("status", "ok"), ("load", 3)), ("data", ()).to_tree_node().with_children(&data))
+    }
+
+    #[test]
+    fn initial_transfer_only_covers_interests() {
+        let change_sizes    = Rc::new(RefCell::new(vec![]));
+        let mut publisher   = InterestPublisher::new(Box::new(CountingPublisher { change_sizes: change_sizes.clone() }));
+        let control         = publisher.get_control();
+
+        publisher.publish(TreeChange::new(&(), &large_tree()));
+        assert!(change_sizes.borrow().is_empty());
+
+        control.add_interest(&"dashboard", TreeExtent::SubTree);
+        assert!(*change_sizes.borrow() == vec![3]);
+        assert!(control.changes_sent() == 1);
+    }
+
+    #[test]
+    fn changes_outside_interests_are_not_sent() {
+        let output          = OutputTreePublisher::new();
+        let reader          = output.get_tree_reader();
+        let mut publisher   = InterestPublisher::new(output);
+        let control         = publisher.get_control();
+
+        publisher.publish(TreeChange::new(&(), &large_tree()));
+        control.add_interest(&"dashboard", TreeExtent::SubTree);
+
+        publisher.publish(TreeChange::new(&("data", 4), &("item", 400)));
+        assert!(control.changes_sent() == 1);
+
+        publisher.publish(TreeChange::new(&("dashboard", "load"), &("load", 7)));
+        assert!(control.changes_sent() == 2);
+        assert!(reader().get_child_at("dashboard").get_child_at("load").get_value().to_int(0) == 7);
+        assert!(reader().lookup_child_with_tag("data").is_none());
+    }
+
+    #[test]
+    fn adding_interest_backfills_new_region() {
+        let change_sizes    = Rc::new(RefCell::new(vec![]));
+        let mut publisher   = InterestPublisher::new(Box::new(CountingPublisher { change_sizes: change_sizes.clone() }));
+        let control         = publisher.get_control();
+
+        publisher.publish(TreeChange::new(&(), &large_tree()));
+        control.add_interest(&"dashboard", TreeExtent::SubTree);
+        publisher.publish(TreeChange::new(&("data", 4), &("item", 400)));
+
+        // Adding the data region sends it (but not the dashboard again)
+        control.add_interest(&"data", TreeExtent::SubTree);
+        assert!(*change_sizes.borrow() == vec![3, 101]);
+
+        // Adding the same interest again doesn't send anything
+        control.add_interest(&"data", TreeExtent::SubTree);
+        assert!(change_sizes.borrow().len() == 2);
+
+        // Once removed, data changes are no longer sent
+        control.remove_interest(&"data", TreeExtent::SubTree);
+        publisher.publish(TreeChange::new(&("data", 5), &("item", 500)));
+        assert!(change_sizes.borrow().len() == 2);
+    }
+
+    #[test]
+    fn unknown_applicability_is_sent() {
+        let mut publisher   = InterestPublisher::new(OutputTreePublisher::new());
+        let control         = publisher.get_control();
+
+        publisher.publish(TreeChange::new(&(), &large_tree()));
+        control.add_interest(&"dashboard", TreeExtent::SubTree);
+
+        // Whether child 1 is the dashboard can't be told from the addresses alone
+        publisher.publish(TreeChange::new(&(1, 0), &("item", 1)));
+        assert!(control.changes_sent() == 2);
+    }
+}
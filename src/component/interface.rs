@@ -0,0 +1,501 @@
+//
+//   Copyright 2016 Andrew Hunter
+//
+//   Licensed under the Apache License, Version 2.0 (the "License");
+//   you may not use this file except in compliance with the License.
+//   You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+//   Unless required by applicable law or agreed to in writing, software
+//   distributed under the License is distributed on an "AS IS" BASIS,
+//   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//   See the License for the specific language governing permissions and
+//   limitations under the License.
+//
+
+//!
+//! # Tree interfaces
+//!
+//! A `TreeInterface` describes the contract between a component and the code that uses it: a set of named inputs
+//! and outputs, each with an address in a hub and the type of the value found there. The same interface is bound
+//! to a hub on both sides:
+//!
+//! * `bind_server()` gives the component an `InterfaceServer`, which can watch its inputs and publish its outputs.
+//!   Binding the server declares the shapes of the inputs and outputs with the hub.
+//! * `bind_client()` gives the user an `InterfaceClient`, which can send values to the inputs and watch the
+//!   outputs. Binding the client checks that something has been bound to every input and output, and that the
+//!   shapes it expects are compatible with those declared there.
+//!
+//! Inputs and outputs are identified by name. Using a name that isn't part of the interface, or a type other
+//! than the one the interface was defined with, is an error.
+//!
+//! ```
+//! # #[macro_use] extern crate tametree;
+//! # use tametree::prelude::*;
+//! # use tametree::component::interface::*;
+//! # fn main() {
+//! let mut hub     = Hub::new();
+//! let interface   = TreeInterface::new("doubler")
+//!     .with_input::<i32, _>("value", &"value")
+//!     .with_output::<i32, _>("doubled", &"doubled");
+//!
+//! let mut server  = interface.bind_server(&mut hub).unwrap();
+//! let mut doubled = server.output::<i32>("doubled").unwrap();
+//! server.on("value", move |value: i32| doubled.publish(&(value * 2))).unwrap();
+//!
+//! let mut client  = interface.bind_client(&mut hub).unwrap();
+//! let result      = client.receiver::<i32>("doubled").unwrap();
+//!
+//! client.send("value", &21).unwrap();
+//! hub.flush();
+//! assert!(result() == Some(42));
+//! # }
+//! ```
+//!
+
+use std::rc::*;
+use std::cell::*;
+use std::fmt;
+use std::any::TypeId;
+use std::marker::PhantomData;
+
+use rustc_serialize::*;
+
+use super::super::tree::*;
+use super::component::*;
+use super::components_are_functions::*;
+use super::hub::*;
+
+///
+/// An input or output of an interface
+///
+#[derive(Clone)]
+struct InterfacePort {
+    name: String,
+    address: TreeAddress,
+    shape: TreeShape,
+    type_id: TypeId
+}
+
+///
+/// Describes the inputs and outputs of a component
+///
+#[derive(Clone)]
+pub struct TreeInterface {
+    name: String,
+    inputs: Vec<InterfacePort>,
+    outputs: Vec<InterfacePort>
+}
+
+///
+/// Error returned when an interface is used incorrectly or can't be bound to a hub
+///
+#[derive(Clone, PartialEq)]
+pub enum InterfaceError {
+    /// The interface has no input or output with this name
+    UnknownPort(String),
+
+    /// The input or output with this name was used with a different type to the one in the interface
+    WrongType(String),
+
+    /// Nothing has been bound to the input or output with this name
+    MissingRoute(String),
+
+    /// The shape of the input or output with this name doesn't match the shape declared in the hub
+    ShapeMismatch(String, ShapeMismatch)
+}
+
+impl fmt::Display for InterfaceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            InterfaceError::UnknownPort(ref name)               => write!(f, "'{}' is not an input or output of the interface", name),
+            InterfaceError::WrongType(ref name)                 => write!(f, "'{}' was used with a different type to the one in the interface", name),
+            InterfaceError::MissingRoute(ref name)              => write!(f, "nothing is bound to '{}'", name),
+            InterfaceError::ShapeMismatch(ref name, ref shapes) => write!(f, "'{}' has the wrong shape: {}", name, shapes)
+        }
+    }
+}
+
+impl fmt::Debug for InterfaceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl TreeInterface {
+    ///
+    /// Creates an interface with no inputs or outputs
+    ///
+    pub fn new(name: &str) -> TreeInterface {
+        TreeInterface { name: name.to_string(), inputs: vec![], outputs: vec![] }
+    }
+
+    ///
+    /// The name of this interface
+    ///
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    ///
+    /// Creates a port for a type (whose shape is that of its default value)
+    ///
+    fn port<T: 'static + Encodable + Default>(name: &str, address: TreeAddress) -> InterfacePort {
+        InterfacePort { name: name.to_string(), address, shape: shape_of::<T>(), type_id: TypeId::of::<T>() }
+    }
+
+    ///
+    /// Adds an input of a particular type, read by the server from an address of the hub
+    ///
+    pub fn with_input<T: 'static + Encodable + Default, TAddress: ToTreeAddress>(mut self, name: &str, address: &TAddress) -> TreeInterface {
+        self.inputs.push(Self::port::<T>(name, address.to_tree_address()));
+        self
+    }
+
+    ///
+    /// Adds an output of a particular type, published by the server to an address of the hub
+    ///
+    pub fn with_output<T: 'static + Encodable + Default, TAddress: ToTreeAddress>(mut self, name: &str, address: &TAddress) -> TreeInterface {
+        self.outputs.push(Self::port::<T>(name, address.to_tree_address()));
+        self
+    }
+
+    ///
+    /// Replaces the shape of an input or output
+    ///
+    /// The shape is normally found from the default value of the type, which doesn't describe the elements of
+    /// collections. This can be used to provide a more complete shape.
+    ///
+    pub fn with_shape(mut self, name: &str, shape: TreeShape) -> TreeInterface {
+        for port in self.inputs.iter_mut().chain(self.outputs.iter_mut()) {
+            if port.name == name {
+                port.shape = shape.clone();
+            }
+        }
+
+        self
+    }
+
+    ///
+    /// Binds the component side of this interface to a hub, declaring the shapes of its inputs and outputs
+    ///
+    pub fn bind_server(&self, hub: &mut Hub) -> Result<InterfaceServer, InterfaceError> {
+        let reads       = self.inputs.iter().map(|port| (port.address.clone(), port.shape.clone())).collect();
+        let publishes   = self.outputs.iter().map(|port| (port.address.clone(), port.shape.clone())).collect();
+
+        hub.declare_shapes(reads, publishes).map_err(|mismatch| {
+            let name = self.inputs.iter().chain(self.outputs.iter())
+                .find(|port| port.address == mismatch.address)
+                .map(|port| port.name.clone())
+                .unwrap_or_default();
+
+            InterfaceError::ShapeMismatch(name, mismatch)
+        })?;
+
+        Ok(InterfaceServer { ports: InterfacePorts::bind(hub, &self.inputs, &self.outputs) })
+    }
+
+    ///
+    /// Binds the user side of this interface to a hub, checking that a server has been bound to every input and
+    /// output with compatible shapes
+    ///
+    pub fn bind_client(&self, hub: &mut Hub) -> Result<InterfaceClient, InterfaceError> {
+        // The client publishes the inputs, which the server must be able to read
+        for port in self.inputs.iter() {
+            let read = hub.shapes_read_from(&port.address);
+            if read.is_empty() {
+                return Err(InterfaceError::MissingRoute(port.name.clone()));
+            }
+
+            for shape in read {
+                Self::check_shapes(port, shape.compatible_with(&port.shape))?;
+            }
+        }
+
+        // The client reads the outputs, which must contain what it expects
+        for port in self.outputs.iter() {
+            let published = hub.shapes_published_to(&port.address);
+            if published.is_empty() {
+                return Err(InterfaceError::MissingRoute(port.name.clone()));
+            }
+
+            for shape in published {
+                Self::check_shapes(port, port.shape.compatible_with(shape))?;
+            }
+        }
+
+        Ok(InterfaceClient { ports: InterfacePorts::bind(hub, &self.outputs, &self.inputs) })
+    }
+
+    ///
+    /// Turns an incompatible shape into an error
+    ///
+    fn check_shapes(port: &InterfacePort, compatibility: ShapeCompatibility) -> Result<(), InterfaceError> {
+        match compatibility {
+            ShapeCompatibility::Incompatible { .. } => Err(InterfaceError::ShapeMismatch(port.name.clone(), ShapeMismatch { address: port.address.clone(), compatibility })),
+            _                                       => Ok(())
+        }
+    }
+}
+
+///
+/// The consumers and publishers for one side of an interface
+///
+struct InterfacePorts {
+    /// The ports this side reads from
+    reading: Vec<(InterfacePort, ConsumerRef)>,
+
+    /// The ports this side publishes to
+    publishing: Vec<(InterfacePort, Rc<RefCell<PublisherRef>>)>
+}
+
+impl InterfacePorts {
+    fn bind(hub: &mut Hub, reading: &[InterfacePort], publishing: &[InterfacePort]) -> InterfacePorts {
+        InterfacePorts {
+            reading:    reading.iter().map(|port| (port.clone(), hub.read_from(&port.address))).collect(),
+            publishing: publishing.iter().map(|port| (port.clone(), Rc::new(RefCell::new(hub.publish_to(&port.address))))).collect()
+        }
+    }
+
+    ///
+    /// Checks that a port has the expected type
+    ///
+    fn check_type<T: 'static>(port: &InterfacePort) -> Result<(), InterfaceError> {
+        if port.type_id == TypeId::of::<T>() { Ok(()) } else { Err(InterfaceError::WrongType(port.name.clone())) }
+    }
+
+    ///
+    /// Finds the consumer for a port that this side reads
+    ///
+    fn consumer<T: 'static>(&mut self, name: &str) -> Result<&mut ConsumerRef, InterfaceError> {
+        let (port, consumer) = self.reading.iter_mut().find(|(port, _)| port.name == name).ok_or_else(|| InterfaceError::UnknownPort(name.to_string()))?;
+        Self::check_type::<T>(port)?;
+
+        Ok(consumer)
+    }
+
+    ///
+    /// Creates a typed publisher for a port that this side publishes
+    ///
+    fn publisher<T: 'static + ToTreeNode>(&self, name: &str) -> Result<InterfacePublisher<T>, InterfaceError> {
+        let (port, publisher) = self.publishing.iter().find(|(port, _)| port.name == name).ok_or_else(|| InterfaceError::UnknownPort(name.to_string()))?;
+        Self::check_type::<T>(port)?;
+
+        Ok(InterfacePublisher { publisher: publisher.clone(), phantom: PhantomData })
+    }
+
+    ///
+    /// Calls a function with the value of a port whenever it changes
+    ///
+    fn on<T: 'static + DecodeFromTreeNode, TCallback: 'static + FnMut(T)>(&mut self, name: &str, callback: TCallback) -> Result<(), InterfaceError> {
+        let consumer        = self.consumer::<T>(name)?;
+        let mut callback    = callback;
+        let mut tree        = "empty".to_tree_node();
+
+        consumer.subscribe(TreeAddress::Here, TreeExtent::SubTree, Box::new(move |change| {
+            tree = change.apply(&tree);
+
+            if let Ok(value) = T::new_from_tree(&tree) {
+                callback(value);
+            }
+        }));
+
+        Ok(())
+    }
+
+    ///
+    /// Creates a function that returns the latest value of a port
+    ///
+    fn receiver<T: 'static + DecodeFromTreeNode>(&mut self, name: &str) -> Result<RecvFn<T>, InterfaceError> {
+        Ok(self.consumer::<T>(name)?.get_receiver())
+    }
+}
+
+///
+/// Publishes values of a particular type to an input or output of an interface
+///
+pub struct InterfacePublisher<T: ToTreeNode> {
+    publisher: Rc<RefCell<PublisherRef>>,
+    phantom: PhantomData<T>
+}
+
+impl<T: ToTreeNode> InterfacePublisher<T> {
+    ///
+    /// Publishes a new value
+    ///
+    pub fn publish(&mut self, value: &T) {
+        self.publisher.borrow_mut().publish(TreeChange::new(&(), value));
+    }
+}
+
+///
+/// The component side of an interface bound to a hub: reads the inputs and publishes the outputs
+///
+pub struct InterfaceServer {
+    ports: InterfacePorts
+}
+
+impl InterfaceServer {
+    ///
+    /// Calls a function with the value of an input whenever it changes
+    ///
+    pub fn on<T: 'static + DecodeFromTreeNode, TCallback: 'static + FnMut(T)>(&mut self, input: &str, callback: TCallback) -> Result<(), InterfaceError> {
+        self.ports.on(input, callback)
+    }
+
+    ///
+    /// Creates a function that returns the latest value of an input
+    ///
+    pub fn input<T: 'static + DecodeFromTreeNode>(&mut self, input: &str) -> Result<RecvFn<T>, InterfaceError> {
+        self.ports.receiver(input)
+    }
+
+    ///
+    /// Creates a publisher for an output
+    ///
+    pub fn output<T: 'static + ToTreeNode>(&self, output: &str) -> Result<InterfacePublisher<T>, InterfaceError> {
+        self.ports.publisher(output)
+    }
+}
+
+///
+/// The user side of an interface bound to a hub: sends values to the inputs and reads the outputs
+///
+pub struct InterfaceClient {
+    ports: InterfacePorts
+}
+
+impl InterfaceClient {
+    ///
+    /// Sends a new value to an input
+    ///
+    pub fn send<T: 'static + ToTreeNode>(&mut self, input: &str, value: &T) -> Result<(), InterfaceError> {
+        self.ports.publisher::<T>(input)?.publish(value);
+        Ok(())
+    }
+
+    ///
+    /// Creates a publisher for an input
+    ///
+    pub fn input<T: 'static + ToTreeNode>(&self, input: &str) -> Result<InterfacePublisher<T>, InterfaceError> {
+        self.ports.publisher(input)
+    }
+
+    ///
+    /// Calls a function with the value of an output whenever it changes
+    ///
+    pub fn on<T: 'static + DecodeFromTreeNode, TCallback: 'static + FnMut(T)>(&mut self, output: &str, callback: TCallback) -> Result<(), InterfaceError> {
+        self.ports.on(output, callback)
+    }
+
+    ///
+    /// Creates a function that returns the latest value of an output
+    ///
+    pub fn receiver<T: 'static + DecodeFromTreeNode>(&mut self, output: &str) -> Result<RecvFn<T>, InterfaceError> {
+        self.ports.receiver(output)
+    }
+}
+
+#[cfg(test)]
+mod interface_tests {
+    use std::rc::*;
+    use std::cell::*;
+
+    use super::super::super::component::*;
+    use super::*;
+
+    tree_struct! {
+        #[derive(Default)]
+        struct Operand {
+            value: i32
+        }
+    }
+
+    tree_struct! {
+        #[derive(Default)]
+        struct TextOperand {
+            value: String
+        }
+    }
+
+    fn calculator() -> TreeInterface {
+        TreeInterface::new("calculator")
+            .with_input::<Operand, _>("a", &("calculator", "a"))
+            .with_input::<Operand, _>("b", &("calculator", "b"))
+            .with_output::<i32, _>("sum", &("calculator", "sum"))
+    }
+
+    ///
+    /// Binds a server for the calculator interface that adds its inputs together
+    ///
+    fn bind_adder(hub: &mut Hub) {
+        let mut server  = calculator().bind_server(hub).unwrap();
+        let sum         = Rc::new(RefCell::new(server.output::<i32>("sum").unwrap()));
+        let a           = Rc::new(Cell::new(0));
+        let b           = Rc::new(Cell::new(0));
+
+        let (a_sum, a_a, a_b) = (sum.clone(), a.clone(), b.clone());
+        server.on("a", move |operand: Operand| { a_a.set(operand.value); a_sum.borrow_mut().publish(&(a_a.get() + a_b.get())); }).unwrap();
+
+        let (b_sum, b_a, b_b) = (sum.clone(), a.clone(), b.clone());
+        server.on("b", move |operand: Operand| { b_b.set(operand.value); b_sum.borrow_mut().publish(&(b_a.get() + b_b.get())); }).unwrap();
+    }
+
+    #[test]
+    fn client_and_server_communicate() {
+        let mut hub = Hub::new();
+        bind_adder(&mut hub);
+
+        let mut client  = calculator().bind_client(&mut hub).unwrap();
+        let sum         = client.receiver::<i32>("sum").unwrap();
+        let sums        = Rc::new(RefCell::new(vec![]));
+        let their_sums  = sums.clone();
+        client.on("sum", move |sum: i32| their_sums.borrow_mut().push(sum)).unwrap();
+
+        client.send("a", &Operand { value: 3 }).unwrap();
+        client.send("b", &Operand { value: 4 }).unwrap();
+        hub.flush();
+
+        assert!(sum() == Some(7));
+        assert!(*sums.borrow() == vec![3, 7]);
+    }
+
+    #[test]
+    fn names_and_types_are_checked() {
+        let mut hub = Hub::new();
+        bind_adder(&mut hub);
+
+        let mut client = calculator().bind_client(&mut hub).unwrap();
+
+        assert!(client.send("c", &Operand { value: 3 }) == Err(InterfaceError::UnknownPort("c".to_string())));
+        assert!(client.send("a", &3) == Err(InterfaceError::WrongType("a".to_string())));
+        assert!(client.receiver::<String>("sum").err() == Some(InterfaceError::WrongType("sum".to_string())));
+    }
+
+    #[test]
+    fn missing_route_is_reported_by_name() {
+        let mut hub = Hub::new();
+
+        assert!(calculator().bind_client(&mut hub).err() == Some(InterfaceError::MissingRoute("a".to_string())));
+    }
+
+    #[test]
+    fn type_mismatch_is_found_at_bind_time() {
+        let mut hub = Hub::new();
+        bind_adder(&mut hub);
+
+        let text_calculator = TreeInterface::new("calculator")
+            .with_input::<Operand, _>("a", &("calculator", "a"))
+            .with_input::<TextOperand, _>("b", &("calculator", "b"))
+            .with_output::<i32, _>("sum", &("calculator", "sum"));
+
+        match text_calculator.bind_client(&mut hub).err() {
+            Some(InterfaceError::ShapeMismatch(name, mismatch)) => {
+                assert!(name == "b");
+                assert!(mismatch.address == ("calculator", "b").to_tree_address());
+            },
+            _ => panic!("expected a shape mismatch")
+        }
+    }
+}
@@ -0,0 +1,505 @@
+//
+//   Copyright 2016 Andrew Hunter
+//
+//   Licensed under the Apache License, Version 2.0 (the "License");
+//   you may not use this file except in compliance with the License.
+//   You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+//   Unless required by applicable law or agreed to in writing, software
+//   distributed under the License is distributed on an "AS IS" BASIS,
+//   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//   See the License for the specific language governing permissions and
+//   limitations under the License.
+//
+
+//!
+//! # Snapshot-consistent joins
+//!
+//! When two inputs of a component are both derived from the same upstream source, the component can see one
+//! input that reflects the latest upstream change alongside another that doesn't yet. The result is a glitch: an
+//! output computed from a combination of values that never existed together (this is the 'diamond problem').
+//!
+//! A `Join` wraps a component and only passes it a combined view of its inputs once they all reflect the same
+//! upstream generation. Each input says which generation it reflects with a child tagged `__generation`
+//! (`GENERATION_TAG`), which should be copied along from the upstream source by whatever computes the input. The
+//! generation can be an integer, or a string in the format used by watermarks (see `tametree::tree::watermark`).
+//!
+//! The join buffers the versions of each input that have arrived but can't be used yet, so an input that runs
+//! ahead of the others doesn't lose the version that matches them. The buffer for each input is bounded: when it
+//! is full, its oldest version is dropped.
+//!
+//! The view passed to the wrapped component has a child for each input, in the order they were added, along with
+//! a child tagged `__stale` (`JOIN_STALE_TAG`) that's normally false. Two settings relax the consistency rule:
+//!
+//! * The staleness window allows the inputs to be up to a number of generations apart.
+//! * The timeout stops a silent input from holding up the others forever: when an input gets more than this number
+//!   of generations ahead of the last view, the latest version of every input is passed on with `__stale` set to
+//!   true.
+//!
+//! ```
+//! # use tametree::prelude::*;
+//! # use tametree::component::join::*;
+//! let mut hub = Hub::new();
+//! let join    = Join::new(component_fn(|view: &TreeRef| view.clone()))
+//!     .with_input(&"a")
+//!     .with_input(&"b");
+//!
+//! hub.add_component(join, &(), &"joined");
+//! ```
+//!
+
+use std::rc::*;
+use std::cell::*;
+use std::collections::VecDeque;
+
+use super::super::tree::*;
+use super::component::*;
+use super::immediate_publisher::*;
+
+///
+/// The tag of the child of each input that indicates the generation it reflects
+///
+pub const GENERATION_TAG: &str = "__generation";
+
+///
+/// The tag of the child of a joined view that indicates whether or not it was generated by the timeout
+///
+pub const JOIN_STALE_TAG: &str = "__stale";
+
+///
+/// Reads the generation of an input
+///
+fn generation_of(input: &TreeRef) -> Option<u64> {
+    let generation = input.get_child_ref_at(GENERATION_TAG)?;
+
+    match *generation.get_value() {
+        TreeValue::Int(generation)      => if generation >= 0 { Some(generation as u64) } else { None },
+        TreeValue::String(ref string)   => string.parse().ok(),
+        _                               => None
+    }
+}
+
+///
+/// Wraps a component so that it's only passed views of its inputs that reflect the same upstream generation
+///
+pub struct Join<TComponent: ConvertToComponent> {
+    component: TComponent,
+    inputs: Vec<TreeAddress>,
+    staleness: u64,
+    timeout: Option<u64>,
+    max_buffered: usize,
+    buffered: Rc<Cell<usize>>
+}
+
+impl<TComponent: ConvertToComponent> Join<TComponent> {
+    ///
+    /// Creates a join with no inputs that wraps a component
+    ///
+    /// By default, every input must reflect exactly the same generation, there's no timeout and up to 16 versions of
+    /// each input are buffered.
+    ///
+    pub fn new(component: TComponent) -> Join<TComponent> {
+        Join { component, inputs: vec![], staleness: 0, timeout: None, max_buffered: 16, buffered: Rc::new(Cell::new(0)) }
+    }
+
+    ///
+    /// Adds an input, at an address relative to the tree the join reads from
+    ///
+    pub fn with_input<TAddress: ToTreeAddress>(mut self, address: &TAddress) -> Join<TComponent> {
+        self.inputs.push(address.to_tree_address());
+        self
+    }
+
+    ///
+    /// Allows the inputs in a view to reflect generations up to this many apart
+    ///
+    pub fn with_staleness_window(mut self, generations: u64) -> Join<TComponent> {
+        self.staleness = generations;
+        self
+    }
+
+    ///
+    /// Passes on a stale view when an input gets more than this many generations ahead of the last view
+    ///
+    pub fn with_timeout(mut self, generations: u64) -> Join<TComponent> {
+        self.timeout = Some(generations);
+        self
+    }
+
+    ///
+    /// Sets the maximum number of versions of each input that are buffered
+    ///
+    pub fn with_buffer_limit(mut self, max_buffered: usize) -> Join<TComponent> {
+        self.max_buffered = max_buffered.max(1);
+        self
+    }
+
+    ///
+    /// Retrieves a function that returns the number of input versions that are currently buffered by this join
+    ///
+    pub fn get_buffered_reader(&self) -> Box<dyn Fn() -> usize> {
+        let buffered = self.buffered.clone();
+
+        Box::new(move || buffered.get())
+    }
+}
+
+///
+/// The versions of a single input that have been received
+///
+struct JoinInput {
+    address: TreeAddress,
+
+    /// The generations and versions of this input that might still be used, oldest first
+    buffered: VecDeque<(u64, TreeRef)>,
+
+    /// The most recent version of this input
+    latest: Option<TreeRef>
+}
+
+impl JoinInput {
+    ///
+    /// The most recent version of this input within a range of generations
+    ///
+    fn version_between(&self, oldest: u64, newest: u64) -> Option<&TreeRef> {
+        self.buffered.iter().rev()
+            .find(|(generation, _)| *generation >= oldest && *generation <= newest)
+            .map(|(_, version)| version)
+    }
+
+    ///
+    /// The newest generation of this input that has been received
+    ///
+    fn newest_generation(&self) -> Option<u64> {
+        self.buffered.back().map(|(generation, _)| *generation)
+    }
+}
+
+///
+/// The running state of a join
+///
+struct JoinState {
+    inputs: Vec<JoinInput>,
+    staleness: u64,
+    timeout: Option<u64>,
+    max_buffered: usize,
+
+    /// The tree the join reads from
+    tree: TreeRef,
+
+    /// The generation of the last view that was passed on
+    last_generation: Option<u64>,
+
+    /// Publishes the views to the wrapped component
+    publisher: PublisherRef,
+
+    /// The number of versions buffered across all of the inputs
+    buffered: Rc<Cell<usize>>
+}
+
+impl JoinState {
+    ///
+    /// Updates the tree and the inputs with a change, and passes on a view if one is ready
+    ///
+    fn process(&mut self, change: &TreeChange) {
+        self.tree = change.apply(&self.tree);
+
+        for input in self.inputs.iter_mut() {
+            if !change.applies_to(&input.address, &TreeExtent::SubTree).unwrap_or(true) {
+                continue;
+            }
+
+            let version = match self.tree.get_child_ref_at(input.address.clone()) {
+                Some(version)   => version,
+                None            => continue
+            };
+            input.latest = Some(version.clone());
+
+            if let Some(generation) = generation_of(&version) {
+                // Only the most recent version of each generation is kept
+                if input.newest_generation() == Some(generation) {
+                    input.buffered.pop_back();
+                }
+
+                input.buffered.push_back((generation, version));
+                if input.buffered.len() > self.max_buffered {
+                    input.buffered.pop_front();
+                }
+            }
+        }
+
+        if let Some(generation) = self.consistent_generation() {
+            self.publish_view(generation, false);
+        } else if let Some(generation) = self.timed_out_generation() {
+            self.publish_view(generation, true);
+        }
+
+        self.buffered.set(self.inputs.iter().map(|input| input.buffered.len()).sum());
+    }
+
+    ///
+    /// Finds the newest generation that every input has a version for (within the staleness window), if it's newer
+    /// than the last view
+    ///
+    fn consistent_generation(&self) -> Option<u64> {
+        let mut candidates: Vec<u64> = self.inputs.iter().flat_map(|input| input.buffered.iter().map(|(generation, _)| *generation)).collect();
+        candidates.sort();
+        candidates.dedup();
+
+        candidates.into_iter().rev()
+            .filter(|generation| self.last_generation.map(|last| *generation > last).unwrap_or(true))
+            .find(|generation| self.inputs.iter().all(|input| input.version_between(generation.saturating_sub(self.staleness), *generation).is_some()))
+    }
+
+    ///
+    /// If an input has got too far ahead of the last view, returns its generation
+    ///
+    fn timed_out_generation(&self) -> Option<u64> {
+        let timeout     = self.timeout?;
+        let newest      = self.inputs.iter().filter_map(|input| input.newest_generation()).max()?;
+        let since       = self.last_generation.map(|last| newest.saturating_sub(last)).unwrap_or(newest);
+
+        if since > timeout { Some(newest) } else { None }
+    }
+
+    ///
+    /// Passes a view of the inputs to the wrapped component
+    ///
+    fn publish_view(&mut self, generation: u64, stale: bool) {
+        let staleness   = self.staleness;
+        let mut view    = vec![];
+
+        for input in self.inputs.iter() {
+            // Stale views use the latest version of each input
+            let version = if stale {
+                input.latest.clone()
+            } else {
+                input.version_between(generation.saturating_sub(staleness), generation).cloned()
+            };
+
+            view.push(version.unwrap_or_else(|| "".to_tree_node()));
+        }
+        view.push((JOIN_STALE_TAG, stale).to_tree_node());
+
+        // Versions older than the view can't be used again
+        for input in self.inputs.iter_mut() {
+            let oldest_needed = generation.saturating_sub(staleness);
+            input.buffered.retain(|(version_generation, _)| *version_generation >= oldest_needed);
+        }
+
+        self.last_generation = Some(generation);
+        self.publisher.publish(TreeChange::new(&(), &"join".to_tree_node().with_children(&view)));
+    }
+}
+
+///
+/// Reference to a join, which keeps the wrapped component alive
+///
+struct JoinRef {
+    _component: ComponentRef
+}
+
+impl Component for JoinRef {
+}
+
+impl Drop for JoinRef {
+    fn drop(&mut self) {
+    }
+}
+
+impl<TComponent: ConvertToComponent> ConvertToComponent for Join<TComponent> {
+    ///
+    /// Creates a component that passes consistent views of its inputs to the wrapped component
+    ///
+    fn into_component(self, consumer: ConsumerRef, publisher: PublisherRef) -> ComponentRef {
+        let mut consumer    = consumer;
+        let view            = ImmediatePublisher::new();
+        let component       = self.component.into_component(view.create_consumer(), publisher);
+
+        let mut state       = JoinState {
+            inputs:             self.inputs.into_iter().map(|address| JoinInput { address, buffered: VecDeque::new(), latest: None }).collect(),
+            staleness:          self.staleness,
+            timeout:            self.timeout,
+            max_buffered:       self.max_buffered,
+            tree:               "empty".to_tree_node(),
+            last_generation:    None,
+            publisher:          view,
+            buffered:           self.buffered
+        };
+
+        consumer.subscribe(TreeAddress::Here, TreeExtent::SubTree, Box::new(move |change| {
+            state.process(change);
+        }));
+
+        Rc::new(JoinRef { _component: component })
+    }
+}
+
+#[cfg(test)]
+mod join_tests {
+    use std::rc::*;
+    use std::cell::*;
+
+    use super::super::super::tree::*;
+    use super::super::super::component::*;
+    use super::super::super::testing::*;
+    use super::*;
+
+    ///
+    /// Republishes the changes to an address of one hub to an address of another
+    ///
+    fn relay(from: &mut Hub, from_address: &str, to: &mut Hub, to_address: &str) {
+        let mut publisher = to.publish_to(&to_address);
+
+        from.read_from(&from_address).subscribe(TreeAddress::Here, TreeExtent::SubTree, Box::new(move |change| {
+            publisher.publish(change.clone());
+        }));
+    }
+
+    ///
+    /// A hub containing a component that transforms a value, keeping its generation
+    ///
+    fn branch_hub(transform: fn(i32) -> i32) -> Hub {
+        let mut hub = Hub::new();
+
+        hub.add_component(component_fn(move |input: &TreeRef| {
+            let value       = input.get_child_ref_at("value").map(|value| value.get_value().to_int(0)).unwrap_or(0);
+            let generation  = input.get_child_ref_at(GENERATION_TAG).map(|generation| generation.get_value().to_int(0)).unwrap_or(0);
+
+            tree!("output", ("value", transform(value)), (GENERATION_TAG, generation))
+        }), &"in", &"out");
+
+        hub
+    }
+
+    ///
+    /// Reads the value of an input from a joined view (or a tree containing both inputs)
+    ///
+    fn input_value(view: &TreeRef, input: &str) -> i32 {
+        view.get_child_ref_at((input, "value").to_tree_address()).map(|value| value.get_value().to_int(0)).unwrap_or(0)
+    }
+
+    ///
+    /// Sets up a diamond: a source counting from 1 to 5, branches computing 2n and n+1, and a hub where the two
+    /// branches are combined. Returns the list of (a, b) pairs the combining component saw.
+    ///
+    fn diamond(scheduler: &mut Scheduler, use_join: bool) -> Rc<RefCell<Vec<(i32, i32)>>> {
+        let mut source  = Hub::new();
+        let mut double  = branch_hub(|value| value * 2);
+        let mut add_one = branch_hub(|value| value + 1);
+        let mut joined  = Hub::new();
+        let seen        = Rc::new(RefCell::new(vec![]));
+
+        relay(&mut source, "count", &mut double, "in");
+        relay(&mut source, "count", &mut add_one, "in");
+        relay(&mut double, "out", &mut joined, "a");
+        relay(&mut add_one, "out", &mut joined, "b");
+
+        // The source counts up to 5, one generation per pump
+        let mut feedback = source.publish_to(&"count");
+        source.read_from(&"count").subscribe(TreeAddress::Here, TreeExtent::SubTree, Box::new(move |change| {
+            let count = change.apply(&"count".to_tree_node()).get_child_ref_at("value").map(|value| value.get_value().to_int(0)).unwrap_or(0);
+
+            if count < 5 {
+                feedback.publish(TreeChange::new(&(), &tree!("count", ("value", count+1), (GENERATION_TAG, count+1))));
+            }
+        }));
+        source.publish_to(&"count").publish(TreeChange::new(&(), &tree!("count", ("value", 1), (GENERATION_TAG, 1))));
+
+        let their_seen  = seen.clone();
+        let combine     = component_fn(move |view: &TreeRef| {
+            their_seen.borrow_mut().push((input_value(view, "a"), input_value(view, "b")));
+            view.clone()
+        });
+
+        if use_join {
+            joined.add_component(Join::new(combine).with_input(&"a").with_input(&"b"), &(), &"combined");
+        } else {
+            joined.add_component(combine, &(), &"combined");
+        }
+
+        scheduler.add("source", source);
+        scheduler.add("double", double);
+        scheduler.add("add_one", add_one);
+        scheduler.add("joined", joined);
+
+        seen
+    }
+
+    ///
+    /// Checks that every pair seen by the combining component was computed from the same source value
+    ///
+    fn check_no_glitches(seen: &[(i32, i32)]) -> Result<(), String> {
+        match seen.iter().find(|(a, b)| a/2 + 1 != *b) {
+            Some(glitch)    => Err(format!("glitch: saw {:?}", glitch)),
+            None            => if seen.last() == Some(&(10, 6)) { Ok(()) } else { Err(format!("never saw the final value: {:?}", seen)) }
+        }
+    }
+
+    #[test]
+    fn join_prevents_glitches() {
+        let result = explore_seeds(0..20, 1000, |scheduler| {
+            let seen = diamond(scheduler, true);
+            move || check_no_glitches(&seen.borrow())
+        });
+
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn diamond_without_join_glitches() {
+        let glitched = (0..20).any(|seed| {
+            check_schedule(SchedulePolicy::seeded(seed), 1000, |scheduler| {
+                let seen = diamond(scheduler, false);
+                move || check_no_glitches(&seen.borrow())
+            }).is_err()
+        });
+
+        assert!(glitched);
+    }
+
+    #[test]
+    fn timeout_fires_when_input_goes_silent() {
+        let mut hub     = Hub::new();
+        let mut a       = hub.publish_to(&"a");
+        let mut b       = hub.publish_to(&"b");
+        let views       = Rc::new(RefCell::new(vec![]));
+        let their_views = views.clone();
+
+        hub.add_component(Join::new(component_fn(move |view: &TreeRef| {
+            let stale = view.get_child_ref_at(JOIN_STALE_TAG).map(|stale| stale.get_value().to_bool(false)).unwrap_or(false);
+            their_views.borrow_mut().push((input_value(view, "a"), input_value(view, "b"), stale));
+            view.clone()
+        })).with_input(&"a").with_input(&"b").with_timeout(3), &(), &"combined");
+
+        // b stops publishing after generation 2
+        for generation in 1..8 {
+            a.publish(TreeChange::new(&(), &tree!("a", ("value", generation), (GENERATION_TAG, generation))));
+            if generation <= 2 {
+                b.publish(TreeChange::new(&(), &tree!("b", ("value", generation), (GENERATION_TAG, generation))));
+            }
+            hub.flush();
+        }
+
+        assert!(*views.borrow() == vec![(1, 1, false), (2, 2, false), (6, 2, true)]);
+    }
+
+    #[test]
+    fn buffered_versions_are_bounded() {
+        let mut hub     = Hub::new();
+        let mut a       = hub.publish_to(&"a");
+        let join        = Join::new(component_fn(|view: &TreeRef| view.clone())).with_input(&"a").with_input(&"b").with_buffer_limit(4);
+        let buffered    = join.get_buffered_reader();
+
+        hub.add_component(join, &(), &"combined");
+
+        for generation in 1..100 {
+            a.publish(TreeChange::new(&(), &tree!("a", ("value", generation), (GENERATION_TAG, generation))));
+            hub.flush();
+        }
+
+        assert!(buffered() == 4);
+    }
+}
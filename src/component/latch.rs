@@ -0,0 +1,401 @@
+//
+//   Copyright 2016 Andrew Hunter
+//
+//   Licensed under the Apache License, Version 2.0 (the "License");
+//   you may not use this file except in compliance with the License.
+//   You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+//   Unless required by applicable law or agreed to in writing, software
+//   distributed under the License is distributed on an "AS IS" BASIS,
+//   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//   See the License for the specific language governing permissions and
+//   limitations under the License.
+//
+
+//!
+//! # Latches
+//!
+//! A `Latch` holds on to the data at one address and only passes it on when a trigger at another address fires.
+//! This is useful for gating: the data can change as often as it likes, but nothing downstream sees it until a
+//! button is pressed or a timer ticks.
+//!
+//! By default, only the latest version of the data is published when the trigger fires. In queued mode, every
+//! version that arrived since the last trigger is published in order. The queue is bounded: when it's full, the
+//! oldest version is dropped and counted.
+//!
+//! When the trigger fires and there's no data waiting, nothing is published unless the latch has been configured
+//! to publish an empty marker: a node with a single child tagged `__latch_empty` (`LATCH_EMPTY_TAG`).
+//!
+//! ```
+//! # use tametree::prelude::*;
+//! # use tametree::component::latch::*;
+//! let mut hub = Hub::new();
+//! let latch   = Latch::new(&"data", &"button")
+//!     .with_trigger(LatchTrigger::ValueEquals(true.to_tree_value()));
+//!
+//! hub.add_component(latch, &(), &"output");
+//! ```
+//!
+
+use std::rc::*;
+use std::cell::*;
+use std::collections::VecDeque;
+
+use super::super::tree::*;
+use super::component::*;
+
+///
+/// The tag of the child of the marker published when a latch is triggered with no data waiting
+///
+pub const LATCH_EMPTY_TAG: &str = "__latch_empty";
+
+///
+/// The condition that causes a latch to publish its data
+///
+pub enum LatchTrigger {
+    /// Any change to the trigger address
+    AnyChange,
+
+    /// A change that leaves the trigger node with the specified value
+    ValueEquals(TreeValue)
+}
+
+///
+/// What a latch publishes when it's triggered
+///
+#[derive(Clone, Copy, PartialEq)]
+pub enum LatchMode {
+    /// Only the most recent version of the data
+    Latest,
+
+    /// Every version of the data received since the last trigger, in order, keeping up to the specified number
+    Queue(usize)
+}
+
+///
+/// Component that holds the data at one address until a trigger at another address fires
+///
+pub struct Latch {
+    data: TreeAddress,
+    trigger: TreeAddress,
+    condition: LatchTrigger,
+    mode: LatchMode,
+    keep_on_release: bool,
+    empty_marker: bool,
+    dropped: Rc<Cell<usize>>
+}
+
+impl Latch {
+    ///
+    /// Creates a latch that publishes the latest data whenever anything changes at the trigger address
+    ///
+    /// Both addresses are relative to the tree the latch reads from.
+    ///
+    pub fn new<TData: ToTreeAddress, TTrigger: ToTreeAddress>(data: &TData, trigger: &TTrigger) -> Latch {
+        Latch {
+            data:               data.to_tree_address(),
+            trigger:            trigger.to_tree_address(),
+            condition:          LatchTrigger::AnyChange,
+            mode:               LatchMode::Latest,
+            keep_on_release:    false,
+            empty_marker:       false,
+            dropped:            Rc::new(Cell::new(0))
+        }
+    }
+
+    ///
+    /// Sets the condition that causes the latch to publish its data
+    ///
+    pub fn with_trigger(mut self, condition: LatchTrigger) -> Latch {
+        self.condition = condition;
+        self
+    }
+
+    ///
+    /// Sets whether the latch publishes only the latest data or every version it has received
+    ///
+    pub fn with_mode(mut self, mode: LatchMode) -> Latch {
+        self.mode = mode;
+        self
+    }
+
+    ///
+    /// Keeps the latest data after the trigger fires, so it's published again by the next trigger even if it
+    /// hasn't changed (by default, the data is cleared once it's been published)
+    ///
+    pub fn keep_on_release(mut self) -> Latch {
+        self.keep_on_release = true;
+        self
+    }
+
+    ///
+    /// Publishes a marker containing a `__latch_empty` node when the trigger fires with no data waiting
+    ///
+    pub fn with_empty_marker(mut self) -> Latch {
+        self.empty_marker = true;
+        self
+    }
+
+    ///
+    /// Retrieves a function that returns the number of versions dropped because the queue was full
+    ///
+    pub fn get_dropped_reader(&self) -> Box<dyn Fn() -> usize> {
+        let dropped = self.dropped.clone();
+
+        Box::new(move || dropped.get())
+    }
+}
+
+///
+/// The running state of a latch
+///
+struct LatchState {
+    latch: Latch,
+
+    /// The tree the latch reads from
+    tree: TreeRef,
+
+    /// The versions of the data that are waiting to be published, oldest first
+    waiting: VecDeque<TreeRef>,
+
+    publisher: PublisherRef
+}
+
+impl LatchState {
+    ///
+    /// Updates the state of the latch with a change
+    ///
+    fn process(&mut self, change: &TreeChange) {
+        self.tree = change.apply(&self.tree);
+
+        // A change to both addresses updates the data before the trigger fires
+        if change.applies_to(&self.latch.data, &TreeExtent::SubTree).unwrap_or(true) {
+            if let Some(data) = self.tree.get_child_ref_at(self.latch.data.clone()) {
+                self.hold(data);
+            }
+        }
+
+        if change.applies_to(&self.latch.trigger, &TreeExtent::SubTree).unwrap_or(true) && self.triggered() {
+            self.release();
+        }
+    }
+
+    ///
+    /// Adds a version of the data to the ones waiting to be published
+    ///
+    fn hold(&mut self, data: TreeRef) {
+        match self.latch.mode {
+            LatchMode::Latest => {
+                self.waiting.clear();
+                self.waiting.push_back(data);
+            },
+
+            LatchMode::Queue(max_length) => {
+                self.waiting.push_back(data);
+
+                while self.waiting.len() > max_length.max(1) {
+                    self.waiting.pop_front();
+                    self.latch.dropped.set(self.latch.dropped.get() + 1);
+                }
+            }
+        }
+    }
+
+    ///
+    /// True if the trigger condition is met by the current tree
+    ///
+    fn triggered(&self) -> bool {
+        match self.latch.condition {
+            LatchTrigger::AnyChange              => true,
+            LatchTrigger::ValueEquals(ref value) => {
+                self.tree.get_child_ref_at(self.latch.trigger.clone())
+                    .map(|trigger| trigger.get_value() == value)
+                    .unwrap_or(false)
+            }
+        }
+    }
+
+    ///
+    /// Publishes the data that's waiting
+    ///
+    fn release(&mut self) {
+        if self.waiting.is_empty() {
+            if self.latch.empty_marker {
+                let marker = "latch".to_tree_node().with_child_node(Some(&(LATCH_EMPTY_TAG, true).to_tree_node()));
+                self.publisher.publish(TreeChange::new(&(), &marker));
+            }
+            return;
+        }
+
+        for data in self.waiting.iter() {
+            self.publisher.publish(TreeChange::new(&(), data));
+        }
+
+        let latest = self.waiting.pop_back();
+        self.waiting.clear();
+
+        if self.latch.keep_on_release {
+            self.waiting.extend(latest);
+        }
+    }
+}
+
+struct LatchRef;
+
+impl Component for LatchRef {
+}
+
+impl Drop for LatchRef {
+    fn drop(&mut self) {
+    }
+}
+
+impl ConvertToComponent for Latch {
+    ///
+    /// Creates a component that publishes the data held by the latch when its trigger fires
+    ///
+    fn into_component(self, consumer: ConsumerRef, publisher: PublisherRef) -> ComponentRef {
+        let mut consumer    = consumer;
+        let mut state       = LatchState {
+            latch:      self,
+            tree:       "empty".to_tree_node(),
+            waiting:    VecDeque::new(),
+            publisher
+        };
+
+        consumer.subscribe(TreeAddress::Here, TreeExtent::SubTree, Box::new(move |change| {
+            state.process(change);
+        }));
+
+        Rc::new(LatchRef)
+    }
+}
+
+#[cfg(test)]
+mod latch_tests {
+    use std::rc::*;
+    use std::cell::*;
+
+    use super::super::super::tree::*;
+    use super::super::super::component::*;
+    use super::*;
+
+    ///
+    /// Creates a hub containing a latch, and returns it along with the list of values the latch has published
+    ///
+    fn latch_hub(latch: Latch) -> (Hub, Rc<RefCell<Vec<i32>>>) {
+        let mut hub     = Hub::new();
+        let published   = Rc::new(RefCell::new(vec![]));
+        let recorded    = published.clone();
+
+        hub.add_component(latch, &(), &"output");
+        hub.read_from(&"output").subscribe(TreeAddress::Here, TreeExtent::SubTree, Box::new(move |change| {
+            if let TreeReplacement::NewNode(ref node) = *change.replacement() {
+                let value = if node.get_child_ref_at(LATCH_EMPTY_TAG).is_some() { -1 } else { node.get_value().to_int(0) };
+                recorded.borrow_mut().push(value);
+            }
+        }));
+
+        (hub, published)
+    }
+
+    #[test]
+    fn data_alone_does_not_propagate() {
+        let (mut hub, published)    = latch_hub(Latch::new(&"data", &"trigger"));
+        let mut data                = hub.publish_to(&"data");
+
+        data.publish(TreeChange::new(&(), &("data", 1)));
+        data.publish(TreeChange::new(&(), &("data", 2)));
+        hub.flush();
+
+        assert!(published.borrow().is_empty());
+    }
+
+    #[test]
+    fn trigger_publishes_latest_value() {
+        let (mut hub, published)    = latch_hub(Latch::new(&"data", &"trigger"));
+        let mut data                = hub.publish_to(&"data");
+        let mut trigger             = hub.publish_to(&"trigger");
+
+        data.publish(TreeChange::new(&(), &("data", 1)));
+        data.publish(TreeChange::new(&(), &("data", 2)));
+        trigger.publish(TreeChange::new(&(), &("trigger", 1)));
+        hub.flush();
+
+        // The data is cleared once it's published, so a second trigger does nothing
+        trigger.publish(TreeChange::new(&(), &("trigger", 2)));
+        hub.flush();
+
+        assert!(*published.borrow() == vec![2]);
+    }
+
+    #[test]
+    fn trigger_can_require_a_value() {
+        let latch                   = Latch::new(&"data", &"trigger").with_trigger(LatchTrigger::ValueEquals(true.to_tree_value())).keep_on_release();
+        let (mut hub, published)    = latch_hub(latch);
+        let mut data                = hub.publish_to(&"data");
+        let mut trigger             = hub.publish_to(&"trigger");
+
+        data.publish(TreeChange::new(&(), &("data", 1)));
+        trigger.publish(TreeChange::new(&(), &("trigger", false)));
+        trigger.publish(TreeChange::new(&(), &("trigger", true)));
+        trigger.publish(TreeChange::new(&(), &("trigger", true)));
+        hub.flush();
+
+        assert!(*published.borrow() == vec![1, 1]);
+    }
+
+    #[test]
+    fn queued_mode_publishes_every_version() {
+        let (mut hub, published)    = latch_hub(Latch::new(&"data", &"trigger").with_mode(LatchMode::Queue(10)));
+        let mut data                = hub.publish_to(&"data");
+        let mut trigger             = hub.publish_to(&"trigger");
+
+        data.publish(TreeChange::new(&(), &("data", 1)));
+        data.publish(TreeChange::new(&(), &("data", 2)));
+        data.publish(TreeChange::new(&(), &("data", 3)));
+        trigger.publish(TreeChange::new(&(), &("trigger", 1)));
+        data.publish(TreeChange::new(&(), &("data", 4)));
+        trigger.publish(TreeChange::new(&(), &("trigger", 2)));
+        hub.flush();
+
+        assert!(*published.borrow() == vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn empty_trigger_publishes_marker_if_configured() {
+        let (mut hub, published)    = latch_hub(Latch::new(&"data", &"trigger"));
+        let mut trigger             = hub.publish_to(&"trigger");
+
+        trigger.publish(TreeChange::new(&(), &("trigger", 1)));
+        hub.flush();
+        assert!(published.borrow().is_empty());
+
+        let (mut hub, published)    = latch_hub(Latch::new(&"data", &"trigger").with_empty_marker());
+        let mut trigger             = hub.publish_to(&"trigger");
+
+        trigger.publish(TreeChange::new(&(), &("trigger", 1)));
+        hub.flush();
+        assert!(*published.borrow() == vec![-1]);
+    }
+
+    #[test]
+    fn queue_drops_oldest_versions() {
+        let latch                   = Latch::new(&"data", &"trigger").with_mode(LatchMode::Queue(2));
+        let dropped                 = latch.get_dropped_reader();
+        let (mut hub, published)    = latch_hub(latch);
+        let mut data                = hub.publish_to(&"data");
+        let mut trigger             = hub.publish_to(&"trigger");
+
+        for value in 1..6 {
+            data.publish(TreeChange::new(&(), &("data", value)));
+        }
+        trigger.publish(TreeChange::new(&(), &("trigger", 1)));
+        hub.flush();
+
+        assert!(*published.borrow() == vec![4, 5]);
+        assert!(dropped() == 3);
+    }
+}
@@ -0,0 +1,119 @@
+//
+//   Copyright 2016 Andrew Hunter
+//
+//   Licensed under the Apache License, Version 2.0 (the "License");
+//   you may not use this file except in compliance with the License.
+//   You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+//   Unless required by applicable law or agreed to in writing, software
+//   distributed under the License is distributed on an "AS IS" BASIS,
+//   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//   See the License for the specific language governing permissions and
+//   limitations under the License.
+//
+
+use std::rc::*;
+use std::cell::*;
+
+use super::super::tree::*;
+use super::component::*;
+
+///
+/// Function that receives the findings from a linting publisher
+///
+pub type LintSink = Box<dyn FnMut(&LintFinding)>;
+
+///
+/// A publisher that lints the tree after each change before passing the change on to another publisher
+///
+/// Only the part of the tree that the change could have affected is linted (see `lint_tree_at()`), so the cost
+/// of linting depends on the size of the change rather than the size of the tree.
+///
+pub struct LintingPublisher {
+    /// Where changes are passed on to
+    target: PublisherRef,
+
+    /// The tree as it is after the changes published so far
+    tree: TreeRef,
+
+    /// The rules to check
+    rules: LintRules,
+
+    /// Where findings are sent
+    sink: LintSink,
+
+    /// The total number of nodes examined so far
+    nodes_examined: Rc<Cell<usize>>
+}
+
+impl LintingPublisher {
+    ///
+    /// Creates a new linting publisher that sends its findings to the specified sink
+    ///
+    pub fn new(target: PublisherRef, rules: LintRules, sink: LintSink) -> Box<LintingPublisher> {
+        Box::new(LintingPublisher { target, tree: "empty".to_tree_node(), rules, sink, nodes_examined: Rc::new(Cell::new(0)) })
+    }
+
+    ///
+    /// Retrieves a function that returns the total number of nodes this publisher has linted
+    ///
+    pub fn get_nodes_examined_reader(&self) -> Box<dyn Fn() -> usize> {
+        let nodes_examined = self.nodes_examined.clone();
+
+        Box::new(move || nodes_examined.get())
+    }
+}
+
+impl Publisher for LintingPublisher {
+    ///
+    /// Publishes a change to the consumers of this component
+    ///
+    fn publish(&mut self, change: TreeChange) {
+        self.tree = change.apply(&self.tree);
+
+        let report = lint_tree_at(&self.tree, change.address(), &self.rules);
+        self.nodes_examined.set(self.nodes_examined.get() + report.nodes_examined);
+
+        for finding in report.findings.iter() {
+            (self.sink)(finding);
+        }
+
+        self.target.publish(change);
+    }
+}
+
+#[cfg(test)]
+mod linting_publisher_tests {
+    use std::rc::*;
+    use std::cell::*;
+
+    use super::super::super::tree::*;
+    use super::super::super::component::*;
+    use super::super::output_tree_publisher::*;
+    use super::*;
+
+    #[test]
+    fn reports_findings_for_changed_subtree() {
+        let findings        = Rc::new(RefCell::new(vec![]));
+        let also_findings   = findings.clone();
+        let output          = OutputTreePublisher::new();
+        let reader          = output.get_tree_reader();
+        let mut publisher   = LintingPublisher::new(output, LintRules::default(), Box::new(move |finding| also_findings.borrow_mut().push(finding.clone())));
+        let examined        = publisher.get_nodes_examined_reader();
+
+        let children: Vec<TreeRef> = (0..100).map(|index| (&*format!("item{}", index), index).to_tree_node()).collect();
+        publisher.publish(TreeChange::new(&(), &tree!("root", ("big", 0).to_tree_node().with_children(&children), tree!("small", ("x", 1)))));
+        assert!(findings.borrow().is_empty());
+
+        let examined_before = examined();
+        publisher.publish(TreeChange::new(&"small", &tree!("small", ("x", 1), ("x", 2))));
+
+        assert!(findings.borrow().len() == 1);
+        assert!(findings.borrow()[0].rule == LintRule::DuplicateTag);
+        assert!(findings.borrow()[0].address == ("small", 1).to_tree_address());
+        assert!(examined() - examined_before < 10);
+        assert!(reader().get_child_at("small").get_child_at(1).get_value().to_int(0) == 2);
+    }
+}
@@ -0,0 +1,525 @@
+//
+//   Copyright 2016 Andrew Hunter
+//
+//   Licensed under the Apache License, Version 2.0 (the "License");
+//   you may not use this file except in compliance with the License.
+//   You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+//   Unless required by applicable law or agreed to in writing, software
+//   distributed under the License is distributed on an "AS IS" BASIS,
+//   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//   See the License for the specific language governing permissions and
+//   limitations under the License.
+//
+
+//!
+//! # Mirroring between tree layouts
+//!
+//! While an application is moving from one tree layout to another, some components will read and write the old
+//! layout and some the new one. A `MirrorComponent` keeps the two in sync. It's configured with pairs of addresses,
+//! one in the old layout and one in the new, and whenever the subtree at one address of a pair changes, it publishes
+//! the same subtree to the other. As the addresses are arbitrary, structural changes can be expressed as pairs: for
+//! instance, `.user_name.` in the old layout can be paired with `.user.name.` in the new one. A pair can also have a
+//! transform for each direction, which is applied to the subtree before it's published on the other side.
+//!
+//! The component must read from and publish to the same tree, which means it will see its own changes again. These
+//! echoes are recognised and not mirrored back. A change that leaves the two sides of a pair with the same content
+//! isn't mirrored either.
+//!
+//! If one side of a pair changes while a change that the component published to that side hasn't arrived yet, both
+//! sides were changed in the same generation. The mirror's conflict policy decides which side is kept, and the
+//! conflict is recorded so it can be read with `get_conflict_reader()`.
+//!
+//! A pair can be limited to a single direction, in which case it acts as a plain remapping from one address to
+//! another.
+//!
+//! ```
+//! # use tametree::prelude::*;
+//! # use tametree::component::mirror::*;
+//! let mut hub = Hub::new();
+//! let mirror  = MirrorComponent::new(MirrorConflictPolicy::PreferNew)
+//!     .with_pair(MirrorPair::new(&"user_name", &("user", "name")));
+//!
+//! hub.add_component(mirror, &(), &());
+//! ```
+//!
+
+use std::rc::*;
+use std::cell::*;
+
+use super::super::tree::*;
+use super::component::*;
+
+///
+/// A function that converts the subtree from one side of a mirrored pair into the subtree for the other
+///
+pub type MirrorTransform = Rc<dyn Fn(&TreeRef) -> TreeRef>;
+
+///
+/// The two layouts that a mirror keeps in sync
+///
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MirrorSide {
+    Old,
+    New
+}
+
+impl MirrorSide {
+    fn other(self) -> MirrorSide {
+        match self {
+            MirrorSide::Old => MirrorSide::New,
+            MirrorSide::New => MirrorSide::Old
+        }
+    }
+}
+
+///
+/// What a mirror does when both sides of a pair are changed in the same generation
+///
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MirrorConflictPolicy {
+    /// The change to the new layout is kept on both sides
+    PreferNew,
+
+    /// The change to the old layout is kept on both sides
+    PreferOld,
+
+    /// Neither side is preferred: the change that arrived first is kept
+    Report
+}
+
+///
+/// A conflict between the two sides of a mirrored pair
+///
+#[derive(Clone, PartialEq)]
+pub struct MirrorConflict {
+    /// The address of the pair in the old layout
+    pub old: TreeAddress,
+
+    /// The address of the pair in the new layout
+    pub new: TreeAddress,
+
+    /// The side whose change was kept
+    pub kept: MirrorSide
+}
+
+///
+/// A pair of addresses that are kept in sync by a mirror
+///
+#[derive(Clone)]
+pub struct MirrorPair {
+    old: TreeAddress,
+    new: TreeAddress,
+    to_new: Option<MirrorTransform>,
+    to_old: Option<MirrorTransform>,
+    old_to_new: bool,
+    new_to_old: bool
+}
+
+impl MirrorPair {
+    ///
+    /// Creates a pair that copies subtrees unchanged in both directions
+    ///
+    pub fn new<TOld: ToTreeAddress, TNew: ToTreeAddress>(old: &TOld, new: &TNew) -> MirrorPair {
+        MirrorPair { old: old.to_tree_address(), new: new.to_tree_address(), to_new: None, to_old: None, old_to_new: true, new_to_old: true }
+    }
+
+    ///
+    /// Transforms the subtrees copied from the old layout to the new one and back again
+    ///
+    /// The tag of the transformed subtree doesn't matter, as it's replaced when the subtree is published.
+    ///
+    pub fn with_transforms<TToNew, TToOld>(mut self, to_new: TToNew, to_old: TToOld) -> MirrorPair
+    where TToNew: 'static + Fn(&TreeRef) -> TreeRef, TToOld: 'static + Fn(&TreeRef) -> TreeRef {
+        self.to_new = Some(Rc::new(to_new));
+        self.to_old = Some(Rc::new(to_old));
+        self
+    }
+
+    ///
+    /// Only copies changes from the old layout to the new one
+    ///
+    pub fn old_to_new_only(mut self) -> MirrorPair {
+        self.new_to_old = false;
+        self
+    }
+
+    ///
+    /// Only copies changes from the new layout to the old one
+    ///
+    pub fn new_to_old_only(mut self) -> MirrorPair {
+        self.old_to_new = false;
+        self
+    }
+
+    ///
+    /// The side, source address, target address and transform for each enabled direction of this pair
+    ///
+    fn directions(&self) -> Vec<(MirrorSide, &TreeAddress, &TreeAddress, Option<&MirrorTransform>)> {
+        let mut directions = vec![];
+
+        if self.old_to_new { directions.push((MirrorSide::Old, &self.old, &self.new, self.to_new.as_ref())); }
+        if self.new_to_old { directions.push((MirrorSide::New, &self.new, &self.old, self.to_old.as_ref())); }
+
+        directions
+    }
+}
+
+///
+/// A component that keeps pairs of addresses in two tree layouts in sync
+///
+pub struct MirrorComponent {
+    pairs: Vec<MirrorPair>,
+    policy: MirrorConflictPolicy,
+    conflicts: Rc<RefCell<Vec<MirrorConflict>>>
+}
+
+impl MirrorComponent {
+    ///
+    /// Creates a mirror with no pairs, which resolves conflicts using the specified policy
+    ///
+    pub fn new(policy: MirrorConflictPolicy) -> MirrorComponent {
+        MirrorComponent { pairs: vec![], policy, conflicts: Rc::new(RefCell::new(vec![])) }
+    }
+
+    ///
+    /// Adds a pair of addresses to keep in sync
+    ///
+    pub fn with_pair(mut self, pair: MirrorPair) -> MirrorComponent {
+        self.pairs.push(pair);
+        self
+    }
+
+    ///
+    /// Retrieves a function that reads the conflicts that have occurred so far
+    ///
+    pub fn get_conflict_reader(&self) -> Box<dyn Fn() -> Vec<MirrorConflict>> {
+        let conflicts = self.conflicts.clone();
+
+        Box::new(move || conflicts.borrow().clone())
+    }
+}
+
+///
+/// True if two trees have the same content (siblings of the root nodes are not compared)
+///
+fn same_tree(a: &TreeRef, b: &TreeRef) -> bool {
+    a.get_tag() == b.get_tag() && same_content(a, b)
+}
+
+///
+/// True if two trees have the same content, ignoring the tags of the root nodes
+///
+fn same_content(a: &TreeRef, b: &TreeRef) -> bool {
+    if Rc::ptr_eq(a, b) {
+        return true;
+    }
+
+    let a_children: Vec<TreeRef> = a.iter_children().collect();
+    let b_children: Vec<TreeRef> = b.iter_children().collect();
+
+    a.get_value() == b.get_value()
+        && a_children.len() == b_children.len()
+        && a_children.iter().zip(b_children.iter()).all(|(a, b)| same_tree(a, b))
+}
+
+///
+/// True if two changes make the same change to a tree
+///
+fn same_change(a: &TreeChange, b: &TreeChange) -> bool {
+    a.address() == b.address() && match (a.replacement(), b.replacement()) {
+        (TreeReplacement::Remove, TreeReplacement::Remove)                          => true,
+        (TreeReplacement::NewNode(a), TreeReplacement::NewNode(b))                  => same_tree(a, b),
+        (TreeReplacement::NewValue(a_tag, a), TreeReplacement::NewValue(b_tag, b))  => a_tag == b_tag && a == b,
+        _                                                                           => false
+    }
+}
+
+///
+/// Creates a change that replaces the subtree at an address (or removes it if there's no subtree)
+///
+fn replace_at(address: &TreeAddress, subtree: Option<TreeRef>) -> TreeChange {
+    match subtree {
+        Some(subtree)   => TreeChange::new(&TreeAddress::Here, &subtree).rebased_to(address),
+        None            => TreeChange::new(address, &())
+    }
+}
+
+///
+/// The state of a running mirror component
+///
+struct MirrorState {
+    pairs: Vec<MirrorPair>,
+    policy: MirrorConflictPolicy,
+    conflicts: Rc<RefCell<Vec<MirrorConflict>>>,
+
+    /// The tree as of the last change received
+    tree: TreeRef,
+
+    /// Changes that have been published but haven't been received back yet
+    pending: Vec<TreeChange>,
+
+    /// Where the mirrored changes are sent
+    publisher: PublisherRef
+}
+
+impl MirrorState {
+    ///
+    /// Publishes a change, remembering it so its echo can be recognised
+    ///
+    fn send(&mut self, change: TreeChange) {
+        self.pending.push(change.clone());
+        self.publisher.publish(change);
+    }
+
+    ///
+    /// Processes a change to the tree
+    ///
+    fn process(&mut self, change: &TreeChange) {
+        self.tree = change.apply(&self.tree);
+
+        // Changes published by this component have already been mirrored
+        if let Some(echo_index) = self.pending.iter().position(|pending| same_change(pending, change)) {
+            self.pending.remove(echo_index);
+            return;
+        }
+
+        for pair in self.pairs.clone() {
+            for (side, from, to, transform) in pair.directions() {
+                if !change.applies_to(from, &TreeExtent::SubTree).unwrap_or(false) {
+                    continue;
+                }
+
+                // Nothing to do if the other side already matches
+                let source  = from.lookup_index(&self.tree);
+                let image   = match transform {
+                    Some(transform) => source.as_ref().map(|source| transform(source)),
+                    None            => source.clone()
+                };
+                let current = to.lookup_index(&self.tree);
+
+                let in_sync = match (&image, &current) {
+                    (Some(image), Some(current))    => same_content(image, current),
+                    (None, None)                    => true,
+                    _                               => false
+                };
+                if in_sync {
+                    continue;
+                }
+
+                // If a change to this side is still on its way, both sides have been changed in the same generation
+                if self.pending.iter().any(|pending| pending.applies_to(from, &TreeExtent::SubTree).unwrap_or(false)) {
+                    let kept = match self.policy {
+                        MirrorConflictPolicy::PreferNew => MirrorSide::New,
+                        MirrorConflictPolicy::PreferOld => MirrorSide::Old,
+                        MirrorConflictPolicy::Report    => side.other()
+                    };
+
+                    self.conflicts.borrow_mut().push(MirrorConflict { old: pair.old.clone(), new: pair.new.clone(), kept });
+
+                    // If the other side is kept, the change that's on its way will overwrite this one
+                    if kept != side {
+                        continue;
+                    }
+
+                    // Otherwise, this side needs to be restored after the change that's on its way arrives
+                    self.send(replace_at(from, source));
+                }
+
+                // Changes that are entirely inside an untransformed subtree can be copied directly
+                let relative = if transform.is_none() && current.is_some() { change.relative_to(from) } else { None };
+                let mirrored = match relative {
+                    Some(relative)  => relative.rebased_to(to),
+                    None            => replace_at(to, image)
+                };
+
+                self.send(mirrored);
+            }
+        }
+    }
+}
+
+struct MirrorComponentRef;
+
+impl Component for MirrorComponentRef {
+}
+
+impl Drop for MirrorComponentRef {
+    fn drop(&mut self) {
+    }
+}
+
+impl ConvertToComponent for MirrorComponent {
+    ///
+    /// Creates a component that mirrors changes between the pairs of addresses in a tree
+    ///
+    fn into_component(self, consumer: ConsumerRef, publisher: PublisherRef) -> ComponentRef {
+        let mut consumer    = consumer;
+        let mut state       = MirrorState {
+            pairs:      self.pairs,
+            policy:     self.policy,
+            conflicts:  self.conflicts,
+            tree:       "empty".to_tree_node(),
+            pending:    vec![],
+            publisher
+        };
+
+        consumer.subscribe(TreeAddress::Here, TreeExtent::SubTree, Box::new(move |change| {
+            state.process(change);
+        }));
+
+        Rc::new(MirrorComponentRef)
+    }
+}
+
+#[cfg(test)]
+mod mirror_tests {
+    use std::rc::*;
+    use std::cell::*;
+
+    use super::super::super::tree::*;
+    use super::super::super::component::*;
+    use super::*;
+
+    ///
+    /// Counts the changes to an address of a hub and keeps track of its latest value
+    ///
+    fn watch(hub: &mut Hub, address: TreeAddress) -> (Rc<Cell<usize>>, Rc<RefCell<TreeRef>>) {
+        let count       = Rc::new(Cell::new(0));
+        let value       = Rc::new(RefCell::new("empty".to_tree_node()));
+        let mut reader  = hub.read_from(&address);
+
+        let (our_count, our_value) = (count.clone(), value.clone());
+        reader.subscribe(TreeAddress::Here, TreeExtent::SubTree, Box::new(move |change| {
+            our_count.set(our_count.get() + 1);
+            let new_value = change.apply(&our_value.borrow());
+            *our_value.borrow_mut() = new_value;
+        }));
+
+        (count, value)
+    }
+
+    fn initial_tree() -> TreeRef {
+        tree!("root", ("user_name", "alice"), tree!("user", ("name", "alice")))
+    }
+
+    #[test]
+    fn writes_appear_in_other_layout_once() {
+        let mut hub         = Hub::new();
+        let mut input       = hub.publish_to(&());
+        hub.add_component(MirrorComponent::new(MirrorConflictPolicy::PreferNew).with_pair(MirrorPair::new(&"user_name", &("user", "name"))), &(), &());
+
+        let (old_count, old_value) = watch(&mut hub, "user_name".to_tree_address());
+        let (new_count, new_value) = watch(&mut hub, ("user", "name").to_tree_address());
+
+        input.publish(TreeChange::new(&(), &initial_tree()));
+        hub.flush();
+        let (old_before, new_before) = (old_count.get(), new_count.get());
+
+        input.publish(TreeChange::new(&"user_name", &("user_name", "bob")));
+        hub.flush();
+        assert!(old_count.get() == old_before + 1);
+        assert!(new_count.get() == new_before + 1);
+        assert!(new_value.borrow().get_value().to_str("") == "bob");
+        assert!(new_value.borrow().get_tag() == "name");
+
+        input.publish(TreeChange::new(&("user", "name"), &("name", "carol")));
+        hub.flush();
+        assert!(old_count.get() == old_before + 2);
+        assert!(new_count.get() == new_before + 2);
+        assert!(old_value.borrow().get_value().to_str("") == "carol");
+        assert!(old_value.borrow().get_tag() == "user_name");
+    }
+
+    fn conflict_result(policy: MirrorConflictPolicy) -> (String, String, Vec<MirrorConflict>) {
+        let mut hub         = Hub::new();
+        let mut input       = hub.publish_to(&());
+        let mirror          = MirrorComponent::new(policy).with_pair(MirrorPair::new(&"user_name", &("user", "name")));
+        let conflicts       = mirror.get_conflict_reader();
+        hub.add_component(mirror, &(), &());
+
+        let (_, old_value) = watch(&mut hub, "user_name".to_tree_address());
+        let (_, new_value) = watch(&mut hub, ("user", "name").to_tree_address());
+
+        input.publish(TreeChange::new(&(), &initial_tree()));
+        hub.flush();
+
+        // Both of these are delivered in the same generation
+        input.publish(TreeChange::new(&"user_name", &("user_name", "old")));
+        input.publish(TreeChange::new(&("user", "name"), &("name", "new")));
+        hub.flush();
+
+        let old_value = old_value.borrow().get_value().to_str("").to_string();
+        let new_value = new_value.borrow().get_value().to_str("").to_string();
+        (old_value, new_value, conflicts())
+    }
+
+    #[test]
+    fn conflicts_follow_policy() {
+        let (old, new, conflicts) = conflict_result(MirrorConflictPolicy::PreferNew);
+        assert!(old == "new" && new == "new");
+        assert!(conflicts.len() == 1);
+        assert!(conflicts[0].kept == MirrorSide::New);
+        assert!(conflicts[0].old == "user_name".to_tree_address());
+
+        let (old, new, conflicts) = conflict_result(MirrorConflictPolicy::PreferOld);
+        assert!(old == "old" && new == "old");
+        assert!(conflicts.len() == 1);
+        assert!(conflicts[0].kept == MirrorSide::Old);
+
+        let (old, new, conflicts) = conflict_result(MirrorConflictPolicy::Report);
+        assert!(old == "old" && new == "old");
+        assert!(conflicts.len() == 1);
+        assert!(conflicts[0].kept == MirrorSide::Old);
+    }
+
+    #[test]
+    fn transforms_round_trip() {
+        let mut hub         = Hub::new();
+        let mut input       = hub.publish_to(&());
+        let pair            = MirrorPair::new(&"cents", &"dollars")
+            .with_transforms(|cents| ("dollars", cents.get_value().to_int(0) as f64 / 100.0).to_tree_node(),
+                             |dollars| ("cents", (dollars.get_value().to_real(0.0) * 100.0).round() as i32).to_tree_node());
+        hub.add_component(MirrorComponent::new(MirrorConflictPolicy::Report).with_pair(pair), &(), &());
+
+        let (old_count, old_value) = watch(&mut hub, "cents".to_tree_address());
+        let (new_count, new_value) = watch(&mut hub, "dollars".to_tree_address());
+
+        input.publish(TreeChange::new(&(), &tree!("root", ("cents", 150), ("dollars", 1.5))));
+        hub.flush();
+        let (old_before, new_before) = (old_count.get(), new_count.get());
+
+        input.publish(TreeChange::new(&"cents", &("cents", 250)));
+        hub.flush();
+        assert!(new_value.borrow().get_value().to_real(0.0) == 2.5);
+
+        input.publish(TreeChange::new(&"dollars", &("dollars", 0.75)));
+        hub.flush();
+        assert!(old_value.borrow().get_value().to_int(0) == 75);
+
+        assert!(old_count.get() == old_before + 2);
+        assert!(new_count.get() == new_before + 2);
+    }
+
+    #[test]
+    fn one_way_pair_only_remaps() {
+        let mut hub         = Hub::new();
+        let mut input       = hub.publish_to(&());
+        hub.add_component(MirrorComponent::new(MirrorConflictPolicy::PreferNew).with_pair(MirrorPair::new(&"user_name", &("user", "name")).old_to_new_only()), &(), &());
+
+        let (_, old_value) = watch(&mut hub, "user_name".to_tree_address());
+        let (_, new_value) = watch(&mut hub, ("user", "name").to_tree_address());
+
+        input.publish(TreeChange::new(&(), &initial_tree()));
+        input.publish(TreeChange::new(&"user_name", &("user_name", "bob")));
+        hub.flush();
+        assert!(new_value.borrow().get_value().to_str("") == "bob");
+
+        input.publish(TreeChange::new(&("user", "name"), &("name", "carol")));
+        hub.flush();
+        assert!(old_value.borrow().get_value().to_str("") == "bob");
+        assert!(new_value.borrow().get_value().to_str("") == "carol");
+    }
+}
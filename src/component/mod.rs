@@ -16,19 +16,50 @@
 
 //! # Software components that communicate using trees
 
-pub use super::tree::*;
+// Tree types that appear in the component traits (use tametree::tree or tametree::prelude for the rest)
+pub use super::tree::{TreeRef, TreeChange, TreeAddress, TreeExtent};
+
 pub use self::component::*;
 pub use self::functions_are_components::*;
 pub use self::components_are_functions::*;
+pub use self::change_transform::*;
 pub use self::pipe::*;
-// pub use self::hub::*;
+pub use self::causal::*;
+pub use self::convergence::*;
+pub use self::hub::*;
+pub use self::trace::*;
+pub use self::quarantine::*;
 
 pub mod component;
-pub mod subscriptionmanager;
+pub mod errors;
+mod subscriptionmanager;
+pub mod adaptive_filter;
+pub mod addressing_audit;
+pub mod redaction;
+pub mod consumer_timing;
+pub mod quarantine;
 pub mod immediate_publisher;
 pub mod bus_publisher;
 pub mod functions_are_components;
 pub mod output_tree_publisher;
+pub mod linting_publisher;
+pub mod budgeted_publisher;
+pub mod tracking_publisher;
+pub mod projection_publisher;
+pub mod interest;
 pub mod components_are_functions;
+pub mod change_transform;
+pub mod multi_output;
+pub mod mirror;
+pub mod join;
+pub mod interface;
+pub mod latch;
+pub mod fallback;
+pub mod durable;
 pub mod pipe;
-// pub mod hub;
+pub mod causal;
+pub mod convergence;
+pub mod hub;
+pub mod trace;
+pub mod wiring;
+pub mod control;
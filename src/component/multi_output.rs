@@ -0,0 +1,402 @@
+//
+//   Copyright 2016 Andrew Hunter
+//
+//   Licensed under the Apache License, Version 2.0 (the "License");
+//   you may not use this file except in compliance with the License.
+//   You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+//   Unless required by applicable law or agreed to in writing, software
+//   distributed under the License is distributed on an "AS IS" BASIS,
+//   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//   See the License for the specific language governing permissions and
+//   limitations under the License.
+//
+
+//!
+//! # Multi-output components
+//!
+//! Some components produce several independent results: for example, a parser might produce the parsed data,
+//! some statistics and a list of errors. Rather than combining these into a single output tree, a component
+//! can publish each of them to a separate, named publisher.
+//!
+//! A `MultiPublisher` holds the named publishers, and `ConvertToMultiComponent` creates a component that
+//! publishes to them. The simplest way to write a multi-output component is as a function that returns a
+//! `MultiOut`:
+//!
+//! ```
+//! # use tametree::prelude::*;
+//! # use tametree::component::multi_output::*;
+//! let component = multi_component_fn(|input: &String| {
+//!     MultiOut::new()
+//!         .set("upper", input.to_uppercase())
+//!         .set("length", input.len() as i32)
+//! });
+//!
+//! let mut endpoint = MultiComponentEndPoint::<String>::new(component, &["upper", "length"]);
+//! endpoint.send("hello".to_string());
+//!
+//! assert!(endpoint.recv::<String>("upper") == Some("HELLO".to_string()));
+//! assert!(endpoint.recv::<i32>("length") == Some(5));
+//! ```
+//!
+//! An output that is left out of a `MultiOut` isn't published, so its previous value is left alone.
+//!
+
+use std::rc::*;
+use std::marker::PhantomData;
+
+use super::super::tree::*;
+use super::component::*;
+use super::immediate_publisher::*;
+use super::output_tree_publisher::*;
+
+///
+/// A set of named publishers
+///
+pub struct MultiPublisher {
+    outputs: Vec<(String, PublisherRef)>
+}
+
+impl MultiPublisher {
+    ///
+    /// Creates a multi-publisher with no outputs
+    ///
+    pub fn new() -> MultiPublisher {
+        MultiPublisher { outputs: vec![] }
+    }
+
+    ///
+    /// Adds a named output (replacing any existing output with the same name)
+    ///
+    pub fn add(&mut self, name: &str, publisher: PublisherRef) {
+        match self.outputs.iter().position(|(existing_name, _)| existing_name == name) {
+            Some(existing_index)    => self.outputs[existing_index].1 = publisher,
+            None                    => self.outputs.push((name.to_string(), publisher))
+        }
+    }
+
+    ///
+    /// Adds a named output, returning the updated multi-publisher
+    ///
+    pub fn with_output(mut self, name: &str, publisher: PublisherRef) -> MultiPublisher {
+        self.add(name, publisher);
+        self
+    }
+
+    ///
+    /// Retrieves the publisher with a particular name
+    ///
+    pub fn get(&mut self, name: &str) -> Option<&mut PublisherRef> {
+        self.outputs.iter_mut().find(|(existing_name, _)| existing_name == name).map(|(_, publisher)| publisher)
+    }
+
+    ///
+    /// The names of the outputs, in the order they were added
+    ///
+    pub fn names(&self) -> Vec<&str> {
+        self.outputs.iter().map(|(name, _)| &**name).collect()
+    }
+}
+
+impl Default for MultiPublisher {
+    fn default() -> MultiPublisher {
+        MultiPublisher::new()
+    }
+}
+
+///
+/// The results produced by one invocation of a multi-output component function
+///
+pub struct MultiOut {
+    values: Vec<(String, TreeRef)>
+}
+
+impl MultiOut {
+    ///
+    /// Creates a result with no outputs
+    ///
+    pub fn new() -> MultiOut {
+        MultiOut { values: vec![] }
+    }
+
+    ///
+    /// Sets the value for a named output
+    ///
+    pub fn set<TValue: ToTreeNode>(mut self, name: &str, value: TValue) -> MultiOut {
+        let tree = value.to_tree_node();
+
+        match self.values.iter().position(|(existing_name, _)| existing_name == name) {
+            Some(existing_index)    => self.values[existing_index].1 = tree,
+            None                    => self.values.push((name.to_string(), tree))
+        }
+
+        self
+    }
+
+    ///
+    /// Retrieves the value set for a named output
+    ///
+    pub fn get(&self, name: &str) -> Option<&TreeRef> {
+        self.values.iter().find(|(existing_name, _)| existing_name == name).map(|(_, value)| value)
+    }
+
+    ///
+    /// Publishes the outputs that have been set to a multi-publisher
+    ///
+    /// Outputs that the publisher doesn't have are ignored.
+    ///
+    pub fn publish_to(self, outputs: &mut MultiPublisher) {
+        for (name, value) in self.values {
+            if let Some(publisher) = outputs.get(&name) {
+                publisher.publish(TreeChange::new(&TreeAddress::Here, &value));
+            }
+        }
+    }
+}
+
+impl Default for MultiOut {
+    fn default() -> MultiOut {
+        MultiOut::new()
+    }
+}
+
+///
+/// Types that implement this trait can be converted into components with several outputs
+///
+pub trait ConvertToMultiComponent {
+    ///
+    /// Converts this object into a component that reads from a consumer and publishes to a set of named publishers
+    ///
+    fn into_component_multi(self, consumer: ConsumerRef, outputs: MultiPublisher) -> ComponentRef;
+}
+
+struct MultiFunctionComponent;
+
+impl Component for MultiFunctionComponent {
+}
+
+impl Drop for MultiFunctionComponent {
+    fn drop(&mut self) {
+    }
+}
+
+impl<TIn: 'static + DecodeFromTreeNode> ConvertToMultiComponent for Box<dyn FnMut(&TIn) -> MultiOut> {
+    fn into_component_multi(self, consumer: ConsumerRef, outputs: MultiPublisher) -> ComponentRef {
+        let mut our_consumer    = consumer;
+        let mut our_outputs     = outputs;
+        let mut action          = self;
+
+        let mut tree = "empty".to_tree_node();
+
+        our_consumer.subscribe(TreeAddress::Here, TreeExtent::SubTree, Box::new(move |change| {
+            tree = change.apply(&tree);
+
+            // TODO: once we have error handling, deal with decoding failing here
+            if let Ok(decoded) = TIn::new_from_tree(&tree) {
+                action(&decoded).publish_to(&mut our_outputs);
+            }
+        }));
+
+        Rc::new(MultiFunctionComponent)
+    }
+}
+
+impl<TIn: 'static + DecodeFromTreeNode> ConvertToMultiComponent for Box<dyn Fn(&TIn) -> MultiOut> {
+    fn into_component_multi(self, consumer: ConsumerRef, outputs: MultiPublisher) -> ComponentRef {
+        let action = self;
+
+        multi_component_fn_mut(move |val| { action(val) }).into_component_multi(consumer, outputs)
+    }
+}
+
+///
+/// Makes a function that returns a `MultiOut` into something that can be converted into a multi-output component
+///
+pub fn multi_component_fn<TIn, F>(func: F) -> Box<dyn Fn(&TIn) -> MultiOut> where F: Fn(&TIn) -> MultiOut + 'static {
+    Box::new(func)
+}
+
+///
+/// Makes a mutable function that returns a `MultiOut` into something that can be converted into a multi-output component
+///
+pub fn multi_component_fn_mut<TIn, F>(func: F) -> Box<dyn FnMut(&TIn) -> MultiOut> where F: FnMut(&TIn) -> MultiOut + 'static {
+    Box::new(func)
+}
+
+///
+/// An endpoint for a multi-output component, which can read each of the named outputs
+///
+pub struct MultiComponentEndPoint<TIn: 'static + ToTreeNode> {
+    _component: ComponentRef,
+    input:      PublisherRef,
+    readers:    Vec<(String, Box<dyn Fn() -> TreeRef>)>,
+
+    phantom_in: PhantomData<TIn>
+}
+
+impl<TIn: 'static + ToTreeNode> MultiComponentEndPoint<TIn> {
+    ///
+    /// Creates an endpoint for a component with the specified outputs
+    ///
+    pub fn new<TComponent: ConvertToMultiComponent>(component: TComponent, output_names: &[&str]) -> MultiComponentEndPoint<TIn> {
+        let input       = ImmediatePublisher::new();
+        let consumer    = input.create_consumer();
+        let mut outputs = MultiPublisher::new();
+        let mut readers = vec![];
+
+        for name in output_names {
+            let output = OutputTreePublisher::new();
+            readers.push((name.to_string(), output.get_tree_reader()));
+            outputs.add(name, output);
+        }
+
+        let component = component.into_component_multi(consumer, outputs);
+
+        MultiComponentEndPoint { _component: component, input, readers, phantom_in: PhantomData }
+    }
+
+    ///
+    /// Sends new data to the component
+    ///
+    pub fn send(&mut self, data: TIn) {
+        self.input.publish(TreeChange::new(&(), &data.to_tree_node()));
+    }
+
+    ///
+    /// Retrieves the current tree for a named output
+    ///
+    pub fn recv_tree(&self, name: &str) -> Option<TreeRef> {
+        self.readers.iter().find(|(reader_name, _)| reader_name == name).map(|(_, reader)| reader())
+    }
+
+    ///
+    /// Retrieves the current value of a named output, or `None` if it doesn't exist or can't be decoded
+    ///
+    pub fn recv<TOut: DecodeFromTreeNode>(&self, name: &str) -> Option<TOut> {
+        self.recv_tree(name).and_then(|tree| TOut::new_from_tree(&tree).ok())
+    }
+}
+
+#[cfg(test)]
+mod multi_output_tests {
+    use std::rc::*;
+    use std::cell::*;
+
+    use super::super::super::tree::*;
+    use super::super::super::component::*;
+    use super::*;
+
+    tree_struct! {
+        struct Stats {
+            pub fields: i32,
+            pub longest: i32
+        }
+    }
+
+    fn parser() -> Box<dyn Fn(&String) -> MultiOut> {
+        multi_component_fn(|input: &String| {
+            let fields: Vec<String> = input.split(',').map(|field| field.to_string()).collect();
+            let result              = MultiOut::new().set("data", fields.join("|"));
+
+            // Empty input produces no statistics
+            if input.is_empty() {
+                result
+            } else {
+                result.set("stats", Stats { fields: fields.len() as i32, longest: fields.iter().map(|field| field.len() as i32).max().unwrap_or(0) })
+            }
+        })
+    }
+
+    #[test]
+    fn endpoint_reads_each_output() {
+        let mut endpoint = MultiComponentEndPoint::<String>::new(parser(), &["data", "stats"]);
+
+        endpoint.send("a,bbb,cc".to_string());
+
+        assert!(endpoint.recv::<String>("data") == Some("a|bbb|cc".to_string()));
+        let stats = endpoint.recv::<Stats>("stats").unwrap();
+        assert!(stats.fields == 3);
+        assert!(stats.longest == 3);
+        assert!(endpoint.recv_tree("missing").is_none());
+    }
+
+    #[test]
+    fn omitted_output_is_left_alone() {
+        let mut endpoint = MultiComponentEndPoint::<String>::new(parser(), &["data", "stats"]);
+
+        endpoint.send("a,bb".to_string());
+        endpoint.send("".to_string());
+
+        assert!(endpoint.recv::<String>("data") == Some("".to_string()));
+        assert!(endpoint.recv::<Stats>("stats").unwrap().fields == 2);
+    }
+
+    #[test]
+    fn hub_outputs_go_to_separate_addresses() {
+        let mut hub         = Hub::new();
+        let mut input       = hub.publish_to(&"input");
+        hub.add_multi_component(parser(), &"input", &[("data", &"parsed"), ("stats", &("info", "stats"))]);
+
+        let data_changes    = Rc::new(Cell::new(0));
+        let stats_changes   = Rc::new(Cell::new(0));
+        let (data_count, stats_count) = (data_changes.clone(), stats_changes.clone());
+
+        hub.read_from(&"parsed").subscribe(TreeAddress::Here, TreeExtent::SubTree, Box::new(move |_| data_count.set(data_count.get()+1)));
+        hub.read_from(&("info", "stats")).subscribe(TreeAddress::Here, TreeExtent::SubTree, Box::new(move |_| stats_count.set(stats_count.get()+1)));
+
+        let parsed: RecvFn<String>          = hub.read_from(&"parsed").get_receiver();
+        let stats: RecvFn<Stats>            = hub.read_from(&("info", "stats")).get_receiver();
+
+        input.publish(TreeChange::new(&(), &"x,yy".to_string()));
+        hub.flush();
+
+        assert!(parsed() == Some("x|yy".to_string()));
+        assert!(stats().unwrap().longest == 2);
+        assert!(data_changes.get() == 1);
+        assert!(stats_changes.get() == 1);
+
+        // Empty input only updates the data
+        input.publish(TreeChange::new(&(), &"".to_string()));
+        hub.flush();
+
+        assert!(parsed() == Some("".to_string()));
+        assert!(stats().unwrap().longest == 2);
+        assert!(data_changes.get() == 2);
+        assert!(stats_changes.get() == 1);
+    }
+
+    #[test]
+    fn hub_multi_component_is_traced_and_can_be_paused() {
+        let mut hub         = Hub::new();
+        let hook            = Rc::new(VecTraceHook::new());
+        let mut input       = hub.publish_to(&"input");
+        hub.add_named_multi_component("parser", parser(), &"input", &[("data", &"parsed"), ("stats", &("info", "stats"))]);
+        hub.set_trace_hook(hook.clone());
+
+        let parsed: RecvFn<String>          = hub.read_from(&"parsed").get_receiver();
+
+        input.publish(TreeChange::new(&(), &"x,yy".to_string()));
+        hub.flush();
+
+        let spans: Vec<_> = hook.spans().into_iter().map(|span| (span.component, span.published)).collect();
+        assert!(spans == vec![("parser".to_string(), Some(2))]);
+
+        // Nothing is published by a paused component
+        assert!(hub.pause_component("parser"));
+        assert!(hub.is_paused("parser"));
+
+        input.publish(TreeChange::new(&(), &"z".to_string()));
+        hub.flush();
+
+        assert!(parsed() == Some("x|yy".to_string()));
+        assert!(hook.spans().len() == 1);
+
+        assert!(hub.resume_component("parser"));
+        input.publish(TreeChange::new(&(), &"a,b,c".to_string()));
+        hub.flush();
+
+        assert!(parsed() == Some("a|b|c".to_string()));
+    }
+}
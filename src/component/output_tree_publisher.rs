@@ -15,6 +15,7 @@
 //
 
 use std::rc::*;
+use std::cell::*;
 
 use super::super::tree::*;
 use super::super::util::clonecell::*;
@@ -31,16 +32,30 @@ use super::component::*;
 ///
 /// Example:
 /// ```
-/// let consumer  = get_consumer();
+/// # use tametree::prelude::*;
+/// # let mut input     = ImmediatePublisher::new();
+/// # let consumer      = input.create_consumer();
+/// # let some_component = component_fn(|x: &i32| { x+1 });
 /// let publisher = OutputTreePublisher::new();
 /// let reader    = publisher.get_tree_reader();
 ///
-/// some_component.into_component(consumer, publisher);
+/// let _component = some_component.into_component(consumer, publisher);
+/// # input.publish(TreeChange::new(&(), &1));
 /// let tree_value = reader();
+/// # assert!(tree_value.get_value().to_int(0) == 2);
 /// ```
 ///
+/// A publisher created with `new_with_watermarks()` also keeps a watermark for each child of the root node, which
+/// can be read with `get_watermark_reader()`. The watermarks are also published in the output tree as the child
+/// tagged `__watermarks`.
+///
+/// A `ValueHistoryTracker` can be attached with `attach_value_history()` to keep the recent values of selected
+/// nodes in the output tree.
+///
 pub struct OutputTreePublisher {
-    tree: Rc<CloneCell<TreeRef>>
+    tree: Rc<CloneCell<TreeRef>>,
+    watermarks: Option<Rc<RefCell<Watermarks>>>,
+    value_history: Option<Rc<RefCell<ValueHistoryTracker>>>
 }
 
 impl Publisher for OutputTreePublisher {
@@ -48,7 +63,22 @@ impl Publisher for OutputTreePublisher {
     /// Publishes a change to the consumers of this component
     ///
     fn publish(&mut self, change: TreeChange) {
-        self.tree.set(change.apply(&self.tree.get()));
+        let tree_before = self.tree.get();
+        let mut result  = change.apply(&tree_before);
+
+        if let Some(ref value_history) = self.value_history {
+            value_history.borrow_mut().record_change(&tree_before, &change, &result);
+        }
+
+        if let Some(ref watermarks) = self.watermarks {
+            let mut watermarks = watermarks.borrow_mut();
+            watermarks.record_change(&tree_before, &change, &result);
+
+            // Updating the watermark subtree isn't itself recorded as a change
+            result = TreeChange::new(&WATERMARKS_TAG, &watermarks.to_tree_node()).apply(&result);
+        }
+
+        self.tree.set(result);
     }
 }
 
@@ -57,7 +87,29 @@ impl OutputTreePublisher {
     /// Creates a new OutputTreePublisher
     ///
     pub fn new() -> Box<OutputTreePublisher> {
-        Box::new(OutputTreePublisher { tree: Rc::new(CloneCell::new("empty".to_tree_node())) })
+        Box::new(OutputTreePublisher { tree: Rc::new(CloneCell::new("empty".to_tree_node())), watermarks: None, value_history: None })
+    }
+
+    ///
+    /// Creates a new OutputTreePublisher that keeps a watermark for each child of the root node
+    ///
+    pub fn new_with_watermarks() -> Box<OutputTreePublisher> {
+        Self::new_with_watermark_tracker(Watermarks::new())
+    }
+
+    ///
+    /// Creates a new OutputTreePublisher that keeps a watermark for each of a set of address prefixes
+    ///
+    pub fn new_with_watermark_prefixes(prefixes: Vec<TreeAddress>) -> Box<OutputTreePublisher> {
+        Self::new_with_watermark_tracker(Watermarks::with_prefixes(prefixes))
+    }
+
+    fn new_with_watermark_tracker(watermarks: Watermarks) -> Box<OutputTreePublisher> {
+        Box::new(OutputTreePublisher {
+            tree:           Rc::new(CloneCell::new("empty".to_tree_node())),
+            watermarks:     Some(Rc::new(RefCell::new(watermarks))),
+            value_history:  None
+        })
     }
 
     ///
@@ -70,4 +122,70 @@ impl OutputTreePublisher {
             tree_reference.get().clone()
         })
     }
-}
\ No newline at end of file
+
+    ///
+    /// Retrieves a function that reads the watermark for an address, if this publisher is tracking watermarks
+    ///
+    pub fn get_watermark_reader(&self) -> Option<Box<dyn Fn(&TreeAddress) -> u64>> {
+        self.watermarks.clone().map(|watermarks| {
+            let reader: Box<dyn Fn(&TreeAddress) -> u64> = Box::new(move |address| watermarks.borrow().watermark(address));
+            reader
+        })
+    }
+
+    ///
+    /// Starts recording the values of the nodes selected by a tracker as changes are published
+    ///
+    /// Returns a reference to the tracker, so its histories can be read after this publisher has been given to a component.
+    ///
+    pub fn attach_value_history(&mut self, tracker: ValueHistoryTracker) -> Rc<RefCell<ValueHistoryTracker>> {
+        let tracker = Rc::new(RefCell::new(tracker));
+        self.value_history = Some(tracker.clone());
+
+        tracker
+    }
+}
+
+#[cfg(test)]
+mod output_tree_publisher_tests {
+    use super::super::super::tree::*;
+    use super::super::component::*;
+    use super::*;
+
+    #[test]
+    fn publishes_watermarks_with_tree() {
+        let mut publisher   = OutputTreePublisher::new_with_watermarks();
+        let tree_reader     = publisher.get_tree_reader();
+        let mark_reader     = publisher.get_watermark_reader().unwrap();
+
+        publisher.publish(TreeChange::new(&(), &tree!("root", ("a", 1), ("b", 2))));
+        let b_mark = mark_reader(&"b".to_tree_address());
+
+        publisher.publish(TreeChange::new(&"a", &("a", 3)));
+        assert!(mark_reader(&"a".to_tree_address()) > b_mark);
+        assert!(mark_reader(&"b".to_tree_address()) == b_mark);
+
+        let published = tree_reader().get_child_ref_at(WATERMARKS_TAG).unwrap();
+        assert!(published.get_child_at("a").get_value().to_str("") == mark_reader(&"a".to_tree_address()).to_string());
+        assert!(published.get_child_at("b").get_value().to_str("") == b_mark.to_string());
+    }
+
+    #[test]
+    fn records_value_history() {
+        let mut publisher   = OutputTreePublisher::new();
+        let history         = publisher.attach_value_history(ValueHistoryTracker::for_addresses(4, vec!["price".to_tree_address()]));
+
+        publisher.publish(TreeChange::new(&(), &tree!("root", ("price", 10))));
+        publisher.publish(TreeChange::new(&"price", &("price", 0)));
+        publisher.publish(TreeChange::new(&"price", &("price", 10)));
+
+        let recorded: Vec<i32> = history.borrow().history(&"price".to_tree_address()).iter().map(|(_, value)| value.to_int(-1)).collect();
+        assert!(recorded == vec![10, 0, 10]);
+    }
+
+    #[test]
+    fn no_watermarks_by_default() {
+        let publisher = OutputTreePublisher::new();
+        assert!(publisher.get_watermark_reader().is_none());
+    }
+}
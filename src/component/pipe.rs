@@ -22,7 +22,7 @@
 //! to represent the pipe between two components. For example:
 //!
 //! ```
-//! # use tametree::component::*;
+//! # use tametree::prelude::*;
 //! let add_one = component_fn(|x: &i32| { x+1 });
 //! let add_two = component_fn(|x: &i32| { x+2 });
 //! 
@@ -36,7 +36,7 @@
 //! This pipe can be used as a component:
 //!
 //! ```
-//! # use tametree::component::*;
+//! # use tametree::prelude::*;
 //! # let add_one = component_fn(|x: &i32| { x+1 });
 //! # let add_two = component_fn(|x: &i32| { x+2 });
 //! # 
@@ -0,0 +1,298 @@
+//
+//   Copyright 2016 Andrew Hunter
+//
+//   Licensed under the Apache License, Version 2.0 (the "License");
+//   you may not use this file except in compliance with the License.
+//   You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+//   Unless required by applicable law or agreed to in writing, software
+//   distributed under the License is distributed on an "AS IS" BASIS,
+//   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//   See the License for the specific language governing permissions and
+//   limitations under the License.
+//
+
+//!
+//! # Projections
+//!
+//! A projection publishes a view of part of a tree: a set of source addresses, each of which is copied to a
+//! target address. It's kept up to date by passing on the changes made to the sources, moved to their targets,
+//! so a change to one source only updates the corresponding target.
+//!
+//! A mapping can also have a transform, which is applied to each changed subtree before it's published (for
+//! example, to hide some fields or to convert values into a format that's better for display).
+//!
+
+use std::rc::*;
+use std::cell::*;
+use std::fmt;
+
+use super::super::tree::*;
+use super::component::*;
+
+///
+/// Function used to transform the subtrees published by a projection
+///
+pub type ProjectionTransform = Box<dyn Fn(&TreeRef) -> TreeRef>;
+
+///
+/// Maps a source address to a target address in a projection
+///
+pub struct ProjectionMapping {
+    source: TreeAddress,
+    target: TreeAddress,
+    transform: Option<ProjectionTransform>
+}
+
+impl ProjectionMapping {
+    ///
+    /// Creates a mapping that copies the subtree at the source address to the target address
+    ///
+    pub fn new<TSource: ToTreeAddress, TTarget: ToTreeAddress>(source: &TSource, target: &TTarget) -> ProjectionMapping {
+        ProjectionMapping { source: source.to_tree_address(), target: target.to_tree_address(), transform: None }
+    }
+
+    ///
+    /// Sets a transform to apply to the changed subtrees before they are published
+    ///
+    pub fn with_transform<TTransform: Fn(&TreeRef) -> TreeRef + 'static>(mut self, transform: TTransform) -> ProjectionMapping {
+        self.transform = Some(Box::new(transform));
+        self
+    }
+
+    ///
+    /// Moves a change relative to the source of this mapping to its target, transforming it if necessary
+    ///
+    fn project(&self, change: &TreeChange) -> TreeChange {
+        let change = match (&self.transform, change.replacement()) {
+            (Some(transform), TreeReplacement::NewNode(node)) => {
+                TreeChange::new(change.address(), &TreeReplacement::NewNode(transform(node)))
+            },
+
+            (Some(transform), TreeReplacement::NewValue(tag, value)) => {
+                let transformed = transform(&(&**tag, value).to_tree_node());
+                TreeChange::new(change.address(), &TreeReplacement::NewValue(transformed.get_tag().to_string(), transformed.get_value().clone()))
+            },
+
+            _ => change.clone()
+        };
+
+        change.rebased_to(&self.target)
+    }
+}
+
+///
+/// Errors that can occur when creating a projection
+///
+#[derive(Clone, PartialEq)]
+pub enum ProjectionError {
+    /// The target addresses of two mappings overlap (one is the same as or inside the other)
+    OverlappingTargets(TreeAddress, TreeAddress)
+}
+
+impl fmt::Display for ProjectionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ProjectionError::OverlappingTargets(ref first, ref second) => write!(f, "projection targets {} and {} overlap", first, second)
+        }
+    }
+}
+
+impl fmt::Debug for ProjectionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+///
+/// Publishes a projection of the tree read from a consumer
+///
+/// The parents of the target addresses must exist in the tree that the projection is published to, as changes
+/// to a missing node are ignored. The projection stops publishing changes once this object is dropped.
+///
+pub struct ProjectionPublisher {
+    /// Where the projection is published (the consumer callbacks only hold a weak reference to this)
+    _target: Rc<RefCell<PublisherRef>>
+}
+
+impl ProjectionPublisher {
+    ///
+    /// Creates a projection that reads from a consumer and publishes to a publisher
+    ///
+    pub fn new(consumer: ConsumerRef, target: PublisherRef, mappings: Vec<ProjectionMapping>) -> Result<ProjectionPublisher, ProjectionError> {
+        // Targets can't overlap, as changes to one mapping would overwrite the other
+        // (Addresses that can't be compared are assumed to overlap)
+        for (index, mapping) in mappings.iter().enumerate() {
+            for other in mappings[(index+1)..].iter() {
+                let overlaps = mapping.target.is_parent_of(&other.target).unwrap_or(true)
+                    || other.target.is_parent_of(&mapping.target).unwrap_or(true);
+
+                if overlaps {
+                    return Err(ProjectionError::OverlappingTargets(mapping.target.clone(), other.target.clone()));
+                }
+            }
+        }
+
+        let target          = Rc::new(RefCell::new(target));
+        let mut consumer    = consumer;
+
+        for mapping in mappings {
+            let weak_target = Rc::downgrade(&target);
+            let source      = mapping.source.clone();
+
+            consumer.subscribe(source, TreeExtent::SubTree, Box::new(move |change| {
+                if let Some(target) = weak_target.upgrade() {
+                    target.borrow_mut().publish(mapping.project(change));
+                }
+            }));
+        }
+
+        Ok(ProjectionPublisher { _target: target })
+    }
+}
+
+impl Component for ProjectionPublisher {
+}
+
+impl Drop for ProjectionPublisher {
+    fn drop(&mut self) {
+    }
+}
+
+#[cfg(test)]
+mod projection_publisher_tests {
+    use std::rc::*;
+    use std::cell::*;
+
+    use super::super::super::tree::*;
+    use super::super::super::component::*;
+    use super::super::immediate_publisher::*;
+    use super::super::output_tree_publisher::*;
+    use super::*;
+
+    ///
+    /// Publisher that records the address of each change it receives
+    ///
+    struct RecordingPublisher {
+        addresses: Rc<RefCell<Vec<TreeAddress>>>
+    }
+
+    impl Publisher for RecordingPublisher {
+        fn publish(&mut self, change: TreeChange) {
+            self.addresses.borrow_mut().push(change.address().clone());
+        }
+    }
+
+    fn source_tree() -> TreeRef {
+        tree!("root", tree!("cpu", ("load", 1), ("temp", 40)), tree!("disk", ("free", 100)), tree!("net", ("rx", 5)), ("secret", "hunter2"))
+    }
+
+    #[test]
+    fn projection_tracks_sources_incrementally() {
+        let mut source  = ImmediatePublisher::new();
+        let output      = OutputTreePublisher::new();
+        let reader      = output.get_tree_reader();
+        let addresses   = Rc::new(RefCell::new(vec![]));
+        let recorder    = RecordingPublisher { addresses: addresses.clone() };
+
+        let _output_projection = ProjectionPublisher::new(source.create_consumer(), output, vec![
+            ProjectionMapping::new(&"cpu", &"processor"),
+            ProjectionMapping::new(&"disk", &"storage"),
+            ProjectionMapping::new(&"net", &"network")
+        ]).unwrap();
+        let _recording_projection = ProjectionPublisher::new(source.create_consumer(), Box::new(recorder), vec![
+            ProjectionMapping::new(&"cpu", &"processor"),
+            ProjectionMapping::new(&"disk", &"storage"),
+            ProjectionMapping::new(&"net", &"network")
+        ]).unwrap();
+
+        source.publish(TreeChange::new(&(), &source_tree()));
+        assert!(addresses.borrow().len() == 3);
+
+        let tree = reader();
+        assert!(tree.get_child_at("processor").get_child_at("temp").get_value().to_int(0) == 40);
+        assert!(tree.get_child_at("storage").get_child_at("free").get_value().to_int(0) == 100);
+        assert!(tree.get_child_at("network").get_child_at("rx").get_value().to_int(0) == 5);
+        assert!(tree.lookup_child_with_tag("secret").is_none());
+
+        // Changing the disk only updates the storage target
+        addresses.borrow_mut().clear();
+        source.publish(TreeChange::new(&("disk", "free"), &("free", 90)));
+
+        assert!(*addresses.borrow() == vec![("storage", "free").to_tree_address()]);
+        assert!(reader().get_child_at("storage").get_child_at("free").get_value().to_int(0) == 90);
+
+        // Changing something that isn't projected doesn't update anything
+        addresses.borrow_mut().clear();
+        source.publish(TreeChange::new(&"secret", &("secret", "swordfish")));
+        assert!(addresses.borrow().is_empty());
+    }
+
+    #[test]
+    fn transform_only_sees_changed_subtrees() {
+        let mut source      = ImmediatePublisher::new();
+        let output          = OutputTreePublisher::new();
+        let reader          = output.get_tree_reader();
+        let invocations     = Rc::new(Cell::new(0));
+        let their_invocations = invocations.clone();
+
+        // Doubles every integer value in the changed subtree
+        fn double(tree: &TreeRef) -> TreeRef {
+            let children: Vec<TreeRef> = tree.iter_children().map(|child| double(&child)).collect();
+            let node: TreeRef = match *tree.get_value() {
+                TreeValue::Int(value)   => (tree.get_tag(), value * 2).to_tree_node(),
+                ref other               => (tree.get_tag(), other).to_tree_node()
+            };
+
+            if children.is_empty() { node } else { node.with_children(&children) }
+        }
+
+        let _projection = ProjectionPublisher::new(source.create_consumer(), output, vec![
+            ProjectionMapping::new(&"cpu", &"cpu").with_transform(move |tree| { their_invocations.set(their_invocations.get()+1); double(tree) }),
+            ProjectionMapping::new(&"disk", &"disk")
+        ]).unwrap();
+
+        source.publish(TreeChange::new(&(), &source_tree()));
+        assert!(invocations.get() == 1);
+        assert!(reader().get_child_at("cpu").get_child_at("temp").get_value().to_int(0) == 80);
+
+        source.publish(TreeChange::new(&("disk", "free"), &("free", 50)));
+        assert!(invocations.get() == 1);
+
+        source.publish(TreeChange::new(&("cpu", "load"), &("load", 3)));
+        assert!(invocations.get() == 2);
+        assert!(reader().get_child_at("cpu").get_child_at("load").get_value().to_int(0) == 6);
+        assert!(reader().get_child_at("cpu").get_child_at("temp").get_value().to_int(0) == 80);
+    }
+
+    #[test]
+    fn overlapping_targets_are_rejected() {
+        let source = ImmediatePublisher::new();
+
+        let result = ProjectionPublisher::new(source.create_consumer(), OutputTreePublisher::new(), vec![
+            ProjectionMapping::new(&"cpu", &"view"),
+            ProjectionMapping::new(&"disk", &("view", "disk"))
+        ]);
+
+        assert!(result.err() == Some(ProjectionError::OverlappingTargets("view".to_tree_address(), ("view", "disk").to_tree_address())));
+    }
+
+    #[test]
+    fn dropping_projection_stops_publishing() {
+        let mut source  = ImmediatePublisher::new();
+        let addresses   = Rc::new(RefCell::new(vec![]));
+        let projection  = ProjectionPublisher::new(source.create_consumer(), Box::new(RecordingPublisher { addresses: addresses.clone() }), vec![
+            ProjectionMapping::new(&"cpu", &"cpu"),
+            ProjectionMapping::new(&"disk", &"disk")
+        ]).unwrap();
+
+        source.publish(TreeChange::new(&(), &source_tree()));
+        assert!(addresses.borrow().len() == 2);
+
+        drop(projection);
+        source.publish(TreeChange::new(&(), &source_tree()));
+        assert!(addresses.borrow().len() == 2);
+    }
+}
@@ -0,0 +1,372 @@
+//
+//   Copyright 2016 Andrew Hunter
+//
+//   Licensed under the Apache License, Version 2.0 (the "License");
+//   you may not use this file except in compliance with the License.
+//   You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+//   Unless required by applicable law or agreed to in writing, software
+//   distributed under the License is distributed on an "AS IS" BASIS,
+//   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//   See the License for the specific language governing permissions and
+//   limitations under the License.
+//
+
+//!
+//! # Quarantine
+//!
+//! Things that enforce rules on changes, such as the barriers on a bus or a `BudgetedPublisher`, normally drop the
+//! changes that break them. When a `Quarantine` is attached, the changes are kept instead, along with the reason
+//! they were stopped and the name of the thing that stopped them. Someone can then look at them and decide to
+//! release them (publishing them again, optionally skipping the check that stopped them) or to discard them.
+//!
+//! A quarantine is shared: cloning it produces another reference to the same queue, so a single quarantine can
+//! collect the changes stopped by several enforcement points. Each enforcement point registers itself as a source
+//! with a function that re-publishes the changes that are released.
+//!
+//! The queue is bounded. When it's full, the overflow policy decides whether the oldest change is dropped to make
+//! room or the new change is rejected (which drops the new change, as would happen with no quarantine at all).
+//!
+
+use std::rc::*;
+use std::cell::*;
+use std::collections::VecDeque;
+
+use super::super::tree::*;
+
+///
+/// The tag of the root node of the tree generated by `Quarantine::as_tree()`
+///
+pub const QUARANTINE_TAG: &str = "__quarantine";
+
+///
+/// What a quarantine does when a change arrives and it's already full
+///
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum QuarantineOverflow {
+    /// Drop the change that has been in the quarantine the longest to make room for the new one
+    DropOldest,
+
+    /// Drop the new change
+    RejectNew
+}
+
+///
+/// A change held in a quarantine
+///
+#[derive(Clone)]
+pub struct QuarantinedChange {
+    /// Identifies this change in the quarantine
+    pub id: u64,
+
+    /// The change that was stopped
+    pub change: TreeChange,
+
+    /// Why the change was stopped
+    pub reason: String,
+
+    /// The name of the enforcement point that stopped the change
+    pub source: String
+}
+
+///
+/// Counts what has happened to the changes sent to a quarantine
+///
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct QuarantineStats {
+    /// The number of changes that have been put in the quarantine
+    pub quarantined: usize,
+
+    /// The number of changes that have been released
+    pub released: usize,
+
+    /// The number of changes that have been discarded
+    pub discarded: usize,
+
+    /// The number of changes that were dropped to make room for newer ones
+    pub dropped_oldest: usize,
+
+    /// The number of changes that were rejected because the quarantine was full
+    pub rejected_new: usize
+}
+
+///
+/// Function called to re-publish a change released from a quarantine. The flag is true if the change should skip
+/// the check that stopped it.
+///
+pub type ReleaseCallback = Box<dyn FnMut(TreeChange, bool)>;
+
+///
+/// The shared state of a quarantine
+///
+struct QuarantineState {
+    capacity: usize,
+    overflow: QuarantineOverflow,
+    next_id: u64,
+    entries: VecDeque<QuarantinedChange>,
+    stats: QuarantineStats,
+
+    /// The functions that re-publish released changes for each source
+    sources: Vec<(String, Rc<RefCell<ReleaseCallback>>)>
+}
+
+///
+/// A bounded queue of changes stopped by enforcement points, waiting to be released or discarded
+///
+#[derive(Clone)]
+pub struct Quarantine {
+    state: Rc<RefCell<QuarantineState>>
+}
+
+impl Quarantine {
+    ///
+    /// Creates a quarantine that can hold up to `capacity` changes
+    ///
+    pub fn new(capacity: usize, overflow: QuarantineOverflow) -> Quarantine {
+        Quarantine { state: Rc::new(RefCell::new(QuarantineState {
+            capacity,
+            overflow,
+            next_id:    0,
+            entries:    VecDeque::new(),
+            stats:      QuarantineStats::default(),
+            sources:    vec![]
+        })) }
+    }
+
+    ///
+    /// Registers an enforcement point, along with the function used to re-publish the changes it stopped when
+    /// they're released
+    ///
+    /// Registering a source with the same name again replaces its release function.
+    ///
+    pub fn add_source(&self, source: &str, release: ReleaseCallback) {
+        let mut state = self.state.borrow_mut();
+
+        state.sources.retain(|(name, _)| name != source);
+        state.sources.push((source.to_string(), Rc::new(RefCell::new(release))));
+    }
+
+    ///
+    /// Puts a change in this quarantine, returning its ID, or None if it was rejected because the quarantine is full
+    ///
+    pub fn quarantine(&self, change: TreeChange, reason: &str, source: &str) -> Option<u64> {
+        let mut state = self.state.borrow_mut();
+
+        if state.entries.len() >= state.capacity {
+            match state.overflow {
+                QuarantineOverflow::RejectNew   => {
+                    state.stats.rejected_new += 1;
+                    return None;
+                },
+
+                QuarantineOverflow::DropOldest  => {
+                    if state.entries.pop_front().is_none() {
+                        // A quarantine with no capacity can't hold anything
+                        state.stats.rejected_new += 1;
+                        return None;
+                    }
+
+                    state.stats.dropped_oldest += 1;
+                }
+            }
+        }
+
+        let id = state.next_id;
+        state.next_id += 1;
+        state.stats.quarantined += 1;
+        state.entries.push_back(QuarantinedChange { id, change, reason: reason.to_string(), source: source.to_string() });
+
+        Some(id)
+    }
+
+    ///
+    /// The changes currently in this quarantine, oldest first
+    ///
+    pub fn pending(&self) -> Vec<QuarantinedChange> {
+        self.state.borrow().entries.iter().cloned().collect()
+    }
+
+    ///
+    /// Removes a change from the quarantine, returning it if it was there
+    ///
+    fn take(&self, id: u64) -> Option<QuarantinedChange> {
+        let mut state = self.state.borrow_mut();
+        let index     = state.entries.iter().position(|entry| entry.id == id)?;
+
+        state.entries.remove(index)
+    }
+
+    ///
+    /// Releases a change, publishing it again through the source that stopped it
+    ///
+    fn release_change(&mut self, id: u64, bypass_check: bool) -> bool {
+        // The release function isn't available if the change's source never registered one
+        let release = {
+            let state   = self.state.borrow();
+            let source  = state.entries.iter().find(|entry| entry.id == id).map(|entry| entry.source.clone());
+
+            source.and_then(|source| state.sources.iter().find(|(name, _)| *name == source).map(|(_, release)| release.clone()))
+        };
+
+        let release = match release {
+            Some(release)   => release,
+            None            => return false
+        };
+
+        match self.take(id) {
+            Some(entry) => {
+                self.state.borrow_mut().stats.released += 1;
+
+                // The state isn't borrowed here, so the change can be quarantined again
+                (*release.borrow_mut())(entry.change, bypass_check);
+                true
+            },
+
+            None => false
+        }
+    }
+
+    ///
+    /// Releases a change from the quarantine, publishing it again. The change is checked again and may end up
+    /// back in the quarantine.
+    ///
+    /// Returns false if the change isn't in the quarantine or its source can't re-publish it.
+    ///
+    pub fn release(&mut self, id: u64) -> bool {
+        self.release_change(id, false)
+    }
+
+    ///
+    /// Releases a change from the quarantine, publishing it again without the check that stopped it
+    ///
+    /// Returns false if the change isn't in the quarantine or its source can't re-publish it.
+    ///
+    pub fn release_with_bypass(&mut self, id: u64) -> bool {
+        self.release_change(id, true)
+    }
+
+    ///
+    /// Removes a change from the quarantine without publishing it. Returns false if the change isn't in the quarantine.
+    ///
+    pub fn discard(&mut self, id: u64) -> bool {
+        if self.take(id).is_some() {
+            self.state.borrow_mut().stats.discarded += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    ///
+    /// Counts what has happened to the changes sent to this quarantine
+    ///
+    pub fn stats(&self) -> QuarantineStats {
+        self.state.borrow().stats
+    }
+
+    ///
+    /// Generates a tree describing this quarantine, which can be published for remote inspection
+    ///
+    /// The tree has a `stats` node and a `pending` node with a child for each change, tagged with its ID.
+    ///
+    pub fn as_tree(&self) -> TreeRef {
+        let state   = self.state.borrow();
+        let stats   = state.stats;
+        let entries: Vec<TreeRef> = state.entries.iter()
+            .map(|entry| entry.id.to_string().as_str().to_tree_node().with_children(&vec![
+                ("source", entry.source.as_str()).to_tree_node(),
+                ("reason", entry.reason.as_str()).to_tree_node(),
+                ("address", entry.change.address().to_string()).to_tree_node()
+            ]))
+            .collect();
+
+        let stats = "stats".to_tree_node().with_children(&vec![
+            ("quarantined", stats.quarantined as i32).to_tree_node(),
+            ("released", stats.released as i32).to_tree_node(),
+            ("discarded", stats.discarded as i32).to_tree_node(),
+            ("dropped_oldest", stats.dropped_oldest as i32).to_tree_node(),
+            ("rejected_new", stats.rejected_new as i32).to_tree_node(),
+            ("overflow", format!("{:?}", state.overflow)).to_tree_node()
+        ]);
+
+        QUARANTINE_TAG.to_tree_node().with_children(&vec![stats, "pending".to_tree_node().with_children(&entries)])
+    }
+}
+
+#[cfg(test)]
+mod quarantine_tests {
+    use std::rc::*;
+    use std::cell::*;
+
+    use super::super::super::tree::*;
+    use super::*;
+
+    fn recording_source(quarantine: &Quarantine, source: &str) -> Rc<RefCell<Vec<(TreeChange, bool)>>> {
+        let released        = Rc::new(RefCell::new(vec![]));
+        let also_released   = released.clone();
+
+        quarantine.add_source(source, Box::new(move |change, bypass| also_released.borrow_mut().push((change, bypass))));
+        released
+    }
+
+    #[test]
+    fn release_and_discard() {
+        let mut quarantine  = Quarantine::new(10, QuarantineOverflow::RejectNew);
+        let released        = recording_source(&quarantine, "test");
+
+        let first   = quarantine.quarantine(TreeChange::new(&"a", &1), "too big", "test").unwrap();
+        let second  = quarantine.quarantine(TreeChange::new(&"b", &2), "too deep", "test").unwrap();
+
+        assert!(quarantine.release_with_bypass(first));
+        assert!(!quarantine.release(first));
+        assert!(quarantine.discard(second));
+        assert!(!quarantine.discard(second));
+
+        assert!(quarantine.pending().is_empty());
+        assert!(released.borrow().len() == 1);
+        assert!(released.borrow()[0].1);
+        assert!(*released.borrow()[0].0.address() == "a".to_tree_address());
+
+        let stats = quarantine.stats();
+        assert!(stats.quarantined == 2 && stats.released == 1 && stats.discarded == 1);
+    }
+
+    #[test]
+    fn reject_new_when_full() {
+        let quarantine = Quarantine::new(2, QuarantineOverflow::RejectNew);
+
+        assert!(quarantine.quarantine(TreeChange::new(&"a", &1), "", "test").is_some());
+        assert!(quarantine.quarantine(TreeChange::new(&"b", &2), "", "test").is_some());
+        assert!(quarantine.quarantine(TreeChange::new(&"c", &3), "", "test").is_none());
+
+        let addresses: Vec<TreeAddress> = quarantine.pending().iter().map(|entry| entry.change.address().clone()).collect();
+        assert!(addresses == vec!["a".to_tree_address(), "b".to_tree_address()]);
+        assert!(quarantine.stats().rejected_new == 1);
+        assert!(quarantine.stats().dropped_oldest == 0);
+    }
+
+    #[test]
+    fn drop_oldest_when_full() {
+        let quarantine = Quarantine::new(2, QuarantineOverflow::DropOldest);
+
+        quarantine.quarantine(TreeChange::new(&"a", &1), "", "test");
+        quarantine.quarantine(TreeChange::new(&"b", &2), "", "test");
+        quarantine.quarantine(TreeChange::new(&"c", &3), "", "test");
+
+        let addresses: Vec<TreeAddress> = quarantine.pending().iter().map(|entry| entry.change.address().clone()).collect();
+        assert!(addresses == vec!["b".to_tree_address(), "c".to_tree_address()]);
+        assert!(quarantine.stats().dropped_oldest == 1);
+        assert!(quarantine.stats().rejected_new == 0);
+    }
+
+    #[test]
+    fn release_without_source_fails() {
+        let mut quarantine  = Quarantine::new(2, QuarantineOverflow::DropOldest);
+        let id              = quarantine.quarantine(TreeChange::new(&"a", &1), "", "nowhere").unwrap();
+
+        assert!(!quarantine.release(id));
+        assert!(quarantine.pending().len() == 1);
+    }
+}
@@ -0,0 +1,680 @@
+//
+//   Copyright 2016 Andrew Hunter
+//
+//   Licensed under the Apache License, Version 2.0 (the "License");
+//   you may not use this file except in compliance with the License.
+//   You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+//   Unless required by applicable law or agreed to in writing, software
+//   distributed under the License is distributed on an "AS IS" BASIS,
+//   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//   See the License for the specific language governing permissions and
+//   limitations under the License.
+//
+
+//!
+//! # Redaction
+//!
+//! When a tree is passed on to something outside of the application, some parts of it (passwords, keys and the
+//! like) shouldn't go with it. A `RedactingConsumer` wraps a consumer so that its subscriptions only see a redacted
+//! version of the tree, and a `RedactingPublisher` does the same for the changes passed through a publisher.
+//!
+//! What's redacted is decided by `RedactionRules`. Each rule has a pattern, such as `config.secrets` or
+//! `users.*.password`, and a policy: the nodes it matches can be left out entirely, have their values replaced by
+//! a placeholder, or have their values replaced by a hash (so that changes can still be noticed without revealing
+//! the value). Tags are left as they are by the last two policies. The parts of a pattern are separated by `.`:
+//! numbers match the child at that index, `*` matches any child and anything else matches a tag.
+//!
+//! Every change is redacted, whatever form it takes: new subtrees have any redacted nodes inside them filtered
+//! out (rebuilding only the nodes leading to them), changes inside redacted nodes are dropped or have their values
+//! replaced, and the indexes in addresses are adjusted for the nodes that are left out, so the receiver always
+//! ends up with the same tree as `redact_tree()` would produce. `redact_tree()` can also be used to redact a
+//! snapshot of a tree, for instance to send as the initial state.
+//!
+//! ```
+//! # use tametree::prelude::*;
+//! # use tametree::component::redaction::*;
+//! let rules       = RedactionRules::new(vec![RedactionRule::new("secrets", RedactionPolicy::Omit)]);
+//! let redacted    = rules.redact_tree(&tree!("config", ("name", "app"), tree!("secrets", ("token", "abc"))));
+//!
+//! assert!(redacted.get_child_ref_at("secrets").is_none());
+//! assert!(redacted.get_child_at("name").get_value().to_str("") == "app");
+//! ```
+//!
+
+use std::rc::*;
+
+use super::super::tree::*;
+use super::component::*;
+use super::adaptive_filter::*;
+
+///
+/// What happens to the nodes matched by a redaction rule
+///
+#[derive(Clone, PartialEq, Debug)]
+pub enum RedactionPolicy {
+    /// The node and everything inside it is left out
+    Omit,
+
+    /// The values of the node and everything inside it are replaced by a string
+    Placeholder(String),
+
+    /// The values of the node and everything inside it are replaced by a hash of the value
+    Hash
+}
+
+///
+/// A part of the pattern for a redaction rule
+///
+#[derive(Clone, PartialEq, Debug)]
+enum PatternPart {
+    Tag(String),
+    Index(usize),
+    Any
+}
+
+///
+/// A pattern matching the nodes to redact, along with what to do with them
+///
+#[derive(Clone, PartialEq, Debug)]
+pub struct RedactionRule {
+    pattern: Vec<PatternPart>,
+    policy: RedactionPolicy
+}
+
+impl RedactionRule {
+    ///
+    /// Creates a rule from a pattern like `users.*.password`
+    ///
+    pub fn new(pattern: &str, policy: RedactionPolicy) -> RedactionRule {
+        let pattern = pattern.split('.')
+            .filter(|part| !part.is_empty())
+            .map(|part| match part.parse::<usize>() {
+                Ok(index)   => PatternPart::Index(index),
+                Err(_)      => if part == "*" { PatternPart::Any } else { PatternPart::Tag(part.to_string()) }
+            })
+            .collect();
+
+        RedactionRule { pattern, policy }
+    }
+
+    ///
+    /// Creates a rule matching the node at a single address
+    ///
+    pub fn at<TAddress: ToTreeAddress>(address: &TAddress, policy: RedactionPolicy) -> RedactionRule {
+        let pattern = flatten_address(&address.to_tree_address()).into_iter()
+            .map(|part| match part {
+                AddressPart::Index(index)   => PatternPart::Index(index),
+                AddressPart::Tag(tag)       => PatternPart::Tag(tag)
+            })
+            .collect();
+
+        RedactionRule { pattern, policy }
+    }
+
+    ///
+    /// True if this rule matches the node at the end of a path
+    ///
+    fn matches(&self, path: &[PathStep]) -> bool {
+        self.pattern.len() == path.len() && self.pattern.iter().zip(path.iter()).all(|(part, (tag, index))| match *part {
+            PatternPart::Tag(ref pattern_tag)   => pattern_tag == tag,
+            PatternPart::Index(pattern_index)   => pattern_index == *index,
+            PatternPart::Any                    => true
+        })
+    }
+}
+
+///
+/// A step along the path to a node: its tag and its index within its parent
+///
+type PathStep = (String, usize);
+
+///
+/// Finds the tag and index of each node along an address
+///
+fn resolve_path(tree: &TreeRef, parts: &[AddressPart]) -> Option<Vec<PathStep>> {
+    let mut path = vec![];
+    let mut node = tree.clone();
+
+    for part in parts {
+        let (index, child) = node.iter_children().enumerate().find(|(index, child)| match *part {
+            AddressPart::Index(part_index)  => *index == part_index,
+            AddressPart::Tag(ref tag)       => child.get_tag() == tag
+        })?;
+
+        path.push((child.get_tag().to_string(), index));
+        node = child;
+    }
+
+    Some(path)
+}
+
+///
+/// Finds the node at the end of a path
+///
+fn node_at_path(tree: &TreeRef, path: &[PathStep]) -> Option<TreeRef> {
+    path.iter().try_fold(tree.clone(), |node, (_, index)| node.lookup_child_at_index(*index))
+}
+
+///
+/// Converts a list of address parts back into an address
+///
+fn address_from_parts(parts: Vec<AddressPart>) -> TreeAddress {
+    parts.into_iter().rev().fold(TreeAddress::Here, |address, part| match part {
+        AddressPart::Index(index)   => TreeAddress::ChildAtIndex(index, Box::new(address)),
+        AddressPart::Tag(tag)       => TreeAddress::ChildWithTag(tag, Box::new(address))
+    })
+}
+
+///
+/// Hashes the contents of a value (using FNV-1a, so the result is the same every time)
+///
+fn hash_value(value: &TreeValue) -> String {
+    let bytes = match *value {
+        TreeValue::Nothing          => vec![b'n'],
+        TreeValue::Bool(value)      => vec![b'b', value as u8],
+        TreeValue::Int(value)       => format!("i{}", value).into_bytes(),
+        TreeValue::Real(value)      => format!("r{:x}", value.to_bits()).into_bytes(),
+        TreeValue::String(ref text) => [b"s", text.as_bytes()].concat(),
+        TreeValue::Data(ref data)   => [b"d", &**data].concat()
+    };
+
+    let hash = bytes.iter().fold(0xcbf29ce484222325u64, |hash, byte| (hash ^ (*byte as u64)).wrapping_mul(0x100000001b3));
+    format!("hash:{:016x}", hash)
+}
+
+///
+/// Applies a policy to a value (values that are nothing are left alone)
+///
+fn redact_value(policy: &RedactionPolicy, value: &TreeValue) -> TreeValue {
+    match (policy, value) {
+        (_, TreeValue::Nothing)                         => TreeValue::Nothing,
+        (RedactionPolicy::Placeholder(placeholder), _)  => placeholder.to_tree_value(),
+        (RedactionPolicy::Hash, value)                  => hash_value(value).to_tree_value(),
+        (RedactionPolicy::Omit, _)                      => TreeValue::Nothing
+    }
+}
+
+///
+/// Applies a policy to the values of a node and all of the nodes inside it (the result has no siblings)
+///
+fn redact_all(node: &TreeRef, policy: &RedactionPolicy) -> TreeRef {
+    let children: Vec<TreeRef>  = node.iter_children().map(|child| redact_all(&child, policy)).collect();
+    let redacted: TreeRef       = Rc::new(BasicTree::new(node.get_tag(), redact_value(policy, node.get_value()), None, None));
+
+    redacted.with_children(&children)
+}
+
+///
+/// A set of redaction rules
+///
+/// When more than one rule matches the same node, the first one is used. When rules match both a node and
+/// something inside it, the rule for the outer node is used.
+///
+#[derive(Clone)]
+pub struct RedactionRules {
+    rules: Rc<Vec<RedactionRule>>
+}
+
+impl RedactionRules {
+    ///
+    /// Creates a set of redaction rules
+    ///
+    pub fn new(rules: Vec<RedactionRule>) -> RedactionRules {
+        RedactionRules { rules: Rc::new(rules) }
+    }
+
+    ///
+    /// The policy for the rule that matches the node at the end of a path, ignoring the nodes that contain it
+    ///
+    fn rule_at(&self, path: &[PathStep]) -> Option<&RedactionPolicy> {
+        self.rules.iter().find(|rule| rule.matches(path)).map(|rule| &rule.policy)
+    }
+
+    ///
+    /// The policy that applies to the node at the end of a path, which may come from a rule for a node that contains it
+    ///
+    fn policy_for(&self, path: &[PathStep]) -> Option<&RedactionPolicy> {
+        (1..=path.len()).filter_map(|len| self.rule_at(&path[0..len])).next()
+    }
+
+    ///
+    /// Creates a redacted copy of a tree
+    ///
+    /// Parts of the tree that don't contain anything to redact are shared with the original. The root node is
+    /// never redacted.
+    ///
+    pub fn redact_tree(&self, tree: &TreeRef) -> TreeRef {
+        self.filter_node(tree, &mut vec![])
+    }
+
+    ///
+    /// Redacts the nodes inside a node at a particular path, returning a node with no siblings
+    ///
+    fn filter_node(&self, node: &TreeRef, path: &mut Vec<PathStep>) -> TreeRef {
+        let filtered = self.filter_descendants(node, path).unwrap_or_else(|| node.clone());
+
+        if filtered.get_sibling_ref().is_some() { filtered.with_sibling_node(None) } else { filtered }
+    }
+
+    ///
+    /// Redacts the nodes inside a node, or returns None if there's nothing to redact
+    ///
+    fn filter_descendants(&self, node: &TreeRef, path: &mut Vec<PathStep>) -> Option<TreeRef> {
+        let children: Vec<TreeRef> = node.iter_children().collect();
+
+        // For each child, None if it's unchanged, Some(None) if it's left out and Some(Some(node)) if it's replaced
+        let mut filtered        = vec![];
+        let mut last_changed    = None;
+
+        for (index, child) in children.iter().enumerate() {
+            path.push((child.get_tag().to_string(), index));
+
+            let result = match self.rule_at(path) {
+                Some(RedactionPolicy::Omit) => Some(None),
+                Some(policy)                => Some(Some(redact_all(child, policy))),
+                None                        => self.filter_descendants(child, path).map(Some)
+            };
+
+            path.pop();
+
+            if result.is_some() {
+                last_changed = Some(index);
+            }
+            filtered.push(result);
+        }
+
+        // The children after the last one that changed can be kept as they are, along with their sibling links
+        let last_changed    = last_changed?;
+        let mut chain       = children.get(last_changed+1).cloned();
+
+        for index in (0..=last_changed).rev() {
+            match filtered[index] {
+                Some(None)              => { },
+                Some(Some(ref child))   => chain = Some(child.with_sibling_node(chain.as_ref())),
+                None                    => chain = Some(children[index].with_sibling_node(chain.as_ref()))
+            }
+        }
+
+        Some(node.with_child_node(chain.as_ref()))
+    }
+
+    ///
+    /// Converts an address in a tree into the equivalent address in its redacted version, adjusting the indexes
+    /// for the nodes that are left out
+    ///
+    fn redacted_address(&self, tree: &TreeRef, parts: &[AddressPart], path: &[PathStep]) -> TreeAddress {
+        let mut node        = tree.clone();
+        let mut redacted    = vec![];
+
+        for (depth, part) in parts.iter().enumerate() {
+            let index = path[depth].1;
+
+            match *part {
+                AddressPart::Tag(ref tag)   => redacted.push(AddressPart::Tag(tag.clone())),
+                AddressPart::Index(_)       => {
+                    let mut sibling_path    = path[0..depth].to_vec();
+                    let omitted             = node.iter_children().take(index).enumerate().filter(|(sibling_index, sibling)| {
+                        sibling_path.push((sibling.get_tag().to_string(), *sibling_index));
+                        let is_omitted = self.rule_at(&sibling_path) == Some(&RedactionPolicy::Omit);
+                        sibling_path.pop();
+
+                        is_omitted
+                    }).count();
+
+                    redacted.push(AddressPart::Index(index - omitted));
+                }
+            }
+
+            node = match node.lookup_child_at_index(index) {
+                Some(child) => child,
+                None        => break
+            };
+        }
+
+        address_from_parts(redacted)
+    }
+
+    ///
+    /// Redacts a change, given the tree before and after it was applied
+    ///
+    /// The result is the change to make to the redacted version of the tree, or None if the change only affects
+    /// parts of the tree that are left out.
+    ///
+    pub fn redact_change(&self, old_tree: &TreeRef, new_tree: &TreeRef, change: &TreeChange) -> Option<TreeChange> {
+        let parts = flatten_address(change.address());
+
+        if parts.is_empty() {
+            // The root is never redacted
+            return match *change.replacement() {
+                TreeReplacement::NewNode(_) => Some(TreeChange::new(&(), &self.redact_tree(new_tree))),
+                _                           => Some(change.clone())
+            };
+        }
+
+        // Removed nodes are found in the old tree, others in the new tree
+        let is_remove   = matches!(*change.replacement(), TreeReplacement::Remove);
+        let tree        = if is_remove { old_tree } else { new_tree };
+        let path        = match resolve_path(tree, &parts) {
+            Some(path)  => path,
+            None        => return self.redact_parent_change(new_tree, &parts)
+        };
+
+        // If the change alters whether or not the node is redacted, or adds or removes siblings, the whole parent is sent
+        let policy      = self.policy_for(&path).cloned();
+        let old_policy  = if is_remove { policy.clone() } else { resolve_path(old_tree, &parts).and_then(|old_path| self.policy_for(&old_path).cloned()) };
+        let adds_nodes  = change.replaces_following() || match *change.replacement() {
+            TreeReplacement::NewNode(ref node)  => node.get_sibling_ref().is_some(),
+            _                                   => false
+        };
+
+        if policy != old_policy || adds_nodes {
+            return self.redact_parent_change(new_tree, &parts);
+        }
+
+        let address = self.redacted_address(tree, &parts, &path);
+
+        let replacement = match (policy, change.replacement()) {
+            (Some(RedactionPolicy::Omit), _)                            => return None,
+            (_, TreeReplacement::Remove)                                => TreeReplacement::Remove,
+            (Some(policy), TreeReplacement::NewValue(tag, value))       => TreeReplacement::NewValue(tag.clone(), redact_value(&policy, value)),
+            (Some(policy), TreeReplacement::NewNode(node))              => TreeReplacement::NewNode(redact_all(node, &policy)),
+            (None, TreeReplacement::NewNode(node))                      => TreeReplacement::NewNode(self.filter_node(node, &mut path.clone())),
+
+            (None, TreeReplacement::NewValue(tag, value))               => {
+                // Renaming a node can change which of the nodes inside it are redacted, so it's sent again
+                let old_tag = resolve_path(old_tree, &parts).and_then(|old_path| old_path.last().map(|(tag, _)| tag.clone()));
+
+                if old_tag.as_ref() == Some(tag) {
+                    TreeReplacement::NewValue(tag.clone(), value.clone())
+                } else {
+                    TreeReplacement::NewNode(self.filter_node(&node_at_path(tree, &path)?, &mut path.clone()))
+                }
+            }
+        };
+
+        Some(TreeChange::new(&address, &replacement))
+    }
+
+    ///
+    /// Creates a change that replaces the parent of the node at an address with its redacted version
+    ///
+    fn redact_parent_change(&self, new_tree: &TreeRef, parts: &[AddressPart]) -> Option<TreeChange> {
+        let parent_parts    = &parts[0..parts.len()-1];
+        let path            = resolve_path(new_tree, parent_parts);
+
+        match (parent_parts.is_empty(), path) {
+            (false, Some(path)) => {
+                let parent  = node_at_path(new_tree, &path)?;
+                let address = self.redacted_address(new_tree, parent_parts, &path);
+
+                match self.policy_for(&path) {
+                    Some(RedactionPolicy::Omit) => None,
+                    Some(policy)                => Some(TreeChange::new(&address, &redact_all(&parent, policy))),
+                    None                        => Some(TreeChange::new(&address, &self.filter_node(&parent, &mut path.clone())))
+                }
+            },
+
+            // Send the whole tree if the parent is the root or can't be found
+            _ => Some(TreeChange::new(&(), &self.redact_tree(new_tree)))
+        }
+    }
+}
+
+///
+/// Keeps track of a tree so that the changes made to it can be redacted
+///
+struct Redactor {
+    rules: RedactionRules,
+    tree: TreeRef
+}
+
+impl Redactor {
+    fn new(rules: RedactionRules) -> Redactor {
+        Redactor { rules, tree: "empty".to_tree_node() }
+    }
+
+    ///
+    /// Applies a change to the tree, returning the redacted version of it
+    ///
+    fn redact(&mut self, change: &TreeChange) -> Option<TreeChange> {
+        let new_tree    = change.apply(&self.tree);
+        let redacted    = self.rules.redact_change(&self.tree, &new_tree, change);
+
+        self.tree = new_tree;
+        redacted
+    }
+}
+
+///
+/// A consumer whose subscriptions only see a redacted version of the tree read by another consumer
+///
+pub struct RedactingConsumer {
+    source: ConsumerRef,
+    rules: RedactionRules
+}
+
+impl RedactingConsumer {
+    ///
+    /// Creates a consumer that redacts the tree read by another consumer
+    ///
+    pub fn new(source: ConsumerRef, rules: RedactionRules) -> Box<RedactingConsumer> {
+        Box::new(RedactingConsumer { source, rules })
+    }
+}
+
+impl Consumer for RedactingConsumer {
+    ///
+    /// Calls a function whenever a particular section of the redacted tree has changed
+    ///
+    fn subscribe(&mut self, address: TreeAddress, extent: TreeExtent, callback: ConsumerCallback) {
+        // Addresses are redacted relative to the whole tree, so every subscription watches all of it
+        let mut redactor = Redactor::new(self.rules.clone());
+        let mut callback = callback;
+
+        self.source.subscribe(TreeAddress::Here, TreeExtent::SubTree, Box::new(move |change| {
+            let redacted = match redactor.redact(change) {
+                Some(redacted)  => redacted,
+                None            => return
+            };
+
+            if redacted.applies_to(&address, &extent).unwrap_or(true) {
+                if let Some(relative_change) = redacted.relative_to(&address) {
+                    callback(&relative_change);
+                }
+            }
+        }));
+    }
+}
+
+///
+/// A publisher that redacts the changes published through it before passing them on to another publisher
+///
+pub struct RedactingPublisher {
+    target: PublisherRef,
+    redactor: Redactor
+}
+
+impl RedactingPublisher {
+    ///
+    /// Creates a publisher that passes redacted changes on to another publisher
+    ///
+    pub fn new(target: PublisherRef, rules: RedactionRules) -> Box<RedactingPublisher> {
+        Box::new(RedactingPublisher { target, redactor: Redactor::new(rules) })
+    }
+}
+
+impl Publisher for RedactingPublisher {
+    ///
+    /// Publishes the redacted version of a change
+    ///
+    fn publish(&mut self, change: TreeChange) {
+        if let Some(redacted) = self.redactor.redact(&change) {
+            self.target.publish(redacted);
+        }
+    }
+}
+
+#[cfg(test)]
+mod redaction_tests {
+    use std::rc::*;
+    use std::cell::*;
+
+    use super::super::super::tree::*;
+    use super::super::super::component::*;
+    use super::super::immediate_publisher::*;
+    use super::*;
+
+    fn child_tags(node: &TreeRef) -> Vec<String> {
+        node.iter_children().map(|child| child.get_tag().to_string()).collect()
+    }
+
+    fn rules() -> RedactionRules {
+        RedactionRules::new(vec![
+            RedactionRule::new("config.secrets", RedactionPolicy::Omit),
+            RedactionRule::new("users.*.password", RedactionPolicy::Placeholder("<redacted>".to_string()))
+        ])
+    }
+
+    fn sample() -> TreeRef {
+        tree!("root",
+            tree!("config", ("name", "app"), tree!("secrets", ("token", "hunter2")), ("port", 80)),
+            tree!("users", tree!("alice", ("password", "hunter2"), ("email", "alice@example.com"))))
+    }
+
+    ///
+    /// The text of everything a change could reveal
+    ///
+    fn change_text(change: &TreeChange) -> String {
+        match *change.replacement() {
+            TreeReplacement::Remove                         => String::new(),
+            TreeReplacement::NewNode(ref node)              => to_tree_text(node),
+            TreeReplacement::NewValue(ref tag, ref value)   => to_tree_text(&(&**tag, value).to_tree_node())
+        }
+    }
+
+    #[test]
+    fn secrets_never_reach_subscriber() {
+        let mut source      = ImmediatePublisher::new();
+        let mut redacting   = RedactingConsumer::new(source.create_consumer(), rules());
+        let delivered       = Rc::new(RefCell::new(vec![]));
+        let also_delivered  = delivered.clone();
+
+        redacting.subscribe(TreeAddress::Here, TreeExtent::SubTree, Box::new(move |change| also_delivered.borrow_mut().push(change.clone())));
+
+        let changes = vec![
+            // Full replacement
+            TreeChange::new(&(), &sample()),
+
+            // Incremental changes inside and around the secrets
+            TreeChange::new(&("config", ("secrets", "token")), &TreeReplacement::NewValue("token".to_string(), "hunter2".to_tree_value())),
+            TreeChange::new(&("config", 2), &TreeReplacement::NewValue("port".to_string(), 8080.to_tree_value())),
+            TreeChange::new(&("users", ("alice", "password")), &("password", "hunter2")),
+            TreeChange::new(&("users", "bob"), &tree!("bob", ("password", "hunter2"))),
+
+            // Renaming a node so that it starts matching a rule, then changing the indexes of the nodes around it
+            TreeChange::new(&("config", "public"), &tree!("public", ("token", "visible"))),
+            TreeChange::new(&("config", 3), &TreeReplacement::NewValue("secrets".to_string(), ().to_tree_value())),
+            TreeChange::new(&("config", 0), &TreeReplacement::Remove),
+            TreeChange::new(&("config", 2), &TreeReplacement::Remove),
+
+            // Replacing every user, as a legacy child change does
+            TreeChange::new(&("users", 0), &tree!("carol", ("password", "hunter2"), ("email", "carol@example.com"))).with_replaces_following(true)
+        ];
+
+        let mut full = "empty".to_tree_node();
+        let mut view = "empty".to_tree_node();
+
+        for change in changes {
+            let before = delivered.borrow().len();
+
+            full = change.apply(&full);
+            source.publish(change);
+
+            for change in delivered.borrow()[before..].iter() {
+                view = change.apply(&view);
+            }
+
+            // The subscriber always has the same tree as a redacted copy of the full tree
+            assert!(to_tree_text(&view) == to_tree_text(&rules().redact_tree(&full)));
+        }
+
+        assert!(delivered.borrow().iter().all(|change| !change_text(change).contains("hunter2")));
+        assert!(to_tree_text(&view).contains("<redacted>"));
+        assert!(view.get_child_at("config").get_child_ref_at("secrets").is_none());
+        assert!(child_tags(&view.get_child_at("config")) == vec!["port"]);
+        assert!(view.get_child_at("config").get_child_at(0).get_value().to_int(0) == 8080);
+        assert!(child_tags(&view.get_child_at("users")) == vec!["carol"]);
+    }
+
+    #[test]
+    fn unredacted_parts_are_shared() {
+        let tree        = sample();
+        let redacted    = rules().redact_tree(&tree);
+        let config      = tree.get_child_at("config");
+
+        // The nodes after the last redacted one are shared, and so are the nodes inside earlier ones
+        assert!(Rc::ptr_eq(&redacted.get_child_at("config").get_child_at("port"), &config.get_child_at("port")));
+        assert!(redacted.get_child_at("users").get_child_at("alice").get_child_at("email").get_value().to_str("") == "alice@example.com");
+
+        let untouched = tree!("root", tree!("other", ("x", 1)));
+        assert!(Rc::ptr_eq(&rules().redact_tree(&untouched), &untouched));
+    }
+
+    #[test]
+    fn hash_policy_is_deterministic() {
+        let rules       = RedactionRules::new(vec![RedactionRule::at(&"key", RedactionPolicy::Hash)]);
+        let first       = rules.redact_tree(&tree!("root", ("key", "secret")));
+        let second      = RedactionRules::new(vec![RedactionRule::new("key", RedactionPolicy::Hash)]).redact_tree(&tree!("root", ("key", "secret")));
+        let different   = rules.redact_tree(&tree!("root", ("key", "other")));
+
+        let hash = first.get_child_at("key").get_value().to_str("").to_string();
+
+        assert!(hash.starts_with("hash:"));
+        assert!(!hash.contains("secret"));
+        assert!(second.get_child_at("key").get_value().to_str("") == hash);
+        assert!(different.get_child_at("key").get_value().to_str("") != hash);
+    }
+
+    #[test]
+    fn redaction_composes_with_hub_addresses() {
+        let mut hub         = Hub::new();
+        let mut input       = hub.publish_to(&"exported");
+        let mut redacting   = RedactingConsumer::new(hub.read_from(&"exported"), rules());
+        let users           = Rc::new(RefCell::new("empty".to_tree_node()));
+        let also_users      = users.clone();
+
+        // Subscriptions inside the redacted tree see changes relative to their own address
+        redacting.subscribe("users".to_tree_address(), TreeExtent::SubTree, Box::new(move |change| {
+            let updated = change.apply(&also_users.borrow());
+            *also_users.borrow_mut() = updated;
+        }));
+
+        // A redacting publisher republishes the redacted tree elsewhere on the hub
+        let republished     = Rc::new(RefCell::new("empty".to_tree_node()));
+        let also_republished = republished.clone();
+        let mut public      = RedactingPublisher::new(hub.publish_to(&"public"), rules());
+        let mut exported    = hub.read_from(&"exported");
+        let mut public_read = hub.read_from(&"public");
+
+        exported.subscribe(TreeAddress::Here, TreeExtent::SubTree, Box::new(move |change| public.publish(change.clone())));
+        public_read.subscribe(TreeAddress::Here, TreeExtent::SubTree, Box::new(move |change| {
+            let updated = change.apply(&also_republished.borrow());
+            *also_republished.borrow_mut() = updated;
+        }));
+
+        input.publish(TreeChange::new(&(), &sample()));
+        hub.flush();
+        input.publish(TreeChange::new(&("users", ("alice", "password")), &TreeReplacement::NewValue("password".to_string(), "hunter3".to_tree_value())));
+        hub.flush();
+
+        assert!(users.borrow().get_child_at("alice").get_child_at("password").get_value().to_str("") == "<redacted>");
+        assert!(users.borrow().get_child_at("alice").get_child_at("email").get_value().to_str("") == "alice@example.com");
+
+        assert!(republished.borrow().get_child_at("config").get_child_ref_at("secrets").is_none());
+        assert!(!to_tree_text(&republished.borrow()).contains("hunter"));
+        assert!(republished.borrow().get_child_at("config").get_child_at("port").get_value().to_int(0) == 80);
+    }
+}
@@ -77,11 +77,32 @@ impl<TData: Clone> SubscriptionManager<TData> {
     }
 
     ///
-    /// Calls the subscriptions matching a particular filter
+    /// Removes the subscriptions whose data doesn't match a filter, returning the number that were removed
     ///
-    pub fn call_subscriptions(&self, call_filter: &Fn(&TData) -> bool, change: &TreeChange) {
+    pub fn retain(&self, keep: &dyn Fn(&TData) -> bool) -> usize {
+        let subscriptions   = self.subscriptions.get();
+        let original_len    = subscriptions.len();
+        let remaining: Vec<_> = subscriptions.into_iter().filter(|subscription| keep(&subscription.data)).collect();
+        let removed         = original_len - remaining.len();
+
+        self.subscriptions.set(remaining);
+        removed
+    }
+
+    ///
+    /// Retrieves the data attached to each of the subscriptions
+    ///
+    pub fn subscription_data(&self) -> Vec<TData> {
+        self.subscriptions.get().iter().map(|subscription| subscription.data.clone()).collect()
+    }
+
+    ///
+    /// Calls the subscriptions matching a particular filter, returning the number that were called
+    ///
+    pub fn call_subscriptions(&self, call_filter: &Fn(&TData) -> bool, change: &TreeChange) -> usize {
         // Retrieve the active subscriptions
-        let subscriptions = self.subscriptions.get();
+        let subscriptions   = self.subscriptions.get();
+        let mut called      = 0;
 
         // Call any subscription matching the filter
         for possible_subscription in subscriptions {
@@ -89,8 +110,11 @@ impl<TData: Clone> SubscriptionManager<TData> {
                 // Caution: this will fail at runtime with a borrowing error if this function is re-entered (ie, if there is a feedback loop)
                 let mut callback = possible_subscription.callback.borrow_mut();
                 callback.run_callback(change);
+                called += 1;
             }
         }
+
+        called
     }
 }
 
@@ -0,0 +1,326 @@
+//
+//   Copyright 2016 Andrew Hunter
+//
+//   Licensed under the Apache License, Version 2.0 (the "License");
+//   you may not use this file except in compliance with the License.
+//   You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+//   Unless required by applicable law or agreed to in writing, software
+//   distributed under the License is distributed on an "AS IS" BASIS,
+//   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//   See the License for the specific language governing permissions and
+//   limitations under the License.
+//
+
+//!
+//! # Tracing component invocations
+//!
+//! A `TraceHook` installed on a `Hub` with `set_trace_hook()` is told whenever a component attached to the hub
+//! processes a change: `on_invoke_start()` is called just before the change is passed to the component and
+//! `on_invoke_end()` just after, along with the number of changes the component published while it was
+//! processing it. This makes it possible to report what a hub is doing through an application's existing tracing
+//! or logging system.
+//!
+//! Components are identified by the name they were given in `Hub::add_named_component()`. Components attached
+//! with `add_component()` are named after the order they were attached in (`component-0`, `component-1`, ...),
+//! skipping any name that another component is already using. The names of the components attached through a
+//! region start with the region's name (`feature/component-0`).
+//!
+//! By default no hook is installed, and the hub doesn't do any work to track invocations beyond checking for one.
+//! `VecTraceHook` records the spans it's told about and is mostly useful for testing.
+//!
+
+use std::cell::*;
+
+use super::super::tree::*;
+
+///
+/// Identifies a span started by a trace hook
+///
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SpanToken(pub u64);
+
+///
+/// Receives notifications when components attached to a hub process changes
+///
+pub trait TraceHook {
+    ///
+    /// A component is about to process a change. The returned token is passed to `on_invoke_end()` when it's done.
+    ///
+    fn on_invoke_start(&self, component: &str, change: &TreeChange) -> SpanToken;
+
+    ///
+    /// A component has finished processing a change, and published the specified number of changes while doing so
+    ///
+    fn on_invoke_end(&self, token: SpanToken, published: usize);
+}
+
+///
+/// A span recorded by a `VecTraceHook`
+///
+#[derive(Clone, PartialEq)]
+pub struct TraceSpan {
+    /// The name of the component that processed the change
+    pub component: String,
+
+    /// The address of the change that was processed
+    pub address: TreeAddress,
+
+    /// The number of changes published while processing the change, or None if the span hasn't ended yet
+    pub published: Option<usize>
+}
+
+///
+/// A trace hook that records every span in the order they were started
+///
+pub struct VecTraceHook {
+    spans: RefCell<Vec<TraceSpan>>
+}
+
+impl VecTraceHook {
+    ///
+    /// Creates a hook that hasn't recorded any spans
+    ///
+    pub fn new() -> VecTraceHook {
+        VecTraceHook { spans: RefCell::new(vec![]) }
+    }
+
+    ///
+    /// The spans recorded so far
+    ///
+    pub fn spans(&self) -> Vec<TraceSpan> {
+        self.spans.borrow().clone()
+    }
+}
+
+impl Default for VecTraceHook {
+    fn default() -> VecTraceHook {
+        VecTraceHook::new()
+    }
+}
+
+impl TraceHook for VecTraceHook {
+    fn on_invoke_start(&self, component: &str, change: &TreeChange) -> SpanToken {
+        let mut spans = self.spans.borrow_mut();
+        spans.push(TraceSpan { component: component.to_string(), address: change.address().clone(), published: None });
+
+        SpanToken((spans.len()-1) as u64)
+    }
+
+    fn on_invoke_end(&self, token: SpanToken, published: usize) {
+        if let Some(span) = self.spans.borrow_mut().get_mut(token.0 as usize) {
+            span.published = Some(published);
+        }
+    }
+}
+
+#[cfg(test)]
+mod trace_tests {
+    use std::rc::*;
+    use std::cell::*;
+
+    use super::super::super::tree::*;
+    use super::super::super::component::*;
+    use super::*;
+
+    ///
+    /// A component that publishes each change it receives a fixed number of times
+    ///
+    struct Repeat(usize);
+
+    ///
+    /// The test components keep publishing until they are dropped
+    ///
+    struct RepeatRef(Rc<Cell<bool>>);
+
+    impl Component for RepeatRef {
+    }
+
+    impl Drop for RepeatRef {
+        fn drop(&mut self) {
+            self.0.set(false);
+        }
+    }
+
+    impl ConvertToComponent for Repeat {
+        fn into_component(self, consumer: ConsumerRef, publisher: PublisherRef) -> ComponentRef {
+            let mut consumer    = consumer;
+            let mut publisher   = publisher;
+            let count           = self.0;
+            let attached        = Rc::new(Cell::new(true));
+            let still_attached  = attached.clone();
+
+            consumer.subscribe(TreeAddress::Here, TreeExtent::SubTree, Box::new(move |change| {
+                if still_attached.get() {
+                    for _ in 0..count {
+                        publisher.publish(change.clone());
+                    }
+                }
+            }));
+
+            Rc::new(RepeatRef(attached))
+        }
+    }
+
+    ///
+    /// A component that hands its publisher over to something else and never publishes anything itself
+    ///
+    struct Lend(Rc<RefCell<Option<PublisherRef>>>);
+
+    impl ConvertToComponent for Lend {
+        fn into_component(self, _consumer: ConsumerRef, publisher: PublisherRef) -> ComponentRef {
+            *self.0.borrow_mut() = Some(publisher);
+
+            Rc::new(RepeatRef(Rc::new(Cell::new(true))))
+        }
+    }
+
+    ///
+    /// A component that publishes each change it receives once, and also publishes it through a lent publisher
+    ///
+    struct Borrow(Rc<RefCell<Option<PublisherRef>>>);
+
+    impl ConvertToComponent for Borrow {
+        fn into_component(self, consumer: ConsumerRef, publisher: PublisherRef) -> ComponentRef {
+            let mut consumer    = consumer;
+            let mut publisher   = publisher;
+            let lent            = self.0;
+            let attached        = Rc::new(Cell::new(true));
+            let still_attached  = attached.clone();
+
+            consumer.subscribe(TreeAddress::Here, TreeExtent::SubTree, Box::new(move |change| {
+                if still_attached.get() {
+                    publisher.publish(change.clone());
+
+                    if let Some(ref mut lent) = *lent.borrow_mut() {
+                        lent.publish(change.clone());
+                    }
+                }
+            }));
+
+            Rc::new(RepeatRef(attached))
+        }
+    }
+
+    fn summary(hook: &VecTraceHook) -> Vec<(String, Option<usize>)> {
+        hook.spans().into_iter().map(|span| (span.component, span.published)).collect()
+    }
+
+    #[test]
+    fn pipeline_records_spans() {
+        let mut hub     = Hub::new();
+        let hook        = Rc::new(VecTraceHook::new());
+        let mut input   = hub.publish_to(&"in");
+
+        hub.add_named_component("double", Repeat(2), &"in", &"mid");
+        hub.add_named_component("forward", Repeat(1), &"mid", &"out");
+        hub.set_trace_hook(hook.clone());
+
+        input.publish(TreeChange::new(&(), &1));
+        hub.flush();
+
+        assert!(summary(&hook) == vec![
+            ("double".to_string(), Some(2)),
+            ("forward".to_string(), Some(1)),
+            ("forward".to_string(), Some(1))
+        ]);
+    }
+
+    #[test]
+    fn publishes_are_attributed_to_their_component() {
+        let mut hub     = Hub::new();
+        let hook        = Rc::new(VecTraceHook::new());
+        let mut input   = hub.publish_to(&"in");
+        let lent        = Rc::new(RefCell::new(None));
+
+        hub.add_named_component("loud", Repeat(3), &"in", &"loud_out");
+        hub.add_named_component("quiet", Repeat(0), &"in", &"quiet_out");
+        hub.add_named_component("lender", Lend(lent.clone()), &"unused", &"lent_out");
+        hub.add_named_component("borrower", Borrow(lent), &"in", &"borrowed_out");
+        hub.set_trace_hook(hook.clone());
+
+        input.publish(TreeChange::new(&(), &1));
+        hub.flush();
+
+        // The lender's publisher is used while the borrower is running, but that publish still belongs to the lender
+        assert!(summary(&hook) == vec![
+            ("loud".to_string(), Some(3)),
+            ("quiet".to_string(), Some(0)),
+            ("borrower".to_string(), Some(1))
+        ]);
+    }
+
+    #[test]
+    fn generated_names_do_not_reuse_names_in_use() {
+        let mut hub     = Hub::new();
+        let hook        = Rc::new(VecTraceHook::new());
+        let mut input   = hub.publish_to(&"in");
+
+        hub.add_named_component("component-1", Repeat(1), &"in", &"first_out");
+        hub.add_component(Repeat(2), &"in", &"second_out");
+        hub.add_component(Repeat(3), &"in", &"third_out");
+        hub.set_trace_hook(hook.clone());
+
+        input.publish(TreeChange::new(&(), &1));
+        hub.flush();
+
+        assert!(summary(&hook) == vec![
+            ("component-1".to_string(), Some(1)),
+            ("component-2".to_string(), Some(2)),
+            ("component-3".to_string(), Some(3))
+        ]);
+        assert!(hub.pause_component("component-2"));
+        assert!(!hub.is_paused("component-1"));
+    }
+
+    #[test]
+    fn region_components_are_traced_and_can_be_paused() {
+        let mut hub     = Hub::new();
+        let hook        = Rc::new(VecTraceHook::new());
+        let mut input   = hub.publish_to(&"in");
+        let mut region  = hub.region("feature");
+
+        region.add_named_component("double", Repeat(2), &"in", &"out").unwrap();
+        hub.set_trace_hook(hook.clone());
+
+        input.publish(TreeChange::new(&(), &1));
+        hub.flush();
+        assert!(summary(&hook) == vec![("double".to_string(), Some(2))]);
+
+        assert!(hub.pause_component("double"));
+        input.publish(TreeChange::new(&(), &2));
+        hub.flush();
+        assert!(hook.spans().len() == 1);
+
+        // Closing the region removes its components from the hub
+        assert!(region.close() == Ok(()));
+        assert!(!hub.pause_component("double"));
+    }
+
+    #[test]
+    fn no_hook_does_no_tracing_work() {
+        let mut hub     = Hub::new();
+        let hook        = Rc::new(VecTraceHook::new());
+        let mut input   = hub.publish_to(&"in");
+
+        hub.add_component(Repeat(2), &"in", &"mid");
+        hub.add_component(Repeat(1), &"mid", &"out");
+
+        input.publish(TreeChange::new(&(), &1));
+        hub.flush();
+        assert!(hub.traced_invocations() == 0);
+
+        hub.set_trace_hook(hook.clone());
+        input.publish(TreeChange::new(&(), &2));
+        hub.flush();
+        assert!(hub.traced_invocations() == 3);
+        assert!(hook.spans().len() == 3);
+
+        hub.clear_trace_hook();
+        input.publish(TreeChange::new(&(), &3));
+        hub.flush();
+        assert!(hub.traced_invocations() == 3);
+    }
+}
@@ -0,0 +1,521 @@
+//
+//   Copyright 2016 Andrew Hunter
+//
+//   Licensed under the Apache License, Version 2.0 (the "License");
+//   you may not use this file except in compliance with the License.
+//   You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+//   Unless required by applicable law or agreed to in writing, software
+//   distributed under the License is distributed on an "AS IS" BASIS,
+//   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//   See the License for the specific language governing permissions and
+//   limitations under the License.
+//
+
+//!
+//! # Tracking publishers and transactions
+//!
+//! A `TrackingPublisher` passes changes on to another publisher and keeps track of the tree they produce. This
+//! makes it possible to publish a set of related changes all at once, based on what the tree contains at the time.
+//!
+//! `transact()` calls a function with a `TransactionView` of the tree and publishes the changes it returns as a
+//! single change, replacing the smallest subtree that contains all of them. If anything else was published through
+//! the publisher while the function was running, the changes are based on an out of date tree: they're thrown
+//! away and the function is called again with a new view, up to a retry limit. The function can also abort the
+//! transaction, in which case nothing is published.
+//!
+//! Lookups in the tracked tree (through `get()` or a `TransactionView` that's still up to date) go through an
+//! `AddressCache`, so a tagged address that's read repeatedly is only searched for once. Every change published
+//! through the publisher is passed to the cache, so structural changes evict the addresses they might affect.
+//!
+//! ```
+//! # use tametree::prelude::*;
+//! # use tametree::component::tracking_publisher::*;
+//! let output          = OutputTreePublisher::new();
+//! let reader          = output.get_tree_reader();
+//! let mut publisher   = TrackingPublisher::new(output);
+//!
+//! publisher.publish(TreeChange::new(&(), &tree!("accounts", ("alice", 10), ("bob", 0))));
+//! publisher.transact(|view| {
+//!     let alice = view.get(&"alice").map(|node| node.get_value().to_int(0)).unwrap_or(0);
+//!     let bob   = view.get(&"bob").map(|node| node.get_value().to_int(0)).unwrap_or(0);
+//!
+//!     if alice < 5 { return Err(TxAbort("not enough money".to_string())); }
+//!
+//!     Ok(vec![TreeChange::new(&"alice", &("alice", alice - 5)), TreeChange::new(&"bob", &("bob", bob + 5))])
+//! }).unwrap();
+//!
+//! assert!(reader().get_child_at("bob").get_value().to_int(0) == 5);
+//! ```
+//!
+
+use std::rc::*;
+use std::cell::*;
+use std::fmt;
+
+use super::super::tree::*;
+use super::component::*;
+use super::adaptive_filter::*;
+
+///
+/// Returned by a transaction function to abort the transaction, with the reason
+///
+#[derive(Clone, PartialEq, Debug)]
+pub struct TxAbort(pub String);
+
+///
+/// Why a transaction didn't publish its changes
+///
+#[derive(Clone, PartialEq, Debug)]
+pub enum TxError {
+    /// The transaction function aborted the transaction
+    Aborted(String),
+
+    /// The tree kept changing while the transaction function was running. The address is where the most recent
+    /// conflicting change was made.
+    Conflict { attempts: usize, address: String }
+}
+
+impl fmt::Display for TxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TxError::Aborted(ref reason)                => write!(f, "transaction aborted: {}", reason),
+            TxError::Conflict { attempts, ref address } => write!(f, "transaction gave up after {} attempts: the tree was changed at {}", attempts, address)
+        }
+    }
+}
+
+///
+/// Read access to the tree as it was when a transaction attempt started
+///
+pub struct TransactionView {
+    tree: TreeRef,
+
+    /// The state of the publisher, and its generation when the view was created
+    state: Rc<RefCell<TrackingState>>,
+    generation: u64
+}
+
+impl TransactionView {
+    ///
+    /// The whole tree
+    ///
+    pub fn tree(&self) -> &TreeRef {
+        &self.tree
+    }
+
+    ///
+    /// Retrieves the node at an address, if there is one
+    ///
+    pub fn get<TAddress: ToTreeAddress>(&self, address: &TAddress) -> Option<TreeRef> {
+        let address     = address.to_tree_address();
+        let mut state   = self.state.borrow_mut();
+
+        // The address cache only describes the tree if nothing has been published since the view was created
+        if state.generation == self.generation {
+            state.get(&address)
+        } else {
+            self.tree.get_child_ref_at(address)
+        }
+    }
+
+    ///
+    /// Decodes the node at an address, returning None if there's no node there or it can't be decoded
+    ///
+    pub fn decode_at<TAddress: ToTreeAddress, TValue: DecodeFromTreeNode>(&self, address: &TAddress) -> Option<TValue> {
+        self.get(address).and_then(|node| TValue::new_from_tree(&node).ok())
+    }
+}
+
+///
+/// The state shared by the clones of a tracking publisher
+///
+struct TrackingState {
+    target: PublisherRef,
+
+    /// The tree after the changes published so far
+    tree: TreeRef,
+
+    /// The number of changes published so far
+    generation: u64,
+
+    /// The address of the most recent change
+    last_address: Option<TreeAddress>,
+
+    /// Indexed forms of the addresses looked up in the tree
+    cache: AddressCache
+}
+
+///
+/// A publisher that keeps track of the tree produced by the changes sent through it
+///
+/// This is a handle: clones of it publish to the same target and share the same tree.
+///
+#[derive(Clone)]
+pub struct TrackingPublisher {
+    state: Rc<RefCell<TrackingState>>,
+    max_attempts: usize
+}
+
+///
+/// Finds the address of the subtree that a set of changes can be combined into
+///
+/// This is the longest address that contains every one of the addresses, except that if one of the changes is at
+/// that address, it's the parent instead: a change can rename or remove the node it's addressed to (or for an
+/// indexed address, move its siblings into its place), so only the parent is sure to contain its result.
+///
+fn common_parent<'a, TIter: Iterator<Item=&'a TreeAddress>>(addresses: TIter) -> TreeAddress {
+    let mut common: Option<Vec<AddressPart>>    = None;
+    let mut shortest                            = usize::MAX;
+
+    for address in addresses {
+        let parts = flatten_address(address);
+        shortest  = shortest.min(parts.len());
+
+        common = Some(match common {
+            None            => parts,
+            Some(common)    => common.into_iter().zip(parts).take_while(|(a, b)| a == b).map(|(a, _)| a).collect()
+        });
+    }
+
+    let mut common = common.unwrap_or_default();
+    if common.len() == shortest {
+        common.pop();
+    }
+
+    common.into_iter().rev().fold(TreeAddress::Here, |address, part| match part {
+        AddressPart::Index(index)   => TreeAddress::ChildAtIndex(index, Box::new(address)),
+        AddressPart::Tag(tag)       => TreeAddress::ChildWithTag(tag, Box::new(address))
+    })
+}
+
+impl TrackingPublisher {
+    ///
+    /// Creates a publisher that passes changes on to another publisher, starting with an empty tree
+    ///
+    pub fn new(target: PublisherRef) -> TrackingPublisher {
+        TrackingPublisher {
+            state:          Rc::new(RefCell::new(TrackingState { target, tree: "empty".to_tree_node(), generation: 0, last_address: None, cache: AddressCache::new() })),
+            max_attempts:   8
+        }
+    }
+
+    ///
+    /// Sets the number of times a transaction function is called before a transaction gives up
+    ///
+    pub fn with_retry_limit(mut self, max_attempts: usize) -> TrackingPublisher {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    ///
+    /// The tree after the changes published so far
+    ///
+    pub fn tree(&self) -> TreeRef {
+        self.state.borrow().tree.clone()
+    }
+
+    ///
+    /// Retrieves the node at an address in the tree after the changes published so far, if there is one
+    ///
+    pub fn get<TAddress: ToTreeAddress>(&self, address: &TAddress) -> Option<TreeRef> {
+        self.state.borrow_mut().get(&address.to_tree_address())
+    }
+
+    ///
+    /// The number of lookups in the tracked tree that were satisfied by the address cache
+    ///
+    pub fn cache_hits(&self) -> usize {
+        self.state.borrow().cache.hits()
+    }
+
+    ///
+    /// The number of lookups in the tracked tree that needed to search the tree
+    ///
+    pub fn cache_misses(&self) -> usize {
+        self.state.borrow().cache.misses()
+    }
+
+    ///
+    /// Publishes the changes returned by a function as a single change, retrying if anything else is published
+    /// while the function is running
+    ///
+    pub fn transact<TFn: FnMut(&TransactionView) -> Result<Vec<TreeChange>, TxAbort>>(&mut self, transaction: TFn) -> Result<(), TxError> {
+        let mut transaction = transaction;
+
+        for _ in 0..self.max_attempts {
+            let (view, generation) = {
+                let state = self.state.borrow();
+                (TransactionView { tree: state.tree.clone(), state: self.state.clone(), generation: state.generation }, state.generation)
+            };
+
+            // The state isn't borrowed here, so the function can publish through a clone of this publisher
+            let changes = transaction(&view).map_err(|TxAbort(reason)| TxError::Aborted(reason))?;
+
+            let mut state = self.state.borrow_mut();
+            if state.generation != generation {
+                continue;
+            }
+
+            if changes.is_empty() {
+                return Ok(());
+            }
+
+            // Combine the changes into one that replaces the subtree containing all of them. The address cache is
+            // updated with the individual changes, which evict less than the combined one would.
+            let parent  = common_parent(changes.iter().map(|change| change.address()));
+            let result  = changes.iter().fold(view.tree.clone(), |tree, change| {
+                state.cache.apply_change(&tree, change);
+                change.apply(&tree)
+            });
+
+            let combined = match (&parent, result.get_child_ref_at(parent.clone())) {
+                (&TreeAddress::Here, _)     => TreeChange::new(&parent, &result),
+                (_, Some(subtree))          => TreeChange::new(&parent, &subtree.with_sibling_node(None)),
+                (_, None)                   => TreeChange::new(&parent, &TreeReplacement::Remove)
+            };
+
+            state.commit(combined);
+            return Ok(());
+        }
+
+        let address = self.state.borrow().last_address.as_ref().map(|address| address.to_string()).unwrap_or_default();
+        Err(TxError::Conflict { attempts: self.max_attempts, address })
+    }
+}
+
+impl TrackingState {
+    fn publish(&mut self, change: TreeChange) {
+        self.cache.apply_change(&self.tree, &change);
+        self.commit(change);
+    }
+
+    ///
+    /// Publishes a change that the address cache has already been updated for
+    ///
+    fn commit(&mut self, change: TreeChange) {
+        self.tree           = change.apply(&self.tree);
+        self.generation     += 1;
+        self.last_address   = Some(change.address().clone());
+
+        self.target.publish(change);
+    }
+
+    fn get(&mut self, address: &TreeAddress) -> Option<TreeRef> {
+        let tree = self.tree.clone();
+        self.cache.canonicalize(&tree, address).and_then(|indexed| tree.get_child_ref_at(indexed))
+    }
+}
+
+impl Publisher for TrackingPublisher {
+    ///
+    /// Publishes a change to the consumers of this component
+    ///
+    fn publish(&mut self, change: TreeChange) {
+        self.state.borrow_mut().publish(change);
+    }
+}
+
+#[cfg(test)]
+mod tracking_publisher_tests {
+    use std::rc::*;
+    use std::cell::*;
+
+    use super::super::super::tree::*;
+    use super::super::output_tree_publisher::*;
+    use super::*;
+
+    fn counter(view: &TransactionView, name: &str) -> i32 {
+        view.get(&name).map(|node| node.get_value().to_int(0)).unwrap_or(0)
+    }
+
+    type TreeReader = Box<dyn Fn() -> TreeRef>;
+
+    ///
+    /// Publisher that counts the changes passed through it
+    ///
+    struct CountingPublisher {
+        target: PublisherRef,
+        count: Rc<Cell<usize>>
+    }
+
+    impl Publisher for CountingPublisher {
+        fn publish(&mut self, change: TreeChange) {
+            self.count.set(self.count.get() + 1);
+            self.target.publish(change);
+        }
+    }
+
+    ///
+    /// Creates a tracking publisher containing three counters, along with a reader for the tree it publishes to and
+    /// the number of changes it's published since then
+    ///
+    fn counters() -> (TrackingPublisher, TreeReader, Rc<Cell<usize>>) {
+        let output      = OutputTreePublisher::new();
+        let reader      = output.get_tree_reader();
+        let published   = Rc::new(Cell::new(0));
+        let mut tracker = TrackingPublisher::new(Box::new(CountingPublisher { target: output, count: published.clone() }));
+
+        tracker.publish(TreeChange::new(&(), &tree!("counters", ("a", 0), ("b", 0), ("c", 0))));
+        published.set(0);
+
+        (tracker, reader, published)
+    }
+
+    #[test]
+    fn overlapping_transactions_both_succeed() {
+        let (mut first, reader, _)  = counters();
+        let mut second              = first.clone();
+        let mut attempts            = 0;
+
+        first.transact(|view| {
+            attempts += 1;
+
+            // The other transaction commits while this one is running (but only the first time round)
+            if attempts == 1 {
+                second.transact(|view| Ok(vec![
+                    TreeChange::new(&"b", &("b", counter(view, "b") + 1)),
+                    TreeChange::new(&"c", &("c", counter(view, "c") + 1))
+                ])).unwrap();
+            }
+
+            Ok(vec![
+                TreeChange::new(&"a", &("a", counter(view, "a") + 1)),
+                TreeChange::new(&"b", &("b", counter(view, "b") + 1))
+            ])
+        }).unwrap();
+
+        assert!(attempts == 2);
+
+        let tree = reader();
+        assert!(tree.get_child_at("a").get_value().to_int(0) == 1);
+        assert!(tree.get_child_at("b").get_value().to_int(0) == 2);
+        assert!(tree.get_child_at("c").get_value().to_int(0) == 1);
+    }
+
+    #[test]
+    fn changes_are_published_as_one() {
+        let (mut tracker, reader, published) = counters();
+
+        tracker.transact(|view| Ok(vec![
+            TreeChange::new(&"a", &("a", counter(view, "a") + 5)),
+            TreeChange::new(&"c", &TreeReplacement::Remove)
+        ])).unwrap();
+
+        assert!(published.get() == 1);
+        assert!(reader().get_child_at("a").get_value().to_int(0) == 5);
+        assert!(reader().get_child_ref_at("c").is_none());
+    }
+
+    #[test]
+    fn abort_leaves_tree_untouched() {
+        let (mut tracker, reader, published)    = counters();
+        let before                              = reader();
+
+        let result = tracker.transact(|view| {
+            if counter(view, "a") == 0 { Err(TxAbort("a is zero".to_string())) } else { Ok(vec![]) }
+        });
+
+        assert!(result == Err(TxError::Aborted("a is zero".to_string())));
+        assert!(published.get() == 0);
+        assert!(Rc::ptr_eq(&before, &reader()));
+    }
+
+    #[test]
+    fn retry_exhaustion_reports_last_conflict() {
+        let (tracker, _, _) = counters();
+        let mut tracker     = tracker.with_retry_limit(3);
+        let mut interloper  = tracker.clone();
+        let mut value       = 0;
+
+        let result = tracker.transact(|_| {
+            value += 1;
+            interloper.publish(TreeChange::new(&"c", &("c", value)));
+
+            Ok(vec![TreeChange::new(&"a", &("a", 1))])
+        });
+
+        assert!(result == Err(TxError::Conflict { attempts: 3, address: ".\"c\".".to_string() }));
+        assert!(tracker.tree().get_child_at("a").get_value().to_int(0) == 0);
+    }
+
+    #[test]
+    fn reads_are_consistent_during_a_transaction() {
+        let (mut tracker, _, _) = counters();
+        let mut interloper      = tracker.clone();
+        let mut seen            = vec![];
+
+        tracker.transact(|view| {
+            let before = counter(view, "a");
+
+            // A publish in the middle of the transaction doesn't change what the view contains
+            if seen.is_empty() {
+                interloper.publish(TreeChange::new(&"a", &("a", 10)));
+            }
+
+            seen.push((before, counter(view, "a")));
+            Ok(vec![TreeChange::new(&"b", &("b", counter(view, "a")))])
+        }).unwrap();
+
+        assert!(seen == vec![(0, 0), (10, 10)]);
+        assert!(tracker.tree().get_child_at("b").get_value().to_int(0) == 10);
+    }
+
+    #[test]
+    fn renaming_a_node_keeps_it() {
+        let (mut tracker, reader, published) = counters();
+
+        tracker.transact(|_| Ok(vec![TreeChange::new(&"a", &("renamed", 5))])).unwrap();
+
+        let tree = reader();
+        assert!(published.get() == 1);
+        assert!(tree.get_child_ref_at("a").is_none());
+        assert!(tree.get_child_at("renamed").get_value().to_int(0) == 5);
+        assert!(tree.iter_children().map(|child| child.get_tag().to_string()).collect::<Vec<_>>() == vec!["renamed", "b", "c"]);
+    }
+
+    #[test]
+    fn removing_by_index_moves_the_later_siblings() {
+        let (mut tracker, reader, _) = counters();
+
+        tracker.transact(|_| Ok(vec![TreeChange::new(&0, &TreeReplacement::Remove)])).unwrap();
+
+        assert!(reader().iter_children().map(|child| child.get_tag().to_string()).collect::<Vec<_>>() == vec!["b", "c"]);
+    }
+
+    #[test]
+    fn repeated_reads_hit_the_address_cache() {
+        let (mut tracker, _, _) = counters();
+
+        for _ in 0..3 {
+            tracker.transact(|view| {
+                let total = counter(view, "c") + counter(view, "b") + 1;
+                Ok(vec![TreeChange::new(&"b", &TreeReplacement::NewValue("b".to_string(), TreeValue::Int(total)))])
+            }).unwrap();
+        }
+
+        // Value changes don't evict anything, so only the first reads of 'b' and 'c' search the tree
+        assert!(tracker.cache_misses() == 2);
+        assert!(tracker.cache_hits() == 4);
+        assert!(tracker.get(&"b").unwrap().get_value().to_int(0) == 3);
+        assert!(tracker.cache_hits() == 5);
+    }
+
+    #[test]
+    fn structural_changes_evict_cached_addresses() {
+        let (mut tracker, _, _) = counters();
+
+        assert!(tracker.get(&"c").unwrap().get_value().to_int(0) == 0);
+
+        // Removing 'a' moves 'c' to a different index, so it has to be found again
+        tracker.publish(TreeChange::new(&"a", &TreeReplacement::Remove));
+        tracker.publish(TreeChange::new(&"b", &("c", 5)));
+
+        assert!(tracker.get(&"c").unwrap().get_value().to_int(0) == 5);
+        assert!(tracker.get(&"a").is_none());
+        assert!(tracker.cache_hits() == 0);
+        assert!(tracker.cache_misses() == 3);
+    }
+}
@@ -0,0 +1,435 @@
+//
+//   Copyright 2016 Andrew Hunter
+//
+//   Licensed under the Apache License, Version 2.0 (the "License");
+//   you may not use this file except in compliance with the License.
+//   You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+//   Unless required by applicable law or agreed to in writing, software
+//   distributed under the License is distributed on an "AS IS" BASIS,
+//   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//   See the License for the specific language governing permissions and
+//   limitations under the License.
+//
+
+//!
+//! # Checking how a hub is wired
+//!
+//! A hub records which addresses each of its components reads from and publishes to, along with the addresses
+//! used by the consumers and publishers created by `read_from()` and `publish_to()`. `Hub::validate_wiring()`
+//! looks for mistakes in this wiring without sending any changes:
+//!
+//! * outputs that nothing reads (a warning: the output might only be needed later)
+//! * inputs that nothing publishes to (a warning)
+//! * places where the addresses that are published to and the addresses that are read use different forms (index
+//!   or tag) for the same part of the tree, so the hub can't tell if changes should be delivered. These are found
+//!   in the same way as an `AddressingAudit` finds them while the hub is running, and are errors
+//! * addresses that are published to by more than one thing, unless the hub has been told that this is intended
+//!   with `Hub::declare_shared_output()` (an error)
+//!
+//! The components and endpoints created through the hub's regions are checked along with the rest of the hub, until
+//! the region they belong to is closed.
+//!
+//! `Hub::set_wiring_validation()` makes the hub check its wiring when it's first pumped. In strict mode, the hub
+//! won't pump at all while its wiring has errors.
+//!
+//! ```
+//! # use tametree::prelude::*;
+//! let mut hub = Hub::new();
+//! let _input  = hub.publish_to(&"in");
+//!
+//! hub.add_named_component("double", component_fn(|x: &i32| x * 2), &"in", &"out");
+//! hub.add_named_component("negate", component_fn(|x: &i32| -x), &"in", &"out");
+//!
+//! let report = hub.validate_wiring();
+//! assert!(report.has_errors());
+//! assert!(report.issues.iter().any(|issue| issue.kind == "double-writer" && issue.components == vec!["double", "negate"]));
+//! ```
+//!
+
+use std::fmt;
+
+use super::super::tree::*;
+use super::addressing_audit::*;
+
+///
+/// The most prefixes that the addressing audit used for wiring checks will track
+///
+const MAX_AUDITED_PREFIXES: usize = 4096;
+
+tree_struct! {
+    ///
+    /// A problem with the wiring of a hub
+    ///
+    #[derive(Clone, PartialEq, Debug)]
+    pub struct WiringIssue {
+        // "error" or "warning"
+        pub severity: String,
+
+        // What kind of problem this is ("unconsumed-output", "unfed-input", "address-kind-mismatch" or "double-writer")
+        pub kind: String,
+
+        // The names of the components and endpoints involved
+        pub components: Vec<String>,
+
+        // The addresses involved, formatted as strings
+        pub addresses: Vec<String>,
+
+        // A description of the problem
+        pub message: String
+    }
+}
+
+tree_struct! {
+    ///
+    /// The results of checking the wiring of a hub
+    ///
+    #[derive(Clone, PartialEq, Debug)]
+    pub struct WiringReport {
+        // The problems that were found, errors first
+        pub issues: Vec<WiringIssue>
+    }
+}
+
+impl WiringReport {
+    ///
+    /// True if any of the issues in this report are errors
+    ///
+    pub fn has_errors(&self) -> bool {
+        self.error_count() > 0
+    }
+
+    ///
+    /// The number of issues that are errors
+    ///
+    pub fn error_count(&self) -> usize {
+        self.issues.iter().filter(|issue| issue.severity == "error").count()
+    }
+
+    ///
+    /// The number of issues that are warnings
+    ///
+    pub fn warning_count(&self) -> usize {
+        self.issues.iter().filter(|issue| issue.severity == "warning").count()
+    }
+
+    ///
+    /// Creates this report as a tree (which can be decoded as a `WiringReport`)
+    ///
+    pub fn as_tree(&self) -> TreeRef {
+        self.to_tree_node()
+    }
+}
+
+impl fmt::Display for WiringReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.issues.is_empty() {
+            writeln!(f, "No wiring problems found")?;
+        }
+
+        for issue in self.issues.iter() {
+            writeln!(f, "{} ({}): {}", issue.severity, issue.kind, issue.message)?;
+            writeln!(f, "    components: {}; addresses: {}", issue.components.join(", "), issue.addresses.join(", "))?;
+        }
+
+        Ok(())
+    }
+}
+
+///
+/// When a hub checks its wiring
+///
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum WiringValidation {
+    /// Only when `validate_wiring()` is called
+    Manual,
+
+    /// When the hub is first pumped. Problems are recorded in the hub's wiring report but don't stop it from pumping
+    OnFirstPump,
+
+    /// Before the hub is pumped, until the wiring has no errors. The hub doesn't pump while there are errors
+    Strict
+}
+
+///
+/// The addresses that something attached to a hub reads from and publishes to
+///
+#[derive(Clone, PartialEq)]
+pub struct ComponentWiring {
+    /// The name of the component or endpoint
+    pub name: String,
+
+    /// True for components, false for the consumers and publishers returned by the hub
+    pub is_component: bool,
+
+    /// The addresses this reads from
+    pub reads: Vec<TreeAddress>,
+
+    /// The addresses this publishes to
+    pub publishes: Vec<TreeAddress>
+}
+
+impl ComponentWiring {
+    ///
+    /// Describes a component
+    ///
+    pub fn component(name: &str, reads: Vec<TreeAddress>, publishes: Vec<TreeAddress>) -> ComponentWiring {
+        ComponentWiring { name: name.to_string(), is_component: true, reads, publishes }
+    }
+
+    ///
+    /// Describes a consumer or publisher returned by a hub
+    ///
+    pub fn endpoint(name: &str, reads: Vec<TreeAddress>, publishes: Vec<TreeAddress>) -> ComponentWiring {
+        ComponentWiring { name: name.to_string(), is_component: false, reads, publishes }
+    }
+}
+
+///
+/// Whether or not changes published to one address can be delivered to a subscription to another (None if the
+/// two addresses use different forms and this can't be decided)
+///
+fn connects(published: &TreeAddress, read: &TreeAddress) -> Option<bool> {
+    match (read.is_parent_of(published), published.is_parent_of(read)) {
+        (Some(true), _) | (_, Some(true))   => Some(true),
+        (Some(false), Some(false))          => Some(false),
+        _                                   => None
+    }
+}
+
+///
+/// True if two published addresses overlap
+///
+fn overlaps(first: &TreeAddress, second: &TreeAddress) -> bool {
+    first.is_parent_of(second) == Some(true) || second.is_parent_of(first) == Some(true)
+}
+
+///
+/// Creates an issue for a wiring report
+///
+fn issue(severity: &str, kind: &str, components: Vec<String>, addresses: Vec<String>, message: String) -> WiringIssue {
+    WiringIssue { severity: severity.to_string(), kind: kind.to_string(), components, addresses, message }
+}
+
+///
+/// Finds the addresses that are published to by more than one thing
+///
+fn double_writers(wiring: &[ComponentWiring], shared: &[TreeAddress]) -> Vec<WiringIssue> {
+    let mut issues = vec![];
+
+    for (pos, first) in wiring.iter().enumerate() {
+        for second in wiring[pos+1..].iter() {
+            for first_address in first.publishes.iter() {
+                for second_address in second.publishes.iter().filter(|address| overlaps(first_address, address)) {
+                    let is_shared = shared.iter().any(|shared| shared.is_parent_of(first_address) == Some(true) && shared.is_parent_of(second_address) == Some(true));
+
+                    if !is_shared {
+                        issues.push(issue("error", "double-writer",
+                            vec![first.name.clone(), second.name.clone()],
+                            vec![first_address.to_string(), second_address.to_string()],
+                            format!("'{}' publishes to {} and '{}' publishes to {}", first.name, first_address, second.name, second_address)));
+                    }
+                }
+            }
+        }
+    }
+
+    issues
+}
+
+///
+/// Finds the places where published and read addresses use different forms, using an addressing audit
+///
+fn address_kind_mismatches(wiring: &[ComponentWiring]) -> Vec<WiringIssue> {
+    let audit = AddressingAudit::new(MAX_AUDITED_PREFIXES);
+
+    for published in wiring.iter().flat_map(|item| item.publishes.iter()) {
+        audit.record_publication(published);
+
+        for read in wiring.iter().flat_map(|item| item.reads.iter()) {
+            audit.record_evaluation(published, read, connects(published, read));
+        }
+    }
+
+    audit.report().risky_prefixes.into_iter().map(|risky| {
+        let publishers  = wiring.iter().filter(|item| item.publishes.iter().any(|address| address.to_string() == risky.example_change));
+        let readers     = wiring.iter().filter(|item| item.reads.iter().any(|address| address.to_string() == risky.example_subscription));
+
+        issue("error", "address-kind-mismatch",
+            publishers.chain(readers).map(|item| item.name.clone()).collect(),
+            vec![risky.example_change.clone(), risky.example_subscription.clone()],
+            format!("below {}, addresses are published using {} and read using {}", risky.prefix, risky.publications, risky.subscriptions))
+    }).collect()
+}
+
+///
+/// Finds the component inputs that nothing publishes to and the component outputs that nothing reads
+///
+fn unconnected(wiring: &[ComponentWiring]) -> Vec<WiringIssue> {
+    let mut issues = vec![];
+
+    // Addresses that might be connected but use a different form are reported as mismatches instead
+    let published   = || wiring.iter().flat_map(|item| item.publishes.iter());
+    let read        = || wiring.iter().flat_map(|item| item.reads.iter());
+
+    for component in wiring.iter().filter(|item| item.is_component) {
+        for input in component.reads.iter().filter(|input| published().all(|address| connects(address, input) == Some(false))) {
+            issues.push(issue("warning", "unfed-input", vec![component.name.clone()], vec![input.to_string()],
+                format!("'{}' reads from {}, but nothing publishes there", component.name, input)));
+        }
+
+        for output in component.publishes.iter().filter(|output| read().all(|address| connects(output, address) == Some(false))) {
+            issues.push(issue("warning", "unconsumed-output", vec![component.name.clone()], vec![output.to_string()],
+                format!("'{}' publishes to {}, but nothing reads from there", component.name, output)));
+        }
+    }
+
+    issues
+}
+
+///
+/// Checks the wiring of a hub
+///
+/// Addresses that are published to by more than one thing are only allowed if they're inside one of the `shared`
+/// addresses.
+///
+pub fn validate_wiring(wiring: &[ComponentWiring], shared: &[TreeAddress]) -> WiringReport {
+    let mut issues = double_writers(wiring, shared);
+
+    issues.extend(address_kind_mismatches(wiring));
+    issues.extend(unconnected(wiring));
+
+    WiringReport { issues }
+}
+
+#[cfg(test)]
+mod wiring_tests {
+    use std::rc::*;
+    use std::cell::*;
+
+    use super::super::super::tree::*;
+    use super::super::super::component::*;
+    use super::*;
+
+    fn kinds(report: &WiringReport) -> Vec<String> {
+        report.issues.iter().map(|issue| issue.kind.clone()).collect()
+    }
+
+    #[test]
+    fn well_wired_hub_has_no_issues() {
+        let mut hub = Hub::new();
+        let _input  = hub.publish_to(&"in");
+        let _output = hub.read_from(&"out");
+
+        hub.add_named_component("add", component_fn(|x: &i32| x + 1), &"in", &"middle");
+        hub.add_named_component("double", component_fn(|x: &i32| x * 2), &"middle", &"out");
+
+        let report = hub.validate_wiring();
+        assert!(report.issues.is_empty());
+        assert!(report.to_string() == "No wiring problems found\n");
+    }
+
+    #[test]
+    fn unconnected_components_are_warnings() {
+        let mut hub = Hub::new();
+
+        hub.add_named_component("lonely", component_fn(|x: &i32| x + 1), &"in", &"out");
+
+        let report = hub.validate_wiring();
+        assert!(kinds(&report) == vec!["unfed-input", "unconsumed-output"]);
+        assert!(!report.has_errors());
+        assert!(report.warning_count() == 2);
+        assert!(report.issues[0].components == vec!["lonely"]);
+        assert!(report.issues[0].addresses == vec!["in".to_tree_address().to_string()]);
+    }
+
+    #[test]
+    fn mixed_address_forms_are_errors() {
+        let mut hub = Hub::new();
+        let _input  = hub.publish_to(&(0, "value"));
+
+        hub.add_named_component("reader", component_fn(|x: &i32| x + 1), &("settings", "value"), &"out");
+        let _output = hub.read_from(&"out");
+
+        let report = hub.validate_wiring();
+        assert!(kinds(&report) == vec!["address-kind-mismatch"]);
+        assert!(report.issues[0].components == vec![format!("publisher for {}", (0, "value").to_tree_address()), "reader".to_string()]);
+    }
+
+    #[test]
+    fn double_writers_are_allowed_once_declared() {
+        let mut hub = Hub::new();
+        let _input  = hub.publish_to(&"in");
+        let _output = hub.read_from(&"out");
+
+        hub.add_named_component("first", component_fn(|x: &i32| x + 1), &"in", &"out");
+        hub.add_named_component("second", component_fn(|x: &i32| x + 2), &"in", &("out", "second"));
+        assert!(kinds(&hub.validate_wiring()) == vec!["double-writer"]);
+
+        hub.declare_shared_output(&"out");
+        assert!(hub.validate_wiring().issues.is_empty());
+    }
+
+    #[test]
+    fn region_wiring_is_checked_until_the_region_is_closed() {
+        let mut hub     = Hub::new();
+        let _input      = hub.publish_to(&"in");
+        let mut region  = hub.region("feature");
+
+        region.add_named_component("add", component_fn(|x: &i32| x + 1), &"in", &"out").unwrap();
+        let _output = region.read_from(&"out").unwrap();
+        assert!(hub.validate_wiring().issues.is_empty());
+
+        // A second writer inside the region is found in the same way as one attached to the hub
+        let mut nested  = region.region("nested").unwrap();
+        let _writer     = nested.publish_to(&"out").unwrap();
+
+        let report = hub.validate_wiring();
+        assert!(kinds(&report) == vec!["double-writer"]);
+        assert!(report.issues[0].components == vec!["add".to_string(), format!("publisher for {}", "out".to_tree_address())]);
+
+        assert!(region.close() == Ok(()));
+        assert!(hub.validate_wiring().issues.is_empty());
+    }
+
+    #[test]
+    fn report_can_be_read_back_from_a_tree() {
+        let mut hub = Hub::new();
+        hub.add_named_component("lonely", component_fn(|x: &i32| x + 1), &"in", &"out");
+
+        let report  = hub.validate_wiring();
+        let decoded = WiringReport::new_from_tree(&report.as_tree()).unwrap();
+
+        assert!(decoded == report);
+    }
+
+    #[test]
+    fn strict_hub_does_not_pump_until_wiring_is_fixed() {
+        let mut hub     = Hub::new();
+        let mut input   = hub.publish_to(&"in");
+        let outputs     = Rc::new(RefCell::new(vec![]));
+        let recorded    = outputs.clone();
+
+        hub.set_wiring_validation(WiringValidation::Strict);
+        hub.add_named_component("double", component_fn(|x: &i32| x * 2), &"in", &"out");
+        hub.add_named_component("negate", component_fn(|x: &i32| -x), &"in", &"out");
+        hub.read_from(&"out").subscribe(TreeAddress::Here, TreeExtent::SubTree, Box::new(move |change| {
+            if let TreeReplacement::NewNode(ref node) = *change.replacement() {
+                recorded.borrow_mut().push(node.get_value().to_int(0));
+            }
+        }));
+
+        input.publish(TreeChange::new(&(), &2));
+        hub.flush();
+        assert!(hub.wiring_report().map(|report| report.has_errors()) == Some(true));
+        assert!(!hub.is_quiescent());
+        assert!(outputs.borrow().is_empty());
+
+        hub.declare_shared_output(&"out");
+        hub.flush();
+        assert!(hub.wiring_report().map(|report| report.has_errors()) == Some(false));
+        assert!(*outputs.borrow() == vec![4, -2]);
+    }
+}
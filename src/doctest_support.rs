@@ -0,0 +1,85 @@
+//
+//   Copyright 2016 Andrew Hunter
+//
+//   Licensed under the Apache License, Version 2.0 (the "License");
+//   you may not use this file except in compliance with the License.
+//   You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+//   Unless required by applicable law or agreed to in writing, software
+//   distributed under the License is distributed on an "AS IS" BASIS,
+//   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//   See the License for the specific language governing permissions and
+//   limitations under the License.
+//
+
+//!
+//! # Doctest fixtures
+//!
+//! A few canonical trees and addresses, so the examples in the documentation can stay short. This module is only
+//! compiled for the crate's own tests or when the `doctest_support` feature is enabled. Doctests are built against
+//! the crate without `cfg(test)`, so the examples that use these fixtures only run with the feature turned on:
+//!
+//! ```text
+//! cargo test --features doctest_support
+//! ```
+//!
+//! Without the feature, those examples are marked as ignored rather than failing to build.
+//!
+//! The sample tree looks like this:
+//!
+//! ```text
+//! root
+//!     first: 1
+//!     second: 2
+//!     third
+//!         child: 3
+//!         other: 4
+//! ```
+//!
+
+use super::tree::*;
+
+///
+/// The sample tree used in the documentation examples
+///
+pub fn sample_tree() -> TreeRef {
+    tree!("root", ("first", 1), ("second", 2), tree!("third", ("child", 3), ("other", 4)))
+}
+
+///
+/// The address of the `child` node in the sample tree, using tags
+///
+pub fn tagged_address() -> TreeAddress {
+    ("third", "child").to_tree_address()
+}
+
+///
+/// The address of the `child` node in the sample tree, using indexes
+///
+pub fn indexed_address() -> TreeAddress {
+    (2, 0).to_tree_address()
+}
+
+///
+/// The tags of the children of a node, in order
+///
+pub fn child_tags(node: &TreeRef) -> Vec<String> {
+    node.iter_children().map(|child| child.get_tag().to_string()).collect()
+}
+
+#[cfg(test)]
+mod doctest_support_tests {
+    use super::super::tree::*;
+    use super::*;
+
+    #[test]
+    fn addresses_refer_to_the_same_node() {
+        let tree = sample_tree();
+
+        assert!(tree.get_child_at(tagged_address()).get_value().to_int(0) == 3);
+        assert!(tree.get_child_at(indexed_address()).get_value().to_int(0) == 3);
+        assert!(child_tags(&tree) == vec!["first", "second", "third"]);
+    }
+}
@@ -85,9 +85,16 @@
 //! relating to the HTML displayed on the client will go straight back to the server. That would seem to make quite
 //! lot of that client-side javascript with all of its JSON encoding and decoding obsolete...
 
-extern crate rustc_serialize;
+pub extern crate rustc_serialize;
 
 #[macro_use]
 pub mod tree;
 pub mod component;           // TODO: new tree change
 mod util;
+pub mod prelude;
+
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+
+#[cfg(any(test, feature = "doctest_support"))]
+pub mod doctest_support;
@@ -0,0 +1,67 @@
+//
+//   Copyright 2016 Andrew Hunter
+//
+//   Licensed under the Apache License, Version 2.0 (the "License");
+//   you may not use this file except in compliance with the License.
+//   You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+//   Unless required by applicable law or agreed to in writing, software
+//   distributed under the License is distributed on an "AS IS" BASIS,
+//   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//   See the License for the specific language governing permissions and
+//   limitations under the License.
+//
+
+//!
+//! # Prelude
+//!
+//! The prelude contains the types, traits, functions and macros needed to build trees and components:
+//!
+//! ```
+//! use tametree::prelude::*;
+//!
+//! let component   = component_fn(|x: &i32| { x+1 });
+//! let mut endpoint = ComponentEndPoint::<i32, i32>::new(component);
+//!
+//! let input = tree!("input", ("value", 5));
+//! endpoint.send(input.get_child_at("value").get_value().to_int(0));
+//! assert!(endpoint.recv() == Some(6));
+//! ```
+//!
+//! The supported ways to import from this crate are:
+//!
+//! * `use tametree::prelude::*;` for everyday use.
+//! * `use tametree::tree::*;` for the complete set of tree types, including the more specialised ones such as
+//!   `AddressCache`, `ApplyBudget` or the lint rules.
+//! * `use tametree::component::*;` for the component traits and the component types that aren't publishers.
+//!   This no longer includes everything in `tametree::tree`: only the tree types that appear in the component
+//!   traits are re-exported from here.
+//! * `use tametree::component::<publisher module>::*;` for the publishers that aren't in the prelude.
+//!
+//! The machinery used to implement publishers (such as the subscription manager) is internal and can't be
+//! imported:
+//!
+//! ```compile_fail
+//! use tametree::component::subscriptionmanager::*;
+//! ```
+//!
+
+pub use tree::{TreeRef, TreeNode, ToTreeNode, TreeNodeLookup, TreeNodeIteration, BasicTree};
+pub use tree::{TreeValue, ToTreeValue};
+pub use tree::{TreeAddress, ToTreeAddress, TreeExtent};
+pub use tree::{TreeChange, TreeReplacement, ToTreeReplacement};
+pub use tree::{EncodeToTreeNode, DecodeFromTreeNode};
+pub use tree;
+pub use tree_struct;
+
+pub use component::{Publisher, PublisherRef, Consumer, ConsumerRef, ConsumerCallback, Component, ComponentRef, ConvertToComponent};
+pub use component::{component_fn, component_fn_mut, to_component, to_component_mut};
+pub use component::{ComponentEndPoint, ComponentEndPointBuilder, Receiver, RecvFn};
+pub use component::{ChangeTransform, MappedConsumer, Transformed};
+pub use component::Pipe;
+pub use component::hub::Hub;
+pub use component::immediate_publisher::ImmediatePublisher;
+pub use component::bus_publisher::TreeChangeBus;
+pub use component::output_tree_publisher::OutputTreePublisher;
@@ -0,0 +1,511 @@
+//
+//   Copyright 2016 Andrew Hunter
+//
+//   Licensed under the Apache License, Version 2.0 (the "License");
+//   you may not use this file except in compliance with the License.
+//   You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+//   Unless required by applicable law or agreed to in writing, software
+//   distributed under the License is distributed on an "AS IS" BASIS,
+//   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//   See the License for the specific language governing permissions and
+//   limitations under the License.
+//
+
+//!
+//! # Deterministic scheduling
+//!
+//! When a system is made up of several buses, the order they are pumped in can change what the system does. A
+//! `Scheduler` takes over pumping a set of buses or hubs and chooses which one to pump next according to a
+//! policy: round-robin, weighted random choices from a seed, or an explicit script. Every schedule it runs is
+//! recorded as a script, so an interleaving that causes a failure can be replayed exactly.
+//!
+//! `explore_seeds()` runs the same scenario with many seeds and checks an invariant after each one:
+//!
+//! ```
+//! # use tametree::prelude::*;
+//! # use tametree::testing::*;
+//! # use std::rc::*;
+//! # use std::cell::*;
+//! let result = explore_seeds(0..10, 100, |scheduler| {
+//!     let total = Rc::new(Cell::new(0));
+//!
+//!     for _ in 0..2 {
+//!         let bus             = TreeChangeBus::new();
+//!         let mut publisher   = bus.create_publisher();
+//!         let their_total     = total.clone();
+//!
+//!         bus.create_consumer().subscribe(TreeAddress::Here, TreeExtent::SubTree, Box::new(move |change| {
+//!             their_total.set(their_total.get() + change.apply(&"".to_tree_node()).get_value().to_int(0));
+//!         }));
+//!         publisher.publish(TreeChange::new(&(), &5));
+//!
+//!         scheduler.add("bus", bus);
+//!     }
+//!
+//!     move || if total.get() == 10 { Ok(()) } else { Err(format!("total was {}", total.get())) }
+//! });
+//!
+//! assert!(result.is_ok());
+//! ```
+//!
+
+use std::ops::Range;
+
+use super::super::component::*;
+use super::super::component::bus_publisher::*;
+
+///
+/// Something whose pumping can be taken over by a scheduler
+///
+pub trait Schedulable {
+    ///
+    /// True if pumping this object would do nothing
+    ///
+    fn is_quiescent(&self) -> bool;
+
+    ///
+    /// Sends one generation of waiting changes
+    ///
+    fn pump_once(&mut self);
+}
+
+impl Schedulable for TreeChangeBus {
+    fn is_quiescent(&self) -> bool {
+        TreeChangeBus::is_quiescent(self)
+    }
+
+    fn pump_once(&mut self) {
+        self.pump();
+    }
+}
+
+impl Schedulable for Hub {
+    fn is_quiescent(&self) -> bool {
+        Hub::is_quiescent(self)
+    }
+
+    fn pump_once(&mut self) {
+        self.pump();
+    }
+}
+
+///
+/// How a scheduler chooses what to pump next
+///
+#[derive(Clone, PartialEq, Debug)]
+pub enum SchedulePolicy {
+    /// Pump each target with waiting changes in turn
+    RoundRobin,
+
+    /// Choose a target with waiting changes at random, using the weights (targets without a weight have a weight of 1)
+    WeightedRandom { seed: u64, weights: Vec<u32> },
+
+    /// Pump the targets with the specified indexes in order, then continue round-robin if any changes are left
+    Script(Vec<usize>)
+}
+
+impl SchedulePolicy {
+    ///
+    /// A random policy where every target has the same weight
+    ///
+    pub fn seeded(seed: u64) -> SchedulePolicy {
+        SchedulePolicy::WeightedRandom { seed, weights: vec![] }
+    }
+}
+
+///
+/// Indicates that a scheduler stopped because it reached its step limit before every target was quiescent
+///
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct StepLimitReached {
+    pub steps: usize
+}
+
+///
+/// Pumps a set of buses in a deterministic order
+///
+pub struct Scheduler {
+    /// How the next target is chosen
+    policy: SchedulePolicy,
+
+    /// The targets, with their names
+    targets: Vec<(String, Box<dyn Schedulable>)>,
+
+    /// The indexes of the targets pumped so far
+    schedule: Vec<usize>,
+
+    /// The next target to consider for round-robin scheduling
+    next_round_robin: usize,
+
+    /// State of the random number generator
+    random_state: u64
+}
+
+impl Scheduler {
+    ///
+    /// Creates a scheduler with no targets
+    ///
+    pub fn new(policy: SchedulePolicy) -> Scheduler {
+        let random_state = match policy {
+            SchedulePolicy::WeightedRandom { seed, .. } => (seed ^ 0x9e37_79b9_7f4a_7c15).max(1),
+            _                                           => 1
+        };
+
+        Scheduler { policy, targets: vec![], schedule: vec![], next_round_robin: 0, random_state }
+    }
+
+    ///
+    /// Adds a target, returning its index in the schedule
+    ///
+    pub fn add<TTarget: Schedulable + 'static>(&mut self, name: &str, target: TTarget) -> usize {
+        self.targets.push((name.to_string(), Box::new(target)));
+        self.targets.len()-1
+    }
+
+    ///
+    /// True if none of the targets has any changes waiting
+    ///
+    pub fn is_quiescent(&self) -> bool {
+        self.targets.iter().all(|(_, target)| target.is_quiescent())
+    }
+
+    ///
+    /// The indexes of the targets that have been pumped so far, which can be replayed with `SchedulePolicy::Script`
+    ///
+    pub fn schedule(&self) -> &Vec<usize> {
+        &self.schedule
+    }
+
+    ///
+    /// Describes the schedule so far (for example, 'pump A, then B twice')
+    ///
+    pub fn describe_schedule(&self) -> String {
+        let mut steps: Vec<(usize, usize)> = vec![];
+
+        for target in self.schedule.iter() {
+            match steps.last_mut() {
+                Some(&mut (last_target, ref mut count)) if last_target == *target => *count += 1,
+                _                                                                   => steps.push((*target, 1))
+            }
+        }
+
+        let descriptions: Vec<String> = steps.iter().map(|&(target, count)| {
+            let name = &self.targets[target].0;
+
+            match count {
+                1 => name.to_string(),
+                2 => format!("{} twice", name),
+                _ => format!("{} {} times", name, count)
+            }
+        }).collect();
+
+        if descriptions.is_empty() {
+            "nothing pumped".to_string()
+        } else {
+            format!("pump {}", descriptions.join(", then "))
+        }
+    }
+
+    ///
+    /// Pumps the next target, returning false if there was nothing to do
+    ///
+    pub fn step(&mut self) -> bool {
+        let next = match self.policy {
+            SchedulePolicy::Script(ref script) if self.schedule.len() < script.len() => Some(script[self.schedule.len()]),
+            SchedulePolicy::WeightedRandom { .. }   => self.next_random_target(),
+            _                                       => self.next_round_robin_target()
+        };
+
+        match next {
+            Some(target) => {
+                self.targets[target].1.pump_once();
+                self.schedule.push(target);
+                true
+            },
+
+            None => false
+        }
+    }
+
+    ///
+    /// Pumps targets until all of them are quiescent, returning the number of steps taken
+    ///
+    pub fn run(&mut self, max_steps: usize) -> Result<usize, StepLimitReached> {
+        let mut steps = 0;
+
+        while self.step() {
+            steps += 1;
+
+            if steps >= max_steps && !self.is_quiescent() {
+                return Err(StepLimitReached { steps });
+            }
+        }
+
+        Ok(steps)
+    }
+
+    ///
+    /// Chooses the next target with waiting changes in round-robin order
+    ///
+    fn next_round_robin_target(&mut self) -> Option<usize> {
+        let count = self.targets.len();
+
+        for offset in 0..count {
+            let target = (self.next_round_robin + offset) % count;
+
+            if !self.targets[target].1.is_quiescent() {
+                self.next_round_robin = (target + 1) % count;
+                return Some(target);
+            }
+        }
+
+        None
+    }
+
+    ///
+    /// Chooses a random target with waiting changes
+    ///
+    fn next_random_target(&mut self) -> Option<usize> {
+        let weights = match self.policy {
+            SchedulePolicy::WeightedRandom { ref weights, .. }  => weights.clone(),
+            _                                                   => vec![]
+        };
+
+        let candidates: Vec<(usize, u64)> = self.targets.iter().enumerate()
+            .filter(|(_, (_, target))| !target.is_quiescent())
+            .map(|(index, _)| (index, weights.get(index).cloned().unwrap_or(1).max(1) as u64))
+            .collect();
+
+        let total: u64 = candidates.iter().map(|(_, weight)| weight).sum();
+        if total == 0 {
+            return None;
+        }
+
+        let mut choice = self.next_random() % total;
+        for (index, weight) in candidates {
+            if choice < weight {
+                return Some(index);
+            }
+            choice -= weight;
+        }
+
+        None
+    }
+
+    ///
+    /// Generates the next random number (xorshift64*)
+    ///
+    fn next_random(&mut self) -> u64 {
+        let mut x = self.random_state;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.random_state = x;
+
+        x.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+}
+
+///
+/// Describes a schedule that broke an invariant
+///
+#[derive(Clone, PartialEq, Debug)]
+pub struct ScheduleFailure {
+    /// The seed that produced the schedule (if it was a random one)
+    pub seed: Option<u64>,
+
+    /// The schedule that was run, which can be replayed with `SchedulePolicy::Script`
+    pub script: Vec<usize>,
+
+    /// A description of the schedule
+    pub description: String,
+
+    /// What went wrong
+    pub message: String
+}
+
+///
+/// Runs a scenario with a particular policy and checks its invariant
+///
+/// The setup function adds the targets to the scheduler (and publishes any initial changes) and returns a
+/// function that checks the state once every target is quiescent.
+///
+pub fn check_schedule<TSetup, TInvariant>(policy: SchedulePolicy, max_steps: usize, setup: TSetup) -> Result<(), ScheduleFailure>
+where TSetup: FnOnce(&mut Scheduler) -> TInvariant, TInvariant: FnOnce() -> Result<(), String> {
+    let seed = match policy {
+        SchedulePolicy::WeightedRandom { seed, .. } => Some(seed),
+        _                                           => None
+    };
+
+    let mut scheduler   = Scheduler::new(policy);
+    let invariant       = setup(&mut scheduler);
+    let result          = match scheduler.run(max_steps) {
+        Ok(_)           => invariant(),
+        Err(limit)      => Err(format!("still running after {} steps", limit.steps))
+    };
+
+    result.map_err(|message| ScheduleFailure { seed, script: scheduler.schedule().clone(), description: scheduler.describe_schedule(), message })
+}
+
+///
+/// Runs a scenario once for each of a range of seeds, returning the first failure
+///
+pub fn explore_seeds<TSetup, TInvariant>(seeds: Range<u64>, max_steps: usize, mut setup: TSetup) -> Result<(), ScheduleFailure>
+where TSetup: FnMut(&mut Scheduler) -> TInvariant, TInvariant: FnOnce() -> Result<(), String> {
+    for seed in seeds {
+        check_schedule(SchedulePolicy::seeded(seed), max_steps, &mut setup)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod scheduler_tests {
+    use std::rc::*;
+    use std::cell::*;
+
+    use super::super::super::tree::*;
+    use super::super::super::component::*;
+    use super::*;
+
+    type Log = Rc<RefCell<Vec<String>>>;
+
+    ///
+    /// Creates a hub that counts down from a value to zero, logging each value it sees
+    ///
+    fn countdown_hub(name: &'static str, start: i32, log: &Log, progress: &Rc<Cell<i32>>) -> Hub {
+        let mut hub         = Hub::new();
+        let mut input       = hub.publish_to(&"value");
+        let mut feedback    = hub.publish_to(&"value");
+        let log             = log.clone();
+        let progress        = progress.clone();
+
+        hub.read_from(&"value").subscribe(TreeAddress::Here, TreeExtent::SubTree, Box::new(move |change| {
+            let value = change.apply(&"value".to_tree_node()).get_value().to_int(0);
+
+            log.borrow_mut().push(format!("{}{}", name, value));
+            progress.set(value);
+
+            if value > 0 {
+                feedback.publish(TreeChange::new(&(), &("value", value-1)));
+            }
+        }));
+
+        input.publish(TreeChange::new(&(), &("value", start)));
+        hub
+    }
+
+    ///
+    /// A countdown whose values must appear in order
+    ///
+    fn check_countdown(log: &[String], name: &str, start: i32) -> Result<(), String> {
+        let seen: Vec<&String>  = log.iter().filter(|entry| entry.starts_with(name)).collect();
+        let expected: Vec<String> = (0..=start).rev().map(|value| format!("{}{}", name, value)).collect();
+
+        if seen.iter().cloned().eq(expected.iter()) { Ok(()) } else { Err(format!("{} counted {:?}", name, seen)) }
+    }
+
+    fn run_two_hubs(policy: SchedulePolicy) -> Vec<String> {
+        let log         = Rc::new(RefCell::new(vec![]));
+        let progress    = Rc::new(Cell::new(0));
+        let mut scheduler = Scheduler::new(policy);
+
+        scheduler.add("A", countdown_hub("a", 3, &log, &progress));
+        scheduler.add("B", countdown_hub("b", 3, &log, &progress));
+        assert!(scheduler.run(100) == Ok(8));
+
+        let result = log.borrow().clone();
+        result
+    }
+
+    ///
+    /// Fixture with an order-sensitive bug: A's final value is assumed to be seen after B has finished counting
+    ///
+    fn order_sensitive_scenario(scheduler: &mut Scheduler) -> impl FnOnce() -> Result<(), String> {
+        let log             = Rc::new(RefCell::new(vec![]));
+        let a_progress      = Rc::new(Cell::new(-1));
+        let b_progress      = Rc::new(Cell::new(-1));
+        let b_when_a_done   = Rc::new(Cell::new(-1));
+
+        let mut a_hub       = countdown_hub("a", 2, &log, &a_progress);
+        let their_b         = b_progress.clone();
+        let their_result    = b_when_a_done.clone();
+        a_hub.read_from(&"value").subscribe(TreeAddress::Here, TreeExtent::SubTree, Box::new(move |change| {
+            if change.apply(&"value".to_tree_node()).get_value().to_int(0) == 0 {
+                their_result.set(their_b.get());
+            }
+        }));
+
+        scheduler.add("A", a_hub);
+        scheduler.add("B", countdown_hub("b", 4, &log, &b_progress));
+
+        move || if b_when_a_done.get() == 0 { Ok(()) } else { Err(format!("A finished when B was at {}", b_when_a_done.get())) }
+    }
+
+    #[test]
+    fn round_robin_alternates() {
+        let log = run_two_hubs(SchedulePolicy::RoundRobin);
+
+        assert!(log == vec!["a3", "b3", "a2", "b2", "a1", "b1", "a0", "b0"]);
+    }
+
+    #[test]
+    fn seeds_give_different_valid_orders() {
+        let orders: Vec<Vec<String>> = (0..10).map(|seed| run_two_hubs(SchedulePolicy::seeded(seed))).collect();
+
+        assert!(orders.iter().any(|order| *order != orders[0]));
+        for order in orders.iter() {
+            assert!(check_countdown(order, "a", 3).is_ok());
+            assert!(check_countdown(order, "b", 3).is_ok());
+        }
+
+        // The same seed always gives the same order
+        assert!(run_two_hubs(SchedulePolicy::seeded(3)) == orders[3]);
+    }
+
+    #[test]
+    fn invariant_holds_across_seeds() {
+        let result = explore_seeds(0..50, 100, |scheduler| {
+            let log         = Rc::new(RefCell::new(vec![]));
+            let progress    = Rc::new(Cell::new(0));
+
+            scheduler.add("A", countdown_hub("a", 3, &log, &progress));
+            scheduler.add("B", countdown_hub("b", 5, &log, &progress));
+
+            move || {
+                check_countdown(&log.borrow(), "a", 3)?;
+                check_countdown(&log.borrow(), "b", 5)
+            }
+        });
+
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn order_sensitive_bug_is_found_and_replayed() {
+        let failure = explore_seeds(0..50, 100, order_sensitive_scenario).unwrap_err();
+
+        assert!(failure.seed.is_some());
+        assert!(failure.description.starts_with("pump "));
+
+        let replayed = check_schedule(SchedulePolicy::Script(failure.script.clone()), 100, order_sensitive_scenario).unwrap_err();
+        assert!(replayed.script == failure.script);
+        assert!(replayed.message == failure.message);
+    }
+
+    #[test]
+    fn describes_schedule() {
+        let mut scheduler = Scheduler::new(SchedulePolicy::Script(vec![0, 1, 1, 0]));
+        let log = Rc::new(RefCell::new(vec![]));
+        let progress = Rc::new(Cell::new(0));
+
+        scheduler.add("A", countdown_hub("a", 1, &log, &progress));
+        scheduler.add("B", countdown_hub("b", 1, &log, &progress));
+        scheduler.run(10).unwrap();
+
+        assert!(scheduler.describe_schedule() == "pump A, then B twice, then A");
+    }
+}
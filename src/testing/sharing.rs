@@ -0,0 +1,315 @@
+//
+//   Copyright 2016 Andrew Hunter
+//
+//   Licensed under the Apache License, Version 2.0 (the "License");
+//   you may not use this file except in compliance with the License.
+//   You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+//   Unless required by applicable law or agreed to in writing, software
+//   distributed under the License is distributed on an "AS IS" BASIS,
+//   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//   See the License for the specific language governing permissions and
+//   limitations under the License.
+//
+
+//!
+//! # Checking structural sharing
+//!
+//! Operations that produce a new version of a tree are supposed to reuse as much of the old tree as they can.
+//! `assert_sharing()` checks which nodes of a new tree are pointer-identical to nodes in an old one, so tests can
+//! make sure that this keeps happening.
+//!
+//! Nodes in a tree are linked through their first child and their next sibling, so replacing a node means that
+//! its parent and all of the siblings that come before it have to be copied too. The 'path' to an address is
+//! made up of all of these nodes: the address itself, its ancestors and the earlier siblings of each of them.
+//!
+//! ```
+//! # use tametree::prelude::*;
+//! # use tametree::testing::*;
+//! let tree    = tree!("root", ("one", 1), ("two", 2), ("three", 3));
+//! let changed = TreeChange::new(&"two", &("two", 4)).apply(&tree);
+//!
+//! assert_sharing(&tree, &changed, SharingExpectation::PathReallocated("two".to_tree_address()));
+//! ```
+//!
+
+use std::collections::HashSet;
+
+use super::super::tree::*;
+
+///
+/// Describes which nodes in a new tree are expected to be shared with an old tree
+///
+pub enum SharingExpectation {
+    /// Every node that isn't in the subtree at the specified address, or on the path to it, is shared
+    SharedOutside(TreeAddress),
+
+    /// Exactly the nodes on the path to the specified address are copied: everything else, including the
+    /// nodes inside that address, is shared
+    PathReallocated(TreeAddress),
+
+    /// At least the specified percentage of the nodes in the new tree are shared
+    SharedAtLeast(u32)
+}
+
+///
+/// Where a node in the new tree is relative to the address in an expectation
+///
+#[derive(Clone, Copy, PartialEq)]
+enum Placement {
+    Path,
+    Inside,
+    Outside
+}
+
+///
+/// Identifies a node by its location in memory
+///
+fn node_id(node: &TreeRef) -> *const u8 {
+    &**node as *const dyn TreeNode as *const u8
+}
+
+///
+/// Converts a list of child indexes to an address
+///
+fn index_address(indexes: &[usize]) -> TreeAddress {
+    indexes.iter().rev().fold(TreeAddress::Here, |address, index| TreeAddress::ChildAtIndex(*index, Box::new(address)))
+}
+
+///
+/// Finds the child indexes that lead to an address in a tree, if it's there
+///
+fn resolve_indexes(tree: &TreeRef, address: &TreeAddress) -> Option<Vec<usize>> {
+    let mut indexes = vec![];
+    let mut node    = tree.clone();
+    let mut address = address;
+
+    loop {
+        let (index, child, rest) = match *address {
+            TreeAddress::Here => return Some(indexes),
+
+            TreeAddress::ChildAtIndex(index, ref rest) => {
+                let child = node.get_child_ref_at(index);
+                (index, child, rest)
+            },
+
+            TreeAddress::ChildWithTag(ref tag, ref rest) => {
+                let found = node.iter_children().enumerate().find(|(_, child)| child.get_tag() == *tag);
+                match found {
+                    Some((index, child))    => (index, Some(child), rest),
+                    None                    => (0, None, rest)
+                }
+            }
+        };
+
+        indexes.push(index);
+        node    = child?;
+        address = &**rest;
+    }
+}
+
+///
+/// Works out where a node is relative to the path in an expectation
+///
+fn placement(node: &[usize], path: &[usize]) -> Placement {
+    if node.len() > path.len() {
+        if node[0..path.len()] == *path { Placement::Inside } else { Placement::Outside }
+    } else if node[0..node.len().saturating_sub(1)] != path[0..node.len().saturating_sub(1)] {
+        Placement::Outside
+    } else {
+        match node.last() {
+            None        => Placement::Path,
+            Some(index) => if *index <= path[node.len()-1] { Placement::Path } else { Placement::Outside }
+        }
+    }
+}
+
+///
+/// Visits every node in a tree, depth first, along with its child indexes
+///
+fn visit_nodes<TVisit: FnMut(&[usize], &TreeRef)>(node: &TreeRef, indexes: &mut Vec<usize>, visit: &mut TVisit) {
+    visit(indexes, node);
+
+    for (index, child) in node.iter_children().enumerate() {
+        indexes.push(index);
+        visit_nodes(&child, indexes, visit);
+        indexes.pop();
+    }
+}
+
+///
+/// Describes the first address in a list, if there is one
+///
+fn first_address(indexes: &Option<Vec<usize>>) -> String {
+    match *indexes {
+        Some(ref indexes)   => index_address(indexes).to_string(),
+        None                => "none".to_string()
+    }
+}
+
+///
+/// Checks which nodes in a new tree are shared with an old one, returning a description of the problem if it
+/// doesn't meet an expectation
+///
+pub fn check_sharing(old: &TreeRef, new: &TreeRef, expected: &SharingExpectation) -> Result<(), String> {
+    // Gather the nodes in the old tree
+    let mut old_nodes = HashSet::new();
+    visit_nodes(old, &mut vec![], &mut |_, node| { old_nodes.insert(node_id(node)); });
+
+    // The path to check against, if there is one
+    let path = match *expected {
+        SharingExpectation::SharedOutside(ref address) | SharingExpectation::PathReallocated(ref address) => {
+            let indexes = resolve_indexes(new, address).or_else(|| resolve_indexes(old, address));
+
+            match indexes {
+                Some(indexes)   => Some(indexes),
+                None            => return Err(format!("the address {} is not in either tree", address))
+            }
+        },
+
+        SharingExpectation::SharedAtLeast(_) => None
+    };
+
+    // Find the first nodes that don't match what's expected
+    let mut unexpectedly_reallocated    = None;
+    let mut unexpectedly_shared         = None;
+    let mut first_reallocated           = None;
+    let mut shared_count                = 0;
+    let mut total_count                 = 0;
+
+    visit_nodes(new, &mut vec![], &mut |indexes, node| {
+        let shared  = old_nodes.contains(&node_id(node));
+        let place   = path.as_ref().map(|path| placement(indexes, path)).unwrap_or(Placement::Outside);
+
+        let (must_share, must_copy) = match *expected {
+            SharingExpectation::SharedOutside(_)    => (place == Placement::Outside, false),
+            SharingExpectation::PathReallocated(_)  => (place != Placement::Path, place == Placement::Path),
+            SharingExpectation::SharedAtLeast(_)    => (false, false)
+        };
+
+        total_count += 1;
+        if shared {
+            shared_count += 1;
+        } else if first_reallocated.is_none() {
+            first_reallocated = Some(indexes.to_vec());
+        }
+
+        if must_share && !shared && unexpectedly_reallocated.is_none() {
+            unexpectedly_reallocated = Some(indexes.to_vec());
+        }
+        if must_copy && shared && unexpectedly_shared.is_none() {
+            unexpectedly_shared = Some(indexes.to_vec());
+        }
+    });
+
+    match *expected {
+        SharingExpectation::SharedAtLeast(percent) => {
+            if shared_count * 100 >= total_count * (percent as usize) {
+                Ok(())
+            } else {
+                Err(format!("only {} of {} nodes were shared (expected at least {}%): the first reallocated address was {}",
+                    shared_count, total_count, percent, first_address(&first_reallocated)))
+            }
+        },
+
+        SharingExpectation::SharedOutside(ref address) | SharingExpectation::PathReallocated(ref address) => {
+            if unexpectedly_reallocated.is_none() && unexpectedly_shared.is_none() {
+                Ok(())
+            } else {
+                Err(format!("unexpected sharing around {}: the first unexpectedly reallocated address was {} and the first unexpectedly shared address was {}",
+                    address, first_address(&unexpectedly_reallocated), first_address(&unexpectedly_shared)))
+            }
+        }
+    }
+}
+
+///
+/// Panics with a description of the problem if the nodes that are shared between two trees don't meet an expectation
+///
+pub fn assert_sharing(old: &TreeRef, new: &TreeRef, expected: SharingExpectation) {
+    if let Err(problem) = check_sharing(old, new, &expected) {
+        panic!("{}", problem);
+    }
+}
+
+#[cfg(test)]
+mod sharing_tests {
+    use super::super::super::tree::*;
+    use super::*;
+
+    fn sample() -> TreeRef {
+        tree!("root", ("one", 1), tree!("two", ("a", 1), ("b", 2)), ("three", 3))
+    }
+
+    #[test]
+    fn shared_outside_accepts_a_replaced_subtree() {
+        let tree    = sample();
+        let changed = TreeChange::new(&"two", &tree!("two", ("c", 3))).apply(&tree);
+
+        assert_sharing(&tree, &changed, SharingExpectation::SharedOutside("two".to_tree_address()));
+    }
+
+    #[test]
+    fn shared_outside_reports_the_first_copied_node() {
+        let tree    = sample();
+        let changed = TreeChange::new(&"three", &("three", 4)).apply(&tree);
+        let result  = check_sharing(&tree, &changed, &SharingExpectation::SharedOutside("one".to_tree_address()));
+
+        // Changing 'three' copies 'two', which is outside the path to 'one'
+        assert!(result == Err("unexpected sharing around .\"one\".: the first unexpectedly reallocated address was .1. and the first unexpectedly shared address was none".to_string()));
+    }
+
+    #[test]
+    fn path_reallocated_accepts_a_new_value() {
+        let tree    = sample();
+        let changed = TreeChange::new(&("two", "b"), &("b", 5)).apply(&tree);
+
+        assert_sharing(&tree, &changed, SharingExpectation::PathReallocated(("two", "b").to_tree_address()));
+    }
+
+    #[test]
+    fn path_reallocated_reports_unexpected_sharing() {
+        let tree    = sample();
+        let changed = TreeChange::new(&"one", &("one", 4)).apply(&tree);
+
+        // Replacing 'one' doesn't copy 'two', which is on the path to 'three'
+        let result  = check_sharing(&tree, &changed, &SharingExpectation::PathReallocated("three".to_tree_address()));
+        assert!(result == Err("unexpected sharing around .\"three\".: the first unexpectedly reallocated address was none and the first unexpectedly shared address was .1.".to_string()));
+    }
+
+    #[test]
+    fn path_reallocated_reports_copied_children() {
+        let tree    = sample();
+        let changed = TreeChange::new(&"two", &tree!("two", ("a", 1), ("b", 2))).apply(&tree);
+        let result  = check_sharing(&tree, &changed, &SharingExpectation::PathReallocated("two".to_tree_address()));
+
+        assert!(result == Err("unexpected sharing around .\"two\".: the first unexpectedly reallocated address was .1.0. and the first unexpectedly shared address was none".to_string()));
+    }
+
+    #[test]
+    fn percentage_of_shared_nodes() {
+        let tree    = sample();
+        let changed = TreeChange::new(&"three", &("three", 4)).apply(&tree);
+
+        // Root, 'one', 'two' and 'three' are copied, 'a' and 'b' are shared
+        assert_sharing(&tree, &changed, SharingExpectation::SharedAtLeast(33));
+
+        let result  = check_sharing(&tree, &changed, &SharingExpectation::SharedAtLeast(50));
+        assert!(result == Err("only 2 of 6 nodes were shared (expected at least 50%): the first reallocated address was .".to_string()));
+    }
+
+    #[test]
+    fn different_shapes_are_reported() {
+        let tree    = sample();
+        let other   = tree!("other", tree!("x", tree!("y", ("z", 1))), ("w", 2));
+
+        assert!(check_sharing(&tree, &other, &SharingExpectation::SharedAtLeast(1)).is_err());
+        assert!(check_sharing(&tree, &other, &SharingExpectation::SharedOutside(("x", "y").to_tree_address())).is_err());
+        assert!(check_sharing(&tree, &other, &SharingExpectation::PathReallocated(("x", ("y", "z")).to_tree_address())).is_err());
+        assert!(check_sharing(&tree, &other, &SharingExpectation::PathReallocated(("missing", "address").to_tree_address()))
+            == Err("the address .\"missing\".\"address\". is not in either tree".to_string()));
+        assert!(check_sharing(&tree, &other, &SharingExpectation::SharedOutside((7, 3).to_tree_address())).is_err());
+    }
+}
@@ -88,6 +88,21 @@ impl TreeAddress {
     ///
     /// Will return None if the two addresses are in incompatible formats (eg, if a tag needs to match up against an indexed address)
     ///
+    #[cfg_attr(feature = "doctest_support", doc = "```")]
+    #[cfg_attr(not(feature = "doctest_support"), doc = "```ignore")]
+    /// # use tametree::prelude::*;
+    /// # use tametree::doctest_support::*;
+    /// let third = "third".to_tree_address();
+    ///
+    /// assert!(third.is_parent_of(&tagged_address()) == Some(true));
+    /// assert!(third.is_parent_of(&third) == Some(true));
+    /// assert!(tagged_address().is_parent_of(&third) == Some(false));
+    /// assert!(TreeAddress::Here.is_parent_of(&indexed_address()) == Some(true));
+    ///
+    /// // Tags can't be compared with indexes, even though both of these addresses refer to 'child' in the sample tree
+    /// assert!(tagged_address().is_parent_of(&indexed_address()) == None);
+    /// ```
+    ///
     pub fn is_parent_of(&self, address: &TreeAddress) -> Option<bool> {
         match *self {
             // 'Here' is the root address, the parent of everything (including itself)
@@ -129,6 +144,14 @@ impl TreeAddress {
     ///
     /// Returns whether or not address is a child of this address or the same address
     ///
+    #[cfg_attr(feature = "doctest_support", doc = "```")]
+    #[cfg_attr(not(feature = "doctest_support"), doc = "```ignore")]
+    /// # use tametree::prelude::*;
+    /// # use tametree::doctest_support::*;
+    /// assert!(tagged_address().is_child_of(&"third".to_tree_address()) == Some(true));
+    /// assert!(tagged_address().is_child_of(&2.to_tree_address()) == None);
+    /// ```
+    ///
     #[inline]
     pub fn is_child_of(&self, address: &TreeAddress) -> Option<bool> {
         address.is_parent_of(self)
@@ -138,6 +161,19 @@ impl TreeAddress {
     /// Transforms this address to a new address that is relative to a particular parent address (or None if the addresses 
     /// are in different formats or if parent_address is not a parent of this address)
     ///
+    #[cfg_attr(feature = "doctest_support", doc = "```")]
+    #[cfg_attr(not(feature = "doctest_support"), doc = "```ignore")]
+    /// # use tametree::prelude::*;
+    /// # use tametree::doctest_support::*;
+    /// assert!(tagged_address().relative_to(&"third".to_tree_address()) == Some("child".to_tree_address()));
+    /// assert!(tagged_address().relative_to(&tagged_address()) == Some(TreeAddress::Here));
+    ///
+    /// // Addresses that aren't inside the parent, or that are in a different format, have no relative address
+    /// assert!(tagged_address().relative_to(&"first".to_tree_address()) == None);
+    /// assert!(tagged_address().relative_to(&2.to_tree_address()) == None);
+    /// assert!(TreeAddress::Here.relative_to(&"third".to_tree_address()) == None);
+    /// ```
+    ///
     pub fn relative_to(&self, parent_address: &TreeAddress) -> Option<TreeAddress> {
         match *self {
             // Here is a root address, so it doesn't match anything but itself
@@ -192,6 +228,15 @@ impl TreeAddress {
     ///
     /// Returns the parent of the current address
     ///
+    #[cfg_attr(feature = "doctest_support", doc = "```")]
+    #[cfg_attr(not(feature = "doctest_support"), doc = "```ignore")]
+    /// # use tametree::prelude::*;
+    /// # use tametree::doctest_support::*;
+    /// assert!(tagged_address().parent() == "third".to_tree_address());
+    /// assert!("third".to_tree_address().parent() == TreeAddress::Here);
+    /// assert!(TreeAddress::Here.parent() == TreeAddress::Here);
+    /// ```
+    ///
     pub fn parent(&self) -> TreeAddress {
         match *self {
             // 'Here' doesn't have a parent other than itself
@@ -217,6 +262,15 @@ impl TreeAddress {
     ///
     /// Returns the last part of the address (before the final `Here`)
     ///
+    #[cfg_attr(feature = "doctest_support", doc = "```")]
+    #[cfg_attr(not(feature = "doctest_support"), doc = "```ignore")]
+    /// # use tametree::prelude::*;
+    /// # use tametree::doctest_support::*;
+    /// assert!(*tagged_address().last_part() == "child".to_tree_address());
+    /// assert!(*indexed_address().last_part() == 0.to_tree_address());
+    /// assert!(*TreeAddress::Here.last_part() == TreeAddress::Here);
+    /// ```
+    ///
     pub fn last_part(&self) -> &TreeAddress {
         let mut last_part = self;
         let mut next_part = self;
@@ -254,13 +308,35 @@ impl fmt::Display for TreeAddress {
 ///
 /// This has `TreeNodeIndex` implemented on it, so `treenode.get_child_ref_at(Addr(0, ()))` will work
 ///
-pub struct Addr<TFirst: ToTreeAddress, TSecond: ToTreeAddress>(TFirst, TSecond);
+pub struct Addr<TFirst: ToTreeAddress, TSecond: ToTreeAddress>(pub TFirst, pub TSecond);
 
 ///
 /// Trait that is implemented by types that can be converted to tree addresses
 ///
 pub trait ToTreeAddress {
+    ///
+    /// Converts this value to a tree address
+    ///
+    /// ```
+    /// # use tametree::prelude::*;
+    /// assert!(().to_tree_address() == TreeAddress::Here);
+    /// assert!(("third", 1).to_tree_address() == TreeAddress::ChildWithTag("third".to_string(), Box::new(1.to_tree_address())));
+    /// ```
+    ///
     fn to_tree_address(&self) -> TreeAddress;
+
+    ///
+    /// Converts this value to a tree address, then appends another address to the end of it
+    ///
+    #[cfg_attr(feature = "doctest_support", doc = "```")]
+    #[cfg_attr(not(feature = "doctest_support"), doc = "```ignore")]
+    /// # use tametree::prelude::*;
+    /// # use tametree::doctest_support::*;
+    /// let address = "third".to_tree_address().to_tree_address_then("child".to_tree_address());
+    ///
+    /// assert!(address == tagged_address());
+    /// ```
+    ///
     fn to_tree_address_then(&self, then: TreeAddress) -> TreeAddress;
 }
 
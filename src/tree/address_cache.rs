@@ -0,0 +1,390 @@
+//
+//   Copyright 2016 Andrew Hunter
+//
+//   Licensed under the Apache License, Version 2.0 (the "License");
+//   you may not use this file except in compliance with the License.
+//   You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+//   Unless required by applicable law or agreed to in writing, software
+//   distributed under the License is distributed on an "AS IS" BASIS,
+//   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//   See the License for the specific language governing permissions and
+//   limitations under the License.
+//
+
+//!
+//! # Address canonicalization cache
+//!
+//! Tagged addresses need to be resolved by searching the children of each node in turn. Anything that holds an
+//! authoritative copy of a tree and needs to resolve the same tagged addresses repeatedly can use an `AddressCache`
+//! to remember the indexed form of these addresses.
+//!
+//! The cache must be told about every change made to the tree (along with the tree as it was before the change)
+//! so that it can evict the entries that the change might make stale. Value changes that leave the tag of a node
+//! alone do not evict anything. Structural changes evict the entries resolved through the parent of the changed
+//! node: replacing or removing a node can change the index of its later siblings and which sibling a tag refers
+//! to, as well as anything beneath it. Entries are also stamped with a generation, so changes whose effects can't
+//! be determined precisely can invalidate everything at once.
+//!
+
+use std::collections::HashMap;
+
+use super::treenode::*;
+use super::address::*;
+use super::change::*;
+use super::iterator::*;
+
+///
+/// One part of a flattened address
+///
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum AddressPart {
+    Index(usize),
+    Tag(String)
+}
+
+///
+/// A resolved address stored in the cache
+///
+struct CacheEntry {
+    /// The indexes of the nodes along the path to this address
+    resolved: Vec<usize>,
+
+    /// The generation of the cache when this entry was resolved
+    generation: u64
+}
+
+///
+/// Caches the results of converting tagged addresses into indexed addresses
+///
+pub struct AddressCache {
+    /// The resolved addresses
+    entries: HashMap<Vec<AddressPart>, CacheEntry>,
+
+    /// Entries from earlier generations than this one are stale
+    generation: u64,
+
+    /// Number of lookups that were satisfied by the cache
+    hits: usize,
+
+    /// Number of lookups that needed to search the tree
+    misses: usize
+}
+
+///
+/// Converts an address into a list of parts
+///
+fn flatten(address: &TreeAddress) -> Vec<AddressPart> {
+    let mut result  = vec![];
+    let mut current = address;
+
+    loop {
+        match *current {
+            TreeAddress::Here                               => return result,
+            TreeAddress::ChildAtIndex(index, ref next)      => { result.push(AddressPart::Index(index)); current = next; },
+            TreeAddress::ChildWithTag(ref tag, ref next)    => { result.push(AddressPart::Tag(tag.clone())); current = next; }
+        }
+    }
+}
+
+///
+/// Converts a list of indexes into an address
+///
+fn unflatten(resolved: &[usize]) -> TreeAddress {
+    resolved.iter().rev().fold(TreeAddress::Here, |address, index| TreeAddress::ChildAtIndex(*index, Box::new(address)))
+}
+
+///
+/// Finds the index of the child of a node matching an address part, if it exists
+///
+fn find_child(node: &TreeRef, part: &AddressPart) -> Option<(usize, TreeRef)> {
+    match *part {
+        AddressPart::Index(index)   => node.iter_children().nth(index).map(|child| (index, child)),
+        AddressPart::Tag(ref tag)   => node.iter_children().enumerate().find(|(_, child)| child.get_tag() == tag)
+    }
+}
+
+impl AddressCache {
+    ///
+    /// Creates a new, empty, address cache
+    ///
+    pub fn new() -> AddressCache {
+        AddressCache { entries: HashMap::new(), generation: 0, hits: 0, misses: 0 }
+    }
+
+    ///
+    /// Converts an address into an address containing only indexes, or None if the address doesn't exist in the tree
+    ///
+    /// The tree must be the one that this cache has been tracking changes for.
+    ///
+    pub fn canonicalize(&mut self, tree: &TreeRef, address: &TreeAddress) -> Option<TreeAddress> {
+        let parts = flatten(address);
+
+        if let Some(entry) = self.entries.get(&parts) {
+            if entry.generation == self.generation {
+                self.hits += 1;
+                return Some(unflatten(&entry.resolved));
+            }
+        }
+
+        self.misses += 1;
+
+        // Search the tree for the address
+        let mut resolved    = vec![];
+        let mut current     = tree.clone();
+
+        for part in parts.iter() {
+            let (index, child) = find_child(&current, part)?;
+
+            resolved.push(index);
+            current = child;
+        }
+
+        let result = unflatten(&resolved);
+        self.entries.insert(parts, CacheEntry { resolved, generation: self.generation });
+
+        Some(result)
+    }
+
+    ///
+    /// Updates the cache for a change that is about to be applied to (or has just been applied to) the specified tree
+    ///
+    /// The tree should be the one from before the change was applied.
+    ///
+    pub fn apply_change(&mut self, tree_before: &TreeRef, change: &TreeChange) {
+        let parts = flatten(change.address());
+
+        // Resolve the parent of the changed node: if it doesn't exist we can't tell what will happen so everything is evicted
+        let mut parent_path = vec![];
+        let mut parent      = tree_before.clone();
+
+        for part in parts.iter().take(parts.len().saturating_sub(1)) {
+            match find_child(&parent, part) {
+                Some((index, child)) => {
+                    parent_path.push(index);
+                    parent = child;
+                },
+
+                None => {
+                    self.invalidate_all();
+                    return;
+                }
+            }
+        }
+
+        // Changing the root node affects every entry
+        let last_part = match parts.last() {
+            Some(last_part) => last_part,
+            None            => { self.invalidate_all(); return; }
+        };
+
+        // Changing only the value of an existing node doesn't affect any addresses
+        if let TreeReplacement::NewValue(ref new_tag, _) = *change.replacement() {
+            if let Some((_, existing)) = find_child(&parent, last_part) {
+                if existing.get_tag() == new_tag {
+                    return;
+                }
+            }
+        }
+
+        // Structural changes can affect any address resolved through the parent of the changed node
+        self.entries.retain(|_, entry| !(entry.resolved.len() > parent_path.len() && entry.resolved.starts_with(&parent_path)));
+    }
+
+    ///
+    /// Marks every entry in the cache as stale
+    ///
+    pub fn invalidate_all(&mut self) {
+        self.generation += 1;
+        self.entries.clear();
+    }
+
+    ///
+    /// The number of lookups that were satisfied from the cache
+    ///
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+
+    ///
+    /// The number of lookups that needed to search the tree
+    ///
+    pub fn misses(&self) -> usize {
+        self.misses
+    }
+
+    ///
+    /// The number of addresses currently stored in the cache
+    ///
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    ///
+    /// True if there are no addresses stored in the cache
+    ///
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl Default for AddressCache {
+    fn default() -> AddressCache {
+        AddressCache::new()
+    }
+}
+
+#[cfg(test)]
+mod address_cache_tests {
+    use super::super::super::tree::*;
+
+    fn sample_tree() -> TreeRef {
+        tree!("root", ("a", 1), tree!("b", ("x", 2), ("y", 3)), tree!("c", ("z", 4)))
+    }
+
+    fn change_and_check(cache: &mut AddressCache, tree: &TreeRef, change: TreeChange) -> TreeRef {
+        cache.apply_change(tree, &change);
+        change.apply(tree)
+    }
+
+    #[test]
+    fn canonicalizes_tagged_address() {
+        let tree        = sample_tree();
+        let mut cache   = AddressCache::new();
+
+        assert!(cache.canonicalize(&tree, &("b", "y").to_tree_address()) == Some((1, 1).to_tree_address()));
+        assert!(cache.canonicalize(&tree, &(2, "z").to_tree_address()) == Some((2, 0).to_tree_address()));
+        assert!(cache.canonicalize(&tree, &("b", "missing").to_tree_address()).is_none());
+    }
+
+    #[test]
+    fn repeated_lookups_hit_the_cache() {
+        let tree        = sample_tree();
+        let mut cache   = AddressCache::new();
+
+        for _ in 0..5 {
+            assert!(cache.canonicalize(&tree, &("c", "z").to_tree_address()) == Some((2, 0).to_tree_address()));
+        }
+
+        assert!(cache.misses() == 1);
+        assert!(cache.hits() == 4);
+    }
+
+    #[test]
+    fn value_changes_do_not_evict() {
+        let mut tree    = sample_tree();
+        let mut cache   = AddressCache::new();
+
+        cache.canonicalize(&tree, &("b", "y").to_tree_address());
+        tree = change_and_check(&mut cache, &tree, TreeChange::new(&("b", "x"), &TreeReplacement::NewValue("x".to_string(), TreeValue::Int(10))));
+
+        assert!(cache.len() == 1);
+        assert!(cache.canonicalize(&tree, &("b", "y").to_tree_address()) == Some((1, 1).to_tree_address()));
+        assert!(cache.hits() == 1);
+    }
+
+    #[test]
+    fn retagging_a_node_evicts_its_siblings() {
+        let mut tree    = sample_tree();
+        let mut cache   = AddressCache::new();
+
+        assert!(cache.canonicalize(&tree, &"c".to_tree_address()) == Some(2.to_tree_address()));
+
+        // Node 'a' becomes a second 'c' which is found first
+        tree = change_and_check(&mut cache, &tree, TreeChange::new(&"a", &TreeReplacement::NewValue("c".to_string(), TreeValue::Nothing)));
+
+        assert!(cache.canonicalize(&tree, &"c".to_tree_address()) == Some(0.to_tree_address()));
+        assert!(cache.misses() == 2);
+    }
+
+    #[test]
+    fn insertion_before_resolved_index_re_resolves() {
+        let mut tree    = sample_tree();
+        let mut cache   = AddressCache::new();
+
+        assert!(cache.canonicalize(&tree, &("c", "z").to_tree_address()) == Some((2, 0).to_tree_address()));
+
+        // Replace 'a' with two nodes, which moves 'c' along by one
+        let inserted = ("new", 0).to_tree_node().with_sibling_node(Some(&("a", 1).to_tree_node()));
+        tree = change_and_check(&mut cache, &tree, TreeChange::new(&0, &inserted));
+
+        assert!(cache.canonicalize(&tree, &("c", "z").to_tree_address()) == Some((3, 0).to_tree_address()));
+        assert!(tree.get_child_ref_at((3, 0).to_tree_address()).unwrap().get_value().to_int(0) == 4);
+        assert!(cache.hits() == 0);
+    }
+
+    #[test]
+    fn removal_before_resolved_index_re_resolves() {
+        let mut tree    = sample_tree();
+        let mut cache   = AddressCache::new();
+
+        assert!(cache.canonicalize(&tree, &"c".to_tree_address()) == Some(2.to_tree_address()));
+        tree = change_and_check(&mut cache, &tree, TreeChange::new(&"a", &()));
+
+        assert!(cache.canonicalize(&tree, &"c".to_tree_address()) == Some(1.to_tree_address()));
+    }
+
+    #[test]
+    fn structural_change_elsewhere_keeps_entries() {
+        let mut tree    = sample_tree();
+        let mut cache   = AddressCache::new();
+
+        cache.canonicalize(&tree, &("c", "z").to_tree_address());
+        cache.canonicalize(&tree, &("b", "x").to_tree_address());
+
+        // Only the entries beneath 'b' can be affected by this
+        tree = change_and_check(&mut cache, &tree, TreeChange::new(&("b", "x"), &()));
+
+        assert!(cache.len() == 1);
+        assert!(cache.canonicalize(&tree, &("c", "z").to_tree_address()) == Some((2, 0).to_tree_address()));
+        assert!(cache.canonicalize(&tree, &("b", "x").to_tree_address()).is_none());
+        assert!(cache.hits() == 1);
+    }
+
+    #[test]
+    fn replacing_root_evicts_everything() {
+        let mut tree    = sample_tree();
+        let mut cache   = AddressCache::new();
+
+        cache.canonicalize(&tree, &("c", "z").to_tree_address());
+        tree = change_and_check(&mut cache, &tree, TreeChange::new(&(), &tree!("root", tree!("c", ("z", 1)))));
+
+        assert!(cache.is_empty());
+        assert!(cache.canonicalize(&tree, &("c", "z").to_tree_address()) == Some((0, 0).to_tree_address()));
+    }
+
+    #[test]
+    fn scripted_hit_rate() {
+        let mut tree    = sample_tree();
+        let mut cache   = AddressCache::new();
+        let watched     = [("b", "y").to_tree_address(), ("c", "z").to_tree_address(), "a".to_tree_address()];
+
+        // Each round looks up every watched address, then makes a change
+        let changes = vec![
+            TreeChange::new(&"a", &TreeReplacement::NewValue("a".to_string(), TreeValue::Int(5))),     // Value only: no evictions
+            TreeChange::new(&("b", "x"), &("x", 7)),                                                    // Evicts ("b", "y") only
+            TreeChange::new(&"b", &tree!("b", ("y", 3))),                                               // Evicts everything under the root
+        ];
+
+        for change in changes {
+            for address in watched.iter() {
+                assert!(cache.canonicalize(&tree, address).is_some());
+            }
+
+            tree = change_and_check(&mut cache, &tree, change);
+        }
+
+        // Round 1: 3 misses, round 2: 3 hits, round 3: 1 miss + 2 hits
+        assert!(cache.misses() == 4);
+        assert!(cache.hits() == 5);
+
+        // Everything was evicted by the last change
+        for address in watched.iter() {
+            assert!(cache.canonicalize(&tree, address).is_some());
+        }
+        assert!(cache.misses() == 7);
+        assert!(cache.canonicalize(&tree, &("b", "y").to_tree_address()) == Some((1, 0).to_tree_address()));
+    }
+}
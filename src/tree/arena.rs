@@ -0,0 +1,328 @@
+//
+//   Copyright 2016 Andrew Hunter
+//
+//   Licensed under the Apache License, Version 2.0 (the "License");
+//   you may not use this file except in compliance with the License.
+//   You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+//   Unless required by applicable law or agreed to in writing, software
+//   distributed under the License is distributed on an "AS IS" BASIS,
+//   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//   See the License for the specific language governing permissions and
+//   limitations under the License.
+//
+
+//!
+//! # Arena trees
+//!
+//! Every `BasicTree` node is a separate reference-counted allocation, and dropping a large tree means following
+//! a long chain of reference decrements. For workloads that build a large temporary tree, look at it and then
+//! throw most of it away, a `TreeArena` can be used instead: its nodes are stored together and are all freed at
+//! once when the arena is dropped.
+//!
+//! Nodes in an arena are referred to by `ArenaTree` handles, which borrow the arena so they can't outlive it.
+//! Any part of the tree that needs to be kept can be copied out into an ordinary tree with `detach()`:
+//!
+//! ```
+//! # use tametree::tree::*;
+//! let kept = {
+//!     let arena   = TreeArena::new();
+//!     let root    = parse_tree_text_in(&arena, "root\n    keep: 1\n    discard: 2").unwrap();
+//!
+//!     arena.detach(&root.get_child().unwrap())
+//! };
+//!
+//! assert!(kept.get_tag() == "keep");
+//! assert!(kept.get_value().to_int(0) == 1);
+//! assert!(kept.get_sibling_ref().is_none());
+//! ```
+//!
+//! Arena nodes can't be changed once they are created, and they don't implement `TreeNode` (whose references
+//! are always `TreeRef`s), so the tree functions in this crate need a detached copy to work with.
+//!
+
+use std::cell::*;
+use std::rc::*;
+
+use super::treenode::*;
+use super::basictree::*;
+use super::values::*;
+
+///
+/// A node stored in an arena
+///
+struct ArenaEntry {
+    /// Where the tag for this node is stored in the arena's tag buffer
+    tag: (usize, usize),
+
+    value: TreeValue,
+    child: Option<usize>,
+    sibling: Option<usize>
+}
+
+///
+/// Storage for the nodes of one or more arena trees
+///
+pub struct TreeArena {
+    /// The nodes in this arena
+    nodes: RefCell<Vec<ArenaEntry>>,
+
+    /// The tags of the nodes in this arena, one after another
+    tags: RefCell<String>,
+
+    /// The number of times the storage for this arena has been allocated
+    allocations: Cell<usize>
+}
+
+///
+/// Handle to a node stored in a `TreeArena`
+///
+#[derive(Clone, Copy)]
+pub struct ArenaTree<'a> {
+    arena: &'a TreeArena,
+    index: usize
+}
+
+impl TreeArena {
+    ///
+    /// Creates a new, empty arena
+    ///
+    pub fn new() -> TreeArena {
+        TreeArena { nodes: RefCell::new(vec![]), tags: RefCell::new(String::new()), allocations: Cell::new(0) }
+    }
+
+    ///
+    /// Creates a new node in this arena
+    ///
+    pub fn add<'a, TValue: ToTreeValue>(&'a self, tag: &str, value: TValue, child: Option<ArenaTree<'a>>, sibling: Option<ArenaTree<'a>>) -> ArenaTree<'a> {
+        let child   = child.map(|node| self.index_of(&node));
+        let sibling = sibling.map(|node| self.index_of(&node));
+
+        let mut tags    = self.tags.borrow_mut();
+        let mut nodes   = self.nodes.borrow_mut();
+
+        // Storage is only reallocated when it runs out of capacity (which happens less often as the arena grows)
+        let tags_capacity   = tags.capacity();
+        let nodes_capacity  = nodes.capacity();
+
+        let tag_start = tags.len();
+        tags.push_str(tag);
+
+        let index = nodes.len();
+        nodes.push(ArenaEntry { tag: (tag_start, tag.len()), value: value.to_tree_value(), child, sibling });
+
+        if tags.capacity() != tags_capacity     { self.allocations.set(self.allocations.get()+1); }
+        if nodes.capacity() != nodes_capacity   { self.allocations.set(self.allocations.get()+1); }
+
+        ArenaTree { arena: self, index }
+    }
+
+    ///
+    /// The number of nodes stored in this arena
+    ///
+    pub fn len(&self) -> usize {
+        self.nodes.borrow().len()
+    }
+
+    ///
+    /// True if there are no nodes in this arena
+    ///
+    pub fn is_empty(&self) -> bool {
+        self.nodes.borrow().is_empty()
+    }
+
+    ///
+    /// The number of times this arena has had to allocate storage
+    ///
+    pub fn allocations(&self) -> usize {
+        self.allocations.get()
+    }
+
+    ///
+    /// Copies a node and its children out of this arena into an ordinary tree
+    ///
+    /// The siblings of the node are not copied. The result is independent of the arena, so it remains valid once
+    /// the arena has been dropped.
+    ///
+    pub fn detach(&self, node: &ArenaTree) -> TreeRef {
+        let index = self.index_of(node);
+
+        self.copy_node(index, None)
+    }
+
+    ///
+    /// Returns the index of a node, which must belong to this arena
+    ///
+    fn index_of(&self, node: &ArenaTree) -> usize {
+        assert!(::std::ptr::eq(self, node.arena), "Arena nodes can only be used with the arena that created them");
+
+        node.index
+    }
+
+    ///
+    /// Copies a node and its children into a BasicTree with the specified sibling
+    ///
+    fn copy_node(&self, index: usize, sibling: Option<TreeRef>) -> TreeRef {
+        // Collect the child indexes so the child chain can be built from the end
+        let mut child_indexes   = vec![];
+        let mut next_child      = self.nodes.borrow()[index].child;
+
+        while let Some(child_index) = next_child {
+            child_indexes.push(child_index);
+            next_child = self.nodes.borrow()[child_index].sibling;
+        }
+
+        let mut child = None;
+        for child_index in child_indexes.into_iter().rev() {
+            child = Some(self.copy_node(child_index, child));
+        }
+
+        let nodes   = self.nodes.borrow();
+        let tags    = self.tags.borrow();
+        let entry   = &nodes[index];
+        let tag     = &tags[entry.tag.0..(entry.tag.0 + entry.tag.1)];
+
+        Rc::new(BasicTree::new(tag, entry.value.clone(), child, sibling))
+    }
+}
+
+impl Default for TreeArena {
+    fn default() -> TreeArena {
+        TreeArena::new()
+    }
+}
+
+impl<'a> ArenaTree<'a> {
+    ///
+    /// Retrieves the tag attached to this node
+    ///
+    /// The arena can't have nodes added to it while the tag is borrowed.
+    ///
+    pub fn get_tag(&self) -> Ref<'a, str> {
+        let index   = self.index;
+        let nodes   = self.arena.nodes.borrow();
+        let tag     = nodes[index].tag;
+
+        Ref::map(self.arena.tags.borrow(), |tags| &tags[tag.0..(tag.0 + tag.1)])
+    }
+
+    ///
+    /// Retrieves the value attached to this node
+    ///
+    /// The arena can't have nodes added to it while the value is borrowed.
+    ///
+    pub fn get_value(&self) -> Ref<'a, TreeValue> {
+        let index = self.index;
+
+        Ref::map(self.arena.nodes.borrow(), |nodes| &nodes[index].value)
+    }
+
+    ///
+    /// Retrieves the first child of this node
+    ///
+    pub fn get_child(&self) -> Option<ArenaTree<'a>> {
+        let arena = self.arena;
+
+        arena.nodes.borrow()[self.index].child.map(|index| ArenaTree { arena, index })
+    }
+
+    ///
+    /// Retrieves the sibling of this node
+    ///
+    pub fn get_sibling(&self) -> Option<ArenaTree<'a>> {
+        let arena = self.arena;
+
+        arena.nodes.borrow()[self.index].sibling.map(|index| ArenaTree { arena, index })
+    }
+}
+
+#[cfg(test)]
+mod arena_tests {
+    use super::super::super::tree::*;
+
+    fn large_tree_text() -> String {
+        let mut text = "root\n".to_string();
+
+        for outer in 0..1000 {
+            text.push_str(&format!("    item{}: {}\n", outer, outer));
+
+            for inner in 0..99 {
+                text.push_str(&format!("        value: {}\n", inner));
+            }
+        }
+
+        text
+    }
+
+    #[test]
+    fn can_build_and_read_arena_tree() {
+        let arena   = TreeArena::new();
+        let leaf    = arena.add("leaf", 2, None, None);
+        let first   = arena.add("first", 1, None, Some(leaf));
+        let root    = arena.add("root", (), Some(first), None);
+
+        assert!(&*root.get_tag() == "root");
+        assert!(*root.get_value() == TreeValue::Nothing);
+        assert!(&*root.get_child().unwrap().get_tag() == "first");
+        assert!(root.get_child().unwrap().get_sibling().unwrap().get_value().to_int(0) == 2);
+        assert!(root.get_sibling().is_none());
+        assert!(arena.len() == 3);
+    }
+
+    #[test]
+    fn arena_import_allocates_less_than_rc_import() {
+        let text = large_tree_text();
+
+        let rc_before   = nodes_created();
+        let rc_tree     = parse_tree_text(&text).unwrap();
+        let rc_nodes    = nodes_created() - rc_before;
+        drop(rc_tree);
+
+        let arena_before    = nodes_created();
+        let arena           = TreeArena::new();
+        parse_tree_text_in(&arena, &text).unwrap();
+        let arena_nodes     = nodes_created() - arena_before;
+
+        assert!(arena.len() == 100001);
+        assert!(rc_nodes >= 100001);
+        assert!(arena_nodes == 0);
+        assert!(arena.allocations() < 100);
+    }
+
+    #[test]
+    fn detached_subtree_outlives_arena() {
+        let detached = {
+            let arena   = TreeArena::new();
+            let root    = parse_tree_text_in(&arena, "root\n    a\n        b: 1\n        c: \"two\"\n    d: 3").unwrap();
+
+            arena.detach(&root.get_child().unwrap())
+        };
+
+        assert!(detached.get_tag() == "a");
+        assert!(detached.get_sibling_ref().is_none());
+        assert!(detached.get_child_at("b").get_value().to_int(0) == 1);
+        assert!(detached.get_child_at("c").get_value().to_str("") == "two");
+    }
+
+    #[test]
+    fn arena_import_matches_rc_import() {
+        let text    = "config\n    name: \"example\"\n    port: 8080\n    servers\n        server: \"alpha\"\n        server: \"beta\"\n    empty\n";
+        let arena   = TreeArena::new();
+        let root    = parse_tree_text_in(&arena, text).unwrap();
+
+        assert!(to_tree_text(&arena.detach(&root)) == to_tree_text(&parse_tree_text(text).unwrap()));
+        assert!(parse_tree_text_in(&arena, "root\n  a\n\tb").err() == parse_tree_text("root\n  a\n\tb").err());
+    }
+
+    #[test]
+    #[should_panic]
+    fn cannot_mix_arenas() {
+        let arena       = TreeArena::new();
+        let other_arena = TreeArena::new();
+        let other_node  = other_arena.add("other", (), None, None);
+
+        arena.detach(&other_node);
+    }
+}
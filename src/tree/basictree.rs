@@ -18,6 +18,26 @@ use super::treenode::*;
 use super::values::*;
 use std::rc::*;
 
+#[cfg(test)]
+thread_local!(static NODES_CREATED: ::std::cell::Cell<usize> = ::std::cell::Cell::new(0));
+
+///
+/// Records that a node was created (tests use this to check which operations create nodes)
+///
+#[inline]
+fn count_node() {
+    #[cfg(test)]
+    NODES_CREATED.with(|count| count.set(count.get()+1));
+}
+
+///
+/// The number of nodes created so far by this thread
+///
+#[cfg(test)]
+pub fn nodes_created() -> usize {
+    NODES_CREATED.with(|count| count.get())
+}
+
 ///
 /// BasicTree is a basic in-memory tree node
 ///
@@ -34,6 +54,7 @@ impl BasicTree {
     /// Creates a new tree node with a particular tag and no siblings
     ///
     pub fn new<TValue: ToTreeValue>(tag: &str, value: TValue, child: Option<TreeRef>, sibling: Option<TreeRef>) -> BasicTree {
+        count_node();
         BasicTree { tag: tag.to_string(), value: value.to_tree_value(), child: child, sibling: sibling }
     }
 
@@ -41,6 +62,7 @@ impl BasicTree {
     /// Copies a node into a new basic node
     ///
     pub fn from<TNode: ToTreeNode>(node: TNode) -> BasicTree {
+        count_node();
         let as_tree_node    = node.to_tree_node();
         let child           = as_tree_node.get_child_ref();
         let sibling         = as_tree_node.get_sibling_ref();
@@ -57,6 +79,7 @@ impl BasicTree {
     /// Copies a node into a new basic node and replaces the references
     ///
     pub fn from_with_references<TNode: ToTreeNode>(node: TNode, new_child: Option<&TreeRef>, new_sibling: Option<&TreeRef>) -> BasicTree {
+        count_node();
         let as_tree_node    = node.to_tree_node();
 
         BasicTree { 
@@ -71,6 +94,7 @@ impl BasicTree {
     /// Copies a node into a new basic node and replaces the child (the sibling is preserved)
     ///
     pub fn from_with_child<TNode: ToTreeNode>(node: TNode, new_child: TreeRef) -> BasicTree {
+        count_node();
         let as_tree_node    = node.to_tree_node();
         let sibling         = as_tree_node.get_sibling_ref();
 
@@ -86,6 +110,7 @@ impl BasicTree {
     /// Copies a node into a new basic node and replaces the sibling (the child is preserved)
     ///
     pub fn from_with_sibling<TNode: ToTreeNode>(node: TNode, new_sibling: TreeRef) -> BasicTree {
+        count_node();
         let as_tree_node    = node.to_tree_node();
         let child           = as_tree_node.get_child_ref();
 
@@ -138,6 +163,7 @@ impl TreeNode for BasicTree {
 
 impl Clone for BasicTree {
     fn clone(&self) -> BasicTree {
+        count_node();
         BasicTree { 
             tag:        self.tag.to_owned(), 
             value:      self.value.to_owned(), 
@@ -163,6 +189,8 @@ impl<'a, TValue: ToTreeValue> ToTreeNode for (&'a str, TValue) {
 mod basictree_tests {
     use super::*;
     use super::super::treenode::*;
+    use super::super::address::*;
+    use super::super::super::testing::*;
 
     #[test]
     fn can_create_basictree() {
@@ -207,4 +235,26 @@ mod basictree_tests {
 
         assert!(copy.get_tag() == "tree");
     }
+
+    #[test]
+    fn with_child_node_replaces_the_children() {
+        let tree        = "root".to_tree_node().with_children(&vec![("first", 1).to_tree_node(), ("second", 2).to_tree_node()]);
+        let new_child   = ("a", 1).to_tree_node().with_sibling_node(Some(&("b", 2).to_tree_node()));
+        let replaced    = tree.with_child_node(Some(&new_child));
+
+        assert!(replaced.get_tag() == "root");
+        assert!(replaced.get_child_ref_at(0).unwrap().get_tag() == "a");
+        assert!(replaced.get_child_ref_at(1).unwrap().get_tag() == "b");
+        assert!(replaced.get_child_ref_at(2).is_none());
+    }
+
+    #[test]
+    fn with_child_node_only_copies_the_node_itself() {
+        let third   = "third".to_tree_node().with_children(&vec![("child", 3).to_tree_node(), ("other", 4).to_tree_node()]);
+        let tree    = "root".to_tree_node().with_children(&vec![("first", 1).to_tree_node(), ("second", 2).to_tree_node(), third]);
+        let trimmed = tree.with_child_node(tree.get_child_ref_at("second").as_ref());
+
+        assert!(trimmed.get_child_ref_at(0).unwrap().get_tag() == "second");
+        assert_sharing(&tree, &trimmed, SharingExpectation::PathReallocated(TreeAddress::Here));
+    }
 }
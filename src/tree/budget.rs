@@ -0,0 +1,203 @@
+//
+//   Copyright 2016 Andrew Hunter
+//
+//   Licensed under the Apache License, Version 2.0 (the "License");
+//   you may not use this file except in compliance with the License.
+//   You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+//   Unless required by applicable law or agreed to in writing, software
+//   distributed under the License is distributed on an "AS IS" BASIS,
+//   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//   See the License for the specific language governing permissions and
+//   limitations under the License.
+//
+
+//!
+//! # Apply budgets
+//!
+//! Applying a change can take an amount of work that is out of proportion to the size of the change: for example,
+//! a change addressed to child 4,000,000,000 of a node will generate four billion padding nodes. When changes come
+//! from a source that isn't trusted, `apply_with_budget()` can be used in place of `apply()` to reject changes
+//! that would exceed a set of limits before doing any of the work.
+//!
+//! The budget is checked by working out the impact of the change with `impact_with_limit()`, which stops walking
+//! the address and the replacement as soon as a limit is exceeded, so checking a change costs no more than the
+//! budget allows.
+//!
+
+use std::fmt;
+
+use super::treenode::*;
+use super::change::*;
+
+///
+/// Limits on the amount of work that applying a single change can perform
+///
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ApplyBudget {
+    /// The maximum number of nodes that can be created or copied while applying the change
+    pub max_new_nodes: usize,
+
+    /// The maximum depth of any node in the changed part of the tree
+    pub max_depth: usize,
+
+    /// The maximum number of nodes in a replacement subtree
+    pub max_replacement_nodes: usize
+}
+
+///
+/// Indicates which limit was exceeded by a change (along with the value of that limit)
+///
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BudgetExceeded {
+    /// Applying the change would create more than this many nodes
+    TooManyNewNodes(usize),
+
+    /// The change addresses or creates a node that is deeper than this
+    TooDeep(usize),
+
+    /// The replacement for the change contains more than this many nodes
+    ReplacementTooLarge(usize)
+}
+
+impl fmt::Display for BudgetExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BudgetExceeded::TooManyNewNodes(limit)      => write!(f, "applying the change would create more than {} nodes", limit),
+            BudgetExceeded::TooDeep(limit)              => write!(f, "the change reaches deeper than {} levels", limit),
+            BudgetExceeded::ReplacementTooLarge(limit)  => write!(f, "the replacement has more than {} nodes", limit)
+        }
+    }
+}
+
+impl ApplyBudget {
+    ///
+    /// Creates a budget that allows any change
+    ///
+    pub fn unlimited() -> ApplyBudget {
+        ApplyBudget { max_new_nodes: usize::MAX, max_depth: usize::MAX, max_replacement_nodes: usize::MAX }
+    }
+}
+
+impl Default for ApplyBudget {
+    ///
+    /// A budget suitable for changes from untrusted sources: generous enough for most legitimate changes
+    ///
+    fn default() -> ApplyBudget {
+        ApplyBudget { max_new_nodes: 65536, max_depth: 1024, max_replacement_nodes: 1048576 }
+    }
+}
+
+impl TreeChange {
+    ///
+    /// Checks that applying this change to a tree will stay within a budget
+    ///
+    pub fn check_budget(&self, original_tree: &TreeRef, budget: &ApplyBudget) -> Result<(), BudgetExceeded> {
+        self.impact_with_limit(original_tree, budget).map(|_| ())
+    }
+
+    ///
+    /// Returns the result of applying this change to an existing tree, or an error if doing so would exceed a budget
+    ///
+    pub fn apply_with_budget(&self, original_tree: &TreeRef, budget: ApplyBudget) -> Result<TreeRef, BudgetExceeded> {
+        self.check_budget(original_tree, &budget)?;
+
+        Ok(self.apply(original_tree))
+    }
+}
+
+#[cfg(test)]
+mod budget_tests {
+    use super::super::super::tree::*;
+
+    fn same_tree(a: &TreeRef, b: &TreeRef) -> bool {
+        let a_children: Vec<TreeRef> = a.iter_children().collect();
+        let b_children: Vec<TreeRef> = b.iter_children().collect();
+
+        a.get_tag() == b.get_tag()
+            && a.get_value() == b.get_value()
+            && a_children.len() == b_children.len()
+            && a_children.iter().zip(b_children.iter()).all(|(a, b)| same_tree(a, b))
+    }
+
+    #[test]
+    fn absurd_index_is_rejected() {
+        let tree    = tree!("root", "child");
+        let change  = TreeChange::new(&4_000_000_000usize, &"far_away");
+
+        assert!(change.apply_with_budget(&tree, ApplyBudget::default()).err() == Some(BudgetExceeded::TooManyNewNodes(65536)));
+    }
+
+    #[test]
+    fn absurd_index_nested_in_address_is_rejected() {
+        let tree    = tree!("root", tree!("child", "grandchild"));
+        let budget  = ApplyBudget { max_new_nodes: 100, max_depth: 10, max_replacement_nodes: 100 };
+        let change  = TreeChange::new(&("child", usize::MAX), &"far_away");
+
+        assert!(change.check_budget(&tree, &budget) == Err(BudgetExceeded::TooManyNewNodes(100)));
+    }
+
+    #[test]
+    fn deep_replacement_is_rejected() {
+        let mut deep = "leaf".to_tree_node();
+        for _ in 0..20 {
+            deep = tree!("level", deep);
+        }
+
+        let tree    = tree!("root", "child");
+        let budget  = ApplyBudget { max_new_nodes: 100, max_depth: 10, max_replacement_nodes: 100 };
+        let change  = TreeChange::new(&0, &deep);
+
+        assert!(change.check_budget(&tree, &budget) == Err(BudgetExceeded::TooDeep(10)));
+    }
+
+    #[test]
+    fn deep_address_is_rejected() {
+        let tree    = tree!("root", "child");
+        let budget  = ApplyBudget { max_new_nodes: 100, max_depth: 3, max_replacement_nodes: 100 };
+        let change  = TreeChange::new(&(0, (0, (0, (0, 0)))), &"deep");
+
+        assert!(change.check_budget(&tree, &budget) == Err(BudgetExceeded::TooDeep(3)));
+    }
+
+    #[test]
+    fn large_replacement_is_rejected_without_a_full_walk() {
+        let children: Vec<TreeRef>  = (0..1000).map(|index| ("item", index).to_tree_node()).collect();
+        let large                   = "large".to_tree_node().with_children(&children);
+        let budget                  = ApplyBudget { max_new_nodes: 100, max_depth: 10, max_replacement_nodes: 50 };
+        let change                  = TreeChange::new(&(), &large);
+
+        assert!(change.check_budget(&"root".to_tree_node(), &budget) == Err(BudgetExceeded::ReplacementTooLarge(50)));
+    }
+
+    #[test]
+    fn changes_under_budget_match_apply() {
+        let tree    = tree!("root", ("one", 1), tree!("two", ("three", 3)), ("four", 4));
+        let budget  = ApplyBudget { max_new_nodes: 20, max_depth: 5, max_replacement_nodes: 20 };
+        let changes = vec![
+            TreeChange::new(&("two", "three"), &("three", 33)),
+            TreeChange::new(&1, &()),
+            TreeChange::new(&5, &"padded"),
+            TreeChange::new(&"five", &tree!("five", "six")),
+            TreeChange::new(&0, &TreeReplacement::NewValue("one".to_string(), TreeValue::Int(11))),
+            TreeChange::new(&(), &tree!("new_root", "child")),
+            TreeChange::new(&0, &("only", 1)).with_replaces_following(true),
+            TreeChange::new(&(1, 0), &()).with_replaces_following(true)
+        ];
+
+        for change in changes {
+            assert!(same_tree(&change.apply_with_budget(&tree, budget).unwrap(), &change.apply(&tree)));
+        }
+    }
+
+    #[test]
+    fn unlimited_budget_matches_apply() {
+        let tree    = tree!("root", "child");
+        let change  = TreeChange::new(&1000, &"padded");
+
+        assert!(same_tree(&change.apply_with_budget(&tree, ApplyBudget::unlimited()).unwrap(), &change.apply(&tree)));
+        assert!(change.apply_with_budget(&tree, ApplyBudget { max_new_nodes: 1000, .. ApplyBudget::unlimited() }).is_err());
+    }
+}
@@ -37,7 +37,7 @@
 //! For small components, the simplest and most common replacement is to update the entire tree at once.
 //!
 //! ```
-//! # use tametree::tree::*;
+//! # use tametree::prelude::*;
 //! let change = TreeChange::new(&(), &("Hello", "World"));
 //! ```
 //!
@@ -45,7 +45,7 @@
 //! node via the `ToTreenNode` interface. This could also be written using the types directly as:
 //!
 //! ```
-//! # use tametree::tree::*;
+//! # use tametree::prelude::*;
 //! let change = TreeChange::new(&TreeAddress::Here, &TreeReplacement::NewNode(("Hello", "World").to_tree_node()));
 //! ```
 //!
@@ -57,6 +57,10 @@
 //!
 //! Finally, there is `applies_to` which works out if a change can apply to a particular tree node or region, so a
 //! component can determine if a change is one that it's interested in.
+//!
+//! Changes also carry a priority, which is 0 unless it's set with `with_priority()`. The priority doesn't affect
+//! what the change does to a tree: it's used by `TreeChangeBus` to deliver urgent changes ahead of others. It's
+//! kept by `relative_to` and `rebased_to`, so it follows a change as it's passed between components.
 //! 
 
 use std::rc::*;
@@ -67,6 +71,27 @@ use super::treenode::*;
 use super::basictree::*;
 use super::values::*;
 
+///
+/// The type of a change made with the legacy `TreeChange::new_legacy()` constructor
+///
+/// These map onto replacements as follows:
+///
+/// * `Child` replaces every child of the node at the address: the change is made at the address of its first
+///   child (the address with a child index of 0 appended), and the new node and its siblings become the whole
+///   list of children. With no node, all of the children are removed.
+/// * `Sibling` replaces the node at the address itself, keeping the siblings that follow it. With no node, the
+///   node at the address is removed and the nodes after it move up.
+///
+#[deprecated(note = "create changes with TreeChange::new(), passing an address and a replacement")]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TreeChangeType {
+    /// Every child of the node at the address is replaced
+    Child,
+
+    /// The node at the address is replaced, keeping its following siblings
+    Sibling
+}
+
 ///
 /// Represents the replacement action to perform on a particular tree node
 ///
@@ -135,12 +160,18 @@ pub struct TreeChange {
     ///
     /// The node at the specified address will be removed and this node will be added in its place. If this node is
     /// none, then the node at the address will be removed. If the node has 
-    replacement: TreeReplacement
+    replacement: TreeReplacement,
+
+    /// How urgently this change should be delivered (higher is more urgent)
+    priority: i32,
+
+    /// True if the siblings following the node at the address are removed too (only legacy `Child` changes do this)
+    replaces_following: bool
 }
 
 impl Clone for TreeChange {
     fn clone(&self) -> TreeChange {
-        TreeChange { address: self.address.clone(), replacement: self.replacement.clone() }
+        TreeChange { address: self.address.clone(), replacement: self.replacement.clone(), priority: self.priority, replaces_following: self.replaces_following }
     }
 }
 
@@ -148,9 +179,128 @@ impl TreeChange {
     ///
     /// Creates a new tree change
     ///
+    #[cfg_attr(feature = "doctest_support", doc = "```")]
+    #[cfg_attr(not(feature = "doctest_support"), doc = "```ignore")]
+    /// # use tametree::prelude::*;
+    /// # use tametree::doctest_support::*;
+    /// let change = TreeChange::new(&tagged_address(), &("child", 5));
+    ///
+    /// assert!(*change.address() == tagged_address());
+    /// assert!(change.apply(&sample_tree()).get_child_at(tagged_address()).get_value().to_int(0) == 5);
+    /// ```
+    ///
     #[inline]
     pub fn new<TAddress: ToTreeAddress, TReplacement: ToTreeReplacement>(root: &TAddress, replacement: &TReplacement) -> TreeChange {
-        TreeChange { address: root.to_tree_address(), replacement: replacement.to_tree_replacement() }
+        TreeChange { address: root.to_tree_address(), replacement: replacement.to_tree_replacement(), priority: 0, replaces_following: false }
+    }
+
+    ///
+    /// Creates a tree change using the older three-argument form
+    ///
+    /// A `Child` change replaces all of the children of the node at the address, and a `Sibling` change replaces
+    /// the node at the address itself (see `TreeChangeType`). A node of `None` removes what would be replaced.
+    ///
+    #[deprecated(note = "use TreeChange::new(), passing an address and a replacement")]
+    #[allow(deprecated)]
+    pub fn new_legacy<TAddress: ToTreeAddress>(address: &TAddress, change_type: TreeChangeType, node: Option<&TreeRef>) -> TreeChange {
+        let (address, replaces_following) = match change_type {
+            TreeChangeType::Child   => (address.to_tree_address_then(0.to_tree_address()), true),
+            TreeChangeType::Sibling => (address.to_tree_address(), false)
+        };
+
+        let replacement = match node {
+            Some(node)  => TreeReplacement::NewNode(node.clone()),
+            None        => TreeReplacement::Remove
+        };
+
+        TreeChange { address, replacement, priority: 0, replaces_following }
+    }
+
+    ///
+    /// The address of the node that this change will replace
+    ///
+    #[inline]
+    pub fn address(&self) -> &TreeAddress {
+        &self.address
+    }
+
+    ///
+    /// How urgently this change should be delivered (0 unless it has been set with `with_priority()`)
+    ///
+    #[inline]
+    pub fn priority(&self) -> i32 {
+        self.priority
+    }
+
+    ///
+    /// Creates a copy of this change with a different priority
+    ///
+    /// ```
+    /// # use tametree::prelude::*;
+    /// let urgent = TreeChange::new(&(), &("input", 1)).with_priority(10);
+    ///
+    /// assert!(urgent.priority() == 10);
+    /// assert!(urgent.rebased_to(&"in".to_tree_address()).priority() == 10);
+    /// ```
+    ///
+    #[inline]
+    pub fn with_priority(self, priority: i32) -> TreeChange {
+        TreeChange { priority, ..self }
+    }
+
+    ///
+    /// True if this change also removes the siblings that follow the node at its address
+    ///
+    /// Changes made with `new()` keep the following siblings. Legacy `Child` changes replace the whole list of
+    /// children, which is represented as a change to the first child that replaces the siblings after it too.
+    ///
+    /// ```
+    /// # #![allow(deprecated)]
+    /// # use tametree::prelude::*;
+    /// # use tametree::tree::TreeChangeType;
+    /// let legacy = TreeChange::new_legacy(&"list", TreeChangeType::Child, Some(&"only".to_tree_node()));
+    ///
+    /// assert!(legacy.replaces_following());
+    /// assert!(*legacy.address() == ("list", 0).to_tree_address());
+    /// assert!(!TreeChange::new(&("list", 0), &"only").replaces_following());
+    /// ```
+    ///
+    #[inline]
+    pub fn replaces_following(&self) -> bool {
+        self.replaces_following
+    }
+
+    ///
+    /// Creates a copy of this change that does or doesn't remove the siblings following the node at its address
+    ///
+    /// ```
+    /// # use tametree::prelude::*;
+    /// let list        = tree!("root", tree!("list", "a", "b", "c"));
+    /// let replace_all = TreeChange::new(&("list", 0), &"only").with_replaces_following(true);
+    ///
+    /// assert!(replace_all.apply(&list).get_child_at("list").iter_children().count() == 1);
+    /// ```
+    ///
+    #[inline]
+    pub fn with_replaces_following(self, replaces_following: bool) -> TreeChange {
+        TreeChange { replaces_following, ..self }
+    }
+
+    ///
+    /// The replacement that this change will make at its address
+    ///
+    #[cfg_attr(feature = "doctest_support", doc = "```")]
+    #[cfg_attr(not(feature = "doctest_support"), doc = "```ignore")]
+    /// # use tametree::prelude::*;
+    /// # use tametree::doctest_support::*;
+    /// let remove = TreeChange::new(&tagged_address(), &());
+    ///
+    /// assert!(match *remove.replacement() { TreeReplacement::Remove => true, _ => false });
+    /// ```
+    ///
+    #[inline]
+    pub fn replacement(&self) -> &TreeReplacement {
+        &self.replacement
     }
 
     ///
@@ -189,8 +339,8 @@ impl TreeChange {
     ///
     /// Returns how a replacement is applied to a particular tree node (or nothing) 
     ///
-    fn perform_replacement(original: Option<&TreeRef>, replacement: &TreeReplacement) -> Option<TreeRef> {
-        let original_sibling = original.and_then(|x| x.get_sibling_ref());
+    fn perform_replacement(original: Option<&TreeRef>, replacement: &TreeReplacement, replaces_following: bool) -> Option<TreeRef> {
+        let original_sibling = if replaces_following { None } else { original.and_then(|x| x.get_sibling_ref()) };
         let original_child   = original.and_then(|x| x.get_child_ref());
 
         match *replacement {
@@ -203,11 +353,11 @@ impl TreeChange {
     ///
     /// Performs the apply operation
     ///
-    fn perform_apply(original: Option<&TreeRef>, address: &TreeAddress, replacement: &TreeReplacement) -> Option<TreeRef> {
+    fn perform_apply(original: Option<&TreeRef>, address: &TreeAddress, replacement: &TreeReplacement, replaces_following: bool) -> Option<TreeRef> {
         match *address {
             TreeAddress::Here => {
                 // Just replace this node
-                Self::perform_replacement(original, replacement)
+                Self::perform_replacement(original, replacement, replaces_following)
             },
 
             TreeAddress::ChildAtIndex(child_index, ref child_address) => {
@@ -222,7 +372,7 @@ impl TreeChange {
                 }
 
                 // Replace the child at this index
-                let new_child       = Self::perform_apply(current.as_ref(), &*child_address, replacement);
+                let new_child       = Self::perform_apply(current.as_ref(), &*child_address, replacement, replaces_following);
 
                 // Pop siblings to generate the new child item
                 current = new_child;
@@ -257,7 +407,7 @@ impl TreeChange {
                 }
 
                 // Replace the child with this tag
-                let new_child       = Self::perform_apply(current.as_ref(), &*child_address, replacement);
+                let new_child       = Self::perform_apply(current.as_ref(), &*child_address, replacement, replaces_following);
 
                 // Pop siblings to generate the new child item
                 current = new_child;
@@ -274,9 +424,49 @@ impl TreeChange {
     ///
     /// Returns the result of applying this tree change to an existing tree
     ///
+    /// Removing a node moves the nodes that follow it up by one index:
+    ///
+    #[cfg_attr(feature = "doctest_support", doc = "```")]
+    #[cfg_attr(not(feature = "doctest_support"), doc = "```ignore")]
+    /// # use tametree::prelude::*;
+    /// # use tametree::doctest_support::*;
+    /// let removed = TreeChange::new(&0, &()).apply(&sample_tree());
+    ///
+    /// assert!(child_tags(&removed) == vec!["second", "third"]);
+    /// assert!(removed.get_child_at(0).get_tag() == "second");
+    /// ```
+    ///
+    /// A new node at the index after the last child adds a new child, and any siblings of a new node are
+    /// inserted along with it:
+    ///
+    #[cfg_attr(feature = "doctest_support", doc = "```")]
+    #[cfg_attr(not(feature = "doctest_support"), doc = "```ignore")]
+    /// # use tametree::prelude::*;
+    /// # use tametree::doctest_support::*;
+    /// let added       = TreeChange::new(&3, &("fourth", 4)).apply(&sample_tree());
+    /// let with_sibling = ("new", 0).to_tree_node().with_sibling_node(Some(&("another", 0).to_tree_node()));
+    /// let inserted    = TreeChange::new(&1, &with_sibling).apply(&sample_tree());
+    ///
+    /// assert!(child_tags(&added) == vec!["first", "second", "third", "fourth"]);
+    /// assert!(child_tags(&inserted) == vec!["first", "new", "another", "third"]);
+    /// ```
+    ///
+    /// A new value replaces the tag and value of a node but keeps its children:
+    ///
+    #[cfg_attr(feature = "doctest_support", doc = "```")]
+    #[cfg_attr(not(feature = "doctest_support"), doc = "```ignore")]
+    /// # use tametree::prelude::*;
+    /// # use tametree::doctest_support::*;
+    /// let change  = TreeChange::new(&"third", &TreeReplacement::NewValue("renamed".to_string(), 9.to_tree_value()));
+    /// let updated = change.apply(&sample_tree());
+    ///
+    /// assert!(updated.get_child_at("renamed").get_value().to_int(0) == 9);
+    /// assert!(child_tags(&updated.get_child_at("renamed")) == vec!["child", "other"]);
+    /// ```
+    ///
     #[inline]
     pub fn apply(&self, original_tree: &TreeRef) -> TreeRef {
-        if let Some(result) = Self::perform_apply(Some(original_tree), &self.address, &self.replacement) {
+        if let Some(result) = Self::perform_apply(Some(original_tree), &self.address, &self.replacement, self.replaces_following) {
             result
         } else {
             // If the change is 'delete the root node' then the result will be 'none' - we return an empty tree for that case
@@ -302,9 +492,29 @@ impl TreeChange {
     ///
     /// Corresponds to testing for an extent of `TreeExtent::SubTree`
     ///
+    #[cfg_attr(feature = "doctest_support", doc = "```")]
+    #[cfg_attr(not(feature = "doctest_support"), doc = "```ignore")]
+    /// # use tametree::prelude::*;
+    /// # use tametree::doctest_support::*;
+    /// let change = TreeChange::new(&tagged_address(), &("child", 5));
+    ///
+    /// // Applies to the changed node, its parents and anything inside it
+    /// assert!(change.applies_to_subtree(&"third".to_tree_address()) == Some(true));
+    /// assert!(change.applies_to_subtree(&("third", ("child", "grandchild")).to_tree_address()) == Some(true));
+    /// assert!(change.applies_to_subtree(&"first".to_tree_address()) == Some(false));
+    ///
+    /// // Can't tell if an indexed address is affected by a tagged change
+    /// assert!(change.applies_to_subtree(&indexed_address()) == None);
+    /// ```
+    ///
     pub fn applies_to_subtree(&self, address: &TreeAddress) -> Option<bool> {
         // TODO: if the change type is 'NewValue' then the change only applies if the address is exact
-        Self::address_applies(&self.address, address)
+        if self.replaces_following {
+            // Every child of the parent is replaced
+            Self::address_applies(&self.address.parent(), address)
+        } else {
+            Self::address_applies(&self.address, address)
+        }
     }
 
     ///
@@ -312,6 +522,16 @@ impl TreeChange {
     ///
     /// Corresponds to testing for an extent of `TreeExtent::Children`
     ///
+    #[cfg_attr(feature = "doctest_support", doc = "```")]
+    #[cfg_attr(not(feature = "doctest_support"), doc = "```ignore")]
+    /// # use tametree::prelude::*;
+    /// # use tametree::doctest_support::*;
+    /// let change = TreeChange::new(&tagged_address(), &("child", 5));
+    ///
+    /// assert!(change.applies_to_child_of(&"third".to_tree_address()) == Some(true));
+    /// assert!(change.applies_to_child_of(&TreeAddress::Here) == Some(false));
+    /// ```
+    ///
     pub fn applies_to_child_of(&self, address: &TreeAddress) -> Option<bool> {
         self.address.parent().is_parent_of(address)
     }
@@ -321,9 +541,26 @@ impl TreeChange {
     ///
     /// Corresponds to testing for an extent of `TreeExtent::ThisNode`
     ///
+    /// A new value only affects the node at its address, but other changes also affect the nodes inside it.
+    ///
+    #[cfg_attr(feature = "doctest_support", doc = "```")]
+    #[cfg_attr(not(feature = "doctest_support"), doc = "```ignore")]
+    /// # use tametree::prelude::*;
+    /// # use tametree::doctest_support::*;
+    /// let new_node    = TreeChange::new(&"third", &("third", 1));
+    /// let new_value   = TreeChange::new(&"third", &TreeReplacement::NewValue("third".to_string(), 1.to_tree_value()));
+    ///
+    /// assert!(new_node.applies_to_only(&tagged_address()) == Some(true));
+    /// assert!(new_value.applies_to_only(&tagged_address()) == Some(false));
+    /// assert!(new_value.applies_to_only(&"third".to_tree_address()) == Some(true));
+    /// ```
+    ///
     pub fn applies_to_only(&self, address: &TreeAddress) -> Option<bool> {
         if let TreeReplacement::NewValue(_, _) = self.replacement {
             Some(self.address == *address)
+        } else if self.replaces_following {
+            let parent = self.address.parent();
+            parent.is_parent_of(address).map(|inside| inside && *address != parent)
         } else {
             self.address.is_parent_of(address)
         }
@@ -332,6 +569,21 @@ impl TreeChange {
     ///
     /// Returns with or not this change affects a node covered by a given extent relative to an address
     ///
+    /// The result is `None` when this can't be worked out without the tree, usually because one address uses tags
+    /// and the other uses indexes. Callers that need to be safe should treat this as a change that does apply.
+    ///
+    #[cfg_attr(feature = "doctest_support", doc = "```")]
+    #[cfg_attr(not(feature = "doctest_support"), doc = "```ignore")]
+    /// # use tametree::prelude::*;
+    /// # use tametree::doctest_support::*;
+    /// let change = TreeChange::new(&tagged_address(), &("child", 5));
+    ///
+    /// assert!(change.applies_to(&"third".to_tree_address(), &TreeExtent::SubTree) == Some(true));
+    /// assert!(change.applies_to(&"third".to_tree_address(), &TreeExtent::ThisNode) == Some(false));
+    /// assert!(change.applies_to(&indexed_address(), &TreeExtent::SubTree) == None);
+    /// assert!(change.applies_to(&indexed_address(), &TreeExtent::SubTree).unwrap_or(true));
+    /// ```
+    ///
     pub fn applies_to(&self, address: &TreeAddress, extent: &TreeExtent) -> Option<bool> {
         match *extent {
             TreeExtent::ThisNode    => self.applies_to_only(address),
@@ -381,13 +633,46 @@ impl TreeChange {
     /// Ie, this reduces the scope of the change. If this change is for `.1.2.`, then asking for
     /// `relative_to(&1.to_tree_address())` will return a change for `.2.`.
     ///
+    #[cfg_attr(feature = "doctest_support", doc = "```")]
+    #[cfg_attr(not(feature = "doctest_support"), doc = "```ignore")]
+    /// # use tametree::prelude::*;
+    /// # use tametree::doctest_support::*;
+    /// let change = TreeChange::new(&tagged_address(), &("child", 5));
+    ///
+    /// assert!(*change.relative_to(&"third".to_tree_address()).unwrap().address() == "child".to_tree_address());
+    /// assert!(change.relative_to(&"first".to_tree_address()).is_none());
+    /// ```
+    ///
+    /// A change to a parent of the address is converted to a replacement of the whole subtree, if it creates one:
+    ///
+    #[cfg_attr(feature = "doctest_support", doc = "```")]
+    #[cfg_attr(not(feature = "doctest_support"), doc = "```ignore")]
+    /// # use tametree::prelude::*;
+    /// # use tametree::doctest_support::*;
+    /// let replace_all = TreeChange::new(&(), &sample_tree());
+    /// let relative    = replace_all.relative_to(&"third".to_tree_address()).unwrap();
+    ///
+    /// assert!(*relative.address() == TreeAddress::Here);
+    /// assert!(relative.apply(&"empty".to_tree_node()).get_child_at("other").get_value().to_int(0) == 4);
+    ///
+    /// // Removing a parent doesn't produce a tree, so there's no relative change
+    /// assert!(TreeChange::new(&"third", &()).relative_to(&tagged_address()).is_none());
+    /// ```
+    ///
     pub fn relative_to(&self, address: &TreeAddress) -> Option<TreeChange> {
+        self.relative_to_ignoring_priority(address).map(|change| change.with_priority(self.priority))
+    }
+
+    ///
+    /// Creates a change relative to a subtree, with the default priority
+    ///
+    fn relative_to_ignoring_priority(&self, address: &TreeAddress) -> Option<TreeChange> {
         if address.is_parent_of(&self.address).unwrap_or(false) {
             // The changes are further down the tree: we can jsut change the root address
             let new_address_opt = self.address.relative_to(address);
 
             if let Some(new_address) = new_address_opt {
-                Some(TreeChange::new(&new_address, &self.replacement))
+                Some(TreeChange { replaces_following: self.replaces_following, ..TreeChange::new(&new_address, &self.replacement) })
             } else {
                 None
             }
@@ -406,7 +691,9 @@ impl TreeChange {
                         let relative_to_tree_maybe      = address.relative_to(&self.address.parent()).and_then(|x| self.adjust_root_address_for_partial_tree(&x));
 
                         if let Some(relative_to_tree) = relative_to_tree_maybe {
+                            // A node after the end of a replaced list of children has been removed
                             Self::relative_to_tree(&parent_of_change, relative_to_tree)
+                                .or_else(|| if self.replaces_following { Some(TreeChange::new(&TreeAddress::Here, &TreeReplacement::Remove)) } else { None })
                         } else {
                             None
                         }
@@ -418,11 +705,52 @@ impl TreeChange {
             }
         }
     }
+
+    ///
+    /// Creates a new tree change that makes this change to the subtree at a particular address instead of the root
+    ///
+    /// This is the opposite of `relative_to()`. A replacement for the whole tree replaces just the node at the
+    /// address (any siblings of the replacement root are dropped), and if the address ends in a tag, it is given
+    /// that tag so the node can still be found at the address.
+    ///
+    #[cfg_attr(feature = "doctest_support", doc = "```")]
+    #[cfg_attr(not(feature = "doctest_support"), doc = "```ignore")]
+    /// # use tametree::prelude::*;
+    /// # use tametree::doctest_support::*;
+    /// let change  = TreeChange::new(&(), &("replacement", 7)).rebased_to(&tagged_address());
+    /// let updated = change.apply(&sample_tree());
+    ///
+    /// assert!(*change.address() == tagged_address());
+    /// assert!(updated.get_child_at(tagged_address()).get_value().to_int(0) == 7);
+    /// ```
+    ///
+    pub fn rebased_to(&self, address: &TreeAddress) -> TreeChange {
+        let new_address = address.to_tree_address_then(self.address.clone());
+
+        let replacement = match (&self.address, address.last_part(), &self.replacement) {
+            (TreeAddress::Here, TreeAddress::ChildWithTag(tag, _), TreeReplacement::NewNode(node)) => {
+                TreeReplacement::NewNode(Rc::new(BasicTree::new(tag, node.get_value().clone(), node.get_child_ref(), None)))
+            },
+
+            (TreeAddress::Here, _, TreeReplacement::NewNode(node)) => {
+                TreeReplacement::NewNode(node.with_sibling_node(None))
+            },
+
+            (TreeAddress::Here, TreeAddress::ChildWithTag(tag, _), TreeReplacement::NewValue(_, value)) => {
+                TreeReplacement::NewValue(tag.clone(), value.clone())
+            },
+
+            (_, _, replacement) => replacement.clone()
+        };
+
+        TreeChange { replaces_following: self.replaces_following, ..TreeChange::new(&new_address, &replacement).with_priority(self.priority) }
+    }
 }
 
 #[cfg(test)]
 mod change_tests {
     use super::super::super::tree::*;
+    use super::super::super::testing::*;
 
     #[test]
     fn can_apply_simple_change_tagged() {
@@ -435,6 +763,7 @@ mod change_tests {
         assert!(!changed_tree.get_child_ref_at("replaced").unwrap().get_sibling_ref().is_none());
         assert!(changed_tree.get_child_ref_at("two").is_none());
         assert!(!changed_tree.get_child_ref_at("three").is_none());
+        assert_sharing(&initial_tree, &changed_tree, SharingExpectation::SharedOutside("replaced".to_tree_address()));
     }
 
     #[test]
@@ -448,6 +777,7 @@ mod change_tests {
         assert!(changed_tree.get_child_ref_at(2).unwrap().get_value().to_int(0) == 3);
         assert!(changed_tree.get_child_ref_at(2).unwrap().get_sibling_ref().is_none());
         assert!(changed_tree.get_child_ref_at(3).is_none());
+        assert_sharing(&initial_tree, &changed_tree, SharingExpectation::SharedOutside(1.to_tree_address()));
     }
 
     #[test]
@@ -460,6 +790,7 @@ mod change_tests {
         assert!(changed_tree.get_child_ref_at(1).unwrap().get_value().to_int(0) == 3);
         assert!(changed_tree.get_child_ref_at(1).unwrap().get_sibling_ref().is_none());
         assert!(changed_tree.get_child_ref_at(2).is_none());
+        assert_sharing(&initial_tree, &changed_tree, SharingExpectation::SharedOutside(1.to_tree_address()));
     }
 
     #[test]
@@ -473,6 +804,7 @@ mod change_tests {
         assert!(changed_tree.get_child_ref_at(2).unwrap().get_value().to_int(0) == 3);
         assert!(changed_tree.get_child_ref_at(2).unwrap().get_sibling_ref().is_none());
         assert!(changed_tree.get_child_ref_at(3).is_none());
+        assert_sharing(&initial_tree, &changed_tree, SharingExpectation::PathReallocated(1.to_tree_address()));
     }
 
     #[test]
@@ -813,4 +1145,144 @@ mod change_tests {
         assert!(changed_tree.get_tag() == "two");
         assert!(changed_tree.get_child_at(0).get_tag() == "three");
     }
+
+    #[test]
+    #[allow(deprecated)]
+    fn legacy_child_change_replaces_child() {
+        // As in the immediate publisher tests: the child of node 1 becomes an 'add' node
+        let original_tree   = tree!("root", "some_other_tree", "consumer_target");
+        let just_add        = ("add", 1).to_tree_node();
+
+        let legacy_change   = TreeChange::new_legacy(&1, TreeChangeType::Child, Some(&just_add));
+        let changed_tree    = legacy_change.apply(&original_tree);
+
+        assert!(legacy_change.address() == &(1, 0).to_tree_address());
+        assert!(changed_tree.get_child_ref_at((1, "add").to_tree_address()).unwrap().get_value().to_int(0) == 1);
+        assert!(changed_tree.get_child_at(1).get_tag() == "consumer_target");
+        assert!(changed_tree.get_child_at(0).get_tag() == "some_other_tree");
+    }
+
+    ///
+    /// The tags of the children of a node, in order
+    ///
+    fn child_tags(tree: &TreeRef) -> Vec<String> {
+        tree.iter_children().map(|child| child.get_tag().to_string()).collect()
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn legacy_child_change_replaces_every_child() {
+        let original_tree   = tree!("root", tree!("list", ("a", 1), ("b", 2), ("c", 3)), "after");
+
+        // The old semantics made the new node (and its siblings) the only children of the node at the address
+        let single          = TreeChange::new_legacy(&0, TreeChangeType::Child, Some(&("new", 4).to_tree_node())).apply(&original_tree);
+        let expected        = original_tree.with_children(&vec![original_tree.get_child_at(0).with_child_node(Some(&("new", 4).to_tree_node())), "after".to_tree_node()]);
+
+        assert!(child_tags(&single.get_child_at(0)) == vec!["new"]);
+        assert!(child_tags(&single.get_child_at(0)) == child_tags(&expected.get_child_at(0)));
+        assert!(single.get_child_at(0).get_child_at(0).get_value().to_int(0) == 4);
+        assert!(child_tags(&single) == vec!["list", "after"]);
+
+        let chain           = tree!("unused", "x", "y").get_child_ref().unwrap();
+        let several         = TreeChange::new_legacy(&0, TreeChangeType::Child, Some(&chain)).apply(&original_tree);
+        assert!(child_tags(&several.get_child_at(0)) == vec!["x", "y"]);
+
+        let removed         = TreeChange::new_legacy(&0, TreeChangeType::Child, None).apply(&original_tree);
+        assert!(removed.get_child_at(0).get_child_ref().is_none());
+        assert!(child_tags(&removed) == vec!["list", "after"]);
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn legacy_child_change_applies_to_every_child() {
+        let change = TreeChange::new_legacy(&0, TreeChangeType::Child, Some(&"new".to_tree_node()));
+
+        assert!(change.applies_to_subtree(&(0, 2).to_tree_address()) == Some(true));
+        assert!(change.applies_to_only(&(0, 2).to_tree_address()) == Some(true));
+        assert!(change.applies_to_only(&0.to_tree_address()) == Some(false));
+        assert!(change.applies_to_subtree(&1.to_tree_address()) == Some(false));
+
+        // A subscriber to a later child finds out that it has been removed
+        let relative = change.relative_to(&(0, 2).to_tree_address()).unwrap();
+        assert!(relative.apply(&"c".to_tree_node()).get_tag() == "");
+
+        // Rebasing the change keeps its meaning
+        let rebased = change.rebased_to(&"outer".to_tree_address());
+        let tree    = rebased.apply(&tree!("root", tree!("outer", tree!("list", "a", "b"))));
+        assert!(child_tags(&tree.get_child_at("outer").get_child_at(0)) == vec!["new"]);
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn legacy_child_change_at_root() {
+        let original_tree   = tree!("root", "old");
+        let changed_tree    = TreeChange::new_legacy(&(), TreeChangeType::Child, Some(&"new".to_tree_node())).apply(&original_tree);
+
+        assert!(changed_tree.get_tag() == "root");
+        assert!(changed_tree.get_child_at(0).get_tag() == "new");
+        assert!(changed_tree.get_child_ref_at(1).is_none());
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn legacy_sibling_change_at_root_replaces_tree() {
+        // As in the immediate publisher and component function tests: the whole tree is replaced
+        let whole_tree      = tree!("root", "some_other_tree", tree!("consumer_target", ("add", 2)));
+        let legacy_change   = TreeChange::new_legacy(&(), TreeChangeType::Sibling, Some(&whole_tree));
+        let modern_change   = TreeChange::new(&TreeAddress::Here, &whole_tree);
+
+        let legacy_tree     = legacy_change.apply(&"empty".to_tree_node());
+        let modern_tree     = modern_change.apply(&"empty".to_tree_node());
+
+        assert!(legacy_tree.get_tag() == modern_tree.get_tag());
+        assert!(legacy_tree.get_child_ref_at((1, "add").to_tree_address()).unwrap().get_value().to_int(0) == 2);
+        assert!(modern_tree.get_child_ref_at((1, "add").to_tree_address()).unwrap().get_value().to_int(0) == 2);
+
+        let passed_tree     = TreeChange::new_legacy(&(), TreeChangeType::Sibling, Some(&"passed".to_tree_node())).apply(&"empty".to_tree_node());
+        assert!(passed_tree.get_tag() == "passed");
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn legacy_sibling_change_keeps_following_siblings() {
+        let original_tree   = tree!("root", "one", "two", "three");
+        let changed_tree    = TreeChange::new_legacy(&1, TreeChangeType::Sibling, Some(&"new".to_tree_node())).apply(&original_tree);
+
+        assert!(changed_tree.get_child_at(0).get_tag() == "one");
+        assert!(changed_tree.get_child_at(1).get_tag() == "new");
+        assert!(changed_tree.get_child_at(2).get_tag() == "three");
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn legacy_none_removes_node() {
+        let original_tree   = tree!("root", tree!("one", "child"), "two");
+
+        let without_child   = TreeChange::new_legacy(&0, TreeChangeType::Child, None).apply(&original_tree);
+        assert!(without_child.get_child_at(0).get_tag() == "one");
+        assert!(without_child.get_child_at(0).get_child_ref().is_none());
+
+        let without_sibling = TreeChange::new_legacy(&0, TreeChangeType::Sibling, None).apply(&original_tree);
+        assert!(without_sibling.get_child_at(0).get_tag() == "two");
+        assert!(without_sibling.get_child_ref_at(1).is_none());
+    }
+
+    #[test]
+    fn rebase_retags_root_replacement() {
+        let change  = TreeChange::new(&(), &("value", 3));
+        let rebased = change.rebased_to(&("a", "b").to_tree_address());
+        let tree    = rebased.apply(&tree!("root", tree!("a", ("b", 1))));
+
+        assert!(*rebased.address() == ("a", "b").to_tree_address());
+        assert!(tree.get_child_at("a").get_child_at("b").get_value().to_int(0) == 3);
+    }
+
+    #[test]
+    fn rebase_then_relative_is_original() {
+        let change  = TreeChange::new(&("x", 1), &("y", 2));
+        let rebased = change.rebased_to(&"a".to_tree_address());
+
+        assert!(*rebased.address() == ("a", ("x", 1)).to_tree_address());
+        assert!(*rebased.relative_to(&"a".to_tree_address()).unwrap().address() == *change.address());
+    }
 }
@@ -0,0 +1,510 @@
+//
+//   Copyright 2016 Andrew Hunter
+//
+//   Licensed under the Apache License, Version 2.0 (the "License");
+//   you may not use this file except in compliance with the License.
+//   You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+//   Unless required by applicable law or agreed to in writing, software
+//   distributed under the License is distributed on an "AS IS" BASIS,
+//   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//   See the License for the specific language governing permissions and
+//   limitations under the License.
+//
+
+//!
+//! # Change log compaction
+//!
+//! A log of the changes made to a tree grows for as long as the tree is changed, even though most of the changes
+//! in a long-running log are overwritten by later ones. `compact_changes()` produces a shorter log that gives the
+//! same final tree when it is applied to the same starting tree.
+//!
+//! Only the final tree is preserved: the intermediate trees produced by replaying a compacted log will usually
+//! be different from those produced by the original log.
+//!
+//! There are two ways to compact a log:
+//!
+//! * `KeepLastPerAddress` drops changes that are completely replaced by a later change. A change is replaced
+//!   when a later change replaces the node at the same address or at one of its parents (or the whole list of
+//!   children that contains it, as a legacy `Child` change does), and none of the changes in between could have
+//!   moved the node to a different address.
+//! * `SnapshotEvery(n)` replaces each run of `n` changes with a single change that replaces the whole tree.
+//!
+//! `Combined` does both, dropping the replaced changes first.
+//!
+//! A change that takes the place of other changes is given the highest priority of the changes it replaces, so
+//! compacting a log never makes an urgent change less urgent.
+//!
+//! A log written by a `DurableBus` can be compacted with `tametree::component::durable::compact_log()`.
+//!
+
+use super::treenode::*;
+use super::address::*;
+use super::change::*;
+
+///
+/// How a change log should be compacted
+///
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CompactionStrategy {
+    /// Drop changes that are replaced by later changes
+    KeepLastPerAddress,
+
+    /// Replace each run of this many changes with a snapshot of the whole tree
+    SnapshotEvery(usize),
+
+    /// Drop replaced changes, then replace each run of this many of the remaining changes with a snapshot
+    Combined(usize)
+}
+
+///
+/// Describes the effect of compacting a change log
+///
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct CompactionStats {
+    /// The number of changes in the original log
+    pub input_changes: usize,
+
+    /// The number of changes in the compacted log
+    pub output_changes: usize,
+
+    /// The number of nodes in the replacements of the original log
+    pub input_nodes: usize,
+
+    /// The number of nodes in the replacements of the compacted log
+    pub output_nodes: usize,
+
+    /// The size of the original log in bytes (only known when compacting a log that's been written out)
+    pub input_bytes: usize,
+
+    /// The size of the compacted log in bytes (only known when compacting a log that's been written out)
+    pub output_bytes: usize
+}
+
+///
+/// Counts the nodes that a change will add to a tree (including the siblings of a new node, which are inserted too)
+///
+fn replacement_nodes(change: &TreeChange) -> usize {
+    fn count(node: &TreeRef) -> usize {
+        let mut total   = 0;
+        let mut next    = Some(node.clone());
+
+        while let Some(current) = next {
+            total += 1;
+            if let Some(child) = current.get_child_ref() {
+                total += count(&child);
+            }
+            next = current.get_sibling_ref();
+        }
+
+        total
+    }
+
+    match *change.replacement() {
+        TreeReplacement::Remove             => 0,
+        TreeReplacement::NewValue(_, _)     => 1,
+        TreeReplacement::NewNode(ref node)  => count(node)
+    }
+}
+
+///
+/// True if a change could move the node at an address to a different address (or change which node a tag refers to)
+///
+fn could_move(change: &TreeChange, address: &TreeAddress) -> bool {
+    let changed = change.address();
+
+    // Changes inside the subtree at the address can't move it
+    if address != changed && address.is_parent_of(changed).unwrap_or(false) {
+        return false;
+    }
+
+    // The change affects the list of children that contains the node it changes: nodes outside that list can't move
+    let list_owner = changed.parent();
+    let in_list = match address.relative_to(&list_owner) {
+        Some(relative)  => relative,
+        None            => return list_owner.is_parent_of(address).unwrap_or(true)
+    };
+
+    // Removing a node or inserting siblings changes the index of every node that follows it, and so does removing
+    // the following siblings
+    if change.replaces_following() {
+        return true;
+    }
+
+    let new_tag = match *change.replacement() {
+        TreeReplacement::Remove                                                 => return true,
+        TreeReplacement::NewNode(ref node) if node.get_sibling_ref().is_some()  => return true,
+        TreeReplacement::NewNode(ref node)                                      => node.get_tag().to_string(),
+        TreeReplacement::NewValue(ref tag, _)                                   => tag.clone()
+    };
+
+    // Replacing a single node only affects the address if it's that node, or if the address's tag could now refer to it
+    match (changed.last_part(), in_list) {
+        (TreeAddress::ChildAtIndex(changed_index, _), TreeAddress::ChildAtIndex(index, _))    => *changed_index == index,
+        (TreeAddress::ChildWithTag(changed_tag, _), TreeAddress::ChildWithTag(tag, _))        => *changed_tag == tag || new_tag == tag,
+        _                                                                                     => true
+    }
+}
+
+///
+/// True if a change leaves something other than the node it created at its address (by removing the node,
+/// inserting siblings before the nodes that follow it, or changing its tag)
+///
+fn leaves_address(change: &TreeChange) -> bool {
+    let new_tag = match *change.replacement() {
+        TreeReplacement::Remove                                                 => return true,
+        TreeReplacement::NewNode(ref node) if node.get_sibling_ref().is_some()  => return true,
+        TreeReplacement::NewNode(ref node)                                      => node.get_tag(),
+        TreeReplacement::NewValue(ref tag, _)                                   => &**tag
+    };
+
+    match *change.address().last_part() {
+        TreeAddress::ChildWithTag(ref tag, _)   => tag != new_tag,
+        _                                       => false
+    }
+}
+
+///
+/// True if a change replaces the whole list of children containing the node at its address
+///
+fn replaces_list(change: &TreeChange) -> bool {
+    change.replaces_following()
+        && matches!(*change.address().last_part(), TreeAddress::ChildAtIndex(0, _))
+        && !matches!(*change.replacement(), TreeReplacement::NewValue(..))
+}
+
+///
+/// True if a later change replaces everything an earlier change did
+///
+fn replaces(later: &TreeChange, earlier: &TreeChange) -> bool {
+    // A later change to the first child that also replaces its following siblings replaces every child of the parent
+    if replaces_list(later) {
+        let parent = later.address().parent();
+
+        if earlier.address() != &parent && parent.is_parent_of(earlier.address()).unwrap_or(false) {
+            return true;
+        }
+    }
+
+    // Removing the following siblings is only undone by a change to the whole list
+    if earlier.replaces_following() {
+        return false;
+    }
+
+    // A later change to the same address won't change the same nodes if the earlier change moved them
+    if later.address() == earlier.address() && leaves_address(earlier) {
+        return false;
+    }
+
+    match *later.replacement() {
+        TreeReplacement::NewNode(_)     => later.address().is_parent_of(earlier.address()).unwrap_or(false),
+        TreeReplacement::NewValue(..)   => later.address() == earlier.address() && matches!(*earlier.replacement(), TreeReplacement::NewValue(..)),
+        TreeReplacement::Remove         => false
+    }
+}
+
+///
+/// Drops the changes that are replaced by later changes
+///
+/// This compares every change against the changes that follow it until one of them could have moved the node it
+/// changes, so it can take time proportional to the square of the length of the log.
+///
+fn keep_last_per_address(initial: &TreeRef, changes: Vec<(u64, TreeChange)>) -> Vec<(u64, TreeChange)> {
+    // Changes to nodes that don't exist add padding nodes, which can be outside the subtree at their address,
+    // so we only compare changes whose address exists in the tree they're applied to
+    let mut tree        = initial.clone();
+    let mut resolves    = vec![];
+
+    for (_, change) in changes.iter() {
+        resolves.push(change.address().lookup_index(&tree).is_some());
+        tree = change.apply(&tree);
+    }
+
+    let mut keep        = vec![true; changes.len()];
+    let mut priorities  = changes.iter().map(|(_, change)| change.priority()).collect::<Vec<_>>();
+
+    for (index, (_, change)) in changes.iter().enumerate() {
+        if !resolves[index] {
+            continue;
+        }
+
+        for (later_index, (_, later)) in changes.iter().enumerate().skip(index+1) {
+            if !resolves[later_index] {
+                break;
+            }
+
+            if replaces(later, change) {
+                // The change that replaces this one is at least as urgent
+                keep[index]             = false;
+                priorities[later_index] = priorities[later_index].max(priorities[index]);
+                break;
+            }
+
+            if could_move(later, change.address()) {
+                break;
+            }
+        }
+    }
+
+    changes.into_iter().zip(keep).zip(priorities)
+        .filter(|((_, keep), _)| *keep)
+        .map(|(((sequence, change), _), priority)| (sequence, change.with_priority(priority)))
+        .collect()
+}
+
+///
+/// Replaces each run of `run_length` changes with a snapshot
+///
+fn snapshot_every(initial: &TreeRef, changes: Vec<(u64, TreeChange)>, run_length: usize) -> Vec<(u64, TreeChange)> {
+    if run_length <= 1 {
+        return changes;
+    }
+
+    let mut tree    = initial.clone();
+    let mut result  = vec![];
+    let full_runs   = changes.len() / run_length;
+
+    let mut priority = i32::MIN;
+
+    for (index, (sequence, change)) in changes.into_iter().enumerate() {
+        if index < full_runs * run_length {
+            tree        = change.apply(&tree);
+            priority    = priority.max(change.priority());
+
+            // The snapshot takes the sequence number of the last change in its run
+            if (index+1) % run_length == 0 {
+                result.push((sequence, TreeChange::new(&TreeAddress::Here, &tree).with_priority(priority)));
+                priority = i32::MIN;
+            }
+        } else {
+            // The changes after the last full run are left as they are
+            result.push((sequence, change));
+        }
+    }
+
+    result
+}
+
+///
+/// Compacts a log of changes that were made to a tree that started as `initial`
+///
+/// Applying the result to `initial` produces the same tree as applying the original changes, but the trees
+/// produced along the way can be different.
+///
+pub fn compact_changes(initial: &TreeRef, changes: Vec<TreeChange>, strategy: CompactionStrategy) -> (Vec<TreeChange>, CompactionStats) {
+    let numbered            = changes.into_iter().enumerate().map(|(index, change)| (index as u64, change)).collect();
+    let (compacted, stats)  = compact_numbered_changes(initial, numbered, strategy);
+
+    (compacted.into_iter().map(|(_, change)| change).collect(), stats)
+}
+
+///
+/// Compacts a log of changes that each have a sequence number, made to a tree that started as `initial`
+///
+/// Each change in the result has the sequence number of the last of the original changes that it takes the place
+/// of, so the sequence numbers in the compacted log are still in order.
+///
+pub fn compact_numbered_changes(initial: &TreeRef, changes: Vec<(u64, TreeChange)>, strategy: CompactionStrategy) -> (Vec<(u64, TreeChange)>, CompactionStats) {
+    let input_changes   = changes.len();
+    let input_nodes     = changes.iter().map(|(_, change)| replacement_nodes(change)).sum();
+
+    let compacted = match strategy {
+        CompactionStrategy::KeepLastPerAddress          => keep_last_per_address(initial, changes),
+        CompactionStrategy::SnapshotEvery(run_length)   => snapshot_every(initial, changes, run_length),
+        CompactionStrategy::Combined(run_length)        => snapshot_every(initial, keep_last_per_address(initial, changes), run_length)
+    };
+
+    let stats = CompactionStats {
+        input_changes,
+        output_changes: compacted.len(),
+        input_nodes,
+        output_nodes:   compacted.iter().map(|(_, change)| replacement_nodes(change)).sum(),
+        input_bytes:    0,
+        output_bytes:   0
+    };
+
+    (compacted, stats)
+}
+
+#[cfg(test)]
+mod compaction_tests {
+    use super::super::super::tree::*;
+
+    fn replay(initial: &TreeRef, changes: &[TreeChange]) -> TreeRef {
+        changes.iter().fold(initial.clone(), |tree, change| change.apply(&tree))
+    }
+
+    fn initial_tree() -> TreeRef {
+        tree!("root", tree!("status", ("state", "idle"), ("count", 0)), tree!("items", ("item", 0), ("item", 1)), ("log", ()))
+    }
+
+    ///
+    /// A log with a lot of changes to a few addresses
+    ///
+    fn churning_log() -> Vec<TreeChange> {
+        let mut changes = vec![];
+
+        for step in 0..100 {
+            changes.push(TreeChange::new(&("status", "count"), &("count", step)));
+            changes.push(TreeChange::new(&("status", "state"), &("state", if step % 2 == 0 { "busy" } else { "idle" })));
+
+            if step % 10 == 0 {
+                changes.push(TreeChange::new(&"status", &tree!("status", ("state", "reset"), ("count", step))));
+            }
+
+            if step % 25 == 0 {
+                changes.push(TreeChange::new(&("items", 0), &TreeReplacement::Remove));
+                changes.push(TreeChange::new(&("items", 1), &("item", step)));
+            }
+        }
+
+        changes
+    }
+
+    fn assert_equivalent(strategy: CompactionStrategy) -> CompactionStats {
+        let initial             = initial_tree();
+        let log                 = churning_log();
+        let expected            = replay(&initial, &log);
+        let (compacted, stats)  = compact_changes(&initial, log, strategy);
+
+        assert!(to_tree_text(&replay(&initial, &compacted)) == to_tree_text(&expected));
+        assert!(stats.output_changes == compacted.len());
+        assert!(stats.output_changes < stats.input_changes);
+
+        stats
+    }
+
+    #[test]
+    fn keep_last_per_address_is_equivalent() {
+        let stats = assert_equivalent(CompactionStrategy::KeepLastPerAddress);
+
+        assert!(stats.input_changes == 218);
+        assert!(stats.output_changes < 30);
+    }
+
+    #[test]
+    fn snapshot_every_is_equivalent() {
+        let stats = assert_equivalent(CompactionStrategy::SnapshotEvery(50));
+
+        // 4 full runs and 18 changes left over
+        assert!(stats.output_changes == 22);
+    }
+
+    #[test]
+    fn combined_is_equivalent() {
+        let combined    = assert_equivalent(CompactionStrategy::Combined(10));
+        let kept        = assert_equivalent(CompactionStrategy::KeepLastPerAddress);
+
+        assert!(combined.output_changes < kept.output_changes);
+    }
+
+    #[test]
+    fn moved_nodes_are_not_dropped() {
+        // The first change is to a node that's moved by the remove, so the last change is to a different node
+        let initial     = tree!("root", ("a", 1), ("b", 2), ("c", 3));
+        let log         = vec![
+            TreeChange::new(&1, &("b", 20)),
+            TreeChange::new(&0, &TreeReplacement::Remove),
+            TreeChange::new(&1, &("c", 30))
+        ];
+        let expected    = replay(&initial, &log);
+
+        let (compacted, _) = compact_changes(&initial, log, CompactionStrategy::KeepLastPerAddress);
+
+        assert!(compacted.len() == 3);
+        assert!(to_tree_text(&replay(&initial, &compacted)) == to_tree_text(&expected));
+    }
+
+    #[test]
+    fn changes_inside_replaced_subtree_are_dropped() {
+        let initial     = initial_tree();
+        let log         = vec![
+            TreeChange::new(&("status", "count"), &("count", 1)),
+            TreeChange::new(&("status", "state"), &("state", "busy")),
+            TreeChange::new(&"status", &tree!("status", ("state", "done"), ("count", 2)))
+        ];
+
+        let (compacted, stats) = compact_changes(&initial, log, CompactionStrategy::KeepLastPerAddress);
+
+        assert!(compacted.len() == 1);
+        assert!(stats.input_nodes == 5);
+        assert!(stats.output_nodes == 3);
+    }
+
+    #[test]
+    fn changes_inside_a_replaced_list_of_children_are_dropped() {
+        let initial     = initial_tree();
+        let log         = vec![
+            TreeChange::new(&("items", 1), &("item", 10)),
+            TreeChange::new(&("items", 0), &("item", 5)),
+            TreeChange::new(&("items", 0), &("item", 20)).with_replaces_following(true)
+        ];
+        let expected    = replay(&initial, &log);
+
+        let (compacted, _) = compact_changes(&initial, log, CompactionStrategy::KeepLastPerAddress);
+
+        assert!(compacted.len() == 1);
+        assert!(compacted[0].replaces_following());
+        assert!(to_tree_text(&replay(&initial, &compacted)) == to_tree_text(&expected));
+    }
+
+    #[test]
+    fn compacted_changes_keep_the_highest_priority() {
+        let initial     = initial_tree();
+        let log         = vec![
+            TreeChange::new(&("status", "count"), &("count", 1)).with_priority(5),
+            TreeChange::new(&("status", "count"), &("count", 2)),
+            TreeChange::new(&("status", "state"), &("state", "busy")).with_priority(2),
+            TreeChange::new(&"log", &("log", "done"))
+        ];
+
+        let (kept, _)       = compact_changes(&initial, log.clone(), CompactionStrategy::KeepLastPerAddress);
+        let (snapshots, _)  = compact_changes(&initial, log, CompactionStrategy::SnapshotEvery(2));
+
+        assert!(kept.iter().map(|change| change.priority()).collect::<Vec<_>>() == vec![5, 2, 0]);
+        assert!(snapshots.iter().map(|change| change.priority()).collect::<Vec<_>>() == vec![5, 2]);
+    }
+
+    #[test]
+    fn random_logs_are_equivalent() {
+        let tags        = ["a", "b", "c"];
+        let mut seed    = 12345u64;
+        let mut next    = move |limit: u64| { seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407); ((seed >> 33) % limit) as usize };
+
+        for _ in 0..200 {
+            let initial = tree!("root", tree!("a", ("b", 1), ("c", 2)), tree!("b", ("a", 3)), ("c", 4));
+            let mut log = vec![];
+
+            for _ in 0..20 {
+                // Pick an address one or two levels deep, using indexes or tags
+                let first: TreeAddress = if next(2) == 0 { next(4).to_tree_address() } else { tags[next(3)].to_tree_address() };
+                let address = if next(2) == 0 {
+                    first
+                } else if next(2) == 0 {
+                    first.to_tree_address_then(next(3).to_tree_address())
+                } else {
+                    first.to_tree_address_then(tags[next(3)].to_tree_address())
+                };
+
+                let value = next(100) as i32;
+                let replacement = match next(4) {
+                    0 => TreeReplacement::Remove,
+                    1 => TreeReplacement::NewValue(tags[next(3)].to_string(), value.to_tree_value()),
+                    2 => TreeReplacement::NewNode(tree!(tags[next(3)], (tags[next(3)], value))),
+                    _ => TreeReplacement::NewNode((tags[next(3)], value).to_tree_node().with_sibling_node(Some(&(tags[next(3)], value).to_tree_node())))
+                };
+
+                log.push(TreeChange::new(&address, &replacement).with_replaces_following(next(5) == 0));
+            }
+
+            let expected = to_tree_text(&replay(&initial, &log));
+
+            for strategy in [CompactionStrategy::KeepLastPerAddress, CompactionStrategy::SnapshotEvery(3), CompactionStrategy::Combined(3)].iter() {
+                let (compacted, _) = compact_changes(&initial, log.clone(), *strategy);
+                assert!(to_tree_text(&replay(&initial, &compacted)) == expected);
+            }
+        }
+    }
+}
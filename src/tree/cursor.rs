@@ -0,0 +1,387 @@
+//
+//   Copyright 2016 Andrew Hunter
+//
+//   Licensed under the Apache License, Version 2.0 (the "License");
+//   you may not use this file except in compliance with the License.
+//   You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+//   Unless required by applicable law or agreed to in writing, software
+//   distributed under the License is distributed on an "AS IS" BASIS,
+//   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//   See the License for the specific language governing permissions and
+//   limitations under the License.
+//
+
+//!
+//! # Resumable iteration
+//!
+//! `iterate_budgeted()` visits a limited number of nodes of a tree, depth-first, and returns an `IterCursor` that
+//! can be used to carry on from where it stopped. This makes it possible to walk a very large tree a slice at a
+//! time, for instance between the events handled by an event loop.
+//!
+//! A cursor only stores the address of the next node to visit, as a list of child indexes, so it can be kept or
+//! sent elsewhere (it displays and parses in the same format as an indexed `TreeAddress`), and a traversal can
+//! be resumed against a newer version of the tree. When it is, the address is resolved again:
+//!
+//! * If the address exists and the nodes along it have the same tags as before, iteration continues from the
+//!   node that is there now. If a sibling before the cursor was removed, this is the node that followed the one
+//!   that was removed, so nothing is missed.
+//! * If a node along the address has a different tag, the node that was there is treated as removed, and
+//!   iteration continues from the node that has taken its place.
+//! * If the address doesn't exist, iteration continues from the next position in depth-first order that does
+//!   exist.
+//!
+//! In the last two cases, the levels of the address that no longer exist are counted as skipped, and added to
+//! the total kept by the cursor. A cursor that was parsed from text doesn't know the tags, so only its indexes
+//! are checked.
+//!
+//! Nodes that are added before the cursor's position after a traversal has started are not visited, and nodes
+//! that were visited already can be visited again if nodes are inserted before them.
+//!
+
+use std::fmt;
+use std::str::FromStr;
+
+use super::treenode::*;
+use super::address::*;
+
+///
+/// The position to resume a budgeted iteration from
+///
+#[derive(Clone, PartialEq, Debug)]
+pub struct IterCursor {
+    /// The child indexes leading to the next node to visit
+    path: Vec<usize>,
+
+    /// The tags of the nodes along the path, if they're known
+    tags: Option<Vec<String>>,
+
+    /// The number of addresses skipped so far when resuming
+    skipped: usize
+}
+
+///
+/// Error returned when a cursor can't be parsed
+///
+#[derive(Clone, PartialEq, Debug)]
+pub struct CursorParseError {
+    /// The text that couldn't be parsed
+    pub text: String
+}
+
+impl fmt::Display for CursorParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "'{}' is not an address made up of child indexes", self.text)
+    }
+}
+
+impl IterCursor {
+    ///
+    /// The address of the next node to visit
+    ///
+    pub fn address(&self) -> TreeAddress {
+        self.path.iter().rev().fold(TreeAddress::Here, |address, index| TreeAddress::ChildAtIndex(*index, Box::new(address)))
+    }
+
+    ///
+    /// The total number of addresses that were skipped while resuming the iteration that produced this cursor
+    ///
+    pub fn skipped(&self) -> usize {
+        self.skipped
+    }
+
+    ///
+    /// The number of addresses that would be skipped if this cursor were resumed against a tree
+    ///
+    pub fn skipped_in(&self, tree: &TreeRef) -> usize {
+        TreePosition::resume(tree, &self.path, self.tags.as_ref()).1
+    }
+}
+
+impl fmt::Display for IterCursor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.address())
+    }
+}
+
+impl FromStr for IterCursor {
+    type Err = CursorParseError;
+
+    ///
+    /// Parses a cursor from an indexed address, such as `.0.2.`, which starts with no skipped addresses
+    ///
+    fn from_str(text: &str) -> Result<IterCursor, CursorParseError> {
+        let error = || CursorParseError { text: text.to_string() };
+
+        if text.is_empty() || !text.starts_with('.') || !text.ends_with('.') {
+            return Err(error());
+        }
+
+        let path = if text == "." {
+            vec![]
+        } else {
+            text[1..text.len()-1].split('.').map(|index| index.parse::<usize>().map_err(|_| error())).collect::<Result<Vec<_>, _>>()?
+        };
+
+        Ok(IterCursor { path, tags: None, skipped: 0 })
+    }
+}
+
+///
+/// A node in a tree that is being iterated over
+///
+struct TreePosition {
+    /// The node that the iteration started from
+    root: TreeRef,
+
+    /// The nodes leading from the root to the current node, along with their index within their parent
+    path: Vec<(TreeRef, usize)>,
+
+    /// False once every node has been visited
+    valid: bool
+}
+
+impl TreePosition {
+    ///
+    /// Finds where to resume from in a tree, returning the position and the number of addresses that were skipped
+    ///
+    fn resume(root: &TreeRef, indexes: &[usize], tags: Option<&Vec<String>>) -> (TreePosition, usize) {
+        let mut position = TreePosition { root: root.clone(), path: vec![], valid: true };
+
+        for (level, index) in indexes.iter().enumerate() {
+            let child = position.current().get_child_ref()
+                .and_then(|first| (0..*index).try_fold(first, |node, _| node.get_sibling_ref()));
+
+            match child {
+                Some(child) => {
+                    let replaced = tags.map(|tags| tags[level] != child.get_tag()).unwrap_or(false);
+                    position.path.push((child, *index));
+
+                    // A node with a different tag has taken the place of the one that was here: it hasn't been visited yet
+                    if replaced {
+                        return (position, indexes.len() - level);
+                    }
+                },
+
+                None        => {
+                    // The rest of the address is missing: every child of the current node from here on would
+                    // be visited before the next node after it
+                    position.next_after_subtree();
+                    return (position, indexes.len() - level);
+                }
+            }
+        }
+
+        (position, 0)
+    }
+
+    ///
+    /// The current node
+    ///
+    fn current(&self) -> &TreeRef {
+        self.path.last().map(|(node, _)| node).unwrap_or(&self.root)
+    }
+
+    ///
+    /// The address of the current node
+    ///
+    fn address(&self) -> TreeAddress {
+        self.path.iter().rev().fold(TreeAddress::Here, |address, (_, index)| TreeAddress::ChildAtIndex(*index, Box::new(address)))
+    }
+
+    ///
+    /// Moves to the next node after the current one and everything beneath it
+    ///
+    fn next_after_subtree(&mut self) {
+        // The siblings of the root are not part of the iteration
+        while let Some((node, index)) = self.path.pop() {
+            if let Some(sibling) = node.get_sibling_ref() {
+                self.path.push((sibling, index+1));
+                return;
+            }
+        }
+
+        self.valid = false;
+    }
+
+    ///
+    /// Moves to the next node in depth-first order
+    ///
+    fn next(&mut self) {
+        match self.current().get_child_ref() {
+            Some(child) => self.path.push((child, 0)),
+            None        => self.next_after_subtree()
+        }
+    }
+}
+
+///
+/// Visits up to `max_nodes` nodes of a tree in depth-first order, starting from a cursor (or from the root if the
+/// cursor is `None`)
+///
+/// The visited nodes are passed to the visit function along with their address relative to the root of the tree.
+/// Returns a cursor for the next node to visit, or `None` if every node has been visited.
+///
+/// ```
+/// # use tametree::prelude::*;
+/// # use tametree::tree::*;
+/// let tree        = tree!("root", tree!("a", "a1", "a2"), "b");
+/// let mut tags    = vec![];
+/// let mut cursor  = None;
+///
+/// loop {
+///     cursor = iterate_budgeted(&tree, cursor, 2, |_address, node| tags.push(node.get_tag().to_string()));
+///     if cursor.is_none() { break; }
+/// }
+///
+/// assert!(tags == vec!["root", "a", "a1", "a2", "b"]);
+/// ```
+///
+pub fn iterate_budgeted<TVisit: FnMut(&TreeAddress, &TreeRef)>(tree: &TreeRef, cursor: Option<IterCursor>, max_nodes: usize, mut visit: TVisit) -> Option<IterCursor> {
+    let (path, tags, mut skipped)       = cursor.map(|cursor| (cursor.path, cursor.tags, cursor.skipped)).unwrap_or((vec![], None, 0));
+    let (mut position, newly_skipped)   = TreePosition::resume(tree, &path, tags.as_ref());
+    skipped += newly_skipped;
+
+    let mut visited = 0;
+    while position.valid && visited < max_nodes {
+        visit(&position.address(), position.current());
+        visited += 1;
+
+        position.next();
+    }
+
+    if position.valid {
+        Some(IterCursor {
+            path:   position.path.iter().map(|(_, index)| *index).collect(),
+            tags:   Some(position.path.iter().map(|(node, _)| node.get_tag().to_string()).collect()),
+            skipped
+        })
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod cursor_tests {
+    use super::super::super::tree::*;
+
+    fn large_tree() -> TreeRef {
+        let leaves  = |prefix: &str| (0..5).map(|index| (format!("{}{}", prefix, index).as_str(), index).to_tree_node()).collect::<Vec<_>>();
+        let a       = "a".to_tree_node().with_children(&leaves("a"));
+        let b       = "b".to_tree_node().with_children(&vec![tree!("b0", "b00", "b01"), "b1".to_tree_node()]);
+        let c       = "c".to_tree_node().with_children(&leaves("c"));
+
+        "root".to_tree_node().with_children(&vec![a, b, c])
+    }
+
+    fn visit_all(tree: &TreeRef) -> Vec<(String, String)> {
+        let mut visited = vec![];
+        let cursor      = iterate_budgeted(tree, None, usize::max_value(), |address, node| visited.push((address.to_string(), node.get_tag().to_string())));
+
+        assert!(cursor.is_none());
+        visited
+    }
+
+    #[test]
+    fn slices_match_one_shot_traversal() {
+        let tree = large_tree();
+
+        for budget in 1..8 {
+            let mut visited = vec![];
+            let mut cursor  = None;
+
+            loop {
+                cursor = iterate_budgeted(&tree, cursor, budget, |address, node| visited.push((address.to_string(), node.get_tag().to_string())));
+                if cursor.is_none() { break; }
+            }
+
+            assert!(visited == visit_all(&tree));
+        }
+    }
+
+    #[test]
+    fn budget_is_exact() {
+        let tree        = large_tree();
+        let total       = visit_all(&tree).len();
+        let mut count   = 0;
+
+        let cursor = iterate_budgeted(&tree, None, 4, |_, _| count += 1);
+        assert!(count == 4);
+        assert!(cursor.unwrap().to_string() == ".0.2.");
+
+        // Exactly the rest of the tree: no cursor is returned as there's nothing left
+        let mut rest    = 0;
+        let cursor      = iterate_budgeted(&tree, Some(".0.2.".parse().unwrap()), total-4, |_, _| rest += 1);
+        assert!(rest == total-4);
+        assert!(cursor.is_none());
+
+        // One short of the rest of the tree: the cursor points at the last node
+        let cursor = iterate_budgeted(&tree, Some(".0.2.".parse().unwrap()), total-5, |_, _| { });
+        assert!(cursor.unwrap().to_string() == ".2.4.");
+    }
+
+    #[test]
+    fn removed_node_is_skipped() {
+        let tree        = large_tree();
+        let mut visited = vec![];
+
+        // Stop at .1.0.1 (b01)
+        let cursor      = iterate_budgeted(&tree, None, 10, |_, node| visited.push(node.get_tag().to_string()));
+        assert!(cursor.as_ref().unwrap().to_string() == ".1.0.1.");
+
+        // Remove b01, which was the last child of b0: iteration carries on at b1
+        let changed = TreeChange::new(&(1, (0, 1)), &()).apply(&tree);
+        assert!(cursor.as_ref().unwrap().skipped_in(&changed) == 1);
+
+        let cursor = iterate_budgeted(&changed, cursor, usize::max_value(), |_, node| visited.push(node.get_tag().to_string()));
+        assert!(cursor.is_none());
+        assert!(visited[10..].to_vec() == vec!["b1", "c", "c0", "c1", "c2", "c3", "c4"]);
+
+        // Removing all of b skips every level of the address, and carries on from c, which has taken its place
+        let cursor      = iterate_budgeted(&tree, None, 10, |_, _| { });
+        let changed     = TreeChange::new(&1, &()).apply(&tree);
+        let mut tags    = vec![];
+        let cursor      = iterate_budgeted(&changed, cursor, 2, |_, node| tags.push(node.get_tag().to_string()));
+        assert!(tags == vec!["c", "c0"]);
+        assert!(cursor.unwrap().skipped() == 3);
+    }
+
+    #[test]
+    fn removed_cursor_node_is_skipped() {
+        let tree        = large_tree();
+        let cursor      = iterate_budgeted(&tree, None, 4, |_, _| { });
+
+        // The cursor is at a2: removing it moves on to a3, which has taken its place
+        let changed     = TreeChange::new(&(0, 2), &()).apply(&tree);
+        let mut tags    = vec![];
+        let cursor      = iterate_budgeted(&changed, cursor, 2, |_, node| tags.push(node.get_tag().to_string()));
+        assert!(tags == vec!["a3", "a4"]);
+        assert!(cursor.unwrap().skipped() == 1);
+    }
+
+    #[test]
+    fn cursor_round_trips_through_address_format() {
+        let tree    = large_tree();
+        let cursor  = iterate_budgeted(&tree, None, 9, |_, _| { }).unwrap();
+
+        let parsed  = cursor.to_string().parse::<IterCursor>().unwrap();
+
+        assert!(cursor.to_string() == cursor.address().to_string());
+        assert!(parsed.address() == cursor.address());
+        assert!(parsed.to_string() == cursor.to_string());
+
+        // The parsed cursor resumes from the same place
+        let mut from_original   = vec![];
+        let mut from_parsed     = vec![];
+        iterate_budgeted(&tree, Some(cursor), 3, |address, _| from_original.push(address.to_string()));
+        iterate_budgeted(&tree, Some(parsed), 3, |address, _| from_parsed.push(address.to_string()));
+        assert!(from_original == from_parsed);
+
+        assert!(".".parse::<IterCursor>().unwrap().address() == TreeAddress::Here);
+        assert!(".\"a\"".parse::<IterCursor>().is_err());
+        assert!("0.1.".parse::<IterCursor>().is_err());
+        assert!(".0.1".parse::<IterCursor>().is_err());
+    }
+}
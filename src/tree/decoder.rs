@@ -14,17 +14,24 @@
 //   limitations under the License.
 //
 
+use std::rc::*;
+
 use rustc_serialize::*;
 
 use super::encoder::*;
 use super::treenode::*;
+use super::basictree::*;
+use super::iterator::*;
 use super::values::*;
 
 ///
 /// Used to help decode tree nodes into other types
 ///
 struct TreeNodeDecoder {
-    current_node: TreeRef
+    current_node: TreeRef,
+
+    /// The children of the maps or sequences that are currently being read (the innermost one is last)
+    collection_children: Vec<Vec<TreeRef>>
 }
 
 #[derive(Debug)]
@@ -37,9 +44,37 @@ pub enum TreeNodeDecodingError {
 }
 
 impl TreeNodeDecoder {
+    fn new(tree: &TreeRef) -> TreeNodeDecoder {
+        TreeNodeDecoder { current_node: tree.to_owned(), collection_children: vec![] }
+    }
+
     fn read_current(&self) -> &TreeValue {
         self.current_node.get_value()
     }
+
+    ///
+    /// Moves into a node, decodes it using the specified function and moves back out again
+    ///
+    fn read_node<T, F>(&mut self, node: TreeRef, f: F) -> Result<T, TreeNodeDecodingError> where F: FnOnce(&mut Self) -> Result<T, TreeNodeDecodingError> {
+        let previous_node = self.current_node.to_owned();
+        self.current_node = node;
+
+        let result = f(self);
+
+        self.current_node = previous_node;
+
+        result
+    }
+
+    ///
+    /// Retrieves the child at the specified index of the map or sequence that's currently being read
+    ///
+    fn collection_child(&self, idx: usize) -> Result<TreeRef, TreeNodeDecodingError> {
+        self.collection_children.last()
+            .and_then(|children| children.get(idx))
+            .map(|child| child.to_owned())
+            .ok_or(TreeNodeDecodingError::MissingField(idx.to_string()))
+    }
 }
 
 #[allow(unused_variables)]          // Unused function parameters are quite common due to the way this trait is designed
@@ -76,7 +111,7 @@ impl Decoder for TreeNodeDecoder {
 
     fn read_str(&mut self) -> Result<String, Self::Error> {
         match *self.read_current() {
-            TreeValue::String(ref x)    => Ok(x.to_owned()),
+            TreeValue::String(ref x)    => Ok(x.to_string()),
             _                           => Err(TreeNodeDecodingError::NodeHasInvalidType)
         }
     }
@@ -182,11 +217,23 @@ impl Decoder for TreeNodeDecoder {
     }
 
     fn read_tuple<T, F>(&mut self, len: usize, f: F) -> Result<T, Self::Error> where F: FnOnce(&mut Self) -> Result<T, Self::Error> {
-        Err(TreeNodeDecodingError::UnsupportedType)
+        // Only (tag, value) pairs are supported
+        if len != 2 {
+            return Err(TreeNodeDecodingError::UnsupportedType);
+        }
+
+        f(self)
     }
 
     fn read_tuple_arg<T, F>(&mut self, a_idx: usize, f: F) -> Result<T, Self::Error> where F: FnOnce(&mut Self) -> Result<T, Self::Error> {
-        Err(TreeNodeDecodingError::UnsupportedType)
+        if a_idx == 0 {
+            // The first argument is decoded from a node whose value is the tag of the current node
+            let tag_node = Rc::new(BasicTree::new("", self.current_node.get_tag(), None, None));
+
+            self.read_node(tag_node, f)
+        } else {
+            f(self)
+        }
     }
 
     fn read_tuple_struct<T, F>(&mut self, s_name: &str, len: usize, f: F) -> Result<T, Self::Error> where F: FnOnce(&mut Self) -> Result<T, Self::Error> {
@@ -202,23 +249,47 @@ impl Decoder for TreeNodeDecoder {
     }
 
     fn read_seq<T, F>(&mut self, f: F) -> Result<T, Self::Error> where F: FnOnce(&mut Self, usize) -> Result<T, Self::Error> {
-        Err(TreeNodeDecodingError::UnsupportedType)
+        // Sequences are read from the children of the current node, in order
+        let children: Vec<TreeRef> = self.current_node.iter_children().collect();
+        let len = children.len();
+
+        self.collection_children.push(children);
+        let result = f(self, len);
+        self.collection_children.pop();
+
+        result
     }
 
     fn read_seq_elt<T, F>(&mut self, idx: usize, f: F) -> Result<T, Self::Error> where F: FnOnce(&mut Self) -> Result<T, Self::Error> {
-        Err(TreeNodeDecodingError::UnsupportedType)
+        let child = self.collection_child(idx)?;
+
+        self.read_node(child, f)
     }
 
     fn read_map<T, F>(&mut self, f: F) -> Result<T, Self::Error> where F: FnOnce(&mut Self, usize) -> Result<T, Self::Error> {
-        Err(TreeNodeDecodingError::UnsupportedType)
+        // Maps are read from the children of the current node: the tag is the key and the node is the value
+        let children: Vec<TreeRef> = self.current_node.iter_children().collect();
+        let len = children.len();
+
+        self.collection_children.push(children);
+        let result = f(self, len);
+        self.collection_children.pop();
+
+        result
     }
 
     fn read_map_elt_key<T, F>(&mut self, idx: usize, f: F) -> Result<T, Self::Error> where F: FnOnce(&mut Self) -> Result<T, Self::Error> {
-        Err(TreeNodeDecodingError::UnsupportedType)
+        // The key is decoded from a node whose value is the tag of the child
+        let child       = self.collection_child(idx)?;
+        let key_node    = Rc::new(BasicTree::new("", child.get_tag(), None, None));
+
+        self.read_node(key_node, f)
     }
 
     fn read_map_elt_val<T, F>(&mut self, idx: usize, f: F) -> Result<T, Self::Error> where F: FnOnce(&mut Self) -> Result<T, Self::Error> {
-        Err(TreeNodeDecodingError::UnsupportedType)
+        let child = self.collection_child(idx)?;
+
+        self.read_node(child, f)
     }
 
     fn error(&mut self, err: &str) -> Self::Error {
@@ -241,7 +312,7 @@ impl<T: Decodable + EncodeToTreeNode> DecodeFromTreeNode for T {
     /// Creates a new object from a tree node
     ///
     fn new_from_tree(tree: &TreeRef) -> Result<T, TreeNodeDecodingError> {
-        let mut decoder = TreeNodeDecoder { current_node: tree.to_owned() };
+        let mut decoder = TreeNodeDecoder::new(tree);
 
         T::decode(&mut decoder)
     }
@@ -251,15 +322,14 @@ impl<T: Decodable + EncodeToTreeNode> DecodeFromTreeNode for T {
 mod decoder_tests {
     use super::super::super::tree::*;
 
-    #[derive(RustcEncodable, RustcDecodable)]
-    struct Test {
-        field1: i32,
-        field2: String,
-        field3: bool
+    tree_struct! {
+        struct Test {
+            field1: i32,
+            field2: String,
+            field3: bool
+        }
     }
 
-    impl EncodeToTreeNode for Test { }
-
     #[test]
     fn encode_decode_structure() {
         let initial_structure = Test { field1: 42, field2: "test string".to_string(), field3: true };
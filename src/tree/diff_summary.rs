@@ -0,0 +1,598 @@
+//
+//   Copyright 2016 Andrew Hunter
+//
+//   Licensed under the Apache License, Version 2.0 (the "License");
+//   you may not use this file except in compliance with the License.
+//   You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+//   Unless required by applicable law or agreed to in writing, software
+//   distributed under the License is distributed on an "AS IS" BASIS,
+//   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//   See the License for the specific language governing permissions and
+//   limitations under the License.
+//
+
+//!
+//! # Diff summaries
+//!
+//! `summarize_diff()` describes the differences between two trees in a form intended for people (or the tools
+//! that show them): a `DiffSummary` lists the nodes that were added, removed, moved or had their value changed,
+//! grouped by kind, with a label and a short preview of each node. A summary is a `tree_struct`, so it can be
+//! encoded as a tree and sent anywhere a tree can go.
+//!
+//! The children of two nodes are matched up by tag: the first child with a particular tag in the old tree is
+//! matched with the first child with that tag in the new tree, the second with the second and so on. Children
+//! that aren't matched have been added or removed. When move detection is turned on, a subtree that was removed
+//! from one place and added with the same content somewhere else is reported as moved instead.
+//!
+//! Addresses are written as compressed paths in labels and previews: tags separated by `.`, with indexes in
+//! brackets for children that can't be found by their tag (`users[2].name`). A chain of nodes that have no value
+//! and a single child is written in the same way in a preview.
+//!
+//! ```
+//! # use tametree::prelude::*;
+//! # use tametree::tree::*;
+//! let old     = tree!("config", ("name", "old"), ("port", 80));
+//! let new     = tree!("config", ("name", "new"), ("port", 80), ("debug", true));
+//! let summary = summarize_diff(&old, &new, SummaryOptions::default());
+//!
+//! assert!(summary.groups.iter().map(|group| group.label.clone()).collect::<Vec<_>>() == vec!["1 added", "1 value changed"]);
+//! assert!(summary.groups[1].entries[0].label == "name: \"old\" -> \"new\"");
+//! ```
+//!
+
+use std::fmt;
+use std::fmt::Write;
+
+use super::treenode::*;
+use super::values::*;
+use super::address::*;
+use super::iterator::*;
+use super::text::*;
+
+///
+/// Options for `summarize_diff()`
+///
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct SummaryOptions {
+    /// If true, entries are grouped by kind. Otherwise they're in a single group, in the order they appear in the trees
+    pub group_by_kind: bool,
+
+    /// If true, subtrees that were removed and added elsewhere with the same content are reported as moved
+    pub detect_moves: bool,
+
+    /// Nodes deeper than this aren't compared one by one: a subtree below this depth that differs is reported as a
+    /// single 'changed' entry
+    pub max_depth: usize,
+
+    /// The number of levels of children written in each preview
+    pub preview_depth: usize,
+
+    /// The maximum number of entries in a summary. Entries past this are counted but not included
+    pub max_entries: usize
+}
+
+impl Default for SummaryOptions {
+    fn default() -> SummaryOptions {
+        SummaryOptions {
+            group_by_kind:  true,
+            detect_moves:   true,
+            max_depth:      usize::MAX,
+            preview_depth:  2,
+            max_entries:    100
+        }
+    }
+}
+
+tree_struct! {
+    ///
+    /// A node that's different between two trees
+    ///
+    #[derive(Clone, PartialEq, Debug)]
+    pub struct DiffEntry {
+        // "added", "removed", "value-changed", "moved" or "changed"
+        pub kind: String,
+
+        // The address of the node in the new tree (or the old tree for removed nodes)
+        pub address: String,
+
+        // For moved nodes, the address of the node in the old tree
+        pub moved_from: String,
+
+        // A description of the difference
+        pub label: String,
+
+        // The value of the node in the old tree, written as in the text format ("" if it's not in the old tree)
+        pub old_value: String,
+
+        // The value of the node in the new tree ("" if it's not in the new tree)
+        pub new_value: String,
+
+        // The number of nodes in the subtree in the old tree (0 if it's not in the old tree)
+        pub old_size: i32,
+
+        // The number of nodes in the subtree in the new tree (0 if it's not in the new tree)
+        pub new_size: i32,
+
+        // A preview of the subtree (from the new tree, except for removed nodes)
+        pub preview: String
+    }
+}
+
+tree_struct! {
+    ///
+    /// The entries of a diff summary of one kind
+    ///
+    #[derive(Clone, PartialEq, Debug)]
+    pub struct DiffGroup {
+        // The kind of the entries in this group, or "all" if the entries aren't grouped
+        pub kind: String,
+
+        // A description of the group, such as "3 added"
+        pub label: String,
+
+        pub entries: Vec<DiffEntry>
+    }
+}
+
+tree_struct! {
+    ///
+    /// A summary of the differences between two trees
+    ///
+    #[derive(Clone, PartialEq, Debug)]
+    pub struct DiffSummary {
+        // The groups of entries (groups with no entries are left out)
+        pub groups: Vec<DiffGroup>,
+
+        // The number of differences that were found
+        pub total: i32,
+
+        // The number of differences that weren't included because of the limit on entries
+        pub overflow: i32
+    }
+}
+
+impl fmt::Display for DiffSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.total == 0 {
+            writeln!(f, "No differences")?;
+        }
+
+        for group in self.groups.iter() {
+            writeln!(f, "{}", group.label)?;
+
+            for entry in group.entries.iter() {
+                writeln!(f, "    {}", entry.label)?;
+            }
+        }
+
+        if self.overflow > 0 {
+            writeln!(f, "({} more differences)", self.overflow)?;
+        }
+
+        Ok(())
+    }
+}
+
+///
+/// The kinds of entry, in the order their groups appear in a summary
+///
+const KINDS: [&str; 5] = ["added", "removed", "value-changed", "moved", "changed"];
+
+///
+/// Describes the size of a group of entries
+///
+fn group_label(kind: &str, count: usize) -> String {
+    match kind {
+        "all"           => format!("{} differences", count),
+        "value-changed" => format!("{} value changed", count),
+        _               => format!("{} {}", count, kind)
+    }
+}
+
+///
+/// A part of a path to a node
+///
+#[derive(Clone, PartialEq)]
+enum PathPart {
+    Index(usize),
+    Tag(String)
+}
+
+///
+/// Converts a path into an address
+///
+fn path_address(path: &[PathPart]) -> TreeAddress {
+    path.iter().rev().fold(TreeAddress::Here, |address, part| match *part {
+        PathPart::Index(index)  => TreeAddress::ChildAtIndex(index, Box::new(address)),
+        PathPart::Tag(ref tag)  => TreeAddress::ChildWithTag(tag.clone(), Box::new(address))
+    })
+}
+
+///
+/// Writes a path as a compressed path (`users[2].name`)
+///
+fn write_path<W: Write>(out: &mut W, path: &[PathPart]) -> fmt::Result {
+    if path.is_empty() {
+        return out.write_str("(root)");
+    }
+
+    for (pos, part) in path.iter().enumerate() {
+        match *part {
+            PathPart::Index(index)  => write!(out, "[{}]", index)?,
+            PathPart::Tag(ref tag)  => {
+                if pos > 0 { out.write_char('.')?; }
+                write_tag(out, tag)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+///
+/// Formats a path as a compressed path
+///
+fn compressed_path(path: &[PathPart]) -> String {
+    let mut result = String::new();
+    let _ = write_path(&mut result, path);
+    result
+}
+
+///
+/// Formats a value as it's written in the text format
+///
+fn value_text(value: &TreeValue) -> String {
+    let mut result = String::new();
+    let _ = write_value(&mut result, value);
+    result
+}
+
+///
+/// Writes a one-line preview of a tree, including children down to a certain depth
+///
+fn write_preview<W: Write>(out: &mut W, tree: &TreeRef, depth: usize) -> fmt::Result {
+    // Chains of nodes with no value and a single child are written as a path
+    let mut node = tree.clone();
+    write_tag(out, node.get_tag())?;
+
+    while node.get_value().is_nothing() {
+        let only_child = match node.get_child_ref() {
+            Some(ref child) if child.get_sibling_ref().is_none()  => child.clone(),
+            _                                                       => break
+        };
+
+        out.write_char('.')?;
+        write_tag(out, only_child.get_tag())?;
+        node = only_child;
+    }
+
+    if !node.get_value().is_nothing() {
+        out.write_str(": ")?;
+        write_value(out, node.get_value())?;
+    }
+
+    if node.get_child_ref().is_some() {
+        if depth == 0 {
+            out.write_str(" { ... }")?;
+        } else {
+            out.write_str(" { ")?;
+
+            for (pos, child) in node.iter_children().enumerate() {
+                if pos > 0 { out.write_str(", ")?; }
+                write_preview(out, &child, depth-1)?;
+            }
+
+            out.write_str(" }")?;
+        }
+    }
+
+    Ok(())
+}
+
+///
+/// The number of nodes in a tree (not counting the siblings of the root)
+///
+fn subtree_size(tree: &TreeRef) -> usize {
+    1 + tree.iter_children().map(|child| subtree_size(&child)).sum::<usize>()
+}
+
+///
+/// True if two trees are the same (not counting the siblings of the roots)
+///
+fn same_tree(a: &TreeRef, b: &TreeRef) -> bool {
+    let a_children: Vec<TreeRef> = a.iter_children().collect();
+    let b_children: Vec<TreeRef> = b.iter_children().collect();
+
+    a.get_tag() == b.get_tag()
+        && a.get_value() == b.get_value()
+        && a_children.len() == b_children.len()
+        && a_children.iter().zip(b_children.iter()).all(|(a, b)| same_tree(a, b))
+}
+
+///
+/// A difference that has been found, before it's turned into an entry
+///
+struct Difference {
+    kind: &'static str,
+    path: Vec<PathPart>,
+    moved_from: Option<Vec<PathPart>>,
+    old: Option<TreeRef>,
+    new: Option<TreeRef>
+}
+
+///
+/// The path part for each of a list of children: the tag if it's the first child with that tag, or the index
+///
+fn child_paths(children: &[TreeRef]) -> Vec<PathPart> {
+    children.iter().enumerate().map(|(pos, child)| {
+        if children[0..pos].iter().any(|earlier| earlier.get_tag() == child.get_tag()) {
+            PathPart::Index(pos)
+        } else {
+            PathPart::Tag(child.get_tag().to_string())
+        }
+    }).collect()
+}
+
+///
+/// Finds the differences between two nodes that have been matched up with each other
+///
+fn find_differences(old: &TreeRef, new: &TreeRef, path: &mut Vec<PathPart>, options: &SummaryOptions, differences: &mut Vec<Difference>) {
+    if old.get_value() != new.get_value() {
+        differences.push(Difference { kind: "value-changed", path: path.clone(), moved_from: None, old: Some(old.clone()), new: Some(new.clone()) });
+    }
+
+    let old_children: Vec<TreeRef> = old.iter_children().collect();
+    let new_children: Vec<TreeRef> = new.iter_children().collect();
+
+    if path.len() >= options.max_depth {
+        let same_children = old_children.len() == new_children.len() && old_children.iter().zip(new_children.iter()).all(|(a, b)| same_tree(a, b));
+
+        if !same_children {
+            differences.push(Difference { kind: "changed", path: path.clone(), moved_from: None, old: Some(old.clone()), new: Some(new.clone()) });
+        }
+        return;
+    }
+
+    let old_paths       = child_paths(&old_children);
+    let new_paths       = child_paths(&new_children);
+    let mut old_matched = vec![false; old_children.len()];
+
+    for (new_pos, new_child) in new_children.iter().enumerate() {
+        // The nth child with a tag is matched with the nth child with the same tag in the old tree
+        let occurrence  = new_children[0..new_pos].iter().filter(|earlier| earlier.get_tag() == new_child.get_tag()).count();
+        let old_pos     = old_children.iter().enumerate().filter(|(_, old_child)| old_child.get_tag() == new_child.get_tag()).map(|(pos, _)| pos).nth(occurrence);
+
+        path.push(new_paths[new_pos].clone());
+
+        match old_pos {
+            Some(old_pos) => {
+                old_matched[old_pos] = true;
+                find_differences(&old_children[old_pos], new_child, path, options, differences);
+            },
+
+            None => differences.push(Difference { kind: "added", path: path.clone(), moved_from: None, old: None, new: Some(new_child.clone()) })
+        }
+
+        path.pop();
+    }
+
+    for (old_pos, old_child) in old_children.iter().enumerate().filter(|(pos, _)| !old_matched[*pos]) {
+        path.push(old_paths[old_pos].clone());
+        differences.push(Difference { kind: "removed", path: path.clone(), moved_from: None, old: Some(old_child.clone()), new: None });
+        path.pop();
+    }
+}
+
+///
+/// Replaces pairs of removed and added subtrees with the same content with moves
+///
+fn detect_moves(differences: Vec<Difference>) -> Vec<Difference> {
+    let mut differences: Vec<Option<Difference>> = differences.into_iter().map(Some).collect();
+
+    for removed_pos in 0..differences.len() {
+        let removed = match differences[removed_pos] {
+            Some(Difference { kind: "removed", old: Some(ref old), ref path, .. }) => (old.clone(), path.clone()),
+            _ => continue
+        };
+
+        let added_pos = differences.iter().position(|difference| match *difference {
+            Some(Difference { kind: "added", new: Some(ref new), .. })  => same_tree(&removed.0, new),
+            _                                                           => false
+        });
+
+        if let Some(added_pos) = added_pos {
+            differences[removed_pos] = None;
+
+            if let Some(ref mut added) = differences[added_pos] {
+                added.kind          = "moved";
+                added.old           = Some(removed.0);
+                added.moved_from    = Some(removed.1);
+            }
+        }
+    }
+
+    differences.into_iter().flatten().collect()
+}
+
+///
+/// Turns a difference into an entry for a summary
+///
+fn entry(difference: Difference, options: &SummaryOptions) -> DiffEntry {
+    let path            = compressed_path(&difference.path);
+    let old_value       = difference.old.as_ref().map(|old| value_text(old.get_value())).unwrap_or_default();
+    let new_value       = difference.new.as_ref().map(|new| value_text(new.get_value())).unwrap_or_default();
+    let old_size        = difference.old.as_ref().map(subtree_size).unwrap_or(0);
+    let new_size        = difference.new.as_ref().map(subtree_size).unwrap_or(0);
+    let moved_from      = difference.moved_from.as_ref().map(|from| compressed_path(from)).unwrap_or_default();
+
+    let label = match difference.kind {
+        "added"         => format!("added {} ({} nodes)", path, new_size),
+        "removed"       => format!("removed {} ({} nodes)", path, old_size),
+        "value-changed" => format!("{}: {} -> {}", path, old_value, new_value),
+        "moved"         => format!("moved {} to {}", moved_from, path),
+        _               => format!("{} changed below the depth limit ({} -> {} nodes)", path, old_size, new_size)
+    };
+
+    let mut preview = String::new();
+    if let Some(tree) = difference.new.as_ref().or(difference.old.as_ref()) {
+        let _ = write_preview(&mut preview, tree, options.preview_depth);
+    }
+
+    DiffEntry {
+        kind:       difference.kind.to_string(),
+        address:    path_address(&difference.path).to_string(),
+        moved_from: difference.moved_from.as_ref().map(|from| path_address(from).to_string()).unwrap_or_default(),
+        label,
+        old_value,
+        new_value,
+        old_size:   old_size as i32,
+        new_size:   new_size as i32,
+        preview
+    }
+}
+
+///
+/// Summarises the differences between two trees
+///
+/// The roots of the two trees are always matched with each other, even if their tags are different. Their
+/// siblings are not compared.
+///
+pub fn summarize_diff(old: &TreeRef, new: &TreeRef, options: SummaryOptions) -> DiffSummary {
+    let mut differences = vec![];
+    find_differences(old, new, &mut vec![], &options, &mut differences);
+
+    if options.detect_moves {
+        differences = detect_moves(differences);
+    }
+
+    let total       = differences.len();
+    let overflow    = total.saturating_sub(options.max_entries);
+    let entries: Vec<DiffEntry> = differences.into_iter().take(options.max_entries).map(|difference| entry(difference, &options)).collect();
+
+    let groups = if !options.group_by_kind {
+        if entries.is_empty() { vec![] } else { vec![DiffGroup { kind: "all".to_string(), label: group_label("all", entries.len()), entries }] }
+    } else {
+        KINDS.iter().filter_map(|kind| {
+            let entries: Vec<DiffEntry> = entries.iter().filter(|entry| entry.kind == *kind).cloned().collect();
+
+            if entries.is_empty() {
+                None
+            } else {
+                Some(DiffGroup { kind: kind.to_string(), label: group_label(kind, entries.len()), entries })
+            }
+        }).collect()
+    };
+
+    DiffSummary { groups, total: total as i32, overflow: overflow as i32 }
+}
+
+#[cfg(test)]
+mod diff_summary_tests {
+    use super::super::super::tree::*;
+
+    fn kinds(summary: &DiffSummary) -> Vec<String> {
+        summary.groups.iter().flat_map(|group| group.entries.iter().map(|entry| entry.kind.clone())).collect()
+    }
+
+    #[test]
+    fn identical_trees_have_no_differences() {
+        let tree    = tree!("root", ("a", 1), tree!("b", ("c", 2)));
+        let summary = summarize_diff(&tree, &tree!("root", ("a", 1), tree!("b", ("c", 2))), SummaryOptions::default());
+
+        assert!(summary.groups.is_empty());
+        assert!(summary.total == 0);
+        assert!(summary.to_string() == "No differences\n");
+    }
+
+    #[test]
+    fn summarizes_additions() {
+        let summary = summarize_diff(&tree!("root", ("a", 1)), &tree!("root", ("a", 1), tree!("b", ("c", 2), ("d", 3))), SummaryOptions::default());
+        let added   = &summary.groups[0].entries[0];
+
+        assert!(kinds(&summary) == vec!["added"]);
+        assert!(added.address == "b".to_tree_address().to_string());
+        assert!(added.new_size == 3 && added.old_size == 0);
+        assert!(added.label == "added b (3 nodes)");
+        assert!(added.preview == "b { c: 2, d: 3 }");
+    }
+
+    #[test]
+    fn summarizes_removals() {
+        let summary = summarize_diff(&tree!("root", ("a", 1), ("a", 2)), &tree!("root", ("a", 1)), SummaryOptions::default());
+        let removed = &summary.groups[0].entries[0];
+
+        assert!(kinds(&summary) == vec!["removed"]);
+        assert!(removed.label == "removed [1] (1 nodes)");
+        assert!(removed.old_value == "2" && removed.new_value.is_empty());
+        assert!(removed.preview == "a: 2");
+    }
+
+    #[test]
+    fn summarizes_value_changes() {
+        let summary = summarize_diff(&tree!("root", tree!("user", ("name", "alice"))), &tree!("root", tree!("user", ("name", "bob"))), SummaryOptions::default());
+        let changed = &summary.groups[0].entries[0];
+
+        assert!(kinds(&summary) == vec!["value-changed"]);
+        assert!(changed.label == "user.name: \"alice\" -> \"bob\"");
+        assert!(changed.old_value == "\"alice\"" && changed.new_value == "\"bob\"");
+    }
+
+    #[test]
+    fn detects_moves_between_parents() {
+        let old = tree!("root", tree!("pending", tree!("job", ("id", 7), ("name", "build"))), tree!("done", ("job", 1)));
+        let new = tree!("root", "pending".to_tree_node(), tree!("done", ("job", 1), tree!("job", ("id", 7), ("name", "build"))));
+
+        let moved = summarize_diff(&old, &new, SummaryOptions::default());
+        assert!(kinds(&moved) == vec!["moved"]);
+        assert!(moved.groups[0].entries[0].label == "moved pending.job to done[1]");
+        assert!(moved.groups[0].entries[0].moved_from == ("pending", "job").to_tree_address().to_string());
+
+        let not_moved = summarize_diff(&old, &new, SummaryOptions { detect_moves: false, ..SummaryOptions::default() });
+        assert!(kinds(&not_moved) == vec!["added", "removed"]);
+    }
+
+    #[test]
+    fn depth_limit_reports_whole_subtrees() {
+        let old     = tree!("root", tree!("a", tree!("b", ("c", 1))));
+        let new     = tree!("root", tree!("a", tree!("b", ("c", 2), ("d", 3))));
+        let summary = summarize_diff(&old, &new, SummaryOptions { max_depth: 1, ..SummaryOptions::default() });
+
+        assert!(kinds(&summary) == vec!["changed"]);
+        assert!(summary.groups[0].entries[0].label == "a changed below the depth limit (3 -> 4 nodes)");
+    }
+
+    #[test]
+    fn previews_are_depth_limited_and_compress_paths() {
+        let new     = tree!("root", tree!("a", tree!("b", tree!("c", ("d", 1), ("e", 2)))));
+        let summary = summarize_diff(&"root".to_tree_node(), &new, SummaryOptions { preview_depth: 0, ..SummaryOptions::default() });
+
+        assert!(summary.groups[0].entries[0].preview == "a.b.c { ... }");
+    }
+
+    #[test]
+    fn entries_are_limited() {
+        let old     = "root".to_tree_node();
+        let new     = tree!("root", ("a", 1), ("b", 2), ("c", 3), ("d", 4), ("e", 5));
+        let summary = summarize_diff(&old, &new, SummaryOptions { max_entries: 2, group_by_kind: false, ..SummaryOptions::default() });
+
+        assert!(summary.total == 5);
+        assert!(summary.overflow == 3);
+        assert!(summary.groups.len() == 1);
+        assert!(summary.groups[0].label == "2 differences");
+        assert!(summary.groups[0].entries.iter().map(|entry| entry.address.clone()).collect::<Vec<_>>() == vec!["a".to_tree_address().to_string(), "b".to_tree_address().to_string()]);
+        assert!(summary.to_string().ends_with("(3 more differences)\n"));
+    }
+
+    #[test]
+    fn summary_round_trips_through_a_tree() {
+        let old     = tree!("root", ("a", 1), tree!("b", ("c", 2)));
+        let new     = tree!("root", ("a", "one"), ("d", true));
+        let summary = summarize_diff(&old, &new, SummaryOptions::default());
+        let decoded = DiffSummary::new_from_tree(&summary.to_tree_node()).unwrap();
+
+        assert!(decoded == summary);
+    }
+}
@@ -16,6 +16,7 @@
 
 use std::result::*;
 use std::rc::*;
+use std::collections::HashMap;
 
 use rustc_serialize::*;
 
@@ -27,21 +28,32 @@ use super::values::*;
 /// Encoder that will write to the specified tree node 
 ///
 struct TreeNodeEncoder {
-    tag:    String,
-    value:  TreeValue,
-    child:  Option<TreeRef>
+    tag:        String,
+    value:      TreeValue,
+    children:   Vec<TreeNodeEncoder>,
+
+    /// The tag to use for the next map value (set by `emit_map_elt_key`)
+    map_key:    Option<String>
 }
 
 impl TreeNodeEncoder {
     fn new() -> TreeNodeEncoder {
         TreeNodeEncoder { 
-            tag:    "".to_string(), 
-            value:  TreeValue::Nothing,
-            child:  None }
+            tag:        "".to_string(), 
+            value:      TreeValue::Nothing,
+            children:   vec![],
+            map_key:    None }
     }
 
     fn to_basic_tree_node_with_sibling(&self, new_sibling: Option<TreeRef>) -> BasicTree {
-        let new_node = BasicTree::new(&*self.tag, self.value.to_owned(), self.child.to_owned(), new_sibling);
+        // Build the children from last to first so each one can be given its sibling
+        let mut child: Option<TreeRef> = None;
+
+        for child_encoder in self.children.iter().rev() {
+            child = Some(Rc::new(child_encoder.to_basic_tree_node_with_sibling(child)));
+        }
+
+        let new_node = BasicTree::new(&*self.tag, self.value.to_owned(), child, new_sibling);
 
         new_node
     }
@@ -49,7 +61,10 @@ impl TreeNodeEncoder {
 
 #[derive(Debug)]
 pub enum TreeNodeCodingError {
-    UnsupportedType
+    UnsupportedType,
+
+    /// A map value was encoded without a key to use as its tag
+    MissingMapKey
 }
 
 #[allow(unused_variables)]          // Unused function parameters are quite common due to the way this trait is designed
@@ -92,12 +107,12 @@ impl Encoder for TreeNodeEncoder {
     }
 
     fn emit_str(&mut self, v: &str) -> Result<(), Self::Error> {
-        self.value = TreeValue::String(v.to_string());
+        self.value = v.to_tree_value();
         Ok(())
     }
 
     fn emit_struct<F>(&mut self, name: &str, len: usize, f: F) -> Result<(), Self::Error> where F: FnOnce(&mut Self) -> Result<(), Self::Error> {
-        self.value = TreeValue::String(name.to_string());
+        self.value = name.to_tree_value();
 
         f(self)
     }
@@ -114,11 +129,8 @@ impl Encoder for TreeNodeEncoder {
             return encoding_result;
         }
 
-        // Replace the child node with the node generated for the new encoder
-        let new_node = node_encoder.to_basic_tree_node_with_sibling(self.child.to_owned());
-
-        // Save the node we just created and update the tree
-        self.child = Some(Rc::new(new_node));
+        // Fields are added in the order they're generated
+        self.children.push(node_encoder);
 
         Ok(())
     }
@@ -176,11 +188,28 @@ impl Encoder for TreeNodeEncoder {
     }
 
     fn emit_tuple<F>(&mut self, len: usize, f: F) -> Result<(), Self::Error> where F: FnOnce(&mut Self) -> Result<(), Self::Error> {
-        Err(TreeNodeCodingError::UnsupportedType)
+        // Only (tag, value) pairs are supported: these become a node with the tag and the value
+        if len != 2 {
+            return Err(TreeNodeCodingError::UnsupportedType);
+        }
+
+        f(self)
     }
 
     fn emit_tuple_arg<F>(&mut self, idx: usize, f: F) -> Result<(), Self::Error> where F: FnOnce(&mut Self) -> Result<(), Self::Error> {
-        Err(TreeNodeCodingError::UnsupportedType)
+        if idx == 0 {
+            // The first argument is the tag, which must be a string
+            let mut tag_encoder = TreeNodeEncoder::new();
+            f(&mut tag_encoder)?;
+
+            match tag_encoder.value {
+                TreeValue::String(tag)  => { self.tag = tag.to_string(); Ok(()) },
+                _                       => Err(TreeNodeCodingError::UnsupportedType)
+            }
+        } else {
+            // The second argument is the content of this node
+            f(self)
+        }
     }
 
     fn emit_tuple_struct<F>(&mut self, name: &str, len: usize, f: F) -> Result<(), Self::Error> where F: FnOnce(&mut Self) -> Result<(), Self::Error> {
@@ -204,23 +233,48 @@ impl Encoder for TreeNodeEncoder {
     }
 
     fn emit_seq<F>(&mut self, len: usize, f: F) -> Result<(), Self::Error> where F: FnOnce(&mut Self) -> Result<(), Self::Error> {
-        Err(TreeNodeCodingError::UnsupportedType)
+        // Sequences are encoded as a set of child nodes (a sequence of (tag, value) pairs is encoded like a map)
+        f(self)
     }
 
     fn emit_seq_elt<F>(&mut self, idx: usize, f: F) -> Result<(), Self::Error> where F: FnOnce(&mut Self) -> Result<(), Self::Error> {
-        Err(TreeNodeCodingError::UnsupportedType)
+        let mut node_encoder = TreeNodeEncoder::new();
+        f(&mut node_encoder)?;
+
+        // Elements are added in the order they're generated
+        self.children.push(node_encoder);
+
+        Ok(())
     }
 
     fn emit_map<F>(&mut self, len: usize, f: F) -> Result<(), Self::Error> where F: FnOnce(&mut Self) -> Result<(), Self::Error> {
-        Err(TreeNodeCodingError::UnsupportedType)
+        // Maps are encoded as a set of child nodes, where the key is the tag and the value is the content of the node
+        f(self)
     }
 
     fn emit_map_elt_key<F>(&mut self, idx: usize, f: F) -> Result<(), Self::Error> where F: FnOnce(&mut Self) -> Result<(), Self::Error> {
-        Err(TreeNodeCodingError::UnsupportedType)
+        // Encode the key on its own so we can read its value
+        let mut key_encoder = TreeNodeEncoder::new();
+        f(&mut key_encoder)?;
+
+        // Only string keys can be used as tags
+        match key_encoder.value {
+            TreeValue::String(key)  => { self.map_key = Some(key.to_string()); Ok(()) },
+            _                       => Err(TreeNodeCodingError::UnsupportedType)
+        }
     }
 
     fn emit_map_elt_val<F>(&mut self, idx: usize, f: F) -> Result<(), Self::Error> where F: FnOnce(&mut Self) -> Result<(), Self::Error> {
-        Err(TreeNodeCodingError::UnsupportedType)
+        // Encode the value into a new node
+        let mut node_encoder = TreeNodeEncoder::new();
+        f(&mut node_encoder)?;
+
+        node_encoder.tag = self.map_key.take().ok_or(TreeNodeCodingError::MissingMapKey)?;
+
+        // Map values are added in the order they're generated
+        self.children.push(node_encoder);
+
+        Ok(())
     }
 }
 
@@ -254,6 +308,8 @@ impl EncodeToTreeNode for String {}
 impl EncodeToTreeNode for i32 {}
 impl EncodeToTreeNode for f64 {}
 impl EncodeToTreeNode for Vec<u8> {}
+impl<V: Encodable + Decodable> EncodeToTreeNode for HashMap<String, V> {}
+impl<V: Encodable + Decodable> EncodeToTreeNode for Vec<(String, V)> {}
 
 impl<T: Encodable + EncodeToTreeNode> ToTreeNode for T {
     ///
@@ -266,25 +322,57 @@ impl<T: Encodable + EncodeToTreeNode> ToTreeNode for T {
 
 #[cfg(test)]
 mod serialize_tests {
+    use rustc_serialize::*;
+
     use super::super::super::tree::*;
+    use super::*;
 
-    #[derive(RustcEncodable, RustcDecodable)]
-    struct Test {
-        field1: i32,
-        field2: String,
-        field3: bool
+    tree_struct! {
+        struct Test {
+            field1: i32,
+            field2: String,
+            field3: bool
+        }
     }
 
-    // One day this ought to be possible via #[derive]
-    impl EncodeToTreeNode for Test { }
-
     #[test]
     fn encode_struct() {
         let test = Test { field1: 32, field2: "Hi".to_string(), field3: true };
         let encoded = test.to_tree_node();
 
         assert!(match *encoded.get_child_at("field1").get_value() { TreeValue::Int(ref x) => *x == 32, _ => false });
-        assert!(match *encoded.get_child_at("field2").get_value() { TreeValue::String(ref x) => *x == "Hi", _ => false });
+        assert!(match *encoded.get_child_at("field2").get_value() { TreeValue::String(ref x) => &**x == "Hi", _ => false });
         assert!(match *encoded.get_child_at("field3").get_value() { TreeValue::Bool(ref x) => *x == true, _ => false });
     }
+
+    #[test]
+    fn struct_fields_are_encoded_in_order() {
+        let test    = Test { field1: 32, field2: "Hi".to_string(), field3: true };
+        let encoded = test.to_tree_node();
+        let tags    = encoded.iter_children().map(|child| child.get_tag().to_string()).collect::<Vec<String>>();
+
+        assert!(tags == vec!["field1", "field2", "field3"]);
+    }
+
+    #[test]
+    fn encode_pairs_as_tagged_children() {
+        let pairs   = vec![("second".to_string(), 2), ("first".to_string(), 1)];
+        let encoded = pairs.to_tree_node();
+
+        assert!(encoded.get_child_at(0).get_tag() == "second");
+        assert!(encoded.get_child_at(1).get_value().to_int(0) == 1);
+    }
+
+    struct KeylessMap;
+
+    impl Encodable for KeylessMap {
+        fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
+            s.emit_map(1, |s| s.emit_map_elt_val(0, |s| s.emit_i32(1)))
+        }
+    }
+
+    #[test]
+    fn map_value_without_key_is_an_error() {
+        assert!(matches!(encode(&KeylessMap), Err(TreeNodeCodingError::MissingMapKey)));
+    }
 }
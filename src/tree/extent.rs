@@ -39,6 +39,20 @@ impl TreeExtent {
     ///
     /// Returns true if this extent will cover the specified address, which is relative to where the extent starts
     ///
+    #[cfg_attr(feature = "doctest_support", doc = "```")]
+    #[cfg_attr(not(feature = "doctest_support"), doc = "```ignore")]
+    /// # use tametree::prelude::*;
+    /// # use tametree::doctest_support::*;
+    /// let child = ("third", ()).to_tree_address();
+    ///
+    /// assert!(TreeExtent::ThisNode.covers(&TreeAddress::Here));
+    /// assert!(!TreeExtent::ThisNode.covers(&child));
+    /// assert!(TreeExtent::Children.covers(&child));
+    /// assert!(!TreeExtent::Children.covers(&tagged_address()));
+    /// assert!(!TreeExtent::Children.covers(&TreeAddress::Here));
+    /// assert!(TreeExtent::SubTree.covers(&tagged_address()));
+    /// ```
+    ///
     pub fn covers(&self, address: &TreeAddress) -> bool {
         match *self {
             TreeExtent::ThisNode => {
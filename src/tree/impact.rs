@@ -0,0 +1,583 @@
+//
+//   Copyright 2016 Andrew Hunter
+//
+//   Licensed under the Apache License, Version 2.0 (the "License");
+//   you may not use this file except in compliance with the License.
+//   You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+//   Unless required by applicable law or agreed to in writing, software
+//   distributed under the License is distributed on an "AS IS" BASIS,
+//   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//   See the License for the specific language governing permissions and
+//   limitations under the License.
+//
+
+//!
+//! # Change impact
+//!
+//! `TreeChange::impact()` works out what applying a change to a tree would do without building the new tree. It
+//! follows the change's address through the tree in the same way as `apply()` (tags match the first child with
+//! that tag, and indexes past the last child create empty padding nodes) and inspects the replacement without
+//! copying it.
+//!
+//! Applying a change can have effects beyond the node at its address: removing a node, or replacing it with a
+//! node that has siblings of its own, moves the siblings that follow it to new indexes. A change that replaces the
+//! following siblings (as legacy `Child` changes do) removes them instead. Padding nodes are created when a change
+//! is addressed beyond the last child of a node, even if the rest of the address can't be followed (in which case
+//! nothing else is changed).
+//!
+//! `impact_with_limit()` stops working out the impact of a change as soon as it would exceed an `ApplyBudget`, so
+//! the work it does on the address and the replacement is no more than the budget allows. This is what
+//! `check_budget()` uses.
+//!
+
+use super::treenode::*;
+use super::address::*;
+use super::change::*;
+use super::budget::*;
+
+///
+/// Describes the effect that applying a change would have on a tree
+///
+#[derive(Clone, PartialEq)]
+pub struct ChangeImpact {
+    /// The (indexed) addresses of the nodes that will be replaced, removed, created or have their value changed.
+    /// Padding nodes are not included.
+    pub touched: Vec<TreeAddress>,
+
+    /// The number of empty padding nodes that will be created
+    pub creates_padding: usize,
+
+    /// The number of nodes that will be removed from the tree (including nodes that are replaced)
+    pub removes_subtree_nodes: usize,
+
+    /// The (indexed) address of the first node that will be moved to a different index, if any
+    pub shifts_siblings_from: Option<TreeAddress>,
+
+    /// The most nodes that applying the change can create or copy: every child passed through on the way to the
+    /// changed node is copied along with the siblings before it, and so are the top-level nodes of the replacement
+    pub new_nodes: usize,
+
+    /// The depth of the deepest node that the change addresses or creates
+    pub depth: usize,
+
+    /// The number of nodes in the replacement (the new node, its siblings and all of their descendants)
+    pub replacement_nodes: usize
+}
+
+///
+/// Converts a list of child indexes into an address
+///
+fn address_from_indexes(indexes: &[usize]) -> TreeAddress {
+    indexes.iter().rev().fold(TreeAddress::Here, |address, index| TreeAddress::ChildAtIndex(*index, Box::new(address)))
+}
+
+///
+/// Counts the nodes in a subtree (a node and its descendants, but not its siblings)
+///
+fn subtree_size(node: &TreeRef) -> usize {
+    let mut count = 1;
+    let mut stack = node.get_child_ref().into_iter().collect::<Vec<_>>();
+
+    while let Some(next) = stack.pop() {
+        count += 1;
+
+        if let Some(sibling) = next.get_sibling_ref() {
+            stack.push(sibling);
+        }
+
+        if let Some(child) = next.get_child_ref() {
+            stack.push(child);
+        }
+    }
+
+    count
+}
+
+impl ChangeImpact {
+    ///
+    /// Counts some new nodes, returning an error if there are now too many
+    ///
+    fn add_new_nodes(&mut self, count: usize, budget: &ApplyBudget) -> Result<(), BudgetExceeded> {
+        self.new_nodes = self.new_nodes.saturating_add(count);
+
+        if self.new_nodes > budget.max_new_nodes {
+            Err(BudgetExceeded::TooManyNewNodes(budget.max_new_nodes))
+        } else {
+            Ok(())
+        }
+    }
+
+    ///
+    /// Records a node at a particular depth, returning an error if it's too deep
+    ///
+    fn add_depth(&mut self, depth: usize, budget: &ApplyBudget) -> Result<(), BudgetExceeded> {
+        self.depth = self.depth.max(depth);
+
+        if depth > budget.max_depth {
+            Err(BudgetExceeded::TooDeep(budget.max_depth))
+        } else {
+            Ok(())
+        }
+    }
+
+    ///
+    /// Counts the nodes in a replacement (the node, its siblings and all of their descendants), stopping as soon as
+    /// a limit is exceeded
+    ///
+    fn add_replacement(&mut self, replacement: &TreeRef, depth: usize, budget: &ApplyBudget) -> Result<(), BudgetExceeded> {
+        let mut stack = vec![(replacement.clone(), depth, true)];
+
+        while let Some((node, node_depth, is_top_level)) = stack.pop() {
+            self.replacement_nodes += 1;
+            if self.replacement_nodes > budget.max_replacement_nodes {
+                return Err(BudgetExceeded::ReplacementTooLarge(budget.max_replacement_nodes));
+            }
+
+            self.add_depth(node_depth, budget)?;
+
+            // The top-level siblings may be copied when the replacement is joined to the existing siblings
+            if is_top_level {
+                self.add_new_nodes(1, budget)?;
+            }
+
+            if let Some(sibling) = node.get_sibling_ref() {
+                stack.push((sibling, node_depth, is_top_level));
+            }
+
+            if let Some(child) = node.get_child_ref() {
+                stack.push((child, node_depth+1, false));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl TreeChange {
+    ///
+    /// Works out what applying this change to a tree would do, without applying it
+    ///
+    pub fn impact(&self, tree: &TreeRef) -> ChangeImpact {
+        match self.impact_with_limit(tree, &ApplyBudget::unlimited()) {
+            Ok(impact)  => impact,
+            Err(_)      => unreachable!("an unlimited budget can't be exceeded")
+        }
+    }
+
+    ///
+    /// Works out what applying this change to a tree would do, giving up as soon as it finds that applying it would
+    /// exceed a budget
+    ///
+    /// The nodes removed from the tree are only counted once the change is known to be within the budget.
+    ///
+    pub fn impact_with_limit(&self, tree: &TreeRef, budget: &ApplyBudget) -> Result<ChangeImpact, BudgetExceeded> {
+        let mut impact  = ChangeImpact { touched: vec![], creates_padding: 0, removes_subtree_nodes: 0, shifts_siblings_from: None, new_nodes: 0, depth: 0, replacement_nodes: 0 };
+        let mut indexes = vec![];
+        let mut current = Some(tree.clone());
+        let mut address = self.address();
+
+        // Whether or not every node that the address passes through exists (a missing node is left alone, along
+        // with everything beneath it, but the rest of the address still counts against the budget)
+        let mut followed = true;
+
+        // Follow the address to the node that will be replaced
+        loop {
+            let (child, next_address) = match *address {
+                TreeAddress::Here => break,
+
+                TreeAddress::ChildAtIndex(child_index, ref next_address) => {
+                    impact.add_new_nodes(child_index.saturating_add(1), budget)?;
+
+                    if current.is_none() {
+                        followed = false;
+                    }
+
+                    let mut child       = current.and_then(|node| node.get_child_ref());
+                    let mut position    = 0;
+                    while position < child_index {
+                        child = match child {
+                            Some(child) => child.get_sibling_ref(),
+                            None        => break
+                        };
+
+                        position += 1;
+                    }
+
+                    // Indexes past the last child are filled in with padding (position is now the number of children)
+                    if child.is_none() && followed {
+                        impact.creates_padding += child_index - position;
+                    }
+
+                    indexes.push(child_index);
+                    (child, next_address)
+                },
+
+                TreeAddress::ChildWithTag(ref child_tag, ref next_address) => {
+                    if current.is_none() {
+                        followed = false;
+                    }
+
+                    // Find the first child with a matching tag (or the position after the last child)
+                    let mut child       = current.and_then(|node| node.get_child_ref());
+                    let mut child_index = 0;
+                    loop {
+                        impact.add_new_nodes(1, budget)?;
+
+                        child = match child {
+                            Some(ref candidate) if candidate.get_tag() != child_tag => candidate.get_sibling_ref(),
+                            _                                                       => break
+                        };
+
+                        child_index += 1;
+                    }
+
+                    indexes.push(child_index);
+                    (child, next_address)
+                }
+            };
+
+            impact.add_depth(indexes.len(), budget)?;
+
+            current = child;
+            address = next_address;
+        }
+
+        // Count the work done by the replacement
+        match *self.replacement() {
+            TreeReplacement::Remove             => { },
+            TreeReplacement::NewValue(_, _)     => impact.add_new_nodes(1, budget)?,
+            TreeReplacement::NewNode(ref node)  => impact.add_replacement(node, indexes.len(), budget)?
+        }
+
+        if !followed {
+            return Ok(impact);
+        }
+
+        let target_address = address_from_indexes(&indexes);
+
+        // Work out the effect of the replacement on the target node
+        let following_sibling = current.as_ref().and_then(|target| target.get_sibling_ref());
+        let num_replaced      = current.as_ref().map(subtree_size).unwrap_or(0);
+
+        let num_inserted = match *self.replacement() {
+            TreeReplacement::Remove => {
+                if current.is_some() {
+                    impact.touched.push(target_address);
+                }
+
+                0
+            },
+
+            TreeReplacement::NewValue(_, _) => {
+                impact.touched.push(target_address);
+                return Ok(impact);
+            },
+
+            TreeReplacement::NewNode(ref new_node) => {
+                // The new node brings its own siblings along with it
+                let mut num_inserted    = 0;
+                let mut next_node       = Some(new_node.clone());
+
+                while let Some(node) = next_node {
+                    let mut inserted_indexes = indexes.clone();
+                    if let Some(last_index) = inserted_indexes.last_mut() {
+                        *last_index += num_inserted;
+                    }
+
+                    impact.touched.push(address_from_indexes(&inserted_indexes));
+
+                    num_inserted    += 1;
+                    next_node       = node.get_sibling_ref();
+                }
+
+                num_inserted
+            }
+        };
+
+        impact.removes_subtree_nodes = num_replaced;
+
+        // A change that replaces the following siblings removes them instead of moving them
+        if self.replaces_following() && !indexes.is_empty() {
+            let mut removed_indexes = indexes.clone();
+            let mut next_node       = following_sibling;
+
+            while let Some(node) = next_node {
+                if let Some(last_index) = removed_indexes.last_mut() {
+                    *last_index += 1;
+                }
+
+                let removed_address = address_from_indexes(&removed_indexes);
+                if !impact.touched.contains(&removed_address) {
+                    impact.touched.push(removed_address);
+                }
+
+                impact.removes_subtree_nodes += subtree_size(&node);
+                next_node = node.get_sibling_ref();
+            }
+
+            return Ok(impact);
+        }
+
+        // The nodes following the target move if the number of nodes at its position changes
+        if following_sibling.is_some() && num_inserted != 1 && !indexes.is_empty() {
+            let mut following_indexes = indexes.clone();
+            if let Some(last_index) = following_indexes.last_mut() {
+                *last_index += 1;
+            }
+
+            impact.shifts_siblings_from = Some(address_from_indexes(&following_indexes));
+        }
+
+        Ok(impact)
+    }
+}
+
+#[cfg(test)]
+mod impact_tests {
+    use super::super::super::tree::*;
+    use super::super::basictree::nodes_created;
+
+    fn count_nodes(tree: &TreeRef) -> usize {
+        1 + tree.iter_children().map(|child| count_nodes(&child)).sum::<usize>()
+    }
+
+    fn replacement_size(tree: &TreeRef, change: &TreeChange, impact: &ChangeImpact) -> usize {
+        if impact.touched.is_empty() {
+            return 0;
+        }
+
+        match *change.replacement() {
+            TreeReplacement::Remove             => 0,
+            TreeReplacement::NewValue(_, _)     => if tree.get_child_ref_at(impact.touched[0].clone()).is_none() { 1 } else { 0 },
+            TreeReplacement::NewNode(ref node)  => {
+                let mut size = 0;
+                let mut next = Some(node.clone());
+                while let Some(node) = next {
+                    size += count_nodes(&node);
+                    next = node.get_sibling_ref();
+                }
+                size
+            }
+        }
+    }
+
+    ///
+    /// Checks an impact against the result of actually applying the change
+    ///
+    fn check_impact(tree: &TreeRef, change: &TreeChange) -> ChangeImpact {
+        let impact  = change.impact(tree);
+        let after   = change.apply(tree);
+
+        // Nodes are only added or removed where the impact says they are
+        let expected_count = count_nodes(tree) + impact.creates_padding + replacement_size(tree, change, &impact) - impact.removes_subtree_nodes;
+        assert!(count_nodes(&after) == expected_count);
+
+        // Every touched node exists after the change (unless it was removed, along with any following siblings)
+        let num_inserted = match *change.replacement() {
+            TreeReplacement::Remove             => 0,
+            TreeReplacement::NewValue(_, _)     => impact.touched.len(),
+            TreeReplacement::NewNode(ref node)  => {
+                let mut count   = 0;
+                let mut next    = Some(node.clone());
+                while let Some(node) = next {
+                    count += 1;
+                    next = node.get_sibling_ref();
+                }
+                count
+            }
+        };
+
+        for address in impact.touched.iter().take(num_inserted) {
+            assert!(after.get_child_ref_at(address.clone()).is_some());
+        }
+
+        // The first shifted sibling is found at a different index afterwards
+        if let Some(ref shifted) = impact.shifts_siblings_from {
+            let before_node = tree.get_child_ref_at(shifted.clone()).unwrap();
+            let shift       = if let TreeReplacement::Remove = *change.replacement() { -1 } else { impact.touched.len() as isize - 1 };
+            let mut indexes = vec![];
+            let mut address = shifted;
+            while let TreeAddress::ChildAtIndex(index, ref next) = *address {
+                indexes.push(index);
+                address = next;
+            }
+
+            let last                = indexes.len()-1;
+            indexes[last]           = (indexes[last] as isize + shift) as usize;
+            let moved_address       = indexes.iter().rev().fold(TreeAddress::Here, |address, index| TreeAddress::ChildAtIndex(*index, Box::new(address)));
+            let after_node          = after.get_child_ref_at(moved_address).unwrap();
+
+            assert!(before_node.get_tag() == after_node.get_tag());
+            assert!(before_node.get_value() == after_node.get_value());
+        }
+
+        impact
+    }
+
+    #[test]
+    fn replace_node() {
+        let tree    = tree!("root", ("a", 1), tree!("b", ("c", 2), ("d", 3)), ("e", 4));
+        let impact  = check_impact(&tree, &TreeChange::new(&"b", &("b", 5)));
+
+        assert!(impact.touched == vec![1.to_tree_address()]);
+        assert!(impact.removes_subtree_nodes == 3);
+        assert!(impact.creates_padding == 0);
+        assert!(impact.shifts_siblings_from.is_none());
+    }
+
+    #[test]
+    fn replace_with_several_siblings() {
+        let tree        = tree!("root", ("a", 1), ("b", 2), ("c", 3));
+        let replacement = ("x", 1).to_tree_node().with_sibling_node(Some(&("y", 2).to_tree_node()));
+        let impact      = check_impact(&tree, &TreeChange::new(&0, &replacement));
+
+        assert!(impact.touched == vec![0.to_tree_address(), 1.to_tree_address()]);
+        assert!(impact.shifts_siblings_from == Some(1.to_tree_address()));
+    }
+
+    #[test]
+    fn remove_node() {
+        let tree    = tree!("root", ("a", 1), tree!("b", ("c", 2)), ("d", 3));
+        let impact  = check_impact(&tree, &TreeChange::new(&"b", &()));
+
+        assert!(impact.touched == vec![1.to_tree_address()]);
+        assert!(impact.removes_subtree_nodes == 2);
+        assert!(impact.shifts_siblings_from == Some(2.to_tree_address()));
+    }
+
+    #[test]
+    fn remove_missing_node() {
+        let tree    = tree!("root", ("a", 1));
+        let impact  = check_impact(&tree, &TreeChange::new(&"missing", &()));
+
+        assert!(impact.touched.is_empty());
+        assert!(impact.removes_subtree_nodes == 0);
+    }
+
+    #[test]
+    fn change_value() {
+        let tree    = tree!("root", ("a", 1), tree!("b", ("c", 2)), ("d", 3));
+        let impact  = check_impact(&tree, &TreeChange::new(&"b", &TreeReplacement::NewValue("b".to_string(), TreeValue::Int(4))));
+
+        assert!(impact.touched == vec![1.to_tree_address()]);
+        assert!(impact.removes_subtree_nodes == 0);
+        assert!(impact.shifts_siblings_from.is_none());
+    }
+
+    #[test]
+    fn append_by_tag_and_index() {
+        let tree = tree!("root", ("a", 1), ("b", 2));
+
+        let by_tag = check_impact(&tree, &TreeChange::new(&"c", &("c", 3)));
+        assert!(by_tag.touched == vec![2.to_tree_address()]);
+        assert!(by_tag.creates_padding == 0);
+
+        let by_index = check_impact(&tree, &TreeChange::new(&2, &("c", 3)));
+        assert!(by_index.touched == vec![2.to_tree_address()]);
+        assert!(by_index.creates_padding == 0);
+    }
+
+    #[test]
+    fn out_of_range_index_counts_padding() {
+        let tree    = tree!("root", ("a", 1), ("b", 2));
+        let impact  = check_impact(&tree, &TreeChange::new(&7, &("far", 3)));
+
+        assert!(impact.touched == vec![7.to_tree_address()]);
+        assert!(impact.creates_padding == 5);
+
+        let empty_parent = check_impact(&tree, &TreeChange::new(&(0, 3), &("far", 3)));
+        assert!(empty_parent.creates_padding == 3);
+    }
+
+    #[test]
+    fn padding_is_created_when_rest_of_address_is_missing() {
+        let tree    = tree!("root", ("a", 1), ("b", 2));
+        let impact  = check_impact(&tree, &TreeChange::new(&(4, (1, 0)), &("lost", 3)));
+
+        assert!(impact.touched.is_empty());
+        assert!(impact.creates_padding == 2);
+    }
+
+    #[test]
+    fn replace_root() {
+        let tree    = tree!("root", ("a", 1), ("b", 2));
+        let impact  = check_impact(&tree, &TreeChange::new(&(), &tree!("new_root", ("c", 3))));
+
+        assert!(impact.touched == vec![TreeAddress::Here]);
+        assert!(impact.removes_subtree_nodes == 3);
+    }
+
+    #[test]
+    fn impact_does_not_create_nodes() {
+        let tree        = tree!("root", ("a", 1), tree!("b", ("c", 2), ("d", 3)), ("e", 4));
+        let changes     = vec![
+            TreeChange::new(&("b", "d"), &("d", 4)),
+            TreeChange::new(&(1, 0), &()),
+            TreeChange::new(&100, &"far"),
+            TreeChange::new(&"e", &TreeReplacement::NewValue("e".to_string(), TreeValue::Int(5)))
+        ];
+
+        for change in changes {
+            let before = nodes_created();
+            change.impact(&tree);
+            assert!(nodes_created() == before);
+
+            change.apply(&tree);
+            assert!(nodes_created() > before);
+        }
+    }
+
+    #[test]
+    fn replacing_the_following_siblings_removes_them() {
+        let tree        = tree!("root", tree!("list", ("a", 1), ("b", 2), tree!("c", ("d", 3))));
+        let replacement = ("x", 1).to_tree_node().with_sibling_node(Some(&("y", 2).to_tree_node()));
+        let impact      = check_impact(&tree, &TreeChange::new(&(0, 0), &replacement).with_replaces_following(true));
+
+        assert!(impact.touched == vec![(0, 0).to_tree_address(), (0, 1).to_tree_address(), (0, 2).to_tree_address()]);
+        assert!(impact.removes_subtree_nodes == 4);
+        assert!(impact.shifts_siblings_from.is_none());
+
+        let removed = check_impact(&tree, &TreeChange::new(&(0, 1), &()).with_replaces_following(true));
+
+        assert!(removed.touched == vec![(0, 1).to_tree_address(), (0, 2).to_tree_address()]);
+        assert!(removed.removes_subtree_nodes == 3);
+        assert!(removed.shifts_siblings_from.is_none());
+    }
+
+    #[test]
+    fn impact_counts_the_work_for_a_change() {
+        let tree    = tree!("root", ("a", 1), tree!("b", ("c", 2), ("d", 3)));
+        let impact  = check_impact(&tree, &TreeChange::new(&("b", 1), &tree!("new", tree!("child", "grandchild"))));
+
+        // Passes 'a' and 'b', then 'c' and 'd' (two copies per level), and adds one top-level node
+        assert!(impact.new_nodes == 5);
+        assert!(impact.depth == 4);
+        assert!(impact.replacement_nodes == 3);
+    }
+
+    #[test]
+    fn limited_impact_stops_at_the_limit() {
+        let tree    = tree!("root", ("a", 1));
+        let budget  = ApplyBudget { max_new_nodes: 100, max_depth: 10, max_replacement_nodes: 100 };
+
+        assert!(TreeChange::new(&usize::MAX, &"far").impact_with_limit(&tree, &budget).err() == Some(BudgetExceeded::TooManyNewNodes(100)));
+        assert!(TreeChange::new(&(0, (0, (0, 0))), &"deep").impact_with_limit(&tree, &ApplyBudget { max_depth: 3, .. budget }).err() == Some(BudgetExceeded::TooDeep(3)));
+
+        let within = TreeChange::new(&5, &"padded").impact_with_limit(&tree, &budget).unwrap();
+        assert!(within.creates_padding == 4);
+        assert!(within.new_nodes == 7);
+    }
+
+    #[test]
+    fn missing_nodes_still_count_against_the_limit() {
+        let tree    = tree!("root", ("a", 1));
+        let budget  = ApplyBudget { max_new_nodes: 10, max_depth: 10, max_replacement_nodes: 10 };
+        let change  = TreeChange::new(&("missing", 20), &"lost");
+
+        assert!(change.impact(&tree).touched.is_empty());
+        assert!(change.impact_with_limit(&tree, &budget).err() == Some(BudgetExceeded::TooManyNewNodes(10)));
+    }
+}
@@ -31,11 +31,35 @@ pub trait TreeNodeIteration {
     ///
     /// Creates an iterator for a particular extent of the tree
     ///
+    /// A subtree is visited depth-first, starting with this node. Its siblings are never included.
+    ///
+    #[cfg_attr(feature = "doctest_support", doc = "```")]
+    #[cfg_attr(not(feature = "doctest_support"), doc = "```ignore")]
+    /// # use tametree::prelude::*;
+    /// # use tametree::doctest_support::*;
+    /// let third = sample_tree().get_child_at("third");
+    /// let tags = |extent| third.iter_extent(extent).map(|node| node.get_tag().to_string()).collect::<Vec<_>>();
+    ///
+    /// assert!(tags(TreeExtent::ThisNode) == vec!["third"]);
+    /// assert!(tags(TreeExtent::Children) == vec!["child", "other"]);
+    /// assert!(tags(TreeExtent::SubTree) == vec!["third", "child", "other"]);
+    /// ```
+    ///
     fn iter_extent(&self, extent: TreeExtent) -> Box<TreeIterator>;
 
     ///
     /// Creates an iterator that covers the child nodes of this node
     ///
+    #[cfg_attr(feature = "doctest_support", doc = "```")]
+    #[cfg_attr(not(feature = "doctest_support"), doc = "```ignore")]
+    /// # use tametree::prelude::*;
+    /// # use tametree::doctest_support::*;
+    /// let tree = sample_tree();
+    ///
+    /// assert!(tree.iter_children().count() == 3);
+    /// assert!(tree.get_child_at("first").iter_children().next().is_none());
+    /// ```
+    ///
     fn iter_children(&self) -> Box<TreeIterator>;
 }
 
@@ -0,0 +1,367 @@
+//
+//   Copyright 2016 Andrew Hunter
+//
+//   Licensed under the Apache License, Version 2.0 (the "License");
+//   you may not use this file except in compliance with the License.
+//   You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+//   Unless required by applicable law or agreed to in writing, software
+//   distributed under the License is distributed on an "AS IS" BASIS,
+//   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//   See the License for the specific language governing permissions and
+//   limitations under the License.
+//
+
+//!
+//! # Keyed collections
+//!
+//! Trees often represent a collection as a run of children with the same tag, where each child has a child
+//! of its own that identifies it. For example, a list of users might look like this:
+//!
+//! ```text
+//! users
+//!   +- user
+//!   |    +- id: "alice"
+//!   |    +- name: "Alice"
+//!   +- user
+//!        +- id: "bob"
+//!        +- name: "Bob"
+//! ```
+//!
+//! A `KeyedVec<T>` decodes this shape into a list of items in tree order, along with an index that makes it
+//! possible to look up items by their key. It encodes back to the same repeated-child shape. The item type
+//! describes the tag of each item and how to find its key by implementing `KeyedCollection`. The key of an item
+//! is always read from the item itself, so it can't disagree with what's encoded in the tree.
+//!
+//! If several items have the same key, they are all kept in the list, but `get()` will return the first one.
+//! This matches how tagged addresses select the first child with a matching tag.
+//!
+
+use std::hash::Hash;
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::slice;
+
+use rustc_serialize::*;
+
+use super::encoder::*;
+
+///
+/// Trait implemented by types that can be stored in a `KeyedVec`
+///
+pub trait KeyedCollection {
+    /// The type of the key that identifies an item
+    type Key: Eq + Hash + Clone;
+
+    ///
+    /// The tag used for each item in the collection
+    ///
+    fn item_tag() -> &'static str;
+
+    ///
+    /// The key of this item (which should be the value of the child that identifies it)
+    ///
+    fn key(&self) -> Self::Key;
+}
+
+///
+/// A list of items stored as a run of same-tagged children, which can be looked up by key
+///
+pub struct KeyedVec<T: KeyedCollection> {
+    /// The items in this collection, in tree order
+    items: Vec<T>,
+
+    /// Maps keys to the index of the first item with that key
+    index: HashMap<T::Key, usize>
+}
+
+impl<T: KeyedCollection> KeyedVec<T> {
+    ///
+    /// Creates a new, empty, keyed collection
+    ///
+    pub fn new() -> KeyedVec<T> {
+        KeyedVec { items: vec![], index: HashMap::new() }
+    }
+
+    ///
+    /// Adds an item to the end of this collection
+    ///
+    pub fn push(&mut self, item: T) {
+        let item_index = self.items.len();
+
+        self.index.entry(item.key()).or_insert(item_index);
+        self.items.push(item);
+    }
+
+    ///
+    /// Retrieves the first item with the specified key
+    ///
+    pub fn get<TKey>(&self, key: &TKey) -> Option<&T> where T::Key: Borrow<TKey>, TKey: ?Sized + Hash + Eq {
+        self.index.get(key).map(|item_index| &self.items[*item_index])
+    }
+
+    ///
+    /// Returns true if there's an item with the specified key
+    ///
+    pub fn contains_key<TKey>(&self, key: &TKey) -> bool where T::Key: Borrow<TKey>, TKey: ?Sized + Hash + Eq {
+        self.index.contains_key(key)
+    }
+
+    ///
+    /// Iterates over the items in this collection in tree order
+    ///
+    pub fn iter<'a>(&'a self) -> slice::Iter<'a, T> {
+        self.items.iter()
+    }
+
+    ///
+    /// The number of items in this collection
+    ///
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    ///
+    /// True if there are no items in this collection
+    ///
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    ///
+    /// Converts this collection into a list of items in tree order
+    ///
+    pub fn into_vec(self) -> Vec<T> {
+        self.items
+    }
+}
+
+impl<T: KeyedCollection> Default for KeyedVec<T> {
+    fn default() -> KeyedVec<T> {
+        KeyedVec::new()
+    }
+}
+
+impl<'a, T: KeyedCollection> IntoIterator for &'a KeyedVec<T> {
+    type Item       = &'a T;
+    type IntoIter   = slice::Iter<'a, T>;
+
+    fn into_iter(self) -> slice::Iter<'a, T> {
+        self.items.iter()
+    }
+}
+
+impl<T: KeyedCollection + Encodable> Encodable for KeyedVec<T> {
+    fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
+        // Encoded as a map where every key is the item tag, which produces a run of same-tagged children
+        s.emit_map(self.items.len(), |s| {
+            for (item_index, item) in self.items.iter().enumerate() {
+                s.emit_map_elt_key(item_index, |s| s.emit_str(T::item_tag()))?;
+                s.emit_map_elt_val(item_index, |s| item.encode(s))?;
+            }
+
+            Ok(())
+        })
+    }
+}
+
+impl<T: KeyedCollection + Decodable> Decodable for KeyedVec<T> {
+    fn decode<D: Decoder>(d: &mut D) -> Result<KeyedVec<T>, D::Error> {
+        d.read_map(|d, len| {
+            let mut result = KeyedVec::new();
+
+            for item_index in 0..len {
+                // Children with a different tag are not part of the collection
+                let tag: String = d.read_map_elt_key(item_index, |d| Decodable::decode(d))?;
+                if tag != T::item_tag() {
+                    continue;
+                }
+
+                let item = d.read_map_elt_val(item_index, |d| T::decode(d))?;
+                result.push(item);
+            }
+
+            Ok(result)
+        })
+    }
+}
+
+impl<T: KeyedCollection + Encodable + Decodable> EncodeToTreeNode for KeyedVec<T> {}
+
+#[cfg(test)]
+mod keyedvec_tests {
+    use std::collections::HashMap;
+
+    use rustc_serialize::*;
+
+    use super::super::super::tree::*;
+
+    struct User {
+        id: String,
+        name: String
+    }
+
+    impl Encodable for User {
+        fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
+            s.emit_struct("User", 2, |s| {
+                s.emit_struct_field("id", 0, |s| self.id.encode(s))?;
+                s.emit_struct_field("name", 1, |s| self.name.encode(s))
+            })
+        }
+    }
+
+    impl Decodable for User {
+        fn decode<D: Decoder>(d: &mut D) -> Result<User, D::Error> {
+            d.read_struct("User", 2, |d| {
+                Ok(User {
+                    id:     d.read_struct_field("id", 0, |d| Decodable::decode(d))?,
+                    name:   d.read_struct_field("name", 1, |d| Decodable::decode(d))?
+                })
+            })
+        }
+    }
+
+    impl EncodeToTreeNode for User { }
+
+    impl KeyedCollection for User {
+        type Key = String;
+
+        fn item_tag() -> &'static str { "user" }
+        fn key(&self) -> String { self.id.clone() }
+    }
+
+    struct Directory {
+        users: KeyedVec<User>,
+        groups: HashMap<String, User>
+    }
+
+    impl Encodable for Directory {
+        fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
+            s.emit_struct("Directory", 2, |s| {
+                s.emit_struct_field("users", 0, |s| self.users.encode(s))?;
+                s.emit_struct_field("groups", 1, |s| self.groups.encode(s))
+            })
+        }
+    }
+
+    impl Decodable for Directory {
+        fn decode<D: Decoder>(d: &mut D) -> Result<Directory, D::Error> {
+            d.read_struct("Directory", 2, |d| {
+                Ok(Directory {
+                    users:  d.read_struct_field("users", 0, |d| Decodable::decode(d))?,
+                    groups: d.read_struct_field("groups", 1, |d| Decodable::decode(d))?
+                })
+            })
+        }
+    }
+
+    impl EncodeToTreeNode for Directory { }
+
+    fn user(id: &str, name: &str) -> TreeRef {
+        tree!("user", ("id", id), ("name", name))
+    }
+
+    #[test]
+    fn decode_keyed_vec() {
+        let users   = tree!("users", user("alice", "Alice"), user("bob", "Bob"), user("carol", "Carol"));
+        let decoded = KeyedVec::<User>::new_from_tree(&users).unwrap();
+
+        assert!(decoded.len() == 3);
+        assert!(decoded.iter().map(|user| user.id.clone()).collect::<Vec<String>>() == vec!["alice", "bob", "carol"]);
+    }
+
+    #[test]
+    fn lookup_by_key() {
+        let users   = tree!("users", user("alice", "Alice"), user("bob", "Bob"));
+        let decoded = KeyedVec::<User>::new_from_tree(&users).unwrap();
+
+        assert!(decoded.get("bob").unwrap().name == "Bob");
+        assert!(decoded.get("alice").unwrap().name == "Alice");
+        assert!(decoded.get("carol").is_none());
+        assert!(decoded.contains_key("alice"));
+    }
+
+    #[test]
+    fn round_trip_preserves_order_and_keys() {
+        let users   = tree!("users", user("zed", "Zed"), user("alice", "Alice"), user("mike", "Mike"));
+        let decoded = KeyedVec::<User>::new_from_tree(&users).unwrap();
+        let encoded = decoded.to_tree_node();
+
+        assert!(encoded.iter_children().all(|child| child.get_tag() == "user"));
+        assert!(encoded.iter_children().map(|child| child.get_child_at("id").get_value().to_str("").to_string()).collect::<Vec<String>>() == vec!["zed", "alice", "mike"]);
+
+        let decoded_again = KeyedVec::<User>::new_from_tree(&encoded).unwrap();
+        assert!(decoded_again.iter().map(|user| user.id.clone()).collect::<Vec<String>>() == vec!["zed", "alice", "mike"]);
+        assert!(decoded_again.get("mike").unwrap().name == "Mike");
+    }
+
+    #[test]
+    fn duplicate_keys_keep_all_items_and_find_first() {
+        let users   = tree!("users", user("alice", "First"), user("bob", "Bob"), user("alice", "Second"));
+        let decoded = KeyedVec::<User>::new_from_tree(&users).unwrap();
+
+        assert!(decoded.len() == 3);
+        assert!(decoded.get("alice").unwrap().name == "First");
+        assert!(decoded.iter().last().unwrap().name == "Second");
+    }
+
+    #[test]
+    fn other_tags_are_skipped() {
+        let users   = tree!("users", user("alice", "Alice"), ("comment", "not a user"), user("bob", "Bob"));
+        let decoded = KeyedVec::<User>::new_from_tree(&users).unwrap();
+
+        assert!(decoded.len() == 2);
+        assert!(decoded.get("bob").is_some());
+    }
+
+    #[test]
+    fn pushed_items_are_indexed_by_their_own_key() {
+        let mut users = KeyedVec::new();
+        users.push(User { id: "alice".to_string(), name: "Alice".to_string() });
+        users.push(User { id: "bob".to_string(), name: "Bob".to_string() });
+
+        let decoded = KeyedVec::<User>::new_from_tree(&users.to_tree_node()).unwrap();
+
+        assert!(users.get("bob").unwrap().name == "Bob");
+        assert!(decoded.iter().map(|user| user.id.clone()).collect::<Vec<String>>() == vec!["alice", "bob"]);
+        assert!(decoded.get("bob").unwrap().name == "Bob");
+    }
+
+    #[test]
+    fn missing_key_is_an_error() {
+        let users   = tree!("users", tree!("user", ("name", "Nobody")));
+        let decoded = KeyedVec::<User>::new_from_tree(&users);
+
+        assert!(decoded.is_err());
+    }
+
+    #[test]
+    fn decode_struct_with_keyed_vec_and_hash_map() {
+        let directory = tree!("directory",
+            tree!("users", user("alice", "Alice"), user("bob", "Bob")),
+            tree!("groups", tree!("admin", ("id", "alice"), ("name", "Alice")), tree!("staff", ("id", "bob"), ("name", "Bob"))));
+        let decoded = Directory::new_from_tree(&directory).unwrap();
+
+        assert!(decoded.users.get("alice").unwrap().name == "Alice");
+        assert!(decoded.groups.len() == 2);
+        assert!(decoded.groups.get("admin").unwrap().id == "alice");
+        assert!(decoded.groups.get("staff").unwrap().id == "bob");
+    }
+
+    #[test]
+    fn encode_hash_map_as_tagged_children() {
+        let mut groups = HashMap::new();
+        groups.insert("admin".to_string(), User { id: "alice".to_string(), name: "Alice".to_string() });
+
+        let mut users = KeyedVec::new();
+        users.push(User { id: "bob".to_string(), name: "Bob".to_string() });
+
+        let directory   = Directory { users, groups };
+        let encoded     = directory.to_tree_node();
+
+        assert!(encoded.get_child_ref_at(("groups", ("admin", "id")).to_tree_address()).unwrap().get_value().to_str("") == "alice");
+        assert!(encoded.get_child_ref_at(("users", ("user", "name")).to_tree_address()).unwrap().get_value().to_str("") == "Bob");
+    }
+}
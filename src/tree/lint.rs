@@ -0,0 +1,405 @@
+//
+//   Copyright 2016 Andrew Hunter
+//
+//   Licensed under the Apache License, Version 2.0 (the "License");
+//   you may not use this file except in compliance with the License.
+//   You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+//   Unless required by applicable law or agreed to in writing, software
+//   distributed under the License is distributed on an "AS IS" BASIS,
+//   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//   See the License for the specific language governing permissions and
+//   limitations under the License.
+//
+
+//!
+//! # Tree linting
+//!
+//! Some tree structures are valid but are usually a sign of a bug: `lint_tree()` looks for these and reports
+//! them as a list of findings. The built-in rules are:
+//!
+//! * `DuplicateTag`: two children of the same node have the same tag. Only the first can be found using a
+//!   tagged address, or decoded as a struct field.
+//! * `MixedEmptyTags`: a node has both children with tags and children without. This usually means that a list
+//!   and a structure have been mixed together.
+//! * `NothingLeaf`: a node other than the root has no value and no children, so it carries no data other than its
+//!   tag.
+//! * `LongSiblingChain`: a node has more children than a threshold. Finding a child in a long list requires
+//!   walking through all of the siblings before it.
+//!
+//! Findings are reported in the order the nodes appear in the tree, with addresses that use child indexes, so
+//! the same tree always produces the same findings.
+//!
+//! `lint_tree_budgeted()` lints a large tree a slice at a time, returning a cursor to carry on from. Linting every
+//! slice in turn produces the same findings as `lint_tree()` if the tree doesn't change in between.
+//!
+
+use std::collections::HashMap;
+
+use super::treenode::*;
+use super::address::*;
+use super::values::*;
+use super::iterator::*;
+use super::cursor::*;
+
+///
+/// Identifies the rule that produced a finding
+///
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LintRule {
+    DuplicateTag,
+    MixedEmptyTags,
+    NothingLeaf,
+    LongSiblingChain
+}
+
+impl LintRule {
+    ///
+    /// A short identifier for this rule
+    ///
+    pub fn id(&self) -> &'static str {
+        match *self {
+            LintRule::DuplicateTag      => "duplicate-tag",
+            LintRule::MixedEmptyTags    => "mixed-empty-tags",
+            LintRule::NothingLeaf       => "nothing-leaf",
+            LintRule::LongSiblingChain  => "long-sibling-chain"
+        }
+    }
+
+    ///
+    /// The severity of the findings generated by this rule
+    ///
+    pub fn severity(&self) -> LintSeverity {
+        match *self {
+            LintRule::NothingLeaf   => LintSeverity::Info,
+            _                       => LintSeverity::Warning
+        }
+    }
+}
+
+///
+/// How serious a finding is
+///
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum LintSeverity {
+    /// Possibly intended, but worth checking
+    Info,
+
+    /// Very likely to be a mistake
+    Warning
+}
+
+///
+/// Something suspicious found in a tree
+///
+#[derive(Clone, PartialEq)]
+pub struct LintFinding {
+    /// The rule that produced this finding
+    pub rule: LintRule,
+
+    /// How serious this finding is
+    pub severity: LintSeverity,
+
+    /// The node that the finding is about
+    pub address: TreeAddress,
+
+    /// A description of the problem
+    pub message: String
+}
+
+///
+/// Chooses which rules are checked when linting a tree
+///
+#[derive(Clone, PartialEq, Debug)]
+pub struct LintRules {
+    pub duplicate_tags: bool,
+    pub mixed_empty_tags: bool,
+    pub nothing_leaves: bool,
+    pub long_sibling_chains: bool,
+
+    /// The largest number of children a node can have before `LongSiblingChain` is reported
+    pub max_siblings: usize
+}
+
+impl Default for LintRules {
+    ///
+    /// Enables every rule
+    ///
+    fn default() -> LintRules {
+        LintRules { duplicate_tags: true, mixed_empty_tags: true, nothing_leaves: true, long_sibling_chains: true, max_siblings: 1000 }
+    }
+}
+
+///
+/// The result of linting part of a tree
+///
+pub struct LintReport {
+    /// The findings, in tree order
+    pub findings: Vec<LintFinding>,
+
+    /// The number of nodes that were examined
+    pub nodes_examined: usize
+}
+
+///
+/// Collects findings while walking a tree
+///
+struct Linter<'a> {
+    rules: &'a LintRules,
+    findings: Vec<LintFinding>,
+    nodes_examined: usize
+}
+
+impl<'a> Linter<'a> {
+    fn report(&mut self, rule: LintRule, address: TreeAddress, message: String) {
+        self.findings.push(LintFinding { rule, severity: rule.severity(), address, message });
+    }
+
+    ///
+    /// Checks a node that isn't the root of the tree being linted
+    ///
+    fn check_node(&mut self, node: &TreeRef, address: &TreeAddress) {
+        if self.rules.nothing_leaves && node.get_child_ref().is_none() {
+            if let TreeValue::Nothing = *node.get_value() {
+                self.report(LintRule::NothingLeaf, address.clone(), format!("Node '{}' has no value and no children", node.get_tag()));
+            }
+        }
+    }
+
+    ///
+    /// Checks the rules that apply to the list of children of a node, and returns the children
+    ///
+    fn check_children(&mut self, node: &TreeRef, address: &TreeAddress) -> Vec<TreeRef> {
+        let children: Vec<TreeRef> = node.iter_children().collect();
+        self.nodes_examined += children.len();
+
+        if self.rules.long_sibling_chains && children.len() > self.rules.max_siblings {
+            self.report(LintRule::LongSiblingChain, address.clone(), format!("Node has {} children (more than {})", children.len(), self.rules.max_siblings));
+        }
+
+        if self.rules.mixed_empty_tags {
+            let num_empty = children.iter().filter(|child| child.get_tag().is_empty()).count();
+
+            if num_empty > 0 && num_empty < children.len() {
+                self.report(LintRule::MixedEmptyTags, address.clone(), format!("Node has {} children without tags and {} with tags", num_empty, children.len()-num_empty));
+            }
+        }
+
+        if self.rules.duplicate_tags {
+            let mut first_index = HashMap::new();
+
+            for (index, child) in children.iter().enumerate() {
+                let tag = child.get_tag();
+                if tag.is_empty() {
+                    continue;
+                }
+
+                if let Some(first) = first_index.get(tag) {
+                    self.report(LintRule::DuplicateTag, address.to_tree_address_then(index.to_tree_address()), format!("Tag '{}' is already used by child {}", tag, first));
+                } else {
+                    first_index.insert(tag.to_string(), index);
+                }
+            }
+        }
+
+        children
+    }
+
+    ///
+    /// Lints a node and everything beneath it
+    ///
+    fn lint_subtree(&mut self, root: &TreeRef, address: TreeAddress, is_root: bool) {
+        let mut stack = vec![(root.clone(), address, is_root)];
+
+        while let Some((node, address, is_root)) = stack.pop() {
+            if !is_root {
+                self.check_node(&node, &address);
+            }
+
+            let children = self.check_children(&node, &address);
+
+            // Push in reverse so the children are visited in order
+            for (index, child) in children.into_iter().enumerate().rev() {
+                stack.push((child, address.to_tree_address_then(index.to_tree_address()), false));
+            }
+        }
+    }
+}
+
+///
+/// Checks a tree for suspicious structures
+///
+pub fn lint_tree(tree: &TreeRef, rules: &LintRules) -> Vec<LintFinding> {
+    let mut linter = Linter { rules, findings: vec![], nodes_examined: 1 };
+    linter.lint_subtree(tree, TreeAddress::Here, true);
+
+    linter.findings
+}
+
+///
+/// Lints only the part of a tree that can be affected by a change to the specified address
+///
+/// This is the node at the address and everything beneath it, along with the list of children it belongs to.
+///
+pub fn lint_tree_at(tree: &TreeRef, address: &TreeAddress, rules: &LintRules) -> LintReport {
+    let mut linter = Linter { rules, findings: vec![], nodes_examined: 0 };
+
+    if let TreeAddress::Here = *address {
+        linter.nodes_examined = 1;
+        linter.lint_subtree(tree, TreeAddress::Here, true);
+    } else {
+        let parent_address = address.parent();
+
+        if let Some(parent) = tree.get_child_ref_at(parent_address.clone()) {
+            linter.check_children(&parent, &parent_address);
+        }
+
+        if let Some(node) = tree.get_child_ref_at(address.clone()) {
+            linter.check_node(&node, address);
+            linter.lint_subtree(&node, address.clone(), true);
+        }
+    }
+
+    LintReport { findings: linter.findings, nodes_examined: linter.nodes_examined }
+}
+
+///
+/// Lints up to `max_nodes` nodes of a tree, starting from a cursor returned by a previous call (or from the root
+/// if the cursor is `None`)
+///
+/// Returns the findings for the nodes examined and a cursor for the next slice, or `None` if the whole tree has
+/// been linted. The rules that apply to a list of children are checked when the parent is visited.
+///
+pub fn lint_tree_budgeted(tree: &TreeRef, rules: &LintRules, cursor: Option<IterCursor>, max_nodes: usize) -> (LintReport, Option<IterCursor>) {
+    let mut linter      = Linter { rules, findings: vec![], nodes_examined: 0 };
+    let mut visited     = 0;
+
+    let cursor = iterate_budgeted(tree, cursor, max_nodes, |address, node| {
+        visited += 1;
+
+        if let TreeAddress::Here = *address {
+        } else {
+            linter.check_node(node, address);
+        }
+
+        linter.check_children(node, address);
+    });
+
+    // The children counted by check_children are only examined when they're visited, which may be in a later slice
+    (LintReport { findings: linter.findings, nodes_examined: visited }, cursor)
+}
+
+#[cfg(test)]
+mod lint_tests {
+    use super::super::super::tree::*;
+
+    fn rules_found(findings: &[LintFinding]) -> Vec<LintRule> {
+        findings.iter().map(|finding| finding.rule).collect()
+    }
+
+    #[test]
+    fn clean_tree_has_no_findings() {
+        let tree = tree!("root", ("a", 1), tree!("b", ("c", "text"), ("d", 2.0)));
+
+        assert!(lint_tree(&tree, &LintRules::default()).is_empty());
+    }
+
+    #[test]
+    fn finds_duplicate_tags() {
+        let tree        = tree!("root", ("a", 1), tree!("b", ("c", 1), ("d", 2), ("c", 3)));
+        let findings    = lint_tree(&tree, &LintRules::default());
+
+        assert!(rules_found(&findings) == vec![LintRule::DuplicateTag]);
+        assert!(findings[0].address == (1, 2).to_tree_address());
+        assert!(findings[0].severity == LintSeverity::Warning);
+    }
+
+    #[test]
+    fn finds_mixed_empty_tags() {
+        let tree        = tree!("root", ("a", 1), tree!("list", ("", 1), ("", 2), ("named", 3)));
+        let findings    = lint_tree(&tree, &LintRules::default());
+
+        assert!(rules_found(&findings) == vec![LintRule::MixedEmptyTags]);
+        assert!(findings[0].address == 1.to_tree_address());
+    }
+
+    #[test]
+    fn finds_nothing_leaves() {
+        let tree        = tree!("root", ("a", 1), tree!("b", ("c", 1), "forgotten"));
+        let findings    = lint_tree(&tree, &LintRules::default());
+
+        assert!(rules_found(&findings) == vec![LintRule::NothingLeaf]);
+        assert!(findings[0].address == (1, 1).to_tree_address());
+        assert!(findings[0].severity == LintSeverity::Info);
+        assert!(findings[0].rule.id() == "nothing-leaf");
+    }
+
+    #[test]
+    fn finds_long_sibling_chains() {
+        let children: Vec<TreeRef>  = (0..20).map(|index| (&*format!("item{}", index), index).to_tree_node()).collect();
+        let tree                    = tree!("root", ("a", 1), ("long", 0).to_tree_node().with_children(&children));
+        let rules                   = LintRules { max_siblings: 10, .. LintRules::default() };
+        let findings                = lint_tree(&tree, &rules);
+
+        assert!(rules_found(&findings) == vec![LintRule::LongSiblingChain]);
+        assert!(findings[0].address == 1.to_tree_address());
+        assert!(lint_tree(&tree, &LintRules::default()).is_empty());
+    }
+
+    #[test]
+    fn disabled_rules_are_not_reported() {
+        let tree    = tree!("root", ("a", 1), ("a", 2), "empty");
+        let rules   = LintRules { duplicate_tags: false, nothing_leaves: false, .. LintRules::default() };
+
+        assert!(rules_found(&lint_tree(&tree, &LintRules::default())) == vec![LintRule::DuplicateTag, LintRule::NothingLeaf]);
+        assert!(lint_tree(&tree, &rules).is_empty());
+    }
+
+    #[test]
+    fn findings_are_in_tree_order() {
+        let tree        = tree!("root", tree!("a", ("x", 1), ("x", 2)), "b", tree!("c", ("y", 1), ("y", 2)));
+        let findings    = lint_tree(&tree, &LintRules::default());
+        let addresses: Vec<String> = findings.iter().map(|finding| finding.address.to_string()).collect();
+
+        assert!(addresses == vec![(0, 1).to_tree_address().to_string(), 1.to_tree_address().to_string(), (2, 1).to_tree_address().to_string()]);
+        assert!(lint_tree(&tree, &LintRules::default()) == findings);
+    }
+
+    #[test]
+    fn lint_at_address_only_examines_subtree() {
+        let children: Vec<TreeRef>  = (0..100).map(|index| tree!("item", ("value", index))).collect();
+        let big                     = ("big", 0).to_tree_node().with_children(&children);
+        let tree                    = tree!("root", big, tree!("small", ("x", 1), ("x", 2)));
+
+        let report = lint_tree_at(&tree, &"small".to_tree_address(), &LintRules::default());
+        assert!(rules_found(&report.findings) == vec![LintRule::DuplicateTag]);
+        assert!(report.findings[0].address == ("small", 1).to_tree_address());
+        assert!(report.nodes_examined == 4);
+    }
+
+    #[test]
+    fn budgeted_lint_matches_full_lint() {
+        let tree        = tree!("root", tree!("a", ("x", 1), ("x", 2)), "b", tree!("c", ("y", 1), "z", ("y", 2)), tree!("d", ("w", 1)));
+        let expected    = lint_tree(&tree, &LintRules::default());
+
+        let mut findings    = vec![];
+        let mut examined    = 0;
+        let mut cursor      = None;
+
+        loop {
+            let (report, next)  = lint_tree_budgeted(&tree, &LintRules::default(), cursor, 3);
+            assert!(report.nodes_examined <= 3);
+
+            findings.extend(report.findings);
+            examined += report.nodes_examined;
+            cursor = next;
+
+            if cursor.is_none() { break; }
+        }
+
+        assert!(findings == expected);
+        assert!(examined == 11);
+    }
+}
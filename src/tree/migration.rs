@@ -0,0 +1,409 @@
+//
+//   Copyright 2016 Andrew Hunter
+//
+//   Licensed under the Apache License, Version 2.0 (the "License");
+//   you may not use this file except in compliance with the License.
+//   You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+//   Unless required by applicable law or agreed to in writing, software
+//   distributed under the License is distributed on an "AS IS" BASIS,
+//   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//   See the License for the specific language governing permissions and
+//   limitations under the License.
+//
+
+//!
+//! # Migrating saved trees
+//!
+//! Trees saved with `save_tree_with_version()` are written in the text format with a header recording the version
+//! of the schema they were written with:
+//!
+//! ```text
+//! # schema-version: 3
+//! config
+//!     name: "example"
+//! ```
+//!
+//! The header is a comment, so the file can still be read by `parse_tree_text()`. Files without a header are
+//! treated as version 0.
+//!
+//! `load_tree_with_migrations()` reads a saved tree and upgrades it to the current version by applying
+//! `Migration`s one after another: each migration converts a tree from one version to a later one. The functions
+//! in this module such as `rename_tag()` and `move_subtree()` create the steps for the common kinds of migration,
+//! and `migration_steps()` combines several of them into a single migration function.
+//!
+//! ```
+//! # use tametree::prelude::*;
+//! # use tametree::tree::*;
+//! let saved       = "# schema-version: 1\nconfig\n    host: \"example.com\"\n";
+//! let migrations  = vec![
+//!     Migration::new(1, 2, rename_tag(&(), "host", "server")),
+//!     Migration::new(2, 3, add_default_child(&(), ("port", 80).to_tree_node()))
+//! ];
+//!
+//! let tree = load_tree_with_migrations(saved.as_bytes(), 3, &migrations).unwrap();
+//!
+//! assert!(tree.get_child_at("server").get_value().to_str("") == "example.com");
+//! assert!(tree.get_child_at("port").get_value().to_int(0) == 80);
+//! ```
+//!
+
+use std::io;
+use std::fmt;
+use std::rc::*;
+
+use super::treenode::*;
+use super::basictree::*;
+use super::values::*;
+use super::address::*;
+use super::change::*;
+use super::iterator::*;
+use super::text::*;
+use super::render::*;
+
+///
+/// The start of the header line that records the schema version of a saved tree
+///
+const VERSION_HEADER: &str = "# schema-version: ";
+
+///
+/// Errors that can occur while loading and migrating a saved tree
+///
+#[derive(Clone, PartialEq, Debug)]
+pub enum MigrationError {
+    /// The saved tree could not be read or parsed
+    Parse(TextParseError),
+
+    /// There's no migration from the first version to the second one
+    MissingStep { from_version: u32, to_version: u32 },
+
+    /// The saved tree has a version that's later than the current version
+    NewerVersion { found: u32, current: u32 },
+
+    /// A migration couldn't convert the node at an address
+    Conversion { address: String, reason: String }
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MigrationError::Parse(ref error)                            => write!(f, "{}", error),
+            MigrationError::MissingStep { from_version, to_version }    => write!(f, "there is no migration from version {} towards version {}", from_version, to_version),
+            MigrationError::NewerVersion { found, current }             => write!(f, "the tree was saved with version {}, which is newer than the current version {}", found, current),
+            MigrationError::Conversion { ref address, ref reason }      => write!(f, "could not migrate {}: {}", address, reason)
+        }
+    }
+}
+
+///
+/// A function that converts a tree from one schema version to another
+///
+pub type MigrationFn = Box<dyn Fn(TreeRef) -> Result<TreeRef, MigrationError>>;
+
+///
+/// Converts saved trees from one schema version to a later one
+///
+pub struct Migration {
+    pub from_version: u32,
+    pub to_version: u32,
+    pub apply: MigrationFn
+}
+
+impl Migration {
+    ///
+    /// Creates a migration between two versions
+    ///
+    pub fn new(from_version: u32, to_version: u32, apply: MigrationFn) -> Migration {
+        Migration { from_version, to_version, apply }
+    }
+}
+
+///
+/// Reads the schema version from the header of a saved tree (0 if there's no header)
+///
+pub fn read_schema_version(src: &str) -> u32 {
+    src.lines().next()
+        .filter(|line| line.starts_with(VERSION_HEADER))
+        .and_then(|line| line[VERSION_HEADER.len()..].trim().parse().ok())
+        .unwrap_or(0)
+}
+
+///
+/// Writes a tree in the text format with a header recording its schema version
+///
+pub fn save_tree_with_version<W: io::Write>(tree: &TreeRef, version: u32, out: W) -> io::Result<()> {
+    let mut writer  = IoWriter::new(out);
+    let result      = fmt::Write::write_fmt(&mut writer, format_args!("{}{}\n", VERSION_HEADER, version))
+        .and_then(|_| write_tree_text(tree, &mut writer));
+
+    writer.finish(result)
+}
+
+///
+/// Reads a saved tree and migrates it to the current schema version
+///
+pub fn load_tree_with_migrations<R: io::Read>(reader: R, current_version: u32, migrations: &[Migration]) -> Result<TreeRef, MigrationError> {
+    let mut reader  = reader;
+    let mut src     = String::new();
+
+    reader.read_to_string(&mut src).map_err(|err| MigrationError::Parse(TextParseError::Io(err.to_string())))?;
+
+    let mut version = read_schema_version(&src);
+    let mut tree    = parse_tree_text(&src).map_err(MigrationError::Parse)?;
+
+    if version > current_version {
+        return Err(MigrationError::NewerVersion { found: version, current: current_version });
+    }
+
+    while version < current_version {
+        let migration = migrations.iter()
+            .find(|migration| migration.from_version == version && migration.to_version > version && migration.to_version <= current_version)
+            .ok_or(MigrationError::MissingStep { from_version: version, to_version: current_version })?;
+
+        tree    = (migration.apply)(tree)?;
+        version = migration.to_version;
+    }
+
+    Ok(tree)
+}
+
+///
+/// Combines several migration steps into one, which applies them in order
+///
+pub fn migration_steps(steps: Vec<MigrationFn>) -> MigrationFn {
+    Box::new(move |tree| steps.iter().try_fold(tree, |tree, step| step(tree)))
+}
+
+///
+/// Creates a copy of a node with a different tag and no siblings
+///
+fn retagged(node: &TreeRef, tag: &str) -> TreeRef {
+    Rc::new(BasicTree::new(tag, node.get_value().clone(), node.get_child_ref(), None))
+}
+
+///
+/// Creates an error for a node that can't be migrated
+///
+fn conversion_error(address: &TreeAddress, reason: &str) -> MigrationError {
+    MigrationError::Conversion { address: address.to_string(), reason: reason.to_string() }
+}
+
+///
+/// Migration step that renames the children of the node at an address that have a particular tag
+///
+/// Trees that don't contain the node are left as they are.
+///
+pub fn rename_tag<TAddress: ToTreeAddress>(parent: &TAddress, old_tag: &str, new_tag: &str) -> MigrationFn {
+    let parent  = parent.to_tree_address();
+    let old_tag = old_tag.to_string();
+    let new_tag = new_tag.to_string();
+
+    Box::new(move |tree| {
+        let node = match tree.get_child_ref_at(parent.clone()) {
+            Some(node)  => node,
+            None        => return Ok(tree)
+        };
+
+        let children: Vec<TreeRef> = node.iter_children()
+            .map(|child| if child.get_tag() == old_tag { retagged(&child, &new_tag) } else { child })
+            .collect();
+
+        Ok(TreeChange::new(&parent, &node.with_children(&children).with_sibling_node(None)).apply(&tree))
+    })
+}
+
+///
+/// Migration step that moves the node at one address to another
+///
+/// If the destination ends with a tag, the node is given that tag. Trees that don't contain the node are left as
+/// they are, but the parent of the destination must exist.
+///
+pub fn move_subtree<TFrom: ToTreeAddress, TTo: ToTreeAddress>(from: &TFrom, to: &TTo) -> MigrationFn {
+    let from    = from.to_tree_address();
+    let to      = to.to_tree_address();
+
+    Box::new(move |tree| {
+        let node = match tree.get_child_ref_at(from.clone()) {
+            Some(node)  => node,
+            None        => return Ok(tree)
+        };
+
+        let node = match *to.last_part() {
+            TreeAddress::ChildWithTag(ref tag, _)   => retagged(&node, tag),
+            _                                       => node.with_sibling_node(None)
+        };
+
+        let removed = TreeChange::new(&from, &TreeReplacement::Remove).apply(&tree);
+        if removed.get_child_ref_at(to.parent()).is_none() {
+            return Err(conversion_error(&to, "the destination has no parent"));
+        }
+
+        Ok(TreeChange::new(&to, &node).apply(&removed))
+    })
+}
+
+///
+/// Migration step that adds a child to the node at an address, if it doesn't already have a child with the same tag
+///
+pub fn add_default_child<TAddress: ToTreeAddress>(parent: &TAddress, default: TreeRef) -> MigrationFn {
+    let parent = parent.to_tree_address();
+
+    Box::new(move |tree| {
+        let node = tree.get_child_ref_at(parent.clone()).ok_or_else(|| conversion_error(&parent, "there's no node to add a child to"))?;
+
+        if node.get_child_ref_at(default.get_tag()).is_some() {
+            Ok(tree)
+        } else {
+            let mut children: Vec<TreeRef> = node.iter_children().collect();
+            children.push(default.clone());
+
+            Ok(TreeChange::new(&parent, &node.with_children(&children).with_sibling_node(None)).apply(&tree))
+        }
+    })
+}
+
+///
+/// Migration step that converts the value of the node at an address
+///
+/// Trees that don't contain the node are left as they are. If the conversion fails, the migration fails with the
+/// reason it returns.
+///
+pub fn convert_value<TAddress: ToTreeAddress, TConvert: 'static + Fn(&TreeValue) -> Result<TreeValue, String>>(address: &TAddress, conversion: TConvert) -> MigrationFn {
+    let address = address.to_tree_address();
+
+    Box::new(move |tree| {
+        let node = match tree.get_child_ref_at(address.clone()) {
+            Some(node)  => node,
+            None        => return Ok(tree)
+        };
+
+        let value = conversion(node.get_value()).map_err(|reason| conversion_error(&address, &reason))?;
+        Ok(TreeChange::new(&address, &TreeReplacement::NewValue(node.get_tag().to_string(), value)).apply(&tree))
+    })
+}
+
+#[cfg(test)]
+mod migration_tests {
+    use super::super::super::tree::*;
+
+    tree_struct! {
+        #[derive(PartialEq, Debug)]
+        struct ServerConfig {
+            server: String,
+            port: i32
+        }
+    }
+
+    tree_struct! {
+        #[derive(PartialEq, Debug)]
+        struct Config {
+            name: String,
+            connection: ServerConfig
+        }
+    }
+
+    ///
+    /// Migrations from version 1, where the host and port are at the top level and the port is a string, to
+    /// version 3, which matches `Config`
+    ///
+    fn config_migrations() -> Vec<Migration> {
+        vec![
+            Migration::new(1, 2, migration_steps(vec![
+                rename_tag(&(), "host", "server"),
+                convert_value(&"port", |value| value.to_str("").parse::<i32>().map(|port| port.to_tree_value()).map_err(|err| err.to_string()))
+            ])),
+            Migration::new(2, 3, migration_steps(vec![
+                add_default_child(&(), "connection".to_tree_node()),
+                move_subtree(&"server", &("connection", "server")),
+                move_subtree(&"port", &("connection", "port")),
+                add_default_child(&(), ("name", "unnamed").to_tree_node())
+            ]))
+        ]
+    }
+
+    #[test]
+    fn version_1_migrates_to_version_3() {
+        let saved   = "# schema-version: 1\nconfig\n    host: \"example.com\"\n    port: \"8080\"\n";
+        let tree    = load_tree_with_migrations(saved.as_bytes(), 3, &config_migrations()).unwrap();
+        let config  = Config::new_from_tree(&tree).unwrap();
+
+        assert!(config == Config { name: "unnamed".to_string(), connection: ServerConfig { server: "example.com".to_string(), port: 8080 } });
+    }
+
+    #[test]
+    fn gap_in_migrations_is_an_error() {
+        let saved       = "# schema-version: 1\nconfig\n";
+        let migrations  = vec![Migration::new(2, 3, migration_steps(vec![]))];
+
+        assert!(load_tree_with_migrations(saved.as_bytes(), 3, &migrations).err() == Some(MigrationError::MissingStep { from_version: 1, to_version: 3 }));
+        assert!(load_tree_with_migrations(saved.as_bytes(), 0, &migrations).err() == Some(MigrationError::NewerVersion { found: 1, current: 0 }));
+    }
+
+    #[test]
+    fn failed_conversion_names_address() {
+        let saved   = "# schema-version: 1\nconfig\n    host: \"example.com\"\n    port: \"eighty\"\n";
+        let result  = load_tree_with_migrations(saved.as_bytes(), 3, &config_migrations());
+
+        assert!(match result { Err(MigrationError::Conversion { ref address, .. }) => address == ".\"port\".", _ => false });
+    }
+
+    #[test]
+    fn rename_tag_renames_matching_children() {
+        let tree    = tree!("root", tree!("list", ("old", 1), ("other", 2), ("old", 3)));
+        let renamed = rename_tag(&"list", "old", "new")(tree).unwrap();
+
+        assert!(renamed.get_child_at("list").iter_children().map(|child| child.get_tag().to_string()).collect::<Vec<_>>() == vec!["new", "other", "new"]);
+        assert!(renamed.get_child_at("list").get_child_at(2).get_value().to_int(0) == 3);
+    }
+
+    #[test]
+    fn move_subtree_moves_and_retags() {
+        let tree    = tree!("root", tree!("a", tree!("inner", ("x", 1))), "b");
+        let moved   = move_subtree(&("a", "inner"), &("b", "moved"))(tree).unwrap();
+
+        assert!(moved.get_child_at("a").get_child_ref().is_none());
+        assert!(moved.get_child_at("b").get_child_at("moved").get_child_at("x").get_value().to_int(0) == 1);
+
+        let error = move_subtree(&"b", &("missing", "b"))(moved).err().unwrap();
+        assert!(error == MigrationError::Conversion { address: ".\"missing\".\"b\".".to_string(), reason: "the destination has no parent".to_string() });
+    }
+
+    #[test]
+    fn add_default_child_only_adds_missing_children() {
+        let tree    = tree!("root", ("a", 1));
+        let added   = add_default_child(&(), ("b", 2).to_tree_node())(tree).unwrap();
+        let kept    = add_default_child(&(), ("a", 5).to_tree_node())(added.clone()).unwrap();
+
+        assert!(added.get_child_at("b").get_value().to_int(0) == 2);
+        assert!(kept.get_child_at("a").get_value().to_int(0) == 1);
+        assert!(kept.iter_children().count() == 2);
+    }
+
+    #[test]
+    fn convert_value_changes_kind() {
+        let tree        = tree!("root", ("flag", "yes"));
+        let to_bool     = |value: &TreeValue| match value.to_str("") { "yes" => Ok(true.to_tree_value()), "no" => Ok(false.to_tree_value()), other => Err(format!("'{}' is not yes or no", other)) };
+        let converted   = convert_value(&"flag", to_bool)(tree).unwrap();
+
+        assert!(*converted.get_child_at("flag").get_value() == TreeValue::Bool(true));
+    }
+
+    #[test]
+    fn saving_writes_the_version() {
+        let saved       = "# schema-version: 1\nconfig\n    host: \"example.com\"\n    port: \"8080\"\n";
+        let tree        = load_tree_with_migrations(saved.as_bytes(), 3, &config_migrations()).unwrap();
+        let mut resaved = vec![];
+
+        save_tree_with_version(&tree, 3, &mut resaved).unwrap();
+        let resaved = String::from_utf8(resaved).unwrap();
+
+        assert!(read_schema_version(&resaved) == 3);
+        assert!(resaved.starts_with("# schema-version: 3\nconfig\n"));
+
+        // Loading the new version doesn't run any migrations
+        let reloaded = load_tree_with_migrations(resaved.as_bytes(), 3, &[]).unwrap();
+        assert!(to_tree_text(&reloaded) == to_tree_text(&tree));
+    }
+}
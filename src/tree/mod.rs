@@ -25,6 +25,21 @@ pub use self::address::*;
 pub use self::extent::*;
 pub use self::iterator::*;
 pub use self::change::*;
+pub use self::keyedvec::*;
+pub use self::orderedmap::*;
+pub use self::text::*;
+pub use self::address_cache::*;
+pub use self::budget::*;
+pub use self::watermark::*;
+pub use self::lint::*;
+pub use self::impact::*;
+pub use self::arena::*;
+pub use self::compaction::*;
+pub use self::value_history::*;
+pub use self::shape::*;
+pub use self::cursor::*;
+pub use self::migration::*;
+pub use self::diff_summary::*;
 
 pub mod treenode;
 pub mod values;
@@ -38,3 +53,19 @@ pub mod address;
 pub mod extent;
 pub mod iterator;
 pub mod change;
+pub mod keyedvec;
+pub mod orderedmap;
+pub mod text;
+pub mod render;
+pub mod address_cache;
+pub mod budget;
+pub mod watermark;
+pub mod lint;
+pub mod impact;
+pub mod arena;
+pub mod compaction;
+pub mod value_history;
+pub mod shape;
+pub mod cursor;
+pub mod migration;
+pub mod diff_summary;
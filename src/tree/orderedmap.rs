@@ -0,0 +1,283 @@
+//
+//   Copyright 2016 Andrew Hunter
+//
+//   Licensed under the Apache License, Version 2.0 (the "License");
+//   you may not use this file except in compliance with the License.
+//   You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+//   Unless required by applicable law or agreed to in writing, software
+//   distributed under the License is distributed on an "AS IS" BASIS,
+//   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//   See the License for the specific language governing permissions and
+//   limitations under the License.
+//
+
+//!
+//! # Ordered maps
+//!
+//! A map is stored in a tree as a set of children, where the tag of each child is the key and the child itself is
+//! the value. There are three types that can be decoded from and encoded to this shape:
+//!
+//! * `HashMap<String, V>` is the most convenient when the order of the children doesn't matter. It encodes its
+//!   entries in the order it iterates over them, which is not predictable.
+//! * `OrderedMap<V>` keeps the entries in the order they appear in the tree (or the order they were inserted),
+//!   and can still look them up by key. Use this when order is meaningful, for example for a list of middleware
+//!   or the columns of a table.
+//! * `Vec<(String, V)>` also keeps the tree order and doesn't build an index. It's the simplest choice if the
+//!   entries are only ever iterated over, and it is the only one of the three that keeps every child when
+//!   several have the same tag.
+//!
+//! When several children have the same tag, `HashMap` and `OrderedMap` keep the value of the last one. An
+//! `OrderedMap` keeps it at the position of the first one.
+//!
+
+use std::collections::HashMap;
+use std::slice;
+
+use rustc_serialize::*;
+
+use super::encoder::*;
+
+///
+/// A map from tags to values that remembers the order its entries were added in
+///
+pub struct OrderedMap<V> {
+    /// The entries in this map, in order
+    entries: Vec<(String, V)>,
+
+    /// Maps keys to the index of their entry
+    index: HashMap<String, usize>
+}
+
+impl<V> OrderedMap<V> {
+    ///
+    /// Creates a new, empty, ordered map
+    ///
+    pub fn new() -> OrderedMap<V> {
+        OrderedMap { entries: vec![], index: HashMap::new() }
+    }
+
+    ///
+    /// Adds a value to the end of this map, or replaces the value of an existing key in place
+    ///
+    /// Returns the old value if the key was already in the map.
+    ///
+    pub fn insert(&mut self, key: String, value: V) -> Option<V> {
+        if let Some(existing_index) = self.index.get(&key) {
+            return Some(::std::mem::replace(&mut self.entries[*existing_index].1, value));
+        }
+
+        self.index.insert(key.clone(), self.entries.len());
+        self.entries.push((key, value));
+
+        None
+    }
+
+    ///
+    /// Retrieves the value for a particular key
+    ///
+    pub fn get(&self, key: &str) -> Option<&V> {
+        self.index.get(key).map(|entry_index| &self.entries[*entry_index].1)
+    }
+
+    ///
+    /// Retrieves a mutable reference to the value for a particular key
+    ///
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut V> {
+        match self.index.get(key) {
+            Some(entry_index)   => Some(&mut self.entries[*entry_index].1),
+            None                => None
+        }
+    }
+
+    ///
+    /// Returns true if there's a value for the specified key
+    ///
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.index.contains_key(key)
+    }
+
+    ///
+    /// Iterates over the keys of this map, in order
+    ///
+    pub fn keys<'a>(&'a self) -> Box<dyn Iterator<Item=&'a str> + 'a> {
+        Box::new(self.entries.iter().map(|(key, _)| &**key))
+    }
+
+    ///
+    /// Iterates over the entries of this map, in order
+    ///
+    pub fn iter<'a>(&'a self) -> slice::Iter<'a, (String, V)> {
+        self.entries.iter()
+    }
+
+    ///
+    /// The number of entries in this map
+    ///
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    ///
+    /// True if this map has no entries
+    ///
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    ///
+    /// Converts this map into a list of entries, in order
+    ///
+    pub fn into_vec(self) -> Vec<(String, V)> {
+        self.entries
+    }
+}
+
+impl<V> Default for OrderedMap<V> {
+    fn default() -> OrderedMap<V> {
+        OrderedMap::new()
+    }
+}
+
+impl<'a, V> IntoIterator for &'a OrderedMap<V> {
+    type Item       = &'a (String, V);
+    type IntoIter   = slice::Iter<'a, (String, V)>;
+
+    fn into_iter(self) -> slice::Iter<'a, (String, V)> {
+        self.entries.iter()
+    }
+}
+
+impl<V: Encodable> Encodable for OrderedMap<V> {
+    fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
+        s.emit_map(self.entries.len(), |s| {
+            for (entry_index, (key, value)) in self.entries.iter().enumerate() {
+                s.emit_map_elt_key(entry_index, |s| s.emit_str(key))?;
+                s.emit_map_elt_val(entry_index, |s| value.encode(s))?;
+            }
+
+            Ok(())
+        })
+    }
+}
+
+impl<V: Decodable> Decodable for OrderedMap<V> {
+    fn decode<D: Decoder>(d: &mut D) -> Result<OrderedMap<V>, D::Error> {
+        d.read_map(|d, len| {
+            let mut result = OrderedMap::new();
+
+            for entry_index in 0..len {
+                let key     = d.read_map_elt_key(entry_index, |d| String::decode(d))?;
+                let value   = d.read_map_elt_val(entry_index, |d| V::decode(d))?;
+
+                result.insert(key, value);
+            }
+
+            Ok(result)
+        })
+    }
+}
+
+impl<V: Encodable + Decodable> EncodeToTreeNode for OrderedMap<V> {}
+
+#[cfg(test)]
+mod orderedmap_tests {
+    use std::collections::HashMap;
+
+    use super::super::super::tree::*;
+
+    fn child_tags(tree: &TreeRef) -> Vec<String> {
+        tree.iter_children().map(|child| child.get_tag().to_string()).collect()
+    }
+
+    #[test]
+    fn insert_keeps_order() {
+        let mut map = OrderedMap::new();
+        map.insert("zebra".to_string(), 1);
+        map.insert("apple".to_string(), 2);
+        map.insert("mango".to_string(), 3);
+
+        assert!(map.keys().collect::<Vec<&str>>() == vec!["zebra", "apple", "mango"]);
+        assert!(map.get("apple") == Some(&2));
+    }
+
+    #[test]
+    fn insert_existing_key_replaces_in_place() {
+        let mut map = OrderedMap::new();
+        map.insert("first".to_string(), 1);
+        map.insert("second".to_string(), 2);
+
+        assert!(map.insert("first".to_string(), 10) == Some(1));
+        assert!(map.keys().collect::<Vec<&str>>() == vec!["first", "second"]);
+        assert!(map.get("first") == Some(&10));
+        assert!(map.len() == 2);
+    }
+
+    #[test]
+    fn ordered_map_round_trip() {
+        let tree    = tree!("columns", ("name", 3), ("age", 1), ("email", 2), ("address", 0));
+        let decoded = OrderedMap::<i32>::new_from_tree(&tree).unwrap();
+
+        assert!(decoded.keys().collect::<Vec<&str>>() == vec!["name", "age", "email", "address"]);
+        assert!(decoded.get("email") == Some(&2));
+
+        let encoded = decoded.to_tree_node();
+        assert!(child_tags(&encoded) == vec!["name", "age", "email", "address"]);
+        assert!(encoded.get_child_at("age").get_value().to_int(0) == 1);
+    }
+
+    #[test]
+    fn vec_of_pairs_round_trip() {
+        let tree    = tree!("middleware", ("logging", 1), ("auth", 2), ("logging", 3), ("compress", 4));
+        let decoded = Vec::<(String, i32)>::new_from_tree(&tree).unwrap();
+
+        assert!(decoded == vec![("logging".to_string(), 1), ("auth".to_string(), 2), ("logging".to_string(), 3), ("compress".to_string(), 4)]);
+
+        let encoded = decoded.to_tree_node();
+        assert!(child_tags(&encoded) == vec!["logging", "auth", "logging", "compress"]);
+        assert!(encoded.get_child_at(2).get_value().to_int(0) == 3);
+    }
+
+    #[test]
+    fn hash_map_encodes_in_iteration_order() {
+        let mut map = HashMap::new();
+        for index in 0..20 {
+            map.insert(format!("key{}", index), index);
+        }
+
+        let encoded         = map.to_tree_node();
+        let iteration_order = map.keys().cloned().collect::<Vec<String>>();
+        assert!(child_tags(&encoded) == iteration_order);
+
+        let decoded = HashMap::<String, i32>::new_from_tree(&encoded).unwrap();
+        assert!(decoded == map);
+    }
+
+    #[test]
+    fn hash_map_decodes_into_ordered_map_in_tree_order() {
+        let mut map = HashMap::new();
+        for index in 0..20 {
+            map.insert(format!("key{}", index), index);
+        }
+
+        // Whatever order the hash map produces, the ordered map follows the tree
+        let encoded = map.to_tree_node();
+        let ordered = OrderedMap::<i32>::new_from_tree(&encoded).unwrap();
+
+        assert!(ordered.keys().map(|key| key.to_string()).collect::<Vec<String>>() == child_tags(&encoded));
+
+        let decoded_again = OrderedMap::<i32>::new_from_tree(&encoded).unwrap();
+        assert!(ordered.keys().collect::<Vec<&str>>() == decoded_again.keys().collect::<Vec<&str>>());
+    }
+
+    #[test]
+    fn duplicate_tags_keep_last_value_at_first_position() {
+        let tree    = tree!("map", ("a", 1), ("b", 2), ("a", 3));
+        let decoded = OrderedMap::<i32>::new_from_tree(&tree).unwrap();
+
+        assert!(decoded.keys().collect::<Vec<&str>>() == vec!["a", "b"]);
+        assert!(decoded.get("a") == Some(&3));
+    }
+}
@@ -0,0 +1,214 @@
+//
+//   Copyright 2016 Andrew Hunter
+//
+//   Licensed under the Apache License, Version 2.0 (the "License");
+//   you may not use this file except in compliance with the License.
+//   You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+//   Unless required by applicable law or agreed to in writing, software
+//   distributed under the License is distributed on an "AS IS" BASIS,
+//   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//   See the License for the specific language governing permissions and
+//   limitations under the License.
+//
+
+//!
+//! # Rendering utilities
+//!
+//! Trees are rendered into anything that implements `std::fmt::Write`, so they can be written into an existing
+//! buffer or streamed out without building the whole rendering in memory first. The functions in this module are
+//! shared by the renderers: they write escaped strings, indentation and base64 data a piece at a time.
+//!
+//! `IoWriter` adapts an `std::io::Write` (such as a file) so a renderer can write to it, keeping hold of the I/O
+//! error that stopped the rendering if there was one:
+//!
+//! ```
+//! # use tametree::prelude::*;
+//! # use tametree::tree::*;
+//! # use tametree::tree::render::*;
+//! let mut bytes   = vec![];
+//! let mut writer  = IoWriter::new(&mut bytes);
+//! let result      = write_tree_text(&tree!("root", ("child", 1)), &mut writer);
+//!
+//! assert!(writer.finish(result).is_ok());
+//! assert!(bytes == b"root\n    child: 1\n");
+//! ```
+//!
+
+use std::io;
+use std::fmt;
+use std::fmt::Write;
+
+///
+/// The characters used by base64, in order
+///
+pub const BASE64_CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+///
+/// Writes a string in double quotes, escaping quotes, backslashes and control characters
+///
+/// Newlines, carriage returns and tabs are written as `\n`, `\r` and `\t`, other control characters as `\u{...}`.
+/// Anything else, including non-ASCII characters, is written as it is.
+///
+pub fn write_quoted<W: Write>(out: &mut W, value: &str) -> fmt::Result {
+    out.write_char('"')?;
+
+    // Unescaped runs of characters are written in one go
+    let mut run_start = 0;
+
+    for (pos, c) in value.char_indices() {
+        let escaped = match c {
+            '"'                     => Some("\\\""),
+            '\\'                    => Some("\\\\"),
+            '\n'                    => Some("\\n"),
+            '\r'                    => Some("\\r"),
+            '\t'                    => Some("\\t"),
+            c if c.is_control()     => None,
+            _                       => continue
+        };
+
+        out.write_str(&value[run_start..pos])?;
+        match escaped {
+            Some(escaped)   => out.write_str(escaped)?,
+            None            => write!(out, "\\u{{{:x}}}", c as u32)?
+        }
+
+        run_start = pos + c.len_utf8();
+    }
+
+    out.write_str(&value[run_start..])?;
+    out.write_char('"')
+}
+
+///
+/// Writes the indentation for a line at a particular depth
+///
+pub fn write_indent<W: Write>(out: &mut W, depth: usize, unit: &str) -> fmt::Result {
+    for _ in 0..depth {
+        out.write_str(unit)?;
+    }
+
+    Ok(())
+}
+
+///
+/// Writes some data as base64, with padding
+///
+pub fn write_base64<W: Write>(out: &mut W, data: &[u8]) -> fmt::Result {
+    for chunk in data.chunks(3) {
+        let bits        = chunk.iter().enumerate().fold(0u32, |bits, (index, byte)| bits | ((*byte as u32) << (16 - index*8)));
+        let mut encoded = [b'='; 4];
+
+        for (index, encoded) in encoded.iter_mut().enumerate().take(chunk.len()+1) {
+            *encoded = BASE64_CHARS[((bits >> (18 - index*6)) & 0x3f) as usize];
+        }
+
+        // The encoded characters are all ASCII
+        out.write_str(::std::str::from_utf8(&encoded).unwrap_or("===="))?;
+    }
+
+    Ok(())
+}
+
+///
+/// Adapts an `io::Write` so that it can be rendered into
+///
+/// `fmt::Error` can't say what went wrong, so the I/O error that caused a rendering to fail is kept by the writer.
+/// `finish()` converts the result of the rendering back into an `io::Result`.
+///
+pub struct IoWriter<W: io::Write> {
+    target: W,
+    error: Option<io::Error>
+}
+
+impl<W: io::Write> IoWriter<W> {
+    ///
+    /// Creates a writer that writes to an `io::Write`
+    ///
+    pub fn new(target: W) -> IoWriter<W> {
+        IoWriter { target, error: None }
+    }
+
+    ///
+    /// Converts the result of a rendering into this writer to an I/O result
+    ///
+    pub fn finish(self, result: fmt::Result) -> io::Result<()> {
+        match (result, self.error) {
+            (_, Some(error))    => Err(error),
+            (Ok(()), None)      => Ok(()),
+            (Err(_), None)      => Err(io::Error::other("the tree could not be rendered"))
+        }
+    }
+}
+
+impl<W: io::Write> Write for IoWriter<W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        match self.target.write_all(s.as_bytes()) {
+            Ok(())      => Ok(()),
+            Err(error)  => {
+                self.error = Some(error);
+                Err(fmt::Error)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod render_tests {
+    use std::io;
+
+    use super::*;
+
+    fn quoted(value: &str) -> String {
+        let mut result = String::new();
+        write_quoted(&mut result, value).unwrap();
+        result
+    }
+
+    #[test]
+    fn quotes_and_backslashes_are_escaped() {
+        assert!(quoted("say \"hi\"") == "\"say \\\"hi\\\"\"");
+        assert!(quoted("back\\slash") == "\"back\\\\slash\"");
+        assert!(quoted("") == "\"\"");
+    }
+
+    #[test]
+    fn control_characters_are_escaped() {
+        assert!(quoted("a\nb\rc\td") == "\"a\\nb\\rc\\td\"");
+        assert!(quoted("bell\u{7}") == "\"bell\\u{7}\"");
+        assert!(quoted("\u{1b}[0m") == "\"\\u{1b}[0m\"");
+    }
+
+    #[test]
+    fn non_ascii_is_written_unchanged() {
+        assert!(quoted("ünïcödé ✓") == "\"ünïcödé ✓\"");
+        assert!(quoted("é\"é") == "\"é\\\"é\"");
+    }
+
+    #[test]
+    fn base64_is_padded() {
+        let encode = |data: &[u8]| { let mut result = String::new(); write_base64(&mut result, data).unwrap(); result };
+
+        assert!(encode(b"").is_empty());
+        assert!(encode(b"H") == "SA==");
+        assert!(encode(b"He") == "SGU=");
+        assert!(encode(b"Hello") == "SGVsbG8=");
+    }
+
+    #[test]
+    fn io_errors_are_kept() {
+        struct Broken;
+
+        impl io::Write for Broken {
+            fn write(&mut self, _buf: &[u8]) -> io::Result<usize> { Err(io::Error::new(io::ErrorKind::BrokenPipe, "broken")) }
+            fn flush(&mut self) -> io::Result<()> { Ok(()) }
+        }
+
+        let mut writer  = IoWriter::new(Broken);
+        let result      = write_quoted(&mut writer, "text");
+
+        assert!(writer.finish(result).err().map(|error| error.kind()) == Some(io::ErrorKind::BrokenPipe));
+    }
+}
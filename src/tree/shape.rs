@@ -0,0 +1,348 @@
+//
+//   Copyright 2016 Andrew Hunter
+//
+//   Licensed under the Apache License, Version 2.0 (the "License");
+//   you may not use this file except in compliance with the License.
+//   You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+//   Unless required by applicable law or agreed to in writing, software
+//   distributed under the License is distributed on an "AS IS" BASIS,
+//   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//   See the License for the specific language governing permissions and
+//   limitations under the License.
+//
+
+//!
+//! # Tree shapes
+//!
+//! The shape of a tree is its structure with the values replaced by their kinds: it says which tagged children
+//! a node has and what kind of value each one holds. Comparing the shape a consumer expects with the shape a
+//! producer generates makes it possible to find out that two components can't talk to each other when they are
+//! wired together, rather than when decoding fails later on.
+//!
+//! `shape_of()` finds the shape of a type by encoding its default value. Collections are empty by default, so
+//! their elements aren't part of the shape.
+//!
+//! Children are matched by tag when comparing shapes, so the order of the fields of a structure doesn't matter.
+//! A shape also has a 64-bit fingerprint, which is the same for identical shapes no matter which process or build
+//! calculates it, and so can be sent to a remote peer to check compatibility without sending the whole shape.
+//!
+//! ```
+//! # #[macro_use] extern crate tametree;
+//! # use tametree::tree::*;
+//! # fn main() {
+//! tree_struct! {
+//!     #[derive(Default)]
+//!     struct Produced { name: String, age: i32 }
+//! }
+//!
+//! tree_struct! {
+//!     #[derive(Default)]
+//!     struct Consumed { name: String }
+//! }
+//!
+//! let produced = shape_of::<Produced>();
+//! let consumed = shape_of::<Consumed>();
+//!
+//! assert!(consumed.compatible_with(&produced) == ShapeCompatibility::Subset);
+//! # }
+//! ```
+//!
+
+use rustc_serialize::*;
+
+use super::treenode::*;
+use super::address::*;
+use super::values::*;
+use super::iterator::*;
+use super::encoder::*;
+
+///
+/// The kind of value stored in a tree node
+///
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ValueKind {
+    Nothing,
+    Bool,
+    Int,
+    Real,
+    String,
+    Data
+}
+
+impl ValueKind {
+    ///
+    /// The kind of a value
+    ///
+    pub fn of(value: &TreeValue) -> ValueKind {
+        match *value {
+            TreeValue::Nothing      => ValueKind::Nothing,
+            TreeValue::Bool(_)      => ValueKind::Bool,
+            TreeValue::Int(_)       => ValueKind::Int,
+            TreeValue::Real(_)      => ValueKind::Real,
+            TreeValue::String(_)    => ValueKind::String,
+            TreeValue::Data(_)      => ValueKind::Data
+        }
+    }
+
+    ///
+    /// A number identifying this kind in fingerprints (these must never change)
+    ///
+    fn fingerprint_id(self) -> u8 {
+        match self {
+            ValueKind::Nothing  => 0,
+            ValueKind::Bool     => 1,
+            ValueKind::Int      => 2,
+            ValueKind::Real     => 3,
+            ValueKind::String   => 4,
+            ValueKind::Data     => 5
+        }
+    }
+}
+
+///
+/// The structure of a tree, with its values replaced by their kinds
+///
+#[derive(Clone, PartialEq, Debug)]
+pub struct TreeShape {
+    kind: ValueKind,
+    children: Vec<(String, TreeShape)>
+}
+
+///
+/// The result of comparing the shape a consumer needs with the shape a producer provides
+///
+#[derive(Clone, PartialEq)]
+pub enum ShapeCompatibility {
+    /// The shapes are the same
+    Identical,
+
+    /// Everything the consumer needs is provided, but the producer also provides things the consumer doesn't use
+    Subset,
+
+    /// The consumer needs things the producer doesn't provide
+    Incompatible {
+        /// Addresses that the consumer needs which aren't in the producer's shape
+        missing: Vec<TreeAddress>,
+
+        /// Addresses where the kinds of value differ, along with the kind the consumer needs and the kind provided
+        mismatched: Vec<(TreeAddress, ValueKind, ValueKind)>
+    }
+}
+
+///
+/// Finds the shape of a type by encoding its default value
+///
+/// This will panic if the type can't be encoded to a tree.
+///
+pub fn shape_of<T: Encodable + Default>() -> TreeShape {
+    let default = encode(&T::default()).expect("type must be encodable as a tree");
+
+    TreeShape::of_tree(&default)
+}
+
+///
+/// Adds some bytes to a 64-bit FNV-1a hash
+///
+fn fnv_hash(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, byte| (hash ^ (*byte as u64)).wrapping_mul(0x100000001b3))
+}
+
+impl TreeShape {
+    ///
+    /// Finds the shape of an existing tree
+    ///
+    pub fn of_tree(tree: &TreeRef) -> TreeShape {
+        TreeShape {
+            kind:       ValueKind::of(tree.get_value()),
+            children:   tree.iter_children().map(|child| (child.get_tag().to_string(), TreeShape::of_tree(&child))).collect()
+        }
+    }
+
+    ///
+    /// The kind of the value of the root node of this shape
+    ///
+    pub fn kind(&self) -> ValueKind {
+        self.kind
+    }
+
+    ///
+    /// The tags and shapes of the children of the root node of this shape
+    ///
+    pub fn children(&self) -> &[(String, TreeShape)] {
+        &self.children
+    }
+
+    ///
+    /// Finds the child matching the nth child with a particular tag
+    ///
+    fn matching_child(&self, tag: &str, occurrence: usize) -> Option<&TreeShape> {
+        self.children.iter().filter(|(child_tag, _)| child_tag == tag).nth(occurrence).map(|(_, shape)| shape)
+    }
+
+    ///
+    /// Compares this shape with another, adding the differences to the lists. Returns true if the shapes are identical.
+    ///
+    fn compare(&self, other: &TreeShape, address: &TreeAddress, missing: &mut Vec<TreeAddress>, mismatched: &mut Vec<(TreeAddress, ValueKind, ValueKind)>) -> bool {
+        let mut identical = self.children.len() == other.children.len();
+
+        if self.kind != other.kind {
+            mismatched.push((address.clone(), self.kind, other.kind));
+            identical = false;
+        }
+
+        for (index, (tag, child)) in self.children.iter().enumerate() {
+            let occurrence      = self.children[0..index].iter().filter(|(earlier_tag, _)| earlier_tag == tag).count();
+            let child_address   = address.to_tree_address_then(tag.as_str().to_tree_address());
+
+            match other.matching_child(tag, occurrence) {
+                Some(other_child)   => identical = child.compare(other_child, &child_address, missing, mismatched) && identical,
+                None                => { missing.push(child_address); identical = false; }
+            }
+        }
+
+        identical
+    }
+
+    ///
+    /// Compares the shape a consumer needs (this shape) with the shape a producer provides
+    ///
+    pub fn compatible_with(&self, provided: &TreeShape) -> ShapeCompatibility {
+        let mut missing     = vec![];
+        let mut mismatched  = vec![];
+        let identical       = self.compare(provided, &TreeAddress::Here, &mut missing, &mut mismatched);
+
+        if !missing.is_empty() || !mismatched.is_empty() {
+            ShapeCompatibility::Incompatible { missing, mismatched }
+        } else if identical {
+            ShapeCompatibility::Identical
+        } else {
+            ShapeCompatibility::Subset
+        }
+    }
+
+    ///
+    /// Adds this shape to a fingerprint hash
+    ///
+    fn hash_into(&self, hash: u64) -> u64 {
+        // Children are hashed in tag order so the order of the fields doesn't matter
+        let mut children: Vec<&(String, TreeShape)> = self.children.iter().collect();
+        children.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        let mut hash = fnv_hash(hash, &[self.kind.fingerprint_id()]);
+        hash = fnv_hash(hash, &(children.len() as u64).to_le_bytes());
+
+        for (tag, child) in children {
+            hash = fnv_hash(hash, &(tag.len() as u64).to_le_bytes());
+            hash = fnv_hash(hash, tag.as_bytes());
+            hash = child.hash_into(hash);
+        }
+
+        hash
+    }
+
+    ///
+    /// A 64-bit fingerprint of this shape
+    ///
+    /// Shapes that are identical have the same fingerprint, which doesn't depend on the process or build that
+    /// calculates it. Different shapes are very unlikely to have the same fingerprint.
+    ///
+    pub fn fingerprint(&self) -> u64 {
+        self.hash_into(0xcbf29ce484222325)
+    }
+}
+
+#[cfg(test)]
+mod shape_tests {
+    use super::super::super::tree::*;
+
+    tree_struct! {
+        #[derive(Default)]
+        struct User {
+            name: String,
+            age: i32,
+            admin: bool
+        }
+    }
+
+    tree_struct! {
+        #[derive(Default)]
+        struct SameUser {
+            admin: bool,
+            name: String,
+            age: i32
+        }
+    }
+
+    tree_struct! {
+        #[derive(Default)]
+        struct UserName {
+            name: String
+        }
+    }
+
+    tree_struct! {
+        #[derive(Default)]
+        struct RenamedUser {
+            full_name: String,
+            age: i32,
+            admin: bool
+        }
+    }
+
+    tree_struct! {
+        #[derive(Default)]
+        struct TextAge {
+            age: String
+        }
+    }
+
+    #[test]
+    fn identical_structs_are_compatible() {
+        assert!(shape_of::<User>().compatible_with(&shape_of::<User>()) == ShapeCompatibility::Identical);
+        assert!(shape_of::<SameUser>().compatible_with(&shape_of::<User>()) == ShapeCompatibility::Identical);
+    }
+
+    #[test]
+    fn consumer_missing_field_is_subset() {
+        assert!(shape_of::<UserName>().compatible_with(&shape_of::<User>()) == ShapeCompatibility::Subset);
+    }
+
+    #[test]
+    fn renamed_field_is_reported() {
+        match shape_of::<User>().compatible_with(&shape_of::<RenamedUser>()) {
+            ShapeCompatibility::Incompatible { missing, mismatched } => {
+                assert!(missing == vec!["name".to_tree_address()]);
+                assert!(mismatched.is_empty());
+            },
+            _ => panic!("renamed field should be incompatible")
+        }
+    }
+
+    #[test]
+    fn different_kind_is_reported() {
+        match shape_of::<TextAge>().compatible_with(&shape_of::<User>()) {
+            ShapeCompatibility::Incompatible { missing, mismatched } => {
+                assert!(missing.is_empty());
+                assert!(mismatched.len() == 1);
+                assert!(mismatched[0].0 == "age".to_tree_address());
+                assert!(mismatched[0].1 == ValueKind::String && mismatched[0].2 == ValueKind::Int);
+            },
+            _ => panic!("different kinds should be incompatible")
+        }
+    }
+
+    #[test]
+    fn fingerprints_are_stable() {
+        // This value must not change between runs or builds, as it's used to compare shapes between processes
+        let shape = TreeShape::of_tree(&tree!(("root", "Point"), ("x", 0), ("y", 0)));
+        assert!(shape.fingerprint() == 0x9a20ba3d45013500);
+
+        assert!(shape_of::<User>().fingerprint() == shape_of::<User>().fingerprint());
+        assert!(shape_of::<User>().fingerprint() == shape_of::<SameUser>().fingerprint());
+        assert!(shape_of::<User>().fingerprint() != shape_of::<RenamedUser>().fingerprint());
+        assert!(shape_of::<User>().fingerprint() != shape_of::<UserName>().fingerprint());
+    }
+}
@@ -0,0 +1,840 @@
+//
+//   Copyright 2016 Andrew Hunter
+//
+//   Licensed under the Apache License, Version 2.0 (the "License");
+//   you may not use this file except in compliance with the License.
+//   You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+//   Unless required by applicable law or agreed to in writing, software
+//   distributed under the License is distributed on an "AS IS" BASIS,
+//   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//   See the License for the specific language governing permissions and
+//   limitations under the License.
+//
+
+//!
+//! # Text format
+//!
+//! A minimal, human-readable format for writing trees, intended for things like test fixtures and configuration.
+//! Each node is written on its own line as `tag: value`, and the children of a node are indented beneath it:
+//!
+//! ```text
+//! # A comment
+//! config
+//!     name: "example"
+//!     port: 8080
+//!     ratio: 0.5
+//!     enabled: true
+//!     servers
+//!         server: "alpha"
+//!         server: "beta"
+//! ```
+//!
+//! The value is optional: a node without one has the value `TreeValue::Nothing`. Values are typed by how they are
+//! written: integers (`42`), reals (`4.2`, `1e10`, and `inf`, `-inf` and `nan` for the values that aren't
+//! finite), booleans (`true`, `false`), strings in double quotes (with the escapes `\"`, `\\`, `\n`, `\r`, `\t`
+//! and `\u{...}`) and binary data as base64 between angle brackets (`<SGVsbG8=>`).
+//!
+//! Tags can be written as they are unless they are empty or contain whitespace, `:`, `#`, `"` or `\`, in which case
+//! they are written as a quoted string.
+//!
+//! Indentation can use spaces or tabs but not both: mixing them is an error as it's ambiguous how they line up.
+//! The children of a node must all have the same indentation. Blank lines and anything following a `#` are
+//! ignored. There must be exactly one root node, which is not indented.
+//!
+//! `to_tree_text()` produces this format (indenting with four spaces), and its output can always be read back
+//! by `parse_tree_text()`.
+//!
+
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+use std::rc::*;
+
+use super::treenode::*;
+use super::basictree::*;
+use super::values::*;
+use super::iterator::*;
+use super::arena::*;
+use super::render::*;
+
+///
+/// Errors that can occur while parsing a tree from text
+///
+#[derive(Debug, Clone, PartialEq)]
+pub enum TextParseError {
+    /// Something other than what was expected was found at the specified line and column
+    Syntax { line: usize, column: usize, expected: String },
+
+    /// The indentation at the specified line and column mixes tabs and spaces
+    MixedIndentation { line: usize, column: usize },
+
+    /// The text could not be read
+    Io(String)
+}
+
+impl fmt::Display for TextParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TextParseError::Syntax { line, column, ref expected }   => write!(f, "line {}, column {}: expected {}", line, column, expected),
+            TextParseError::MixedIndentation { line, column }       => write!(f, "line {}, column {}: indentation mixes tabs and spaces", line, column),
+            TextParseError::Io(ref message)                         => write!(f, "could not read tree text: {}", message)
+        }
+    }
+}
+
+///
+/// A node that has been read but not yet created: its tag, value and first child
+///
+type PendingNode<TNode> = (String, TreeValue, Option<TNode>);
+
+///
+/// A node whose children are still being read
+///
+struct PartialNode<TNode> {
+    indent:         usize,
+    child_indent:   Option<usize>,
+    tag:            String,
+    value:          TreeValue,
+    children:       Vec<PendingNode<TNode>>
+}
+
+impl<TNode> PartialNode<TNode> {
+    ///
+    /// Creates the children of this node (from the last to the first, so each can be created with its sibling)
+    ///
+    fn finish<TMakeNode: FnMut(String, TreeValue, Option<TNode>, Option<TNode>) -> TNode>(self, make_node: &mut TMakeNode) -> PendingNode<TNode> {
+        let mut child = None;
+
+        for (tag, value, grandchild) in self.children.into_iter().rev() {
+            child = Some(make_node(tag, value, grandchild, child));
+        }
+
+        (self.tag, self.value, child)
+    }
+}
+
+///
+/// Reads the characters of a single line, keeping track of the column
+///
+struct LineReader {
+    line:   usize,
+    chars:  Vec<char>,
+    pos:    usize
+}
+
+impl LineReader {
+    fn new(line: usize, source: &str) -> LineReader {
+        LineReader { line, chars: source.chars().collect(), pos: 0 }
+    }
+
+    #[inline]
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).cloned()
+    }
+
+    #[inline]
+    fn column(&self) -> usize {
+        self.pos + 1
+    }
+
+    fn error<T>(&self, expected: &str) -> Result<T, TextParseError> {
+        Err(TextParseError::Syntax { line: self.line, column: self.column(), expected: expected.to_string() })
+    }
+
+    ///
+    /// True if there's nothing but a comment left on this line
+    ///
+    fn at_end(&self) -> bool {
+        matches!(self.peek(), None | Some('#'))
+    }
+
+    fn skip_spaces(&mut self) {
+        while let Some(c) = self.peek() {
+            if c == ' ' || c == '\t' {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+    }
+
+    ///
+    /// Reads the indentation at the start of the line, returning its length
+    ///
+    fn read_indent(&mut self, indent_char: &mut Option<char>) -> Result<usize, TextParseError> {
+        while let Some(c) = self.peek() {
+            if c != ' ' && c != '\t' {
+                break;
+            }
+
+            // Every indentation character must be the same as the first one we saw in the text
+            if *indent_char.get_or_insert(c) != c {
+                return Err(TextParseError::MixedIndentation { line: self.line, column: self.column() });
+            }
+
+            self.pos += 1;
+        }
+
+        Ok(self.pos)
+    }
+
+    ///
+    /// Reads a quoted string (the reader should be at the opening quote)
+    ///
+    fn read_quoted(&mut self) -> Result<String, TextParseError> {
+        let mut result = String::new();
+        self.pos += 1;
+
+        loop {
+            match self.peek() {
+                None        => return self.error("a closing '\"'"),
+                Some('"')   => { self.pos += 1; return Ok(result); },
+
+                Some('\\')  => {
+                    self.pos += 1;
+
+                    match self.peek() {
+                        Some('"')   => result.push('"'),
+                        Some('\\')  => result.push('\\'),
+                        Some('n')   => result.push('\n'),
+                        Some('r')   => result.push('\r'),
+                        Some('t')   => result.push('\t'),
+                        Some('u')   => { result.push(self.read_unicode_escape()?); continue; },
+                        _           => return self.error("an escape sequence (one of \\\", \\\\, \\n, \\r, \\t or \\u{...})")
+                    }
+
+                    self.pos += 1;
+                },
+
+                Some(c)     => { result.push(c); self.pos += 1; }
+            }
+        }
+    }
+
+    ///
+    /// Reads a `u{...}` escape (the reader should be at the 'u')
+    ///
+    fn read_unicode_escape(&mut self) -> Result<char, TextParseError> {
+        self.pos += 1;
+        if self.peek() != Some('{') {
+            return self.error("'{' after \\u");
+        }
+        self.pos += 1;
+
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            if c == '}' {
+                break;
+            }
+            self.pos += 1;
+        }
+
+        let hex: String = self.chars[start..self.pos].iter().cloned().collect();
+        if self.peek() != Some('}') {
+            return self.error("'}' to end the \\u escape");
+        }
+
+        match u32::from_str_radix(&hex, 16).ok().and_then(::std::char::from_u32) {
+            Some(c) => { self.pos += 1; Ok(c) },
+            None    => { self.pos = start; self.error("a hexadecimal unicode character code") }
+        }
+    }
+
+    ///
+    /// Reads a run of characters that are not whitespace, comments or separators
+    ///
+    fn read_bare(&mut self) -> String {
+        let start = self.pos;
+
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() || c == ':' || c == '#' || c == '"' {
+                break;
+            }
+            self.pos += 1;
+        }
+
+        self.chars[start..self.pos].iter().cloned().collect()
+    }
+
+    ///
+    /// Reads the tag of a node
+    ///
+    fn read_tag(&mut self) -> Result<String, TextParseError> {
+        if self.peek() == Some('"') {
+            self.read_quoted()
+        } else {
+            let tag = self.read_bare();
+
+            if tag.is_empty() {
+                self.error("a tag")
+            } else {
+                Ok(tag)
+            }
+        }
+    }
+
+    ///
+    /// Reads the value of a node (the reader should be just after the ':')
+    ///
+    fn read_value(&mut self) -> Result<TreeValue, TextParseError> {
+        self.skip_spaces();
+
+        match self.peek() {
+            None | Some('#')    => Ok(TreeValue::Nothing),
+            Some('"')           => Ok(self.read_quoted()?.to_tree_value()),
+            Some('<')           => Ok(self.read_data()?.to_tree_value()),
+
+            _                   => {
+                let start = self.pos;
+                let token = self.read_bare();
+
+                let is_integer = !token.is_empty() && token.chars().enumerate().all(|(index, c)| c.is_ascii_digit() || (index == 0 && (c == '-' || c == '+')));
+
+                // Rust will parse other spellings of infinity and NaN as reals, so only digits are allowed here
+                let is_real = token.chars().any(|c| c.is_ascii_digit()) && token.chars().all(|c| c.is_ascii_digit() || "+-.eE".contains(c));
+
+                match token.as_str() {
+                    "true"                  => Ok(TreeValue::Bool(true)),
+                    "false"                 => Ok(TreeValue::Bool(false)),
+                    "inf"                   => Ok(TreeValue::Real(f64::INFINITY)),
+                    "-inf"                  => Ok(TreeValue::Real(f64::NEG_INFINITY)),
+                    "nan"                   => Ok(TreeValue::Real(f64::NAN)),
+                    _ if is_integer         => token.parse::<i32>().map(TreeValue::Int).or_else(|_| { self.pos = start; self.error("an integer that fits in 32 bits") }),
+                    _ if is_real            => token.parse::<f64>().map(TreeValue::Real).or_else(|_| { self.pos = start; self.error("a number") }),
+                    _                       => { self.pos = start; self.error("a value (a number, true, false, a quoted string or <base64 data>)") }
+                }
+            }
+        }
+    }
+
+    ///
+    /// Reads base64 data between angle brackets (the reader should be at the '<')
+    ///
+    fn read_data(&mut self) -> Result<Vec<u8>, TextParseError> {
+        self.pos += 1;
+        let start = self.pos;
+
+        while let Some(c) = self.peek() {
+            if c == '>' {
+                break;
+            }
+            self.pos += 1;
+        }
+
+        if self.peek() != Some('>') {
+            return self.error("a closing '>'");
+        }
+
+        let encoded: String = self.chars[start..self.pos].iter().cloned().collect();
+        match base64_decode(&encoded) {
+            Some(data)  => { self.pos += 1; Ok(data) },
+            None        => { self.pos = start; self.error("base64 data") }
+        }
+    }
+}
+
+///
+/// Parses a tree from its text representation
+///
+pub fn parse_tree_text(src: &str) -> Result<TreeRef, TextParseError> {
+    parse_tree_text_with(src, |tag, value, child, sibling| Rc::new(BasicTree::new(&tag, value, child, sibling)))
+}
+
+///
+/// Parses a tree from its text representation, sharing identical strings and data between its values
+///
+pub fn parse_tree_text_interned(src: &str, interner: &mut ValueInterner) -> Result<TreeRef, TextParseError> {
+    parse_tree_text_with(src, |tag, value, child, sibling| Rc::new(BasicTree::new(&tag, interner.intern(value), child, sibling)))
+}
+
+///
+/// Parses a tree from its text representation, storing the nodes in an arena
+///
+pub fn parse_tree_text_in<'a>(arena: &'a TreeArena, src: &str) -> Result<ArenaTree<'a>, TextParseError> {
+    parse_tree_text_with(src, |tag, value, child, sibling| arena.add(&tag, value, child, sibling))
+}
+
+///
+/// Parses a tree from its text representation, using a function to create each node from its tag, value, child
+/// and sibling
+///
+fn parse_tree_text_with<TNode, TMakeNode>(src: &str, mut make_node: TMakeNode) -> Result<TNode, TextParseError>
+where TMakeNode: FnMut(String, TreeValue, Option<TNode>, Option<TNode>) -> TNode {
+    let mut levels: Vec<PartialNode<TNode>>         = vec![];
+    let mut root: Option<PendingNode<TNode>>        = None;
+    let mut indent_char: Option<char>               = None;
+
+    for (line_index, line) in src.lines().enumerate() {
+        let mut reader  = LineReader::new(line_index+1, line);
+        let indent      = reader.read_indent(&mut indent_char)?;
+
+        // Blank lines and comments are skipped
+        if reader.at_end() {
+            continue;
+        }
+
+        // Read the contents of this line
+        let tag = reader.read_tag()?;
+        reader.skip_spaces();
+
+        let value = if reader.peek() == Some(':') {
+            reader.pos += 1;
+            reader.read_value()?
+        } else {
+            TreeValue::Nothing
+        };
+
+        reader.skip_spaces();
+        if !reader.at_end() {
+            return reader.error("the end of the line or ':' followed by a value");
+        }
+
+        // Finish any nodes that are at the same or greater indentation than this one
+        while levels.last().map(|level| level.indent >= indent).unwrap_or(false) {
+            let finished = levels.pop().unwrap().finish(&mut make_node);
+
+            match levels.last_mut() {
+                Some(parent)    => parent.children.push(finished),
+                None            => root = Some(finished)
+            }
+        }
+
+        // This is either the root node or a child of the last node
+        if root.is_some() {
+            return Err(TextParseError::Syntax { line: reader.line, column: 1, expected: "the end of the text (there can only be one root node)".to_string() });
+        }
+
+        match levels.last_mut() {
+            None => {
+                if indent != 0 {
+                    return Err(TextParseError::Syntax { line: reader.line, column: 1, expected: "a root node with no indentation".to_string() });
+                }
+            },
+
+            Some(parent) => {
+                if *parent.child_indent.get_or_insert(indent) != indent {
+                    return Err(TextParseError::Syntax { line: reader.line, column: indent+1, expected: "indentation matching the other children of this node".to_string() });
+                }
+            }
+        }
+
+        levels.push(PartialNode { indent, child_indent: None, tag, value, children: vec![] });
+    }
+
+    // Finish the remaining nodes
+    while let Some(level) = levels.pop() {
+        let finished = level.finish(&mut make_node);
+
+        match levels.last_mut() {
+            Some(parent)    => parent.children.push(finished),
+            None            => root = Some(finished)
+        }
+    }
+
+    match root {
+        Some((tag, value, child))   => Ok(make_node(tag, value, child, None)),
+        None                        => Err(TextParseError::Syntax { line: src.lines().count()+1, column: 1, expected: "a root node".to_string() })
+    }
+}
+
+///
+/// Reads a tree in text format from a file (useful for loading test fixtures)
+///
+pub fn load_tree_text<P: AsRef<Path>>(path: P) -> Result<TreeRef, TextParseError> {
+    let mut src = String::new();
+
+    File::open(path.as_ref())
+        .and_then(|mut file| file.read_to_string(&mut src))
+        .map_err(|err| TextParseError::Io(format!("{}: {}", path.as_ref().display(), err)))?;
+
+    parse_tree_text(&src)
+}
+
+///
+/// Writes a tag, quoting it if it can't be read back as a bare tag
+///
+pub fn write_tag<W: fmt::Write>(out: &mut W, tag: &str) -> fmt::Result {
+    let needs_quotes = tag.is_empty() || tag.chars().any(|c| c.is_whitespace() || c.is_control() || c == ':' || c == '#' || c == '"' || c == '\\');
+
+    if needs_quotes {
+        write_quoted(out, tag)
+    } else {
+        out.write_str(tag)
+    }
+}
+
+///
+/// Writes a value as it appears after the ':'
+///
+pub fn write_value<W: fmt::Write>(out: &mut W, value: &TreeValue) -> fmt::Result {
+    match *value {
+        TreeValue::Nothing          => Ok(()),
+        TreeValue::Bool(val)        => out.write_str(if val { "true" } else { "false" }),
+        TreeValue::Int(val)         => write!(out, "{}", val),
+        TreeValue::Real(val)        => if val.is_nan() { out.write_str("nan") } else { write!(out, "{:?}", val) },
+        TreeValue::String(ref val)  => write_quoted(out, val),
+        TreeValue::Data(ref val)    => { out.write_char('<')?; write_base64(out, val)?; out.write_char('>') }
+    }
+}
+
+///
+/// Writes the text representation of a tree to a `fmt::Write`
+///
+/// The text is written a piece at a time, so large trees can be streamed out without building the whole text in
+/// memory. The siblings of the root node are not written.
+///
+pub fn write_tree_text<W: fmt::Write>(tree: &TreeRef, out: &mut W) -> fmt::Result {
+    let mut stack = vec![(tree.to_owned(), 0)];
+
+    while let Some((node, depth)) = stack.pop() {
+        write_indent(out, depth, "    ")?;
+        write_tag(out, node.get_tag())?;
+
+        if !node.get_value().is_nothing() {
+            out.write_str(": ")?;
+            write_value(out, node.get_value())?;
+        }
+
+        out.write_char('\n')?;
+
+        // Children are pushed in reverse so the first one is written next
+        let children: Vec<TreeRef> = node.iter_children().collect();
+        for child in children.into_iter().rev() {
+            stack.push((child, depth+1));
+        }
+    }
+
+    Ok(())
+}
+
+///
+/// Writes the text representation of a tree to an `io::Write`, such as a file
+///
+pub fn write_tree_text_io<W: io::Write>(tree: &TreeRef, out: W) -> io::Result<()> {
+    let mut writer  = IoWriter::new(out);
+    let result      = write_tree_text(tree, &mut writer);
+
+    writer.finish(result)
+}
+
+///
+/// Converts a tree to its text representation
+///
+/// The siblings of the root node are not written.
+///
+pub fn to_tree_text(tree: &TreeRef) -> String {
+    let mut result = String::new();
+
+    // Writing to a String can't fail
+    let _ = write_tree_text(tree, &mut result);
+
+    result
+}
+
+fn base64_decode(encoded: &str) -> Option<Vec<u8>> {
+    let bytes = encoded.as_bytes();
+    if !bytes.len().is_multiple_of(4) {
+        return None;
+    }
+
+    let mut result = vec![];
+
+    for chunk in bytes.chunks(4) {
+        let padding = chunk.iter().rev().take_while(|c| **c == b'=').count();
+        if padding > 2 {
+            return None;
+        }
+
+        let mut bits = 0u32;
+        for (index, c) in chunk[0..4-padding].iter().enumerate() {
+            let sextet = BASE64_CHARS.iter().position(|base64_char| base64_char == c)?;
+            bits |= (sextet as u32) << (18 - index*6);
+        }
+
+        for index in 0..(3-padding) {
+            result.push(((bits >> (16 - index*8)) & 0xff) as u8);
+        }
+    }
+
+    Some(result)
+}
+
+#[cfg(test)]
+mod text_tests {
+    use std::fmt;
+    use std::env;
+    use std::fs::File;
+    use std::io::Write;
+
+    use super::super::super::tree::*;
+
+    fn values_equal(a: &TreeValue, b: &TreeValue) -> bool {
+        match (a, b) {
+            (&TreeValue::Real(a), &TreeValue::Real(b))  => a == b || (a.is_nan() && b.is_nan()),
+            _                                           => a == b
+        }
+    }
+
+    fn trees_equal(a: &TreeRef, b: &TreeRef) -> bool {
+        let a_children: Vec<TreeRef> = a.iter_children().collect();
+        let b_children: Vec<TreeRef> = b.iter_children().collect();
+
+        a.get_tag() == b.get_tag()
+            && values_equal(a.get_value(), b.get_value())
+            && a_children.len() == b_children.len()
+            && a_children.iter().zip(b_children.iter()).all(|(a, b)| trees_equal(a, b))
+    }
+
+    #[test]
+    fn parse_simple_tree() {
+        let tree = parse_tree_text("root\n    one: 1\n    two: 2\n").unwrap();
+
+        assert!(tree.get_tag() == "root");
+        assert!(tree.get_value().is_nothing());
+        assert!(tree.get_child_at("one").get_value().to_int(0) == 1);
+        assert!(tree.get_child_at("two").get_value().to_int(0) == 2);
+        assert!(tree.get_child_ref_at(2).is_none());
+    }
+
+    #[test]
+    fn parse_value_kinds() {
+        let tree = parse_tree_text("root\n  int: -42\n  real: 4.5\n  exp: 1e3\n  yes: true\n  no: false\n  str: \"Hello, \\\"world\\\"\\n\"\n  data: <SGVsbG8=>\n  nothing:\n  also_nothing\n").unwrap();
+
+        assert!(tree.get_child_at("int").get_value() == &TreeValue::Int(-42));
+        assert!(tree.get_child_at("real").get_value() == &TreeValue::Real(4.5));
+        assert!(tree.get_child_at("exp").get_value() == &TreeValue::Real(1000.0));
+        assert!(tree.get_child_at("yes").get_value() == &TreeValue::Bool(true));
+        assert!(tree.get_child_at("no").get_value() == &TreeValue::Bool(false));
+        assert!(tree.get_child_at("str").get_value() == &"Hello, \"world\"\n".to_tree_value());
+        assert!(tree.get_child_at("data").get_value() == &b"Hello".to_vec().to_tree_value());
+        assert!(tree.get_child_at("nothing").get_value().is_nothing());
+        assert!(tree.get_child_at("also_nothing").get_value().is_nothing());
+    }
+
+    #[test]
+    fn comments_and_blank_lines_are_ignored() {
+        let tree = parse_tree_text("# Header comment\n\nroot # the root\n\n    # Indented comment\n    child: \"# not a comment\" # a comment\n   \n").unwrap();
+
+        assert!(tree.get_tag() == "root");
+        assert!(tree.get_child_at(0).get_value().to_str("") == "# not a comment");
+        assert!(tree.get_child_ref_at(1).is_none());
+    }
+
+    #[test]
+    fn parse_nested_children() {
+        let tree = parse_tree_text("root\n  a\n    a1: 1\n    a2: 2\n  b\n    b1\n      b11: 3\n  c: 4\n").unwrap();
+        let expected = tree!("root", tree!("a", ("a1", 1), ("a2", 2)), tree!("b", tree!("b1", ("b11", 3))), ("c", 4));
+
+        assert!(trees_equal(&tree, &expected));
+    }
+
+    #[test]
+    fn format_tree() {
+        let tree = tree!("root", tree!("a", ("a1", 1)), ("b", "text"), ("c", 1.5), ("d", true));
+
+        assert!(to_tree_text(&tree) == "root\n    a\n        a1: 1\n    b: \"text\"\n    c: 1.5\n    d: true\n");
+    }
+
+    ///
+    /// A writer that counts what's written to it, and fails once a limit is passed
+    ///
+    struct LimitedWriter {
+        written: usize,
+        largest_write: usize,
+        limit: usize
+    }
+
+    impl fmt::Write for LimitedWriter {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            if self.written + s.len() > self.limit {
+                return Err(fmt::Error);
+            }
+
+            self.written        += s.len();
+            self.largest_write  = self.largest_write.max(s.len());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn writing_matches_formatting() {
+        let trees = vec![
+            tree!("root", tree!("a", ("a1", 1)), ("b", "text"), ("c", 1.5), ("d", true)),
+            tree!("", ("with space", "quote\"and\\slash"), ("data", vec![1u8, 2, 3, 4]), ("ünïcödé", "\u{7}bell")),
+            ("single", ()).to_tree_node()
+        ];
+
+        for tree in trees {
+            let mut written = String::new();
+            let mut bytes   = vec![];
+
+            write_tree_text(&tree, &mut written).unwrap();
+            write_tree_text_io(&tree, &mut bytes).unwrap();
+
+            assert!(written == to_tree_text(&tree));
+            assert!(bytes == to_tree_text(&tree).into_bytes());
+        }
+    }
+
+    #[test]
+    fn large_trees_are_streamed() {
+        let items: Vec<TreeRef> = (0..1000).map(|index| tree!("item", ("index", index), ("name", "some text that takes up space"))).collect();
+        let tree                = "root".to_tree_node().with_children(&items);
+        let length              = to_tree_text(&tree).len();
+
+        // Nothing is built up before being written
+        let mut writer = LimitedWriter { written: 0, largest_write: 0, limit: usize::MAX };
+        write_tree_text(&tree, &mut writer).unwrap();
+        assert!(writer.written == length);
+        assert!(writer.largest_write < 64);
+
+        // Errors stop the rendering
+        let mut writer = LimitedWriter { written: 0, largest_write: 0, limit: 1000 };
+        assert!(write_tree_text(&tree, &mut writer).is_err());
+        assert!(writer.written <= 1000);
+    }
+
+    #[test]
+    fn round_trip_every_value_kind() {
+        let tree = tree!("root",
+            ("int", i32::MIN), ("real", 2.0), ("tiny", 1e-300), ("bool", false),
+            ("string", "tab\tquote\"backslash\\bell\u{7}unicode\u{e9}"), ("data", vec![0u8, 1, 254, 255]), ("empty_data", Vec::<u8>::new()),
+            "nothing");
+        let text    = to_tree_text(&tree);
+        let parsed  = parse_tree_text(&text).unwrap();
+
+        assert!(trees_equal(&tree, &parsed));
+        assert!(to_tree_text(&parsed) == text);
+    }
+
+    #[test]
+    fn round_trip_awkward_tags() {
+        let tree    = tree!("", "with space", "with:colon", "with#hash", "with\"quote", "tab\there", "ünïcödé");
+        let parsed  = parse_tree_text(&to_tree_text(&tree)).unwrap();
+
+        assert!(trees_equal(&tree, &parsed));
+    }
+
+    #[test]
+    fn round_trip_deep_nesting() {
+        let mut tree = ("leaf", 0).to_tree_node();
+        for depth in 1..200 {
+            tree = tree!(("level", depth), tree);
+        }
+
+        let parsed = parse_tree_text(&to_tree_text(&tree)).unwrap();
+
+        assert!(trees_equal(&tree, &parsed));
+    }
+
+    #[test]
+    fn tabs_can_be_used_for_indentation() {
+        let tree = parse_tree_text("root\n\tchild\n\t\tgrandchild: 1\n").unwrap();
+
+        assert!(tree.get_child_ref_at(("child", "grandchild").to_tree_address()).unwrap().get_value().to_int(0) == 1);
+    }
+
+    #[test]
+    fn mixing_tabs_and_spaces_is_rejected() {
+        assert!(parse_tree_text("root\n    child\n\tother\n").err() == Some(TextParseError::MixedIndentation { line: 3, column: 1 }));
+        assert!(parse_tree_text("root\n \tchild\n").err() == Some(TextParseError::MixedIndentation { line: 2, column: 2 }));
+    }
+
+    #[test]
+    fn inconsistent_indentation_is_rejected() {
+        let result = parse_tree_text("root\n    a\n          a1\n        a2\n");
+
+        match result {
+            Err(TextParseError::Syntax { line, column, .. }) => { assert!(line == 4); assert!(column == 9); },
+            _ => panic!("expected a syntax error")
+        }
+    }
+
+    #[test]
+    fn only_one_root_is_allowed() {
+        match parse_tree_text("root\nanother_root\n") {
+            Err(TextParseError::Syntax { line, .. })    => assert!(line == 2),
+            _                                           => panic!("expected a syntax error")
+        }
+    }
+
+    #[test]
+    fn empty_text_is_an_error() {
+        assert!(parse_tree_text("# nothing here\n").is_err());
+    }
+
+    #[test]
+    fn bad_value_reports_position() {
+        match parse_tree_text("root\n  child: not_a_value\n") {
+            Err(TextParseError::Syntax { line, column, expected })  => { assert!(line == 2); assert!(column == 10); assert!(expected.contains("value")); },
+            _                                                       => panic!("expected a syntax error")
+        }
+    }
+
+    #[test]
+    fn unterminated_string_reports_position() {
+        match parse_tree_text("root: \"unterminated\n") {
+            Err(TextParseError::Syntax { line, column, .. })    => { assert!(line == 1); assert!(column == 20); },
+            _                                                   => panic!("expected a syntax error")
+        }
+    }
+
+    #[test]
+    fn out_of_range_integer_is_an_error() {
+        assert!(parse_tree_text("root: 99999999999\n").is_err());
+    }
+
+    #[test]
+    fn non_finite_reals_round_trip() {
+        let tree    = tree!("root", ("positive", f64::INFINITY), ("negative", f64::NEG_INFINITY), ("nan", f64::NAN));
+        let text    = to_tree_text(&tree);
+        let parsed  = parse_tree_text(&text).unwrap();
+
+        assert!(text == "root\n    positive: inf\n    negative: -inf\n    nan: nan\n");
+        assert!(trees_equal(&tree, &parsed));
+    }
+
+    #[test]
+    fn other_spellings_of_non_finite_reals_are_errors() {
+        for value in ["infinity", "Infinity", "INF", "+inf", "NaN", "NAN", "-nan", "e", "1.2.3x"].iter() {
+            assert!(parse_tree_text(&format!("root: {}\n", value)).is_err());
+        }
+    }
+
+    #[test]
+    fn load_fixture_from_file() {
+        let path = env::temp_dir().join("tametree_text_fixture.tree");
+        File::create(&path).unwrap().write_all(b"fixture\n    value: 42\n").unwrap();
+
+        let tree = load_tree_text(&path).unwrap();
+        assert!(tree.get_child_at("value").get_value().to_int(0) == 42);
+
+        assert!(matches!(load_tree_text(env::temp_dir().join("tametree_no_such_fixture.tree")), Err(TextParseError::Io(_))));
+    }
+
+    #[test]
+    fn interned_import_shares_repeated_strings() {
+        // 10,000 status nodes (in groups, as long sibling chains are dropped recursively)
+        let mut text = "root\n".to_string();
+        for _ in 0..100 {
+            text.push_str("    group\n");
+            for _ in 0..100 {
+                text.push_str("        status: \"active\"\n");
+            }
+        }
+
+        let mut interner    = ValueInterner::new();
+        let tree            = parse_tree_text_interned(&text, &mut interner).unwrap();
+        let statuses: Vec<TreeRef> = tree.iter_children().flat_map(|group| group.iter_children()).collect();
+        let first           = statuses[0].get_value().to_str("").as_ptr();
+
+        assert!(interner.len() == 1);
+        assert!(statuses.len() == 10000);
+        assert!(statuses.iter().all(|status| status.get_value().to_str("").as_ptr() == first));
+    }
+}
@@ -32,11 +32,33 @@ pub trait TreeNode {
     ///
     /// Retrieves a reference to the child of this tree node (or None if this node has no child)
     ///
+    /// Only the first child is returned: the others are its siblings.
+    ///
+    #[cfg_attr(feature = "doctest_support", doc = "```")]
+    #[cfg_attr(not(feature = "doctest_support"), doc = "```ignore")]
+    /// # use tametree::prelude::*;
+    /// # use tametree::doctest_support::*;
+    /// let tree = sample_tree();
+    ///
+    /// assert!(tree.get_child_ref().unwrap().get_tag() == "first");
+    /// assert!(tree.get_child_ref().unwrap().get_child_ref().is_none());
+    /// ```
+    ///
     fn get_child_ref(&self) -> Option<TreeRef>;
 
     ///
     /// Retrieves a reference to the sibling of this tree node (or None if this node has no sibling)
     ///
+    #[cfg_attr(feature = "doctest_support", doc = "```")]
+    #[cfg_attr(not(feature = "doctest_support"), doc = "```ignore")]
+    /// # use tametree::prelude::*;
+    /// # use tametree::doctest_support::*;
+    /// let first = sample_tree().get_child_at("first");
+    ///
+    /// assert!(first.get_sibling_ref().unwrap().get_tag() == "second");
+    /// assert!(sample_tree().get_sibling_ref().is_none());
+    /// ```
+    ///
     fn get_sibling_ref(&self) -> Option<TreeRef>;
 
     ///
@@ -52,11 +74,49 @@ pub trait TreeNode {
     ///
     /// Creates a copy of this node with different references
     ///
+    /// The original node is left unchanged.
+    ///
+    #[cfg_attr(feature = "doctest_support", doc = "```")]
+    #[cfg_attr(not(feature = "doctest_support"), doc = "```ignore")]
+    /// # use tametree::prelude::*;
+    /// # use tametree::doctest_support::*;
+    /// let tree    = sample_tree();
+    /// let emptied = tree.with_references(None, None);
+    ///
+    /// assert!(emptied.get_tag() == "root");
+    /// assert!(emptied.get_child_ref().is_none());
+    /// assert!(child_tags(&tree) == vec!["first", "second", "third"]);
+    /// ```
+    ///
     fn with_references(&self, new_child: Option<&TreeRef>, new_sibling: Option<&TreeRef>) -> TreeRef;
 
     ///
     /// Creates a copy of this node with a specific child node
     ///
+    /// The new child keeps its own siblings, so this replaces all of the children of this node.
+    ///
+    #[cfg_attr(feature = "doctest_support", doc = "```")]
+    #[cfg_attr(not(feature = "doctest_support"), doc = "```ignore")]
+    /// # use tametree::prelude::*;
+    /// # use tametree::doctest_support::*;
+    /// let new_child   = ("a", 1).to_tree_node().with_sibling_node(Some(&("b", 2).to_tree_node()));
+    /// let tree        = sample_tree().with_child_node(Some(&new_child));
+    ///
+    /// assert!(child_tags(&tree) == vec!["a", "b"]);
+    /// ```
+    ///
+    /// Only this node is copied: the new child and its siblings are shared with wherever they came from.
+    ///
+    #[cfg_attr(feature = "doctest_support", doc = "```")]
+    #[cfg_attr(not(feature = "doctest_support"), doc = "```ignore")]
+    /// # use tametree::prelude::*;
+    /// # use tametree::doctest_support::*;
+    /// let tree    = sample_tree();
+    /// let trimmed = tree.with_child_node(tree.get_child_ref_at("second").as_ref());
+    ///
+    /// assert!(child_tags(&trimmed) == vec!["second", "third"]);
+    /// ```
+    ///
     #[inline]
     fn with_child_node(&self, new_child: Option<&TreeRef>) -> TreeRef {
         self.with_references(new_child, self.get_sibling_ref().as_ref())
@@ -65,6 +125,19 @@ pub trait TreeNode {
     ///
     /// Creates a copy of this node with a specific sibling node
     ///
+    /// This only changes the following sibling: it doesn't add the copy to the parent of this node.
+    ///
+    #[cfg_attr(feature = "doctest_support", doc = "```")]
+    #[cfg_attr(not(feature = "doctest_support"), doc = "```ignore")]
+    /// # use tametree::prelude::*;
+    /// # use tametree::doctest_support::*;
+    /// let tree    = sample_tree();
+    /// let first   = tree.get_child_at("first").with_sibling_node(None);
+    ///
+    /// assert!(first.get_sibling_ref().is_none());
+    /// assert!(tree.get_child_at("first").get_sibling_ref().is_some());
+    /// ```
+    ///
     #[inline]
     fn with_sibling_node(&self, new_sibling: Option<&TreeRef>) -> TreeRef {
         self.with_references(self.get_child_ref().as_ref(), new_sibling)
@@ -73,6 +146,19 @@ pub trait TreeNode {
     ///
     /// Creates a copy of this node with a specific set of child nodes
     ///
+    /// The existing siblings of the new children are replaced, so each node in the list becomes exactly one child.
+    ///
+    #[cfg_attr(feature = "doctest_support", doc = "```")]
+    #[cfg_attr(not(feature = "doctest_support"), doc = "```ignore")]
+    /// # use tametree::prelude::*;
+    /// # use tametree::doctest_support::*;
+    /// let tree        = sample_tree();
+    /// let reversed    = tree.with_children(&vec![tree.get_child_at("third"), tree.get_child_at("first")]);
+    ///
+    /// assert!(child_tags(&reversed) == vec!["third", "first"]);
+    /// assert!(child_tags(&reversed.get_child_at("third")) == vec!["child", "other"]);
+    /// ```
+    ///
     fn with_children(&self, new_children: &Vec<TreeRef>) -> TreeRef {
         let mut new_child = None;
 
@@ -86,6 +172,16 @@ pub trait TreeNode {
     ///
     /// Looks up the child at the specified index
     ///
+    #[cfg_attr(feature = "doctest_support", doc = "```")]
+    #[cfg_attr(not(feature = "doctest_support"), doc = "```ignore")]
+    /// # use tametree::prelude::*;
+    /// # use tametree::doctest_support::*;
+    /// let tree = sample_tree();
+    ///
+    /// assert!(tree.lookup_child_at_index(1).unwrap().get_tag() == "second");
+    /// assert!(tree.lookup_child_at_index(3).is_none());
+    /// ```
+    ///
     fn lookup_child_at_index(&self, index: usize) -> Option<TreeRef> {
         let mut result = self.get_child_ref();
 
@@ -97,7 +193,17 @@ pub trait TreeNode {
     }
 
     ///
-    /// Looks up the child at the specified index
+    /// Looks up the child with the specified tag
+    ///
+    /// If several children have the same tag, this finds the first of them.
+    ///
+    /// ```
+    /// # use tametree::prelude::*;
+    /// let tree = tree!("root", ("item", 1), ("item", 2));
+    ///
+    /// assert!(tree.lookup_child_with_tag("item").unwrap().get_value().to_int(0) == 1);
+    /// assert!(tree.lookup_child_with_tag("missing").is_none());
+    /// ```
     ///
     fn lookup_child_with_tag(&self, tag: &str) -> Option<TreeRef> {
         let mut current = self.get_child_ref();
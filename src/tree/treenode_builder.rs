@@ -23,18 +23,83 @@
 macro_rules! tree {
     ( $root: expr, $( $child: expr ), * ) => {
         {
-            let root            = $root.to_tree_node();
-            let mut child_list  = Vec::new();
-
-            $(
-                child_list.push($child.to_tree_node());
-            )*
+            let root        = $root.to_tree_node();
+            let child_list  = vec![ $( $child.to_tree_node() ),* ];
 
             root.with_children(&child_list)
         }
     }
 }
 
+///
+/// Macro that declares a struct that can be encoded to and decoded from a tree node
+///
+/// Each field becomes a child of the encoded node, tagged with the field name. The field types must
+/// themselves be `Encodable` and `Decodable`.
+///
+/// ```
+/// # use tametree::prelude::*;
+/// tree_struct! {
+///     struct Point {
+///         x: i32,
+///         y: i32
+///     }
+/// }
+///
+/// let point = Point { x: 1, y: 2 }.to_tree_node();
+/// assert!(point.get_child_at("y").get_value().to_int(0) == 2);
+/// ```
+///
+#[macro_export]
+macro_rules! tree_struct {
+    ( @one $field: ident ) => { 1 };
+
+    ( $(#[$attr: meta])* $vis: vis struct $name: ident { $( $field_vis: vis $field: ident : $field_type: ty ),* $(,)* } ) => {
+        $(#[$attr])*
+        $vis struct $name {
+            $( $field_vis $field: $field_type ),*
+        }
+
+        impl $crate::rustc_serialize::Encodable for $name {
+            #[allow(unused_assignments, unused_mut, unused_variables)]
+            fn encode<S: $crate::rustc_serialize::Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
+                s.emit_struct(stringify!($name), 0 $( + tree_struct!(@one $field) )*, |s| {
+                    let mut field_index = 0;
+
+                    $(
+                        s.emit_struct_field(stringify!($field), field_index, |s| $crate::rustc_serialize::Encodable::encode(&self.$field, s))?;
+                        field_index += 1;
+                    )*
+
+                    Ok(())
+                })
+            }
+        }
+
+        impl $crate::rustc_serialize::Decodable for $name {
+            #[allow(unused_assignments, unused_mut, unused_variables)]
+            fn decode<D: $crate::rustc_serialize::Decoder>(d: &mut D) -> Result<$name, D::Error> {
+                d.read_struct(stringify!($name), 0 $( + tree_struct!(@one $field) )*, |d| {
+                    let mut field_index = 0;
+
+                    Ok($name {
+                        $(
+                            $field: {
+                                let index = field_index;
+                                field_index += 1;
+
+                                d.read_struct_field(stringify!($field), index, $crate::rustc_serialize::Decodable::decode)?
+                            }
+                        ),*
+                    })
+                })
+            }
+        }
+
+        impl $crate::tree::EncodeToTreeNode for $name { }
+    }
+}
+
 #[cfg(test)]
 mod treenode_builder_tests {
     use super::super::treenode::*;
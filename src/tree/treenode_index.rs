@@ -68,11 +68,34 @@ pub trait TreeNodeLookup {
     ///
     /// Looks up a child node at a particular index (panics if the child does not exist)
     ///
+    /// Indexes can be child numbers, tags or whole addresses.
+    ///
+    #[cfg_attr(feature = "doctest_support", doc = "```")]
+    #[cfg_attr(not(feature = "doctest_support"), doc = "```ignore")]
+    /// # use tametree::prelude::*;
+    /// # use tametree::doctest_support::*;
+    /// let tree = sample_tree();
+    ///
+    /// assert!(tree.get_child_at(1).get_tag() == "second");
+    /// assert!(tree.get_child_at("third").get_child_at(1).get_tag() == "other");
+    /// assert!(tree.get_child_at(tagged_address()).get_value().to_int(0) == 3);
+    /// ```
+    ///
     fn get_child_at<TIndex: TreeNodeIndex>(&self, index: TIndex) -> TreeRef;
 
     ///
     /// Looks up a child node at a particular index
     ///
+    #[cfg_attr(feature = "doctest_support", doc = "```")]
+    #[cfg_attr(not(feature = "doctest_support"), doc = "```ignore")]
+    /// # use tametree::prelude::*;
+    /// # use tametree::doctest_support::*;
+    /// let tree = sample_tree();
+    ///
+    /// assert!(tree.get_child_ref_at(indexed_address()).unwrap().get_tag() == "child");
+    /// assert!(tree.get_child_ref_at(("third", "missing").to_tree_address()).is_none());
+    /// ```
+    ///
     fn get_child_ref_at<TIndex: TreeNodeIndex>(&self, index: TIndex) -> Option<TreeRef>;
 }
 
@@ -0,0 +1,332 @@
+//
+//   Copyright 2016 Andrew Hunter
+//
+//   Licensed under the Apache License, Version 2.0 (the "License");
+//   you may not use this file except in compliance with the License.
+//   You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+//   Unless required by applicable law or agreed to in writing, software
+//   distributed under the License is distributed on an "AS IS" BASIS,
+//   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//   See the License for the specific language governing permissions and
+//   limitations under the License.
+//
+
+//!
+//! # Value history
+//!
+//! Snapshots of a whole tree are too coarse to find out what happened to a single value: a value that briefly
+//! changed and changed back is easy to miss. A `ValueHistoryTracker` keeps the most recent values of a few
+//! selected nodes instead.
+//!
+//! Every change increases the generation of the tracker. Each tracked node affected by the change (as determined
+//! by `applies_to()` with the `SubTree` extent) records its value after the change along with the generation.
+//! A node that was removed records `TreeValue::Nothing`. Each node keeps at most `ring_size` entries, discarding
+//! the oldest first, so the memory used is bounded by the ring size and the number of tracked nodes.
+//!
+//! Nodes are selected either by address or by a parent address, in which case every child of the parent is tracked
+//! (including children created later on). Changes that can't affect a selection don't cause any tree lookups.
+//! Where it's not possible to tell whether or not a change affects a node (for example, because one address is
+//! tagged and the other is indexed), the node is assumed to be affected.
+//!
+//! The histories can be represented as a tree for publication. Each tracked node is a child tagged with its address
+//! (eg `."prices"."apple"`), and each entry is a child of that tagged with the generation.
+//!
+
+use std::ops::Range;
+
+use super::treenode::*;
+use super::address::*;
+use super::extent::*;
+use super::change::*;
+use super::values::*;
+use super::iterator::*;
+
+///
+/// The tag used for the node that value histories are published under
+///
+pub const VALUE_HISTORY_TAG: &str = "__value_history";
+
+///
+/// Selects the nodes whose values are tracked by a `ValueHistoryTracker`
+///
+#[derive(Clone, PartialEq)]
+pub enum HistorySelector {
+    /// Tracks the node at an address
+    Address(TreeAddress),
+
+    /// Tracks each child of the node at an address (by tag), including children that are created later on
+    ChildrenOf(TreeAddress)
+}
+
+///
+/// Keeps the recent values of a set of nodes in a tree
+///
+pub struct ValueHistoryTracker {
+    /// The nodes to track
+    selectors: Vec<HistorySelector>,
+
+    /// The maximum number of entries to keep for each node
+    ring_size: usize,
+
+    /// The generation of the tree, increased for every change
+    generation: u64,
+
+    /// The recent values of each node that has been affected by a change, oldest first
+    histories: Vec<(TreeAddress, Vec<(u64, TreeValue)>)>,
+
+    /// The number of times a node has been looked up in a tree
+    lookups: usize
+}
+
+impl ValueHistoryTracker {
+    ///
+    /// Creates a tracker that keeps up to `ring_size` values for each of the selected nodes
+    ///
+    /// The ring size must be at least 1.
+    ///
+    pub fn new(ring_size: usize, selectors: Vec<HistorySelector>) -> ValueHistoryTracker {
+        assert!(ring_size > 0, "value histories must keep at least one entry");
+
+        ValueHistoryTracker { selectors, ring_size, generation: 0, histories: vec![], lookups: 0 }
+    }
+
+    ///
+    /// Creates a tracker that keeps up to `ring_size` values for each of a list of addresses
+    ///
+    pub fn for_addresses(ring_size: usize, addresses: Vec<TreeAddress>) -> ValueHistoryTracker {
+        Self::new(ring_size, addresses.into_iter().map(HistorySelector::Address).collect())
+    }
+
+    ///
+    /// Adds an entry to the history for an address
+    ///
+    fn push(&mut self, address: TreeAddress, value: TreeValue) {
+        let generation  = self.generation;
+        let ring_size   = self.ring_size;
+
+        let history_index = match self.histories.iter().position(|(existing, _)| *existing == address) {
+            Some(index) => index,
+            None        => {
+                self.histories.push((address, Vec::with_capacity(ring_size)));
+                self.histories.len()-1
+            }
+        };
+
+        let history = &mut self.histories[history_index].1;
+        if history.len() >= ring_size {
+            history.remove(0);
+        }
+        history.push((generation, value));
+    }
+
+    ///
+    /// Reads the value of the node at an address, or `Nothing` if it doesn't exist
+    ///
+    fn read_value(&mut self, tree: &TreeRef, address: &TreeAddress) -> TreeValue {
+        self.lookups += 1;
+
+        address.lookup_index(tree)
+            .map(|node| node.get_value().clone())
+            .unwrap_or(TreeValue::Nothing)
+    }
+
+    ///
+    /// Finds the tagged addresses of the children of the node at a parent address
+    ///
+    fn children_of(&mut self, tree: &TreeRef, parent: &TreeAddress, into: &mut Vec<TreeAddress>) {
+        self.lookups += 1;
+
+        if let Some(node) = parent.lookup_index(tree) {
+            for child in node.iter_children() {
+                let address = parent.to_tree_address_then(child.get_tag().to_tree_address());
+
+                if !into.contains(&address) {
+                    into.push(address);
+                }
+            }
+        }
+    }
+
+    ///
+    /// Records the values of the tracked nodes that are affected by a change
+    ///
+    pub fn record_change(&mut self, tree_before: &TreeRef, change: &TreeChange, tree_after: &TreeRef) {
+        self.generation += 1;
+
+        for selector in self.selectors.clone() {
+            match selector {
+                HistorySelector::Address(address) => {
+                    if change.applies_to(&address, &TreeExtent::SubTree).unwrap_or(true) {
+                        let value = self.read_value(tree_after, &address);
+                        self.push(address, value);
+                    }
+                },
+
+                HistorySelector::ChildrenOf(parent) => {
+                    if !change.applies_to(&parent, &TreeExtent::SubTree).unwrap_or(true) {
+                        continue;
+                    }
+
+                    // Children that were removed by the change are only in the tree from before it
+                    let mut children = vec![];
+                    self.children_of(tree_before, &parent, &mut children);
+                    self.children_of(tree_after, &parent, &mut children);
+
+                    for address in children {
+                        if change.applies_to(&address, &TreeExtent::SubTree).unwrap_or(true) {
+                            let value = self.read_value(tree_after, &address);
+                            self.push(address, value);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    ///
+    /// The recent values of the node at an address, oldest first
+    ///
+    /// Addresses are matched exactly, so children selected by `ChildrenOf` must be addressed by tag.
+    ///
+    pub fn history(&self, address: &TreeAddress) -> &[(u64, TreeValue)] {
+        self.histories.iter()
+            .find(|(existing, _)| existing == address)
+            .map(|(_, history)| history.as_slice())
+            .unwrap_or(&[])
+    }
+
+    ///
+    /// True if the history for an address has an entry for any of a range of generations
+    ///
+    /// Entries that have been discarded from the history are not considered.
+    ///
+    pub fn changed_in(&self, address: &TreeAddress, generations: Range<u64>) -> bool {
+        self.history(address).iter().any(|(generation, _)| generations.contains(generation))
+    }
+
+    ///
+    /// The generation of the tree (the number of changes that have been recorded)
+    ///
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    ///
+    /// The number of times this tracker has looked up a node in a tree
+    ///
+    pub fn lookups(&self) -> usize {
+        self.lookups
+    }
+
+    ///
+    /// Creates a tree containing the histories (tagged with `VALUE_HISTORY_TAG`)
+    ///
+    pub fn as_tree(&self) -> TreeRef {
+        let nodes: Vec<TreeRef> = self.histories.iter()
+            .map(|(address, history)| {
+                let entries: Vec<TreeRef> = history.iter()
+                    .map(|(generation, value)| (generation.to_string().as_str(), value.clone()).to_tree_node())
+                    .collect();
+
+                address.to_string().as_str().to_tree_node().with_children(&entries)
+            })
+            .collect();
+
+        VALUE_HISTORY_TAG.to_tree_node().with_children(&nodes)
+    }
+}
+
+#[cfg(test)]
+mod value_history_tests {
+    use super::super::super::tree::*;
+
+    fn apply(tracker: &mut ValueHistoryTracker, tree: &TreeRef, change: TreeChange) -> TreeRef {
+        let new_tree = change.apply(tree);
+        tracker.record_change(tree, &change, &new_tree);
+        new_tree
+    }
+
+    fn values(tracker: &ValueHistoryTracker, address: TreeAddress) -> Vec<(u64, i32)> {
+        tracker.history(&address).iter().map(|(generation, value)| (*generation, if value.is_nothing() { -1 } else { value.to_int(0) })).collect()
+    }
+
+    #[test]
+    fn records_scripted_changes_including_removal() {
+        let mut tree    = tree!("root", tree!("prices", ("apple", 10), ("pear", 20)));
+        let mut tracker = ValueHistoryTracker::for_addresses(8, vec![("prices", "apple").to_tree_address()]);
+
+        tree = apply(&mut tracker, &tree, TreeChange::new(&("prices", "apple"), &("apple", 11)));
+        tree = apply(&mut tracker, &tree, TreeChange::new(&("prices", "pear"), &("pear", 21)));
+        tree = apply(&mut tracker, &tree, TreeChange::new(&("prices", "apple"), &("apple", 0)));
+        tree = apply(&mut tracker, &tree, TreeChange::new(&("prices", "apple"), &()));
+        tree = apply(&mut tracker, &tree, TreeChange::new(&("prices", 1), &("apple", 12)));
+        let _ = apply(&mut tracker, &tree, TreeChange::new(&"prices", &tree!("prices", ("apple", 13))));
+
+        // The indexed change can't be compared with the tagged address, so it's recorded too
+        assert!(values(&tracker, ("prices", "apple").to_tree_address()) == vec![(1, 11), (3, 0), (4, -1), (5, 12), (6, 13)]);
+        assert!(tracker.changed_in(&("prices", "apple").to_tree_address(), 3..4));
+        assert!(!tracker.changed_in(&("prices", "apple").to_tree_address(), 2..3));
+    }
+
+    #[test]
+    fn untracked_changes_do_not_look_anything_up() {
+        let mut tree    = tree!("root", tree!("prices", ("apple", 10)), tree!("stock", ("apple", 5)));
+        let mut tracker = ValueHistoryTracker::new(4, vec![
+            HistorySelector::Address(("prices", "apple").to_tree_address()),
+            HistorySelector::ChildrenOf("prices".to_tree_address())
+        ]);
+
+        for count in 0..10 {
+            tree = apply(&mut tracker, &tree, TreeChange::new(&("stock", "apple"), &("apple", count)));
+        }
+
+        assert!(tracker.lookups() == 0);
+        assert!(tracker.history(&("stock", "apple").to_tree_address()).is_empty());
+        assert!(tracker.generation() == 10);
+    }
+
+    #[test]
+    fn children_selector_tracks_new_children() {
+        let mut tree    = tree!("root", tree!("prices", ("apple", 10)), "other");
+        let mut tracker = ValueHistoryTracker::new(4, vec![HistorySelector::ChildrenOf("prices".to_tree_address())]);
+
+        tree = apply(&mut tracker, &tree, TreeChange::new(&("prices", "apple"), &("apple", 11)));
+        tree = apply(&mut tracker, &tree, TreeChange::new(&("prices", 1), &("pear", 20)));
+        let _ = apply(&mut tracker, &tree, TreeChange::new(&("prices", "pear"), &("pear", 21)));
+
+        assert!(values(&tracker, ("prices", "apple").to_tree_address()) == vec![(1, 11), (2, 11)]);
+        assert!(values(&tracker, ("prices", "pear").to_tree_address()) == vec![(2, 20), (3, 21)]);
+    }
+
+    #[test]
+    fn ring_keeps_most_recent_entries() {
+        let mut tree    = tree!("root", ("a", 0));
+        let mut tracker = ValueHistoryTracker::for_addresses(3, vec!["a".to_tree_address()]);
+
+        for value in 1..10 {
+            tree = apply(&mut tracker, &tree, TreeChange::new(&"a", &("a", value)));
+        }
+
+        assert!(values(&tracker, "a".to_tree_address()) == vec![(7, 7), (8, 8), (9, 9)]);
+        assert!(!tracker.changed_in(&"a".to_tree_address(), 1..7));
+    }
+
+    #[test]
+    fn history_tree_lists_entries() {
+        let mut tree    = tree!("root", ("a", 0));
+        let mut tracker = ValueHistoryTracker::for_addresses(3, vec!["a".to_tree_address()]);
+
+        tree = apply(&mut tracker, &tree, TreeChange::new(&"a", &("a", 1)));
+        let _ = apply(&mut tracker, &tree, TreeChange::new(&"a", &("a", 2)));
+
+        let history = tracker.as_tree();
+        assert!(history.get_tag() == VALUE_HISTORY_TAG);
+
+        let entries = history.get_child_ref_at("a".to_tree_address().to_string().as_str()).unwrap();
+        assert!(entries.get_child_at("1").get_value().to_int(0) == 1);
+        assert!(entries.get_child_at("2").get_value().to_int(0) == 2);
+    }
+}
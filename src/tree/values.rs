@@ -14,17 +14,24 @@
 //   limitations under the License.
 //
 
+use std::rc::*;
+use std::hash::Hash;
+use std::collections::HashSet;
+
 ///
 /// Represents the possible values of an attribute on a tree node
 ///
+/// Strings and data are reference counted, so cloning a value (which happens whenever a node is copied while
+/// applying a change) never copies their contents.
+///
 #[derive(PartialEq, Clone)]
 pub enum TreeValue {
     Nothing,
     Bool(bool),
     Int(i32),
     Real(f64),
-    String(String),
-    Data(Vec<u8>)
+    String(Rc<str>),
+    Data(Rc<[u8]>)
 }
 
 ///
@@ -100,13 +107,163 @@ impl ToTreeValue for f64 {
 }
 
 impl<'a> ToTreeValue for &'a str {
-    fn to_tree_value(&self) -> TreeValue { TreeValue::String(self.to_string()) }
+    fn to_tree_value(&self) -> TreeValue { TreeValue::String(Rc::from(*self)) }
 }
 
 impl ToTreeValue for String {
-    fn to_tree_value(&self) -> TreeValue { TreeValue::String(self.to_owned()) }
+    fn to_tree_value(&self) -> TreeValue { TreeValue::String(Rc::from(&**self)) }
 }
 
 impl ToTreeValue for Vec<u8> {
-    fn to_tree_value(&self) -> TreeValue { TreeValue::Data(self.to_owned()) }
+    fn to_tree_value(&self) -> TreeValue { TreeValue::Data(Rc::from(&**self)) }
+}
+
+///
+/// Shares identical strings and data between tree values
+///
+/// Values built with `ToTreeValue` each get their own copy of their string or data. Passing them through
+/// `intern()` instead makes every equal value share a single copy, which saves memory when a tree contains
+/// many copies of the same string (such as the values of an enumerated field).
+///
+pub struct ValueInterner {
+    strings: HashSet<Rc<str>>,
+    data: HashSet<Rc<[u8]>>
+}
+
+impl ValueInterner {
+    ///
+    /// Creates a new interner that hasn't seen any values
+    ///
+    pub fn new() -> ValueInterner {
+        ValueInterner { strings: HashSet::new(), data: HashSet::new() }
+    }
+
+    ///
+    /// Returns a value equal to the specified one, sharing its string or data with any equal value passed in before
+    ///
+    pub fn intern<TValue: ToTreeValue>(&mut self, value: TValue) -> TreeValue {
+        match value.to_tree_value() {
+            TreeValue::String(string)   => TreeValue::String(Self::share(&mut self.strings, string)),
+            TreeValue::Data(data)       => TreeValue::Data(Self::share(&mut self.data, data)),
+            other                       => other
+        }
+    }
+
+    ///
+    /// The number of distinct strings and data values that have been interned
+    ///
+    pub fn len(&self) -> usize {
+        self.strings.len() + self.data.len()
+    }
+
+    ///
+    /// True if nothing has been interned yet
+    ///
+    pub fn is_empty(&self) -> bool {
+        self.strings.is_empty() && self.data.is_empty()
+    }
+
+    fn share<T: ?Sized + Eq + Hash>(values: &mut HashSet<Rc<T>>, value: Rc<T>) -> Rc<T> {
+        if let Some(existing) = values.get(&value) {
+            return existing.clone();
+        }
+
+        values.insert(value.clone());
+        value
+    }
+}
+
+impl Default for ValueInterner {
+    fn default() -> ValueInterner {
+        ValueInterner::new()
+    }
+}
+
+#[cfg(test)]
+mod values_tests {
+    use std::rc::*;
+
+    use super::super::super::tree::*;
+
+    fn string_ptr(value: &TreeValue) -> *const u8 {
+        value.to_str("").as_ptr()
+    }
+
+    #[test]
+    fn cloning_shares_string() {
+        let value = "active".to_tree_value();
+
+        assert!(string_ptr(&value.clone()) == string_ptr(&value));
+        assert!(value.to_str("") == "active");
+    }
+
+    #[test]
+    fn interned_values_share_contents() {
+        let mut interner    = ValueInterner::new();
+        let first           = interner.intern("pending");
+        let second          = interner.intern("pending".to_string());
+        let other           = interner.intern("error");
+        let number          = interner.intern(42);
+
+        assert!(string_ptr(&first) == string_ptr(&second));
+        assert!(string_ptr(&first) != string_ptr(&other));
+        assert!(first == "pending".to_tree_value());
+        assert!(number == TreeValue::Int(42));
+        assert!(interner.len() == 2);
+
+        let data        = interner.intern(vec![1u8, 2, 3]);
+        let same_data   = interner.intern(vec![1u8, 2, 3]);
+        match (data, same_data) {
+            (TreeValue::Data(ref a), TreeValue::Data(ref b))    => assert!(Rc::ptr_eq(a, b)),
+            _                                                   => panic!("expected data values")
+        }
+    }
+
+    #[test]
+    fn applying_change_does_not_copy_strings() {
+        let children: Vec<TreeRef>  = (0..1000).map(|index| ("status", if index % 2 == 0 { "active" } else { "pending" }).to_tree_node()).collect();
+        let tree                    = ("root", ()).to_tree_node().with_children(&children);
+        let before: Vec<*const u8>  = tree.iter_children().map(|child| string_ptr(child.get_value())).collect();
+
+        // Changing the last child copies every node before it
+        let changed                 = TreeChange::new(&999, &("status", "error")).apply(&tree);
+        let after: Vec<*const u8>   = changed.iter_children().map(|child| string_ptr(child.get_value())).collect();
+
+        assert!(changed.get_child_at(999).get_value().to_str("") == "error");
+        assert!(before[0..999] == after[0..999]);
+    }
+
+    #[test]
+    fn interned_status_is_stored_once_for_ten_thousand_nodes() {
+        // Grouped so that no sibling chain is long enough to be dropped with deep recursion
+        let mut text = "root\n".to_string();
+        for _ in 0..100 {
+            text.push_str("    group\n");
+            for _ in 0..100 {
+                text.push_str("        status: \"active\"\n");
+            }
+        }
+
+        let mut interner    = ValueInterner::new();
+        let interned        = parse_tree_text_interned(&text, &mut interner).unwrap();
+        let separate        = parse_tree_text(&text).unwrap();
+        let statuses        = |tree: &TreeRef| tree.iter_children().flat_map(|group| group.iter_children()).collect::<Vec<_>>();
+
+        let shared          = statuses(&interned);
+        let copies          = statuses(&separate);
+        assert!(shared.len() == 10000);
+
+        // Every interned node refers to the same string (which is also held by the interner)...
+        match *shared[0].get_value() {
+            TreeValue::String(ref first)    => {
+                assert!(shared.iter().all(|status| string_ptr(status.get_value()) == first.as_ptr()));
+                assert!(Rc::strong_count(first) == 10000 + 1);
+            },
+            _                               => panic!("expected a string value")
+        }
+
+        // ... whereas without the interner each node has its own copy
+        assert!(interner.len() == 1);
+        assert!(string_ptr(copies[0].get_value()) != string_ptr(copies[1].get_value()));
+    }
 }
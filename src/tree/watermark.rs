@@ -0,0 +1,326 @@
+//
+//   Copyright 2016 Andrew Hunter
+//
+//   Licensed under the Apache License, Version 2.0 (the "License");
+//   you may not use this file except in compliance with the License.
+//   You may obtain a copy of the License at
+//
+//       http://www.apache.org/licenses/LICENSE-2.0
+//
+//   Unless required by applicable law or agreed to in writing, software
+//   distributed under the License is distributed on an "AS IS" BASIS,
+//   WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//   See the License for the specific language governing permissions and
+//   limitations under the License.
+//
+
+//!
+//! # Watermarks
+//!
+//! A watermark is a version number for a part of a tree that changes whenever that part of the tree changes. It
+//! provides a cheap way for a cache to tell if its copy of a subtree is out of date without comparing trees.
+//!
+//! `Watermarks` tracks a set of address prefixes: either a fixed list, or every child of the root node. Every
+//! change increases the version number, and each prefix affected by the change (as determined by `applies_to()`
+//! with the `SubTree` extent) records the new version as its watermark. Where it's not possible to tell whether
+//! or not a change affects a prefix (for example, because one address is tagged and the other is indexed), the
+//! prefix is assumed to be affected: an unnecessary change to a watermark is harmless but a missed one is not.
+//!
+//! The watermarks can also be represented as a tree, so they can be published along with the tree they describe.
+//! This tree mirrors the prefixes: the watermark for `.a.b.` is the value of the node `.a.b.` in the watermark
+//! tree (as a string, as the versions don't fit in a `TreeValue::Int`). Indexed parts of the prefix use the index
+//! as the tag.
+//!
+
+use super::treenode::*;
+use super::address::*;
+use super::extent::*;
+use super::change::*;
+use super::values::*;
+use super::iterator::*;
+use super::address_cache::*;
+
+///
+/// The tag used for the node that watermarks are published under
+///
+pub const WATERMARKS_TAG: &str = "__watermarks";
+
+///
+/// Tracks the version of a set of subtrees of a tree
+///
+pub struct Watermarks {
+    /// The prefixes to track, or None to track each child of the root node
+    prefixes: Option<Vec<TreeAddress>>,
+
+    /// The version of the tree as a whole, increased for every change
+    version: u64,
+
+    /// The version where each tracked prefix last changed
+    marks: Vec<(TreeAddress, u64)>
+}
+
+///
+/// Retrieves the number of parts in an address
+///
+fn address_depth(address: &TreeAddress) -> usize {
+    let mut depth   = 0;
+    let mut current = address;
+
+    loop {
+        match *current {
+            TreeAddress::Here                       => return depth,
+            TreeAddress::ChildAtIndex(_, ref next)  => { depth += 1; current = next; },
+            TreeAddress::ChildWithTag(_, ref next)  => { depth += 1; current = next; }
+        }
+    }
+}
+
+impl Watermarks {
+    ///
+    /// Creates a set of watermarks that tracks each child of the root node (identified by its tag)
+    ///
+    pub fn new() -> Watermarks {
+        Watermarks { prefixes: None, version: 0, marks: vec![] }
+    }
+
+    ///
+    /// Creates a set of watermarks that tracks the specified address prefixes
+    ///
+    pub fn with_prefixes(prefixes: Vec<TreeAddress>) -> Watermarks {
+        let marks = prefixes.iter().map(|prefix| (prefix.clone(), 0)).collect();
+
+        Watermarks { prefixes: Some(prefixes), version: 0, marks }
+    }
+
+    ///
+    /// Returns whether or not a change affects the subtree at a particular prefix
+    ///
+    fn affects(change: &TreeChange, prefix: &TreeAddress, tree_before: &TreeRef, tree_after: &TreeRef) -> bool {
+        if let Some(applies) = change.applies_to(prefix, &TreeExtent::SubTree) {
+            return applies;
+        }
+
+        // The addresses are in different formats: resolve the prefix to indexes and try again
+        let mut cache           = AddressCache::new();
+        let resolved_before     = cache.canonicalize(tree_before, prefix);
+        let resolved_after      = cache.canonicalize(tree_after, prefix);
+
+        // If the prefix refers to a different node after the change then it's affected
+        if resolved_before != resolved_after {
+            return true;
+        }
+
+        resolved_before
+            .and_then(|resolved| change.applies_to(&resolved, &TreeExtent::SubTree))
+            .unwrap_or(true)
+    }
+
+    ///
+    /// Finds the prefixes that should be checked for a change
+    ///
+    fn prefixes_for_change(&self, tree_before: &TreeRef, tree_after: &TreeRef) -> Vec<TreeAddress> {
+        match self.prefixes {
+            Some(ref prefixes)  => prefixes.clone(),
+
+            None                => {
+                // Every root child from before and after the change (the watermarks themselves are not tracked)
+                let mut prefixes: Vec<TreeAddress> = vec![];
+
+                for child in tree_before.iter_children().chain(tree_after.iter_children()) {
+                    let prefix = child.get_tag().to_tree_address();
+
+                    if child.get_tag() != WATERMARKS_TAG && !prefixes.contains(&prefix) {
+                        prefixes.push(prefix);
+                    }
+                }
+
+                prefixes
+            }
+        }
+    }
+
+    ///
+    /// Updates the watermarks for a change
+    ///
+    pub fn record_change(&mut self, tree_before: &TreeRef, change: &TreeChange, tree_after: &TreeRef) {
+        self.version += 1;
+
+        for prefix in self.prefixes_for_change(tree_before, tree_after) {
+            let version = self.version;
+
+            // Prefixes that haven't been seen before start at the current version
+            match self.marks.iter().position(|(address, _)| *address == prefix) {
+                Some(mark_index)    => if Self::affects(change, &prefix, tree_before, tree_after) { self.marks[mark_index].1 = version },
+                None                => self.marks.push((prefix, version))
+            }
+        }
+    }
+
+    ///
+    /// Retrieves the watermark for a particular address
+    ///
+    /// This is the watermark of the most specific tracked prefix that contains the address. Addresses that
+    /// are not inside a tracked prefix (including the root address) use the version of the whole tree.
+    ///
+    pub fn watermark(&self, address: &TreeAddress) -> u64 {
+        self.marks.iter()
+            .filter(|&(prefix, _)| prefix.is_parent_of(address).unwrap_or(false))
+            .max_by_key(|&(prefix, _)| address_depth(prefix))
+            .map(|&(_, mark)| mark)
+            .unwrap_or(self.version)
+    }
+
+    ///
+    /// The version of the tree as a whole
+    ///
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    ///
+    /// Creates a tree containing the current watermarks (tagged with `WATERMARKS_TAG`)
+    ///
+    pub fn to_tree_node(&self) -> TreeRef {
+        let mut result = WATERMARKS_TAG.to_tree_node();
+
+        // Set the shallowest prefixes first so the deeper ones can be added beneath them
+        let mut marks: Vec<&(TreeAddress, u64)> = self.marks.iter().collect();
+        marks.sort_by_key(|&(prefix, _)| address_depth(prefix));
+
+        for &&(ref prefix, mark) in marks.iter() {
+            // Build a tagged version of the prefix, creating any nodes that don't exist yet along the way
+            let mut tagged  = TreeAddress::Here;
+            let mut current = prefix;
+            let mut tag     = String::new();
+
+            loop {
+                let next = match *current {
+                    TreeAddress::Here                               => break,
+                    TreeAddress::ChildAtIndex(index, ref next)      => { tag = index.to_string(); next },
+                    TreeAddress::ChildWithTag(ref child_tag, ref next) => { tag = child_tag.clone(); next }
+                };
+
+                tagged = tagged.to_tree_address_then(tag.as_str().to_tree_address());
+                if result.get_child_ref_at(tagged.clone()).is_none() {
+                    result = TreeChange::new(&tagged, &TreeReplacement::NewValue(tag.clone(), TreeValue::Nothing)).apply(&result);
+                }
+
+                current = next;
+            }
+
+            result = TreeChange::new(&tagged, &TreeReplacement::NewValue(tag, mark.to_string().to_tree_value())).apply(&result);
+        }
+
+        result
+    }
+}
+
+impl Default for Watermarks {
+    fn default() -> Watermarks {
+        Watermarks::new()
+    }
+}
+
+#[cfg(test)]
+mod watermark_tests {
+    use super::super::super::tree::*;
+
+    fn apply(watermarks: &mut Watermarks, tree: &TreeRef, change: TreeChange) -> TreeRef {
+        let new_tree = change.apply(tree);
+        watermarks.record_change(tree, &change, &new_tree);
+        new_tree
+    }
+
+    fn read_mark(marks: &TreeRef, address: TreeAddress) -> u64 {
+        marks.get_child_ref_at(address).unwrap().get_value().to_str("").parse().unwrap()
+    }
+
+    #[test]
+    fn change_bumps_only_its_prefix() {
+        let mut tree        = tree!("root", tree!("a", ("x", 1)), tree!("b", ("y", 2)));
+        let mut watermarks  = Watermarks::new();
+
+        tree = apply(&mut watermarks, &tree, TreeChange::new(&("a", "x"), &("x", 10)));
+        let a_mark = watermarks.watermark(&"a".to_tree_address());
+        let b_mark = watermarks.watermark(&"b".to_tree_address());
+
+        tree = apply(&mut watermarks, &tree, TreeChange::new(&("a", "x"), &("x", 11)));
+        assert!(watermarks.watermark(&"a".to_tree_address()) > a_mark);
+        assert!(watermarks.watermark(&"b".to_tree_address()) == b_mark);
+
+        let _ = apply(&mut watermarks, &tree, TreeChange::new(&("b", "y"), &("y", 20)));
+        assert!(watermarks.watermark(&"b".to_tree_address()) > b_mark);
+    }
+
+    #[test]
+    fn indexed_change_bumps_tagged_prefix() {
+        let mut tree        = tree!("root", tree!("a", ("x", 1)), tree!("b", ("y", 2)));
+        let mut watermarks  = Watermarks::new();
+
+        tree = apply(&mut watermarks, &tree, TreeChange::new(&(), &tree.clone()));
+        let a_mark = watermarks.watermark(&"a".to_tree_address());
+        let b_mark = watermarks.watermark(&"b".to_tree_address());
+
+        let _ = apply(&mut watermarks, &tree, TreeChange::new(&(1, 0), &("y", 3)));
+        assert!(watermarks.watermark(&"a".to_tree_address()) == a_mark);
+        assert!(watermarks.watermark(&"b".to_tree_address()) > b_mark);
+    }
+
+    #[test]
+    fn root_replacement_bumps_everything() {
+        let mut tree        = tree!("root", tree!("a", ("x", 1)), tree!("b", ("y", 2)));
+        let mut watermarks  = Watermarks::with_prefixes(vec!["a".to_tree_address(), "b".to_tree_address()]);
+
+        tree = apply(&mut watermarks, &tree, TreeChange::new(&("a", "x"), &("x", 10)));
+        let b_mark = watermarks.watermark(&"b".to_tree_address());
+
+        let _ = apply(&mut watermarks, &tree, TreeChange::new(&(), &tree!("root", "a", "b")));
+        assert!(watermarks.watermark(&"a".to_tree_address()) == watermarks.version());
+        assert!(watermarks.watermark(&"b".to_tree_address()) == watermarks.version());
+        assert!(watermarks.watermark(&"b".to_tree_address()) > b_mark);
+    }
+
+    #[test]
+    fn deep_addresses_use_enclosing_prefix() {
+        let mut tree        = tree!("root", tree!("a", tree!("inner", ("x", 1))), "b");
+        let mut watermarks  = Watermarks::with_prefixes(vec!["a".to_tree_address(), ("a", "inner").to_tree_address()]);
+
+        tree = apply(&mut watermarks, &tree, TreeChange::new(&("a", ("inner", "x")), &("x", 2)));
+        let _ = apply(&mut watermarks, &tree, TreeChange::new(&"b", &"c"));
+
+        assert!(watermarks.watermark(&("a", ("inner", "x")).to_tree_address()) == 1);
+        assert!(watermarks.watermark(&"a".to_tree_address()) == 1);
+        assert!(watermarks.watermark(&"b".to_tree_address()) == 2);
+        assert!(watermarks.watermark(&TreeAddress::Here) == 2);
+    }
+
+    #[test]
+    fn watermarks_are_monotonic() {
+        let mut tree        = tree!("root", ("a", 0));
+        let mut watermarks  = Watermarks::new();
+        let mut last_mark   = 0;
+
+        for value in 1..20 {
+            tree = apply(&mut watermarks, &tree, TreeChange::new(&"a", &("a", value)));
+
+            let mark = watermarks.watermark(&"a".to_tree_address());
+            assert!(mark > last_mark);
+            last_mark = mark;
+        }
+    }
+
+    #[test]
+    fn watermark_tree_matches_accessor() {
+        let mut tree        = tree!("root", tree!("a", tree!("inner", ("x", 1))), "b");
+        let mut watermarks  = Watermarks::with_prefixes(vec!["b".to_tree_address(), ("a", "inner").to_tree_address(), "a".to_tree_address()]);
+
+        tree = apply(&mut watermarks, &tree, TreeChange::new(&("a", ("inner", "x")), &("x", 2)));
+        let _ = apply(&mut watermarks, &tree, TreeChange::new(&"b", &"c"));
+
+        let marks = watermarks.to_tree_node();
+        assert!(marks.get_tag() == WATERMARKS_TAG);
+        assert!(read_mark(&marks, "a".to_tree_address()) == watermarks.watermark(&"a".to_tree_address()));
+        assert!(read_mark(&marks, ("a", "inner").to_tree_address()) == watermarks.watermark(&("a", "inner").to_tree_address()));
+        assert!(read_mark(&marks, "b".to_tree_address()) == watermarks.watermark(&"b".to_tree_address()));
+    }
+}